@@ -0,0 +1,65 @@
+//! 受保护路由的认证中间件
+//!
+//! 未启用认证时直接放行；启用后要求 `Authorization: Bearer <JWT>`，
+//! 校验通过的用户放进请求扩展，handler 通过 `Extension<AuthenticatedUser>` 取用。
+
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+
+use super::routes::{ApiResponse, AppState};
+
+/// 通过令牌认证的用户
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub id: uuid::Uuid,
+    pub username: String,
+    /// 令牌只允许追加，不能删除
+    pub append_only: bool,
+}
+
+pub async fn require_auth(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(auth) = &state.auth else {
+        return next.run(request).await;
+    };
+
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    let Some(token) = token else {
+        return unauthorized("Authentication required");
+    };
+    let Some(claims) = auth.verify(token.trim(), state.clock.now()) else {
+        return unauthorized("Invalid or expired token");
+    };
+    // 用户被删除后，尚未过期的令牌也不再有效
+    if state.repository.get_user(claims.sub).await.is_err() {
+        return unauthorized("Invalid or expired token");
+    }
+
+    tracing::Span::current().record("user", claims.username.as_str());
+    request.extensions_mut().insert(AuthenticatedUser {
+        id: claims.sub,
+        username: claims.username,
+        append_only: claims.append_only,
+    });
+    next.run(request).await
+}
+
+fn unauthorized(message: &str) -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, "Bearer")],
+        Json(ApiResponse::error(message)),
+    )
+        .into_response()
+}
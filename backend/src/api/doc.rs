@@ -16,7 +16,8 @@
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
-use crate::api::routes::{ApiResponse, FileInfo};
+use crate::api::routes::{ApiResponse, AuthToken, Capabilities, FileInfo, HealthStatus};
+use rustcloud_types::{FileVersionRecord, ServiceHealth, UploadStatus, UserInfo};
 
 #[derive(OpenApi)]
 #[openapi(
@@ -30,7 +31,17 @@ use crate::api::routes::{ApiResponse, FileInfo};
         )
     ),
     components(
-        schemas(FileInfo, ApiResponse)
+        schemas(
+            FileInfo,
+            ApiResponse,
+            HealthStatus,
+            ServiceHealth,
+            Capabilities,
+            AuthToken,
+            UserInfo,
+            UploadStatus,
+            FileVersionRecord
+        )
     ),
     tags(
         (name = "files", description = "文件操作"),
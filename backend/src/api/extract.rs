@@ -0,0 +1,34 @@
+//! 写操作使用的路径提取器
+
+use axum::{
+    extract::{FromRequestParts, Path},
+    http::{request::Parts, StatusCode},
+    response::Json,
+};
+use rustcloud_types::path;
+
+use super::routes::ApiResponse;
+
+/// 规范化后的 `{*path}`：分隔符统一为 "/"，Windows 上无法创建的路径直接返回 400
+pub struct LogicalPath(pub String);
+
+impl<S: Send + Sync> FromRequestParts<S> for LogicalPath {
+    type Rejection = (StatusCode, Json<ApiResponse>);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(raw) = Path::<String>::from_request_parts(parts, state)
+            .await
+            .map_err(|e| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(ApiResponse::error(&e.body_text())),
+                )
+            })?;
+        path::normalize(&raw).map(LogicalPath).map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error(&format!("Invalid path: {}", e))),
+            )
+        })
+    }
+}
@@ -0,0 +1,367 @@
+//! 把挂载点下的请求转发到远程服务器
+//!
+//! 在认证之后、handler 之前运行：按匹配到的路由取出请求涉及的路径，
+//! 落在某个挂载点下时换成远程路径转发，响应中的路径再换回本地路径；否则交给本地 handler。
+//! 分块上传会话创建在远程服务器上，之后按会话 ID 转发分块和完成请求。
+
+use std::collections::HashMap;
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::{FromRequestParts, MatchedPath, Path, Query, Request, State},
+    http::{header, request::Parts, HeaderName, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use futures_util::TryStreamExt;
+
+use super::routes::{ApiResponse, AppState, CONTENT_HASH_HEADER};
+use crate::db::MountRecord;
+use crate::service::federation::{api_url, localize};
+
+/// 以文件路径为参数、会被转发的路由
+const FORWARDED_ROUTES: &[&str] = &[
+    "/api/files/{*path}",
+    "/api/stream/{*path}",
+    "/api/preview/{*path}",
+    "/api/metadata/{*path}",
+];
+
+/// 以上传会话 ID 为参数的路由
+const UPLOAD_ROUTES: &[&str] = &[
+    "/api/uploads/{id}",
+    "/api/uploads/{id}/chunks/{n}",
+    "/api/uploads/{id}/complete",
+];
+
+/// 新建文件夹和上传会话的请求体很小，超过该大小不再尝试解析
+const MAX_JSON_REQUEST: usize = 64 * 1024;
+
+/// 转发给远程服务器的请求头；认证头换成挂载的令牌
+const FORWARDED_REQUEST_HEADERS: &[&str] = &[
+    "accept",
+    "content-type",
+    "content-length",
+    "range",
+    "if-match",
+    "if-none-match",
+    "if-modified-since",
+    "if-range",
+    CONTENT_HASH_HEADER,
+];
+
+/// 原样返回给客户端的响应头
+const FORWARDED_RESPONSE_HEADERS: &[&str] = &[
+    "content-type",
+    "content-length",
+    "content-range",
+    "content-disposition",
+    "accept-ranges",
+    "etag",
+    "last-modified",
+    "retry-after",
+];
+
+/// 请求涉及的路径在哪里
+enum Target {
+    /// `/api/<route>/{*path}`
+    Item { route: Vec<String>, path: String },
+    /// GET /api/files?path=
+    Listing {
+        query: HashMap<String, String>,
+        path: String,
+    },
+    /// POST /api/files 和 POST /api/uploads，请求体中的 path
+    Json {
+        route: &'static str,
+        body: serde_json::Value,
+        path: String,
+    },
+}
+
+impl Target {
+    fn path(&self) -> &str {
+        match self {
+            Target::Item { path, .. }
+            | Target::Listing { path, .. }
+            | Target::Json { path, .. } => path,
+        }
+    }
+}
+
+pub async fn forward_mounted(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if state.mounts.is_empty() {
+        return next.run(request).await;
+    }
+    let (mut parts, body) = request.into_parts();
+    let matched = parts
+        .extensions
+        .get::<MatchedPath>()
+        .map(|m| m.as_str().to_string())
+        .unwrap_or_default();
+
+    // 远程会话的后续请求原样转发，路径部分不变
+    if UPLOAD_ROUTES.contains(&matched.as_str()) {
+        let id = Path::<HashMap<String, String>>::from_request_parts(&mut parts, &state)
+            .await
+            .ok()
+            .and_then(|Path(mut params)| params.remove("id"));
+        let mount = id.as_deref().and_then(|id| state.mounts.upload_mount(id));
+        let (Some(id), Some(mount)) = (id, mount) else {
+            return next.run(Request::from_parts(parts, body)).await;
+        };
+        let target = Target::Item {
+            route: parts
+                .uri
+                .path()
+                .trim_start_matches('/')
+                .split('/')
+                .map(str::to_string)
+                .collect(),
+            path: String::new(),
+        };
+        let completed = matched.ends_with("/complete");
+        let response = forward(&state, &mount, target, String::new(), parts, body).await;
+        if completed && response.status().is_success() {
+            state.mounts.forget_upload(&id);
+        }
+        return response;
+    }
+
+    let (target, body) = match matched.as_str() {
+        "/api/files" if parts.method == Method::GET => {
+            let query = Query::<HashMap<String, String>>::try_from_uri(&parts.uri)
+                .map(|Query(query)| query)
+                .unwrap_or_default();
+            let path = query.get("path").cloned().unwrap_or_default();
+            (Some(Target::Listing { query, path }), body)
+        }
+        route @ ("/api/files" | "/api/uploads") if parts.method == Method::POST => {
+            let route = if route == "/api/files" {
+                "files"
+            } else {
+                "uploads"
+            };
+            let Ok(bytes) = to_bytes(body, MAX_JSON_REQUEST).await else {
+                return (
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    Json(ApiResponse::error("Request body too large")),
+                )
+                    .into_response();
+            };
+            let target = serde_json::from_slice::<serde_json::Value>(&bytes)
+                .ok()
+                .and_then(|body| {
+                    let path = body.get("path")?.as_str()?.to_string();
+                    Some(Target::Json { route, body, path })
+                });
+            (target, Body::from(bytes))
+        }
+        route if FORWARDED_ROUTES.contains(&route) => {
+            let target = Path::<String>::from_request_parts(&mut parts, &state)
+                .await
+                .ok()
+                .map(|Path(path)| Target::Item {
+                    route: route
+                        .trim_start_matches('/')
+                        .trim_end_matches("/{*path}")
+                        .split('/')
+                        .map(str::to_string)
+                        .collect(),
+                    path,
+                });
+            (target, body)
+        }
+        _ => (None, body),
+    };
+
+    let resolved = target
+        .as_ref()
+        .and_then(|target| state.mounts.resolve(target.path()));
+    let (Some(target), Some((mount, remote))) = (target, resolved) else {
+        return next.run(Request::from_parts(parts, body)).await;
+    };
+
+    // 挂载点本身由挂载记录代表，不能在远程被删除或覆盖
+    let writes = matches!(target, Target::Json { .. }) || parts.method != Method::GET;
+    if writes && target.path().trim_matches('/') == mount.path {
+        return (
+            StatusCode::CONFLICT,
+            Json(ApiResponse::error(&format!(
+                "{} is a mount point; remove the mount instead",
+                mount.path
+            ))),
+        )
+            .into_response();
+    }
+
+    let creates_upload = matches!(
+        target,
+        Target::Json {
+            route: "uploads",
+            ..
+        }
+    );
+    let response = forward(&state, &mount, target, remote, parts, body).await;
+    if !creates_upload || !response.status().is_success() {
+        return response;
+    }
+    remember_upload(&state, &mount, response).await
+}
+
+async fn forward(
+    state: &AppState,
+    mount: &MountRecord,
+    target: Target,
+    remote: String,
+    parts: Parts,
+    body: Body,
+) -> Response {
+    let url = match &target {
+        // 挂载整个远程存储时，挂载点本身对应远程的根目录列表
+        Target::Item { route, .. } if !(remote.is_empty() && route == &["api", "files"]) => {
+            let mut segments: Vec<&str> = route.iter().map(String::as_str).collect();
+            if !remote.is_empty() {
+                segments.extend(remote.split('/'));
+            }
+            api_url(&mount.url, &segments).map(|mut url| {
+                url.set_query(parts.uri.query());
+                url
+            })
+        }
+        Target::Item { .. } | Target::Listing { .. } => {
+            api_url(&mount.url, &["api", "files"]).map(|mut url| {
+                let mut pairs = url.query_pairs_mut();
+                if let Target::Listing { query, .. } = &target {
+                    for (key, value) in query.iter().filter(|(key, _)| *key != "path") {
+                        pairs.append_pair(key, value);
+                    }
+                }
+                pairs.append_pair("path", &remote);
+                drop(pairs);
+                url
+            })
+        }
+        Target::Json { route, .. } => api_url(&mount.url, &["api", route]).map(|mut url| {
+            url.set_query(parts.uri.query());
+            url
+        }),
+    };
+    let url = match url {
+        Ok(url) => url,
+        Err(e) => return bad_gateway(mount, &e.to_string()),
+    };
+
+    let mut request = state
+        .mounts
+        .request(mount.token.as_deref(), parts.method.clone(), url);
+    for name in FORWARDED_REQUEST_HEADERS {
+        // 改写过的请求体长度由 reqwest 重新计算
+        if matches!(target, Target::Json { .. }) && *name == "content-length" {
+            continue;
+        }
+        if let Some(value) = parts.headers.get(*name) {
+            request = request.header(*name, value);
+        }
+    }
+    request = match target {
+        Target::Json { mut body, .. } => {
+            body["path"] = serde_json::Value::String(remote);
+            request.json(&body)
+        }
+        _ if matches!(parts.method, Method::PUT | Method::POST | Method::PATCH) => {
+            request.body(reqwest::Body::wrap_stream(body.into_data_stream()))
+        }
+        _ => request,
+    };
+
+    let response = match request.send().await {
+        Ok(response) => response,
+        Err(e) => return bad_gateway(mount, &e.to_string()),
+    };
+    localize_response(mount, response).await
+}
+
+// 记下远程会话 ID 属于哪个挂载，之后的分块请求据此转发
+async fn remember_upload(state: &AppState, mount: &MountRecord, response: Response) -> Response {
+    let (parts, body) = response.into_parts();
+    let Ok(bytes) = to_bytes(body, MAX_JSON_REQUEST).await else {
+        return bad_gateway(mount, "upload session response too large");
+    };
+    let id = serde_json::from_slice::<ApiResponse>(&bytes)
+        .ok()
+        .and_then(|body| Some(body.data?.get("id")?.as_str()?.to_string()));
+    if let Some(id) = id {
+        state.mounts.remember_upload(id, mount);
+    }
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+// JSON 和 NDJSON 响应改写其中的路径，其余内容边收边转发
+async fn localize_response(mount: &MountRecord, response: reqwest::Response) -> Response {
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let ndjson = content_type.starts_with("application/x-ndjson");
+    let rewrite = ndjson || content_type.starts_with("application/json");
+
+    let mut builder = Response::builder().status(response.status());
+    for name in FORWARDED_RESPONSE_HEADERS {
+        // 改写后长度会变，由下面重新设置
+        if rewrite && *name == "content-length" {
+            continue;
+        }
+        if let Some(value) = response.headers().get(*name) {
+            builder = builder.header(HeaderName::from_static(name), value);
+        }
+    }
+    if !rewrite {
+        let stream = response.bytes_stream().map_err(std::io::Error::other);
+        return builder
+            .body(Body::from_stream(stream))
+            .unwrap_or_else(|e| bad_gateway(mount, &e.to_string()));
+    }
+
+    let bytes = match response.bytes().await {
+        Ok(bytes) => bytes,
+        Err(e) => return bad_gateway(mount, &e.to_string()),
+    };
+    let rewritten = if ndjson {
+        let mut out = Vec::with_capacity(bytes.len());
+        for line in bytes.split(|b| *b == b'\n').filter(|line| !line.is_empty()) {
+            out.extend(rewrite_json(mount, line).unwrap_or_else(|| line.to_vec()));
+            out.push(b'\n');
+        }
+        out
+    } else {
+        rewrite_json(mount, &bytes).unwrap_or_else(|| bytes.to_vec())
+    };
+    builder
+        .header(header::CONTENT_LENGTH, rewritten.len())
+        .body(Body::from(Bytes::from(rewritten)))
+        .unwrap_or_else(|e| bad_gateway(mount, &e.to_string()))
+}
+
+fn rewrite_json(mount: &MountRecord, bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut value: serde_json::Value = serde_json::from_slice(bytes).ok()?;
+    localize(&mut value, mount);
+    serde_json::to_vec(&value).ok()
+}
+
+fn bad_gateway(mount: &MountRecord, reason: &str) -> Response {
+    (
+        StatusCode::BAD_GATEWAY,
+        Json(ApiResponse::error(&format!(
+            "Mounted server {} is unreachable: {}",
+            mount.url, reason
+        ))),
+    )
+        .into_response()
+}
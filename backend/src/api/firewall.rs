@@ -0,0 +1,111 @@
+//! 按客户端地址放行、拒绝和封禁
+//!
+//! 最外层的中间件：被拒绝或封禁的地址不会走到认证和 handler。
+//! 携带了凭据（Bearer 令牌、管理员令牌或登录请求）却被拒绝的请求计为一次认证失败。
+//! 没有对端地址时（例如测试中直接调用 Router）不做检查。
+
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+
+use super::routes::{ApiResponse, AppState, ADMIN_TOKEN_HEADER};
+use crate::service::firewall::{AuditEntry, AuditEvent, Verdict};
+
+/// 中间件确定的客户端地址，handler 用 `Option<Extension<PeerIp>>` 取得
+#[derive(Debug, Clone, Copy)]
+pub struct PeerIp(pub IpAddr);
+
+pub async fn guard_network(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(ip) = peer_ip(&state, &request) else {
+        return next.run(request).await;
+    };
+    request.extensions_mut().insert(PeerIp(ip));
+    let now = state.clock.now();
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+    let audit = |event| AuditEntry {
+        at: now,
+        ip: Some(ip),
+        user: None,
+        event,
+        method: method.clone(),
+        path: path.clone(),
+        detail: None,
+    };
+
+    match state.firewall.check(ip, now) {
+        Verdict::Allowed => {}
+        Verdict::Denied => {
+            state.firewall.audit(audit(AuditEvent::Denied));
+            return (
+                StatusCode::FORBIDDEN,
+                Json(ApiResponse::error(
+                    "Access from this address is not allowed",
+                )),
+            )
+                .into_response();
+        }
+        Verdict::Banned(until) => {
+            state.firewall.audit(audit(AuditEvent::Blocked));
+            let retry_after = (until - now).num_seconds().max(1);
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, retry_after.to_string())],
+                Json(ApiResponse::error(
+                    "Too many failed authentication attempts; try again later",
+                )),
+            )
+                .into_response();
+        }
+    }
+
+    let admin_attempt = request.headers().contains_key(ADMIN_TOKEN_HEADER);
+    let presented_credentials = admin_attempt
+        || request.headers().contains_key(header::AUTHORIZATION)
+        || path == "/api/auth/login";
+    let banned = audit(AuditEvent::Banned);
+
+    let response = next.run(request).await;
+    if presented_credentials {
+        let status = response.status();
+        if status == StatusCode::UNAUTHORIZED || (admin_attempt && status == StatusCode::FORBIDDEN)
+        {
+            if state.firewall.record_failure(ip, now).is_some() {
+                state.firewall.audit(banned);
+            }
+        } else if status.is_success() {
+            state.firewall.record_success(ip);
+        }
+    }
+    response
+}
+
+fn peer_ip(state: &AppState, request: &Request) -> Option<IpAddr> {
+    let forwarded = state
+        .firewall
+        .trust_forwarded_for()
+        .then(|| {
+            request
+                .headers()
+                .get("x-forwarded-for")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.split(',').next())
+                .and_then(|ip| ip.trim().parse().ok())
+        })
+        .flatten();
+    forwarded.or_else(|| {
+        request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|info| info.0.ip())
+    })
+}
@@ -0,0 +1,93 @@
+// [知识点 #157] 请求级 span 与客户端身份
+// ----------------------------------------
+// 题目：日志里只有 "PUT /api/files/a.txt"，怎样知道是哪台机器发起的？
+//
+// 讲解：
+// tracing 的 span 表示一段有上下文的执行过程，span 上的字段会附加到
+// 其中产生的每一条事件上。中间件为每个请求创建一个 span：
+// 1. 从 X-Device-Id 请求头解析设备，并与已注册设备核对
+// 2. 把设备 id 和名称记录为 span 字段
+// 3. 把 ClientIdentity 放进请求扩展，handler 和后续中间件可以直接取用
+//
+// 之后 handler 里任何一条 warn!/info!，都会自动带上 device=... 字段，
+// 不需要每个调用点手动拼接
+//
+// 注意 tokio::spawn 出去的任务不会继承当前 span，
+// 需要用 .in_current_span() 显式传递
+//
+// 思考：没有认证时，客户端自报的设备 id 可信吗？
+// ----------------------------------------
+
+use std::time::Instant;
+
+use axum::{
+    extract::{Request, State},
+    http::HeaderMap,
+    middleware::Next,
+    response::Response,
+};
+use tracing::field::{display, Empty};
+use tracing::Instrument;
+
+use super::routes::AppState;
+use crate::db::Repository;
+
+/// 客户端声明的设备 id
+pub const DEVICE_ID_HEADER: &str = "x-device-id";
+
+/// 发起请求的已注册设备
+#[derive(Debug, Clone)]
+pub struct ClientIdentity {
+    pub device_id: uuid::Uuid,
+    pub device_name: String,
+}
+
+impl ClientIdentity {
+    /// 未注册或格式错误的设备 id 按匿名请求处理
+    pub async fn resolve(repository: &Repository, headers: &HeaderMap) -> Option<Self> {
+        let device_id = headers
+            .get(DEVICE_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())?;
+        let device = repository.get_device(device_id).await.ok()?;
+        Some(ClientIdentity {
+            device_id: device.id,
+            device_name: device.name,
+        })
+    }
+}
+
+pub async fn identify_client(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let identity = ClientIdentity::resolve(&state.repository, request.headers()).await;
+
+    let span = tracing::info_span!(
+        "request",
+        method = %request.method(),
+        path = %request.uri().path(),
+        device_id = Empty,
+        device = Empty,
+        user = Empty,
+    );
+    if let Some(identity) = identity {
+        span.record("device_id", display(identity.device_id));
+        span.record("device", display(&identity.device_name));
+        request.extensions_mut().insert(identity);
+    }
+
+    async move {
+        let started = Instant::now();
+        let response = next.run(request).await;
+        tracing::info!(
+            status = response.status().as_u16(),
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "request completed"
+        );
+        response
+    }
+    .instrument(span)
+    .await
+}
@@ -1,4 +1,15 @@
+pub mod auth;
 pub mod doc;
+pub mod extract;
+pub mod federation;
+pub mod firewall;
+pub mod identity;
+pub mod namespace;
+pub mod path_guard;
 pub mod routes;
+pub mod security;
+pub mod version;
 
-pub use routes::create_router_with_services;
+pub use routes::{
+    create_router_with_backends, create_router_with_services, create_router_with_supervisor,
+};
@@ -0,0 +1,259 @@
+//! 把请求限制在用户自己的命名空间内
+//!
+//! 开启 `RUSTCLOUD_USER_NAMESPACES` 后在路由之前运行（`{*path}` 参数在路由时就已取出）：
+//! 按令牌认出用户，把地址、查询参数和请求体中的路径换成 users/{id}/ 下的完整路径，
+//! JSON 响应中的路径再换回来，见 [`crate::service::namespace`]。
+//! 令牌缺失或无效时原样放行，由 require_auth 拒绝。
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::{Request, State},
+    http::{header, request::Parts, HeaderValue, Method, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+
+use super::routes::{ApiResponse, AppState};
+use crate::service::namespace::{localize_json, Namespace};
+
+/// 紧跟着文件路径的路由前缀
+const PATH_ROUTES: &[&str] = &[
+    "/api/files/",
+    "/api/dirs/",
+    "/api/preview/",
+    "/api/stream/",
+    "/api/metadata/",
+    "/api/holds/",
+];
+
+/// 查询参数中的路径：(路由, 参数名)
+const PATH_QUERIES: &[(&str, &str)] = &[
+    ("/api/files", "path"),
+    ("/api/hashes", "prefix"),
+    ("/api/comments", "path"),
+];
+
+/// 请求体中的路径字段：(路由, 字段名)
+const PATH_FIELDS: &[(&str, &str)] = &[
+    ("/api/files", "path"),
+    ("/api/uploads", "path"),
+    ("/api/shares", "path"),
+    ("/api/comments", "path"),
+    ("/api/holds", "path"),
+    ("/api/lifecycle/rules", "folder"),
+    ("/api/lifecycle/rules", "archive_to"),
+];
+
+/// 管理接口：审计日志中的路径是完整路径，响应原样返回
+const UNSCOPED_ROUTES: &[&str] = &["/api/security/"];
+
+/// 与 Json 提取器默认的请求体上限相同，更大的请求体 handler 本来也会拒绝
+const MAX_JSON_BODY: usize = 2 * 1024 * 1024;
+
+pub async fn scope_to_user(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(auth) = state.auth.as_ref().filter(|auth| auth.user_namespaces) else {
+        return next.run(request).await;
+    };
+    let claims = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .and_then(|token| auth.verify(token.trim(), state.clock.now()));
+    let Some(claims) = claims else {
+        return next.run(request).await;
+    };
+    let namespace = Namespace::for_user(claims.sub);
+    // 落盘模式下按目录列出文件，新用户的根目录要先存在
+    if state.materialize_files {
+        let root = state.storage_path.join(namespace.root());
+        if !root.is_dir() {
+            let _ = tokio::fs::create_dir_all(&root).await;
+        }
+    }
+
+    let (mut parts, body) = request.into_parts();
+    let original = parts.uri.path().to_string();
+    parts.uri = scope_uri(&parts.uri, &namespace);
+    let body = match scope_body(&mut parts, &original, body, &namespace).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    // 压缩过的响应无法改写其中的路径
+    parts.headers.remove(header::ACCEPT_ENCODING);
+    parts.extensions.insert(namespace.clone());
+    let response = next.run(Request::from_parts(parts, body)).await;
+    if UNSCOPED_ROUTES
+        .iter()
+        .any(|route| original.starts_with(route))
+    {
+        return response;
+    }
+    localize_response(response, &namespace).await
+}
+
+fn scope_uri(uri: &Uri, namespace: &Namespace) -> Uri {
+    let path = uri.path();
+    let scoped_path = PATH_ROUTES
+        .iter()
+        .find_map(|route| {
+            let rest = path.strip_prefix(route)?;
+            Some(format!("{}{}", route, namespace.scope(rest)))
+        })
+        .unwrap_or_else(|| path.to_string());
+
+    let mut query = uri.query().map(str::to_string);
+    if let Some((_, key)) = PATH_QUERIES.iter().find(|(route, _)| *route == path) {
+        let mut pairs: Vec<(String, String)> =
+            reqwest::Url::parse(&format!("http://localhost/?{}", uri.query().unwrap_or("")))
+                .map(|url| url.query_pairs().into_owned().collect())
+                .unwrap_or_default();
+        match pairs.iter_mut().find(|(name, _)| name == key) {
+            Some((_, value)) => *value = scope_query(namespace, key, value),
+            None => pairs.push((key.to_string(), scope_query(namespace, key, ""))),
+        }
+        let mut url = reqwest::Url::parse("http://localhost/").expect("static URL is valid");
+        url.query_pairs_mut().extend_pairs(pairs);
+        query = url.query().map(str::to_string);
+    }
+
+    let path_and_query = match query {
+        Some(query) => format!("{}?{}", scoped_path, query),
+        None => scoped_path,
+    };
+    path_and_query.parse().unwrap_or_else(|_| uri.clone())
+}
+
+// 前缀按字符串匹配，根目录要带上 "/"，否则也会匹配到同样开头的其他目录
+fn scope_query(namespace: &Namespace, key: &str, value: &str) -> String {
+    if key == "prefix" {
+        format!("{}/{}", namespace.root(), value)
+    } else {
+        namespace.scope(value)
+    }
+}
+
+async fn scope_body(
+    parts: &mut Parts,
+    path: &str,
+    body: Body,
+    namespace: &Namespace,
+) -> Result<Body, Response> {
+    if parts.method != Method::POST {
+        return Ok(body);
+    }
+    let fields: Vec<&str> = PATH_FIELDS
+        .iter()
+        .filter(|(route, _)| *route == path)
+        .map(|(_, field)| *field)
+        .collect();
+    let moves =
+        path.starts_with("/api/files/") && (path.ends_with("/move") || path.ends_with("/copy"));
+    let plans = path == "/api/sync/plan";
+    if fields.is_empty() && !moves && !plans {
+        return Ok(body);
+    }
+
+    let Ok(bytes) = to_bytes(body, MAX_JSON_BODY).await else {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(ApiResponse::error("Request body too large")),
+        )
+            .into_response());
+    };
+    // 不是 JSON 时交给 handler 报告错误
+    let Ok(mut value) = serde_json::from_slice::<serde_json::Value>(&bytes) else {
+        return Ok(Body::from(bytes));
+    };
+    let scope = |value: Option<&mut serde_json::Value>| {
+        if let Some(value) = value {
+            if let Some(path) = value.as_str() {
+                *value = serde_json::Value::String(namespace.scope(path));
+            }
+        }
+    };
+    for field in fields {
+        scope(value.get_mut(field));
+    }
+    if moves {
+        scope(value.get_mut("to"));
+    }
+    if plans {
+        if let Some(files) = value.get_mut("local_files").and_then(|f| f.as_array_mut()) {
+            for file in files {
+                scope(file.get_mut("path"));
+            }
+        }
+        if let Some(dirs) = value.get_mut("local_dirs").and_then(|d| d.as_array_mut()) {
+            dirs.iter_mut().for_each(|dir| scope(Some(dir)));
+        }
+    }
+
+    let bytes = serde_json::to_vec(&value).unwrap_or_else(|_| bytes.to_vec());
+    parts
+        .headers
+        .insert(header::CONTENT_LENGTH, HeaderValue::from(bytes.len()));
+    Ok(Body::from(bytes))
+}
+
+// 文件内容（带 Content-Disposition）原样返回，JSON 和 NDJSON 改写其中的路径
+async fn localize_response(response: Response, namespace: &Namespace) -> Response {
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let ndjson = content_type.starts_with("application/x-ndjson");
+    let json = content_type.starts_with("application/json");
+    let headers = response.headers();
+    if !(json || ndjson)
+        || headers.contains_key(header::CONTENT_DISPOSITION)
+        || headers.contains_key(header::CONTENT_ENCODING)
+    {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = to_bytes(body, usize::MAX).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let rewritten = if ndjson {
+        let mut out = Vec::with_capacity(bytes.len());
+        for line in bytes.split(|b| *b == b'\n').filter(|line| !line.is_empty()) {
+            match serde_json::from_slice::<serde_json::Value>(line) {
+                // 单独一行的条目不在命名空间内时整行去掉
+                Ok(value)
+                    if value
+                        .get("path")
+                        .and_then(|path| path.as_str())
+                        .is_some_and(|path| !namespace.contains(path)) => {}
+                Ok(mut value) => {
+                    localize_json(&mut value, namespace);
+                    out.extend(serde_json::to_vec(&value).unwrap_or_else(|_| line.to_vec()));
+                    out.push(b'\n');
+                }
+                Err(_) => {
+                    out.extend(line);
+                    out.push(b'\n');
+                }
+            }
+        }
+        out
+    } else {
+        match serde_json::from_slice::<serde_json::Value>(&bytes) {
+            Ok(mut value) => {
+                localize_json(&mut value, namespace);
+                serde_json::to_vec(&value).unwrap_or_else(|_| bytes.to_vec())
+            }
+            Err(_) => bytes.to_vec(),
+        }
+    };
+    parts
+        .headers
+        .insert(header::CONTENT_LENGTH, HeaderValue::from(rewritten.len()));
+    Response::from_parts(parts, Body::from(Bytes::from(rewritten)))
+}
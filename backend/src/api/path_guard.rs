@@ -0,0 +1,85 @@
+//! 路径穿越防护
+//!
+//! 所有受保护路由在进入 handler 之前检查 `{*path}` 参数和 `path` 查询参数：
+//! 解码后含 ".."、以 "/" 或盘符开头、或经由符号链接指向存储根目录之外的路径一律返回 400。
+//! 请求体中的路径由各 handler 用 `rustcloud_types::path::normalize` 规范化，写盘前再用 [`resolve`] 检查。
+
+use std::collections::HashMap;
+use std::path::{Path as FsPath, PathBuf};
+
+use axum::{
+    extract::{FromRequestParts, Path, Query, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+
+use super::routes::{ApiResponse, AppState};
+use crate::error::{Error, Result};
+
+/// 不访问文件系统的检查：上级目录、绝对路径、盘符和 NUL
+pub fn check(logical: &str) -> Result<()> {
+    let traversal = || Err(Error::PathTraversal(logical.to_string()));
+    if logical.starts_with(['/', '\\']) || logical.contains('\0') {
+        return traversal();
+    }
+    // "C:"、"C:\\Windows" 在 Windows 上 join 时会替换掉存储根目录
+    let bytes = logical.as_bytes();
+    if bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+        return traversal();
+    }
+    if logical.split(['/', '\\']).any(|segment| segment == "..") {
+        return traversal();
+    }
+    Ok(())
+}
+
+/// 逻辑路径在存储根目录下对应的位置；已存在的部分经符号链接解析后也必须仍在根目录之内
+pub async fn resolve(root: &FsPath, logical: &str) -> Result<PathBuf> {
+    check(logical)?;
+    let joined = root.join(logical);
+    // 根目录还不存在时，其下也不可能有符号链接
+    let Ok(real_root) = tokio::fs::canonicalize(root).await else {
+        return Ok(joined);
+    };
+    let mut existing = joined.as_path();
+    loop {
+        match tokio::fs::canonicalize(existing).await {
+            Ok(real) if real.starts_with(&real_root) => return Ok(joined),
+            Ok(_) => return Err(Error::PathTraversal(logical.to_string())),
+            Err(_) => match existing.parent() {
+                Some(parent) if parent.starts_with(root) => existing = parent,
+                _ => return Ok(joined),
+            },
+        }
+    }
+}
+
+pub async fn reject_traversal(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (mut parts, body) = request.into_parts();
+    let mut candidates = Vec::new();
+    if let Ok(Path(params)) =
+        Path::<HashMap<String, String>>::from_request_parts(&mut parts, &state).await
+    {
+        candidates.extend(params.get("path").cloned());
+    }
+    if let Ok(Query(query)) = Query::<HashMap<String, String>>::try_from_uri(&parts.uri) {
+        candidates.extend(query.get("path").cloned());
+    }
+
+    for candidate in candidates {
+        if let Err(e) = resolve(&state.storage_path, &candidate).await {
+            tracing::warn!("Rejected path {:?}: {}", candidate, e);
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error(&e.to_string())),
+            )
+                .into_response();
+        }
+    }
+    next.run(Request::from_parts(parts, body)).await
+}
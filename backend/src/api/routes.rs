@@ -15,21 +15,84 @@
 // ----------------------------------------
 
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
-    response::{IntoResponse, Json},
-    routing::{delete, get, post, put},
+    body::{Body, Bytes},
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo, DefaultBodyLimit, Extension, FromRequest, Multipart, Path, Query, Request,
+        State,
+    },
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Json, Response},
+    routing::{delete, get, patch, post, put},
     Router,
 };
+use chrono::Datelike;
+use futures_util::StreamExt;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
 use std::sync::Arc;
-use utoipa::ToSchema;
+use tokio::sync::broadcast;
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
 
-use crate::config::Config;
-use crate::db::{FileRecord, NewDeviceRecord, Repository};
+use super::auth::{require_auth, AuthenticatedUser};
+use super::extract::LogicalPath;
+use super::federation::forward_mounted;
+use super::firewall::{guard_network, PeerIp};
+use super::identity::{identify_client, ClientIdentity};
+use super::namespace::scope_to_user;
+use super::path_guard::{self, reject_traversal};
+use super::security::secure_browser_requests;
+use super::version::reject_outdated_clients;
+use crate::config::{Config, ConflictStrategy, ReputationPolicy, WebSecurityConfig};
+use crate::db::{
+    DeviceRecord, DirectoryRecord, FileRecord, MetadataStore, NewDeviceRecord, NewLifecycleRule,
+    NewMountRecord, NewRateClass, NewShareRecord, NewTrashEntry, NewUserRecord,
+    NotificationChannel, NotificationEvent, NotificationRule, Repository, ShareAccessRecord,
+    ShareLimits, ShareRecord, TrashEntry, TrashItem, UserRecord,
+};
 use crate::error::Error;
-use crate::service::storage::{StorageConfig, StorageService};
-use crate::service::sync::{SyncAction, SyncEngine};
+use crate::service::access::{AccessTracker, ACCESS_FLUSH_INTERVAL};
+use crate::service::account::{AccountService, ErasureSummary};
+use crate::service::append_only::AppendOnly;
+use crate::service::auth::{AuthService, MIN_PASSWORD_LEN};
+use crate::service::bandwidth::{self, BandwidthLimiter, Direction};
+use crate::service::changes::ChangeJournal;
+use crate::service::clock::Clock;
+use crate::service::compression::COMPRESS_MAX_SIZE;
+use crate::service::direction::SyncDirections;
+use crate::service::expected_writes::ExpectedWrites;
+use crate::service::federation::Mounts;
+use crate::service::feed::ChangeFeed;
+use crate::service::firewall::{self, AuditEntry, AuditEvent, Firewall};
+use crate::service::lifecycle::LifecycleService;
+use crate::service::listing::DirectoryCache;
+use crate::service::locks::PathLocks;
+use crate::service::media;
+use crate::service::namespace::{self, Namespace};
+use crate::service::notify::{FileChange, Notification, Notifier};
+use crate::service::preview::{self, PreviewRange, DEFAULT_PREVIEW_LINES, MAX_PREVIEW_BYTES};
+use crate::service::proxy::{PendingWrite, ProxyService, Upstream};
+use crate::service::quota::QuotaService;
+use crate::service::reputation::{ReputationService, Verdict, VERDICT_METADATA_KEY};
+use crate::service::share::ShareStreams;
+use crate::service::shred::{ShredSummary, Shredder};
+use crate::service::storage::{StagedUpload, StorageBackend, StorageConfig, StorageService};
+use crate::service::supervisor::Supervisor;
+use crate::service::sync::{SyncAction, SyncEngine, SYNC_EXPIRY_INTERVAL, SYNC_TIMEOUT};
+use crate::service::trash::{TrashService, TRASH_SWEEP_INTERVAL};
+use crate::service::uploads::{UploadSession, UploadSessions};
+use crate::service::version::VersionService;
+use rustcloud_types::delta::Delta;
+use rustcloud_types::path::{self as logical_path, case_key};
+
+use rustcloud_types::{
+    feature, ChangeEvent, ChangeKind, DeviceHeartbeat, HashEntry, SyncDirection, UploadStatus,
+    PROTOCOL_VERSION,
+};
+pub use rustcloud_types::{ApiResponse, AuthToken, Capabilities, FileInfo, HealthStatus};
 
 // [知识点 #001] Arc 与 RwLock 的组合
 // ----------------------------------------
@@ -48,6 +111,11 @@ use crate::service::sync::{SyncAction, SyncEngine};
 // ----------------------------------------
 pub type AppState = Arc<AppData>;
 
+/// 客户端上传时携带的内容 SHA-256，服务端据此校验传输完整性
+pub const CONTENT_HASH_HEADER: &str = "x-content-sha256";
+
+pub const ADMIN_TOKEN_HEADER: &str = "x-admin-token";
+
 // [知识点 #085] 应用状态设计
 // ----------------------------------------
 // 题目：AppData 应该包含哪些内容？
@@ -56,7 +124,7 @@ pub type AppState = Arc<AppData>;
 // 应用状态是所有 handler 共享的数据：
 // - storage_path: 文件存储根目录
 // - repository: 数据库访问层
-// - storage: 文件存储服务
+// - files / storage: 文件记录与对象存储（trait 对象，可替换）
 // - max_file_size: 最大文件大小限制
 //
 // 所有服务使用 Arc 共享，避免重复创建
@@ -66,67 +134,185 @@ pub type AppState = Arc<AppData>;
 pub struct AppData {
     pub storage_path: std::path::PathBuf,
     pub repository: Repository,
-    pub storage: StorageService,
+    /// 文件记录；默认与 repository 是同一个数据库
+    pub files: Arc<dyn MetadataStore>,
+    pub storage: Arc<dyn StorageBackend>,
     pub sync_engine: SyncEngine,
+    pub notifier: Notifier,
+    pub reputation: Option<ReputationService>,
+    pub admin_token: Option<String>,
+    /// 未启用认证时为 None
+    pub auth: Option<AuthService>,
+    pub lifecycle: LifecycleService,
+    pub access: AccessTracker,
+    pub bandwidth: BandwidthLimiter,
+    pub share_streams: ShareStreams,
+    pub path_locks: PathLocks,
+    pub listings: DirectoryCache,
+    pub uploads: UploadSessions,
+    pub versions: VersionService,
+    /// 代理模式下的上游；独立运行时为 None
+    pub proxy: Option<ProxyService>,
+    /// 挂载到本地路径下的远程目录
+    pub mounts: Mounts,
+    /// 按客户端地址的访问控制与失败封禁
+    pub firewall: Firewall,
+    /// 安全响应头与可信的页面来源
+    pub web_security: WebSecurityConfig,
+    /// 后台服务的所有者，健康检查从这里读取状态
+    pub supervisor: Supervisor,
+    /// 写请求的变更日志与各设备的同步游标
+    pub changes: ChangeJournal,
+    /// 服务端即将写入存储目录的文件，文件监控据此忽略自己引起的事件
+    pub expected_writes: ExpectedWrites,
+    /// 推送给 WebSocket 订阅者的文件变更
+    pub feed: ChangeFeed,
+    /// 与 repository 共用的时钟
+    pub clock: Arc<dyn Clock>,
     pub max_file_size: u64,
+    pub materialize_files: bool,
+    pub conflicts: ConflictStrategy,
+    pub append_only: AppendOnly,
+    /// 只上传、只下载的目录
+    pub directions: SyncDirections,
+    /// 删除时覆写数据；未开启时为 None
+    pub shredder: Option<Shredder>,
+    /// 删除的文件先移入回收站；保留天数为 0 时不启用
+    pub trash: TrashService,
+    /// 账户数据的导出与删除
+    pub accounts: AccountService,
+    /// 按用户统计用量，上传前检查配额
+    pub quotas: QuotaService,
+    /// 低于该版本的客户端被拒绝
+    pub min_client_version: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct ListFilesQuery {
     pub path: Option<String>,
+    /// 以 NDJSON 逐条返回，适合条目很多的目录
+    #[serde(default)]
+    pub stream: bool,
+    /// 列出该时刻的目录，由历史版本和删除、移动记录还原
+    pub as_of: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HashesQuery {
+    /// 只列出路径以此开头的文件，例如 `docs/`
+    #[serde(default)]
+    pub prefix: String,
 }
 
-#[derive(Debug, Serialize, ToSchema)]
-pub struct FileInfo {
+#[derive(Debug, Deserialize)]
+pub struct RegisterDeviceRequest {
     pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CredentialsRequest {
+    pub username: String,
+    pub password: String,
+    /// 签发只追加的令牌，给备份程序使用
+    #[serde(default)]
+    pub append_only: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CommentsQuery {
     pub path: String,
-    pub is_dir: bool,
-    pub size: u64,
-    pub modified: Option<String>,
-    pub hash: Option<String>,
-    pub version: Option<i32>,
 }
 
-#[derive(Debug, Serialize, ToSchema)]
-pub struct ApiResponse {
-    pub success: bool,
-    pub data: Option<serde_json::Value>,
-    pub error: Option<String>,
+#[derive(Debug, Deserialize)]
+pub struct AddCommentRequest {
+    pub path: String,
+    /// 未启用认证时由客户端自行填写；启用后使用登录用户名
+    pub author: Option<String>,
+    pub text: String,
 }
 
-impl ApiResponse {
-    pub fn success<T: Serialize>(data: T) -> Self {
-        ApiResponse {
-            success: true,
-            data: Some(serde_json::to_value(data).unwrap_or(serde_json::Value::Null)),
-            error: None,
-        }
-    }
+#[derive(Debug, Deserialize)]
+pub struct ActivityQuery {
+    pub limit: Option<usize>,
+}
 
-    pub fn error(msg: &str) -> Self {
-        ApiResponse {
-            success: false,
-            data: None,
-            error: Some(msg.to_string()),
+/// 动态流中的一条记录，按时间倒序返回
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ActivityEntry {
+    FileUpdated {
+        path: String,
+        version: i32,
+        at: chrono::DateTime<chrono::Utc>,
+    },
+    Comment {
+        path: String,
+        author: String,
+        text: String,
+        at: chrono::DateTime<chrono::Utc>,
+    },
+}
+
+impl ActivityEntry {
+    fn at(&self) -> chrono::DateTime<chrono::Utc> {
+        match self {
+            ActivityEntry::FileUpdated { at, .. } | ActivityEntry::Comment { at, .. } => *at,
         }
     }
 }
 
+const DEFAULT_ACTIVITY_LIMIT: usize = 50;
+const MAX_ACTIVITY_LIMIT: usize = 500;
+
+const MAX_METADATA_ENTRIES: usize = 64;
+const MAX_METADATA_KEY_LEN: usize = 128;
+const MAX_METADATA_VALUE_LEN: usize = 4096;
+
 #[derive(Debug, Deserialize)]
-pub struct RegisterDeviceRequest {
-    pub name: String,
+pub struct SearchQuery {
+    /// 路径子串，不区分大小写
+    pub q: Option<String>,
+    /// 只返回带有该自定义元数据键的文件
+    pub meta_key: Option<String>,
+    /// 与 meta_key 一起使用，要求值相等
+    pub meta_value: Option<String>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResult {
+    pub files: Vec<FileInfo>,
+    /// 截断前的匹配总数
+    pub total: usize,
 }
 
+const DEFAULT_SEARCH_LIMIT: usize = 100;
+const MAX_SEARCH_LIMIT: usize = 1000;
+
 #[derive(Debug, Deserialize)]
 pub struct CreateFolderRequest {
     pub path: String,
 }
 
-// TODO: 未来用于接收二进制文件上传
-#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
-pub struct UploadRequest {
-    pub content: String,
+pub struct AuditQuery {
+    pub limit: Option<usize>,
+}
+
+const DEFAULT_AUDIT_LIMIT: usize = 100;
+const MAX_AUDIT_LIMIT: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateMountRequest {
+    /// 本地挂载点
+    pub path: String,
+    /// 远程服务器地址，如 https://cloud.example.com
+    pub url: String,
+    /// 远程服务器上的目录，默认整个存储
+    #[serde(default)]
+    pub remote_path: String,
+    /// 远程服务器未启用认证时可省略
+    pub token: Option<String>,
 }
 
 // [知识点 #061] async fn 与 axum handler
@@ -144,15 +330,17 @@ pub struct UploadRequest {
 // 思考：async 函数的调用和同步函数有什么区别？
 // ----------------------------------------
 pub async fn create_router(config: Config) -> Router {
-    let db_path = config.storage_path.join("db.json");
-
-    let repository = Repository::new(db_path)
+    let repository = Repository::open(&config.storage_path, config.database)
         .await
         .expect("Failed to init repository");
     let storage = StorageService::new(StorageConfig {
         storage_path: config.storage_path.clone(),
-        chunk_size: 4 * 1024 * 1024,
-    });
+        chunk_size: config.chunk_size,
+    })
+    .with_workers(&config.workers)
+    .with_verify_reads(config.verify_reads)
+    .with_compression(config.compress_objects)
+    .with_shard_depth(config.shard_depth);
 
     create_router_with_services(config, Arc::new(repository), Arc::new(storage)).await
 }
@@ -164,6 +352,8 @@ pub async fn create_router(config: Config) -> Router {
 // 讲解：
 // - create_router: 简单场景，自动创建服务
 // - create_router_with_services: 测试和高级场景，注入外部服务
+// - create_router_with_backends: 进一步替换文件记录与对象存储的实现
+// - create_router_with_supervisor: 后台服务交给调用方的 Supervisor，便于统一关闭
 //
 // 依赖注入的好处：
 // 1. 测试时可以注入 mock 服务
@@ -176,505 +366,5892 @@ pub async fn create_router_with_services(
     config: Config,
     repository: Arc<Repository>,
     storage: Arc<StorageService>,
+) -> Router {
+    let files: Arc<dyn MetadataStore> = repository.clone();
+    create_router_with_backends(config, repository, files, storage).await
+}
+
+/// 文件记录与对象存储由调用方提供，测试据此注入故障
+pub async fn create_router_with_backends(
+    config: Config,
+    repository: Arc<Repository>,
+    files: Arc<dyn MetadataStore>,
+    storage: Arc<dyn StorageBackend>,
+) -> Router {
+    create_router_with_supervisor(
+        config,
+        repository,
+        files,
+        storage,
+        Supervisor::new(),
+        ExpectedWrites::new(),
+        ChangeFeed::new(),
+    )
+    .await
+}
+
+/// 访问记录落盘、上游转发和通知投递都登记到 supervisor 下；
+/// expected_writes 与 feed 和文件监控共享：服务端自己的写入不会被重新导入，
+/// 监控发现的外部修改也推送给订阅者
+pub async fn create_router_with_supervisor(
+    config: Config,
+    repository: Arc<Repository>,
+    files: Arc<dyn MetadataStore>,
+    storage: Arc<dyn StorageBackend>,
+    supervisor: Supervisor,
+    expected_writes: ExpectedWrites,
+    feed: ChangeFeed,
 ) -> Router {
     let sync_engine = SyncEngine::new(repository.clone());
+    let expiry = sync_engine.clone();
+    supervisor.spawn("sync-expiry", move |shutdown| {
+        expiry
+            .clone()
+            .run_expiry(SYNC_EXPIRY_INTERVAL, SYNC_TIMEOUT, shutdown)
+    });
+    let notifier =
+        Notifier::new((*repository).clone(), config.smtp.clone()).with_tasks(supervisor.tasks());
+    let access = AccessTracker::new((*repository).clone());
+    let flusher = access.clone();
+    supervisor.spawn("access-flusher", move |shutdown| {
+        flusher.clone().run_flusher(ACCESS_FLUSH_INTERVAL, shutdown)
+    });
+    let proxy = config.upstream.clone().map(|upstream| {
+        let proxy = ProxyService::new(upstream, &config.storage_path);
+        let forwarder = proxy.clone();
+        let storage = storage.clone();
+        supervisor.spawn("proxy-forwarder", move |shutdown| {
+            forwarder.clone().run_forwarder(storage.clone(), shutdown)
+        });
+        proxy
+    });
+    let versions = VersionService::new(storage.clone(), (*repository).clone());
+    let mounts = Mounts::new(
+        repository
+            .list_mounts()
+            .await
+            .expect("Failed to load mounts"),
+    );
+    let shredder = config
+        .shred_deleted
+        .then(|| Shredder::new(storage.clone(), (*repository).clone(), &config.storage_path));
+    let accounts =
+        AccountService::new((*repository).clone(), storage.clone(), &config.storage_path);
+    let trash = TrashService::new(
+        (*repository).clone(),
+        &config.storage_path,
+        config.trash_retention_days,
+    )
+    .with_shredder(shredder.clone());
+    if trash.enabled() {
+        let sweeper = trash.clone();
+        supervisor.spawn("trash-sweeper", move |shutdown| {
+            sweeper.clone().run_sweeper(TRASH_SWEEP_INTERVAL, shutdown)
+        });
+    }
+    let quotas = match &config.auth {
+        Some(auth) => QuotaService::new(
+            files.clone(),
+            (*repository).clone(),
+            auth.default_quota_bytes,
+        )
+        .with_namespaces(auth.user_namespaces),
+        None => QuotaService::new(files.clone(), (*repository).clone(), None),
+    };
     let state: AppState = Arc::new(AppData {
         storage_path: config.storage_path.clone(),
         repository: (*repository).clone(),
-        storage: (*storage).clone(),
+        files,
+        storage,
         sync_engine,
+        notifier,
+        reputation: config.reputation.clone().map(ReputationService::new),
+        admin_token: config.admin_token.clone(),
+        auth: config.auth.as_ref().map(AuthService::new),
+        lifecycle: LifecycleService::new((*repository).clone(), config.storage_path.clone())
+            .with_append_only(AppendOnly::new(&config.append_only))
+            .with_shredder(shredder.clone()),
+        access,
+        bandwidth: BandwidthLimiter::new((*repository).clone()),
+        share_streams: ShareStreams::new(),
+        path_locks: PathLocks::new(),
+        listings: DirectoryCache::new(),
+        // 与对象存储放在同一文件系统，完成时分块可以直接拼接
+        uploads: UploadSessions::new(
+            config.storage_path.join("objects").join("uploads"),
+            repository.clock(),
+        ),
+        versions,
+        proxy,
+        mounts,
+        firewall: Firewall::new(&config.network, &config.storage_path)
+            .expect("Invalid network access rules"),
+        web_security: config.web.clone(),
+        supervisor,
+        changes: ChangeJournal::new(),
+        expected_writes,
+        feed,
+        clock: repository.clock(),
         max_file_size: config.max_file_size,
+        materialize_files: config.materialize_files,
+        conflicts: config.conflicts,
+        append_only: AppendOnly::new(&config.append_only),
+        directions: SyncDirections::new(&config.upload_only, &config.download_only),
+        shredder,
+        trash,
+        accounts,
+        quotas,
+        min_client_version: config.min_client_version.clone(),
     });
 
     build_router(state)
 }
 
 fn build_router(state: AppState) -> Router {
-    Router::new()
+    // 健康检查、协议握手、登录注册和公开分享链接不需要令牌
+    let public = Router::new()
         .route("/api/health", get(health_check))
+        .route("/api/capabilities", get(get_capabilities))
+        .route("/api/auth/register", post(register_user))
+        .route("/api/auth/login", post(login))
+        .route("/api/shares/{id}/download", get(download_share));
+
+    let routes = Router::new()
+        .route("/api/auth/me", get(current_user))
+        .route("/api/me/usage", get(get_usage))
+        .route("/api/users/{id}/quota", put(set_user_quota))
+        .route("/api/account", delete(erase_account))
+        .route("/api/account/export", post(start_export))
+        .route("/api/account/export/{id}", get(get_export))
+        .route("/api/account/export/{id}/archive", get(download_export))
+        .route("/api/account/erasure", post(request_erasure))
         .route("/api/files", get(list_files))
         .route("/api/files", post(create_folder))
+        .route("/api/dirs/{*path}", post(create_directory))
         .route("/api/files/{*path}", get(get_file))
         .route("/api/files/{*path}", put(upload_file))
+        // 大小由 handler 边接收边检查，不使用 Multipart 默认的 2MB 上限
+        .route(
+            "/api/files/{*path}",
+            post(post_file).layer(DefaultBodyLimit::disable()),
+        )
+        .route("/api/files/{*path}", patch(patch_file))
         .route("/api/files/{*path}", delete(delete_file))
+        .route("/api/uploads", post(create_upload))
+        .route("/api/uploads/{id}", get(get_upload))
+        .route("/api/uploads/{id}/chunks/{n}", put(upload_chunk))
+        .route("/api/uploads/{id}/complete", post(complete_upload))
+        .route("/api/preview/{*path}", get(preview_file))
+        .route("/api/stream/{*path}", get(stream_file))
+        .route("/api/metadata/{*path}", patch(patch_metadata))
+        .route("/api/search", get(search_files))
         .route("/api/devices", post(register_device))
         .route("/api/devices", get(list_devices))
         .route("/api/devices/{id}/heartbeat", post(device_heartbeat))
+        .route("/api/ws", get(watch_changes))
+        .route(
+            "/api/notifications/preferences/{user}",
+            get(get_notification_preferences),
+        )
+        .route(
+            "/api/notifications/preferences/{user}",
+            put(set_notification_preferences),
+        )
+        .route("/api/holds", get(list_legal_holds))
+        .route("/api/holds", post(add_legal_hold))
+        .route("/api/holds/{*path}", delete(remove_legal_hold))
+        .route("/api/lifecycle/rules", get(list_lifecycle_rules))
+        .route("/api/lifecycle/rules", post(create_lifecycle_rule))
+        .route("/api/lifecycle/rules/{id}", delete(delete_lifecycle_rule))
+        .route("/api/lifecycle/preview", get(preview_lifecycle))
+        .route("/api/lifecycle/run", post(run_lifecycle))
+        .route("/api/reports/cold", get(cold_data_report))
+        .route("/api/reports/duplicates", get(duplicates_report))
+        .route("/api/reports/largest", get(largest_files_report))
+        .route("/api/reports/stale", get(stale_files_report))
+        .route("/api/bandwidth/classes", get(list_rate_classes))
+        .route("/api/bandwidth/classes", post(create_rate_class))
+        .route("/api/bandwidth/classes/{id}", delete(delete_rate_class))
+        .route("/api/shares", get(list_shares))
+        .route("/api/shares", post(create_share))
+        .route("/api/shares/{id}", delete(delete_share))
+        .route("/api/shares/{id}/stats", get(get_share_stats))
+        .route("/api/comments", get(list_comments))
+        .route("/api/comments", post(add_comment))
+        .route("/api/comments/{id}", delete(delete_comment))
+        .route("/api/activity", get(list_activity))
+        .route("/api/photos", get(list_photos))
+        .route("/api/photos/by-date", get(list_photo_years))
+        .route("/api/photos/by-date/{*path}", get(list_photos_by_date))
+        .route("/api/chunk-policy", get(get_chunk_policy))
+        .route("/api/proxy/status", get(proxy_status))
         .route("/api/versions", get(list_versions))
+        .route("/api/hashes", get(list_hashes))
+        .route("/api/mounts", get(list_mounts))
+        .route("/api/mounts", post(create_mount))
+        .route("/api/mounts/{id}", delete(delete_mount))
+        .route("/api/security/bans", get(list_bans))
+        .route("/api/security/bans/{ip}", delete(delete_ban))
+        .route("/api/security/audit", get(list_audit))
         .route("/api/syncs/{file_id}", get(get_sync_status))
         .route("/api/sync/plan", post(create_sync_plan))
         .route("/api/sync/execute", post(execute_sync))
+        .route("/api/sync/directions", get(list_sync_directions))
+        .route("/api/conflicts", get(list_conflicts))
+        .route("/api/trash", get(list_trash))
+        .route("/api/trash/{id}/restore", post(restore_trash))
+        .route("/api/trash/{id}", delete(purge_trash))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            journal_changes,
+        ))
+        // 后加的 route_layer 在外层：先认证，再决定是否转发到挂载的服务器
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            forward_mounted,
+        ))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_auth))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            reject_traversal,
+        ))
+        .merge(public)
+        .with_state(state.clone());
+
+    // Router::layer 在路由之后运行，改写路径的中间件要包在整个路由外面：
+    // routes 按改写后的地址匹配，{*path} 参数取到的已是命名空间内的完整路径
+    Router::new()
+        .fallback_service(routes)
+        .layer(middleware::from_fn_with_state(state.clone(), scope_to_user))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            reject_outdated_clients,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            throttle_transfers,
+        ))
+        .layer(middleware::from_fn_with_state(state.clone(), guard_network))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            identify_client,
+        ))
+        // 最外层：被防火墙拒绝的响应也带上安全头
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            secure_browser_requests,
+        ))
         .with_state(state)
 }
 
-async fn health_check() -> impl IntoResponse {
-    Json(ApiResponse::success("ok"))
+/// 成功后会改变文件内容或文件树的请求
+fn changes_files(method: &Method, path: &str) -> bool {
+    if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) {
+        return false;
+    }
+    path.starts_with("/api/files")
+        || path.starts_with("/api/dirs/")
+        || path.starts_with("/api/metadata/")
+        || path == "/api/sync/execute"
+        || path == "/api/account"
+        || (path.starts_with("/api/trash/") && path.ends_with("/restore"))
+        || (path.starts_with("/api/uploads/") && path.ends_with("/complete"))
 }
 
-async fn list_files(
-    State(state): State<AppState>,
-    Query(query): Query<ListFilesQuery>,
-) -> impl IntoResponse {
-    let base_path = &state.storage_path;
-
-    let target_path = if let Some(p) = query.path {
-        base_path.join(&p)
-    } else {
-        base_path.clone()
-    };
-
-    match list_directory(&target_path, base_path) {
-        Ok(files) => Json(ApiResponse::success(files)),
-        Err(e) => Json(ApiResponse::error(&e.to_string())),
+// 成功的写请求记入变更日志，心跳据此提示其他设备同步
+async fn journal_changes(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let changes = changes_files(request.method(), request.uri().path());
+    let origin = request
+        .extensions()
+        .get::<ClientIdentity>()
+        .map(|c| c.device_id);
+    let response = next.run(request).await;
+    if changes && response.status().is_success() {
+        state.changes.record(origin);
     }
+    response
 }
 
-async fn create_folder(
+// 按当前生效的速率等级限制请求体和响应体的传输速度
+async fn throttle_transfers(
     State(state): State<AppState>,
-    Json(req): Json<CreateFolderRequest>,
-) -> impl IntoResponse {
-    let folder_path = state.storage_path.join(&req.path);
+    request: Request,
+    next: Next,
+) -> Response {
+    let device_id = request
+        .extensions()
+        .get::<ClientIdentity>()
+        .map(|c| c.device_id);
 
-    if folder_path.exists() {
-        return (
-            StatusCode::CONFLICT,
-            Json(ApiResponse::error("Folder already exists")),
-        );
+    let request = match state
+        .bandwidth
+        .bucket_for(device_id, Direction::Upload)
+        .await
+    {
+        Some(bucket) => request.map(|body| bandwidth::throttle(body, bucket)),
+        None => request,
+    };
+    let response = next.run(request).await;
+    match state
+        .bandwidth
+        .bucket_for(device_id, Direction::Download)
+        .await
+    {
+        Some(bucket) => response.map(|body| bandwidth::throttle(body, bucket)),
+        None => response,
     }
+}
 
-    match tokio::fs::create_dir_all(&folder_path).await {
-        Ok(_) => {
-            let info = FileInfo {
-                name: folder_path
-                    .file_name()
-                    .map(|n| n.to_string_lossy().to_string())
-                    .unwrap_or_default(),
-                path: req.path,
-                is_dir: true,
-                size: 0,
-                modified: Some(chrono::Utc::now().to_rfc3339()),
-                hash: None,
-                version: None,
-            };
-            (StatusCode::OK, Json(ApiResponse::success(info)))
-        }
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ApiResponse::error(&format!("Failed to create folder: {}", e))),
-        ),
-    }
+async fn health_check(State(state): State<AppState>) -> impl IntoResponse {
+    // 有后台服务在崩溃重启时、或有对象等待修复时仍能响应请求，标记为 degraded 而不是失败
+    let services = state.supervisor.health();
+    let corrupted_objects = state.storage.corrupted_objects();
+    let status = if services.iter().all(|s| s.state == "running") && corrupted_objects.is_empty() {
+        "ok"
+    } else {
+        "degraded"
+    };
+    Json(ApiResponse::success(HealthStatus {
+        status: status.to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        time: state.clock.now(),
+        services,
+        corrupted_objects,
+    }))
 }
 
-async fn get_file(State(state): State<AppState>, Path(path): Path<String>) -> impl IntoResponse {
-    let file_path = state.storage_path.join(&path);
+/// 本服务端实现的可选功能
+const FEATURES: [&str; 19] = [
+    feature::CONTENT_HASH,
+    feature::STREAMED_LISTING,
+    feature::RANGE_DOWNLOAD,
+    feature::METADATA,
+    feature::SYNC_PLAN,
+    feature::CHUNKED_STORAGE,
+    feature::RESUMABLE_UPLOAD,
+    feature::VERSION_HISTORY,
+    feature::RAW_DOWNLOAD,
+    feature::PENDING_CHANGES,
+    feature::CHANGE_FEED,
+    feature::CONFLICTS,
+    feature::DELTA_SYNC,
+    feature::MOVE,
+    feature::SYNC_DIRECTIONS,
+    feature::COPY,
+    feature::TIME_TRAVEL,
+    feature::HASH_LISTING,
+    feature::DIRECTORIES,
+];
 
-    if !file_path.exists() {
-        return (
-            StatusCode::NOT_FOUND,
-            Json(ApiResponse::error("File not found")),
-        );
+async fn get_capabilities(State(state): State<AppState>) -> impl IntoResponse {
+    let mut features: Vec<String> = FEATURES.iter().map(|f| f.to_string()).collect();
+    if state.trash.enabled() {
+        features.push(feature::TRASH.to_string());
     }
-
-    if file_path.is_dir() {
-        match list_directory(&file_path, &state.storage_path) {
-            Ok(files) => (StatusCode::OK, Json(ApiResponse::success(files))),
-            Err(e) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::error(&e.to_string())),
-            ),
-        }
-    } else {
-        match tokio::fs::read(&file_path).await {
-            Ok(content) => {
-                let hash = state.storage.compute_hash(&file_path).await.ok();
-                let db_record = state.repository.get_file_by_path(&path).await.ok();
-
-                let info = FileInfo {
-                    name: file_path
-                        .file_name()
-                        .map(|n| n.to_string_lossy().to_string())
-                        .unwrap_or_default(),
-                    path,
-                    is_dir: false,
-                    size: content.len() as u64,
-                    modified: file_path
-                        .metadata()
-                        .ok()
-                        .and_then(|m| m.modified().ok())
-                        .map(|t| {
-                            let datetime: chrono::DateTime<chrono::Utc> = t.into();
-                            datetime.to_rfc3339()
-                        }),
-                    hash,
-                    version: db_record.map(|r| r.version),
-                };
-                (StatusCode::OK, Json(ApiResponse::success(info)))
-            }
-            Err(e) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::error(&e.to_string())),
-            ),
-        }
+    if state.auth.is_some() {
+        features.push(feature::AUTH.to_string());
+        features.push(feature::QUOTAS.to_string());
     }
+    Json(ApiResponse::success(Capabilities {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        protocol: PROTOCOL_VERSION,
+        min_protocol: PROTOCOL_VERSION,
+        min_client_version: state.min_client_version.clone(),
+        features,
+        compression: Vec::new(),
+    }))
 }
 
-// [知识点 #130] 文件上传与版本控制集成
-// ----------------------------------------
-// 题目：如何将文件上传与版本控制结合？
-//
-// 讲解：
-// 1. 接收文件内容
-// 2. 计算哈希并存储到对象存储
-// 3. 创建/更新数据库记录
-// 4. 返回文件信息
-//
-// 这样实现了去重存储和版本追踪
-//
-// 思考：如何处理大文件上传？
-// ----------------------------------------
-async fn upload_file(
+async fn register_user(
     State(state): State<AppState>,
-    Path(path): Path<String>,
-    body: String,
+    Json(req): Json<CredentialsRequest>,
 ) -> impl IntoResponse {
-    // [知识点 #136] 文件大小校验
-    // ----------------------------------------
-    // 题目：为什么要限制上传文件大小？
-    //
-    // 讲解：
-    // 限制文件大小可以：
-    // 1. 防止 DoS 攻击（上传超大文件耗尽服务器资源）
-    // 2. 控制存储成本
-    // 3. 保证服务稳定性
-    //
-    // 对于大文件，应该使用分块上传 API
-    //
-    // 思考：如何实现断点续传？
-    // ----------------------------------------
-    if body.len() as u64 > state.max_file_size {
+    let Some(auth) = &state.auth else {
         return (
-            StatusCode::PAYLOAD_TOO_LARGE,
-            Json(ApiResponse::error(&format!(
-                "File too large. Max size: {} bytes, got: {} bytes",
-                state.max_file_size,
-                body.len()
-            ))),
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error(
+                "Authentication is not enabled on this server",
+            )),
+        );
+    };
+    if !auth.allow_registration {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::error("Registration is disabled")),
         );
     }
-
-    let file_path = state.storage_path.join(&path);
-
-    if let Some(parent) = file_path.parent() {
-        if let Err(e) = tokio::fs::create_dir_all(parent).await {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::error(&format!(
-                    "Failed to create directory: {}",
-                    e
-                ))),
-            );
-        }
+    let username = req.username.trim();
+    if username.is_empty()
+        || username
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control())
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(
+                "Username must be non-empty without whitespace",
+            )),
+        );
     }
-
-    // 写入文件
-    if let Err(e) = tokio::fs::write(&file_path, &body).await {
+    if req.password.chars().count() < MIN_PASSWORD_LEN {
         return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ApiResponse::error(&format!("Failed to write file: {}", e))),
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(&format!(
+                "Password must be at least {} characters",
+                MIN_PASSWORD_LEN
+            ))),
         );
     }
 
-    // 存储到对象存储并获取哈希
-    let (hash, size) = match state.storage.store_file(&file_path).await {
-        Ok(result) => result,
+    let password_hash = match AuthService::hash_password(&req.password) {
+        Ok(hash) => hash,
         Err(e) => {
             return (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::error(&format!("Failed to store file: {}", e))),
-            );
+                Json(ApiResponse::error(&e)),
+            )
         }
     };
-
-    // 更新数据库记录
-    let record = match state.repository.get_file_by_path(&path).await {
-        Ok(existing) => {
-            state
-                .repository
-                .update_file(existing.id, Some(hash.clone()), size)
-                .await
+    let user = match state
+        .repository
+        .create_user(NewUserRecord {
+            username: username.to_string(),
+            password_hash,
+        })
+        .await
+    {
+        Ok(user) => user,
+        Err(Error::AlreadyExists(_)) => {
+            return (
+                StatusCode::CONFLICT,
+                Json(ApiResponse::error("Username is already taken")),
+            )
         }
-        Err(_) => {
-            state
-                .repository
-                .create_file(crate::db::NewFileRecord {
-                    path: path.clone(),
-                    hash: Some(hash.clone()),
-                    size,
-                })
-                .await
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error(&e.to_string())),
+            )
         }
     };
 
-    match record {
-        Ok(record) => {
-            let info = FileInfo {
-                name: file_path
-                    .file_name()
-                    .map(|n| n.to_string_lossy().to_string())
-                    .unwrap_or_default(),
-                path,
-                is_dir: false,
-                size,
-                modified: Some(record.updated_at.to_rfc3339()),
-                hash: Some(hash),
-                version: Some(record.version),
-            };
-            (StatusCode::OK, Json(ApiResponse::success(info)))
-        }
+    match auth.issue(&user, req.append_only, state.clock.now()) {
+        Ok(token) => (StatusCode::CREATED, Json(ApiResponse::success(token))),
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ApiResponse::error(&format!(
-                "Failed to update record: {}",
-                e
-            ))),
+            Json(ApiResponse::error(&e)),
         ),
     }
 }
 
-async fn delete_file(State(state): State<AppState>, Path(path): Path<String>) -> impl IntoResponse {
-    let file_path = state.storage_path.join(&path);
-
-    if !file_path.exists() {
+async fn login(
+    State(state): State<AppState>,
+    Json(req): Json<CredentialsRequest>,
+) -> impl IntoResponse {
+    let Some(auth) = &state.auth else {
         return (
             StatusCode::NOT_FOUND,
-            Json(ApiResponse::error("File not found")),
+            Json(ApiResponse::error(
+                "Authentication is not enabled on this server",
+            )),
+        );
+    };
+    // 用户不存在与口令错误返回同样的信息，不泄露用户名是否已注册
+    let user = state
+        .repository
+        .find_user_by_name(req.username.trim())
+        .await
+        .filter(|user| AuthService::verify_password(&req.password, &user.password_hash));
+    let Some(user) = user else {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(ApiResponse::error("Invalid username or password")),
         );
+    };
+
+    match auth.issue(&user, req.append_only, state.clock.now()) {
+        Ok(token) => (StatusCode::OK, Json(ApiResponse::success(token))),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&e)),
+        ),
     }
+}
 
-    // 从数据库删除记录
-    if let Ok(record) = state.repository.get_file_by_path(&path).await {
-        if let Err(e) = state.repository.delete_file(record.id).await {
+async fn current_user(
+    State(state): State<AppState>,
+    user: Option<Extension<AuthenticatedUser>>,
+) -> impl IntoResponse {
+    let Some(Extension(user)) = user else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error(
+                "Authentication is not enabled on this server",
+            )),
+        );
+    };
+    match state.repository.get_user(user.id).await {
+        Ok(record) => (StatusCode::OK, Json(ApiResponse::success(record.info()))),
+        Err(e) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error(&e.to_string())),
+        ),
+    }
+}
+
+async fn get_usage(
+    State(state): State<AppState>,
+    user: Option<Extension<AuthenticatedUser>>,
+) -> impl IntoResponse {
+    let Some(Extension(user)) = user else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error(
+                "Authentication is not enabled on this server",
+            )),
+        );
+    };
+    match state.quotas.usage(user.id).await {
+        Ok(usage) => (StatusCode::OK, Json(ApiResponse::success(usage))),
+        Err(e) => error_response(&e),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetQuotaRequest {
+    /// 为空时改用服务端的默认配额
+    pub quota_bytes: Option<u64>,
+}
+
+async fn set_user_quota(
+    State(state): State<AppState>,
+    Path(id): Path<uuid::Uuid>,
+    headers: HeaderMap,
+    Json(req): Json<SetQuotaRequest>,
+) -> impl IntoResponse {
+    if let Err(rejection) = require_admin(&state, &headers, "Changing a quota") {
+        return rejection;
+    }
+    match state.repository.set_user_quota(id, req.quota_bytes).await {
+        Ok(record) => (StatusCode::OK, Json(ApiResponse::success(record.info()))),
+        Err(e) => error_response(&e),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ErasureQuery {
+    pub confirmation: Option<String>,
+}
+
+// 账户数据接口只在启用认证时存在；只追加令牌既不能导出也不能删除账户
+async fn account_user(
+    state: &AppData,
+    user: Option<&AuthenticatedUser>,
+) -> Result<UserRecord, (StatusCode, Json<ApiResponse>)> {
+    let Some(user) = user else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error(
+                "Authentication is not enabled on this server",
+            )),
+        ));
+    };
+    if let Some(rejection) = append_only_token_rejection(Some(user), "account") {
+        return Err(rejection);
+    }
+    state
+        .repository
+        .get_user(user.id)
+        .await
+        .map_err(|e| error_response(&e))
+}
+
+// 导出在后台进行，客户端轮询 GET /api/account/export/{id}，完成后下载归档
+async fn start_export(
+    State(state): State<AppState>,
+    user: Option<Extension<AuthenticatedUser>>,
+    peer: Option<Extension<PeerIp>>,
+) -> impl IntoResponse {
+    let user = match account_user(&state, user.as_deref()).await {
+        Ok(user) => user,
+        Err(rejection) => return rejection,
+    };
+    let job = state.accounts.start_export(&user).await;
+    firewall::append_audit(
+        &firewall::audit_path(&state.storage_path),
+        &AuditEntry {
+            at: state.clock.now(),
+            ip: peer.map(|peer| peer.0 .0),
+            user: Some(user.username),
+            event: AuditEvent::Exported,
+            method: "POST".to_string(),
+            path: "/api/account/export".to_string(),
+            detail: Some(format!("export {}", job.id)),
+        },
+    );
+    (StatusCode::ACCEPTED, Json(ApiResponse::success(job)))
+}
+
+async fn get_export(
+    State(state): State<AppState>,
+    user: Option<Extension<AuthenticatedUser>>,
+    Path(id): Path<uuid::Uuid>,
+) -> impl IntoResponse {
+    let user = match account_user(&state, user.as_deref()).await {
+        Ok(user) => user,
+        Err(rejection) => return rejection,
+    };
+    match state.accounts.export(user.id, id) {
+        Some(job) => (StatusCode::OK, Json(ApiResponse::success(job))),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Export not found")),
+        ),
+    }
+}
+
+async fn download_export(
+    State(state): State<AppState>,
+    user: Option<Extension<AuthenticatedUser>>,
+    Path(id): Path<uuid::Uuid>,
+    request: Request,
+) -> Response {
+    let user = match account_user(&state, user.as_deref()).await {
+        Ok(user) => user,
+        Err(rejection) => return rejection.into_response(),
+    };
+    let Some(archive) = state.accounts.export_archive(user.id, id) else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Export not found or not ready")),
+        )
+            .into_response();
+    };
+    let mut response =
+        match ServeFile::new_with_mime(archive, &mime_guess::mime::APPLICATION_OCTET_STREAM)
+            .oneshot(request)
+            .await
+        {
+            Ok(response) => response.map(Body::new),
+            Err(e) => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ApiResponse::error(&e.to_string())),
+                )
+                    .into_response()
+            }
+        };
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/zip"),
+    );
+    response.headers_mut().insert(
+        header::CONTENT_DISPOSITION,
+        content_disposition("attachment", "rustcloud-export.zip"),
+    );
+    response
+}
+
+// 删除账户的第一步：取得确认令牌，10 分钟内带着它调用 DELETE /api/account
+async fn request_erasure(
+    State(state): State<AppState>,
+    user: Option<Extension<AuthenticatedUser>>,
+) -> impl IntoResponse {
+    let user = match account_user(&state, user.as_deref()).await {
+        Ok(user) => user,
+        Err(rejection) => return rejection,
+    };
+    let confirmation = state.accounts.request_erasure(user.id);
+    (StatusCode::OK, Json(ApiResponse::success(confirmation)))
+}
+
+async fn erase_account(
+    State(state): State<AppState>,
+    user: Option<Extension<AuthenticatedUser>>,
+    Query(query): Query<ErasureQuery>,
+) -> impl IntoResponse {
+    let user = match account_user(&state, user.as_deref()).await {
+        Ok(user) => user,
+        Err(rejection) => return rejection,
+    };
+    let confirmed = query
+        .confirmation
+        .is_some_and(|token| state.accounts.confirm_erasure(user.id, &token));
+    if !confirmed {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(
+                "Missing or expired confirmation token; request one with POST /api/account/erasure",
+            )),
+        );
+    }
+
+    let mut summary = ErasureSummary::default();
+    let mut erased = Vec::new();
+    for record in state.files.list_files().await.unwrap_or_default() {
+        if record.owner != Some(user.id) {
+            continue;
+        }
+        let held = state
+            .repository
+            .find_legal_hold(&record.path)
+            .await
+            .is_some();
+        if held || state.append_only.covers(&record.path) {
+            if let Err(e) = state.files.set_file_owner(record.id, None).await {
+                tracing::warn!("Failed to clear owner of {}: {}", record.path, e);
+            }
+            summary.retained.push(record.path);
+        } else {
+            erased.push(record);
+        }
+    }
+
+    // 与删除文件相同：记录删除之后才能判断对象是否还被引用
+    let objects = match &state.shredder {
+        Some(shredder) => shredder.objects_of(&erased).await,
+        None => Vec::new(),
+    };
+    let mut shredded = ShredSummary::default();
+    let mut erased_paths = Vec::new();
+    for record in erased {
+        let _guard = state.path_locks.lock(&case_key(&record.path)).await;
+        if let Err(e) = state.files.delete_file(record.id).await {
             tracing::warn!("Failed to delete file record: {}", e);
+            continue;
+        }
+        summary.files += 1;
+        summary.bytes += record.size;
+        state.feed.deleted(&record.path);
+        if let Some(proxy) = &state.proxy {
+            proxy.enqueue(PendingWrite::Delete {
+                path: record.path.clone(),
+                recursive: false,
+            });
+        }
+
+        let file_path = state.storage_path.join(&record.path);
+        if file_path.is_file() {
+            state.expected_writes.expect_removal(&record.path);
+            let removed = match &state.shredder {
+                Some(shredder) => shredder.shred(Some(&file_path), &[]).await.map(|s| {
+                    shredded.files += s.files;
+                    shredded.bytes += s.bytes;
+                }),
+                None => tokio::fs::remove_file(&file_path)
+                    .await
+                    .map_err(Error::from),
+            };
+            if let Err(e) = removed {
+                tracing::warn!("Failed to remove {}: {}", record.path, e);
+            }
+        }
+        state.notifier.notify(Notification::FileChanged {
+            path: record.path.clone(),
+            change: FileChange::Deleted,
+        });
+        erased_paths.push(record.path);
+    }
+    if let Some(shredder) = &state.shredder {
+        match shredder.shred(None, &objects).await {
+            Ok(s) => {
+                shredded.files += s.files;
+                shredded.bytes += s.bytes;
+            }
+            Err(e) => tracing::warn!("Failed to shred erased objects: {}", e),
+        }
+        shredder.record(
+            state.clock.now(),
+            None,
+            None,
+            "DELETE",
+            "/api/account",
+            shredded,
+        );
+    }
+
+    // 已删除文件保留的历史版本和路径记录同样属于该用户；保留下来的文件不动
+    let remaining: std::collections::HashSet<uuid::Uuid> = state
+        .files
+        .list_files()
+        .await
+        .unwrap_or_default()
+        .iter()
+        .map(|record| record.id)
+        .collect();
+    let mut purged = std::collections::HashSet::new();
+    for departure in state.repository.list_departures().await.unwrap_or_default() {
+        if departure.owner != Some(user.id)
+            || remaining.contains(&departure.file_id)
+            || !purged.insert(departure.file_id)
+        {
+            continue;
+        }
+        if let Err(e) = state.repository.purge_file_history(departure.file_id).await {
+            tracing::warn!("Failed to purge history of {}: {}", departure.path, e);
+        }
+    }
+
+    // 用户创建的分享链接，以及指向已删除文件的链接
+    for share in state.repository.list_shares().await.unwrap_or_default() {
+        let ours = share.created_by == Some(user.id) || erased_paths.contains(&share.path);
+        if ours && state.repository.delete_share(share.id).await.is_ok() {
+            summary.shares += 1;
+        }
+    }
+    for comment in state.repository.list_comments().await.unwrap_or_default() {
+        if comment.author.eq_ignore_ascii_case(&user.username)
+            && state.repository.delete_comment(comment.id).await.is_ok()
+        {
+            summary.comments += 1;
+        }
+    }
+    state.accounts.forget(user.id).await;
+
+    let audit_path = firewall::audit_path(&state.storage_path);
+    let username = user.username.clone();
+    let anonymized = {
+        let audit_path = audit_path.clone();
+        tokio::task::spawn_blocking(move || firewall::anonymize_audit(&audit_path, &username))
+            .await
+            .map_err(|e| Error::Io(std::io::Error::other(e)))
+            .and_then(|result| result)
+    };
+    match anonymized {
+        Ok(count) => summary.audit_entries = count,
+        Err(e) => tracing::warn!("Failed to anonymize audit log: {}", e),
+    }
+
+    if let Err(e) = state.repository.delete_user(user.id).await {
+        return error_response(&e);
+    }
+
+    // 这条记录本身不带用户名和地址
+    firewall::append_audit(
+        &audit_path,
+        &AuditEntry {
+            at: state.clock.now(),
+            ip: None,
+            user: None,
+            event: AuditEvent::Erased,
+            method: "DELETE".to_string(),
+            path: "/api/account".to_string(),
+            detail: Some(format!(
+                "{} file(s), {} share(s), {} comment(s) erased; {} file(s) retained",
+                summary.files,
+                summary.shares,
+                summary.comments,
+                summary.retained.len()
+            )),
+        },
+    );
+    tracing::info!(
+        "Erased account: {} file(s), {} bytes, {} retained",
+        summary.files,
+        summary.bytes,
+        summary.retained.len()
+    );
+    (StatusCode::OK, Json(ApiResponse::success(summary)))
+}
+
+async fn list_files(
+    State(state): State<AppState>,
+    Query(query): Query<ListFilesQuery>,
+) -> Response {
+    let base_path = &state.storage_path;
+    let dir = query.path.unwrap_or_default();
+    let target_path = base_path.join(&dir);
+
+    if let Some(at) = query.as_of {
+        // 本地只有上游的缓存，没有完整的历史
+        if state.proxy.is_some() {
+            return (
+                StatusCode::NOT_IMPLEMENTED,
+                Json(ApiResponse::error(
+                    "Listing past states is not supported in proxy mode",
+                )),
+            )
+                .into_response();
+        }
+        let mut records = match state.versions.files_as_of(at).await {
+            Ok(records) => records,
+            Err(e) => return error_response(&e).into_response(),
+        };
+        records.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut files = Vec::new();
+        merge_record_entries(&mut files, &records, &dir);
+        if files.is_empty() && !dir.is_empty() {
+            return Json(ApiResponse::error(
+                &Error::NotFound(target_path).to_string(),
+            ))
+            .into_response();
+        }
+        if query.stream {
+            return ndjson_response(files);
+        }
+        return Json(ApiResponse::success(files)).into_response();
+    }
+
+    // 代理模式下列出上游的目录，上游不可用时列出本地缓存
+    if let Some(proxy) = &state.proxy {
+        match proxy.list(&dir).await {
+            Ok(mut files) => {
+                overlay_pending(&state, proxy, &dir, &mut files).await;
+                merge_mount_entries(&state, &mut files, &dir);
+                if query.stream {
+                    return ndjson_response(files);
+                }
+                return Json(ApiResponse::success(files)).into_response();
+            }
+            Err(e) => tracing::debug!("Listing {} from cache: {}", dir, e),
+        }
+    }
+
+    let files = if state.materialize_files || target_path.is_dir() {
+        let mut files = match state.listings.list(&target_path, base_path).await {
+            Ok(files) => (*files).clone(),
+            Err(e) if state.materialize_files => {
+                return Json(ApiResponse::error(&e.to_string())).into_response()
+            }
+            Err(_) => Vec::new(),
+        };
+        if state.materialize_files {
+            let records = state.files.list_files().await.unwrap_or_default();
+            attach_record_metadata(&mut files, &records);
+        } else {
+            // 文件和目录记录取自同一快照，列出过程中的移动不会让条目重复或丢失
+            let snapshot = match state.repository.snapshot().await {
+                Ok(snapshot) => snapshot,
+                Err(e) => return error_response(&e).into_response(),
+            };
+            merge_record_entries(&mut files, &snapshot.files, &dir);
+            merge_directory_entries(&mut files, &snapshot.directories, &dir);
+            attach_record_metadata(&mut files, &snapshot.files);
+        }
+        merge_mount_entries(&state, &mut files, &dir);
+        files
+    } else {
+        let snapshot = match state.repository.snapshot().await {
+            Ok(snapshot) => snapshot,
+            Err(e) => return error_response(&e).into_response(),
+        };
+        let mut files = Vec::new();
+        merge_record_entries(&mut files, &snapshot.files, &dir);
+        merge_directory_entries(&mut files, &snapshot.directories, &dir);
+        merge_mount_entries(&state, &mut files, &dir);
+        // 空目录的记录本身就说明目录存在
+        let recorded = snapshot.directories.iter().any(|d| d.path == dir);
+        if files.is_empty() && !dir.is_empty() && !recorded {
+            return Json(ApiResponse::error(
+                &Error::NotFound(target_path).to_string(),
+            ))
+            .into_response();
+        }
+        files
+    };
+
+    if query.stream {
+        return ndjson_response(files);
+    }
+    Json(ApiResponse::success(files)).into_response()
+}
+
+/// 每批序列化的条目数，避免把整个列表拼成一个大字符串
+const NDJSON_BATCH: usize = 1000;
+
+// 每行一个 FileInfo，客户端收到一行就能处理一行
+fn ndjson_response(files: Vec<FileInfo>) -> Response {
+    let batches = futures_util::stream::iter(files)
+        .chunks(NDJSON_BATCH)
+        .map(|batch| {
+            let mut buf = Vec::new();
+            for file in &batch {
+                serde_json::to_writer(&mut buf, file)?;
+                buf.push(b'\n');
+            }
+            Ok::<_, serde_json::Error>(Bytes::from(buf))
+        });
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(batches),
+    )
+        .into_response()
+}
+
+async fn create_folder(
+    State(state): State<AppState>,
+    Json(req): Json<CreateFolderRequest>,
+) -> impl IntoResponse {
+    let path = match logical_path::normalize(&req.path) {
+        Ok(path) => path,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error(&format!("Invalid path: {}", e))),
+            )
+        }
+    };
+    match make_directory(&state, path).await {
+        Ok(info) => (StatusCode::OK, Json(ApiResponse::success(info))),
+        Err(rejection) => rejection,
+    }
+}
+
+// MKCOL 风格：路径就是要创建的目录，缺少的上级目录一并创建
+async fn create_directory(
+    State(state): State<AppState>,
+    LogicalPath(path): LogicalPath,
+) -> impl IntoResponse {
+    match make_directory(&state, path).await {
+        Ok(info) => (StatusCode::CREATED, Json(ApiResponse::success(info))),
+        Err(rejection) => rejection,
+    }
+}
+
+// 磁盘上建好目录之后再记入数据库：未落盘模式下空目录只能靠记录出现在列表和同步计划中
+async fn make_directory(
+    state: &AppData,
+    path: String,
+) -> std::result::Result<FileInfo, (StatusCode, Json<ApiResponse>)> {
+    if let Some(rejection) = direction_rejection(state, &path, false) {
+        return Err(rejection);
+    }
+    let folder_path = path_guard::resolve(&state.storage_path, &path)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error(&e.to_string())),
+            )
+        })?;
+    if let Some(hold) = state.repository.find_legal_hold(&path).await {
+        return Err(held_response(&hold));
+    }
+
+    let _guard = state.path_locks.lock(&case_key(&path)).await;
+    let recorded = state
+        .repository
+        .list_directories()
+        .await
+        .unwrap_or_default()
+        .iter()
+        .any(|d| case_key(&d.path) == case_key(&path));
+    if recorded || upload_target_exists(state, &path).await {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(ApiResponse::error("Folder already exists")),
+        ));
+    }
+
+    if let Err(e) = tokio::fs::create_dir_all(&folder_path).await {
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&format!("Failed to create folder: {}", e))),
+        ));
+    }
+    let directory = state
+        .repository
+        .create_directory(&path)
+        .await
+        .map_err(|e| error_response(&e))?;
+    if let Some(proxy) = &state.proxy {
+        proxy.enqueue(PendingWrite::Folder { path: path.clone() });
+    }
+    Ok(FileInfo {
+        name: path.rsplit('/').next().unwrap_or(&path).to_string(),
+        path: directory.path,
+        is_dir: true,
+        size: 0,
+        modified: Some(directory.created_at.to_rfc3339()),
+        hash: None,
+        version: None,
+        deduplicated: false,
+        metadata: BTreeMap::new(),
+    })
+}
+
+// 通配符段之后不能再接固定段，"{path}/raw" 由 handler 识别：去掉后缀后是一个文件时下载其原始内容，
+// 否则按普通路径处理（文件不能有子项，"docs/raw" 这样的路径不会被误认）；"{path}/signature" 同理
+async fn get_file(
+    State(state): State<AppState>,
+    Path(path): Path<String>,
+    request: Request,
+) -> Response {
+    if let Some(file) = path.strip_suffix("/raw") {
+        match resolve_content_path(&state, file).await {
+            Ok(source) => {
+                let disposition = content_disposition("attachment", file);
+                let identity = request.extensions().get::<ClientIdentity>().cloned();
+                let record = state.files.get_file_by_path(file).await.ok();
+                let response = send_file(&state, file, &source, request, disposition).await;
+                // 完整下载、续传或 304 之后设备都持有当前版本
+                if let Some(record) = record.filter(|_| {
+                    response.status().is_success() || response.status() == StatusCode::NOT_MODIFIED
+                }) {
+                    record_base(&state, identity.as_ref(), &record).await;
+                }
+                return response;
+            }
+            // 文件存在只是内容损坏，不能再当作普通路径查询
+            Err((status, message)) if (status, message) == CORRUPTED => {
+                return (status, Json(ApiResponse::error(message))).into_response()
+            }
+            Err(_) => {}
+        }
+    }
+    if let Some(file) = path.strip_suffix("/signature") {
+        if let Ok(source) = resolve_content_path(&state, file).await {
+            return file_signature(&state, &source).await.into_response();
+        }
+    }
+    file_info(state, path).await.into_response()
+}
+
+// 增量上传的第一步：客户端拿到当前内容的块签名，只发送与这些块不同的部分
+async fn file_signature(
+    state: &AppData,
+    source: &std::path::Path,
+) -> (StatusCode, Json<ApiResponse>) {
+    match state.storage.signature(source).await {
+        Ok(signature) => (StatusCode::OK, Json(ApiResponse::success(signature))),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&format!(
+                "Failed to compute signature: {}",
+                e
+            ))),
+        ),
+    }
+}
+
+async fn file_info(state: AppState, path: String) -> impl IntoResponse {
+    // 历史版本只记录在本地元数据中，代理模式下也不向上游查询
+    if let Some(file) = path.strip_suffix("/versions") {
+        if let Ok(record) = state.files.get_file_by_path(file).await {
+            return match state.versions.history(&record).await {
+                Ok(versions) => (StatusCode::OK, Json(ApiResponse::success(versions))),
+                Err(e) => (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ApiResponse::error(&format!(
+                        "Failed to list versions: {}",
+                        e
+                    ))),
+                ),
+            };
+        }
+    }
+    match read_through(&state, &path).await {
+        Some(Upstream::Listing(mut files)) => {
+            if let Some(proxy) = &state.proxy {
+                overlay_pending(&state, proxy, &path, &mut files).await;
+            }
+            merge_mount_entries(&state, &mut files, &path);
+            return (StatusCode::OK, Json(ApiResponse::success(files)));
+        }
+        Some(Upstream::Missing) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::error("File not found")),
+            )
+        }
+        _ => {}
+    }
+    let file_path = state.storage_path.join(&path);
+
+    if !file_path.exists() {
+        // 未落盘模式下，文件只存在于元数据和对象存储中
+        if !state.materialize_files {
+            if let Ok(record) = state.files.get_file_by_path(&path).await {
+                return (
+                    StatusCode::OK,
+                    Json(ApiResponse::success(FileInfo::from_record(&record))),
+                );
+            }
+            let records = state.files.list_files().await.unwrap_or_default();
+            let mut files = Vec::new();
+            merge_record_entries(&mut files, &records, &path);
+            merge_mount_entries(&state, &mut files, &path);
+            if !files.is_empty() {
+                return (StatusCode::OK, Json(ApiResponse::success(files)));
+            }
+        }
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("File not found")),
+        );
+    }
+
+    if file_path.is_dir() {
+        match state.listings.list(&file_path, &state.storage_path).await {
+            Ok(files) => {
+                let mut files = (*files).clone();
+                merge_mount_entries(&state, &mut files, &path);
+                (StatusCode::OK, Json(ApiResponse::success(files)))
+            }
+            Err(e) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error(&e.to_string())),
+            ),
+        }
+    } else {
+        match tokio::fs::metadata(&file_path).await {
+            Ok(meta) => {
+                state.access.touch(&path);
+                let hash = state.storage.compute_hash(&file_path).await.ok();
+                let db_record = state.files.get_file_by_path(&path).await.ok();
+
+                let info = FileInfo {
+                    name: file_path
+                        .file_name()
+                        .map(|n| n.to_string_lossy().to_string())
+                        .unwrap_or_default(),
+                    path,
+                    is_dir: false,
+                    size: meta.len(),
+                    modified: meta.modified().ok().map(|t| {
+                        let datetime: chrono::DateTime<chrono::Utc> = t.into();
+                        datetime.to_rfc3339()
+                    }),
+                    hash,
+                    version: db_record.as_ref().map(|r| r.version),
+                    deduplicated: false,
+                    metadata: db_record.map(|r| r.metadata).unwrap_or_default(),
+                };
+                (StatusCode::OK, Json(ApiResponse::success(info)))
+            }
+            Err(e) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error(&e.to_string())),
+            ),
         }
     }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PreviewQuery {
+    /// 返回的行数，默认 100
+    pub lines: Option<usize>,
+    /// 取末尾的行而不是开头
+    #[serde(default)]
+    pub tail: bool,
+    /// 指定 offset/length 时按字节读取，忽略 lines/tail
+    pub offset: Option<u64>,
+    pub length: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FilePreview {
+    pub path: String,
+    pub size: u64,
+    pub offset: u64,
+    pub truncated: bool,
+    pub content: String,
+}
+
+// 通配符段之后不能再接固定段，所以预览挂在 /api/preview/{*path} 而不是 /api/files/{*path}/preview
+async fn preview_file(
+    State(state): State<AppState>,
+    Path(path): Path<String>,
+    Query(query): Query<PreviewQuery>,
+) -> impl IntoResponse {
+    let range = if query.offset.is_some() || query.length.is_some() {
+        PreviewRange::Bytes {
+            offset: query.offset.unwrap_or(0),
+            length: query.length.unwrap_or(MAX_PREVIEW_BYTES),
+        }
+    } else {
+        let lines = query.lines.unwrap_or(DEFAULT_PREVIEW_LINES);
+        if query.tail {
+            PreviewRange::Tail(lines)
+        } else {
+            PreviewRange::Head(lines)
+        }
+    };
+
+    let source = match resolve_content_path(&state, &path).await {
+        Ok(source) => source,
+        Err((status, message)) => return (status, Json(ApiResponse::error(message))),
+    };
+
+    state.access.touch(&path);
+    match preview::preview_file(&source, range).await {
+        Ok(preview) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(FilePreview {
+                path,
+                size: preview.size,
+                offset: preview.offset,
+                truncated: preview.truncated,
+                content: preview.content,
+            })),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&e.to_string())),
+        ),
+    }
+}
+
+// [知识点 #148] HTTP Range 与媒体拖动
+// ----------------------------------------
+// 题目：为什么 <video> 拖动进度条时不需要重新下载整个文件？
+//
+// 讲解：
+// 浏览器和播放器通过 Range 请求只取需要的片段：
+// 1. 服务端在响应中声明 Accept-Ranges: bytes
+// 2. 拖动时客户端发送 Range: bytes=1048576-
+// 3. 服务端返回 206 Partial Content 和 Content-Range
+//
+// 媒体能否直接播放还取决于：
+// - Content-Type 正确（video/mp4 而不是 application/octet-stream）
+// - Content-Disposition: inline（attachment 会触发下载）
+// - 响应体是原始字节，不能包在 JSON 里
+//
+// tower-http 的 ServeFile 已实现 Range、If-Modified-Since 等细节，这里只需提供文件位置和类型
+//
+// 思考：多段 Range（bytes=0-99,200-299）应该如何响应？
+// ----------------------------------------
+async fn stream_file(
+    State(state): State<AppState>,
+    Path(path): Path<String>,
+    request: Request,
+) -> Response {
+    serve_content(&state, &path, request).await
+}
+
+// 原样返回文件内容，Range/条件请求由 ServeFile 处理
+async fn serve_content(state: &AppData, path: &str, request: Request) -> Response {
+    let source = match resolve_content_path(state, path).await {
+        Ok(source) => source,
+        Err((status, message)) => {
+            return (status, Json(ApiResponse::error(message))).into_response()
+        }
+    };
+    let disposition = content_disposition("inline", path);
+    send_file(state, path, &source, request, disposition).await
+}
+
+// Range、Content-Length 和 If-Modified-Since 由 ServeFile 处理，内容边读边发送；
+// ETag 取记录中的内容哈希，If-None-Match 命中时直接返回 304，If-Range 按 ETag 判断
+async fn send_file(
+    state: &AppData,
+    path: &str,
+    source: &std::path::Path,
+    mut request: Request,
+    disposition: HeaderValue,
+) -> Response {
+    if !source.exists() {
+        if let Some(response) =
+            send_object_stream(state, path, request.headers(), &disposition).await
+        {
+            return response;
+        }
+    }
+    let etag = stored_etag(state, path, source).await;
+    if let Some(etag) = &etag {
+        if let Some(condition) = request.headers().get(header::IF_NONE_MATCH) {
+            if etag_matches(condition, etag) {
+                return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag.clone())]).into_response();
+            }
+            // 带 If-None-Match 时忽略 If-Modified-Since（RFC 9110 13.1.3）
+            request.headers_mut().remove(header::IF_MODIFIED_SINCE);
+        }
+    }
+    // ServeFile 不处理 If-Range：续传时内容已经变化（或无法确认没变）就忽略 Range，返回完整内容
+    if let Some(condition) = request.headers().get(header::IF_RANGE) {
+        let unchanged = etag.as_ref().is_some_and(|etag| condition == etag);
+        if !unchanged {
+            request.headers_mut().remove(header::RANGE);
+        }
+        request.headers_mut().remove(header::IF_RANGE);
+    }
+
+    // 对象文件没有扩展名，类型按逻辑路径推断
+    let mime = mime_guess::from_path(path).first_or_octet_stream();
+    let mut response = match ServeFile::new_with_mime(source, &mime)
+        .oneshot(request)
+        .await
+    {
+        Ok(response) => response.map(Body::new),
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error(&e.to_string())),
+            )
+                .into_response()
+        }
+    };
+    response
+        .headers_mut()
+        .insert(header::CONTENT_DISPOSITION, disposition);
+    if let Some(etag) = etag {
+        response.headers_mut().insert(header::ETAG, etag);
+    }
+    if response.status().is_success() {
+        state.access.touch(path);
+    }
+    response
+}
+
+// 分块存储的对象没有单独的对象文件，ServeFile 无从打开：按清单依次读取各块，边读边发送。
+// 单段 Range 按清单中的块偏移只读取相关的块，返回 206；多段 Range 按 RFC 9110 忽略，返回完整内容
+async fn send_object_stream(
+    state: &AppData,
+    path: &str,
+    headers: &HeaderMap,
+    disposition: &HeaderValue,
+) -> Option<Response> {
+    let record = state.files.get_file_by_path(path).await.ok()?;
+    let hash = record.hash?;
+    let etag = HeaderValue::from_str(&format!("\"{}\"", hash)).ok()?;
+    if let Some(condition) = headers.get(header::IF_NONE_MATCH) {
+        if etag_matches(condition, &etag) {
+            return Some((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+        }
+    }
+    // If-Range 与 ETag 不符时内容已经变化，忽略 Range
+    let range = headers
+        .get(header::RANGE)
+        .filter(|_| {
+            headers
+                .get(header::IF_RANGE)
+                .is_none_or(|condition| condition == etag)
+        })
+        .and_then(|range| byte_range(range, record.size));
+    let content_range = match &range {
+        Some(Ok(range)) => format!("bytes {}-{}/{}", range.start, range.end - 1, record.size),
+        Some(Err(())) => {
+            return Some(
+                (
+                    StatusCode::RANGE_NOT_SATISFIABLE,
+                    [(header::CONTENT_RANGE, format!("bytes */{}", record.size))],
+                )
+                    .into_response(),
+            );
+        }
+        None => String::new(),
+    };
+    let object = match &range {
+        Some(Ok(range)) => state
+            .storage
+            .stream_object_range(&hash, range.start, range.end - range.start)
+            .await
+            .map(|content| (range.end - range.start, content)),
+        _ => state.storage.stream_object(&hash).await,
+    };
+    let (size, content) = match object {
+        Ok(object) => object,
+        Err(Error::Corrupted(_)) => {
+            let (status, message) = CORRUPTED;
+            return Some((status, Json(ApiResponse::error(message))).into_response());
+        }
+        Err(_) => return None,
+    };
+    let mime = mime_guess::from_path(path).first_or_octet_stream();
+    state.access.touch(path);
+    let mut response = Response::builder()
+        .header(header::CONTENT_TYPE, mime.as_ref())
+        .header(header::CONTENT_LENGTH, size)
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::ETAG, etag)
+        .header(header::CONTENT_DISPOSITION, disposition);
+    if range.is_some() {
+        response = response
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(header::CONTENT_RANGE, content_range);
+    }
+    response.body(Body::from_stream(content)).ok()
+}
+
+// 解析单段 Range（bytes=a-b、bytes=a-、bytes=-n）；无法识别或多段时返回 None，
+// 起点超出内容时返回 Err，按 416 处理
+fn byte_range(header: &HeaderValue, size: u64) -> Option<Result<std::ops::Range<u64>, ()>> {
+    let spec = header.to_str().ok()?.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (first, last) = spec.split_once('-')?;
+    let (first, last) = (first.trim(), last.trim());
+    let range = if first.is_empty() {
+        let suffix: u64 = last.parse().ok()?;
+        size.saturating_sub(suffix)..size
+    } else {
+        let start: u64 = first.parse().ok()?;
+        let end = match last {
+            "" => size,
+            last => last.parse::<u64>().ok()?.saturating_add(1).min(size),
+        };
+        if end <= start && start < size {
+            return None;
+        }
+        start..end
+    };
+    if range.start < range.end {
+        Some(Ok(range))
+    } else {
+        Some(Err(()))
+    }
+}
+
+// 记录的大小与磁盘上的内容不一致时（例如落盘文件被外部修改、尚未重新索引），哈希已过期，不提供 ETag
+async fn stored_etag(state: &AppData, path: &str, source: &std::path::Path) -> Option<HeaderValue> {
+    let record = state.files.get_file_by_path(path).await.ok()?;
+    let size = tokio::fs::metadata(source).await.ok()?.len();
+    if record.size != size {
+        return None;
+    }
+    HeaderValue::from_str(&format!("\"{}\"", record.hash?)).ok()
+}
+
+// GET 使用弱比较：忽略 W/ 前缀
+fn etag_matches(condition: &HeaderValue, etag: &HeaderValue) -> bool {
+    let Ok(condition) = condition.to_str() else {
+        return false;
+    };
+    let etag = etag.to_str().unwrap_or_default();
+    condition.split(',').map(str::trim).any(|candidate| {
+        candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag
+    })
+}
+
+// [知识点 #130] 文件上传与版本控制集成
+// ----------------------------------------
+// 题目：如何将文件上传与版本控制结合？
+//
+// 讲解：
+// 1. 接收文件内容
+// 2. 计算哈希并存储到对象存储
+// 3. 创建/更新数据库记录
+// 4. 返回文件信息
+//
+// 这样实现了去重存储和版本追踪
+//
+// 思考：如何处理大文件上传？
+// ----------------------------------------
+/// 上传目标路径已存在时的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnConflict {
+    /// 写入新版本（默认）
+    #[default]
+    Overwrite,
+    /// 另存为 "name (1).ext"
+    Rename,
+    /// 返回 409
+    Fail,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UploadQuery {
+    #[serde(default)]
+    pub on_conflict: OnConflict,
+}
+
+/// 只差大小写的已有文件也算存在，Windows 客户端上它们是同一个文件
+async fn upload_target_exists(state: &AppData, path: &str) -> bool {
+    if state.storage_path.join(path).exists() {
+        return true;
+    }
+    // 同样写法的上级目录也会出现在结果中，逐个确认是不是文件
+    for spelling in state.files.path_spellings(path).await.unwrap_or_default() {
+        if state.files.get_file_by_path(&spelling).await.is_ok() {
+            return true;
+        }
+    }
+    false
+}
+
+/// 沿用已有文件和目录的大小写写法：逐段查找，规则同 adopt_existing_case
+async fn existing_case_path(state: &AppData, path: &str) -> String {
+    let mut adopted = String::new();
+    for segment in path.split('/') {
+        let candidate = match adopted.as_str() {
+            "" => segment.to_string(),
+            parent => format!("{}/{}", parent, segment),
+        };
+        let spellings = state
+            .files
+            .path_spellings(&candidate)
+            .await
+            .unwrap_or_default();
+        // 前面的段已换成已有写法，只看与之一致的；完全相同的写法优先
+        let siblings: Vec<String> = spellings
+            .into_iter()
+            .filter(|s| adopted.is_empty() || s.starts_with(&format!("{}/", adopted)))
+            .collect();
+        adopted = if siblings.contains(&candidate) {
+            candidate
+        } else {
+            siblings.into_iter().next().unwrap_or(candidate)
+        };
+    }
+    adopted
+}
+
+/// "docs/report.pdf" 的第 n 个备选名 "docs/report (n).pdf"
+fn numbered_path(path: &str, n: usize) -> String {
+    let (dir, name) = match path.rsplit_once('/') {
+        Some((dir, name)) => (Some(dir), name),
+        None => (None, path),
+    };
+    // 以点开头的隐藏文件（.bashrc）整体视为文件名
+    let renamed = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{} ({}).{}", stem, n, ext),
+        _ => format!("{} ({})", name, n),
+    };
+    match dir {
+        Some(dir) => format!("{}/{}", dir, renamed),
+        None => renamed,
+    }
+}
+
+/// "docs/report.pdf" 的冲突副本 "docs/report (conflicted copy from laptop).pdf"，
+/// 已存在时依次尝试 "(conflicted copy 2 from laptop)"、"(conflicted copy 3 from laptop)"……
+async fn conflicted_copy_path(state: &AppData, path: &str, device: &str) -> String {
+    let (dir, name) = match path.rsplit_once('/') {
+        Some((dir, name)) => (Some(dir), name),
+        None => (None, path),
+    };
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, Some(ext)),
+        _ => (name, None),
+    };
+    // 设备名里的斜杠会被当成目录
+    let device = device.replace(['/', '\\'], "-");
+    let mut n = 1;
+    loop {
+        let label = match n {
+            1 => format!("conflicted copy from {}", device),
+            n => format!("conflicted copy {} from {}", n, device),
+        };
+        let renamed = match ext {
+            Some(ext) => format!("{} ({}).{}", stem, label, ext),
+            None => format!("{} ({})", stem, label),
+        };
+        let candidate = match dir {
+            Some(dir) => format!("{}/{}", dir, renamed),
+            None => renamed,
+        };
+        if !upload_target_exists(state, &candidate).await {
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
+/// 设备上传或下载之后持有该版本，之后据此判断它的上传是否基于过期版本
+async fn record_base(state: &AppData, identity: Option<&ClientIdentity>, record: &FileRecord) {
+    let Some(identity) = identity else {
+        return;
+    };
+    if let Err(e) = state
+        .sync_engine
+        .record_base(identity.device_id, record.id, record.version)
+        .await
+    {
+        tracing::warn!("Failed to record synced version of {}: {}", record.path, e);
+    }
+}
+
+/// 图片只读取开头这么多字节来提取尺寸和拍摄时间
+const MEDIA_PREFIX_LIMIT: usize = 256 * 1024;
+
+/// what 是 "File" 或 "Chunk"
+fn too_large(what: &str, limit: u64) -> (StatusCode, Json<ApiResponse>) {
+    (
+        StatusCode::PAYLOAD_TOO_LARGE,
+        Json(ApiResponse::error(&format!(
+            "{} too large. Max size: {} bytes",
+            what, limit
+        ))),
+    )
+}
+
+// 边收边写入暂存文件，累计大小超过 limit 时立即中止，不等请求体收完
+async fn receive_upload<S, E>(
+    state: &AppData,
+    mut stream: S,
+    what: &str,
+    limit: u64,
+) -> Result<StagedUpload, (StatusCode, Json<ApiResponse>)>
+where
+    S: futures_util::Stream<Item = Result<Bytes, E>> + Unpin,
+    E: std::fmt::Display,
+{
+    let storage_error = |e: Error| {
+        (
+            e.status_code(),
+            Json(ApiResponse::error(&format!("Failed to store file: {}", e))),
+        )
+    };
+    let mut upload = state.storage.begin_upload().await.map_err(storage_error)?;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error(&format!(
+                    "Failed to read upload body: {}",
+                    e
+                ))),
+            )
+        })?;
+        if upload.size() + chunk.len() as u64 > limit {
+            return Err(too_large(what, limit));
+        }
+        upload.write(&chunk).await.map_err(storage_error)?;
+    }
+    Ok(upload)
+}
+
+async fn upload_file(
+    State(state): State<AppState>,
+    LogicalPath(path): LogicalPath,
+    Query(query): Query<UploadQuery>,
+    identity: Option<Extension<ClientIdentity>>,
+    user: Option<Extension<AuthenticatedUser>>,
+    headers: HeaderMap,
+    body: Body,
+) -> impl IntoResponse {
+    // [知识点 #136] 文件大小校验
+    // ----------------------------------------
+    // 题目：为什么要限制上传文件大小？
+    //
+    // 讲解：
+    // 限制文件大小可以：
+    // 1. 防止 DoS 攻击（上传超大文件耗尽服务器资源）
+    // 2. 控制存储成本
+    // 3. 保证服务稳定性
+    //
+    // Content-Length 已经超限时直接拒绝；没有声明长度（分块传输）时
+    // 由 receive_upload 在接收过程中累计检查
+    //
+    // 思考：如何实现断点续传？
+    // ----------------------------------------
+    let declared = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if declared.is_some_and(|len| len > state.max_file_size) {
+        return too_large("File", state.max_file_size);
+    }
+
+    let upload =
+        match receive_upload(&state, body.into_data_stream(), "File", state.max_file_size).await {
+            Ok(upload) => upload,
+            Err(response) => return response,
+        };
+    store_upload(
+        &state,
+        path,
+        query.on_conflict,
+        &headers,
+        identity.as_deref(),
+        user.map(|user| user.id),
+        upload,
+    )
+    .await
+}
+
+// 通配段只能在路由末尾，POST 的动作以路径后缀区分：
+// "/multipart" 是表单上传，"/rollback/{version}" 回滚到历史版本，"/move" 移动或重命名，"/copy" 复制
+async fn post_file(
+    State(state): State<AppState>,
+    LogicalPath(path): LogicalPath,
+    Query(query): Query<UploadQuery>,
+    headers: HeaderMap,
+    request: Request,
+) -> (StatusCode, Json<ApiResponse>) {
+    if let Some(path) = path.strip_suffix("/multipart") {
+        let identity = request.extensions().get::<ClientIdentity>().cloned();
+        let owner = request
+            .extensions()
+            .get::<AuthenticatedUser>()
+            .map(|user| user.id);
+        return match Multipart::from_request(request, &state).await {
+            Ok(multipart) => {
+                upload_multipart(
+                    &state,
+                    path,
+                    query,
+                    &headers,
+                    identity.as_ref(),
+                    owner,
+                    multipart,
+                )
+                .await
+            }
+            Err(e) => (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error(&e.body_text())),
+            ),
+        };
+    }
+    if let Some((path, version)) = path.rsplit_once("/rollback/") {
+        let owner = request
+            .extensions()
+            .get::<AuthenticatedUser>()
+            .map(|user| user.id);
+        return rollback_file(&state, path, version, &headers, owner).await;
+    }
+    if let Some(path) = path.strip_suffix("/copy") {
+        let owner = request
+            .extensions()
+            .get::<AuthenticatedUser>()
+            .map(|user| user.id);
+        return match Json::<CopyRequest>::from_request(request, &state).await {
+            Ok(Json(req)) => copy_file(&state, owner, path, &req.to).await,
+            Err(e) => (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error(&e.body_text())),
+            ),
+        };
+    }
+    if let Some(path) = path.strip_suffix("/move") {
+        let user = request.extensions().get::<AuthenticatedUser>().cloned();
+        return match Json::<MoveRequest>::from_request(request, &state).await {
+            Ok(Json(req)) => move_file(&state, user.as_ref(), path, &req.to).await,
+            Err(e) => (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error(&e.body_text())),
+            ),
+        };
+    }
+    (
+        StatusCode::NOT_FOUND,
+        Json(ApiResponse::error(
+            "Use POST /api/files/{path}/multipart, /api/files/{path}/rollback/{version}, /api/files/{path}/move or /api/files/{path}/copy",
+        )),
+    )
+}
+
+/// 浏览器表单上传：取名为 file 的字段作为文件内容，其余字段忽略
+async fn upload_multipart(
+    state: &AppData,
+    path: &str,
+    query: UploadQuery,
+    headers: &HeaderMap,
+    identity: Option<&ClientIdentity>,
+    owner: Option<uuid::Uuid>,
+    mut multipart: Multipart,
+) -> (StatusCode, Json<ApiResponse>) {
+    loop {
+        match multipart.next_field().await {
+            Ok(Some(field)) if field.name() == Some("file") => {
+                let upload = match receive_upload(state, field, "File", state.max_file_size).await {
+                    Ok(upload) => upload,
+                    Err(response) => return response,
+                };
+                return store_upload(
+                    state,
+                    path.to_string(),
+                    query.on_conflict,
+                    headers,
+                    identity,
+                    owner,
+                    upload,
+                )
+                .await;
+            }
+            Ok(Some(_)) => continue,
+            Ok(None) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(ApiResponse::error("Missing form field: file")),
+                )
+            }
+            Err(e) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(ApiResponse::error(&format!(
+                        "Invalid multipart body: {}",
+                        e
+                    ))),
+                )
+            }
+        }
+    }
+}
+
+// 历史版本的内容作为新版本写回，之前的版本都保留；代理模式下照常转发到上游
+async fn rollback_file(
+    state: &AppData,
+    path: &str,
+    version: &str,
+    headers: &HeaderMap,
+    owner: Option<uuid::Uuid>,
+) -> (StatusCode, Json<ApiResponse>) {
+    let Ok(version) = version.parse::<i32>() else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(&format!("Invalid version: {}", version))),
+        );
+    };
+    let Ok(record) = state.files.get_file_by_path(path).await else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("File not found")),
+        );
+    };
+    let upload = match state.versions.stage(&record, version).await {
+        Ok(upload) => upload,
+        Err(Error::NotFound(_)) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::error(&format!(
+                    "Version {} of {} not found",
+                    version, path
+                ))),
+            )
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error(&format!(
+                    "Failed to restore version: {}",
+                    e
+                ))),
+            )
+        }
+    };
+    store_upload(
+        state,
+        record.path,
+        OnConflict::Overwrite,
+        headers,
+        None,
+        owner,
+        upload,
+    )
+    .await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MoveRequest {
+    /// 新路径，父目录不存在时自动创建
+    pub to: String,
+}
+
+// 只改记录的路径：id、版本号和历史版本都跟着记录走，不需要重新上传。
+// 目录整体移动时其下所有记录一起改路径，任何一条失败都把已改的改回去
+async fn move_file(
+    state: &AppData,
+    user: Option<&AuthenticatedUser>,
+    from: &str,
+    to: &str,
+) -> (StatusCode, Json<ApiResponse>) {
+    let to = match logical_path::normalize(to) {
+        Ok(to) => to,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error(&format!("Invalid path: {}", e))),
+            )
+        }
+    };
+    // 父目录沿用已有的大小写写法，文件名本身按请求的写法，只改大小写的重命名才能生效
+    let to = match to.rsplit_once('/') {
+        Some((parent, name)) => format!("{}/{}", existing_case_path(state, parent).await, name),
+        None => to,
+    };
+    if to == from || case_key(&to).starts_with(&format!("{}/", case_key(from))) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(&format!(
+                "Cannot move {} to {}",
+                from, to
+            ))),
+        );
+    }
+    for path in [from, to.as_str()] {
+        if let Some(rejection) = append_only_rejection(state, user, path) {
+            return rejection;
+        }
+    }
+    // 移出相当于在源目录中删除，移入相当于在目标目录中写入
+    if let Some(rejection) =
+        direction_rejection(state, from, true).or_else(|| direction_rejection(state, &to, false))
+    {
+        return rejection;
+    }
+    // 移动无法拆成已有的上游写操作转发，代理模式下直接拒绝
+    if state.proxy.is_some() {
+        return (
+            StatusCode::NOT_IMPLEMENTED,
+            Json(ApiResponse::error(
+                "Moving files is not supported in proxy mode",
+            )),
+        );
+    }
+    let source = state.storage_path.join(from);
+    let target = match path_guard::resolve(&state.storage_path, &to).await {
+        Ok(target) => target,
+        Err(e) => return error_response(&e),
+    };
+
+    // 两个路径按固定顺序加锁，同时进行的反向移动不会互相等待
+    let mut keys = [case_key(from), case_key(&to)];
+    keys.sort();
+    let _first = state.path_locks.lock(&keys[0]).await;
+    let _second = match keys[0] == keys[1] {
+        true => None,
+        false => Some(state.path_locks.lock(&keys[1]).await),
+    };
+
+    let all = state.files.list_files().await.unwrap_or_default();
+    let prefix = format!("{}/", from);
+    let records: Vec<&FileRecord> = all
+        .iter()
+        .filter(|r| r.path == from || r.path.starts_with(&prefix))
+        .collect();
+    let directories = state
+        .repository
+        .list_directories()
+        .await
+        .unwrap_or_default();
+    let moves_directories = directories.iter().any(|d| d.is_under(from));
+    if records.is_empty() && !moves_directories && !source.exists() {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("File not found")),
+        );
+    }
+
+    // 只差大小写的已有路径也算被占用，除非它就是要移动的源
+    let target_key = case_key(&to);
+    let target_prefix = format!("{}/", target_key);
+    let occupied = all.iter().any(|r| {
+        let key = case_key(&r.path);
+        (key == target_key || key.starts_with(&target_prefix))
+            && !(r.path == from || r.path.starts_with(&prefix))
+    }) || directories
+        .iter()
+        .any(|d| case_key(&d.path) == target_key && !d.is_under(from))
+        || (target.exists() && target_key != case_key(from));
+    if occupied {
+        return error_response(&Error::AlreadyExists(to.into()));
+    }
+    for path in [from, to.as_str()] {
+        if let Some(hold) = state.repository.find_overlapping_legal_hold(path).await {
+            return held_response(&hold);
+        }
+    }
+
+    let mut moved = Vec::with_capacity(records.len());
+    for record in &records {
+        let path = format!("{}{}", to, &record.path[from.len()..]);
+        match state.files.move_file(record.id, &path).await {
+            Ok(record) => moved.push(record),
+            Err(e) => {
+                for (record, original) in moved.iter().zip(&records) {
+                    let _ = state.files.move_file(record.id, &original.path).await;
+                }
+                return error_response(&e);
+            }
+        }
+    }
+
+    if source.exists() {
+        // 监控随后收到的事件是服务端自己的改动，旧路径的删除和新路径的写入都要登记
+        state.expected_writes.expect_removal(from);
+        for record in &moved {
+            if let Some(hash) = &record.hash {
+                state.expected_writes.expect_write(&record.path, hash);
+            }
+        }
+        let result = match target.parent() {
+            Some(parent) => tokio::fs::create_dir_all(parent).await,
+            None => Ok(()),
+        };
+        if let Err(e) = result.and(tokio::fs::rename(&source, &target).await) {
+            for (record, original) in moved.iter().zip(&records) {
+                let _ = state.files.move_file(record.id, &original.path).await;
+            }
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error(&format!("Failed to move: {}", e))),
+            );
+        }
+    }
+
+    if moves_directories {
+        if let Err(e) = state.repository.move_directories(from, &to).await {
+            tracing::warn!("Failed to move directory records: {}", e);
+        }
+    }
+
+    // 订阅者看到的移动是旧路径删除、新路径创建，与监控发现的移动一致
+    for (record, original) in moved.iter().zip(&records) {
+        state.feed.deleted(&original.path);
+        state.feed.written(
+            ChangeKind::Created,
+            &record.path,
+            record.hash.clone(),
+            record.version,
+        );
+        state.notifier.notify(Notification::FileChanged {
+            path: original.path.clone(),
+            change: FileChange::Deleted,
+        });
+        state.notifier.notify(Notification::FileChanged {
+            path: record.path.clone(),
+            change: FileChange::Created,
+        });
+    }
+    let files: Vec<FileInfo> = moved.iter().map(FileInfo::from_record).collect();
+    (StatusCode::OK, Json(ApiResponse::success(files)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CopyRequest {
+    /// 副本的路径，已存在时返回 409
+    pub to: String,
+}
+
+// 内容寻址的对象可以被任意多条记录引用：副本只是一条指向同一哈希的新记录，
+// 对象存储中不多写一个字节。副本有自己的版本历史，从版本 1 开始
+async fn copy_file(
+    state: &AppData,
+    owner: Option<uuid::Uuid>,
+    from: &str,
+    to: &str,
+) -> (StatusCode, Json<ApiResponse>) {
+    let to = match logical_path::normalize(to) {
+        Ok(to) => existing_case_path(state, &to).await,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error(&format!("Invalid path: {}", e))),
+            )
+        }
+    };
+    if let Some(rejection) = direction_rejection(state, &to, false) {
+        return rejection;
+    }
+    // 副本需要作为上传转发，而源文件可能只在上游
+    if state.proxy.is_some() {
+        return (
+            StatusCode::NOT_IMPLEMENTED,
+            Json(ApiResponse::error(
+                "Copying files is not supported in proxy mode",
+            )),
+        );
+    }
+    let target = match path_guard::resolve(&state.storage_path, &to).await {
+        Ok(target) => target,
+        Err(e) => return error_response(&e),
+    };
+
+    let _guard = state.path_locks.lock(&case_key(&to)).await;
+    let source = match state.files.get_file_by_path(from).await {
+        Ok(source) => source,
+        Err(_) if state.storage_path.join(from).is_dir() => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error("Only files can be copied")),
+            )
+        }
+        Err(_) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::error("File not found")),
+            )
+        }
+    };
+    let Some(hash) = source.hash.clone() else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("File has no stored content")),
+        );
+    };
+    if upload_target_exists(state, &to).await {
+        return error_response(&Error::AlreadyExists(to.into()));
+    }
+    if let Some(hold) = state.repository.find_legal_hold(&to).await {
+        return held_response(&hold);
+    }
+    // 副本不多占对象存储，但和其他文件一样计入用户的用量
+    if let Some(rejection) = quota_rejection(state, owner, &to, source.size, OnConflict::Fail).await
+    {
+        return rejection;
+    }
+    if !state.storage.file_exists(&hash).await {
+        return error_response(&Error::NotFound(state.storage.object_path(&hash)));
+    }
+
+    // 落盘模式下副本也要有明文文件，对象存储仍然只有一份
+    if state.materialize_files {
+        let result = match target.parent() {
+            Some(parent) => tokio::fs::create_dir_all(parent).await,
+            None => Ok(()),
+        };
+        state.expected_writes.expect_write(&to, &hash);
+        let copied = result.and(
+            tokio::fs::copy(state.storage_path.join(&source.path), &target)
+                .await
+                .map(|_| ()),
+        );
+        if let Err(e) = copied {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error(&format!("Failed to copy: {}", e))),
+            );
+        }
+    }
+
+    let record = state
+        .files
+        .create_file(crate::db::NewFileRecord {
+            path: to.clone(),
+            hash: Some(hash.clone()),
+            size: source.size,
+        })
+        .await;
+    let record = match (record, owner) {
+        (Ok(record), Some(owner)) => state.files.set_file_owner(record.id, Some(owner)).await,
+        (record, _) => record,
+    };
+    // 图片信息和自定义元数据描述的是内容，跟着复制
+    let record = match record {
+        Ok(record) if source.media.is_some() => {
+            state
+                .files
+                .set_file_media(record.id, source.media.clone())
+                .await
+        }
+        other => other,
+    };
+    let record = match record {
+        Ok(record) if !source.metadata.is_empty() => {
+            let patch = source
+                .metadata
+                .iter()
+                .map(|(key, value)| (key.clone(), Some(value.clone())))
+                .collect();
+            state.files.update_file_metadata(record.id, patch).await
+        }
+        other => other,
+    };
+    let record = match record {
+        Ok(record) if state.materialize_files => {
+            let fs_id = crate::service::fs_id::read(&target);
+            state.files.set_file_fs_id(record.id, fs_id).await
+        }
+        other => other,
+    };
+
+    match record {
+        Ok(record) => {
+            notify_storage_growth(state, record.size).await;
+            state.notifier.notify(Notification::FileChanged {
+                path: record.path.clone(),
+                change: FileChange::Created,
+            });
+            state.feed.written(
+                ChangeKind::Created,
+                &record.path,
+                Some(hash),
+                record.version,
+            );
+            let info = FileInfo {
+                deduplicated: true,
+                ..FileInfo::from_record(&record)
+            };
+            (StatusCode::OK, Json(ApiResponse::success(info)))
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&format!(
+                "Failed to create record: {}",
+                e
+            ))),
+        ),
+    }
+}
+
+// 同样以路径后缀区分动作，目前只有 "/delta"
+async fn patch_file(
+    State(state): State<AppState>,
+    LogicalPath(path): LogicalPath,
+    identity: Option<Extension<ClientIdentity>>,
+    user: Option<Extension<AuthenticatedUser>>,
+    headers: HeaderMap,
+    body: Body,
+) -> (StatusCode, Json<ApiResponse>) {
+    let owner = user.map(|user| user.id);
+    match path.strip_suffix("/delta") {
+        Some(path) => apply_delta(&state, path, &headers, identity.as_deref(), owner, body).await,
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Use PATCH /api/files/{path}/delta")),
+        ),
+    }
+}
+
+// 增量是对照签名时的内容计算的：If-Match 带签名中的哈希，内容已经变化就只能整体上传。
+// 检查之后、写入之前内容仍可能被改动，所以还要求 X-Content-Sha256，拼错的结果不会写入
+async fn apply_delta(
+    state: &AppData,
+    path: &str,
+    headers: &HeaderMap,
+    identity: Option<&ClientIdentity>,
+    owner: Option<uuid::Uuid>,
+    body: Body,
+) -> (StatusCode, Json<ApiResponse>) {
+    let source = match resolve_content_path(state, path).await {
+        Ok(source) => source,
+        Err((status, message)) => return (status, Json(ApiResponse::error(message))),
+    };
+    let (Some(condition), true) = (
+        headers.get(header::IF_MATCH),
+        headers.contains_key(CONTENT_HASH_HEADER),
+    ) else {
+        return (
+            StatusCode::PRECONDITION_REQUIRED,
+            Json(ApiResponse::error(
+                "Delta uploads require If-Match with the signature hash and X-Content-Sha256",
+            )),
+        );
+    };
+    let current = stored_etag(state, path, &source).await;
+    if !current.is_some_and(|etag| etag_matches(condition, &etag)) {
+        return (
+            StatusCode::PRECONDITION_FAILED,
+            Json(ApiResponse::error(&format!(
+                "{} changed since the signature was taken; upload the whole file",
+                path
+            ))),
+        );
+    }
+
+    let declared = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if declared.is_some_and(|len| len > state.max_file_size) {
+        return too_large("Delta", state.max_file_size);
+    }
+    let bytes = match axum::body::to_bytes(body, state.max_file_size as usize).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error(&format!("Failed to read delta: {}", e))),
+            )
+        }
+    };
+    let base_size = match tokio::fs::metadata(&source).await {
+        Ok(meta) => meta.len(),
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error(&e.to_string())),
+            )
+        }
+    };
+    let delta = match Delta::decode(&bytes).and_then(|delta| {
+        let len = delta.output_len(base_size)?;
+        Ok((delta, len))
+    }) {
+        Ok((_, len)) if len > state.max_file_size => return too_large("File", state.max_file_size),
+        Ok((delta, _)) => delta,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error(&format!("Invalid delta: {}", e))),
+            )
+        }
+    };
+
+    let upload = match state.storage.apply_delta(&source, &delta).await {
+        Ok(upload) => upload,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error(&format!("Failed to apply delta: {}", e))),
+            )
+        }
+    };
+    store_upload(
+        state,
+        path.to_string(),
+        OnConflict::Overwrite,
+        headers,
+        identity,
+        owner,
+        upload,
+    )
+    .await
+}
+
+// 请求体已经完整落到暂存文件，之后的处理与上传方式无关
+async fn store_upload(
+    state: &AppData,
+    path: String,
+    on_conflict: OnConflict,
+    headers: &HeaderMap,
+    identity: Option<&ClientIdentity>,
+    owner: Option<uuid::Uuid>,
+    upload: StagedUpload,
+) -> (StatusCode, Json<ApiResponse>) {
+    if let Some(rejection) = direction_rejection(state, &path, false) {
+        return rejection;
+    }
+    let response = write_upload(state, path, on_conflict, headers, identity, owner, upload).await;
+    // 代理模式下新内容稍后转发到上游；去重命中说明上游已有或已在队列中
+    if let (Some(proxy), Some(data)) = (&state.proxy, &response.1.data) {
+        if let Ok(FileInfo {
+            path,
+            hash: Some(hash),
+            deduplicated: false,
+            ..
+        }) = serde_json::from_value(data.clone())
+        {
+            proxy.enqueue(PendingWrite::Upload { path, hash });
+        }
+    }
+    response
+}
+
+// 写入本地存储并更新记录，代理模式下从上游拉取的缓存也经由这里写入
+async fn write_upload(
+    state: &AppData,
+    path: String,
+    on_conflict: OnConflict,
+    headers: &HeaderMap,
+    identity: Option<&ClientIdentity>,
+    owner: Option<uuid::Uuid>,
+    mut upload: StagedUpload,
+) -> (StatusCode, Json<ApiResponse>) {
+    // 同一路径的上传、删除整体串行，避免记录与磁盘内容交错；
+    // 只差大小写的路径共用一把锁
+    let _guard = state.path_locks.lock(&case_key(&path)).await;
+    let path = existing_case_path(state, &path).await;
+
+    let path = match on_conflict {
+        OnConflict::Overwrite => path,
+        _ if !upload_target_exists(state, &path).await => path,
+        OnConflict::Fail => return error_response(&Error::AlreadyExists(path.into())),
+        OnConflict::Rename => {
+            let mut n = 1;
+            while upload_target_exists(state, &numbered_path(&path, n)).await {
+                n += 1;
+            }
+            numbered_path(&path, n)
+        }
+    };
+
+    // 在写盘之前拦截，保留中的文件连明文副本也不能被覆盖
+    if let Some(hold) = state.repository.find_legal_hold(&path).await {
+        return held_response(&hold);
+    }
+
+    let file_path = match path_guard::resolve(&state.storage_path, &path).await {
+        Ok(file_path) => file_path,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error(&e.to_string())),
+            )
+        }
+    };
+
+    // [知识点 #143] 上传去重短路
+    // ----------------------------------------
+    // 题目：为什么要在写盘之前先比较哈希？
+    //
+    // 讲解：
+    // 客户端经常重复上传未修改的文件（例如重新同步整个目录）。
+    // 接收请求体时已经算出 SHA-256，与数据库记录中的 hash 比较：
+    // - 相同：跳过写文件、跳过对象存储、跳过版本递增，直接返回现有记录
+    // - 不同：走正常上传流程
+    //
+    // 这样重复上传既不会虚增版本号，也不会产生多余的磁盘 IO
+    //
+    // 思考：如果磁盘上的文件被外部修改过，仅比较数据库 hash 够吗？
+    // ----------------------------------------
+    let content_hash = match upload.finish().await {
+        Ok(hash) => hash,
+        Err(e) => {
+            return (
+                e.status_code(),
+                Json(ApiResponse::error(&format!("Failed to store file: {}", e))),
+            )
+        }
+    };
+
+    // 客户端声明的哈希与实际收到的内容不一致，说明传输过程中数据损坏
+    if let Some(expected) = headers
+        .get(CONTENT_HASH_HEADER)
+        .and_then(|v| v.to_str().ok())
+    {
+        if !expected.eq_ignore_ascii_case(&content_hash) {
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(ApiResponse::error(&format!(
+                    "Checksum mismatch: expected {}, received {}",
+                    expected, content_hash
+                ))),
+            );
+        }
+    }
+    if let Ok(existing) = state.files.get_file_by_path(&path).await {
+        let stored = if state.materialize_files {
+            file_path.is_file()
+        } else {
+            state.storage.file_exists(&content_hash).await
+        };
+        if existing.hash.as_deref() == Some(content_hash.as_str()) && stored {
+            record_base(state, identity, &existing).await;
+            let info = FileInfo {
+                name: file_path
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                path,
+                is_dir: false,
+                size: existing.size,
+                modified: Some(existing.updated_at.to_rfc3339()),
+                hash: existing.hash,
+                version: Some(existing.version),
+                deduplicated: true,
+                metadata: existing.metadata,
+            };
+            return (StatusCode::OK, Json(ApiResponse::success(info)));
+        }
+    }
+
+    // 设备基于过期版本修改了文件，直接覆盖会丢掉其他设备在此期间的修改
+    let mut conflict = None;
+    if let (Some(identity), Ok(existing)) = (identity, state.files.get_file_by_path(&path).await) {
+        match state
+            .sync_engine
+            .detect_conflict(identity.device_id, &existing, &content_hash)
+            .await
+        {
+            Ok(Some(base)) => conflict = Some((identity, existing.id, base)),
+            Ok(None) => {}
+            Err(e) => tracing::warn!("Failed to check {} for conflicts: {}", path, e),
+        }
+    }
+    let (path, file_path) = match conflict {
+        None => (path, file_path),
+        Some((identity, file_id, base)) if state.conflicts == ConflictStrategy::Reject => {
+            if let Err(e) = state
+                .sync_engine
+                .record_conflict(identity.device_id, file_id, base, None)
+                .await
+            {
+                tracing::warn!("Failed to record conflict on {}: {}", path, e);
+            }
+            return error_response(&Error::Conflict(format!(
+                "{} changed on the server since version {}; download it before uploading again",
+                path, base
+            )));
+        }
+        Some((identity, _, _)) => {
+            let copy = conflicted_copy_path(state, &path, &identity.device_name).await;
+            if let Some(hold) = state.repository.find_legal_hold(&copy).await {
+                return held_response(&hold);
+            }
+            match path_guard::resolve(&state.storage_path, &copy).await {
+                Ok(file_path) => (copy, file_path),
+                Err(e) => {
+                    return (
+                        StatusCode::BAD_REQUEST,
+                        Json(ApiResponse::error(&e.to_string())),
+                    )
+                }
+            }
+        }
+    };
+
+    // 在路径锁内、最终路径确定之后检查配额，覆盖时旧内容不再计入；
+    // 有配额时同时持有用户的锁到记录写入为止，见 QuotaService::reserve
+    let _quota = match owner {
+        Some(owner) => {
+            let replaced = state
+                .files
+                .get_file_by_path(&path)
+                .await
+                .map_or(0, |record| record.size);
+            match state.quotas.reserve(owner, upload.size(), replaced).await {
+                Ok(guard) => guard,
+                Err(e) => return error_response(&e),
+            }
+        }
+        None => None,
+    };
+
+    // 内容已存在时上面已经短路返回，只有新内容才查询信誉服务
+    let verdict = match &state.reputation {
+        Some(reputation) => {
+            let verdict = reputation.check(&content_hash).await;
+            if verdict == Verdict::Malicious && reputation.policy() == ReputationPolicy::Block {
+                return (
+                    StatusCode::FORBIDDEN,
+                    Json(ApiResponse::error(
+                        "Upload blocked: content is flagged as malicious",
+                    )),
+                );
+            }
+            Some(verdict)
+        }
+        None => None,
+    };
+
+    // [知识点 #144] 对象存储是权威副本
+    // ----------------------------------------
+    // 题目：为什么明文文件可以是可选的？
+    //
+    // 讲解：
+    // 内容寻址的对象（objects/ab/cdef...）已经完整保存了文件内容，
+    // 目录树结构由数据库中的 FileRecord.path 表示。
+    // 额外在 storage_path/<path> 落一份明文文件会让磁盘占用翻倍，
+    // 它只在需要直接浏览存储目录（或启用文件监控）时才有价值。
+    //
+    // materialize_files = false 时：
+    // - 上传只写入对象存储
+    // - 列表、查询、删除都基于元数据
+    //
+    // 思考：关闭落盘后，文件监控还能发现哪些变化？
+    // ----------------------------------------
+    // 存入后暂存文件会被移走，先读出图片开头用于提取媒体信息
+    let media_prefix = if media::is_photo(&path) {
+        upload.read_prefix(MEDIA_PREFIX_LIMIT).await.ok()
+    } else {
+        None
+    };
+    let stored = if state.materialize_files {
+        write_materialized(state, &path, &file_path, &mut upload).await
+    } else {
+        state.storage.store_staged(&mut upload).await
+    };
+    let (hash, size) = match stored {
+        Ok(result) => result,
+        Err(e) => {
+            return (
+                e.status_code(),
+                Json(ApiResponse::error(&format!("Failed to store file: {}", e))),
+            );
+        }
+    };
+
+    // 更新数据库记录
+    let mut previous_size = 0;
+    let mut change = FileChange::Created;
+    let record = match state.files.get_file_by_path(&path).await {
+        Ok(existing) => {
+            previous_size = existing.size;
+            change = FileChange::Updated;
+            state
+                .files
+                .update_file(existing.id, Some(hash.clone()), size)
+                .await
+        }
+        Err(_) => {
+            let record = state
+                .files
+                .create_file(crate::db::NewFileRecord {
+                    path: path.clone(),
+                    hash: Some(hash.clone()),
+                    size,
+                })
+                .await;
+            // 已认证的上传者成为文件的所有者，账户数据的导出和删除据此找到文件
+            match (record, owner) {
+                (Ok(record), Some(owner)) => {
+                    state.files.set_file_owner(record.id, Some(owner)).await
+                }
+                (record, _) => record,
+            }
+        }
+    };
+
+    // 图片附带尺寸和拍摄时间，替换为非图片内容时清除旧的元数据
+    let media = media_prefix.and_then(|prefix| media::extract_metadata(&path, &prefix));
+    let record = match record {
+        Ok(record) if record.media != media => state.files.set_file_media(record.id, media).await,
+        other => other,
+    };
+    // 记下落盘文件的 inode，文件监控据此把外部移动识别为同一个文件
+    let record = match record {
+        Ok(record) if state.materialize_files => {
+            let fs_id = crate::service::fs_id::read(&file_path);
+            if record.fs_id != fs_id {
+                state.files.set_file_fs_id(record.id, fs_id).await
+            } else {
+                Ok(record)
+            }
+        }
+        other => other,
+    };
+    let record = match (record, verdict) {
+        (Ok(record), Some(verdict)) => {
+            let patch = BTreeMap::from([(
+                VERDICT_METADATA_KEY.to_string(),
+                Some(verdict.as_str().to_string()),
+            )]);
+            state.files.update_file_metadata(record.id, patch).await
+        }
+        (other, _) => other,
+    };
+
+    match record {
+        Ok(record) => {
+            if let Some((identity, file_id, base)) = conflict {
+                if let Err(e) = state
+                    .sync_engine
+                    .record_conflict(identity.device_id, file_id, base, Some(record.path.clone()))
+                    .await
+                {
+                    tracing::warn!("Failed to record conflict on {}: {}", record.path, e);
+                }
+            }
+            record_base(state, identity, &record).await;
+            if size > previous_size {
+                notify_storage_growth(state, size - previous_size).await;
+            }
+            state.notifier.notify(Notification::FileChanged {
+                path: record.path.clone(),
+                change,
+            });
+            let kind = match change {
+                FileChange::Created => ChangeKind::Created,
+                _ => ChangeKind::Modified,
+            };
+            state
+                .feed
+                .written(kind, &record.path, Some(hash.clone()), record.version);
+            let info = FileInfo {
+                name: file_path
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                path,
+                is_dir: false,
+                size,
+                modified: Some(record.updated_at.to_rfc3339()),
+                hash: Some(hash),
+                version: Some(record.version),
+                deduplicated: false,
+                metadata: record.metadata,
+            };
+            (StatusCode::OK, Json(ApiResponse::success(info)))
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&format!(
+                "Failed to update record: {}",
+                e
+            ))),
+        ),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateUploadRequest {
+    pub path: String,
+    pub size: u64,
+    /// 期望的分块大小，服务端会限制在允许范围内
+    pub chunk_size: Option<usize>,
+}
+
+async fn upload_status(state: &AppData, session: &UploadSession) -> UploadStatus {
+    UploadStatus {
+        id: session.id.to_string(),
+        path: session.path.clone(),
+        size: session.size,
+        chunk_size: session.chunk_size,
+        chunk_count: session.chunk_count(),
+        received: state.uploads.received(session).await,
+    }
+}
+
+fn upload_not_found(id: uuid::Uuid) -> (StatusCode, Json<ApiResponse>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ApiResponse::error(&format!("Upload not found: {}", id))),
+    )
+}
+
+// 目标已存在且 on_conflict=fail、或路径处于保留中时，在传输之前就拒绝
+async fn create_upload(
+    State(state): State<AppState>,
+    Query(query): Query<UploadQuery>,
+    user: Option<Extension<AuthenticatedUser>>,
+    Json(req): Json<CreateUploadRequest>,
+) -> impl IntoResponse {
+    let path = match logical_path::normalize(&req.path) {
+        Ok(path) => existing_case_path(&state, &path).await,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error(&format!("Invalid path: {}", e))),
+            )
+        }
+    };
+    if req.size > state.max_file_size {
+        return too_large("File", state.max_file_size);
+    }
+    if let Some(rejection) = direction_rejection(&state, &path, false) {
+        return rejection;
+    }
+    // 完成时还会再检查一次，这里先拒绝注定超出配额的上传
+    let owner = user.map(|user| user.id);
+    if let Some(rejection) =
+        quota_rejection(&state, owner, &path, req.size, query.on_conflict).await
+    {
+        return rejection;
+    }
+    if query.on_conflict == OnConflict::Fail && upload_target_exists(&state, &path).await {
+        return error_response(&Error::AlreadyExists(path.into()));
+    }
+    if let Some(hold) = state.repository.find_legal_hold(&path).await {
+        return held_response(&hold);
+    }
+
+    // 不需要分块的小文件作为只有一块的会话处理
+    let chunk_size = state
+        .storage
+        .negotiate_chunk_size(req.size, req.chunk_size)
+        .map_or(req.size, |size| size as u64);
+    match state.uploads.create(path, req.size, chunk_size).await {
+        Ok(session) => (
+            StatusCode::CREATED,
+            Json(ApiResponse::success(upload_status(&state, &session).await)),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&format!(
+                "Failed to create upload: {}",
+                e
+            ))),
+        ),
+    }
+}
+
+async fn get_upload(
+    State(state): State<AppState>,
+    Path(id): Path<uuid::Uuid>,
+) -> impl IntoResponse {
+    match state.uploads.get(id).await {
+        Ok(session) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(upload_status(&state, &session).await)),
+        ),
+        Err(_) => upload_not_found(id),
+    }
+}
+
+// 同一分块可以重复上传，后到的覆盖先到的；X-Content-Sha256 按分块内容校验
+async fn upload_chunk(
+    State(state): State<AppState>,
+    Path((id, n)): Path<(uuid::Uuid, u64)>,
+    headers: HeaderMap,
+    body: Body,
+) -> impl IntoResponse {
+    let Ok(session) = state.uploads.get(id).await else {
+        return upload_not_found(id);
+    };
+    let Some(expected) = session.chunk_len(n) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(&format!(
+                "Chunk {} out of range, upload has {} chunk(s)",
+                n,
+                session.chunk_count()
+            ))),
+        );
+    };
+
+    let mut upload = match receive_upload(&state, body.into_data_stream(), "Chunk", expected).await
+    {
+        Ok(upload) => upload,
+        Err(response) => return response,
+    };
+    if upload.size() != expected {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(&format!(
+                "Chunk {} must be {} bytes, got {}",
+                n,
+                expected,
+                upload.size()
+            ))),
+        );
+    }
+    let stored = async {
+        let hash = upload.finish().await?;
+        if let Some(declared) = headers
+            .get(CONTENT_HASH_HEADER)
+            .and_then(|v| v.to_str().ok())
+        {
+            if !declared.eq_ignore_ascii_case(&hash) {
+                return Ok(Some((declared.to_string(), hash)));
+            }
+        }
+        upload
+            .persist(&state.uploads.chunk_path(&session, n))
+            .await?;
+        Ok::<_, Error>(None)
+    }
+    .await;
+    match stored {
+        Ok(None) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(upload_status(&state, &session).await)),
+        ),
+        Ok(Some((declared, actual))) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(ApiResponse::error(&format!(
+                "Checksum mismatch: expected {}, received {}",
+                declared, actual
+            ))),
+        ),
+        Err(e) => (
+            e.status_code(),
+            Json(ApiResponse::error(&format!("Failed to store chunk: {}", e))),
+        ),
+    }
+}
+
+// 按序拼接所有分块，之后与普通上传相同；X-Content-Sha256 校验整个文件
+// 没有走 store_chunked：读取、下载和去重都按整个对象进行，拼接后复用同一条落盘流程
+async fn complete_upload(
+    State(state): State<AppState>,
+    Path(id): Path<uuid::Uuid>,
+    Query(query): Query<UploadQuery>,
+    identity: Option<Extension<ClientIdentity>>,
+    user: Option<Extension<AuthenticatedUser>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let Ok(session) = state.uploads.get(id).await else {
+        return upload_not_found(id);
+    };
+    let received = state.uploads.received(&session).await;
+    let missing: Vec<String> = (0..session.chunk_count())
+        .filter(|n| !received.contains(n))
+        .map(|n| n.to_string())
+        .collect();
+    if !missing.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(&format!(
+                "Missing chunks: {}",
+                missing.join(", ")
+            ))),
+        );
+    }
+
+    let assembled = async {
+        let mut upload = state.storage.begin_upload().await?;
+        let mut buffer = vec![0u8; 1024 * 1024];
+        for n in 0..session.chunk_count() {
+            let mut chunk = tokio::fs::File::open(state.uploads.chunk_path(&session, n)).await?;
+            loop {
+                let read = tokio::io::AsyncReadExt::read(&mut chunk, &mut buffer).await?;
+                if read == 0 {
+                    break;
+                }
+                upload.write(&buffer[..read]).await?;
+            }
+        }
+        Ok::<_, Error>(upload)
+    }
+    .await;
+    let upload = match assembled {
+        Ok(upload) => upload,
+        Err(e) => {
+            return (
+                e.status_code(),
+                Json(ApiResponse::error(&format!(
+                    "Failed to assemble upload: {}",
+                    e
+                ))),
+            )
+        }
+    };
+
+    let response = store_upload(
+        &state,
+        session.path.clone(),
+        query.on_conflict,
+        &headers,
+        identity.as_deref(),
+        user.map(|user| user.id),
+        upload,
+    )
+    .await;
+    // 失败时保留会话，客户端可以直接重试 complete
+    if response.0.is_success() {
+        if let Err(e) = state.uploads.remove(id).await {
+            tracing::warn!("Failed to remove finished upload {}: {}", id, e);
+        }
+    }
+    response
+}
+
+// 只追加的目录，以及持只追加令牌的调用者，都不能删除
+fn append_only_rejection(
+    state: &AppData,
+    user: Option<&AuthenticatedUser>,
+    path: &str,
+) -> Option<(StatusCode, Json<ApiResponse>)> {
+    if let Some(rejection) = append_only_token_rejection(user, path) {
+        return Some(rejection);
+    }
+    let folder = state.append_only.overlapping(path)?;
+    Some(error_response(&Error::AppendOnly(folder.to_string())))
+}
+
+fn append_only_token_rejection(
+    user: Option<&AuthenticatedUser>,
+    target: &str,
+) -> Option<(StatusCode, Json<ApiResponse>)> {
+    user.filter(|user| user.append_only)?;
+    Some(error_response(&Error::AppendOnly(format!(
+        "{} (the access token is append-only)",
+        target
+    ))))
+}
+
+/// 所有文件记录；启用命名空间时只保留其中的文件
+async fn visible_files(
+    state: &AppData,
+    namespace: Option<&Namespace>,
+) -> crate::error::Result<Vec<FileRecord>> {
+    let mut files = state.files.list_files().await?;
+    files.retain(|record| namespace::visible(namespace, &record.path));
+    Ok(files)
+}
+
+/// 写入 incoming 字节后超出上传者的配额时拒绝；覆盖已有文件时旧内容不再计入
+async fn quota_rejection(
+    state: &AppData,
+    owner: Option<uuid::Uuid>,
+    path: &str,
+    incoming: u64,
+    on_conflict: OnConflict,
+) -> Option<(StatusCode, Json<ApiResponse>)> {
+    let owner = owner?;
+    let replaced = match on_conflict {
+        OnConflict::Overwrite => state
+            .files
+            .get_file_by_path(path)
+            .await
+            .map_or(0, |record| record.size),
+        _ => 0,
+    };
+    let checked = state.quotas.check(owner, incoming, replaced).await;
+    checked.err().map(|e| error_response(&e))
+}
+
+/// 客户端在只下载目录中写入（delete 为 false），或删除会波及只上传、只下载目录时拒绝
+fn direction_rejection(
+    state: &AppData,
+    path: &str,
+    delete: bool,
+) -> Option<(StatusCode, Json<ApiResponse>)> {
+    let folder = match delete {
+        true => state.directions.blocks_delete(path),
+        false => state.directions.blocks_write(path),
+    }?;
+    let direction = match folder.direction {
+        SyncDirection::UploadOnly => "upload-only",
+        SyncDirection::DownloadOnly => "download-only",
+    };
+    Some(error_response(&Error::SyncDirection(format!(
+        "{} is {}",
+        match folder.folder.as_str() {
+            "" => "/",
+            folder => folder,
+        },
+        direction
+    ))))
+}
+
+// 状态码由错误类型决定，消息即错误本身的描述
+fn error_response(e: &Error) -> (StatusCode, Json<ApiResponse>) {
+    (e.status_code(), Json(ApiResponse::error(&e.to_string())))
+}
+
+// 本次上传让已用空间增长了 growth 字节
+async fn notify_storage_growth(state: &AppData, growth: u64) {
+    if let Ok(used_after) = state.files.total_size().await {
+        state.notifier.notify(Notification::StorageNearlyFull {
+            used_before: used_after.saturating_sub(growth),
+            used_after,
+        });
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteQuery {
+    /// 删除目录时必须显式指定
+    #[serde(default)]
+    pub recursive: bool,
+}
+
+/// 删除涉及的文件数和字节数
+#[derive(Debug, Serialize)]
+pub struct DeleteSummary {
+    pub files: usize,
+    pub bytes: u64,
+    /// 移入回收站时的条目，可用 `POST /api/trash/{id}/restore` 恢复
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trash_id: Option<uuid::Uuid>,
+}
+
+async fn delete_file(
+    State(state): State<AppState>,
+    user: Option<Extension<AuthenticatedUser>>,
+    peer: Option<Extension<PeerIp>>,
+    Path(path): Path<String>,
+    Query(query): Query<DeleteQuery>,
+) -> impl IntoResponse {
+    if let Some(rejection) = append_only_rejection(&state, user.as_deref(), &path) {
+        return rejection;
+    }
+    if let Some(rejection) = direction_rejection(&state, &path, true) {
+        return rejection;
+    }
+    let _guard = state.path_locks.lock(&case_key(&path)).await;
+    let file_path = state.storage_path.join(&path);
+
+    // 路径本身及其下所有子路径的记录
+    let prefix = format!("{}/", path.trim_end_matches('/'));
+    let records: Vec<FileRecord> = state
+        .files
+        .list_files()
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|r| r.path == path || r.path.starts_with(&prefix))
+        .collect();
+
+    let directories: Vec<DirectoryRecord> = state
+        .repository
+        .list_directories()
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|d| d.is_under(&path))
+        .collect();
+
+    if let Some(hold) = state.repository.find_overlapping_legal_hold(&path).await {
+        return held_response(&hold);
+    }
+
+    if !file_path.exists()
+        && directories.is_empty()
+        && (state.materialize_files || records.is_empty())
+    {
+        // 代理模式下没有缓存的文件可能只在上游，交给上游判断
+        if let Some(proxy) = &state.proxy {
+            proxy.enqueue(PendingWrite::Delete {
+                path,
+                recursive: query.recursive,
+            });
+            return (
+                StatusCode::OK,
+                Json(ApiResponse::success(DeleteSummary {
+                    files: 0,
+                    bytes: 0,
+                    trash_id: None,
+                })),
+            );
+        }
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("File not found")),
+        );
+    }
+
+    let mut summary = match records.as_slice() {
+        // 未被记录的明文文件
+        [] if file_path.is_file() => DeleteSummary {
+            files: 1,
+            bytes: file_path.metadata().map(|m| m.len()).unwrap_or(0),
+            trash_id: None,
+        },
+        _ => DeleteSummary {
+            files: records.len(),
+            bytes: records.iter().map(|r| r.size).sum(),
+            trash_id: None,
+        },
+    };
+
+    // 目录（包括只存在于记录中的虚拟目录）非空时必须带 recursive=true，
+    // 防止手误的路径一次删掉整棵树
+    let is_dir =
+        file_path.is_dir() || !directories.is_empty() || records.iter().any(|r| r.path != path);
+    let has_entries = std::fs::read_dir(&file_path).is_ok_and(|mut d| d.next().is_some())
+        || directories.iter().any(|d| d.path != path);
+    if is_dir && !query.recursive && (summary.files > 0 || has_entries) {
+        let message = format!(
+            "Directory is not empty ({} files, {} bytes); pass recursive=true to delete it",
+            summary.files, summary.bytes
+        );
+        return (
+            StatusCode::CONFLICT,
+            Json(ApiResponse::error_with_data(&message, summary)),
+        );
+    }
+
+    // 回收站开启时先把内容移走、记下条目，失败时什么都没有删除；到期清除时才粉碎
+    if state.trash.enabled() {
+        if file_path.exists() {
+            state.expected_writes.expect_removal(&path);
+        }
+        let entry = NewTrashEntry {
+            path: path.clone(),
+            files: records.clone(),
+            directories: directories.clone(),
+            size: summary.bytes,
+            deleted_by: user.as_ref().map(|user| user.id),
+        };
+        match state.trash.put(entry, &file_path).await {
+            Ok(entry) => summary.trash_id = Some(entry.id),
+            Err(e) => return error_response(&e),
+        }
+    }
+
+    // 记录删除之后才能判断对象是否还被引用，先记下它们用到的对象
+    let objects = match (&state.shredder, summary.trash_id) {
+        (Some(shredder), None) => shredder.objects_of(&records).await,
+        _ => Vec::new(),
+    };
+
+    // 从数据库删除记录
+    for record in records {
+        match state.files.delete_file(record.id).await {
+            Ok(_) => {
+                state.feed.deleted(&record.path);
+                state.notifier.notify(Notification::FileChanged {
+                    path: record.path,
+                    change: FileChange::Deleted,
+                });
+            }
+            Err(e) => tracing::warn!("Failed to delete file record: {}", e),
+        }
+    }
+    if !directories.is_empty() {
+        if let Err(e) = state.repository.delete_directories(&path).await {
+            tracing::warn!("Failed to delete directory records: {}", e);
+        }
+    }
+    if let Some(proxy) = &state.proxy {
+        proxy.enqueue(PendingWrite::Delete {
+            path: path.clone(),
+            recursive: query.recursive,
+        });
+    }
+    if summary.trash_id.is_some() {
+        return (StatusCode::OK, Json(ApiResponse::success(summary)));
+    }
+
+    if let Some(shredder) = &state.shredder {
+        let file = file_path.exists().then(|| {
+            state.expected_writes.expect_removal(&path);
+            file_path.as_path()
+        });
+        return match shredder.shred(file, &objects).await {
+            Ok(shredded) => {
+                let ip = peer.map(|peer| peer.0 .0);
+                let user = user.as_ref().map(|user| user.username.as_str());
+                shredder.record(state.clock.now(), ip, user, "DELETE", &path, shredded);
+                (StatusCode::OK, Json(ApiResponse::success(summary)))
+            }
+            Err(e) => error_response(&e),
+        };
+    }
+
+    if !file_path.exists() {
+        return (StatusCode::OK, Json(ApiResponse::success(summary)));
+    }
+
+    state.expected_writes.expect_removal(&path);
+    let result = if file_path.is_dir() {
+        tokio::fs::remove_dir_all(&file_path).await
+    } else {
+        tokio::fs::remove_file(&file_path).await
+    };
+
+    match result {
+        Ok(_) => (StatusCode::OK, Json(ApiResponse::success(summary))),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&format!("Failed to delete: {}", e))),
+        ),
+    }
+}
+
+// [知识点 #131] 设备管理 API
+// ----------------------------------------
+// 题目：设备注册与心跳的作用？
+//
+// 讲解：
+// 文件同步需要知道有哪些设备：
+// - 注册：新设备加入时获取唯一 ID
+// - 心跳：设备定期报告在线状态
+// - 列表：查看所有已注册设备
+//
+// 这是分布式系统的基础设施
+//
+// 思考：如何检测设备离线？
+// 超过 DEVICE_OFFLINE_AFTER_SECS 没有心跳的设备在列表中标记为离线
+// ----------------------------------------
+const DEVICE_OFFLINE_AFTER_SECS: i64 = 300;
+
+/// 设备列表中的一项，附带按最近心跳推算的在线状态
+#[derive(Debug, Serialize)]
+pub struct DeviceStatus {
+    #[serde(flatten)]
+    pub device: DeviceRecord,
+    pub online: bool,
+}
+
+async fn register_device(
+    State(state): State<AppState>,
+    user: Option<Extension<AuthenticatedUser>>,
+    Json(req): Json<RegisterDeviceRequest>,
+) -> impl IntoResponse {
+    let registered = async {
+        let device = state
+            .repository
+            .create_device(NewDeviceRecord { name: req.name })
+            .await?;
+        match user {
+            Some(user) => {
+                state
+                    .repository
+                    .set_device_owner(device.id, Some(user.id))
+                    .await
+            }
+            None => Ok(device),
+        }
+    };
+    match registered.await {
+        Ok(device) => {
+            state.notifier.notify(Notification::DeviceRegistered {
+                device_name: device.name.clone(),
+            });
+            (StatusCode::OK, Json(ApiResponse::success(device)))
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&format!(
+                "Failed to register device: {}",
+                e
+            ))),
+        ),
+    }
+}
+
+async fn list_devices(
+    State(state): State<AppState>,
+    namespace: Option<Extension<Namespace>>,
+) -> impl IntoResponse {
+    match state.repository.list_devices().await {
+        Ok(devices) => {
+            let now = state.clock.now();
+            let timeout = chrono::Duration::seconds(DEVICE_OFFLINE_AFTER_SECS);
+            let devices: Vec<DeviceStatus> = devices
+                .into_iter()
+                .filter(|device| owns_device(namespace.as_deref(), device))
+                .map(|device| DeviceStatus {
+                    online: device.is_online(now, timeout),
+                    device,
+                })
+                .collect();
+            (StatusCode::OK, Json(ApiResponse::success(devices)))
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&format!(
+                "Failed to list devices: {}",
+                e
+            ))),
+        ),
+    }
+}
+
+async fn device_heartbeat(
+    State(state): State<AppState>,
+    namespace: Option<Extension<Namespace>>,
+    Path(id): Path<uuid::Uuid>,
+) -> impl IntoResponse {
+    // 别人的设备当作不存在
+    if let Ok(device) = state.repository.get_device(id).await {
+        if !owns_device(namespace.as_deref(), &device) {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::error("Device not found")),
+            );
+        }
+    }
+    match state.repository.update_device_last_seen(id).await {
+        Ok(device) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(DeviceHeartbeat {
+                device,
+                pending_changes: state.changes.has_pending(id),
+            })),
+        ),
+        Err(e) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error(&format!("Device not found: {}", e))),
+        ),
+    }
+}
+
+/// 启用命名空间时只能看到自己注册的设备
+fn owns_device(namespace: Option<&Namespace>, device: &DeviceRecord) -> bool {
+    namespace.is_none_or(|namespace| device.owner == Some(namespace.user))
+}
+
+// 订阅文件变更，每条消息是一个 JSON 编码的 ChangeEvent
+async fn watch_changes(
+    State(state): State<AppState>,
+    namespace: Option<Extension<Namespace>>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let changes = state.feed.subscribe();
+    let namespace = namespace.map(|Extension(namespace)| namespace);
+    ws.on_upgrade(move |socket| push_changes(socket, changes, namespace))
+}
+
+// 落后太多时以 1013 关闭：漏掉的变更无法补发，客户端重连后应做一次完整同步。
+// 启用命名空间时只推送自己的变更，路径换成用户看到的路径
+async fn push_changes(
+    mut socket: WebSocket,
+    mut changes: broadcast::Receiver<ChangeEvent>,
+    namespace: Option<Namespace>,
+) {
+    loop {
+        tokio::select! {
+            change = changes.recv() => match change {
+                Ok(mut change) => {
+                    if let Some(namespace) = &namespace {
+                        let Some(path) = namespace.localize(&change.path) else {
+                            continue;
+                        };
+                        change.path = path;
+                    }
+                    let Ok(text) = serde_json::to_string(&change) else {
+                        continue;
+                    };
+                    if socket.send(Message::Text(text.into())).await.is_err() {
+                        break;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    let frame = CloseFrame {
+                        code: close_code::AGAIN,
+                        reason: format!("Missed {} changes, resync", missed).into(),
+                    };
+                    let _ = socket.send(Message::Close(Some(frame))).await;
+                    break;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            // 客户端不发业务消息，只需要发现它断开
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+async fn list_comments(
+    State(state): State<AppState>,
+    Query(query): Query<CommentsQuery>,
+) -> impl IntoResponse {
+    let record = match state.files.get_file_by_path(&query.path).await {
+        Ok(record) => record,
+        Err(_) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::error("File not found")),
+            )
+        }
+    };
+
+    match state.repository.list_comments_by_file(record.id).await {
+        Ok(mut comments) => {
+            comments.sort_by_key(|c| c.created_at);
+            (StatusCode::OK, Json(ApiResponse::success(comments)))
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&e.to_string())),
+        ),
+    }
+}
+
+async fn add_comment(
+    State(state): State<AppState>,
+    user: Option<Extension<AuthenticatedUser>>,
+    Json(req): Json<AddCommentRequest>,
+) -> impl IntoResponse {
+    let text = req.text.trim();
+    if text.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("Comment text is empty")),
+        );
+    }
+
+    let record = match state.files.get_file_by_path(&req.path).await {
+        Ok(record) => record,
+        Err(_) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::error("File not found")),
+            )
+        }
+    };
+
+    let author = match user {
+        Some(Extension(user)) => user.username,
+        None => req
+            .author
+            .map(|a| a.trim().to_string())
+            .filter(|a| !a.is_empty())
+            .unwrap_or_else(|| "anonymous".to_string()),
+    };
+    let new_comment = crate::db::NewCommentRecord {
+        file_id: record.id,
+        author,
+        text: text.to_string(),
+    };
+    match state.repository.create_comment(new_comment).await {
+        Ok(comment) => (StatusCode::CREATED, Json(ApiResponse::success(comment))),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&e.to_string())),
+        ),
+    }
+}
+
+async fn delete_comment(
+    State(state): State<AppState>,
+    namespace: Option<Extension<Namespace>>,
+    Path(id): Path<uuid::Uuid>,
+) -> impl IntoResponse {
+    // 启用命名空间时别人文件上的评论当作不存在
+    if namespace.is_some() {
+        let comments = state.repository.list_comments().await.unwrap_or_default();
+        let file = match comments.iter().find(|comment| comment.id == id) {
+            Some(comment) => state.repository.get_file_by_id(comment.file_id).await.ok(),
+            None => None,
+        };
+        if !file.is_some_and(|file| namespace::visible(namespace.as_deref(), &file.path)) {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::error("Comment not found")),
+            );
+        }
+    }
+
+    match state.repository.delete_comment(id).await {
+        Ok(()) => (StatusCode::OK, Json(ApiResponse::success(true))),
+        Err(Error::NotFound(_)) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Comment not found")),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&e.to_string())),
+        ),
+    }
+}
+
+// 文件更新与评论合并成一条时间线
+async fn list_activity(
+    State(state): State<AppState>,
+    namespace: Option<Extension<Namespace>>,
+    Query(query): Query<ActivityQuery>,
+) -> impl IntoResponse {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_ACTIVITY_LIMIT)
+        .min(MAX_ACTIVITY_LIMIT);
+    let files = visible_files(&state, namespace.as_deref())
+        .await
+        .unwrap_or_default();
+    let comments = state.repository.list_comments().await.unwrap_or_default();
+
+    let paths: std::collections::HashMap<uuid::Uuid, &str> =
+        files.iter().map(|f| (f.id, f.path.as_str())).collect();
+
+    let mut entries: Vec<ActivityEntry> = files
+        .iter()
+        .map(|f| ActivityEntry::FileUpdated {
+            path: f.path.clone(),
+            version: f.version,
+            at: f.updated_at,
+        })
+        .collect();
+    entries.extend(comments.into_iter().filter_map(|c| {
+        Some(ActivityEntry::Comment {
+            path: paths.get(&c.file_id)?.to_string(),
+            author: c.author,
+            text: c.text,
+            at: c.created_at,
+        })
+    }));
+
+    entries.sort_by_key(|e| std::cmp::Reverse(e.at()));
+    entries.truncate(limit);
+    (StatusCode::OK, Json(ApiResponse::success(entries)))
+}
+
+// 请求体是 JSON 对象：字符串值写入，null 删除该键（类似 JSON Merge Patch）
+// 通配符段之后不能再接固定段，所以挂在 /api/metadata/{*path}
+async fn patch_metadata(
+    State(state): State<AppState>,
+    Path(path): Path<String>,
+    Json(patch): Json<BTreeMap<String, Option<String>>>,
+) -> impl IntoResponse {
+    if let Some(message) = patch.iter().find_map(|(key, value)| {
+        if key.is_empty() || key.len() > MAX_METADATA_KEY_LEN {
+            Some(format!(
+                "Metadata key must be 1-{} bytes",
+                MAX_METADATA_KEY_LEN
+            ))
+        } else if value
+            .as_ref()
+            .is_some_and(|v| v.len() > MAX_METADATA_VALUE_LEN)
+        {
+            Some(format!(
+                "Metadata value for '{}' exceeds {} bytes",
+                key, MAX_METADATA_VALUE_LEN
+            ))
+        } else {
+            None
+        }
+    }) {
+        return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(&message)));
+    }
+
+    let record = match state.files.get_file_by_path(&path).await {
+        Ok(record) => record,
+        Err(_) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::error("File not found")),
+            )
+        }
+    };
+
+    let mut keys: std::collections::BTreeSet<&String> = record.metadata.keys().collect();
+    for (key, value) in &patch {
+        match value {
+            Some(_) => keys.insert(key),
+            None => keys.remove(key),
+        };
+    }
+    if keys.len() > MAX_METADATA_ENTRIES {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(&format!(
+                "At most {} metadata entries per file",
+                MAX_METADATA_ENTRIES
+            ))),
+        );
+    }
+
+    match state.files.update_file_metadata(record.id, patch).await {
+        Ok(record) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(FileInfo::from_record(&record))),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&e.to_string())),
+        ),
+    }
+}
+
+// 在所有文件记录中按路径和自定义元数据搜索
+async fn search_files(
+    State(state): State<AppState>,
+    namespace: Option<Extension<Namespace>>,
+    Query(query): Query<SearchQuery>,
+) -> impl IntoResponse {
+    if query.meta_value.is_some() && query.meta_key.is_none() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("meta_value requires meta_key")),
+        );
+    }
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_SEARCH_LIMIT)
+        .min(MAX_SEARCH_LIMIT);
+    let needle = query.q.as_deref().unwrap_or("").to_lowercase();
+
+    let namespace = namespace.as_deref();
+    let mut records: Vec<FileRecord> = visible_files(&state, namespace)
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        // 按用户看到的路径匹配，命名空间的前缀不算
+        .filter(|r| {
+            let path = namespace.and_then(|ns| ns.localize(&r.path));
+            let path = path.as_deref().unwrap_or(&r.path);
+            path.to_lowercase().contains(&needle)
+        })
+        .filter(|r| match (&query.meta_key, &query.meta_value) {
+            (Some(key), Some(value)) => r.metadata.get(key) == Some(value),
+            (Some(key), None) => r.metadata.contains_key(key),
+            _ => true,
+        })
+        .collect();
+    records.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let total = records.len();
+    let files = records
+        .iter()
+        .take(limit)
+        .map(FileInfo::from_record)
+        .collect();
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success(SearchResult { files, total })),
+    )
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PhotoQuery {
+    /// 拍摄日期下限（含）
+    pub from: Option<chrono::NaiveDate>,
+    /// 拍摄日期上限（不含）
+    pub to: Option<chrono::NaiveDate>,
+}
+
+// 按拍摄日期筛选图片，结果按时间排序并附带媒体元数据
+async fn list_photos(
+    State(state): State<AppState>,
+    namespace: Option<Extension<Namespace>>,
+    Query(query): Query<PhotoQuery>,
+) -> impl IntoResponse {
+    let records = match visible_files(&state, namespace.as_deref()).await {
+        Ok(records) => records,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error(&e.to_string())),
+            )
+        }
+    };
+
+    let mut photos: Vec<(chrono::NaiveDateTime, FileRecord)> = records
+        .into_iter()
+        .filter_map(|record| media::capture_date(&record).map(|date| (date, record)))
+        .filter(|(date, _)| query.from.is_none_or(|from| date.date() >= from))
+        .filter(|(date, _)| query.to.is_none_or(|to| date.date() < to))
+        .collect();
+    photos.sort_by_key(|(date, _)| *date);
+
+    let photos: Vec<FileRecord> = photos.into_iter().map(|(_, record)| record).collect();
+    (StatusCode::OK, Json(ApiResponse::success(photos)))
+}
+
+async fn list_photo_years(
+    State(state): State<AppState>,
+    namespace: Option<Extension<Namespace>>,
+) -> impl IntoResponse {
+    photos_by_date(&state, namespace.as_deref(), "").await
+}
+
+// 虚拟目录 by-date/2024/05：年、月两级目录，月目录下是当月拍摄的图片
+async fn list_photos_by_date(
+    State(state): State<AppState>,
+    namespace: Option<Extension<Namespace>>,
+    Path(path): Path<String>,
+) -> impl IntoResponse {
+    photos_by_date(&state, namespace.as_deref(), &path).await
+}
+
+async fn photos_by_date(
+    state: &AppData,
+    namespace: Option<&Namespace>,
+    path: &str,
+) -> (StatusCode, Json<ApiResponse>) {
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let parsed: Option<(Option<i32>, Option<u32>)> = match segments.as_slice() {
+        [] => Some((None, None)),
+        [year] => year.parse().ok().map(|y| (Some(y), None)),
+        [year, month] => year
+            .parse()
+            .ok()
+            .zip(month.parse().ok())
+            .map(|(y, m)| (Some(y), Some(m))),
+        _ => None,
+    };
+    let Some((year, month)) = parsed else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Invalid date path")),
+        );
+    };
+
+    let records = visible_files(state, namespace).await.unwrap_or_default();
+    let mut dated: Vec<(chrono::NaiveDateTime, &FileRecord)> = records
+        .iter()
+        .filter_map(|record| media::capture_date(record).map(|date| (date, record)))
+        .filter(|(date, _)| year.is_none_or(|y| date.year() == y))
+        .filter(|(date, _)| month.is_none_or(|m| date.month() == m))
+        .collect();
+    dated.sort_by_key(|(date, _)| *date);
+
+    // 虚拟目录也放在命名空间内，响应改写时才不会被当作别人的路径去掉
+    let virtual_path = |path: String| match namespace {
+        Some(namespace) => namespace.scope(&path),
+        None => path,
+    };
+    let mut entries: Vec<FileInfo> = Vec::new();
+    for (date, record) in dated {
+        let entry = match (year, month) {
+            (None, _) => FileInfo::virtual_dir(
+                date.year().to_string(),
+                virtual_path(format!("by-date/{}", date.year())),
+            ),
+            (Some(y), None) => FileInfo::virtual_dir(
+                format!("{:02}", date.month()),
+                virtual_path(format!("by-date/{}/{:02}", y, date.month())),
+            ),
+            (Some(_), Some(_)) => FileInfo::from_record(record),
+        };
+        if entries.last().map(|e| &e.path) != Some(&entry.path) {
+            entries.push(entry);
+        }
+    }
+
+    (StatusCode::OK, Json(ApiResponse::success(entries)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChunkPolicyQuery {
+    pub size: u64,
+    pub chunk_size: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChunkPolicy {
+    pub chunked: bool,
+    pub chunk_size: Option<usize>,
+}
+
+// 客户端上传前用文件大小（可附带期望的分块大小）询问服务端采用的分块策略
+async fn get_chunk_policy(
+    State(state): State<AppState>,
+    Query(query): Query<ChunkPolicyQuery>,
+) -> impl IntoResponse {
+    let chunk_size = state
+        .storage
+        .negotiate_chunk_size(query.size, query.chunk_size);
+    Json(ApiResponse::success(ChunkPolicy {
+        chunked: chunk_size.is_some(),
+        chunk_size,
+    }))
+}
+
+async fn list_versions(
+    State(state): State<AppState>,
+    namespace: Option<Extension<Namespace>>,
+) -> impl IntoResponse {
+    match visible_files(&state, namespace.as_deref()).await {
+        Ok(files) => (StatusCode::OK, Json(ApiResponse::success(files))),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&format!(
+                "Failed to list versions: {}",
+                e
+            ))),
+        ),
+    }
+}
+
+/// 小于这个大小的列表不压缩，省下的字节抵不过压缩的开销
+const GZIP_MIN_SIZE: usize = 1024;
+
+// 客户端比对整个状态只需要路径、哈希和版本，不需要完整记录里的时间戳。
+// ETag 是响应内容的哈希：状态没变时带 If-None-Match 再次请求只得到 304
+async fn list_hashes(
+    State(state): State<AppState>,
+    Query(query): Query<HashesQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let mut entries: Vec<HashEntry> = match state.files.list_files().await {
+        Ok(records) => records
+            .iter()
+            .filter(|record| record.path.starts_with(&query.prefix))
+            .map(HashEntry::from)
+            .collect(),
+        Err(e) => return error_response(&e).into_response(),
+    };
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    let body = match serde_json::to_vec(&ApiResponse::success(entries)) {
+        Ok(body) => body,
+        Err(e) => return error_response(&e.into()).into_response(),
+    };
+
+    let etag = {
+        use sha2::{Digest, Sha256};
+        HeaderValue::from_str(&format!("\"{:x}\"", Sha256::digest(&body)))
+            .expect("hex digest is a valid header value")
+    };
+    if let Some(condition) = headers.get(header::IF_NONE_MATCH) {
+        if etag_matches(condition, &etag) {
+            return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+        }
+    }
+
+    let accepts_gzip = headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| coding.split(';').next().unwrap_or_default().trim() == "gzip");
+    let mut response = Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ETAG, etag)
+        .header(header::VARY, "accept-encoding");
+    let body = if accepts_gzip && body.len() >= GZIP_MIN_SIZE {
+        use std::io::Write;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        match encoder.write_all(&body).and_then(|_| encoder.finish()) {
+            Ok(compressed) => {
+                response = response.header(header::CONTENT_ENCODING, "gzip");
+                compressed
+            }
+            Err(_) => body,
+        }
+    } else {
+        body
+    };
+    response
+        .body(Body::from(body))
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+}
+
+async fn get_sync_status(
+    State(state): State<AppState>,
+    namespace: Option<Extension<Namespace>>,
+    Path(file_id): Path<uuid::Uuid>,
+) -> impl IntoResponse {
+    if namespace.is_some() {
+        let file = state.repository.get_file_by_id(file_id).await.ok();
+        if !file.is_some_and(|file| namespace::visible(namespace.as_deref(), &file.path)) {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::error("File not found")),
+            );
+        }
+    }
+
+    match state.repository.list_syncs_by_file(file_id).await {
+        Ok(syncs) => (StatusCode::OK, Json(ApiResponse::success(syncs))),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&format!(
+                "Failed to get sync status: {}",
+                e
+            ))),
+        ),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SyncPlanRequest {
+    pub local_files: Vec<FileRecord>,
+    /// 本地的空目录；早期客户端不发送，计划中也就不含目录
+    #[serde(default)]
+    pub local_dirs: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SyncPlanItem {
+    pub file_id: String,
+    pub path: String,
+    pub action: String,
+    /// 目录条目的 file_id 为空，upload 表示在服务端创建，download 表示在本地创建
+    pub is_dir: bool,
+}
+
+fn sync_action_name(action: SyncAction) -> String {
+    match action {
+        SyncAction::Upload => "upload".to_string(),
+        SyncAction::Download => "download".to_string(),
+        SyncAction::Delete => "delete".to_string(),
+        SyncAction::Skip => "skip".to_string(),
+    }
+}
+
+async fn create_sync_plan(
+    State(state): State<AppState>,
+    identity: Option<Extension<ClientIdentity>>,
+    Json(req): Json<SyncPlanRequest>,
+) -> impl IntoResponse {
+    let device = identity.as_ref().map(|identity| identity.device_id);
+    let plans = async {
+        let plans = state
+            .sync_engine
+            .create_sync_plan(&req.local_files, device)
+            .await?;
+        let directories = match &req.local_dirs {
+            Some(local_dirs) => {
+                state
+                    .sync_engine
+                    .create_directory_plan(&req.local_files, local_dirs)
+                    .await?
+            }
+            None => Vec::new(),
+        };
+        Ok::<_, Error>((plans, directories))
+    }
+    .await;
+    match plans {
+        Ok((plans, directories)) => {
+            // 计划基于此刻的完整状态，之后的变更才算新的
+            if let Some(Extension(identity)) = identity {
+                state.changes.mark_synced(identity.device_id);
+            }
+            let mut items: Vec<SyncPlanItem> = plans
+                .into_iter()
+                .map(|p| SyncPlanItem {
+                    file_id: p.file_id.to_string(),
+                    path: p.path,
+                    action: sync_action_name(p.action),
+                    is_dir: false,
+                })
+                .collect();
+            items.extend(directories.into_iter().map(|d| SyncPlanItem {
+                file_id: String::new(),
+                path: d.path,
+                action: sync_action_name(d.action),
+                is_dir: true,
+            }));
+            (StatusCode::OK, Json(ApiResponse::success(items)))
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&format!("Failed to create sync plan: {}", e))),
+        ),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SyncExecuteRequest {
+    pub file_id: uuid::Uuid,
+    pub device_id: uuid::Uuid,
+    pub action: String,
+}
+
+async fn execute_sync(
+    State(state): State<AppState>,
+    user: Option<Extension<AuthenticatedUser>>,
+    namespace: Option<Extension<Namespace>>,
+    peer: Option<Extension<PeerIp>>,
+    Json(req): Json<SyncExecuteRequest>,
+) -> impl IntoResponse {
+    let action = match req.action.as_str() {
+        "upload" => SyncAction::Upload,
+        "download" => SyncAction::Download,
+        "delete" => SyncAction::Delete,
+        "skip" => SyncAction::Skip,
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error("Invalid action. Must be: upload, download, delete, or skip")),
+            );
+        }
+    };
+    let record = state.repository.get_file_by_id(req.file_id).await.ok();
+    if namespace.is_some()
+        && !record
+            .as_ref()
+            .is_some_and(|record| namespace::visible(namespace.as_deref(), &record.path))
+    {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("File not found")),
+        );
+    }
+    if action == SyncAction::Delete {
+        let Some(record) = &record else {
+            return error_response(&Error::NotFound(std::path::PathBuf::from(format!(
+                "file:{}",
+                req.file_id
+            ))));
+        };
+        if let Some(rejection) = append_only_rejection(&state, user.as_deref(), &record.path) {
+            return rejection;
+        }
+        if let Some(rejection) = direction_rejection(&state, &record.path, true) {
+            return rejection;
+        }
+    }
+
+    let apply = async {
+        match (action, record) {
+            (SyncAction::Delete, Some(record)) => {
+                remove_synced_file(&state, user.as_deref(), peer.as_deref(), record).await
+            }
+            _ => Ok(()),
+        }
+    };
+    match state
+        .sync_engine
+        .sync_file(req.file_id, req.device_id, apply)
+        .await
+    {
+        Ok(_) => (StatusCode::OK, Json(ApiResponse::success(true))),
+        Err(e) => {
+            notify_sync_failure(&state, req.device_id).await;
+            error_response(&e)
+        }
+    }
+}
+
+/// 同步计划中的删除：和 DELETE /api/files 一样持路径锁、检查保留，
+/// 回收站开启时移入回收站，否则粉碎或删除明文文件
+async fn remove_synced_file(
+    state: &AppData,
+    user: Option<&AuthenticatedUser>,
+    peer: Option<&PeerIp>,
+    record: FileRecord,
+) -> crate::error::Result<()> {
+    let _guard = state.path_locks.lock(&case_key(&record.path)).await;
+    // 等锁期间文件可能已被移动或删除
+    let record = state.repository.get_file_by_id(record.id).await?;
+    if let Some(hold) = state.repository.find_legal_hold(&record.path).await {
+        return Err(Error::Held(hold.path));
+    }
+    let path = record.path.clone();
+    let file_path = state.storage_path.join(&path);
+
+    if state.trash.enabled() {
+        if file_path.exists() {
+            state.expected_writes.expect_removal(&path);
+        }
+        let entry = NewTrashEntry {
+            path: path.clone(),
+            size: record.size,
+            files: vec![record.clone()],
+            directories: Vec::new(),
+            deleted_by: user.map(|user| user.id),
+        };
+        state.trash.put(entry, &file_path).await?;
+    }
+    let objects = match &state.shredder {
+        Some(shredder) if !state.trash.enabled() => {
+            shredder.objects_of(std::slice::from_ref(&record)).await
+        }
+        _ => Vec::new(),
+    };
+
+    state.files.delete_file(record.id).await?;
+    state.feed.deleted(&path);
+    state.notifier.notify(Notification::FileChanged {
+        path: path.clone(),
+        change: FileChange::Deleted,
+    });
+    if let Some(proxy) = &state.proxy {
+        proxy.enqueue(PendingWrite::Delete {
+            path: path.clone(),
+            recursive: false,
+        });
+    }
+    if state.trash.enabled() {
+        return Ok(());
+    }
+
+    if let Some(shredder) = &state.shredder {
+        let file = file_path.is_file().then(|| {
+            state.expected_writes.expect_removal(&path);
+            file_path.as_path()
+        });
+        let shredded = shredder.shred(file, &objects).await?;
+        let ip = peer.map(|peer| peer.0);
+        let user = user.map(|user| user.username.as_str());
+        shredder.record(state.clock.now(), ip, user, "POST", &path, shredded);
+        return Ok(());
+    }
+    if file_path.is_file() {
+        state.expected_writes.expect_removal(&path);
+        tokio::fs::remove_file(&file_path).await?;
+    }
+    Ok(())
+}
+
+async fn list_conflicts(
+    State(state): State<AppState>,
+    namespace: Option<Extension<Namespace>>,
+) -> impl IntoResponse {
+    match state.sync_engine.list_conflicts().await {
+        Ok(mut conflicts) => {
+            conflicts.retain(|conflict| namespace::visible(namespace.as_deref(), &conflict.path));
+            (StatusCode::OK, Json(ApiResponse::success(conflicts)))
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&format!(
+                "Failed to list conflicts: {}",
+                e
+            ))),
+        ),
+    }
+}
+
+fn trash_disabled() -> (StatusCode, Json<ApiResponse>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ApiResponse::error("Trash is not enabled on this server")),
+    )
+}
+
+// 启用命名空间时别人删除的条目当作不存在
+async fn trash_entry(
+    state: &AppData,
+    namespace: Option<&Namespace>,
+    id: uuid::Uuid,
+) -> Result<TrashEntry, (StatusCode, Json<ApiResponse>)> {
+    if !state.trash.enabled() {
+        return Err(trash_disabled());
+    }
+    match state.repository.get_trash_entry(id).await {
+        Ok(entry) if namespace::visible(namespace, &entry.path) => Ok(entry),
+        Ok(_) | Err(Error::NotFound(_)) => Err((
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Trash entry not found")),
+        )),
+        Err(e) => Err(error_response(&e)),
+    }
+}
+
+/// 回收站中的条目，最近删除的在前
+async fn list_trash(
+    State(state): State<AppState>,
+    namespace: Option<Extension<Namespace>>,
+) -> impl IntoResponse {
+    if !state.trash.enabled() {
+        return trash_disabled();
+    }
+    match state.repository.list_trash().await {
+        Ok(mut entries) => {
+            entries.retain(|entry| namespace::visible(namespace.as_deref(), &entry.path));
+            entries.sort_by_key(|entry| std::cmp::Reverse(entry.deleted_at));
+            let items: Vec<TrashItem> = entries
+                .iter()
+                .map(|entry| entry.info(state.trash.retention_days()))
+                .collect();
+            (StatusCode::OK, Json(ApiResponse::success(items)))
+        }
+        Err(e) => error_response(&e),
+    }
+}
+
+/// 把条目恢复到原路径；原路径已被占用时返回 409，条目保留在回收站
+async fn restore_trash(
+    State(state): State<AppState>,
+    namespace: Option<Extension<Namespace>>,
+    Path(id): Path<uuid::Uuid>,
+) -> impl IntoResponse {
+    let entry = match trash_entry(&state, namespace.as_deref(), id).await {
+        Ok(entry) => entry,
+        Err(rejection) => return rejection,
+    };
+    let path = entry.path.clone();
+    if let Some(rejection) = direction_rejection(&state, &path, false) {
+        return rejection;
+    }
+    let target = match path_guard::resolve(&state.storage_path, &path).await {
+        Ok(target) => target,
+        Err(e) => return error_response(&e),
+    };
+    let _guard = state.path_locks.lock(&case_key(&path)).await;
+
+    // 与移动一样，只差大小写的已有路径也算被占用；删除后留下的空目录不算
+    let key = case_key(&path);
+    let prefix = format!("{}/", key);
+    let files = state.files.list_files().await.unwrap_or_default();
+    let occupied = files.iter().any(|r| {
+        let other = case_key(&r.path);
+        other == key || other.starts_with(&prefix)
+    }) || target.is_file()
+        || std::fs::read_dir(&target).is_ok_and(|mut d| d.next().is_some());
+    if occupied {
+        return error_response(&Error::AlreadyExists(path.into()));
+    }
+
+    for record in &entry.files {
+        if let Some(hash) = &record.hash {
+            state.expected_writes.expect_write(&record.path, hash);
+        }
+    }
+    let restored = match state.trash.restore(&entry, &target).await {
+        Ok(restored) => restored,
+        Err(e) => return error_response(&e),
+    };
+    for record in &restored {
+        state.feed.written(
+            ChangeKind::Created,
+            &record.path,
+            record.hash.clone(),
+            record.version,
+        );
+        state.notifier.notify(Notification::FileChanged {
+            path: record.path.clone(),
+            change: FileChange::Created,
+        });
+    }
+    let item = entry.info(state.trash.retention_days());
+    (StatusCode::OK, Json(ApiResponse::success(item)))
+}
+
+/// 立即清除条目，不再等保留期满
+async fn purge_trash(
+    State(state): State<AppState>,
+    user: Option<Extension<AuthenticatedUser>>,
+    namespace: Option<Extension<Namespace>>,
+    peer: Option<Extension<PeerIp>>,
+    Path(id): Path<uuid::Uuid>,
+) -> impl IntoResponse {
+    if let Some(rejection) = append_only_token_rejection(user.as_deref(), "Purging trash") {
+        return rejection;
+    }
+    let entry = match trash_entry(&state, namespace.as_deref(), id).await {
+        Ok(entry) => entry,
+        Err(rejection) => return rejection,
+    };
+    match state.trash.purge(&entry).await {
+        Ok(shredded) => {
+            if let (Some(shredder), Some(shredded)) = (&state.shredder, shredded) {
+                let ip = peer.map(|peer| peer.0 .0);
+                let user = user.as_ref().map(|user| user.username.as_str());
+                shredder.record(state.clock.now(), ip, user, "DELETE", &entry.path, shredded);
+            }
+            (StatusCode::OK, Json(ApiResponse::success(true)))
+        }
+        Err(e) => error_response(&e),
+    }
+}
+
+async fn list_sync_directions(State(state): State<AppState>) -> impl IntoResponse {
+    Json(ApiResponse::success(state.directions.folders()))
+}
+
+async fn notify_sync_failure(state: &AppData, device_id: uuid::Uuid) {
+    let since = state.clock.now() - chrono::Duration::hours(24);
+    let failures = state
+        .repository
+        .count_failed_syncs(device_id, since)
+        .await
+        .unwrap_or(0);
+    let device_name = match state.repository.get_device(device_id).await {
+        Ok(device) => device.name,
+        Err(_) => device_id.to_string(),
+    };
+    state.notifier.notify(Notification::SyncFailures {
+        device_name,
+        failures,
+    });
+}
+
+// [知识点 #153] 法律保留（WORM）
+// ----------------------------------------
+// 题目：有留存义务的数据，怎样保证在期限内不被改动？
+//
+// 讲解：
+// WORM（Write Once Read Many）：写入后只读，不可修改也不可删除
+// 对某个路径加保留后：
+// - 该路径及其子路径的上传、更新、删除都被拒绝（423 Locked）
+// - 删除一个包含保留路径的目录同样被拒绝
+// - 检查放在 Repository 的写操作里，同步引擎、文件监控等入口都会被拦住
+//
+// 加保留任何人都可以做，解除必须由管理员（X-Admin-Token）操作：
+// 多一道锁只会让数据更安全，去掉锁才需要授权
+//
+// 思考：如果保留期间磁盘上的明文文件被直接改动，应该怎么处理？
+// ----------------------------------------
+#[derive(Debug, Deserialize)]
+pub struct AddLegalHoldRequest {
+    pub path: String,
+    pub reason: Option<String>,
+}
+
+fn held_response(hold: &crate::db::LegalHold) -> (StatusCode, Json<ApiResponse>) {
+    (
+        StatusCode::LOCKED,
+        Json(ApiResponse::error(
+            &Error::Held(hold.path.clone()).to_string(),
+        )),
+    )
+}
+
+async fn list_legal_holds(State(state): State<AppState>) -> impl IntoResponse {
+    match state.repository.list_legal_holds().await {
+        Ok(holds) => (StatusCode::OK, Json(ApiResponse::success(holds))),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&e.to_string())),
+        ),
+    }
+}
+
+async fn add_legal_hold(
+    State(state): State<AppState>,
+    Json(req): Json<AddLegalHoldRequest>,
+) -> impl IntoResponse {
+    let path = req.path.trim_matches('/').to_string();
+    if path.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("Path must not be empty")),
+        );
+    }
+
+    // 只能保留已存在的文件或目录
+    let prefix = format!("{}/", path);
+    let tracked = state
+        .files
+        .list_files()
+        .await
+        .unwrap_or_default()
+        .iter()
+        .any(|r| r.path == path || r.path.starts_with(&prefix));
+    if !tracked && !state.storage_path.join(&path).exists() {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("File not found")),
+        );
+    }
+
+    match state.repository.add_legal_hold(&path, req.reason).await {
+        Ok(hold) => (StatusCode::CREATED, Json(ApiResponse::success(hold))),
+        Err(Error::AlreadyExists(_)) => (
+            StatusCode::CONFLICT,
+            Json(ApiResponse::error("Path is already under legal hold")),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&e.to_string())),
+        ),
+    }
+}
+
+async fn remove_legal_hold(
+    State(state): State<AppState>,
+    Path(path): Path<String>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if let Err(rejection) = require_admin(&state, &headers, "Lifting a legal hold") {
+        return rejection;
+    }
+
+    match state
+        .repository
+        .remove_legal_hold(path.trim_matches('/'))
+        .await
+    {
+        Ok(_) => (StatusCode::OK, Json(ApiResponse::success(true))),
+        Err(Error::NotFound(_)) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("No legal hold on this path")),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&e.to_string())),
+        ),
+    }
+}
+
+// 管理操作要求服务端配置了管理员令牌，且请求头中的令牌与之相同
+fn require_admin(
+    state: &AppData,
+    headers: &HeaderMap,
+    action: &str,
+) -> Result<(), (StatusCode, Json<ApiResponse>)> {
+    let Some(expected) = state.admin_token.as_deref() else {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::error(&format!(
+                "{} requires RUSTCLOUD_ADMIN_TOKEN to be configured",
+                action
+            ))),
+        ));
+    };
+    let provided = headers
+        .get(ADMIN_TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok());
+    if provided != Some(expected) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::error("Invalid admin token")),
+        ));
+    }
+    Ok(())
+}
+
+// 启用命名空间时每个用户只看到、只能删除自己的规则
+async fn list_lifecycle_rules(
+    State(state): State<AppState>,
+    namespace: Option<Extension<Namespace>>,
+) -> impl IntoResponse {
+    match state.repository.list_lifecycle_rules().await {
+        Ok(mut rules) => {
+            if let Some(namespace) = namespace.as_deref() {
+                rules.retain(|rule| rule.owner == Some(namespace.user));
+            }
+            (StatusCode::OK, Json(ApiResponse::success(rules)))
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&e.to_string())),
+        ),
+    }
+}
+
+async fn create_lifecycle_rule(
+    State(state): State<AppState>,
+    user: Option<Extension<AuthenticatedUser>>,
+    namespace: Option<Extension<Namespace>>,
+    Json(mut req): Json<NewLifecycleRule>,
+) -> impl IntoResponse {
+    if let Some(rejection) = append_only_token_rejection(user.as_deref(), "lifecycle rules") {
+        return rejection;
+    }
+    req.owner = namespace.map(|namespace| namespace.user);
+    req.folder = req.folder.trim_matches('/').to_string();
+    req.archive_to = req.archive_to.map(|p| p.trim_matches('/').to_string());
+
+    let error = if req.folder.is_empty() {
+        Some("Folder must not be empty")
+    } else if req.delete_after_days.is_none() && req.archive_after_days.is_none() {
+        Some("Set delete_after_days and/or archive_after_days")
+    } else if req.archive_after_days.is_some()
+        && req.archive_to.as_deref().is_none_or(str::is_empty)
+    {
+        Some("archive_after_days requires archive_to")
+    } else {
+        None
+    };
+    if let Some(message) = error {
+        return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(message)));
+    }
+
+    match state.repository.create_lifecycle_rule(req).await {
+        Ok(rule) => (StatusCode::CREATED, Json(ApiResponse::success(rule))),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&e.to_string())),
+        ),
+    }
+}
+
+async fn delete_lifecycle_rule(
+    State(state): State<AppState>,
+    namespace: Option<Extension<Namespace>>,
+    Path(id): Path<uuid::Uuid>,
+) -> impl IntoResponse {
+    if let Some(namespace) = namespace.as_deref() {
+        let rules = state
+            .repository
+            .list_lifecycle_rules()
+            .await
+            .unwrap_or_default();
+        if !rules
+            .iter()
+            .any(|rule| rule.id == id && rule.owner == Some(namespace.user))
+        {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::error("Lifecycle rule not found")),
+            );
+        }
+    }
+    match state.repository.delete_lifecycle_rule(id).await {
+        Ok(_) => (StatusCode::OK, Json(ApiResponse::success(true))),
+        Err(Error::NotFound(_)) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Lifecycle rule not found")),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&e.to_string())),
+        ),
+    }
+}
+
+async fn list_rate_classes(State(state): State<AppState>) -> impl IntoResponse {
+    match state.repository.list_rate_classes().await {
+        Ok(classes) => (StatusCode::OK, Json(ApiResponse::success(classes))),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&e.to_string())),
+        ),
+    }
+}
+
+// 速率等级对所有客户端生效，只有管理员可以增删，否则客户端可以删掉限制自己的等级
+async fn create_rate_class(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<NewRateClass>,
+) -> impl IntoResponse {
+    if let Err(rejection) = require_admin(&state, &headers, "Creating a rate class") {
+        return rejection;
+    }
+    let error = if req.name.trim().is_empty() {
+        Some("Name must not be empty")
+    } else if req.upload_bytes_per_sec.is_none() && req.download_bytes_per_sec.is_none() {
+        Some("Set upload_bytes_per_sec and/or download_bytes_per_sec")
+    } else if req.upload_bytes_per_sec == Some(0) || req.download_bytes_per_sec == Some(0) {
+        Some("Rates must be greater than 0")
+    } else if req.start_hour.is_some() != req.end_hour.is_some() {
+        Some("start_hour and end_hour must be set together")
+    } else if req.start_hour.is_some_and(|h| h > 23) || req.end_hour.is_some_and(|h| h > 24) {
+        Some("Hours must be within 0-24")
+    } else if req.start_hour.is_some() && req.start_hour == req.end_hour {
+        Some("start_hour and end_hour must differ")
+    } else {
+        None
+    };
+    if let Some(message) = error {
+        return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(message)));
+    }
+
+    match state.repository.create_rate_class(req).await {
+        Ok(class) => (StatusCode::CREATED, Json(ApiResponse::success(class))),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&e.to_string())),
+        ),
+    }
+}
+
+async fn delete_rate_class(
+    State(state): State<AppState>,
+    Path(id): Path<uuid::Uuid>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if let Err(rejection) = require_admin(&state, &headers, "Removing a rate class") {
+        return rejection;
+    }
+    match state.repository.delete_rate_class(id).await {
+        Ok(_) => (StatusCode::OK, Json(ApiResponse::success(true))),
+        Err(Error::NotFound(_)) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Rate class not found")),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&e.to_string())),
+        ),
+    }
+}
+
+async fn list_mounts(State(state): State<AppState>) -> impl IntoResponse {
+    match state.repository.list_mounts().await {
+        Ok(mounts) => {
+            let infos: Vec<_> = mounts.iter().map(|m| m.info()).collect();
+            (StatusCode::OK, Json(ApiResponse::success(infos)))
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&e.to_string())),
+        ),
+    }
+}
+
+// 挂载点必须是本地不存在的路径；挂载前先确认远程目录可以访问
+async fn create_mount(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<CreateMountRequest>,
+) -> impl IntoResponse {
+    if let Err(rejection) = require_admin(&state, &headers, "Mounting a remote share") {
+        return rejection;
+    }
+    let path = match logical_path::normalize(&req.path) {
+        Ok(path) if !path.is_empty() => path,
+        Ok(_) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error("Mount path must not be empty")),
+            )
+        }
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error(&format!("Invalid path: {}", e))),
+            )
+        }
+    };
+    let url = req.url.trim().trim_end_matches('/').to_string();
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(
+                "URL must start with http:// or https://",
+            )),
+        );
+    }
+
+    let prefix = format!("{}/", path);
+    let records = state.files.list_files().await.unwrap_or_default();
+    let exists = state.storage_path.join(&path).exists()
+        || records
+            .iter()
+            .any(|r| r.path == path || r.path.starts_with(&prefix));
+    if exists {
+        return (
+            StatusCode::CONFLICT,
+            Json(ApiResponse::error(&format!(
+                "{} already exists locally",
+                path
+            ))),
+        );
+    }
+
+    let new_mount = NewMountRecord {
+        path,
+        url,
+        remote_path: req.remote_path.trim_matches('/').to_string(),
+        token: req.token.filter(|t| !t.is_empty()),
+    };
+    match state.mounts.probe(&new_mount).await {
+        Ok(()) => {}
+        Err(Error::NotFound(_)) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error(&format!(
+                    "'{}' is not a folder on {}",
+                    new_mount.remote_path, new_mount.url
+                ))),
+            )
+        }
+        // 远程拒绝的是请求里给的令牌，不是调用者本身的登录
+        Err(e @ Error::Unauthorized(_)) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error(&e.to_string())),
+            )
+        }
+        Err(e) => {
+            return (
+                StatusCode::BAD_GATEWAY,
+                Json(ApiResponse::error(&e.to_string())),
+            )
+        }
+    }
+
+    // 父目录在本地建好，挂载点才会出现在普通的目录列表里
+    if let Some(parent) = state.storage_path.join(&new_mount.path).parent() {
+        if let Err(e) = tokio::fs::create_dir_all(parent).await {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error(&e.to_string())),
+            );
+        }
+    }
+    match state.repository.create_mount(new_mount).await {
+        Ok(mount) => {
+            state.mounts.insert(mount.clone());
+            (
+                StatusCode::CREATED,
+                Json(ApiResponse::success(mount.info())),
+            )
+        }
+        Err(Error::AlreadyExists(existing)) => (
+            StatusCode::CONFLICT,
+            Json(ApiResponse::error(&format!(
+                "Overlaps the mount at {}",
+                existing.display()
+            ))),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&e.to_string())),
+        ),
+    }
+}
+
+async fn delete_mount(
+    State(state): State<AppState>,
+    Path(id): Path<uuid::Uuid>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if let Err(rejection) = require_admin(&state, &headers, "Removing a mount") {
+        return rejection;
+    }
+    match state.repository.delete_mount(id).await {
+        Ok(_) => {
+            state.mounts.remove(id);
+            (StatusCode::OK, Json(ApiResponse::success(true)))
+        }
+        Err(Error::NotFound(_)) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Mount not found")),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&e.to_string())),
+        ),
+    }
+}
+
+async fn list_bans(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    if let Err(rejection) = require_admin(&state, &headers, "Listing bans") {
+        return rejection;
+    }
+    let bans = state.firewall.bans(state.clock.now());
+    (StatusCode::OK, Json(ApiResponse::success(bans)))
+}
+
+async fn delete_ban(
+    State(state): State<AppState>,
+    Path(ip): Path<std::net::IpAddr>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if let Err(rejection) = require_admin(&state, &headers, "Lifting a ban") {
+        return rejection;
+    }
+    if state.firewall.unban(ip) {
+        (StatusCode::OK, Json(ApiResponse::success(true)))
+    } else {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Address is not banned")),
+        )
+    }
+}
+
+async fn list_audit(
+    State(state): State<AppState>,
+    Query(query): Query<AuditQuery>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if let Err(rejection) = require_admin(&state, &headers, "Reading the audit log") {
+        return rejection;
+    }
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_AUDIT_LIMIT)
+        .min(MAX_AUDIT_LIMIT);
+    match state.firewall.recent_audit(limit).await {
+        Ok(entries) => (StatusCode::OK, Json(ApiResponse::success(entries))),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&e.to_string())),
+        ),
+    }
+}
+
+// 预览：列出规则现在执行会产生的操作，不做任何修改
+async fn preview_lifecycle(
+    State(state): State<AppState>,
+    namespace: Option<Extension<Namespace>>,
+) -> impl IntoResponse {
+    let now = state.clock.now();
+    let planned = match namespace.as_deref() {
+        Some(namespace) => state.lifecycle.plan_for(now, namespace.user).await,
+        None => state.lifecycle.plan(now).await,
+    };
+    match planned {
+        Ok(actions) => (StatusCode::OK, Json(ApiResponse::success(actions))),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&e.to_string())),
+        ),
+    }
+}
+
+// 立即执行一次，不必等待后台任务
+// 启用命名空间时只执行调用者自己的规则，其余规则留给后台任务
+async fn run_lifecycle(
+    State(state): State<AppState>,
+    user: Option<Extension<AuthenticatedUser>>,
+    namespace: Option<Extension<Namespace>>,
+) -> impl IntoResponse {
+    if let Some(rejection) = append_only_token_rejection(user.as_deref(), "lifecycle rules") {
+        return rejection;
+    }
+    let now = state.clock.now();
+    let report = match namespace.as_deref() {
+        Some(namespace) => state.lifecycle.run_for(now, namespace.user).await,
+        None => state.lifecycle.run(now).await,
+    };
+    match report {
+        Ok(report) => (StatusCode::OK, Json(ApiResponse::success(report))),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&e.to_string())),
+        ),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ColdReportQuery {
+    /// 多少天未读取也未修改算作冷数据
+    pub days: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ColdFile {
+    pub path: String,
+    pub size: u64,
+    pub last_used_at: chrono::DateTime<chrono::Utc>,
+    pub idle_days: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ColdReport {
+    pub days: u32,
+    pub total_files: usize,
+    pub total_bytes: u64,
+    /// 最久未使用的在前
+    pub files: Vec<ColdFile>,
+}
+
+const DEFAULT_COLD_DAYS: u32 = 90;
+
+async fn cold_data_report(
+    State(state): State<AppState>,
+    namespace: Option<Extension<Namespace>>,
+    Query(query): Query<ColdReportQuery>,
+) -> impl IntoResponse {
+    // 先落盘内存中的访问记录，避免刚读过的文件被算作冷数据
+    if let Err(e) = state.access.flush().await {
+        tracing::warn!("Failed to flush access times: {}", e);
+    }
+
+    let days = query.days.unwrap_or(DEFAULT_COLD_DAYS);
+    let now = state.clock.now();
+    let mut files: Vec<ColdFile> = visible_files(&state, namespace.as_deref())
+        .await
+        .unwrap_or_default()
+        .iter()
+        .map(|r| ColdFile {
+            path: r.path.clone(),
+            size: r.size,
+            last_used_at: r.last_used_at(),
+            idle_days: (now - r.last_used_at()).num_days(),
+        })
+        .filter(|f| f.idle_days >= days as i64)
+        .collect();
+    files.sort_by_key(|f| f.last_used_at);
+
+    let report = ColdReport {
+        days,
+        total_files: files.len(),
+        total_bytes: files.iter().map(|f| f.size).sum(),
+        files,
+    };
+    (StatusCode::OK, Json(ApiResponse::success(report)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DuplicatesQuery {
+    /// 忽略小于该字节数的文件，默认跳过空文件
+    pub min_size: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DuplicateGroup {
+    pub hash: String,
+    pub size: u64,
+    pub paths: Vec<String>,
+    /// 只保留一份时逻辑上可以省下的空间
+    pub reclaimable_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DuplicatesReport {
+    pub total_groups: usize,
+    pub reclaimable_bytes: u64,
+    /// 可节省空间多的在前
+    pub groups: Vec<DuplicateGroup>,
+}
+
+// 对象存储按哈希去重，物理上只存一份；这里统计的是目录树中逻辑上的重复
+async fn duplicates_report(
+    State(state): State<AppState>,
+    namespace: Option<Extension<Namespace>>,
+    Query(query): Query<DuplicatesQuery>,
+) -> impl IntoResponse {
+    let min_size = query.min_size.unwrap_or(1);
+    let mut by_hash: HashMap<String, DuplicateGroup> = HashMap::new();
+    let records = visible_files(&state, namespace.as_deref()).await;
+    for record in records.unwrap_or_default() {
+        let Some(hash) = record.hash else { continue };
+        if record.size < min_size {
+            continue;
+        }
+        by_hash
+            .entry(hash.clone())
+            .or_insert_with(|| DuplicateGroup {
+                hash,
+                size: record.size,
+                paths: Vec::new(),
+                reclaimable_bytes: 0,
+            })
+            .paths
+            .push(record.path);
+    }
+
+    let mut groups: Vec<DuplicateGroup> = by_hash
+        .into_values()
+        .filter(|g| g.paths.len() > 1)
+        .map(|mut g| {
+            g.paths.sort();
+            g.reclaimable_bytes = g.size * (g.paths.len() as u64 - 1);
+            g
+        })
+        .collect();
+    groups.sort_by(|a, b| {
+        b.reclaimable_bytes
+            .cmp(&a.reclaimable_bytes)
+            .then_with(|| a.paths[0].cmp(&b.paths[0]))
+    });
+
+    let report = DuplicatesReport {
+        total_groups: groups.len(),
+        reclaimable_bytes: groups.iter().map(|g| g.reclaimable_bytes).sum(),
+        groups,
+    };
+    (StatusCode::OK, Json(ApiResponse::success(report)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UsageReportQuery {
+    /// 返回的文件数上限
+    pub top: Option<usize>,
+    /// 目录汇总取路径的前几级
+    pub depth: Option<usize>,
+    /// 仅 stale 报告使用：多少个月未读取也未修改
+    pub months: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UsageFile {
+    pub path: String,
+    pub size: u64,
+    pub last_used_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FolderUsage {
+    /// 根目录下的文件汇总到 "/"
+    pub folder: String,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UsageReport {
+    pub total_files: usize,
+    pub total_bytes: u64,
+    /// 只包含前 top 个文件，汇总数据覆盖全部匹配的文件
+    pub files: Vec<UsageFile>,
+    /// 占用空间大的在前
+    pub folders: Vec<FolderUsage>,
+}
+
+const DEFAULT_REPORT_TOP: usize = 20;
+const DEFAULT_REPORT_DEPTH: usize = 1;
+const DEFAULT_STALE_MONTHS: u32 = 6;
+
+/// 路径的前 depth 级目录，例如 depth = 1 时 "photos/2023/a.jpg" -> "photos"
+fn rollup_folder(path: &str, depth: usize) -> String {
+    let dirs: Vec<&str> = path.split('/').collect();
+    let dirs = &dirs[..dirs.len() - 1];
+    if dirs.is_empty() || depth == 0 {
+        return "/".to_string();
+    }
+    dirs[..depth.min(dirs.len())].join("/")
+}
+
+/// 启用命名空间时按用户看到的路径汇总目录，汇总出的目录再放回命名空间内
+fn build_usage_report(
+    mut records: Vec<crate::db::FileRecord>,
+    namespace: Option<&Namespace>,
+    query: &UsageReportQuery,
+) -> UsageReport {
+    let depth = query.depth.unwrap_or(DEFAULT_REPORT_DEPTH);
+    let mut folders: HashMap<String, FolderUsage> = HashMap::new();
+    for record in &records {
+        let folder = match namespace {
+            Some(ns) => {
+                let path = ns.localize(&record.path).unwrap_or_default();
+                match rollup_folder(&path, depth) {
+                    root if root == "/" => root,
+                    folder => ns.scope(&folder),
+                }
+            }
+            None => rollup_folder(&record.path, depth),
+        };
+        let usage = folders.entry(folder.clone()).or_insert(FolderUsage {
+            folder,
+            files: 0,
+            bytes: 0,
+        });
+        usage.files += 1;
+        usage.bytes += record.size;
+    }
+    let mut folders: Vec<FolderUsage> = folders.into_values().collect();
+    folders.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.folder.cmp(&b.folder)));
+
+    records.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+    let total_files = records.len();
+    let total_bytes = records.iter().map(|r| r.size).sum();
+    let files = records
+        .into_iter()
+        .take(query.top.unwrap_or(DEFAULT_REPORT_TOP))
+        .map(|r| UsageFile {
+            last_used_at: r.last_used_at(),
+            path: r.path,
+            size: r.size,
+        })
+        .collect();
+
+    UsageReport {
+        total_files,
+        total_bytes,
+        files,
+        folders,
+    }
+}
+
+// rcloud du --top 的数据来源
+async fn largest_files_report(
+    State(state): State<AppState>,
+    namespace: Option<Extension<Namespace>>,
+    Query(query): Query<UsageReportQuery>,
+) -> impl IntoResponse {
+    let namespace = namespace.as_deref();
+    let records = visible_files(&state, namespace).await.unwrap_or_default();
+    let report = build_usage_report(records, namespace, &query);
+    (StatusCode::OK, Json(ApiResponse::success(report)))
+}
+
+async fn stale_files_report(
+    State(state): State<AppState>,
+    namespace: Option<Extension<Namespace>>,
+    Query(query): Query<UsageReportQuery>,
+) -> impl IntoResponse {
+    if let Err(e) = state.access.flush().await {
+        tracing::warn!("Failed to flush access times: {}", e);
+    }
+
+    let months = query.months.unwrap_or(DEFAULT_STALE_MONTHS);
+    let cutoff = state
+        .clock
+        .now()
+        .checked_sub_months(chrono::Months::new(months))
+        .unwrap_or(chrono::DateTime::<chrono::Utc>::MIN_UTC);
+    let namespace = namespace.as_deref();
+    let records = visible_files(&state, namespace)
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|r| r.last_used_at() <= cutoff)
+        .collect();
+    let report = build_usage_report(records, namespace, &query);
+    (StatusCode::OK, Json(ApiResponse::success(report)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateShareRequest {
+    pub path: String,
+    /// 有效期（小时），不填则永久有效
+    pub expires_in_hours: Option<i64>,
+    #[serde(flatten)]
+    pub limits: ShareLimits,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShareStats {
+    pub share_id: uuid::Uuid,
+    pub path: String,
+    pub access_count: usize,
+    pub bytes_served: u64,
+    pub unique_ips: usize,
+    /// 正在进行的下载
+    pub active_streams: usize,
+    pub last_accessed_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// 最近的访问，新的在前
+    pub recent: Vec<ShareAccessRecord>,
+}
+
+const RECENT_SHARE_ACCESSES: usize = 20;
+
+async fn create_share(
+    State(state): State<AppState>,
+    user: Option<Extension<AuthenticatedUser>>,
+    Json(req): Json<CreateShareRequest>,
+) -> impl IntoResponse {
+    if req.expires_in_hours.is_some_and(|h| h <= 0) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("expires_in_hours must be positive")),
+        );
+    }
+    let limits = &req.limits;
+    if limits.max_downloads == Some(0)
+        || limits.max_bytes == Some(0)
+        || limits.max_concurrent == Some(0)
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("Share limits must be positive")),
+        );
+    }
 
-    let result = if file_path.is_dir() {
-        tokio::fs::remove_dir_all(&file_path).await
-    } else {
-        tokio::fs::remove_file(&file_path).await
-    };
+    let expires_at = req
+        .expires_in_hours
+        .map(|h| state.clock.now() + chrono::Duration::hours(h));
+    match state
+        .repository
+        .create_share(NewShareRecord {
+            path: req.path,
+            expires_at,
+            limits: req.limits,
+            created_by: user.map(|user| user.id),
+        })
+        .await
+    {
+        Ok(share) => (StatusCode::CREATED, Json(ApiResponse::success(share))),
+        Err(Error::NotFound(_)) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("File not found")),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&e.to_string())),
+        ),
+    }
+}
 
-    match result {
+async fn list_shares(State(state): State<AppState>) -> impl IntoResponse {
+    match state.repository.list_shares().await {
+        Ok(shares) => (StatusCode::OK, Json(ApiResponse::success(shares))),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(&e.to_string())),
+        ),
+    }
+}
+
+async fn delete_share(
+    State(state): State<AppState>,
+    namespace: Option<Extension<Namespace>>,
+    Path(id): Path<uuid::Uuid>,
+) -> impl IntoResponse {
+    if let Ok(share) = state.repository.get_share(id).await {
+        if !namespace::visible(namespace.as_deref(), &share.path) {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::error("Share not found")),
+            );
+        }
+    }
+    match state.repository.delete_share(id).await {
         Ok(_) => (StatusCode::OK, Json(ApiResponse::success(true))),
+        Err(Error::NotFound(_)) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Share not found")),
+        ),
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ApiResponse::error(&format!("Failed to delete: {}", e))),
+            Json(ApiResponse::error(&e.to_string())),
         ),
     }
 }
 
-// [知识点 #131] 设备管理 API
+// [知识点 #151] 分享访问日志
 // ----------------------------------------
-// 题目：设备注册与心跳的作用？
+// 题目：怎么知道发出去的分享链接有没有被打开、是否已经泄露？
 //
 // 讲解：
-// 文件同步需要知道有哪些设备：
-// - 注册：新设备加入时获取唯一 ID
-// - 心跳：设备定期报告在线状态
-// - 列表：查看所有已注册设备
+// 每次通过分享链接下载都追加一条访问记录：时间、来源 IP、返回的字节数
+// - 字节数取响应的 Content-Length，Range 请求只计实际返回的部分
+// - IP 优先取反向代理设置的 X-Forwarded-For，否则取 TCP 连接的对端地址
+// - 失败的请求（404、416 等）不计入
 //
-// 这是分布式系统的基础设施
+// 统计接口汇总访问次数、总流量、不同 IP 数量，
+// 陌生 IP 大量出现时说明链接可能已经外泄，应当撤销
 //
-// 思考：如何检测设备离线？
+// 思考：X-Forwarded-For 可以被客户端伪造，什么时候才能信任它？
 // ----------------------------------------
-async fn register_device(
+async fn download_share(
     State(state): State<AppState>,
-    Json(req): Json<RegisterDeviceRequest>,
-) -> impl IntoResponse {
-    match state
+    Path(id): Path<uuid::Uuid>,
+    request: Request,
+) -> Response {
+    let share = match state.repository.get_share(id).await {
+        Ok(share) => share,
+        Err(_) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::error("Share not found")),
+            )
+                .into_response()
+        }
+    };
+    if share.is_expired(state.clock.now()) {
+        return (
+            StatusCode::GONE,
+            Json(ApiResponse::error("Share link has expired")),
+        )
+            .into_response();
+    }
+
+    let accesses = state
         .repository
-        .create_device(NewDeviceRecord { name: req.name })
+        .list_share_accesses(share.id)
         .await
-    {
-        Ok(device) => (StatusCode::OK, Json(ApiResponse::success(device))),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
+        .unwrap_or_default();
+    let bytes_served = accesses.iter().map(|a| a.bytes).sum();
+    if let Some(quota) = share.exhausted_quota(accesses.len() as u64, bytes_served) {
+        return (
+            StatusCode::GONE,
             Json(ApiResponse::error(&format!(
-                "Failed to register device: {}",
-                e
+                "Share link has reached its {}",
+                quota
             ))),
-        ),
+        )
+            .into_response();
+    }
+    let Some(guard) = state
+        .share_streams
+        .try_acquire(share.id, share.limits.max_concurrent)
+    else {
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(ApiResponse::error(
+                "Too many concurrent downloads for this share",
+            )),
+        )
+            .into_response();
+    };
+
+    let ip = client_ip(&request);
+    let response = serve_content(&state, &share.path, request).await;
+    if !response.status().is_success() {
+        return response;
     }
+    let bytes = response
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    record_share_access(&state, &share, ip, bytes).await;
+    response.map(|body| guard.attach(body))
 }
 
-async fn list_devices(State(state): State<AppState>) -> impl IntoResponse {
-    match state.repository.list_devices().await {
-        Ok(devices) => (StatusCode::OK, Json(ApiResponse::success(devices))),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ApiResponse::error(&format!(
-                "Failed to list devices: {}",
-                e
-            ))),
-        ),
+async fn record_share_access(state: &AppData, share: &ShareRecord, ip: Option<String>, bytes: u64) {
+    let access = ShareAccessRecord {
+        share_id: share.id,
+        accessed_at: state.clock.now(),
+        ip: ip.clone(),
+        bytes,
+    };
+    if let Err(e) = state.repository.record_share_access(access).await {
+        tracing::warn!("Failed to record access to share {}: {}", share.id, e);
     }
+    state.notifier.notify(Notification::ShareAccessed {
+        path: share.path.clone(),
+        ip,
+    });
 }
 
-async fn device_heartbeat(
+fn client_ip(request: &Request) -> Option<String> {
+    request
+        .headers()
+        .get("x-forwarded-for")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .map(|ip| ip.trim().to_string())
+        .filter(|ip| !ip.is_empty())
+        .or_else(|| {
+            request
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|info| info.0.ip().to_string())
+        })
+}
+
+async fn get_share_stats(
     State(state): State<AppState>,
+    namespace: Option<Extension<Namespace>>,
     Path(id): Path<uuid::Uuid>,
 ) -> impl IntoResponse {
-    match state.repository.update_device_last_seen(id).await {
-        Ok(device) => (StatusCode::OK, Json(ApiResponse::success(device))),
-        Err(e) => (
-            StatusCode::NOT_FOUND,
-            Json(ApiResponse::error(&format!("Device not found: {}", e))),
-        ),
-    }
+    let share = match state.repository.get_share(id).await {
+        Ok(share) if namespace::visible(namespace.as_deref(), &share.path) => share,
+        _ => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::error("Share not found")),
+            )
+        }
+    };
+    let accesses = state
+        .repository
+        .list_share_accesses(id)
+        .await
+        .unwrap_or_default();
+
+    let unique_ips = accesses
+        .iter()
+        .filter_map(|a| a.ip.as_deref())
+        .collect::<std::collections::HashSet<_>>()
+        .len();
+    let stats = ShareStats {
+        share_id: share.id,
+        path: share.path,
+        access_count: accesses.len(),
+        bytes_served: accesses.iter().map(|a| a.bytes).sum(),
+        unique_ips,
+        active_streams: state.share_streams.active(share.id),
+        last_accessed_at: accesses.last().map(|a| a.accessed_at),
+        recent: accesses
+            .iter()
+            .rev()
+            .take(RECENT_SHARE_ACCESSES)
+            .cloned()
+            .collect(),
+    };
+    (StatusCode::OK, Json(ApiResponse::success(stats)))
 }
 
-async fn list_versions(State(state): State<AppState>) -> impl IntoResponse {
-    match state.repository.list_files().await {
-        Ok(files) => (StatusCode::OK, Json(ApiResponse::success(files))),
+#[derive(Debug, Deserialize)]
+pub struct NotificationPreferencesRequest {
+    pub rules: Vec<NotificationRule>,
+}
+
+// 登录后只能读写自己的规则：规则按用户名保存，否则可以替别人订阅通知
+fn other_user_rejection(
+    caller: Option<&AuthenticatedUser>,
+    user: &str,
+) -> Option<(StatusCode, Json<ApiResponse>)> {
+    caller.filter(|caller| caller.username != user).map(|_| {
+        (
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::error(
+                "Notification preferences belong to another user",
+            )),
+        )
+    })
+}
+
+async fn get_notification_preferences(
+    State(state): State<AppState>,
+    caller: Option<Extension<AuthenticatedUser>>,
+    Path(user): Path<String>,
+) -> impl IntoResponse {
+    if let Some(rejection) = other_user_rejection(caller.as_deref(), &user) {
+        return rejection;
+    }
+    match state.repository.get_notification_preferences(&user).await {
+        Ok(prefs) => (StatusCode::OK, Json(ApiResponse::success(prefs))),
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ApiResponse::error(&format!(
-                "Failed to list versions: {}",
-                e
-            ))),
+            Json(ApiResponse::error(&e.to_string())),
         ),
     }
 }
 
-async fn get_sync_status(
+// 整体替换该用户的规则列表
+async fn set_notification_preferences(
     State(state): State<AppState>,
-    Path(file_id): Path<uuid::Uuid>,
+    caller: Option<Extension<AuthenticatedUser>>,
+    Path(user): Path<String>,
+    Json(req): Json<NotificationPreferencesRequest>,
 ) -> impl IntoResponse {
-    match state.repository.list_syncs_by_file(file_id).await {
-        Ok(syncs) => (StatusCode::OK, Json(ApiResponse::success(syncs))),
+    if let Some(rejection) = other_user_rejection(caller.as_deref(), &user) {
+        return rejection;
+    }
+    if let Some(message) = req
+        .rules
+        .iter()
+        .find_map(|rule| validate_rule(rule, state.notifier.email_enabled()))
+    {
+        return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(&message)));
+    }
+
+    match state
+        .repository
+        .set_notification_preferences(&user, req.rules)
+        .await
+    {
+        Ok(prefs) => (StatusCode::OK, Json(ApiResponse::success(prefs))),
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ApiResponse::error(&format!(
-                "Failed to get sync status: {}",
-                e
-            ))),
+            Json(ApiResponse::error(&e.to_string())),
         ),
     }
 }
 
-#[derive(Debug, Deserialize)]
-pub struct SyncPlanRequest {
-    pub local_files: Vec<FileRecord>,
+fn validate_rule(rule: &NotificationRule, email_enabled: bool) -> Option<String> {
+    match &rule.channel {
+        NotificationChannel::Webhook { url }
+            if !(url.starts_with("http://") || url.starts_with("https://")) =>
+        {
+            return Some(format!("Webhook URL must be http(s): {}", url));
+        }
+        NotificationChannel::Email { .. } if !email_enabled => {
+            return Some("Email notifications require SMTP to be configured".to_string());
+        }
+        NotificationChannel::Email { to } if !to.contains('@') => {
+            return Some(format!("Invalid email address: {}", to));
+        }
+        _ => {}
+    }
+    if rule.path.is_some() && rule.event != NotificationEvent::FileChanged {
+        return Some("path is only supported for file_changed rules".to_string());
+    }
+    match (rule.event, rule.threshold) {
+        (NotificationEvent::StorageNearlyFull, None) => {
+            Some("storage_nearly_full requires a threshold in bytes".to_string())
+        }
+        (_, Some(0)) => Some("Threshold must be greater than 0".to_string()),
+        _ => None,
+    }
 }
 
-#[derive(Debug, Serialize)]
-pub struct SyncPlanItem {
-    pub file_id: String,
-    pub path: String,
-    pub action: String,
+/// 可以直接打开读取的内容：落盘文件、对象文件，或还原到暂存文件的对象，暂存文件随之删除
+struct ContentSource {
+    path: std::path::PathBuf,
+    _staged: Option<StagedUpload>,
 }
 
-async fn create_sync_plan(
-    State(state): State<AppState>,
-    Json(req): Json<SyncPlanRequest>,
-) -> impl IntoResponse {
-    match state.sync_engine.create_sync_plan(&req.local_files).await {
-        Ok(plans) => {
-            let items: Vec<SyncPlanItem> = plans
-                .into_iter()
-                .map(|p| SyncPlanItem {
-                    file_id: p.file_id.to_string(),
-                    path: p.path,
-                    action: match p.action {
-                        SyncAction::Upload => "upload".to_string(),
-                        SyncAction::Download => "download".to_string(),
-                        SyncAction::Delete => "delete".to_string(),
-                        SyncAction::Skip => "skip".to_string(),
-                    },
-                })
-                .collect();
-            (StatusCode::OK, Json(ApiResponse::success(items)))
+impl From<std::path::PathBuf> for ContentSource {
+    fn from(path: std::path::PathBuf) -> Self {
+        ContentSource {
+            path,
+            _staged: None,
         }
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ApiResponse::error(&format!("Failed to create sync plan: {}", e))),
-        ),
     }
 }
 
-#[derive(Debug, Deserialize)]
-pub struct SyncExecuteRequest {
-    pub file_id: uuid::Uuid,
-    pub device_id: uuid::Uuid,
-    pub action: String,
+impl std::ops::Deref for ContentSource {
+    type Target = std::path::Path;
+
+    fn deref(&self) -> &std::path::Path {
+        &self.path
+    }
 }
 
-async fn execute_sync(
-    State(state): State<AppState>,
-    Json(req): Json<SyncExecuteRequest>,
-) -> impl IntoResponse {
-    let action = match req.action.as_str() {
-        "upload" => SyncAction::Upload,
-        "download" => SyncAction::Download,
-        "delete" => SyncAction::Delete,
-        "skip" => SyncAction::Skip,
-        _ => {
-            return (
-                StatusCode::BAD_REQUEST,
-                Json(ApiResponse::error("Invalid action. Must be: upload, download, delete, or skip")),
-            );
-        }
-    };
+impl AsRef<std::path::Path> for ContentSource {
+    fn as_ref(&self) -> &std::path::Path {
+        &self.path
+    }
+}
 
-    match state.sync_engine.sync_file(req.file_id, req.device_id, action).await {
-        Ok(_) => (StatusCode::OK, Json(ApiResponse::success(true))),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ApiResponse::error(&format!("Failed to execute sync: {}", e))),
-        ),
+// 找到路径对应内容在磁盘上的位置：落盘文件或对象文件
+async fn resolve_content_path(
+    state: &AppData,
+    path: &str,
+) -> std::result::Result<ContentSource, (StatusCode, &'static str)> {
+    if let Some(Upstream::Missing) = read_through(state, path).await {
+        return Err((StatusCode::NOT_FOUND, "File not found"));
     }
+    local_content_path(state, path).await
 }
 
-fn list_directory(
-    target: &std::path::Path,
-    base: &std::path::Path,
-) -> Result<Vec<FileInfo>, Error> {
-    if !target.exists() {
-        return Err(Error::NotFound(target.to_path_buf()));
+/// 对象内容与哈希不一致，不发送错误的内容
+const CORRUPTED: (StatusCode, &str) = (
+    StatusCode::INTERNAL_SERVER_ERROR,
+    "File content is corrupted on the server",
+);
+
+async fn local_content_path(
+    state: &AppData,
+    path: &str,
+) -> std::result::Result<ContentSource, (StatusCode, &'static str)> {
+    let file_path = state.storage_path.join(path);
+    if file_path.is_file() {
+        return Ok(file_path.into());
+    }
+    if file_path.is_dir() {
+        return Err((StatusCode::BAD_REQUEST, "Path is a directory"));
+    }
+    if state.materialize_files {
+        return Err((StatusCode::NOT_FOUND, "File not found"));
     }
 
-    if !target.is_dir() {
-        return Err(Error::InvalidPath("Not a directory".to_string()));
+    // 未落盘模式下直接读取对象文件。压缩存储或分块存储的小对象没有可以直接打开的对象文件，
+    // 按内容还原到暂存文件；大对象只会分块存储，下载时按清单逐块发送，见 send_object_stream
+    match state.files.get_file_by_path(path).await {
+        Ok(FileRecord {
+            hash: Some(hash),
+            size,
+            ..
+        }) => match state.storage.check_object(&hash).await {
+            Err(Error::Corrupted(_)) => Err(CORRUPTED),
+            _ if size <= COMPRESS_MAX_SIZE && !state.storage.object_path(&hash).exists() => {
+                match state.storage.stage_object(&hash).await {
+                    Ok(staged) => Ok(ContentSource {
+                        path: staged.path().to_path_buf(),
+                        _staged: Some(staged),
+                    }),
+                    Err(Error::Corrupted(_)) => Err(CORRUPTED),
+                    Err(_) => Err((StatusCode::NOT_FOUND, "File content not found")),
+                }
+            }
+            _ => Ok(state.storage.object_path(&hash).into()),
+        },
+        Ok(_) => Err((StatusCode::NOT_FOUND, "File content not found")),
+        Err(_) => Err((StatusCode::NOT_FOUND, "File not found")),
     }
+}
 
-    let mut files = Vec::new();
-    let entries = std::fs::read_dir(target)?;
+// 代理模式下读取前先向上游确认，必要时把上游的内容拉进本地缓存；
+// 返回 None 表示按本地缓存处理：独立运行、本地有未转发的写入，或上游不可用
+async fn read_through(state: &AppData, path: &str) -> Option<Upstream> {
+    let proxy = state.proxy.as_ref()?;
+    if proxy.is_pending(path) {
+        return None;
+    }
+    let upstream = match proxy.lookup(path).await {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            tracing::debug!("Serving {} from cache: {}", path, e);
+            return None;
+        }
+    };
+    if let Upstream::File(info) = &upstream {
+        if let Err(e) = fill_cache(state, proxy, path, info).await {
+            tracing::warn!("Failed to cache {} from upstream: {}", path, e);
+        }
+    }
+    Some(upstream)
+}
 
-    for entry in entries {
-        let entry = entry?;
-        let path = entry.path();
-        let metadata = entry.metadata()?;
+// 本地没有缓存或缓存的哈希与上游不同时，从上游拉取一份写入本地
+async fn fill_cache(
+    state: &AppData,
+    proxy: &ProxyService,
+    path: &str,
+    info: &FileInfo,
+) -> crate::error::Result<()> {
+    let cached = state.files.get_file_by_path(path).await.ok();
+    if cached.is_some_and(|record| record.hash == info.hash)
+        && local_content_path(state, path).await.is_ok()
+    {
+        return Ok(());
+    }
 
-        let relative_path = path
-            .strip_prefix(base)
-            .ok()
-            .and_then(|p| p.to_str())
-            .map(|s| s.to_string())
-            .unwrap_or_default();
+    let mut upload = state.storage.begin_upload().await?;
+    let mut stream = proxy.fetch(path).await?.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| Error::Upstream(e.to_string()))?;
+        upload.write(&chunk).await?;
+    }
+    let mut headers = HeaderMap::new();
+    if let Some(hash) = info
+        .hash
+        .as_deref()
+        .and_then(|h| HeaderValue::from_str(h).ok())
+    {
+        headers.insert(CONTENT_HASH_HEADER, hash);
+    }
+    let (status, Json(response)) = write_upload(
+        state,
+        path.to_string(),
+        OnConflict::Overwrite,
+        &headers,
+        None,
+        None,
+        upload,
+    )
+    .await;
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(Error::Upstream(response.error.unwrap_or_default()))
+    }
+}
 
-        files.push(FileInfo {
-            name: entry.file_name().to_string_lossy().to_string(),
-            path: relative_path,
-            is_dir: metadata.is_dir(),
-            size: metadata.len(),
-            modified: metadata.modified().ok().map(|t| {
-                let datetime: chrono::DateTime<chrono::Utc> = t.into();
-                datetime.to_rfc3339()
+// 上游的列表加上本地尚未转发的写入，刚上传的文件立即可见
+async fn overlay_pending(
+    state: &AppData,
+    proxy: &ProxyService,
+    dir: &str,
+    files: &mut Vec<FileInfo>,
+) {
+    let dir = dir.trim_matches('/');
+    for write in proxy.pending() {
+        let path = write.path();
+        if path.rsplit_once('/').map_or("", |(parent, _)| parent) != dir {
+            continue;
+        }
+        files.retain(|file| file.path != path);
+        match write {
+            PendingWrite::Upload { .. } => {
+                if let Ok(record) = state.files.get_file_by_path(path).await {
+                    files.push(FileInfo::from_record(&record));
+                }
+            }
+            PendingWrite::Folder { .. } => files.push(FileInfo {
+                name: path.rsplit('/').next().unwrap_or(path).to_string(),
+                path: path.to_string(),
+                is_dir: true,
+                size: 0,
+                modified: None,
+                hash: None,
+                version: None,
+                deduplicated: false,
+                metadata: BTreeMap::new(),
             }),
-            hash: None,
-            version: None,
-        });
+            PendingWrite::Delete { .. } => {}
+        }
     }
+}
 
-    Ok(files)
+#[derive(Debug, Serialize)]
+pub struct ProxyStatus {
+    pub upstream: String,
+    /// 尚未转发到上游的写操作，按转发顺序
+    pub pending: Vec<PendingWrite>,
+    /// 最近一次转发失败或被上游拒绝的原因，之后转发成功时清空
+    pub last_error: Option<String>,
+}
+
+async fn proxy_status(State(state): State<AppState>) -> impl IntoResponse {
+    let Some(proxy) = &state.proxy else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("This server is not running as a proxy")),
+        );
+    };
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success(ProxyStatus {
+            upstream: proxy.url().to_string(),
+            pending: proxy.pending(),
+            last_error: proxy.last_error(),
+        })),
+    )
+}
+
+// inline 让浏览器直接播放/显示，attachment 让浏览器另存为；非 ASCII 文件名按 RFC 5987 编码
+fn content_disposition(kind: &'static str, path: &str) -> HeaderValue {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let plain = name
+        .chars()
+        .all(|c| (c.is_ascii_graphic() && c != '"' && c != '\\') || c == ' ');
+    let value = if plain {
+        format!("{}; filename=\"{}\"", kind, name)
+    } else {
+        let encoded: String = name
+            .bytes()
+            .map(|b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                    (b as char).to_string()
+                }
+                _ => format!("%{:02X}", b),
+            })
+            .collect();
+        format!("{}; filename*=UTF-8''{}", kind, encoded)
+    };
+    HeaderValue::from_str(&value).unwrap_or_else(|_| HeaderValue::from_static(kind))
+}
+
+// 暂存文件移到存储目录下作为明文文件，再写入对象存储；落盘前先登记，文件监控会跳过这次写入
+async fn write_materialized(
+    state: &AppData,
+    path: &str,
+    file_path: &std::path::Path,
+    upload: &mut StagedUpload,
+) -> crate::error::Result<(String, u64)> {
+    if let Some(parent) = file_path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let hash = upload.finish().await?;
+    state.expected_writes.expect_write(path, &hash);
+    upload.persist(file_path).await?;
+    state.storage.store_file(file_path).await
+}
+
+// 磁盘目录列表中的文件补上记录里的哈希、版本和自定义元数据
+fn attach_record_metadata(files: &mut [FileInfo], records: &[FileRecord]) {
+    let by_path: HashMap<&str, &FileRecord> =
+        records.iter().map(|r| (r.path.as_str(), r)).collect();
+    for file in files.iter_mut().filter(|f| !f.is_dir) {
+        if let Some(record) = by_path.get(file.path.as_str()) {
+            file.metadata = record.metadata.clone();
+            // 磁盘扫描得不到哈希和版本，以元数据记录为准
+            file.hash = record.hash.clone();
+            file.version = Some(record.version);
+        }
+    }
+}
+
+// 把只存在于元数据中的条目（未落盘的文件及其父目录）合并进目录列表
+// 挂载点作为目录出现在上级目录的列表中
+fn merge_mount_entries(state: &AppData, files: &mut Vec<FileInfo>, dir: &str) {
+    for entry in state.mounts.entries_under(dir) {
+        if !files.iter().any(|f| f.path == entry.path) {
+            files.push(entry);
+        }
+    }
+}
+
+// 显式创建的目录（包括空目录）作为目录条目合并进列表，更深的目录显示为其上级目录
+fn merge_directory_entries(files: &mut Vec<FileInfo>, directories: &[DirectoryRecord], dir: &str) {
+    let dir = dir.trim_matches('/');
+    let prefix = if dir.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", dir)
+    };
+    for directory in directories {
+        let Some(rest) = directory.path.strip_prefix(&prefix) else {
+            continue;
+        };
+        let child = rest.split('/').next().unwrap_or(rest);
+        let child_path = format!("{}{}", prefix, child);
+        if !child.is_empty() && !files.iter().any(|f| f.path == child_path) {
+            files.push(FileInfo::virtual_dir(child.to_string(), child_path));
+        }
+    }
+}
+
+fn merge_record_entries(files: &mut Vec<FileInfo>, records: &[FileRecord], dir: &str) {
+    let dir = dir.trim_matches('/');
+    let prefix = if dir.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", dir)
+    };
+    let mut seen: std::collections::HashSet<String> =
+        files.iter().map(|f| f.path.clone()).collect();
+
+    for record in records {
+        let Some(rest) = record.path.strip_prefix(&prefix) else {
+            continue;
+        };
+        match rest.split_once('/') {
+            None => {
+                if seen.insert(record.path.clone()) {
+                    files.push(FileInfo::from_record(record));
+                }
+            }
+            Some((child, _)) => {
+                let child_path = format!("{}{}", prefix, child);
+                if seen.insert(child_path.clone()) {
+                    files.push(FileInfo::virtual_dir(child.to_string(), child_path));
+                }
+            }
+        }
+    }
 }
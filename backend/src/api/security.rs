@@ -0,0 +1,106 @@
+//! 面向浏览器的安全响应头与跨站写请求拦截
+//!
+//! 所有 API 响应都带上 HSTS、nosniff、禁止嵌入框架和严格的 CSP：接口只返回数据，
+//! 用户上传的 HTML 在浏览器中直接打开时也不会执行脚本。
+//!
+//! 接口通过 `Authorization` 请求头认证，不使用 Cookie，但未启用认证时任何网页都能
+//! 让浏览器向本机服务端发起写请求。因此写请求（GET/HEAD/OPTIONS 以外）要求来自
+//! 同源页面或配置的可信来源：优先看浏览器填写的 `Sec-Fetch-Site`，旧浏览器退回到
+//! 比较 `Origin` 与 `Host`。两者都没有的请求（CLI、脚本）不受影响。
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+
+use super::routes::{ApiResponse, AppState};
+use crate::config::WebSecurityConfig;
+
+const CONTENT_SECURITY_POLICY: &str = "default-src 'none'; img-src 'self'; media-src 'self'; \
+     style-src 'unsafe-inline'; frame-ancestors 'none'; sandbox";
+
+pub async fn secure_browser_requests(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = if is_safe_method(request.method())
+        || is_same_origin(request.headers(), &state.web_security)
+    {
+        next.run(request).await
+    } else {
+        tracing::warn!(
+            method = %request.method(),
+            path = %request.uri().path(),
+            "Rejected cross-site write request"
+        );
+        (
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::error("Cross-site requests are not allowed")),
+        )
+            .into_response()
+    };
+
+    // 挂载转发等场景下 handler 可能已经设置了同名头，保留原值
+    let headers = response.headers_mut();
+    if state.web_security.hsts_max_age_secs > 0 {
+        if let Ok(value) = HeaderValue::from_str(&format!(
+            "max-age={}; includeSubDomains",
+            state.web_security.hsts_max_age_secs
+        )) {
+            headers
+                .entry(header::STRICT_TRANSPORT_SECURITY)
+                .or_insert(value);
+        }
+    }
+    headers
+        .entry(header::X_CONTENT_TYPE_OPTIONS)
+        .or_insert(HeaderValue::from_static("nosniff"));
+    headers
+        .entry(header::X_FRAME_OPTIONS)
+        .or_insert(HeaderValue::from_static("DENY"));
+    headers
+        .entry(header::CONTENT_SECURITY_POLICY)
+        .or_insert(HeaderValue::from_static(CONTENT_SECURITY_POLICY));
+    headers
+        .entry(header::REFERRER_POLICY)
+        .or_insert(HeaderValue::from_static("no-referrer"));
+    response
+}
+
+fn is_safe_method(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+fn is_same_origin(headers: &HeaderMap, config: &WebSecurityConfig) -> bool {
+    let origin = headers.get(header::ORIGIN).and_then(|v| v.to_str().ok());
+    if let Some(origin) = origin {
+        let origin = origin.trim_end_matches('/');
+        if config
+            .trusted_origins
+            .iter()
+            .any(|trusted| trusted.trim_end_matches('/').eq_ignore_ascii_case(origin))
+        {
+            return true;
+        }
+    }
+
+    // "none" 表示用户直接在地址栏发起，"same-site" 包括同一站点的其他子域名，不放行
+    if let Some(site) = headers.get("sec-fetch-site").and_then(|v| v.to_str().ok()) {
+        return matches!(site, "same-origin" | "none");
+    }
+
+    let Some(origin) = origin else {
+        return true;
+    };
+    // 沙箱页面、本地文件等不透明来源为 "null"
+    let Some((_, authority)) = origin.split_once("://") else {
+        return false;
+    };
+    headers
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|host| host.eq_ignore_ascii_case(authority))
+}
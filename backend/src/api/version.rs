@@ -0,0 +1,83 @@
+//! 拒绝低于最低版本的客户端
+//!
+//! 设置 `RUSTCLOUD_MIN_CLIENT_VERSION` 后，`X-Client-Version` 低于该版本的请求返回
+//! 426 和升级提示，避免旧客户端按过时的约定同步、把错误的状态写回服务端。
+//! 最低版本也出现在 `/api/capabilities` 中，客户端连接时就能给出提示；
+//! 健康检查和协议握手不受限制。同步和写操作缺少该请求头时同样视为过旧，
+//! 否则旧客户端只要不发版本头就能绕过检查；浏览器发起的请求（网页、分享页）不带版本头，照常放行。
+
+use std::cmp::Ordering;
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use rustcloud_types::{compare_versions, CLIENT_VERSION_HEADER};
+
+use super::routes::{ApiResponse, AppState};
+
+/// 旧客户端也要能访问，才能得知需要升级
+const UNCHECKED_ROUTES: &[&str] = &["/api/health", "/api/capabilities"];
+
+/// 缺少版本头时仍然要求升级的请求：同步，以及上传、删除等写操作
+fn requires_version(request: &Request) -> bool {
+    let read_only = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+    !read_only || request.uri().path().starts_with("/api/sync/")
+}
+
+/// 浏览器总会带上 Origin、Sec-Fetch-Site 或会话 Cookie 之一，命令行客户端都不会
+fn is_browser(headers: &HeaderMap) -> bool {
+    headers.contains_key(header::ORIGIN)
+        || headers.contains_key("sec-fetch-site")
+        || headers.contains_key(header::COOKIE)
+}
+
+pub async fn reject_outdated_clients(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(minimum) = &state.min_client_version else {
+        return next.run(request).await;
+    };
+    if UNCHECKED_ROUTES.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+    let version = request
+        .headers()
+        .get(CLIENT_VERSION_HEADER)
+        .and_then(|v| v.to_str().ok());
+    // 无法解析的版本号按过旧处理：多半是不遵守约定的旧客户端
+    let outdated = match version {
+        Some(version) => {
+            compare_versions(version, minimum).is_none_or(|order| order == Ordering::Less)
+        }
+        None => requires_version(&request) && !is_browser(request.headers()),
+    };
+    if !outdated {
+        return next.run(request).await;
+    }
+
+    let version = version.unwrap_or("(unknown)");
+    tracing::warn!(
+        path = %request.uri().path(),
+        version,
+        "Rejected outdated client"
+    );
+    (
+        StatusCode::UPGRADE_REQUIRED,
+        Json(ApiResponse::error_with_data(
+            &format!(
+                "Client version {} is no longer supported by this server; please upgrade to {} or newer",
+                version, minimum
+            ),
+            serde_json::json!({ "min_client_version": minimum }),
+        )),
+    )
+        .into_response()
+}
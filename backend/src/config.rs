@@ -16,9 +16,14 @@
 // ----------------------------------------
 
 use serde::Deserialize;
+use std::fmt;
 use std::path::PathBuf;
 
-#[derive(Debug, Clone, Deserialize)]
+use crate::error::{Error, Result};
+use crate::service::firewall::IpNet;
+use crate::service::storage::{DEFAULT_SHARD_DEPTH, MAX_CHUNK_SIZE, MIN_CHUNK_SIZE, SHARD_DEPTHS};
+
+#[derive(Clone, Deserialize)]
 pub struct Config {
     #[serde(default = "default_host")]
     pub host: String,
@@ -29,8 +34,487 @@ pub struct Config {
     #[serde(default = "default_storage_path")]
     pub storage_path: PathBuf,
 
+    /// 元数据的存储方式，数据库文件位于 storage_path 下
+    #[serde(default)]
+    pub database: DatabaseBackend,
+
     #[serde(default = "default_max_file_size")]
     pub max_file_size: u64,
+
+    /// 基础分块大小；小于它的文件不分块，超大文件按策略自动放大
+    #[serde(default = "default_chunk_size")]
+    pub chunk_size: usize,
+
+    /// 对象目录 objects/ab/... 取哈希的前几个字符（2 或 4）；改动后启动时在后台迁移已有对象
+    #[serde(default = "default_shard_depth")]
+    pub shard_depth: usize,
+
+    /// 上传时是否在 storage_path 下额外落一份明文文件（对象存储始终是权威副本）
+    #[serde(default = "default_materialize_files")]
+    pub materialize_files: bool,
+
+    /// 启动时用元数据中的哈希预热 路径 -> (修改时间, 大小, 哈希) 缓存，
+    /// 文件信息查询和文件监控不必重新读取没变的文件
+    #[serde(default)]
+    pub warm_hash_cache: bool,
+
+    /// 读取对象时校验内容是否与哈希一致：小对象在发送前校验，大对象在后台校验
+    #[serde(default)]
+    pub verify_reads: bool,
+
+    /// 小对象用 zstd 压缩存储，积累足够的样本后训练字典，相似的小文件压缩率更高
+    #[serde(default)]
+    pub compress_objects: bool,
+
+    /// 设备基于过期版本上传时另存副本还是拒绝
+    #[serde(default)]
+    pub conflicts: ConflictStrategy,
+
+    /// 只允许追加的目录：可以上传新文件和新版本，不能删除，生命周期规则也不处理
+    #[serde(default)]
+    pub append_only: Vec<String>,
+
+    /// 只上传的目录：客户端只能上传，不能删除，同步时也不下载
+    #[serde(default)]
+    pub upload_only: Vec<String>,
+
+    /// 只下载的目录：客户端不能上传、新建、删除或移动其中的文件
+    #[serde(default)]
+    pub download_only: Vec<String>,
+
+    /// 删除文件时覆写不再被引用的对象文件和明文文件，再从磁盘移除
+    #[serde(default)]
+    pub shred_deleted: bool,
+
+    /// 删除的文件在回收站中保留的天数，到期后清除（开启粉碎时此时才粉碎）；0 表示直接删除
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: u32,
+
+    /// 文件监控跳过的路径（gitignore 写法），存储目录根部的 .rcloudignore 排在其后
+    #[serde(default)]
+    pub ignore: Vec<String>,
+
+    /// 邮件通知使用的 SMTP 服务器；未配置时只能使用 webhook 通知
+    #[serde(default)]
+    pub smtp: Option<SmtpConfig>,
+
+    /// 外部哈希信誉服务；未配置时不检查
+    #[serde(default)]
+    pub reputation: Option<ReputationConfig>,
+
+    /// 管理操作（如解除法律保留）需要在 X-Admin-Token 请求头中提供该令牌
+    #[serde(default)]
+    pub admin_token: Option<String>,
+
+    /// 低于该版本的客户端（按 X-Client-Version 请求头判断）被拒绝并提示升级；未设置时不检查
+    #[serde(default)]
+    pub min_client_version: Option<String>,
+
+    /// 生命周期规则的执行间隔（秒），0 表示不自动执行
+    #[serde(default = "default_lifecycle_interval_secs")]
+    pub lifecycle_interval_secs: u64,
+
+    /// 用户认证；未配置时所有接口都无需登录
+    #[serde(default)]
+    pub auth: Option<AuthConfig>,
+
+    /// 作为另一台 RustCloud 前面的缓存代理运行；未配置时是独立的服务端
+    #[serde(default)]
+    pub upstream: Option<UpstreamConfig>,
+
+    /// 按客户端地址放行/拒绝，以及认证失败过多时的临时封禁
+    #[serde(default)]
+    pub network: NetworkConfig,
+
+    /// 浏览器访问时的安全响应头与跨站写请求检查
+    #[serde(default)]
+    pub web: WebSecurityConfig,
+
+    /// 阻塞线程池、哈希计算和文件操作的并发上限
+    #[serde(default)]
+    pub workers: WorkerConfig,
+}
+
+/// 元数据存储后端
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DatabaseBackend {
+    /// db.json，每次修改整体重写，适合记录较少的部署
+    #[default]
+    Json,
+    /// db.sqlite；首次启动时自动导入已有的 db.json
+    Sqlite,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct AuthConfig {
+    /// 签发 JWT 使用的 HS256 密钥，更换后所有已签发的令牌失效
+    pub jwt_secret: String,
+
+    #[serde(default = "default_token_ttl_secs")]
+    pub token_ttl_secs: u64,
+
+    /// 是否开放 /api/auth/register
+    #[serde(default = "default_allow_registration")]
+    pub allow_registration: bool,
+
+    /// 每个用户只能看到自己的文件，存放在存储目录的 users/{用户 ID}/ 下
+    #[serde(default)]
+    pub user_namespaces: bool,
+
+    /// 没有单独设置配额的用户名下文件可占用的字节数；未配置时不限制
+    #[serde(default)]
+    pub default_quota_bytes: Option<u64>,
+}
+
+/// 代理模式：读取时从上游拉取并缓存到本地，写入先落本地再异步转发
+#[derive(Clone, Deserialize)]
+pub struct UpstreamConfig {
+    /// 上游服务端地址，如 "https://cloud.example.com"
+    pub url: String,
+
+    /// 上游启用认证时使用的 JWT
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NetworkConfig {
+    /// 非空时只允许这些地址或网段访问，如 "192.168.1.0/24"、"::1"
+    #[serde(default)]
+    pub allow: Vec<String>,
+
+    /// 始终拒绝的地址或网段，优先于 allow
+    #[serde(default)]
+    pub deny: Vec<String>,
+
+    /// 窗口期内认证失败达到该次数后临时封禁该地址，0 表示不封禁
+    #[serde(default = "default_ban_after_failures")]
+    pub ban_after_failures: u32,
+
+    #[serde(default = "default_failure_window_secs")]
+    pub failure_window_secs: u64,
+
+    #[serde(default = "default_ban_secs")]
+    pub ban_secs: u64,
+
+    /// 位于反向代理之后时，以 X-Forwarded-For 的第一个地址作为客户端地址
+    #[serde(default)]
+    pub trust_forwarded_for: bool,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig {
+            allow: Vec::new(),
+            deny: Vec::new(),
+            ban_after_failures: default_ban_after_failures(),
+            failure_window_secs: default_failure_window_secs(),
+            ban_secs: default_ban_secs(),
+            trust_forwarded_for: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebSecurityConfig {
+    /// 除同源页面外，允许发起写请求的页面来源，如 "https://files.example.com"
+    #[serde(default)]
+    pub trusted_origins: Vec<String>,
+
+    /// Strict-Transport-Security 的 max-age（秒），0 表示不发送
+    #[serde(default = "default_hsts_max_age_secs")]
+    pub hsts_max_age_secs: u64,
+}
+
+impl Default for WebSecurityConfig {
+    fn default() -> Self {
+        WebSecurityConfig {
+            trusted_origins: Vec::new(),
+            hsts_max_age_secs: default_hsts_max_age_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WorkerConfig {
+    /// tokio 阻塞线程池的线程上限；文件读写和哈希计算都在这里执行
+    #[serde(default = "default_blocking_threads")]
+    pub blocking_threads: usize,
+
+    /// 同时进行的哈希计算数，默认等于 CPU 核数
+    #[serde(default = "default_hash_workers")]
+    pub hash_workers: usize,
+
+    /// 对象存储同时进行的文件操作数
+    #[serde(default = "default_max_fs_ops")]
+    pub max_fs_ops: usize,
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        WorkerConfig {
+            blocking_threads: default_blocking_threads(),
+            hash_workers: default_hash_workers(),
+            max_fs_ops: default_max_fs_ops(),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct SmtpConfig {
+    pub host: String,
+
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+
+    /// 发件人地址，如 "RustCloud <noreply@example.com>"
+    pub from: String,
+
+    #[serde(default)]
+    pub username: Option<String>,
+
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ReputationConfig {
+    /// 查询地址，{hash} 会被替换为内容的 SHA-256
+    pub url: String,
+
+    /// 以 x-apikey 请求头发送
+    #[serde(default)]
+    pub api_key: Option<String>,
+
+    #[serde(default)]
+    pub policy: ReputationPolicy,
+}
+
+/// 同步冲突的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictStrategy {
+    /// 另存为 "name (conflicted copy from 设备名).ext"，原文件保持不变
+    #[default]
+    Copy,
+    /// 返回 409，由客户端处理
+    Reject,
+}
+
+/// 命中恶意内容时的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReputationPolicy {
+    /// 照常保存，只在元数据中标记
+    #[default]
+    Flag,
+    /// 拒绝上传
+    Block,
+}
+
+// 启动日志会打印完整配置，令牌、密钥和口令只显示是否设置
+fn redacted(secret: &Option<String>) -> &'static str {
+    match secret {
+        Some(_) => "<redacted>",
+        None => "<unset>",
+    }
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("storage_path", &self.storage_path)
+            .field("database", &self.database)
+            .field("max_file_size", &self.max_file_size)
+            .field("chunk_size", &self.chunk_size)
+            .field("shard_depth", &self.shard_depth)
+            .field("materialize_files", &self.materialize_files)
+            .field("warm_hash_cache", &self.warm_hash_cache)
+            .field("verify_reads", &self.verify_reads)
+            .field("compress_objects", &self.compress_objects)
+            .field("conflicts", &self.conflicts)
+            .field("append_only", &self.append_only)
+            .field("upload_only", &self.upload_only)
+            .field("download_only", &self.download_only)
+            .field("shred_deleted", &self.shred_deleted)
+            .field("trash_retention_days", &self.trash_retention_days)
+            .field("ignore", &self.ignore)
+            .field("smtp", &self.smtp)
+            .field("reputation", &self.reputation)
+            .field("admin_token", &redacted(&self.admin_token))
+            .field("min_client_version", &self.min_client_version)
+            .field("lifecycle_interval_secs", &self.lifecycle_interval_secs)
+            .field("auth", &self.auth)
+            .field("upstream", &self.upstream)
+            .field("network", &self.network)
+            .field("web", &self.web)
+            .field("workers", &self.workers)
+            .finish()
+    }
+}
+
+impl fmt::Debug for AuthConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthConfig")
+            .field("jwt_secret", &"<redacted>")
+            .field("token_ttl_secs", &self.token_ttl_secs)
+            .field("allow_registration", &self.allow_registration)
+            .field("user_namespaces", &self.user_namespaces)
+            .field("default_quota_bytes", &self.default_quota_bytes)
+            .finish()
+    }
+}
+
+impl fmt::Debug for UpstreamConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UpstreamConfig")
+            .field("url", &self.url)
+            .field("token", &redacted(&self.token))
+            .finish()
+    }
+}
+
+impl fmt::Debug for SmtpConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SmtpConfig")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("from", &self.from)
+            .field("username", &self.username)
+            .field("password", &redacted(&self.password))
+            .finish()
+    }
+}
+
+impl fmt::Debug for ReputationConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReputationConfig")
+            .field("url", &self.url)
+            .field("api_key", &redacted(&self.api_key))
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+// [知识点 #173] 敏感配置的来源
+// ----------------------------------------
+// 题目：为什么不直接把令牌写在环境变量里？
+//
+// 讲解：
+// 环境变量会被子进程继承，也可能出现在 `ps e`、容器 inspect 的输出中。
+// 容器编排通常把密钥挂载成文件（Docker/Kubernetes secrets），
+// 或者由密码管理器、Vault 之类的命令行工具按需取出。
+//
+// 每个敏感项按以下顺序取第一个设置了的来源：
+// 1. NAME：直接给出值
+// 2. NAME_FILE：读取文件内容，去掉末尾换行
+// 3. NAME_COMMAND：执行命令，取标准输出，去掉末尾换行
+//
+// 明确指定了来源却读取失败时启动失败，而不是当作未设置：
+// 未设置 JWT 密钥意味着关闭认证
+//
+// 思考：密钥轮换时如何避免重启服务？
+// ----------------------------------------
+fn secret(name: &str) -> Result<Option<String>> {
+    if let Some(value) = std::env::var(name).ok().filter(|v| !v.is_empty()) {
+        return Ok(Some(value));
+    }
+
+    let file_var = format!("{}_FILE", name);
+    if let Some(path) = std::env::var_os(&file_var).filter(|p| !p.is_empty()) {
+        let content = std::fs::read_to_string(&path).map_err(|e| {
+            Error::Config(format!("Failed to read {} ({:?}): {}", file_var, path, e))
+        })?;
+        return Ok(non_empty(content));
+    }
+
+    let command_var = format!("{}_COMMAND", name);
+    if let Some(command) = std::env::var(&command_var).ok().filter(|c| !c.is_empty()) {
+        let output = shell(&command)
+            .stderr(std::process::Stdio::inherit())
+            .output()
+            .map_err(|e| Error::Config(format!("Failed to run {}: {}", command_var, e)))?;
+        if !output.status.success() {
+            return Err(Error::Config(format!(
+                "{} exited with {}",
+                command_var, output.status
+            )));
+        }
+        let content = String::from_utf8(output.stdout)
+            .map_err(|_| Error::Config(format!("{} printed non-UTF-8 output", command_var)))?;
+        return Ok(non_empty(content));
+    }
+
+    Ok(None)
+}
+
+fn non_empty(content: String) -> Option<String> {
+    let value = content.trim_end_matches(['\r', '\n']);
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+fn shell(command: &str) -> std::process::Command {
+    if cfg!(windows) {
+        let mut cmd = std::process::Command::new("cmd");
+        cmd.args(["/C", command]);
+        cmd
+    } else {
+        let mut cmd = std::process::Command::new("sh");
+        cmd.args(["-c", command]);
+        cmd
+    }
+}
+
+fn default_trash_retention_days() -> u32 {
+    30
+}
+
+fn default_lifecycle_interval_secs() -> u64 {
+    3600
+}
+
+fn default_token_ttl_secs() -> u64 {
+    7 * 24 * 3600
+}
+
+fn default_allow_registration() -> bool {
+    true
+}
+
+fn default_ban_after_failures() -> u32 {
+    5
+}
+
+fn default_failure_window_secs() -> u64 {
+    600
+}
+
+fn default_ban_secs() -> u64 {
+    900
+}
+
+fn default_hsts_max_age_secs() -> u64 {
+    180 * 24 * 3600
+}
+
+fn default_blocking_threads() -> usize {
+    // 与 tokio 的默认值一致
+    512
+}
+
+fn default_hash_workers() -> usize {
+    std::thread::available_parallelism().map_or(4, |n| n.get())
+}
+
+fn default_max_fs_ops() -> usize {
+    128
+}
+
+fn default_smtp_port() -> u16 {
+    587
 }
 
 fn default_host() -> String {
@@ -49,18 +533,31 @@ fn default_max_file_size() -> u64 {
     100 * 1024 * 1024 // 100MB
 }
 
+fn default_shard_depth() -> usize {
+    DEFAULT_SHARD_DEPTH
+}
+
+fn default_chunk_size() -> usize {
+    4 * 1024 * 1024 // 4MB
+}
+
+fn default_materialize_files() -> bool {
+    true
+}
+
 impl Config {
-    pub fn from_file(path: &str) -> crate::error::Result<Self> {
+    pub fn from_file(path: &str) -> Result<Self> {
         let content = std::fs::read_to_string(path)
-            .map_err(|e| crate::error::Error::Config(format!("Failed to read config: {}", e)))?;
+            .map_err(|e| Error::Config(format!("Failed to read config: {}", e)))?;
 
         let config: Config = toml::from_str(&content)
-            .map_err(|e| crate::error::Error::Config(format!("Failed to parse config: {}", e)))?;
+            .map_err(|e| Error::Config(format!("Failed to parse config: {}", e)))?;
 
         Ok(config)
     }
 
-    pub fn from_env_or_default() -> Self {
+    /// 敏感项除了直接设置，还可以通过 `_FILE` / `_COMMAND` 后缀的变量提供
+    pub fn from_env_or_default() -> Result<Self> {
         let host = std::env::var("RUSTCLOUD_HOST").unwrap_or_else(|_| default_host());
         let port = std::env::var("RUSTCLOUD_PORT")
             .ok()
@@ -69,20 +566,358 @@ impl Config {
         let storage_path = std::env::var("RUSTCLOUD_STORAGE_PATH")
             .map(PathBuf::from)
             .unwrap_or_else(|_| default_storage_path());
+        let database = match std::env::var("RUSTCLOUD_DATABASE").as_deref() {
+            Ok("sqlite") => DatabaseBackend::Sqlite,
+            _ => DatabaseBackend::Json,
+        };
         let max_file_size = std::env::var("RUSTCLOUD_MAX_FILE_SIZE")
             .ok()
             .and_then(|s| s.parse().ok())
             .unwrap_or_else(default_max_file_size);
+        let chunk_size = std::env::var("RUSTCLOUD_CHUNK_SIZE")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_else(default_chunk_size);
+        let shard_depth = std::env::var("RUSTCLOUD_SHARD_DEPTH")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_else(default_shard_depth);
+        let conflicts = match std::env::var("RUSTCLOUD_CONFLICT_STRATEGY").as_deref() {
+            Ok("reject") => ConflictStrategy::Reject,
+            _ => ConflictStrategy::Copy,
+        };
+        let materialize_files = std::env::var("RUSTCLOUD_MATERIALIZE_FILES")
+            .map(|v| v != "false")
+            .unwrap_or_else(|_| default_materialize_files());
+        let warm_hash_cache = std::env::var("RUSTCLOUD_WARM_HASH_CACHE").is_ok_and(|v| v == "true");
+        let verify_reads = std::env::var("RUSTCLOUD_VERIFY_READS").is_ok_and(|v| v == "true");
+        let compress_objects =
+            std::env::var("RUSTCLOUD_COMPRESS_OBJECTS").is_ok_and(|v| v == "true");
+        // 设置了 RUSTCLOUD_SMTP_HOST 和 RUSTCLOUD_SMTP_FROM 才启用邮件
+        let smtp = match (
+            std::env::var("RUSTCLOUD_SMTP_HOST"),
+            std::env::var("RUSTCLOUD_SMTP_FROM"),
+        ) {
+            (Ok(host), Ok(from)) => Some(SmtpConfig {
+                host,
+                port: std::env::var("RUSTCLOUD_SMTP_PORT")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or_else(default_smtp_port),
+                from,
+                username: std::env::var("RUSTCLOUD_SMTP_USERNAME").ok(),
+                password: secret("RUSTCLOUD_SMTP_PASSWORD")?,
+            }),
+            _ => None,
+        };
+
+        let api_key = secret("RUSTCLOUD_REPUTATION_API_KEY")?;
+        let reputation =
+            std::env::var("RUSTCLOUD_REPUTATION_URL")
+                .ok()
+                .map(|url| ReputationConfig {
+                    url,
+                    api_key,
+                    policy: match std::env::var("RUSTCLOUD_REPUTATION_POLICY").as_deref() {
+                        Ok("block") => ReputationPolicy::Block,
+                        _ => ReputationPolicy::Flag,
+                    },
+                });
 
-        Config {
+        let admin_token = secret("RUSTCLOUD_ADMIN_TOKEN")?;
+        let min_client_version = std::env::var("RUSTCLOUD_MIN_CLIENT_VERSION")
+            .ok()
+            .filter(|version| !version.is_empty());
+
+        let lifecycle_interval_secs = std::env::var("RUSTCLOUD_LIFECYCLE_INTERVAL_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_else(default_lifecycle_interval_secs);
+
+        // 设置了 RUSTCLOUD_JWT_SECRET 才启用认证
+        let auth = secret("RUSTCLOUD_JWT_SECRET")?.map(|jwt_secret| AuthConfig {
+            jwt_secret,
+            token_ttl_secs: std::env::var("RUSTCLOUD_TOKEN_TTL_SECS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or_else(default_token_ttl_secs),
+            allow_registration: std::env::var("RUSTCLOUD_ALLOW_REGISTRATION")
+                .map(|v| v != "false")
+                .unwrap_or_else(|_| default_allow_registration()),
+            user_namespaces: std::env::var("RUSTCLOUD_USER_NAMESPACES").is_ok_and(|v| v == "true"),
+            default_quota_bytes: std::env::var("RUSTCLOUD_USER_QUOTA_BYTES")
+                .ok()
+                .and_then(|s| s.parse().ok()),
+        });
+
+        // 设置了 RUSTCLOUD_UPSTREAM_URL 才以代理模式运行
+        let token = secret("RUSTCLOUD_UPSTREAM_TOKEN")?;
+        let upstream = std::env::var("RUSTCLOUD_UPSTREAM_URL")
+            .ok()
+            .filter(|url| !url.is_empty())
+            .map(|url| UpstreamConfig { url, token });
+
+        let list = |name: &str| -> Vec<String> {
+            std::env::var(name)
+                .unwrap_or_default()
+                .split(',')
+                .map(|entry| entry.trim().to_string())
+                .filter(|entry| !entry.is_empty())
+                .collect()
+        };
+        let append_only = list("RUSTCLOUD_APPEND_ONLY_PATHS");
+        let upload_only = list("RUSTCLOUD_UPLOAD_ONLY_PATHS");
+        let download_only = list("RUSTCLOUD_DOWNLOAD_ONLY_PATHS");
+        let shred_deleted = std::env::var("RUSTCLOUD_SHRED_DELETED").is_ok_and(|v| v == "true");
+        let trash_retention_days = std::env::var("RUSTCLOUD_TRASH_RETENTION_DAYS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_else(default_trash_retention_days);
+        let ignore = list("RUSTCLOUD_IGNORE");
+        let network = NetworkConfig {
+            allow: list("RUSTCLOUD_ALLOW_IPS"),
+            deny: list("RUSTCLOUD_DENY_IPS"),
+            ban_after_failures: std::env::var("RUSTCLOUD_BAN_AFTER_FAILURES")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or_else(default_ban_after_failures),
+            failure_window_secs: std::env::var("RUSTCLOUD_FAILURE_WINDOW_SECS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or_else(default_failure_window_secs),
+            ban_secs: std::env::var("RUSTCLOUD_BAN_SECS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or_else(default_ban_secs),
+            trust_forwarded_for: std::env::var("RUSTCLOUD_TRUST_FORWARDED_FOR")
+                .is_ok_and(|v| v == "true"),
+        };
+        let web = WebSecurityConfig {
+            trusted_origins: list("RUSTCLOUD_TRUSTED_ORIGINS"),
+            hsts_max_age_secs: std::env::var("RUSTCLOUD_HSTS_MAX_AGE_SECS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or_else(default_hsts_max_age_secs),
+        };
+
+        let workers = WorkerConfig {
+            blocking_threads: std::env::var("RUSTCLOUD_BLOCKING_THREADS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or_else(default_blocking_threads),
+            hash_workers: std::env::var("RUSTCLOUD_HASH_WORKERS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or_else(default_hash_workers),
+            max_fs_ops: std::env::var("RUSTCLOUD_MAX_FS_OPS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or_else(default_max_fs_ops),
+        };
+
+        Ok(Config {
             host,
             port,
             storage_path,
+            database,
             max_file_size,
-        }
+            chunk_size,
+            shard_depth,
+            materialize_files,
+            warm_hash_cache,
+            verify_reads,
+            compress_objects,
+            conflicts,
+            append_only,
+            upload_only,
+            download_only,
+            shred_deleted,
+            trash_retention_days,
+            ignore,
+            smtp,
+            reputation,
+            admin_token,
+            min_client_version,
+            lifecycle_interval_secs,
+            auth,
+            upstream,
+            network,
+            web,
+            workers,
+        })
     }
 
     pub fn addr(&self) -> String {
         format!("{}:{}", self.host, self.port)
     }
+
+    /// 启动前检查配置，一次列出全部问题，每条都指出应该修改哪个设置，
+    /// 而不是等到第一个请求才失败。会创建存储目录并短暂占用监听端口
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+
+        if let Err(e) = check_writable(&self.storage_path) {
+            problems.push(format!(
+                "storage path {:?} is not writable ({}); point RUSTCLOUD_STORAGE_PATH at a writable directory",
+                self.storage_path, e
+            ));
+        }
+        if let Err(e) = std::net::TcpListener::bind(self.addr()) {
+            problems.push(format!(
+                "cannot listen on {} ({}); choose a free address with RUSTCLOUD_HOST / RUSTCLOUD_PORT",
+                self.addr(),
+                e
+            ));
+        }
+        if !(MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&self.chunk_size) {
+            problems.push(format!(
+                "chunk size {} is out of range; set RUSTCLOUD_CHUNK_SIZE between {} and {} bytes",
+                self.chunk_size, MIN_CHUNK_SIZE, MAX_CHUNK_SIZE
+            ));
+        }
+        if !SHARD_DEPTHS.contains(&self.shard_depth) {
+            problems.push(format!(
+                "shard depth {} is not supported; set RUSTCLOUD_SHARD_DEPTH to 2 or 4",
+                self.shard_depth
+            ));
+        }
+        if self.max_file_size == 0 {
+            problems.push(
+                "max file size is 0, so every upload would be rejected; set RUSTCLOUD_MAX_FILE_SIZE"
+                    .to_string(),
+            );
+        }
+
+        for (value, var) in [
+            (self.workers.blocking_threads, "RUSTCLOUD_BLOCKING_THREADS"),
+            (self.workers.hash_workers, "RUSTCLOUD_HASH_WORKERS"),
+            (self.workers.max_fs_ops, "RUSTCLOUD_MAX_FS_OPS"),
+        ] {
+            if value == 0 {
+                problems.push(format!(
+                    "{} is 0, so the server could never make progress; set it to at least 1",
+                    var
+                ));
+            }
+        }
+        // 哈希任务占用阻塞线程，超出的部分只会排队
+        if self.workers.hash_workers > self.workers.blocking_threads {
+            problems.push(format!(
+                "{} hash workers cannot run on {} blocking threads; lower RUSTCLOUD_HASH_WORKERS or raise RUSTCLOUD_BLOCKING_THREADS",
+                self.workers.hash_workers, self.workers.blocking_threads
+            ));
+        }
+
+        if let Some(version) = &self.min_client_version {
+            if rustcloud_types::compare_versions(version, version).is_none() {
+                problems.push(format!(
+                    "minimum client version {:?} is not a version number; set RUSTCLOUD_MIN_CLIENT_VERSION like 1.4.0",
+                    version
+                ));
+            }
+        }
+
+        if let Some(auth) = &self.auth {
+            if auth.token_ttl_secs == 0 {
+                problems.push(
+                    "tokens would expire immediately; set RUSTCLOUD_TOKEN_TTL_SECS above 0"
+                        .to_string(),
+                );
+            }
+        }
+        if let Some(upstream) = &self.upstream {
+            if let Err(e) = check_http_url(&upstream.url) {
+                problems.push(format!(
+                    "upstream URL {:?} {}; set RUSTCLOUD_UPSTREAM_URL to e.g. https://cloud.example.com",
+                    upstream.url, e
+                ));
+            }
+        }
+        if let Some(reputation) = &self.reputation {
+            if let Err(e) = check_http_url(&reputation.url) {
+                problems.push(format!(
+                    "reputation URL {:?} {}; fix RUSTCLOUD_REPUTATION_URL",
+                    reputation.url, e
+                ));
+            } else if !reputation.url.contains("{hash}") {
+                problems.push(format!(
+                    "reputation URL {:?} has no {{hash}} placeholder, so every lookup would ask about the same thing; add it to RUSTCLOUD_REPUTATION_URL",
+                    reputation.url
+                ));
+            }
+        }
+        if let Some(smtp) = &self.smtp {
+            if let Err(e) = smtp.from.parse::<lettre::message::Mailbox>() {
+                problems.push(format!(
+                    "sender address {:?} is invalid ({}); set RUSTCLOUD_SMTP_FROM to e.g. \"RustCloud <noreply@example.com>\"",
+                    smtp.from, e
+                ));
+            }
+        }
+
+        for (rules, var) in [
+            (&self.network.allow, "RUSTCLOUD_ALLOW_IPS"),
+            (&self.network.deny, "RUSTCLOUD_DENY_IPS"),
+        ] {
+            for rule in rules {
+                if rule.parse::<IpNet>().is_err() {
+                    problems.push(format!(
+                        "{:?} in {} is not an address or network; use forms like 10.0.0.0/8 or ::1",
+                        rule, var
+                    ));
+                }
+            }
+        }
+        if self.network.ban_after_failures > 0 && self.network.failure_window_secs == 0 {
+            problems.push(
+                "failures are never counted with a 0s window; set RUSTCLOUD_FAILURE_WINDOW_SECS, or RUSTCLOUD_BAN_AFTER_FAILURES=0 to disable bans"
+                    .to_string(),
+            );
+        }
+        for origin in &self.web.trusted_origins {
+            if let Err(e) = check_origin(origin) {
+                problems.push(format!(
+                    "trusted origin {:?} {}; list origins in RUSTCLOUD_TRUSTED_ORIGINS as scheme://host[:port]",
+                    origin, e
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            return Ok(());
+        }
+        Err(Error::Config(format!(
+            "{} problem(s) found:\n  - {}",
+            problems.len(),
+            problems.join("\n  - ")
+        )))
+    }
+}
+
+// 实际写入并删除一个探测文件：目录存在但只读、磁盘已满等情况只有写的时候才会暴露
+fn check_writable(dir: &std::path::Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let probe = dir.join(format!(".rcloud-write-check-{}", std::process::id()));
+    std::fs::write(&probe, b"ok")?;
+    std::fs::remove_file(&probe)
+}
+
+fn check_http_url(url: &str) -> std::result::Result<(), String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("is not a valid URL ({})", e))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err("must use http or https".to_string());
+    }
+    Ok(())
+}
+
+// 浏览器发送的 Origin 只有协议、主机和端口
+fn check_origin(origin: &str) -> std::result::Result<(), String> {
+    let parsed = reqwest::Url::parse(origin).map_err(|e| format!("is not a valid URL ({})", e))?;
+    if !matches!(parsed.scheme(), "http" | "https") || parsed.host_str().is_none() {
+        return Err("must be an http or https origin".to_string());
+    }
+    if parsed.path() != "/" || parsed.query().is_some() {
+        return Err("must not include a path".to_string());
+    }
+    Ok(())
 }
@@ -0,0 +1,1030 @@
+// [知识点 #081] Arc<Mutex> 与内部可变性
+// ----------------------------------------
+// 题目：为什么 JsonBackend 用 Arc<Mutex<Database>> 而不是直接持有 Database？
+//
+// 讲解：
+// 仓库需要在多个 handler 之间共享，且需要修改数据。
+// Arc<Mutex<T>> 组合：
+// - Arc：多所有权，允许 clone 出多个引用
+// - Mutex：内部可变性，允许通过不可变引用修改数据
+//
+// Mutex vs RwLock：
+// - Mutex：同一时刻只有一个线程可以访问（读写都互斥）
+// - RwLock：允许多个读者或一个写者
+//
+// 这里用 Mutex 是因为大多数操作都需要写入，RwLock 优势不明显
+//
+// 思考：什么情况下应该用 RwLock 而非 Mutex？
+// ----------------------------------------
+
+use async_trait::async_trait;
+use rustcloud_types::path::case_key;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::{MappedMutexGuard, Mutex, MutexGuard};
+
+use super::models::is_same_or_descendant;
+use super::models::{
+    CommentRecord, Database, DeviceRecord, DirectoryRecord, FileDeparture, FileRecord,
+    FileVersionRecord, FsId, LegalHold, LifecycleRule, MediaMetadata, MountRecord,
+    NewCommentRecord, NewDeviceRecord, NewFileRecord, NewLifecycleRule, NewMountRecord,
+    NewRateClass, NewShareRecord, NewSyncRecord, NewTrashEntry, NewUserRecord,
+    NotificationPreferences, NotificationRule, RateClass, ShareAccessRecord, ShareRecord,
+    SyncRecord, SyncStatus, TrashEntry, UserRecord,
+};
+use super::repository::RepositoryBackend;
+use super::snapshot::Snapshot;
+use crate::error::{Error, Result};
+use crate::service::clock::Clock;
+use crate::service::storage::write_atomic;
+
+/// 全部记录保存在一个 JSON 文件中，每次修改整体重写
+pub struct JsonBackend {
+    /// 快照共享同一个 Database，修改时写时复制
+    data: Arc<Mutex<Arc<Database>>>,
+    db_path: PathBuf,
+    clock: Arc<dyn Clock>,
+}
+
+impl JsonBackend {
+    pub async fn open(db_path: PathBuf, clock: Arc<dyn Clock>) -> Result<Self> {
+        let mut database: Database = if db_path.exists() {
+            let content = tokio::fs::read_to_string(&db_path).await?;
+            serde_json::from_str(&content).unwrap_or_default()
+        } else {
+            Database::default()
+        };
+        // 旧版本不记录历史版本，之前的文件只能补上当前版本
+        let recorded: HashSet<uuid::Uuid> = database.versions.iter().map(|v| v.file_id).collect();
+        let missing: Vec<FileVersionRecord> = database
+            .files
+            .iter()
+            .filter(|f| !recorded.contains(&f.id))
+            .map(FileVersionRecord::of)
+            .collect();
+        database.versions.extend(missing);
+
+        Ok(JsonBackend {
+            data: Arc::new(Mutex::new(Arc::new(database))),
+            db_path,
+            clock,
+        })
+    }
+
+    // 修改数据前取得锁；还有快照在使用当前数据时先复制一份
+    async fn write(&self) -> MappedMutexGuard<'_, Database> {
+        MutexGuard::map(self.data.lock().await, Arc::make_mut)
+    }
+
+    async fn save(&self) -> Result<()> {
+        let data = self.data.lock().await;
+        let content = serde_json::to_string_pretty(&**data)?;
+        write_atomic(&self.db_path, content.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl RepositoryBackend for JsonBackend {
+    fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    async fn snapshot(&self) -> Result<Snapshot> {
+        Ok(Snapshot::new(self.data.lock().await.clone()))
+    }
+
+    // [知识点 #043] async 方法与锁的作用域
+    // ----------------------------------------
+    // 题目：为什么 lock().await 后要尽快释放锁？
+    //
+    // 讲解：
+    // Mutex::lock().await 会等待获取锁，持有锁期间其他任务无法访问。
+    // 如果在持有锁时执行耗时操作或 .await，会阻塞其他任务。
+    //
+    // 最佳实践：
+    // 1. 获取锁后尽快完成操作
+    // 2. 避免在持有锁时调用其他 async 函数
+    // 3. 如果必须调用，考虑先克隆需要的数据再释放锁
+    //
+    // 思考：如果必须在持有锁时 .await，有什么解决方案？
+    // ----------------------------------------
+
+    async fn create_file(&self, new_file: NewFileRecord) -> Result<FileRecord> {
+        let mut data = self.write().await;
+
+        // 检查路径是否已存在
+        if data.files.iter().any(|f| f.path == new_file.path) {
+            return Err(Error::AlreadyExists(PathBuf::from(&new_file.path)));
+        }
+        ensure_not_held(&data, &new_file.path)?;
+
+        let record = FileRecord::new(new_file, self.clock.now());
+        data.files.push(record.clone());
+        data.versions.push(FileVersionRecord::of(&record));
+        drop(data); // 提前释放锁
+
+        self.save().await?;
+        Ok(record)
+    }
+
+    async fn get_file_by_path(&self, path: &str) -> Result<FileRecord> {
+        let data = self.data.lock().await;
+        data.files
+            .iter()
+            .find(|f| f.path == path)
+            .cloned()
+            .ok_or_else(|| Error::NotFound(PathBuf::from(path)))
+    }
+
+    async fn get_file_by_id(&self, id: uuid::Uuid) -> Result<FileRecord> {
+        let data = self.data.lock().await;
+        data.files
+            .iter()
+            .find(|f| f.id == id)
+            .cloned()
+            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("file:{}", id))))
+    }
+
+    async fn update_file(
+        &self,
+        id: uuid::Uuid,
+        hash: Option<String>,
+        size: u64,
+    ) -> Result<FileRecord> {
+        let mut guard = self.write().await;
+        let data = &mut *guard;
+        let file = data
+            .files
+            .iter_mut()
+            .find(|f| f.id == id)
+            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("file:{}", id))))?;
+
+        if let Some(hold) = data.legal_holds.iter().find(|h| h.covers(&file.path)) {
+            return Err(Error::Held(hold.path.clone()));
+        }
+        file.hash = hash;
+        file.size = size;
+        file.increment_version(self.clock.now());
+        let record = file.clone();
+        data.versions.push(FileVersionRecord::of(&record));
+        drop(guard);
+
+        self.save().await?;
+        Ok(record)
+    }
+
+    async fn set_file_media(
+        &self,
+        id: uuid::Uuid,
+        media: Option<MediaMetadata>,
+    ) -> Result<FileRecord> {
+        let mut data = self.write().await;
+        let file = data
+            .files
+            .iter_mut()
+            .find(|f| f.id == id)
+            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("file:{}", id))))?;
+
+        file.media = media;
+        let record = file.clone();
+        drop(data);
+
+        self.save().await?;
+        Ok(record)
+    }
+
+    async fn update_file_metadata(
+        &self,
+        id: uuid::Uuid,
+        patch: BTreeMap<String, Option<String>>,
+    ) -> Result<FileRecord> {
+        let mut data = self.write().await;
+        let file = data
+            .files
+            .iter_mut()
+            .find(|f| f.id == id)
+            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("file:{}", id))))?;
+
+        for (key, value) in patch {
+            match value {
+                Some(value) => file.metadata.insert(key, value),
+                None => file.metadata.remove(&key),
+            };
+        }
+        let record = file.clone();
+        drop(data);
+
+        self.save().await?;
+        Ok(record)
+    }
+
+    async fn set_file_fs_id(&self, id: uuid::Uuid, fs_id: Option<FsId>) -> Result<FileRecord> {
+        let mut data = self.write().await;
+        let file = data
+            .files
+            .iter_mut()
+            .find(|f| f.id == id)
+            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("file:{}", id))))?;
+
+        file.fs_id = fs_id;
+        let record = file.clone();
+        drop(data);
+
+        self.save().await?;
+        Ok(record)
+    }
+
+    async fn find_file_by_fs_id(&self, fs_id: FsId) -> Option<FileRecord> {
+        let data = self.data.lock().await;
+        data.files.iter().find(|f| f.fs_id == Some(fs_id)).cloned()
+    }
+
+    async fn set_file_owner(
+        &self,
+        id: uuid::Uuid,
+        owner: Option<uuid::Uuid>,
+    ) -> Result<FileRecord> {
+        let mut data = self.write().await;
+        let file = data
+            .files
+            .iter_mut()
+            .find(|f| f.id == id)
+            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("file:{}", id))))?;
+
+        file.owner = owner;
+        let record = file.clone();
+        drop(data);
+
+        self.save().await?;
+        Ok(record)
+    }
+
+    async fn delete_file(&self, id: uuid::Uuid) -> Result<()> {
+        let mut data = self.write().await;
+        let idx = data
+            .files
+            .iter()
+            .position(|f| f.id == id)
+            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("file:{}", id))))?;
+
+        ensure_not_held(&data, &data.files[idx].path)?;
+        let file = data.files.remove(idx);
+        // 同时删除相关的同步记录和评论，历史版本保留
+        data.syncs.retain(|s| s.file_id != id);
+        data.comments.retain(|c| c.file_id != id);
+        data.departures.push(FileDeparture {
+            file_id: id,
+            path: file.path,
+            moved_to: None,
+            departed_at: self.clock.now(),
+            owner: file.owner,
+        });
+        drop(data);
+
+        self.save().await
+    }
+
+    async fn record_file_accesses(
+        &self,
+        accesses: HashMap<String, chrono::DateTime<chrono::Utc>>,
+    ) -> Result<usize> {
+        let mut data = self.write().await;
+        let mut updated = 0;
+        for file in data.files.iter_mut() {
+            if let Some(&at) = accesses.get(&file.path) {
+                if file.last_accessed_at.is_none_or(|prev| at > prev) {
+                    file.last_accessed_at = Some(at);
+                    updated += 1;
+                }
+            }
+        }
+        drop(data);
+
+        if updated > 0 {
+            self.save().await?;
+        }
+        Ok(updated)
+    }
+
+    async fn move_file(&self, id: uuid::Uuid, new_path: &str) -> Result<FileRecord> {
+        let mut guard = self.write().await;
+        let data = &mut *guard;
+        if data.files.iter().any(|f| f.path == new_path) {
+            return Err(Error::AlreadyExists(PathBuf::from(new_path)));
+        }
+        ensure_not_held(data, new_path)?;
+
+        let file = data
+            .files
+            .iter_mut()
+            .find(|f| f.id == id)
+            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("file:{}", id))))?;
+        if let Some(hold) = data.legal_holds.iter().find(|h| h.covers(&file.path)) {
+            return Err(Error::Held(hold.path.clone()));
+        }
+
+        let departure = FileDeparture {
+            file_id: id,
+            path: std::mem::replace(&mut file.path, new_path.to_string()),
+            moved_to: Some(new_path.to_string()),
+            departed_at: self.clock.now(),
+            owner: file.owner,
+        };
+        file.updated_at = departure.departed_at;
+        let record = file.clone();
+        data.departures.push(departure);
+        drop(guard);
+
+        self.save().await?;
+        Ok(record)
+    }
+
+    async fn list_files(&self) -> Result<Vec<FileRecord>> {
+        let data = self.data.lock().await;
+        Ok(data.files.clone())
+    }
+
+    async fn total_size(&self) -> Result<u64> {
+        let data = self.data.lock().await;
+        Ok(data.files.iter().map(|f| f.size).sum())
+    }
+
+    async fn owner_usage(&self, owner: uuid::Uuid, root: Option<&str>) -> Result<(usize, u64)> {
+        let data = self.data.lock().await;
+        let owned = data.files.iter().filter(|f| match f.owner {
+            Some(id) => id == owner,
+            None => root.is_some_and(|root| is_same_or_descendant(&f.path, root)),
+        });
+        Ok(owned.fold((0, 0), |(files, bytes), f| (files + 1, bytes + f.size)))
+    }
+
+    // 记录都在内存中，持锁逐条比较，不复制记录
+    async fn path_spellings(&self, path: &str) -> Result<Vec<String>> {
+        let key = case_key(path);
+        let depth = path.split('/').count();
+        let data = self.data.lock().await;
+        let spellings: BTreeSet<String> = data
+            .files
+            .iter()
+            .filter_map(|f| {
+                let segments: Vec<&str> = f.path.split('/').collect();
+                let spelling = segments.get(..depth)?.join("/");
+                (case_key(&spelling) == key).then_some(spelling)
+            })
+            .collect();
+        Ok(spellings.into_iter().collect())
+    }
+
+    async fn list_file_versions(&self, file_id: uuid::Uuid) -> Result<Vec<FileVersionRecord>> {
+        let data = self.data.lock().await;
+        let mut versions: Vec<FileVersionRecord> = data
+            .versions
+            .iter()
+            .filter(|v| v.file_id == file_id)
+            .cloned()
+            .collect();
+        versions.sort_by_key(|v| v.version);
+        Ok(versions)
+    }
+
+    async fn list_departures(&self) -> Result<Vec<FileDeparture>> {
+        let data = self.data.lock().await;
+        Ok(data.departures.clone())
+    }
+
+    async fn purge_file_history(&self, file_id: uuid::Uuid) -> Result<()> {
+        let mut data = self.write().await;
+        data.versions.retain(|v| v.file_id != file_id);
+        data.departures.retain(|d| d.file_id != file_id);
+        drop(data);
+
+        self.save().await
+    }
+
+    async fn create_sync(&self, new_sync: NewSyncRecord) -> Result<SyncRecord> {
+        let mut data = self.write().await;
+
+        // 验证 file_id 存在
+        if !data.files.iter().any(|f| f.id == new_sync.file_id) {
+            return Err(Error::NotFound(PathBuf::from(format!(
+                "file:{}",
+                new_sync.file_id
+            ))));
+        }
+
+        let record = SyncRecord::new(new_sync, self.clock.now())?;
+        data.syncs.push(record.clone());
+        drop(data);
+
+        self.save().await?;
+        Ok(record)
+    }
+
+    async fn update_sync_status(&self, id: uuid::Uuid, status: SyncStatus) -> Result<SyncRecord> {
+        let mut data = self.write().await;
+        let sync = data
+            .syncs
+            .iter_mut()
+            .find(|s| s.id == id)
+            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("sync:{}", id))))?;
+
+        sync.transition(status, self.clock.now())?;
+        let record = sync.clone();
+        drop(data);
+
+        self.save().await?;
+        Ok(record)
+    }
+
+    async fn expire_stuck_syncs(
+        &self,
+        stuck_since: chrono::DateTime<chrono::Utc>,
+    ) -> Result<usize> {
+        let now = self.clock.now();
+        let mut data = self.write().await;
+        let mut expired = 0;
+        for sync in data
+            .syncs
+            .iter_mut()
+            .filter(|s| s.sync_status == SyncStatus::Syncing && s.last_sync_at < stuck_since)
+        {
+            sync.transition(SyncStatus::Failed, now)?;
+            expired += 1;
+        }
+        drop(data);
+
+        if expired > 0 {
+            self.save().await?;
+        }
+        Ok(expired)
+    }
+
+    async fn list_syncs_by_file(&self, file_id: uuid::Uuid) -> Result<Vec<SyncRecord>> {
+        let data = self.data.lock().await;
+        Ok(data
+            .syncs
+            .iter()
+            .filter(|s| s.file_id == file_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn count_failed_syncs(
+        &self,
+        device_id: uuid::Uuid,
+        since: chrono::DateTime<chrono::Utc>,
+    ) -> Result<usize> {
+        let data = self.data.lock().await;
+        Ok(data
+            .syncs
+            .iter()
+            .filter(|s| {
+                s.device_id == device_id
+                    && s.sync_status == SyncStatus::Failed
+                    && s.last_sync_at >= since
+            })
+            .count())
+    }
+
+    async fn list_conflicts(&self) -> Result<Vec<SyncRecord>> {
+        let data = self.data.lock().await;
+        Ok(data
+            .syncs
+            .iter()
+            .filter(|s| s.sync_status == SyncStatus::Conflict)
+            .cloned()
+            .collect())
+    }
+
+    async fn create_comment(&self, new_comment: NewCommentRecord) -> Result<CommentRecord> {
+        let mut data = self.write().await;
+
+        if !data.files.iter().any(|f| f.id == new_comment.file_id) {
+            return Err(Error::NotFound(PathBuf::from(format!(
+                "file:{}",
+                new_comment.file_id
+            ))));
+        }
+
+        let record = CommentRecord::new(new_comment, self.clock.now());
+        data.comments.push(record.clone());
+        drop(data);
+
+        self.save().await?;
+        Ok(record)
+    }
+
+    async fn list_comments_by_file(&self, file_id: uuid::Uuid) -> Result<Vec<CommentRecord>> {
+        let data = self.data.lock().await;
+        Ok(data
+            .comments
+            .iter()
+            .filter(|c| c.file_id == file_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn list_comments(&self) -> Result<Vec<CommentRecord>> {
+        let data = self.data.lock().await;
+        Ok(data.comments.clone())
+    }
+
+    async fn delete_comment(&self, id: uuid::Uuid) -> Result<()> {
+        let mut data = self.write().await;
+        let idx = data
+            .comments
+            .iter()
+            .position(|c| c.id == id)
+            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("comment:{}", id))))?;
+
+        data.comments.remove(idx);
+        drop(data);
+
+        self.save().await
+    }
+
+    async fn create_device(&self, new_device: NewDeviceRecord) -> Result<DeviceRecord> {
+        let mut data = self.write().await;
+        let record = DeviceRecord::new(new_device, self.clock.now());
+        data.devices.push(record.clone());
+        drop(data);
+
+        self.save().await?;
+        Ok(record)
+    }
+
+    async fn get_device(&self, id: uuid::Uuid) -> Result<DeviceRecord> {
+        let data = self.data.lock().await;
+        data.devices
+            .iter()
+            .find(|d| d.id == id)
+            .cloned()
+            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("device:{}", id))))
+    }
+
+    async fn update_device_last_seen(&self, id: uuid::Uuid) -> Result<DeviceRecord> {
+        let mut data = self.write().await;
+        let device = data
+            .devices
+            .iter_mut()
+            .find(|d| d.id == id)
+            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("device:{}", id))))?;
+
+        device.update_last_seen(self.clock.now());
+        let record = device.clone();
+        drop(data);
+
+        self.save().await?;
+        Ok(record)
+    }
+
+    async fn list_devices(&self) -> Result<Vec<DeviceRecord>> {
+        let data = self.data.lock().await;
+        Ok(data.devices.clone())
+    }
+
+    async fn set_device_owner(
+        &self,
+        id: uuid::Uuid,
+        owner: Option<uuid::Uuid>,
+    ) -> Result<DeviceRecord> {
+        let mut data = self.write().await;
+        let device = data
+            .devices
+            .iter_mut()
+            .find(|d| d.id == id)
+            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("device:{}", id))))?;
+
+        device.owner = owner;
+        let record = device.clone();
+        drop(data);
+
+        self.save().await?;
+        Ok(record)
+    }
+
+    async fn create_user(&self, new_user: NewUserRecord) -> Result<UserRecord> {
+        let mut data = self.write().await;
+        if data
+            .users
+            .iter()
+            .any(|u| u.username.eq_ignore_ascii_case(&new_user.username))
+        {
+            return Err(Error::AlreadyExists(PathBuf::from(format!(
+                "user:{}",
+                new_user.username
+            ))));
+        }
+        let record = UserRecord::new(new_user, self.clock.now());
+        data.users.push(record.clone());
+        drop(data);
+
+        self.save().await?;
+        Ok(record)
+    }
+
+    async fn find_user_by_name(&self, username: &str) -> Option<UserRecord> {
+        let data = self.data.lock().await;
+        data.users
+            .iter()
+            .find(|u| u.username.eq_ignore_ascii_case(username))
+            .cloned()
+    }
+
+    async fn get_user(&self, id: uuid::Uuid) -> Result<UserRecord> {
+        let data = self.data.lock().await;
+        data.users
+            .iter()
+            .find(|u| u.id == id)
+            .cloned()
+            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("user:{}", id))))
+    }
+
+    async fn set_user_quota(&self, id: uuid::Uuid, quota_bytes: Option<u64>) -> Result<UserRecord> {
+        let mut data = self.write().await;
+        let user = data
+            .users
+            .iter_mut()
+            .find(|u| u.id == id)
+            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("user:{}", id))))?;
+
+        user.quota_bytes = quota_bytes;
+        let record = user.clone();
+        drop(data);
+
+        self.save().await?;
+        Ok(record)
+    }
+
+    async fn delete_user(&self, id: uuid::Uuid) -> Result<()> {
+        let mut data = self.write().await;
+        let idx = data
+            .users
+            .iter()
+            .position(|u| u.id == id)
+            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("user:{}", id))))?;
+        let user = data.users.remove(idx);
+        data.notification_preferences
+            .retain(|p| p.user != user.username);
+        drop(data);
+
+        self.save().await
+    }
+
+    async fn get_notification_preferences(&self, user: &str) -> Result<NotificationPreferences> {
+        let data = self.data.lock().await;
+        Ok(data
+            .notification_preferences
+            .iter()
+            .find(|p| p.user == user)
+            .cloned()
+            .unwrap_or_else(|| NotificationPreferences {
+                user: user.to_string(),
+                rules: Vec::new(),
+                updated_at: self.clock.now(),
+            }))
+    }
+
+    async fn set_notification_preferences(
+        &self,
+        user: &str,
+        rules: Vec<NotificationRule>,
+    ) -> Result<NotificationPreferences> {
+        let mut data = self.write().await;
+        let prefs = NotificationPreferences {
+            user: user.to_string(),
+            rules,
+            updated_at: self.clock.now(),
+        };
+        match data
+            .notification_preferences
+            .iter_mut()
+            .find(|p| p.user == user)
+        {
+            Some(existing) => *existing = prefs.clone(),
+            None => data.notification_preferences.push(prefs.clone()),
+        }
+        drop(data);
+
+        self.save().await?;
+        Ok(prefs)
+    }
+
+    async fn list_notification_preferences(&self) -> Result<Vec<NotificationPreferences>> {
+        let data = self.data.lock().await;
+        Ok(data.notification_preferences.clone())
+    }
+
+    async fn create_share(&self, new_share: NewShareRecord) -> Result<ShareRecord> {
+        let mut data = self.write().await;
+
+        if !data.files.iter().any(|f| f.path == new_share.path) {
+            return Err(Error::NotFound(PathBuf::from(&new_share.path)));
+        }
+
+        let record = ShareRecord::new(new_share, self.clock.now());
+        data.shares.push(record.clone());
+        drop(data);
+
+        self.save().await?;
+        Ok(record)
+    }
+
+    async fn get_share(&self, id: uuid::Uuid) -> Result<ShareRecord> {
+        let data = self.data.lock().await;
+        data.shares
+            .iter()
+            .find(|s| s.id == id)
+            .cloned()
+            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("share:{}", id))))
+    }
+
+    async fn list_shares(&self) -> Result<Vec<ShareRecord>> {
+        let data = self.data.lock().await;
+        Ok(data.shares.clone())
+    }
+
+    async fn delete_share(&self, id: uuid::Uuid) -> Result<()> {
+        let mut data = self.write().await;
+        let idx = data
+            .shares
+            .iter()
+            .position(|s| s.id == id)
+            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("share:{}", id))))?;
+
+        data.shares.remove(idx);
+        data.share_accesses.retain(|a| a.share_id != id);
+        drop(data);
+
+        self.save().await
+    }
+
+    async fn record_share_access(&self, access: ShareAccessRecord) -> Result<()> {
+        let mut data = self.write().await;
+        data.share_accesses.push(access);
+        drop(data);
+
+        self.save().await
+    }
+
+    async fn list_share_accesses(&self, share_id: uuid::Uuid) -> Result<Vec<ShareAccessRecord>> {
+        let data = self.data.lock().await;
+        Ok(data
+            .share_accesses
+            .iter()
+            .filter(|a| a.share_id == share_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn list_legal_holds(&self) -> Result<Vec<LegalHold>> {
+        let data = self.data.lock().await;
+        Ok(data.legal_holds.clone())
+    }
+
+    async fn find_legal_hold(&self, path: &str) -> Option<LegalHold> {
+        let data = self.data.lock().await;
+        data.legal_holds.iter().find(|h| h.covers(path)).cloned()
+    }
+
+    async fn find_overlapping_legal_hold(&self, path: &str) -> Option<LegalHold> {
+        let data = self.data.lock().await;
+        data.legal_holds.iter().find(|h| h.overlaps(path)).cloned()
+    }
+
+    async fn add_legal_hold(&self, path: &str, reason: Option<String>) -> Result<LegalHold> {
+        let mut data = self.write().await;
+        if data.legal_holds.iter().any(|h| h.path == path) {
+            return Err(Error::AlreadyExists(PathBuf::from(path)));
+        }
+
+        let hold = LegalHold {
+            path: path.to_string(),
+            reason,
+            created_at: self.clock.now(),
+        };
+        data.legal_holds.push(hold.clone());
+        drop(data);
+
+        self.save().await?;
+        Ok(hold)
+    }
+
+    async fn remove_legal_hold(&self, path: &str) -> Result<()> {
+        let mut data = self.write().await;
+        let idx = data
+            .legal_holds
+            .iter()
+            .position(|h| h.path == path)
+            .ok_or_else(|| Error::NotFound(PathBuf::from(path)))?;
+
+        data.legal_holds.remove(idx);
+        drop(data);
+
+        self.save().await
+    }
+
+    async fn list_lifecycle_rules(&self) -> Result<Vec<LifecycleRule>> {
+        let data = self.data.lock().await;
+        Ok(data.lifecycle_rules.clone())
+    }
+
+    async fn create_lifecycle_rule(&self, new_rule: NewLifecycleRule) -> Result<LifecycleRule> {
+        let mut data = self.write().await;
+        let rule = LifecycleRule::new(new_rule, self.clock.now());
+        data.lifecycle_rules.push(rule.clone());
+        drop(data);
+
+        self.save().await?;
+        Ok(rule)
+    }
+
+    async fn delete_lifecycle_rule(&self, id: uuid::Uuid) -> Result<()> {
+        let mut data = self.write().await;
+        let idx = data
+            .lifecycle_rules
+            .iter()
+            .position(|r| r.id == id)
+            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("lifecycle:{}", id))))?;
+
+        data.lifecycle_rules.remove(idx);
+        drop(data);
+
+        self.save().await
+    }
+
+    async fn list_rate_classes(&self) -> Result<Vec<RateClass>> {
+        let data = self.data.lock().await;
+        Ok(data.rate_classes.clone())
+    }
+
+    async fn create_rate_class(&self, new_class: NewRateClass) -> Result<RateClass> {
+        let mut data = self.write().await;
+        let class = RateClass::new(new_class, self.clock.now());
+        data.rate_classes.push(class.clone());
+        drop(data);
+
+        self.save().await?;
+        Ok(class)
+    }
+
+    async fn delete_rate_class(&self, id: uuid::Uuid) -> Result<()> {
+        let mut data = self.write().await;
+        let idx = data
+            .rate_classes
+            .iter()
+            .position(|c| c.id == id)
+            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("rate-class:{}", id))))?;
+
+        data.rate_classes.remove(idx);
+        drop(data);
+
+        self.save().await
+    }
+
+    async fn list_mounts(&self) -> Result<Vec<MountRecord>> {
+        let data = self.data.lock().await;
+        Ok(data.mounts.clone())
+    }
+
+    async fn create_mount(&self, new_mount: NewMountRecord) -> Result<MountRecord> {
+        let mut data = self.write().await;
+        if let Some(existing) = data.mounts.iter().find(|m| m.overlaps(&new_mount.path)) {
+            return Err(Error::AlreadyExists(PathBuf::from(&existing.path)));
+        }
+        let mount = MountRecord::new(new_mount, self.clock.now());
+        data.mounts.push(mount.clone());
+        drop(data);
+
+        self.save().await?;
+        Ok(mount)
+    }
+
+    async fn delete_mount(&self, id: uuid::Uuid) -> Result<()> {
+        let mut data = self.write().await;
+        let idx = data
+            .mounts
+            .iter()
+            .position(|m| m.id == id)
+            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("mount:{}", id))))?;
+
+        data.mounts.remove(idx);
+        drop(data);
+
+        self.save().await
+    }
+
+    async fn list_directories(&self) -> Result<Vec<DirectoryRecord>> {
+        let data = self.data.lock().await;
+        Ok(data.directories.clone())
+    }
+
+    async fn create_directory(&self, path: &str) -> Result<DirectoryRecord> {
+        let mut data = self.write().await;
+        if data.directories.iter().any(|d| d.path == path) {
+            return Err(Error::AlreadyExists(PathBuf::from(path)));
+        }
+
+        let directory = DirectoryRecord {
+            path: path.to_string(),
+            created_at: self.clock.now(),
+        };
+        data.directories.push(directory.clone());
+        drop(data);
+
+        self.save().await?;
+        Ok(directory)
+    }
+
+    async fn delete_directories(&self, path: &str) -> Result<usize> {
+        let mut data = self.write().await;
+        let before = data.directories.len();
+        data.directories.retain(|d| !d.is_under(path));
+        let removed = before - data.directories.len();
+        drop(data);
+
+        if removed > 0 {
+            self.save().await?;
+        }
+        Ok(removed)
+    }
+
+    async fn move_directories(&self, path: &str, new_path: &str) -> Result<usize> {
+        let mut data = self.write().await;
+        let mut moved = 0;
+        for directory in data.directories.iter_mut().filter(|d| d.is_under(path)) {
+            directory.path = format!("{}{}", new_path, &directory.path[path.len()..]);
+            moved += 1;
+        }
+        drop(data);
+
+        if moved > 0 {
+            self.save().await?;
+        }
+        Ok(moved)
+    }
+
+    async fn restore_file(&self, record: FileRecord) -> Result<FileRecord> {
+        let mut data = self.write().await;
+        if data
+            .files
+            .iter()
+            .any(|f| f.path == record.path || f.id == record.id)
+        {
+            return Err(Error::AlreadyExists(PathBuf::from(&record.path)));
+        }
+        ensure_not_held(&data, &record.path)?;
+
+        data.files.push(record.clone());
+        drop(data);
+
+        self.save().await?;
+        Ok(record)
+    }
+
+    async fn list_trash(&self) -> Result<Vec<TrashEntry>> {
+        let data = self.data.lock().await;
+        Ok(data.trash.clone())
+    }
+
+    async fn get_trash_entry(&self, id: uuid::Uuid) -> Result<TrashEntry> {
+        let data = self.data.lock().await;
+        data.trash
+            .iter()
+            .find(|t| t.id == id)
+            .cloned()
+            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("trash:{}", id))))
+    }
+
+    async fn create_trash_entry(&self, new_entry: NewTrashEntry) -> Result<TrashEntry> {
+        let entry = TrashEntry::new(new_entry, self.clock.now());
+        let mut data = self.write().await;
+        data.trash.push(entry.clone());
+        drop(data);
+
+        self.save().await?;
+        Ok(entry)
+    }
+
+    async fn delete_trash_entry(&self, id: uuid::Uuid) -> Result<()> {
+        let mut data = self.write().await;
+        let idx = data
+            .trash
+            .iter()
+            .position(|t| t.id == id)
+            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("trash:{}", id))))?;
+        data.trash.remove(idx);
+        drop(data);
+
+        self.save().await
+    }
+}
+
+fn ensure_not_held(data: &Database, path: &str) -> Result<()> {
+    match data.legal_holds.iter().find(|h| h.covers(path)) {
+        Some(hold) => Err(Error::Held(hold.path.clone())),
+        None => Ok(()),
+    }
+}
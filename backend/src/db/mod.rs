@@ -1,7 +1,30 @@
+pub mod json;
 pub mod models;
 pub mod repository;
+pub mod snapshot;
+pub mod sqlite;
+pub mod store;
 
+pub use json::JsonBackend;
 pub use models::{
-    DeviceRecord, FileRecord, NewDeviceRecord, NewFileRecord, NewSyncRecord, SyncRecord, SyncStatus,
+    CommentRecord, DeviceRecord, DirectoryRecord, FileDeparture, FileRecord, FileVersionRecord,
+    FsId, LegalHold, LifecycleRule, MediaMetadata, MountInfo, MountRecord, NewCommentRecord,
+    NewDeviceRecord, NewFileRecord, NewLifecycleRule, NewMountRecord, NewRateClass, NewShareRecord,
+    NewSyncRecord, NewTrashEntry, NewUserRecord, NotificationChannel, NotificationEvent,
+    NotificationPreferences, NotificationRule, RateClass, ShareAccessRecord, ShareLimits,
+    ShareRecord, SyncRecord, SyncStatus, SyncTransition, TrashEntry, TrashItem, UserInfo,
+    UserRecord,
 };
-pub use repository::Repository;
+pub use repository::{Repository, RepositoryBackend};
+pub use snapshot::Snapshot;
+pub use sqlite::SqliteBackend;
+pub use store::MetadataStore;
+
+/// 存储目录下的数据库文件名
+pub const JSON_DB_FILE: &str = "db.json";
+pub const SQLITE_DB_FILE: &str = "db.sqlite";
+
+/// 存储目录下属于数据库的文件（含 SQLite 的日志文件和迁移后保留的 db.json），不是用户文件
+pub fn is_database_file(logical: &str) -> bool {
+    logical.starts_with(JSON_DB_FILE) || logical.starts_with(SQLITE_DB_FILE)
+}
@@ -19,39 +19,12 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FileRecord {
-    pub id: Uuid,
-    pub path: String,
-    pub hash: Option<String>,
-    pub size: u64,
-    pub version: i32,
-    pub created_at: DateTime<Utc>,
-    pub updated_at: DateTime<Utc>,
-}
+use crate::error::{Error, Result};
 
-// [知识点 #024] 新建记录与完整记录分离
-// ----------------------------------------
-// 题目：为什么需要 NewFileRecord 和 FileRecord 两个结构体？
-//
-// 讲解：
-// 这是 Rust 中常见的 "输入类型" 与 "存储类型" 分离模式：
-// - NewFileRecord：创建时需要的字段（不含 id, created_at 等自动生成的）
-// - FileRecord：完整记录，包含所有字段
-//
-// 这样设计的好处：
-// 1. 类型系统强制调用者提供必需字段
-// 2. 自动生成的字段不会被误设置
-// 3. API 更清晰，不易出错
-//
-// 思考：有没有办法用一个结构体实现两种用途？
-// ----------------------------------------
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct NewFileRecord {
-    pub path: String,
-    pub hash: Option<String>,
-    pub size: u64,
-}
+pub use rustcloud_types::{
+    DeviceRecord, FileRecord, FileVersionRecord, FsId, MediaMetadata, NewDeviceRecord,
+    NewFileRecord, TrashItem, UserInfo,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SyncRecord {
@@ -59,7 +32,23 @@ pub struct SyncRecord {
     pub device_id: Uuid,
     pub file_id: Uuid,
     pub sync_status: SyncStatus,
+    /// 最近一次状态变化的时间
     pub last_sync_at: DateTime<Utc>,
+    /// 每次状态变化及其时间，按先后顺序；早期版本的记录为空
+    #[serde(default)]
+    pub history: Vec<SyncTransition>,
+    /// 同步完成后设备持有的文件版本；冲突记录中是设备修改前的版本
+    #[serde(default)]
+    pub base_version: Option<i32>,
+    /// 冲突时另存的副本路径
+    #[serde(default)]
+    pub conflict_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SyncTransition {
+    pub status: SyncStatus,
+    pub at: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -67,6 +56,10 @@ pub struct NewSyncRecord {
     pub device_id: Uuid,
     pub file_id: Uuid,
     pub sync_status: SyncStatus,
+    #[serde(default)]
+    pub base_version: Option<i32>,
+    #[serde(default)]
+    pub conflict_path: Option<String>,
 }
 
 // [知识点 #025] 枚举与数据库映射
@@ -85,13 +78,15 @@ pub struct NewSyncRecord {
 //
 // 思考：如何处理枚举值变更（数据库迁移）？
 // ----------------------------------------
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum SyncStatus {
     Pending,
     Syncing,
     Completed,
     Failed,
+    /// 设备基于过期版本上传，内容另存为副本或被拒绝
+    Conflict,
 }
 
 impl SyncStatus {
@@ -101,71 +96,629 @@ impl SyncStatus {
             SyncStatus::Syncing => "SYNCING",
             SyncStatus::Completed => "COMPLETED",
             SyncStatus::Failed => "FAILED",
+            SyncStatus::Conflict => "CONFLICT",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "PENDING" => Some(SyncStatus::Pending),
+            "SYNCING" => Some(SyncStatus::Syncing),
+            "COMPLETED" => Some(SyncStatus::Completed),
+            "FAILED" => Some(SyncStatus::Failed),
+            "CONFLICT" => Some(SyncStatus::Conflict),
+            _ => None,
+        }
+    }
+
+    /// 新记录只能从等待或直接从传输开始
+    pub fn is_initial(self) -> bool {
+        matches!(self, SyncStatus::Pending | SyncStatus::Syncing)
+    }
+
+    /// 合法的状态转换：Pending -> Syncing -> Completed / Failed / Conflict，失败后重试回到 Pending
+    pub fn can_become(self, next: SyncStatus) -> bool {
+        matches!(
+            (self, next),
+            (SyncStatus::Pending, SyncStatus::Syncing)
+                | (SyncStatus::Syncing, SyncStatus::Completed)
+                | (SyncStatus::Syncing, SyncStatus::Failed)
+                | (SyncStatus::Syncing, SyncStatus::Conflict)
+                | (SyncStatus::Failed, SyncStatus::Pending)
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommentRecord {
+    pub id: Uuid,
+    pub file_id: Uuid,
+    pub author: String,
+    pub text: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewCommentRecord {
+    pub file_id: Uuid,
+    pub author: String,
+    pub text: String,
+}
+
+/// 口令只保存 Argon2 的 PHC 字符串，对外返回时转换为 UserInfo
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserRecord {
+    pub id: Uuid,
+    pub username: String,
+    pub password_hash: String,
+    pub created_at: DateTime<Utc>,
+    /// 名下文件可占用的字节数；为空时使用服务端的默认配额
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewUserRecord {
+    pub username: String,
+    pub password_hash: String,
+}
+
+impl UserRecord {
+    pub fn new(new_record: NewUserRecord, now: DateTime<Utc>) -> Self {
+        UserRecord {
+            id: Uuid::new_v4(),
+            username: new_record.username,
+            password_hash: new_record.password_hash,
+            created_at: now,
+            quota_bytes: None,
         }
     }
+
+    pub fn info(&self) -> UserInfo {
+        UserInfo {
+            id: self.id,
+            username: self.username.clone(),
+            created_at: self.created_at,
+        }
+    }
+}
+
+/// 文件离开某个路径的记录：删除或移走时各追加一条。
+/// 配合历史版本可以还原过去某一时刻每个文件所在的路径
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileDeparture {
+    pub file_id: Uuid,
+    /// 离开之前的路径
+    pub path: String,
+    /// 移动后的路径；删除时为 None
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub moved_to: Option<String>,
+    pub departed_at: DateTime<Utc>,
+    /// 文件的创建者，删除账户时据此清除其历史
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<Uuid>,
+}
+
+/// 显式创建的目录。有文件的目录由文件路径隐含，记录让空目录也能列出和同步
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirectoryRecord {
+    pub path: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl DirectoryRecord {
+    /// 是否是 path 本身或其下的目录
+    pub fn is_under(&self, path: &str) -> bool {
+        is_same_or_descendant(&self.path, path)
+    }
+}
+
+/// 移入回收站的路径：删除时路径下的文件记录和目录记录，恢复时原样放回。
+/// 内容（存储目录中的文件或整棵目录）保存在 objects/trash/{id}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashEntry {
+    pub id: Uuid,
+    pub path: String,
+    pub files: Vec<FileRecord>,
+    pub directories: Vec<DirectoryRecord>,
+    /// 删除时的总大小，包括未被记录的文件
+    pub size: u64,
+    pub deleted_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_by: Option<Uuid>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewTrashEntry {
+    pub path: String,
+    pub files: Vec<FileRecord>,
+    pub directories: Vec<DirectoryRecord>,
+    pub size: u64,
+    pub deleted_by: Option<Uuid>,
 }
 
+impl TrashEntry {
+    pub fn new(new_entry: NewTrashEntry, now: DateTime<Utc>) -> Self {
+        TrashEntry {
+            id: Uuid::new_v4(),
+            path: new_entry.path,
+            files: new_entry.files,
+            directories: new_entry.directories,
+            size: new_entry.size,
+            deleted_at: now,
+            deleted_by: new_entry.deleted_by,
+        }
+    }
+
+    /// 保留 retention_days 天后自动清除
+    pub fn expires_at(&self, retention_days: u32) -> DateTime<Utc> {
+        self.deleted_at + chrono::Duration::days(retention_days as i64)
+    }
+
+    pub fn info(&self, retention_days: u32) -> TrashItem {
+        TrashItem {
+            id: self.id,
+            path: self.path.clone(),
+            files: self.files.len(),
+            size: self.size,
+            deleted_at: self.deleted_at,
+            expires_at: self.expires_at(retention_days),
+        }
+    }
+}
+
+/// 法律保留：路径本身及其下所有内容禁止修改和删除，直到管理员解除
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LegalHold {
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl LegalHold {
+    /// path 是否位于保留范围内（自身或子路径）
+    pub fn covers(&self, path: &str) -> bool {
+        is_same_or_descendant(path, &self.path)
+    }
+
+    /// 对 path 的整体删除是否会波及保留范围（path 是保留路径的祖先也算）
+    pub fn overlaps(&self, path: &str) -> bool {
+        self.covers(path) || is_same_or_descendant(&self.path, path)
+    }
+}
+
+/// path 等于 ancestor 或位于其下
+pub(crate) fn is_same_or_descendant(path: &str, ancestor: &str) -> bool {
+    path == ancestor
+        || path
+            .strip_prefix(ancestor)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// 目录级生命周期规则，由后台任务周期性执行
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct DeviceRecord {
+pub struct LifecycleRule {
+    pub id: Uuid,
+    /// 规则作用的目录（不含首尾斜杠）
+    pub folder: String,
+    /// 最后修改超过 N 天的文件被删除
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delete_after_days: Option<u32>,
+    /// 超过 M 天未访问的文件被移动到 archive_to 目录
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive_after_days: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive_to: Option<String>,
+    /// 启用命名空间时创建规则的用户，规则只作用于该用户的文件
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewLifecycleRule {
+    pub folder: String,
+    pub delete_after_days: Option<u32>,
+    pub archive_after_days: Option<u32>,
+    pub archive_to: Option<String>,
+    /// 由服务端按令牌填写，请求体中的值被忽略
+    #[serde(skip)]
+    pub owner: Option<Uuid>,
+}
+
+impl LifecycleRule {
+    pub fn new(new_rule: NewLifecycleRule, now: DateTime<Utc>) -> Self {
+        LifecycleRule {
+            id: Uuid::new_v4(),
+            folder: new_rule.folder,
+            delete_after_days: new_rule.delete_after_days,
+            archive_after_days: new_rule.archive_after_days,
+            archive_to: new_rule.archive_to,
+            owner: new_rule.owner,
+            created_at: now,
+        }
+    }
+
+    pub fn applies_to(&self, path: &str) -> bool {
+        is_same_or_descendant(path, &self.folder) && path != self.folder
+    }
+}
+
+/// 传输速率等级，命中的等级限制对应方向的总带宽
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateClass {
     pub id: Uuid,
     pub name: String,
-    pub last_seen: DateTime<Utc>,
+    /// 只对该设备生效；为空时对所有请求生效
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<Uuid>,
+    /// 生效时段 [start_hour, end_hour)，按服务器本地时间；start > end 表示跨越午夜
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_hour: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_hour: Option<u32>,
+    /// 上传（客户端 -> 服务器）字节/秒
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload_bytes_per_sec: Option<u64>,
+    /// 下载（服务器 -> 客户端）字节/秒
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_bytes_per_sec: Option<u64>,
+    pub created_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct NewDeviceRecord {
+pub struct NewRateClass {
     pub name: String,
+    pub device_id: Option<Uuid>,
+    pub start_hour: Option<u32>,
+    pub end_hour: Option<u32>,
+    pub upload_bytes_per_sec: Option<u64>,
+    pub download_bytes_per_sec: Option<u64>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct Database {
-    pub files: Vec<FileRecord>,
-    pub syncs: Vec<SyncRecord>,
-    pub devices: Vec<DeviceRecord>,
+impl RateClass {
+    pub fn new(new_class: NewRateClass, now: DateTime<Utc>) -> Self {
+        RateClass {
+            id: Uuid::new_v4(),
+            name: new_class.name,
+            device_id: new_class.device_id,
+            start_hour: new_class.start_hour,
+            end_hour: new_class.end_hour,
+            upload_bytes_per_sec: new_class.upload_bytes_per_sec,
+            download_bytes_per_sec: new_class.download_bytes_per_sec,
+            created_at: now,
+        }
+    }
+
+    /// 该等级是否对指定设备在指定小时生效
+    pub fn applies_to(&self, device_id: Option<Uuid>, hour: u32) -> bool {
+        if self.device_id.is_some() && self.device_id != device_id {
+            return false;
+        }
+        match (self.start_hour, self.end_hour) {
+            (Some(start), Some(end)) if start <= end => (start..end).contains(&hour),
+            (Some(start), Some(end)) => hour >= start || hour < end,
+            _ => true,
+        }
+    }
 }
 
-impl FileRecord {
-    pub fn new(new_record: NewFileRecord) -> Self {
-        let now = Utc::now();
-        FileRecord {
+/// 挂载到本地命名空间的远程目录，挂载点下的请求转发到远程服务器
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MountRecord {
+    pub id: Uuid,
+    /// 本地挂载点（不含首尾斜杠）
+    pub path: String,
+    /// 远程服务器地址
+    pub url: String,
+    /// 远程服务器上被挂载的目录，为空表示整个存储
+    #[serde(default)]
+    pub remote_path: String,
+    /// 访问远程服务器的令牌，只保存在数据库中，接口返回时不含令牌
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewMountRecord {
+    pub path: String,
+    pub url: String,
+    pub remote_path: String,
+    pub token: Option<String>,
+}
+
+/// 接口返回的挂载信息
+#[derive(Debug, Clone, Serialize)]
+pub struct MountInfo {
+    pub id: Uuid,
+    pub path: String,
+    pub url: String,
+    pub remote_path: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl MountRecord {
+    pub fn new(new_mount: NewMountRecord, now: DateTime<Utc>) -> Self {
+        MountRecord {
             id: Uuid::new_v4(),
-            path: new_record.path,
-            hash: new_record.hash,
-            size: new_record.size,
-            version: 1,
+            path: new_mount.path,
+            url: new_mount.url,
+            remote_path: new_mount.remote_path,
+            token: new_mount.token,
             created_at: now,
-            updated_at: now,
         }
     }
 
-    pub fn increment_version(&mut self) {
-        self.version += 1;
-        self.updated_at = Utc::now();
+    pub fn info(&self) -> MountInfo {
+        MountInfo {
+            id: self.id,
+            path: self.path.clone(),
+            url: self.url.clone(),
+            remote_path: self.remote_path.clone(),
+            created_at: self.created_at,
+        }
+    }
+
+    /// 两个挂载点互相包含时不能同时存在
+    pub fn overlaps(&self, path: &str) -> bool {
+        is_same_or_descendant(path, &self.path) || is_same_or_descendant(&self.path, path)
+    }
+
+    /// 挂载点下的本地路径对应的远程路径；不在挂载点下时为 None
+    pub fn remote_for(&self, path: &str) -> Option<String> {
+        let rest = path.strip_prefix(&self.path)?;
+        if !rest.is_empty() && !rest.starts_with('/') {
+            return None;
+        }
+        let rest = rest.trim_start_matches('/');
+        Some(match (self.remote_path.is_empty(), rest.is_empty()) {
+            (true, _) => rest.to_string(),
+            (false, true) => self.remote_path.clone(),
+            (false, false) => format!("{}/{}", self.remote_path, rest),
+        })
+    }
+
+    /// 远程返回的路径换回挂载点下的本地路径；不在被挂载目录下时为 None
+    pub fn local_for(&self, remote: &str) -> Option<String> {
+        let rest = if self.remote_path.is_empty() {
+            remote
+        } else {
+            let rest = remote.strip_prefix(&self.remote_path)?;
+            if !rest.is_empty() && !rest.starts_with('/') {
+                return None;
+            }
+            rest.trim_start_matches('/')
+        };
+        Some(if rest.is_empty() {
+            self.path.clone()
+        } else {
+            format!("{}/{}", self.path, rest)
+        })
     }
 }
 
+/// 分享链接，持有链接 id 即可下载对应路径的文件
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShareRecord {
+    pub id: Uuid,
+    pub path: String,
+    pub created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(flatten)]
+    pub limits: ShareLimits,
+    /// 创建链接的用户；未启用认证时为空
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_by: Option<Uuid>,
+}
+
+/// 分享链接的用量上限，均为可选
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ShareLimits {
+    /// 最多成功下载次数
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_downloads: Option<u64>,
+    /// 最多累计发送的字节数
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<u64>,
+    /// 同时进行的下载数
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewShareRecord {
+    pub path: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub limits: ShareLimits,
+    pub created_by: Option<Uuid>,
+}
+
+/// 分享链接的一次访问
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShareAccessRecord {
+    pub share_id: Uuid,
+    pub accessed_at: DateTime<Utc>,
+    pub ip: Option<String>,
+    /// 本次响应的字节数（Range 请求只计实际返回的部分）
+    pub bytes: u64,
+}
+
+/// 会触发通知的事件类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationEvent {
+    /// 有新设备注册
+    DeviceRegistered,
+    /// 同一设备 24 小时内同步失败次数达到阈值（默认 1）
+    SyncFailures,
+    /// 已用存储空间超过阈值字节数
+    StorageNearlyFull,
+    /// 分享链接被访问
+    ShareAccessed,
+    /// 文件被创建、修改或删除，可用 path 限定范围
+    FileChanged,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NotificationChannel {
+    Webhook { url: String },
+    Email { to: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationRule {
+    pub event: NotificationEvent,
+    pub channel: NotificationChannel,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<u64>,
+    /// 只关心该路径及其子路径下的事件，未设置时不限范围
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default = "default_rule_enabled")]
+    pub enabled: bool,
+}
+
+fn default_rule_enabled() -> bool {
+    true
+}
+
+impl NotificationRule {
+    pub fn covers_path(&self, path: &str) -> bool {
+        self.path
+            .as_deref()
+            .map(|prefix| prefix.trim_matches('/'))
+            .is_none_or(|prefix| prefix.is_empty() || is_same_or_descendant(path, prefix))
+    }
+}
+
+/// 某个用户的通知偏好，整体读写
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationPreferences {
+    pub user: String,
+    pub rules: Vec<NotificationRule>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Database {
+    pub files: Vec<FileRecord>,
+    pub syncs: Vec<SyncRecord>,
+    pub devices: Vec<DeviceRecord>,
+    /// 旧版本的 db.json 没有该字段
+    #[serde(default)]
+    pub comments: Vec<CommentRecord>,
+    #[serde(default)]
+    pub notification_preferences: Vec<NotificationPreferences>,
+    #[serde(default)]
+    pub shares: Vec<ShareRecord>,
+    #[serde(default)]
+    pub share_accesses: Vec<ShareAccessRecord>,
+    #[serde(default)]
+    pub legal_holds: Vec<LegalHold>,
+    #[serde(default)]
+    pub lifecycle_rules: Vec<LifecycleRule>,
+    #[serde(default)]
+    pub rate_classes: Vec<RateClass>,
+    #[serde(default)]
+    pub users: Vec<UserRecord>,
+    /// 文件的历史版本，按写入顺序
+    #[serde(default)]
+    pub versions: Vec<FileVersionRecord>,
+    #[serde(default)]
+    pub mounts: Vec<MountRecord>,
+    /// 文件删除和移动的记录，按发生顺序
+    #[serde(default)]
+    pub departures: Vec<FileDeparture>,
+    #[serde(default)]
+    pub directories: Vec<DirectoryRecord>,
+    #[serde(default)]
+    pub trash: Vec<TrashEntry>,
+}
+
 impl SyncRecord {
-    pub fn new(new_record: NewSyncRecord) -> Self {
-        SyncRecord {
+    pub fn new(new_record: NewSyncRecord, now: DateTime<Utc>) -> Result<Self> {
+        if !new_record.sync_status.is_initial() {
+            return Err(Error::InvalidTransition(format!(
+                "a sync cannot start as {}",
+                new_record.sync_status.as_str()
+            )));
+        }
+        Ok(SyncRecord {
             id: Uuid::new_v4(),
             device_id: new_record.device_id,
             file_id: new_record.file_id,
             sync_status: new_record.sync_status,
-            last_sync_at: Utc::now(),
+            last_sync_at: now,
+            history: vec![SyncTransition {
+                status: new_record.sync_status,
+                at: now,
+            }],
+            base_version: new_record.base_version,
+            conflict_path: new_record.conflict_path,
+        })
+    }
+
+    /// 按状态机推进并记下时间；非法的转换返回 `Error::InvalidTransition`，记录不变
+    pub fn transition(&mut self, status: SyncStatus, now: DateTime<Utc>) -> Result<()> {
+        if !self.sync_status.can_become(status) {
+            return Err(Error::InvalidTransition(format!(
+                "sync {} cannot go from {} to {}",
+                self.id,
+                self.sync_status.as_str(),
+                status.as_str()
+            )));
         }
+        self.sync_status = status;
+        self.last_sync_at = now;
+        self.history.push(SyncTransition { status, at: now });
+        Ok(())
     }
 }
 
-impl DeviceRecord {
-    pub fn new(new_record: NewDeviceRecord) -> Self {
-        DeviceRecord {
+impl CommentRecord {
+    pub fn new(new_record: NewCommentRecord, now: DateTime<Utc>) -> Self {
+        CommentRecord {
             id: Uuid::new_v4(),
-            name: new_record.name,
-            last_seen: Utc::now(),
+            file_id: new_record.file_id,
+            author: new_record.author,
+            text: new_record.text,
+            created_at: now,
+        }
+    }
+}
+
+impl ShareRecord {
+    pub fn new(new_record: NewShareRecord, now: DateTime<Utc>) -> Self {
+        ShareRecord {
+            id: Uuid::new_v4(),
+            path: new_record.path,
+            created_at: now,
+            expires_at: new_record.expires_at,
+            limits: new_record.limits,
+            created_by: new_record.created_by,
         }
     }
 
-    pub fn update_last_seen(&mut self) {
-        self.last_seen = Utc::now();
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+
+    /// 已用完的配额；用量统计来自访问记录
+    pub fn exhausted_quota(&self, downloads: u64, bytes: u64) -> Option<&'static str> {
+        if self
+            .limits
+            .max_downloads
+            .is_some_and(|max| downloads >= max)
+        {
+            Some("download limit")
+        } else if self.limits.max_bytes.is_some_and(|max| bytes >= max) {
+            Some("bandwidth limit")
+        } else {
+            None
+        }
     }
 }
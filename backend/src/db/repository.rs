@@ -1,241 +1,337 @@
-// [知识点 #081] Arc<Mutex> 与内部可变性
+//! 元数据仓库
+//!
+//! `Repository` 是共享的句柄，具体的持久化由 `RepositoryBackend` 的实现负责：
+//! - `JsonBackend`：全部记录放在内存中，每次修改整体重写 db.json，适合小规模部署
+//! - `SqliteBackend`：按表存储并建立索引，修改只写入受影响的行
+
+// [知识点 #167] trait 对象与 Deref 转发
 // ----------------------------------------
-// 题目：为什么 Repository 用 Arc<Mutex<Database>> 而不是直接持有 Database？
+// 题目：为什么 Repository 不直接定义成 trait，而是包一层结构体？
 //
 // 讲解：
-// Repository 需要在多个 handler 之间共享，且需要修改数据。
-// Arc<Mutex<T>> 组合：
-// - Arc：多所有权，允许 clone 出多个引用
-// - Mutex：内部可变性，允许通过不可变引用修改数据
-//
-// Mutex vs RwLock：
-// - Mutex：同一时刻只有一个线程可以访问（读写都互斥）
-// - RwLock：允许多个读者或一个写者
+// 仓库在各个服务之间按值克隆、以 Arc<Repository> 共享，调用点遍布整个项目。
+// 把后端换成 Arc<dyn RepositoryBackend> 放进结构体里：
+// - Clone 只是增加引用计数，克隆出的句柄指向同一个后端
+// - 实现 Deref<Target = dyn RepositoryBackend> 后，repository.create_file(..)
+//   会自动解引用到 trait 方法，原有调用点不需要改动
+// - 构造函数（new / sqlite / open）仍然是结构体上的关联函数
 //
-// 这里用 Mutex 是因为大多数操作都需要写入，RwLock 优势不明显
+// async_trait 把 async fn 改写成返回 Pin<Box<dyn Future>> 的方法，
+// 这样 trait 才能作为 trait 对象使用（dyn 兼容）。
 //
-// 思考：什么情况下应该用 RwLock 而非 Mutex？
+// 思考：Deref 用于"委托"有什么争议？什么时候应该改为显式转发？
 // ----------------------------------------
 
-use std::path::PathBuf;
+use async_trait::async_trait;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::sync::Mutex;
 
+use super::json::JsonBackend;
 use super::models::{
-    Database, DeviceRecord, FileRecord, NewDeviceRecord, NewFileRecord, NewSyncRecord, SyncRecord,
-    SyncStatus,
+    CommentRecord, DeviceRecord, DirectoryRecord, FileDeparture, FileRecord, FileVersionRecord,
+    FsId, LegalHold, LifecycleRule, MediaMetadata, MountRecord, NewCommentRecord, NewDeviceRecord,
+    NewFileRecord, NewLifecycleRule, NewMountRecord, NewRateClass, NewShareRecord, NewSyncRecord,
+    NewTrashEntry, NewUserRecord, NotificationPreferences, NotificationRule, RateClass,
+    ShareAccessRecord, ShareRecord, SyncRecord, SyncStatus, TrashEntry, UserRecord,
 };
-use crate::error::{Error, Result};
+use super::snapshot::Snapshot;
+use super::sqlite::SqliteBackend;
+use super::{JSON_DB_FILE, SQLITE_DB_FILE};
+use crate::config::DatabaseBackend;
+use crate::error::Result;
+use crate::service::clock::{Clock, SystemClock};
 
 #[derive(Clone)]
 pub struct Repository {
-    data: Arc<Mutex<Database>>,
-    db_path: PathBuf,
+    backend: Arc<dyn RepositoryBackend>,
 }
 
 impl Repository {
+    /// 使用 db_path 处的 JSON 文件
     pub async fn new(db_path: PathBuf) -> Result<Self> {
-        let database = if db_path.exists() {
-            let content = tokio::fs::read_to_string(&db_path).await?;
-            serde_json::from_str(&content).unwrap_or_default()
-        } else {
-            Database::default()
-        };
-
-        Ok(Repository {
-            data: Arc::new(Mutex::new(database)),
-            db_path,
-        })
+        Self::with_clock(db_path, Arc::new(SystemClock)).await
     }
 
-    async fn save(&self) -> Result<()> {
-        let data = self.data.lock().await;
-        let content = serde_json::to_string_pretty(&*data)?;
-        tokio::fs::write(&self.db_path, content).await?;
-        Ok(())
+    /// 记录的时间戳都取自 clock，测试可传入 VirtualClock
+    pub async fn with_clock(db_path: PathBuf, clock: Arc<dyn Clock>) -> Result<Self> {
+        Ok(Self::from_backend(JsonBackend::open(db_path, clock).await?))
     }
 
-    // [知识点 #043] async 方法与锁的作用域
-    // ----------------------------------------
-    // 题目：为什么 lock().await 后要尽快释放锁？
-    //
-    // 讲解：
-    // Mutex::lock().await 会等待获取锁，持有锁期间其他任务无法访问。
-    // 如果在持有锁时执行耗时操作或 .await，会阻塞其他任务。
-    //
-    // 最佳实践：
-    // 1. 获取锁后尽快完成操作
-    // 2. 避免在持有锁时调用其他 async 函数
-    // 3. 如果必须调用，考虑先克隆需要的数据再释放锁
-    //
-    // 思考：如果必须在持有锁时 .await，有什么解决方案？
-    // ----------------------------------------
-
-    pub async fn create_file(&self, new_file: NewFileRecord) -> Result<FileRecord> {
-        let mut data = self.data.lock().await;
-
-        // 检查路径是否已存在
-        if data.files.iter().any(|f| f.path == new_file.path) {
-            return Err(Error::AlreadyExists(PathBuf::from(&new_file.path)));
-        }
-
-        let record = FileRecord::new(new_file);
-        data.files.push(record.clone());
-        drop(data); // 提前释放锁
+    /// 使用 db_path 处的 SQLite 数据库，不存在时创建
+    pub async fn sqlite(db_path: PathBuf, clock: Arc<dyn Clock>) -> Result<Self> {
+        Ok(Self::from_backend(
+            SqliteBackend::open(db_path, clock).await?,
+        ))
+    }
 
-        self.save().await?;
-        Ok(record)
+    /// 按配置打开存储目录下的数据库。
+    ///
+    /// 首次以 SQLite 启动时，如果存在 db.json 就把其中的记录导入新数据库，
+    /// 导入后 db.json 重命名为 db.json.migrated，之后不再读取。
+    pub async fn open(storage_path: &Path, backend: DatabaseBackend) -> Result<Self> {
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        let json_path = storage_path.join(JSON_DB_FILE);
+        match backend {
+            DatabaseBackend::Json => Self::with_clock(json_path, clock).await,
+            DatabaseBackend::Sqlite => {
+                let sqlite = SqliteBackend::open(storage_path.join(SQLITE_DB_FILE), clock).await?;
+                if json_path.exists() {
+                    if sqlite.is_empty().await? {
+                        let imported = sqlite.import_json(&json_path).await?;
+                        let migrated = json_path.with_extension("json.migrated");
+                        tokio::fs::rename(&json_path, &migrated).await?;
+                        tracing::info!(
+                            "Imported {} records from {:?} into SQLite; old file kept as {:?}",
+                            imported,
+                            json_path,
+                            migrated
+                        );
+                    } else {
+                        tracing::warn!(
+                            "Ignoring {:?}: the SQLite database already has records",
+                            json_path
+                        );
+                    }
+                }
+                Ok(Self::from_backend(sqlite))
+            }
+        }
     }
 
-    pub async fn get_file_by_path(&self, path: &str) -> Result<FileRecord> {
-        let data = self.data.lock().await;
-        data.files
-            .iter()
-            .find(|f| f.path == path)
-            .cloned()
-            .ok_or_else(|| Error::NotFound(PathBuf::from(path)))
+    pub fn from_backend(backend: impl RepositoryBackend + 'static) -> Self {
+        Repository {
+            backend: Arc::new(backend),
+        }
     }
+}
+
+impl Deref for Repository {
+    type Target = dyn RepositoryBackend;
 
-    pub async fn get_file_by_id(&self, id: uuid::Uuid) -> Result<FileRecord> {
-        let data = self.data.lock().await;
-        data.files
-            .iter()
-            .find(|f| f.id == id)
-            .cloned()
-            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("file:{}", id))))
+    fn deref(&self) -> &Self::Target {
+        &*self.backend
     }
+}
+
+/// 元数据的持久化接口。
+///
+/// 删除文件时一并删除它的同步记录和评论；处于法律保留中的路径不能被创建、修改、移动或删除，
+/// 违反时返回 `Error::Held`。
+#[async_trait]
+pub trait RepositoryBackend: Send + Sync {
+    /// 记录的时间戳都取自该时钟
+    fn clock(&self) -> Arc<dyn Clock>;
 
-    pub async fn update_file(
+    /// 当前全部元数据的一致快照，之后的修改不会反映到快照中
+    async fn snapshot(&self) -> Result<Snapshot>;
+
+    async fn create_file(&self, new_file: NewFileRecord) -> Result<FileRecord>;
+
+    async fn get_file_by_path(&self, path: &str) -> Result<FileRecord>;
+
+    async fn get_file_by_id(&self, id: uuid::Uuid) -> Result<FileRecord>;
+
+    async fn update_file(
         &self,
         id: uuid::Uuid,
         hash: Option<String>,
         size: u64,
-    ) -> Result<FileRecord> {
-        let mut data = self.data.lock().await;
-        let file = data
-            .files
-            .iter_mut()
-            .find(|f| f.id == id)
-            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("file:{}", id))))?;
-
-        file.hash = hash;
-        file.size = size;
-        file.increment_version();
-        let record = file.clone();
-        drop(data);
-
-        self.save().await?;
-        Ok(record)
-    }
+    ) -> Result<FileRecord>;
 
-    pub async fn delete_file(&self, id: uuid::Uuid) -> Result<()> {
-        let mut data = self.data.lock().await;
-        let idx = data
-            .files
-            .iter()
-            .position(|f| f.id == id)
-            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("file:{}", id))))?;
+    /// 更新媒体元数据，不产生新版本
+    async fn set_file_media(
+        &self,
+        id: uuid::Uuid,
+        media: Option<MediaMetadata>,
+    ) -> Result<FileRecord>;
 
-        data.files.remove(idx);
-        // 同时删除相关的同步记录
-        data.syncs.retain(|s| s.file_id != id);
-        drop(data);
+    /// 合并更新自定义元数据：值为 None 的键被删除，不产生新版本
+    async fn update_file_metadata(
+        &self,
+        id: uuid::Uuid,
+        patch: BTreeMap<String, Option<String>>,
+    ) -> Result<FileRecord>;
 
-        self.save().await
-    }
+    /// 更新落盘文件的文件系统标识，不产生新版本
+    async fn set_file_fs_id(&self, id: uuid::Uuid, fs_id: Option<FsId>) -> Result<FileRecord>;
 
-    pub async fn list_files(&self) -> Result<Vec<FileRecord>> {
-        let data = self.data.lock().await;
-        Ok(data.files.clone())
-    }
+    async fn find_file_by_fs_id(&self, fs_id: FsId) -> Option<FileRecord>;
 
-    pub async fn create_sync(&self, new_sync: NewSyncRecord) -> Result<SyncRecord> {
-        let mut data = self.data.lock().await;
+    /// 记下创建文件的用户，不产生新版本
+    async fn set_file_owner(&self, id: uuid::Uuid, owner: Option<uuid::Uuid>)
+        -> Result<FileRecord>;
 
-        // 验证 file_id 存在
-        if !data.files.iter().any(|f| f.id == new_sync.file_id) {
-            return Err(Error::NotFound(PathBuf::from(format!(
-                "file:{}",
-                new_sync.file_id
-            ))));
-        }
+    /// 删除记录并记下一条 FileDeparture；历史版本保留，用于列出过去的目录
+    async fn delete_file(&self, id: uuid::Uuid) -> Result<()>;
+
+    /// 批量写入访问时间，只保存更新的值；返回实际更新的文件数
+    async fn record_file_accesses(
+        &self,
+        accesses: HashMap<String, chrono::DateTime<chrono::Utc>>,
+    ) -> Result<usize>;
 
-        let record = SyncRecord::new(new_sync);
-        data.syncs.push(record.clone());
-        drop(data);
+    /// 修改记录的路径并记下一条 FileDeparture，源路径和目标路径都不能处于保留中
+    async fn move_file(&self, id: uuid::Uuid, new_path: &str) -> Result<FileRecord>;
 
-        self.save().await?;
-        Ok(record)
-    }
+    async fn list_files(&self) -> Result<Vec<FileRecord>>;
+
+    /// 所有文件记录的大小之和，即已用存储空间
+    async fn total_size(&self) -> Result<u64>;
+
+    /// 用户名下的文件数和大小之和：创建者为 owner 的记录，以及 root 目录下没有创建者的记录
+    async fn owner_usage(&self, owner: uuid::Uuid, root: Option<&str>) -> Result<(usize, u64)>;
+
+    /// 已有记录中与 path 只差大小写（按 `case_key` 比较）的写法，按字典序排列：
+    /// 文件本身的路径，或文件所在的某一级上级目录
+    async fn path_spellings(&self, path: &str) -> Result<Vec<String>>;
+
+    /// 文件的全部版本，按版本号升序，最后一条即当前版本
+    async fn list_file_versions(&self, file_id: uuid::Uuid) -> Result<Vec<FileVersionRecord>>;
+
+    /// 所有文件的删除和移动记录，按发生顺序
+    async fn list_departures(&self) -> Result<Vec<FileDeparture>>;
+
+    /// 彻底忘掉一个已删除的文件：历史版本和删除、移动记录都不再保留
+    async fn purge_file_history(&self, file_id: uuid::Uuid) -> Result<()>;
 
-    pub async fn update_sync_status(
+    /// 新记录只能处于 Pending 或 Syncing，否则返回 `Error::InvalidTransition`
+    async fn create_sync(&self, new_sync: NewSyncRecord) -> Result<SyncRecord>;
+
+    /// 按 `SyncStatus::can_become` 校验后推进状态，并在 history 中记下时间
+    async fn update_sync_status(&self, id: uuid::Uuid, status: SyncStatus) -> Result<SyncRecord>;
+
+    /// 把 stuck_since 之后再没有变化的 Syncing 记录标记为 Failed，返回处理的条数
+    async fn expire_stuck_syncs(&self, stuck_since: chrono::DateTime<chrono::Utc>)
+        -> Result<usize>;
+
+    async fn list_syncs_by_file(&self, file_id: uuid::Uuid) -> Result<Vec<SyncRecord>>;
+
+    /// 统计某设备自 since 以来失败的同步次数
+    async fn count_failed_syncs(
+        &self,
+        device_id: uuid::Uuid,
+        since: chrono::DateTime<chrono::Utc>,
+    ) -> Result<usize>;
+
+    /// 所有 Conflict 状态的同步记录，按发生先后排列
+    async fn list_conflicts(&self) -> Result<Vec<SyncRecord>>;
+
+    async fn create_comment(&self, new_comment: NewCommentRecord) -> Result<CommentRecord>;
+
+    async fn list_comments_by_file(&self, file_id: uuid::Uuid) -> Result<Vec<CommentRecord>>;
+
+    async fn list_comments(&self) -> Result<Vec<CommentRecord>>;
+
+    async fn delete_comment(&self, id: uuid::Uuid) -> Result<()>;
+
+    async fn create_device(&self, new_device: NewDeviceRecord) -> Result<DeviceRecord>;
+
+    async fn get_device(&self, id: uuid::Uuid) -> Result<DeviceRecord>;
+
+    async fn update_device_last_seen(&self, id: uuid::Uuid) -> Result<DeviceRecord>;
+
+    async fn list_devices(&self) -> Result<Vec<DeviceRecord>>;
+
+    /// 记下注册设备的用户
+    async fn set_device_owner(
         &self,
         id: uuid::Uuid,
-        status: SyncStatus,
-    ) -> Result<SyncRecord> {
-        let mut data = self.data.lock().await;
-        let sync = data
-            .syncs
-            .iter_mut()
-            .find(|s| s.id == id)
-            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("sync:{}", id))))?;
-
-        sync.sync_status = status;
-        sync.last_sync_at = chrono::Utc::now();
-        let record = sync.clone();
-        drop(data);
-
-        self.save().await?;
-        Ok(record)
-    }
+        owner: Option<uuid::Uuid>,
+    ) -> Result<DeviceRecord>;
 
-    pub async fn list_syncs_by_file(&self, file_id: uuid::Uuid) -> Result<Vec<SyncRecord>> {
-        let data = self.data.lock().await;
-        Ok(data
-            .syncs
-            .iter()
-            .filter(|s| s.file_id == file_id)
-            .cloned()
-            .collect())
-    }
+    /// 用户名不区分大小写，已被占用时返回 AlreadyExists
+    async fn create_user(&self, new_user: NewUserRecord) -> Result<UserRecord>;
 
-    pub async fn create_device(&self, new_device: NewDeviceRecord) -> Result<DeviceRecord> {
-        let mut data = self.data.lock().await;
-        let record = DeviceRecord::new(new_device);
-        data.devices.push(record.clone());
-        drop(data);
+    async fn find_user_by_name(&self, username: &str) -> Option<UserRecord>;
 
-        self.save().await?;
-        Ok(record)
-    }
+    async fn get_user(&self, id: uuid::Uuid) -> Result<UserRecord>;
 
-    pub async fn get_device(&self, id: uuid::Uuid) -> Result<DeviceRecord> {
-        let data = self.data.lock().await;
-        data.devices
-            .iter()
-            .find(|d| d.id == id)
-            .cloned()
-            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("device:{}", id))))
-    }
+    /// 设置用户的配额，None 表示改用服务端的默认配额
+    async fn set_user_quota(&self, id: uuid::Uuid, quota_bytes: Option<u64>) -> Result<UserRecord>;
 
-    pub async fn update_device_last_seen(&self, id: uuid::Uuid) -> Result<DeviceRecord> {
-        let mut data = self.data.lock().await;
-        let device = data
-            .devices
-            .iter_mut()
-            .find(|d| d.id == id)
-            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("device:{}", id))))?;
+    /// 删除用户，同时删除以其用户名保存的通知偏好
+    async fn delete_user(&self, id: uuid::Uuid) -> Result<()>;
 
-        device.update_last_seen();
-        let record = device.clone();
-        drop(data);
+    /// 未设置过偏好的用户返回空规则
+    async fn get_notification_preferences(&self, user: &str) -> Result<NotificationPreferences>;
 
-        self.save().await?;
-        Ok(record)
-    }
+    async fn set_notification_preferences(
+        &self,
+        user: &str,
+        rules: Vec<NotificationRule>,
+    ) -> Result<NotificationPreferences>;
 
-    pub async fn list_devices(&self) -> Result<Vec<DeviceRecord>> {
-        let data = self.data.lock().await;
-        Ok(data.devices.clone())
-    }
+    async fn list_notification_preferences(&self) -> Result<Vec<NotificationPreferences>>;
+
+    /// 只能分享已跟踪的文件
+    async fn create_share(&self, new_share: NewShareRecord) -> Result<ShareRecord>;
+
+    async fn get_share(&self, id: uuid::Uuid) -> Result<ShareRecord>;
+
+    async fn list_shares(&self) -> Result<Vec<ShareRecord>>;
+
+    /// 撤销分享，同时删除其访问记录
+    async fn delete_share(&self, id: uuid::Uuid) -> Result<()>;
+
+    async fn record_share_access(&self, access: ShareAccessRecord) -> Result<()>;
+
+    /// 按访问时间先后返回
+    async fn list_share_accesses(&self, share_id: uuid::Uuid) -> Result<Vec<ShareAccessRecord>>;
+
+    async fn list_legal_holds(&self) -> Result<Vec<LegalHold>>;
+
+    /// 覆盖 path 的保留（path 自身或其祖先目录上的保留）
+    async fn find_legal_hold(&self, path: &str) -> Option<LegalHold>;
+
+    /// 删除 path 时会波及的保留，包括其子路径上的保留
+    async fn find_overlapping_legal_hold(&self, path: &str) -> Option<LegalHold>;
+
+    async fn add_legal_hold(&self, path: &str, reason: Option<String>) -> Result<LegalHold>;
+
+    async fn remove_legal_hold(&self, path: &str) -> Result<()>;
+
+    async fn list_lifecycle_rules(&self) -> Result<Vec<LifecycleRule>>;
+
+    async fn create_lifecycle_rule(&self, new_rule: NewLifecycleRule) -> Result<LifecycleRule>;
+
+    async fn delete_lifecycle_rule(&self, id: uuid::Uuid) -> Result<()>;
+
+    async fn list_rate_classes(&self) -> Result<Vec<RateClass>>;
+
+    async fn create_rate_class(&self, new_class: NewRateClass) -> Result<RateClass>;
+
+    async fn delete_rate_class(&self, id: uuid::Uuid) -> Result<()>;
+
+    async fn list_mounts(&self) -> Result<Vec<MountRecord>>;
+
+    /// 与已有挂载点互相包含时返回 AlreadyExists
+    async fn create_mount(&self, new_mount: NewMountRecord) -> Result<MountRecord>;
+
+    async fn delete_mount(&self, id: uuid::Uuid) -> Result<()>;
+
+    /// 显式创建的目录，按创建顺序
+    async fn list_directories(&self) -> Result<Vec<DirectoryRecord>>;
+
+    /// 同一路径已有记录时返回 AlreadyExists
+    async fn create_directory(&self, path: &str) -> Result<DirectoryRecord>;
+
+    /// 删除 path 及其下所有目录的记录，返回删除的条数
+    async fn delete_directories(&self, path: &str) -> Result<usize>;
+
+    /// path 及其下所有目录的记录改到 new_path 下，返回修改的条数
+    async fn move_directories(&self, path: &str, new_path: &str) -> Result<usize>;
+
+    /// 把从回收站恢复的记录原样放回（id、版本号不变），路径或 id 已被占用时返回 AlreadyExists
+    async fn restore_file(&self, record: FileRecord) -> Result<FileRecord>;
+
+    /// 回收站中的条目，按删除先后
+    async fn list_trash(&self) -> Result<Vec<TrashEntry>>;
+
+    async fn get_trash_entry(&self, id: uuid::Uuid) -> Result<TrashEntry>;
+
+    async fn create_trash_entry(&self, new_entry: NewTrashEntry) -> Result<TrashEntry>;
+
+    async fn delete_trash_entry(&self, id: uuid::Uuid) -> Result<()>;
 }
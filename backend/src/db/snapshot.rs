@@ -0,0 +1,59 @@
+// [知识点 #190] 写时复制的快照：Arc::make_mut
+// ----------------------------------------
+// 题目：导出账户要先列文件、再逐个查版本、再查分享，中途有人删了文件会怎样？
+//
+// 讲解：
+// 多次调用之间不持有锁，每次看到的都是当时的状态，
+// 拼出来的结果可能一半是删除前、一半是删除后（"撕裂"的读）。
+// 整个过程都持有全局锁又会挡住所有写入。
+//
+// JSON 后端把数据放在 Arc<Database> 里：
+// - 取快照只是在锁内克隆一次 Arc，之后读多久都不再占用锁
+// - 写入用 Arc::make_mut：没有快照在外面时引用计数为 1，直接原地修改；
+//   有快照时先复制一份再改，快照看到的内容保持不变
+// 于是读方拿到的是某一时刻完整一致的数据，写方也不会被长时间的读挡住。
+//
+// SQLite 后端在一个读事务中读出所有表，WAL 模式下事务内看到的是同一时刻的数据。
+//
+// 思考：快照长时间不释放时，频繁的写入会带来什么开销？
+// ----------------------------------------
+
+use std::ops::Deref;
+use std::sync::Arc;
+
+use super::models::{Database, FileVersionRecord};
+
+/// 某一时刻全部元数据的只读视图，克隆只增加引用计数。
+///
+/// 用于需要多次读取、又要求前后一致的场景，例如导出、列出目录和还原过去的文件列表
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    database: Arc<Database>,
+}
+
+impl Snapshot {
+    pub fn new(database: Arc<Database>) -> Self {
+        Snapshot { database }
+    }
+
+    /// 文件的全部版本，按版本号升序，与 list_file_versions 一致
+    pub fn file_versions(&self, file_id: uuid::Uuid) -> Vec<FileVersionRecord> {
+        let mut versions: Vec<FileVersionRecord> = self
+            .database
+            .versions
+            .iter()
+            .filter(|v| v.file_id == file_id)
+            .cloned()
+            .collect();
+        versions.sort_by_key(|v| v.version);
+        versions
+    }
+}
+
+impl Deref for Snapshot {
+    type Target = Database;
+
+    fn deref(&self) -> &Self::Target {
+        &self.database
+    }
+}
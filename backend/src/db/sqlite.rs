@@ -0,0 +1,2109 @@
+//! SQLite 存储的元数据仓库
+//!
+//! 每类记录一张表，常用的查询条件（路径、文件 id、设备 id、文件系统标识）都建了索引；
+//! 嵌套结构（媒体信息、自定义元数据、通知规则）以 JSON 文本存在单独的列中。
+//! rusqlite 是同步接口，所有语句都放到 spawn_blocking 中执行。
+
+use async_trait::async_trait;
+use chrono::{DateTime, SecondsFormat, Utc};
+use rusqlite::types::Type;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use rustcloud_types::path::case_key;
+use serde::de::DeserializeOwned;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use super::models::{
+    CommentRecord, Database, DeviceRecord, DirectoryRecord, FileDeparture, FileRecord,
+    FileVersionRecord, FsId, LegalHold, LifecycleRule, MediaMetadata, MountRecord,
+    NewCommentRecord, NewDeviceRecord, NewFileRecord, NewLifecycleRule, NewMountRecord,
+    NewRateClass, NewShareRecord, NewSyncRecord, NewTrashEntry, NewUserRecord,
+    NotificationPreferences, NotificationRule, RateClass, ShareAccessRecord, ShareLimits,
+    ShareRecord, SyncRecord, SyncStatus, TrashEntry, UserRecord,
+};
+use super::repository::RepositoryBackend;
+use super::snapshot::Snapshot;
+use crate::error::{Error, Result};
+use crate::service::clock::Clock;
+
+/// 表结构变化时递增，并在 migrate 中补上升级语句
+const SCHEMA_VERSION: i32 = 12;
+
+/// 建在升级时补上的列上的索引；旧库要等 migrate 补完列才能创建
+const MIGRATED_INDEXES: &str = "
+CREATE INDEX IF NOT EXISTS files_owner ON files (owner);
+CREATE INDEX IF NOT EXISTS files_path_key ON files (path_key);
+";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS files (
+    id BLOB PRIMARY KEY,
+    path TEXT NOT NULL UNIQUE,
+    hash TEXT,
+    size INTEGER NOT NULL,
+    version INTEGER NOT NULL,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    media TEXT,
+    metadata TEXT NOT NULL DEFAULT '{}',
+    last_accessed_at TEXT,
+    fs_device INTEGER,
+    fs_inode INTEGER,
+    owner BLOB,
+    path_key TEXT
+);
+CREATE INDEX IF NOT EXISTS files_fs_id ON files (fs_device, fs_inode);
+
+CREATE TABLE IF NOT EXISTS file_versions (
+    file_id BLOB NOT NULL,
+    version INTEGER NOT NULL,
+    hash TEXT,
+    size INTEGER NOT NULL,
+    created_at TEXT NOT NULL,
+    PRIMARY KEY (file_id, version)
+);
+
+CREATE TABLE IF NOT EXISTS file_departures (
+    file_id BLOB NOT NULL,
+    path TEXT NOT NULL,
+    moved_to TEXT,
+    departed_at TEXT NOT NULL,
+    owner BLOB
+);
+CREATE INDEX IF NOT EXISTS file_departures_file ON file_departures (file_id);
+
+CREATE TABLE IF NOT EXISTS devices (
+    id BLOB PRIMARY KEY,
+    name TEXT NOT NULL,
+    last_seen TEXT NOT NULL,
+    owner BLOB
+);
+
+CREATE TABLE IF NOT EXISTS syncs (
+    id BLOB PRIMARY KEY,
+    device_id BLOB NOT NULL,
+    file_id BLOB NOT NULL,
+    sync_status TEXT NOT NULL,
+    last_sync_at TEXT NOT NULL,
+    history TEXT NOT NULL DEFAULT '[]',
+    base_version INTEGER,
+    conflict_path TEXT
+);
+CREATE INDEX IF NOT EXISTS syncs_file ON syncs (file_id);
+CREATE INDEX IF NOT EXISTS syncs_device_status ON syncs (device_id, sync_status, last_sync_at);
+
+CREATE TABLE IF NOT EXISTS comments (
+    id BLOB PRIMARY KEY,
+    file_id BLOB NOT NULL,
+    author TEXT NOT NULL,
+    text TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS comments_file ON comments (file_id);
+
+CREATE TABLE IF NOT EXISTS users (
+    id BLOB PRIMARY KEY,
+    username TEXT NOT NULL UNIQUE COLLATE NOCASE,
+    password_hash TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    quota_bytes INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS notification_preferences (
+    user TEXT PRIMARY KEY,
+    rules TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS shares (
+    id BLOB PRIMARY KEY,
+    path TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    expires_at TEXT,
+    max_downloads INTEGER,
+    max_bytes INTEGER,
+    max_concurrent INTEGER,
+    created_by BLOB
+);
+
+CREATE TABLE IF NOT EXISTS share_accesses (
+    share_id BLOB NOT NULL,
+    accessed_at TEXT NOT NULL,
+    ip TEXT,
+    bytes INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS share_accesses_share ON share_accesses (share_id);
+
+CREATE TABLE IF NOT EXISTS legal_holds (
+    path TEXT PRIMARY KEY,
+    reason TEXT,
+    created_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS lifecycle_rules (
+    id BLOB PRIMARY KEY,
+    folder TEXT NOT NULL,
+    delete_after_days INTEGER,
+    archive_after_days INTEGER,
+    archive_to TEXT,
+    created_at TEXT NOT NULL,
+    owner BLOB
+);
+
+CREATE TABLE IF NOT EXISTS rate_classes (
+    id BLOB PRIMARY KEY,
+    name TEXT NOT NULL,
+    device_id BLOB,
+    start_hour INTEGER,
+    end_hour INTEGER,
+    upload_bytes_per_sec INTEGER,
+    download_bytes_per_sec INTEGER,
+    created_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS mounts (
+    id BLOB PRIMARY KEY,
+    path TEXT NOT NULL,
+    url TEXT NOT NULL,
+    remote_path TEXT NOT NULL,
+    token TEXT,
+    created_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS directories (
+    path TEXT PRIMARY KEY,
+    created_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS trash (
+    id BLOB PRIMARY KEY,
+    path TEXT NOT NULL,
+    files TEXT NOT NULL,
+    directories TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    deleted_at TEXT NOT NULL,
+    deleted_by BLOB
+);
+";
+
+/// is_empty 检查的表，即所有表
+const TABLES: &[&str] = &[
+    "files",
+    "file_versions",
+    "file_departures",
+    "devices",
+    "syncs",
+    "comments",
+    "users",
+    "notification_preferences",
+    "shares",
+    "share_accesses",
+    "legal_holds",
+    "lifecycle_rules",
+    "rate_classes",
+    "mounts",
+    "directories",
+    "trash",
+];
+
+const FILE_COLUMNS: &str = "id, path, hash, size, version, created_at, updated_at, media, \
+     metadata, last_accessed_at, fs_device, fs_inode, owner";
+const VERSION_COLUMNS: &str = "file_id, version, hash, size, created_at";
+const DEPARTURE_COLUMNS: &str = "file_id, path, moved_to, departed_at, owner";
+const SYNC_COLUMNS: &str =
+    "id, device_id, file_id, sync_status, last_sync_at, history, base_version, conflict_path";
+const COMMENT_COLUMNS: &str = "id, file_id, author, text, created_at";
+const DEVICE_COLUMNS: &str = "id, name, last_seen, owner";
+const USER_COLUMNS: &str = "id, username, password_hash, created_at, quota_bytes";
+const SHARE_COLUMNS: &str =
+    "id, path, created_at, expires_at, max_downloads, max_bytes, max_concurrent, created_by";
+const LIFECYCLE_COLUMNS: &str =
+    "id, folder, delete_after_days, archive_after_days, archive_to, created_at, owner";
+const RATE_CLASS_COLUMNS: &str = "id, name, device_id, start_hour, end_hour, \
+     upload_bytes_per_sec, download_bytes_per_sec, created_at";
+const MOUNT_COLUMNS: &str = "id, path, url, remote_path, token, created_at";
+const DIRECTORY_COLUMNS: &str = "path, created_at";
+const TRASH_COLUMNS: &str = "id, path, files, directories, size, deleted_at, deleted_by";
+
+/// 列表按插入顺序（rowid）返回，与 JSON 后端一致
+pub struct SqliteBackend {
+    conn: Arc<Mutex<Connection>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl SqliteBackend {
+    pub async fn open(db_path: PathBuf, clock: Arc<dyn Clock>) -> Result<Self> {
+        let conn = tokio::task::spawn_blocking(move || -> Result<Connection> {
+            let conn = Connection::open(&db_path)?;
+            // WAL 下读写互不阻塞；写入只追加日志，不重写整个文件
+            conn.pragma_update(None, "journal_mode", "WAL")?;
+            conn.pragma_update(None, "synchronous", "NORMAL")?;
+            conn.busy_timeout(std::time::Duration::from_secs(5))?;
+            migrate(&conn)?;
+            Ok(conn)
+        })
+        .await
+        .map_err(std::io::Error::other)??;
+
+        Ok(SqliteBackend {
+            conn: Arc::new(Mutex::new(conn)),
+            clock,
+        })
+    }
+
+    async fn call<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().unwrap_or_else(|e| e.into_inner());
+            f(&mut conn)
+        })
+        .await
+        .map_err(std::io::Error::other)?
+    }
+
+    /// 没有任何记录，可以导入旧数据
+    pub async fn is_empty(&self) -> Result<bool> {
+        self.call(|conn| {
+            for table in TABLES {
+                let sql = format!("SELECT EXISTS (SELECT 1 FROM {})", table);
+                if conn.query_row(&sql, [], |row| row.get::<_, bool>(0))? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        })
+        .await
+    }
+
+    /// 把 db.json 中的全部记录写入数据库，在同一个事务中完成；返回导入的记录数。
+    ///
+    /// 与加载 JSON 后端不同，文件无法解析时返回错误而不是当作空库，避免迁移时丢失数据。
+    pub async fn import_json(&self, json_path: &Path) -> Result<usize> {
+        let content = tokio::fs::read_to_string(json_path).await?;
+        let database: Database = serde_json::from_str(&content)?;
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            for file in &database.files {
+                insert_file(&tx, file)?;
+            }
+            for version in &database.versions {
+                insert_version(&tx, version)?;
+            }
+            for departure in &database.departures {
+                insert_departure(&tx, departure)?;
+            }
+            for sync in &database.syncs {
+                insert_sync(&tx, sync)?;
+            }
+            for device in &database.devices {
+                insert_device(&tx, device)?;
+            }
+            for comment in &database.comments {
+                insert_comment(&tx, comment)?;
+            }
+            for prefs in &database.notification_preferences {
+                upsert_preferences(&tx, prefs)?;
+            }
+            for share in &database.shares {
+                insert_share(&tx, share)?;
+            }
+            for access in &database.share_accesses {
+                insert_share_access(&tx, access)?;
+            }
+            for hold in &database.legal_holds {
+                insert_legal_hold(&tx, hold)?;
+            }
+            for rule in &database.lifecycle_rules {
+                insert_lifecycle_rule(&tx, rule)?;
+            }
+            for class in &database.rate_classes {
+                insert_rate_class(&tx, class)?;
+            }
+            for user in &database.users {
+                insert_user(&tx, user)?;
+            }
+            for mount in &database.mounts {
+                insert_mount(&tx, mount)?;
+            }
+            for directory in &database.directories {
+                insert_directory(&tx, directory)?;
+            }
+            for entry in &database.trash {
+                insert_trash_entry(&tx, entry)?;
+            }
+            backfill_versions(&tx)?;
+            tx.commit()?;
+
+            Ok(database.files.len()
+                + database.versions.len()
+                + database.departures.len()
+                + database.syncs.len()
+                + database.devices.len()
+                + database.comments.len()
+                + database.notification_preferences.len()
+                + database.shares.len()
+                + database.share_accesses.len()
+                + database.legal_holds.len()
+                + database.lifecycle_rules.len()
+                + database.rate_classes.len()
+                + database.users.len()
+                + database.mounts.len()
+                + database.directories.len()
+                + database.trash.len())
+        })
+        .await
+    }
+}
+
+fn migrate(conn: &Connection) -> Result<()> {
+    let version: i32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version > SCHEMA_VERSION {
+        return Err(Error::Config(format!(
+            "Database schema version {} is newer than this server supports ({})",
+            version, SCHEMA_VERSION
+        )));
+    }
+    conn.execute_batch(SCHEMA)?;
+    if version < 2 {
+        backfill_versions(conn)?;
+    }
+    // 版本 4 开始记录同步状态的变化历史
+    if version < 4 {
+        add_column(conn, "syncs", "history", "TEXT NOT NULL DEFAULT '[]'")?;
+    }
+    // 版本 5 开始记录设备持有的版本和冲突副本
+    if version < 5 {
+        add_column(conn, "syncs", "base_version", "INTEGER")?;
+        add_column(conn, "syncs", "conflict_path", "TEXT")?;
+    }
+    // 版本 6 开始记录文件和分享链接的创建者
+    if version < 6 {
+        add_column(conn, "files", "owner", "BLOB")?;
+        add_column(conn, "shares", "created_by", "BLOB")?;
+    }
+    // 版本 7 开始记录文件的删除和移动（file_departures 由 SCHEMA 创建），删除文件时保留历史版本
+    // 版本 8 开始记录显式创建的目录（directories 由 SCHEMA 创建）
+    // 版本 9 开始删除的文件先进入回收站（trash 由 SCHEMA 创建）
+    // 版本 10 开始记录设备的所有者和用户的配额
+    if version < 10 {
+        add_column(conn, "devices", "owner", "BLOB")?;
+        add_column(conn, "users", "quota_bytes", "INTEGER")?;
+    }
+    // 版本 11 开始记录生命周期规则的所有者
+    if version < 11 {
+        add_column(conn, "lifecycle_rules", "owner", "BLOB")?;
+    }
+    // 版本 12 开始记录不区分大小写比较用的路径键
+    if version < 12 {
+        add_column(conn, "files", "path_key", "TEXT")?;
+        backfill_path_keys(conn)?;
+    }
+    conn.execute_batch(MIGRATED_INDEXES)?;
+    conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+    Ok(())
+}
+
+/// 旧库的表已存在，CREATE TABLE IF NOT EXISTS 不会补上新列
+fn add_column(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<()> {
+    let exists: bool = conn.query_row(
+        &format!(
+            "SELECT EXISTS (SELECT 1 FROM pragma_table_info('{}') WHERE name = ?1)",
+            table
+        ),
+        [column],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        conn.execute_batch(&format!(
+            "ALTER TABLE {} ADD COLUMN {} {}",
+            table, column, definition
+        ))?;
+    }
+    Ok(())
+}
+
+/// path_key 由 case_key 计算，SQLite 的 lower() 只处理 ASCII，不能用它在 SQL 中补上
+fn backfill_path_keys(conn: &Connection) -> Result<()> {
+    let paths: Vec<(uuid::Uuid, String)> = conn
+        .prepare("SELECT id, path FROM files WHERE path_key IS NULL")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    let mut update = conn.prepare("UPDATE files SET path_key = ?2 WHERE id = ?1")?;
+    for (id, path) in paths {
+        update.execute(params![id, case_key(&path)])?;
+    }
+    Ok(())
+}
+
+/// 版本 2 开始记录历史版本，之前的文件只能补上当前版本
+fn backfill_versions(conn: &Connection) -> Result<()> {
+    conn.execute(
+        &format!(
+            "INSERT OR IGNORE INTO file_versions ({}) \
+             SELECT id, version, hash, size, updated_at FROM files",
+            VERSION_COLUMNS
+        ),
+        [],
+    )?;
+    Ok(())
+}
+
+/// 固定宽度的 RFC 3339 文本，按字符串比较即按时间先后比较
+fn ts(at: DateTime<Utc>) -> String {
+    at.to_rfc3339_opts(SecondsFormat::Nanos, false)
+}
+
+fn json_column<T: DeserializeOwned>(row: &Row, idx: usize) -> rusqlite::Result<Option<T>> {
+    row.get::<_, Option<String>>(idx)?
+        .map(|text| {
+            serde_json::from_str(&text)
+                .map_err(|e| rusqlite::Error::FromSqlConversionFailure(idx, Type::Text, e.into()))
+        })
+        .transpose()
+}
+
+fn file_from_row(row: &Row) -> rusqlite::Result<FileRecord> {
+    let fs_device: Option<i64> = row.get(10)?;
+    let fs_inode: Option<i64> = row.get(11)?;
+    Ok(FileRecord {
+        id: row.get(0)?,
+        path: row.get(1)?,
+        hash: row.get(2)?,
+        size: row.get(3)?,
+        version: row.get(4)?,
+        created_at: row.get(5)?,
+        updated_at: row.get(6)?,
+        media: json_column(row, 7)?,
+        metadata: json_column(row, 8)?.unwrap_or_default(),
+        last_accessed_at: row.get(9)?,
+        // 按位存储，超过 i64::MAX 的标识也能还原
+        fs_id: fs_device.zip(fs_inode).map(|(device, inode)| FsId {
+            device: device as u64,
+            inode: inode as u64,
+        }),
+        owner: row.get(12)?,
+    })
+}
+
+fn insert_file(conn: &Connection, file: &FileRecord) -> Result<()> {
+    conn.execute(
+        &format!(
+            "INSERT INTO files ({}, path_key) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+            FILE_COLUMNS
+        ),
+        params![
+            file.id,
+            file.path,
+            file.hash,
+            file.size,
+            file.version,
+            ts(file.created_at),
+            ts(file.updated_at),
+            file.media.as_ref().map(serde_json::to_string).transpose()?,
+            serde_json::to_string(&file.metadata)?,
+            file.last_accessed_at.map(ts),
+            file.fs_id.map(|f| f.device as i64),
+            file.fs_id.map(|f| f.inode as i64),
+            file.owner,
+            case_key(&file.path),
+        ],
+    )?;
+    Ok(())
+}
+
+/// 写回除 id 和创建时间外的所有列
+fn update_file_row(conn: &Connection, file: &FileRecord) -> Result<()> {
+    conn.execute(
+        "UPDATE files SET path = ?2, hash = ?3, size = ?4, version = ?5, updated_at = ?6, \
+         media = ?7, metadata = ?8, last_accessed_at = ?9, fs_device = ?10, fs_inode = ?11, \
+         owner = ?12, path_key = ?13 WHERE id = ?1",
+        params![
+            file.id,
+            file.path,
+            file.hash,
+            file.size,
+            file.version,
+            ts(file.updated_at),
+            file.media.as_ref().map(serde_json::to_string).transpose()?,
+            serde_json::to_string(&file.metadata)?,
+            file.last_accessed_at.map(ts),
+            file.fs_id.map(|f| f.device as i64),
+            file.fs_id.map(|f| f.inode as i64),
+            file.owner,
+            case_key(&file.path),
+        ],
+    )?;
+    Ok(())
+}
+
+fn version_from_row(row: &Row) -> rusqlite::Result<FileVersionRecord> {
+    Ok(FileVersionRecord {
+        file_id: row.get(0)?,
+        version: row.get(1)?,
+        hash: row.get(2)?,
+        size: row.get(3)?,
+        created_at: row.get(4)?,
+    })
+}
+
+fn insert_version(conn: &Connection, version: &FileVersionRecord) -> Result<()> {
+    conn.execute(
+        &format!(
+            "INSERT INTO file_versions ({}) VALUES (?1, ?2, ?3, ?4, ?5)",
+            VERSION_COLUMNS
+        ),
+        params![
+            version.file_id,
+            version.version,
+            version.hash,
+            version.size,
+            ts(version.created_at),
+        ],
+    )?;
+    Ok(())
+}
+
+fn departure_from_row(row: &Row) -> rusqlite::Result<FileDeparture> {
+    Ok(FileDeparture {
+        file_id: row.get(0)?,
+        path: row.get(1)?,
+        moved_to: row.get(2)?,
+        departed_at: row.get(3)?,
+        owner: row.get(4)?,
+    })
+}
+
+fn insert_departure(conn: &Connection, departure: &FileDeparture) -> Result<()> {
+    conn.execute(
+        &format!(
+            "INSERT INTO file_departures ({}) VALUES (?1, ?2, ?3, ?4, ?5)",
+            DEPARTURE_COLUMNS
+        ),
+        params![
+            departure.file_id,
+            departure.path,
+            departure.moved_to,
+            ts(departure.departed_at),
+            departure.owner,
+        ],
+    )?;
+    Ok(())
+}
+
+fn file_by_id(conn: &Connection, id: uuid::Uuid) -> Result<FileRecord> {
+    conn.query_row(
+        &format!("SELECT {} FROM files WHERE id = ?1", FILE_COLUMNS),
+        [id],
+        file_from_row,
+    )
+    .optional()?
+    .ok_or_else(|| Error::NotFound(PathBuf::from(format!("file:{}", id))))
+}
+
+fn file_exists(conn: &Connection, column: &str, value: impl rusqlite::ToSql) -> Result<bool> {
+    let sql = format!("SELECT EXISTS (SELECT 1 FROM files WHERE {} = ?1)", column);
+    Ok(conn.query_row(&sql, [value], |row| row.get(0))?)
+}
+
+fn sync_from_row(row: &Row) -> rusqlite::Result<SyncRecord> {
+    let status: String = row.get(3)?;
+    Ok(SyncRecord {
+        id: row.get(0)?,
+        device_id: row.get(1)?,
+        file_id: row.get(2)?,
+        sync_status: SyncStatus::parse(&status).ok_or_else(|| {
+            rusqlite::Error::FromSqlConversionFailure(
+                3,
+                Type::Text,
+                format!("unknown sync status {}", status).into(),
+            )
+        })?,
+        last_sync_at: row.get(4)?,
+        history: json_column(row, 5)?.unwrap_or_default(),
+        base_version: row.get(6)?,
+        conflict_path: row.get(7)?,
+    })
+}
+
+fn insert_sync(conn: &Connection, sync: &SyncRecord) -> Result<()> {
+    conn.execute(
+        &format!(
+            "INSERT INTO syncs ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            SYNC_COLUMNS
+        ),
+        params![
+            sync.id,
+            sync.device_id,
+            sync.file_id,
+            sync.sync_status.as_str(),
+            ts(sync.last_sync_at),
+            serde_json::to_string(&sync.history)?,
+            sync.base_version,
+            sync.conflict_path,
+        ],
+    )?;
+    Ok(())
+}
+
+fn update_sync(conn: &Connection, sync: &SyncRecord) -> Result<()> {
+    conn.execute(
+        "UPDATE syncs SET sync_status = ?2, last_sync_at = ?3, history = ?4 WHERE id = ?1",
+        params![
+            sync.id,
+            sync.sync_status.as_str(),
+            ts(sync.last_sync_at),
+            serde_json::to_string(&sync.history)?,
+        ],
+    )?;
+    Ok(())
+}
+
+fn comment_from_row(row: &Row) -> rusqlite::Result<CommentRecord> {
+    Ok(CommentRecord {
+        id: row.get(0)?,
+        file_id: row.get(1)?,
+        author: row.get(2)?,
+        text: row.get(3)?,
+        created_at: row.get(4)?,
+    })
+}
+
+fn insert_comment(conn: &Connection, comment: &CommentRecord) -> Result<()> {
+    conn.execute(
+        &format!(
+            "INSERT INTO comments ({}) VALUES (?1, ?2, ?3, ?4, ?5)",
+            COMMENT_COLUMNS
+        ),
+        params![
+            comment.id,
+            comment.file_id,
+            comment.author,
+            comment.text,
+            ts(comment.created_at),
+        ],
+    )?;
+    Ok(())
+}
+
+fn device_from_row(row: &Row) -> rusqlite::Result<DeviceRecord> {
+    Ok(DeviceRecord {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        last_seen: row.get(2)?,
+        owner: row.get(3)?,
+    })
+}
+
+fn insert_device(conn: &Connection, device: &DeviceRecord) -> Result<()> {
+    conn.execute(
+        &format!(
+            "INSERT INTO devices ({}) VALUES (?1, ?2, ?3, ?4)",
+            DEVICE_COLUMNS
+        ),
+        params![device.id, device.name, ts(device.last_seen), device.owner],
+    )?;
+    Ok(())
+}
+
+fn user_from_row(row: &Row) -> rusqlite::Result<UserRecord> {
+    Ok(UserRecord {
+        id: row.get(0)?,
+        username: row.get(1)?,
+        password_hash: row.get(2)?,
+        created_at: row.get(3)?,
+        quota_bytes: row.get(4)?,
+    })
+}
+
+fn insert_user(conn: &Connection, user: &UserRecord) -> Result<()> {
+    conn.execute(
+        &format!(
+            "INSERT INTO users ({}) VALUES (?1, ?2, ?3, ?4, ?5)",
+            USER_COLUMNS
+        ),
+        params![
+            user.id,
+            user.username,
+            user.password_hash,
+            ts(user.created_at),
+            user.quota_bytes
+        ],
+    )?;
+    Ok(())
+}
+
+fn preferences_from_row(row: &Row) -> rusqlite::Result<NotificationPreferences> {
+    Ok(NotificationPreferences {
+        user: row.get(0)?,
+        rules: json_column(row, 1)?.unwrap_or_default(),
+        updated_at: row.get(2)?,
+    })
+}
+
+fn upsert_preferences(conn: &Connection, prefs: &NotificationPreferences) -> Result<()> {
+    conn.execute(
+        "INSERT INTO notification_preferences (user, rules, updated_at) VALUES (?1, ?2, ?3) \
+         ON CONFLICT (user) DO UPDATE SET rules = excluded.rules, updated_at = excluded.updated_at",
+        params![
+            prefs.user,
+            serde_json::to_string(&prefs.rules)?,
+            ts(prefs.updated_at)
+        ],
+    )?;
+    Ok(())
+}
+
+fn share_from_row(row: &Row) -> rusqlite::Result<ShareRecord> {
+    Ok(ShareRecord {
+        id: row.get(0)?,
+        path: row.get(1)?,
+        created_at: row.get(2)?,
+        expires_at: row.get(3)?,
+        limits: ShareLimits {
+            max_downloads: row.get(4)?,
+            max_bytes: row.get(5)?,
+            max_concurrent: row.get(6)?,
+        },
+        created_by: row.get(7)?,
+    })
+}
+
+fn insert_share(conn: &Connection, share: &ShareRecord) -> Result<()> {
+    conn.execute(
+        &format!(
+            "INSERT INTO shares ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            SHARE_COLUMNS
+        ),
+        params![
+            share.id,
+            share.path,
+            ts(share.created_at),
+            share.expires_at.map(ts),
+            share.limits.max_downloads,
+            share.limits.max_bytes,
+            share.limits.max_concurrent,
+            share.created_by,
+        ],
+    )?;
+    Ok(())
+}
+
+fn share_access_from_row(row: &Row) -> rusqlite::Result<ShareAccessRecord> {
+    Ok(ShareAccessRecord {
+        share_id: row.get(0)?,
+        accessed_at: row.get(1)?,
+        ip: row.get(2)?,
+        bytes: row.get(3)?,
+    })
+}
+
+fn insert_share_access(conn: &Connection, access: &ShareAccessRecord) -> Result<()> {
+    conn.execute(
+        "INSERT INTO share_accesses (share_id, accessed_at, ip, bytes) VALUES (?1, ?2, ?3, ?4)",
+        params![
+            access.share_id,
+            ts(access.accessed_at),
+            access.ip,
+            access.bytes
+        ],
+    )?;
+    Ok(())
+}
+
+fn legal_hold_from_row(row: &Row) -> rusqlite::Result<LegalHold> {
+    Ok(LegalHold {
+        path: row.get(0)?,
+        reason: row.get(1)?,
+        created_at: row.get(2)?,
+    })
+}
+
+fn insert_legal_hold(conn: &Connection, hold: &LegalHold) -> Result<()> {
+    conn.execute(
+        "INSERT INTO legal_holds (path, reason, created_at) VALUES (?1, ?2, ?3)",
+        params![hold.path, hold.reason, ts(hold.created_at)],
+    )?;
+    Ok(())
+}
+
+/// 保留通常只有几条，取出后用与 JSON 后端相同的规则匹配
+fn legal_holds(conn: &Connection) -> Result<Vec<LegalHold>> {
+    let mut stmt =
+        conn.prepare("SELECT path, reason, created_at FROM legal_holds ORDER BY rowid")?;
+    let holds = stmt
+        .query_map([], legal_hold_from_row)?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(holds)
+}
+
+fn ensure_not_held(conn: &Connection, path: &str) -> Result<()> {
+    match legal_holds(conn)?.into_iter().find(|h| h.covers(path)) {
+        Some(hold) => Err(Error::Held(hold.path)),
+        None => Ok(()),
+    }
+}
+
+fn lifecycle_rule_from_row(row: &Row) -> rusqlite::Result<LifecycleRule> {
+    Ok(LifecycleRule {
+        id: row.get(0)?,
+        folder: row.get(1)?,
+        delete_after_days: row.get(2)?,
+        archive_after_days: row.get(3)?,
+        archive_to: row.get(4)?,
+        created_at: row.get(5)?,
+        owner: row.get(6)?,
+    })
+}
+
+fn insert_lifecycle_rule(conn: &Connection, rule: &LifecycleRule) -> Result<()> {
+    conn.execute(
+        &format!(
+            "INSERT INTO lifecycle_rules ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            LIFECYCLE_COLUMNS
+        ),
+        params![
+            rule.id,
+            rule.folder,
+            rule.delete_after_days,
+            rule.archive_after_days,
+            rule.archive_to,
+            ts(rule.created_at),
+            rule.owner,
+        ],
+    )?;
+    Ok(())
+}
+
+fn rate_class_from_row(row: &Row) -> rusqlite::Result<RateClass> {
+    Ok(RateClass {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        device_id: row.get(2)?,
+        start_hour: row.get(3)?,
+        end_hour: row.get(4)?,
+        upload_bytes_per_sec: row.get(5)?,
+        download_bytes_per_sec: row.get(6)?,
+        created_at: row.get(7)?,
+    })
+}
+
+fn insert_rate_class(conn: &Connection, class: &RateClass) -> Result<()> {
+    conn.execute(
+        &format!(
+            "INSERT INTO rate_classes ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            RATE_CLASS_COLUMNS
+        ),
+        params![
+            class.id,
+            class.name,
+            class.device_id,
+            class.start_hour,
+            class.end_hour,
+            class.upload_bytes_per_sec,
+            class.download_bytes_per_sec,
+            ts(class.created_at),
+        ],
+    )?;
+    Ok(())
+}
+
+fn mount_from_row(row: &Row) -> rusqlite::Result<MountRecord> {
+    Ok(MountRecord {
+        id: row.get(0)?,
+        path: row.get(1)?,
+        url: row.get(2)?,
+        remote_path: row.get(3)?,
+        token: row.get(4)?,
+        created_at: row.get(5)?,
+    })
+}
+
+fn insert_mount(conn: &Connection, mount: &MountRecord) -> Result<()> {
+    conn.execute(
+        &format!(
+            "INSERT INTO mounts ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            MOUNT_COLUMNS
+        ),
+        params![
+            mount.id,
+            mount.path,
+            mount.url,
+            mount.remote_path,
+            mount.token,
+            ts(mount.created_at),
+        ],
+    )?;
+    Ok(())
+}
+
+fn directory_from_row(row: &Row) -> rusqlite::Result<DirectoryRecord> {
+    Ok(DirectoryRecord {
+        path: row.get(0)?,
+        created_at: row.get(1)?,
+    })
+}
+
+fn insert_directory(conn: &Connection, directory: &DirectoryRecord) -> Result<()> {
+    conn.execute(
+        &format!(
+            "INSERT INTO directories ({}) VALUES (?1, ?2)",
+            DIRECTORY_COLUMNS
+        ),
+        params![directory.path, ts(directory.created_at)],
+    )?;
+    Ok(())
+}
+
+fn directories(conn: &Connection) -> Result<Vec<DirectoryRecord>> {
+    query_all(
+        conn,
+        &format!(
+            "SELECT {} FROM directories ORDER BY rowid",
+            DIRECTORY_COLUMNS
+        ),
+        [],
+        directory_from_row,
+    )
+}
+
+// 记录和目录以 JSON 文本整体保存，只在恢复时整体读回
+fn trash_entry_from_row(row: &Row) -> rusqlite::Result<TrashEntry> {
+    Ok(TrashEntry {
+        id: row.get(0)?,
+        path: row.get(1)?,
+        files: json_column(row, 2)?.unwrap_or_default(),
+        directories: json_column(row, 3)?.unwrap_or_default(),
+        size: row.get(4)?,
+        deleted_at: row.get(5)?,
+        deleted_by: row.get(6)?,
+    })
+}
+
+fn insert_trash_entry(conn: &Connection, entry: &TrashEntry) -> Result<()> {
+    conn.execute(
+        &format!(
+            "INSERT INTO trash ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            TRASH_COLUMNS
+        ),
+        params![
+            entry.id,
+            entry.path,
+            serde_json::to_string(&entry.files)?,
+            serde_json::to_string(&entry.directories)?,
+            entry.size,
+            ts(entry.deleted_at),
+            entry.deleted_by,
+        ],
+    )?;
+    Ok(())
+}
+
+// 整张表，按插入顺序
+fn table<T>(
+    conn: &Connection,
+    table: &str,
+    columns: &str,
+    map: impl FnMut(&Row) -> rusqlite::Result<T>,
+) -> Result<Vec<T>> {
+    let sql = format!("SELECT {} FROM {} ORDER BY rowid", columns, table);
+    query_all(conn, &sql, [], map)
+}
+
+// 在一个读事务中读出所有表，与 import_json 写入的内容一一对应
+fn load_database(conn: &mut Connection) -> Result<Database> {
+    let tx = conn.transaction()?;
+    let database = Database {
+        files: table(&tx, "files", FILE_COLUMNS, file_from_row)?,
+        syncs: table(&tx, "syncs", SYNC_COLUMNS, sync_from_row)?,
+        devices: table(&tx, "devices", DEVICE_COLUMNS, device_from_row)?,
+        comments: table(&tx, "comments", COMMENT_COLUMNS, comment_from_row)?,
+        notification_preferences: table(
+            &tx,
+            "notification_preferences",
+            "user, rules, updated_at",
+            preferences_from_row,
+        )?,
+        shares: table(&tx, "shares", SHARE_COLUMNS, share_from_row)?,
+        share_accesses: table(
+            &tx,
+            "share_accesses",
+            "share_id, accessed_at, ip, bytes",
+            share_access_from_row,
+        )?,
+        legal_holds: legal_holds(&tx)?,
+        lifecycle_rules: table(
+            &tx,
+            "lifecycle_rules",
+            LIFECYCLE_COLUMNS,
+            lifecycle_rule_from_row,
+        )?,
+        rate_classes: table(&tx, "rate_classes", RATE_CLASS_COLUMNS, rate_class_from_row)?,
+        users: table(&tx, "users", USER_COLUMNS, user_from_row)?,
+        versions: table(&tx, "file_versions", VERSION_COLUMNS, version_from_row)?,
+        mounts: table(&tx, "mounts", MOUNT_COLUMNS, mount_from_row)?,
+        departures: table(
+            &tx,
+            "file_departures",
+            DEPARTURE_COLUMNS,
+            departure_from_row,
+        )?,
+        directories: directories(&tx)?,
+        trash: table(&tx, "trash", TRASH_COLUMNS, trash_entry_from_row)?,
+    };
+    tx.commit()?;
+    Ok(database)
+}
+
+fn query_all<T>(
+    conn: &Connection,
+    sql: &str,
+    params: impl rusqlite::Params,
+    map: impl FnMut(&Row) -> rusqlite::Result<T>,
+) -> Result<Vec<T>> {
+    let mut stmt = conn.prepare(sql)?;
+    let rows = stmt
+        .query_map(params, map)?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(rows)
+}
+
+/// 删除 id 对应的一行，不存在时返回 NotFound(kind:id)
+fn delete_by_id(conn: &Connection, table: &str, kind: &str, id: uuid::Uuid) -> Result<()> {
+    let sql = format!("DELETE FROM {} WHERE id = ?1", table);
+    if conn.execute(&sql, [id])? == 0 {
+        return Err(Error::NotFound(PathBuf::from(format!("{}:{}", kind, id))));
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl RepositoryBackend for SqliteBackend {
+    fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    // 没有常驻内存的数据可共享，每次读出全部表，开销与数据量成正比
+    async fn snapshot(&self) -> Result<Snapshot> {
+        let database = self.call(load_database).await?;
+        Ok(Snapshot::new(Arc::new(database)))
+    }
+
+    async fn create_file(&self, new_file: NewFileRecord) -> Result<FileRecord> {
+        let record = FileRecord::new(new_file, self.clock.now());
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            if file_exists(&tx, "path", &record.path)? {
+                return Err(Error::AlreadyExists(PathBuf::from(&record.path)));
+            }
+            ensure_not_held(&tx, &record.path)?;
+            insert_file(&tx, &record)?;
+            insert_version(&tx, &FileVersionRecord::of(&record))?;
+            tx.commit()?;
+            Ok(record)
+        })
+        .await
+    }
+
+    async fn get_file_by_path(&self, path: &str) -> Result<FileRecord> {
+        let path = path.to_string();
+        self.call(move |conn| {
+            conn.query_row(
+                &format!("SELECT {} FROM files WHERE path = ?1", FILE_COLUMNS),
+                [&path],
+                file_from_row,
+            )
+            .optional()?
+            .ok_or_else(|| Error::NotFound(PathBuf::from(path)))
+        })
+        .await
+    }
+
+    async fn get_file_by_id(&self, id: uuid::Uuid) -> Result<FileRecord> {
+        self.call(move |conn| file_by_id(conn, id)).await
+    }
+
+    async fn update_file(
+        &self,
+        id: uuid::Uuid,
+        hash: Option<String>,
+        size: u64,
+    ) -> Result<FileRecord> {
+        let now = self.clock.now();
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let mut file = file_by_id(&tx, id)?;
+            ensure_not_held(&tx, &file.path)?;
+            file.hash = hash;
+            file.size = size;
+            file.increment_version(now);
+            update_file_row(&tx, &file)?;
+            insert_version(&tx, &FileVersionRecord::of(&file))?;
+            tx.commit()?;
+            Ok(file)
+        })
+        .await
+    }
+
+    async fn set_file_media(
+        &self,
+        id: uuid::Uuid,
+        media: Option<MediaMetadata>,
+    ) -> Result<FileRecord> {
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let mut file = file_by_id(&tx, id)?;
+            file.media = media;
+            update_file_row(&tx, &file)?;
+            tx.commit()?;
+            Ok(file)
+        })
+        .await
+    }
+
+    async fn update_file_metadata(
+        &self,
+        id: uuid::Uuid,
+        patch: BTreeMap<String, Option<String>>,
+    ) -> Result<FileRecord> {
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let mut file = file_by_id(&tx, id)?;
+            for (key, value) in patch {
+                match value {
+                    Some(value) => file.metadata.insert(key, value),
+                    None => file.metadata.remove(&key),
+                };
+            }
+            update_file_row(&tx, &file)?;
+            tx.commit()?;
+            Ok(file)
+        })
+        .await
+    }
+
+    async fn set_file_fs_id(&self, id: uuid::Uuid, fs_id: Option<FsId>) -> Result<FileRecord> {
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let mut file = file_by_id(&tx, id)?;
+            file.fs_id = fs_id;
+            update_file_row(&tx, &file)?;
+            tx.commit()?;
+            Ok(file)
+        })
+        .await
+    }
+
+    async fn set_file_owner(
+        &self,
+        id: uuid::Uuid,
+        owner: Option<uuid::Uuid>,
+    ) -> Result<FileRecord> {
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let mut file = file_by_id(&tx, id)?;
+            file.owner = owner;
+            update_file_row(&tx, &file)?;
+            tx.commit()?;
+            Ok(file)
+        })
+        .await
+    }
+
+    async fn find_file_by_fs_id(&self, fs_id: FsId) -> Option<FileRecord> {
+        self.call(move |conn| {
+            Ok(conn
+                .query_row(
+                    &format!(
+                        "SELECT {} FROM files WHERE fs_device = ?1 AND fs_inode = ?2",
+                        FILE_COLUMNS
+                    ),
+                    params![fs_id.device as i64, fs_id.inode as i64],
+                    file_from_row,
+                )
+                .optional()?)
+        })
+        .await
+        .ok()
+        .flatten()
+    }
+
+    async fn delete_file(&self, id: uuid::Uuid) -> Result<()> {
+        let now = self.clock.now();
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let file = file_by_id(&tx, id)?;
+            ensure_not_held(&tx, &file.path)?;
+            delete_by_id(&tx, "files", "file", id)?;
+            // 同时删除相关的同步记录和评论，历史版本保留
+            tx.execute("DELETE FROM syncs WHERE file_id = ?1", [id])?;
+            tx.execute("DELETE FROM comments WHERE file_id = ?1", [id])?;
+            insert_departure(
+                &tx,
+                &FileDeparture {
+                    file_id: id,
+                    path: file.path,
+                    moved_to: None,
+                    departed_at: now,
+                    owner: file.owner,
+                },
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn record_file_accesses(
+        &self,
+        accesses: HashMap<String, chrono::DateTime<chrono::Utc>>,
+    ) -> Result<usize> {
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let mut updated = 0;
+            {
+                let mut stmt = tx.prepare(
+                    "UPDATE files SET last_accessed_at = ?2 \
+                     WHERE path = ?1 AND (last_accessed_at IS NULL OR last_accessed_at < ?2)",
+                )?;
+                for (path, at) in &accesses {
+                    updated += stmt.execute(params![path, ts(*at)])?;
+                }
+            }
+            tx.commit()?;
+            Ok(updated)
+        })
+        .await
+    }
+
+    async fn move_file(&self, id: uuid::Uuid, new_path: &str) -> Result<FileRecord> {
+        let new_path = new_path.to_string();
+        let now = self.clock.now();
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            if file_exists(&tx, "path", &new_path)? {
+                return Err(Error::AlreadyExists(PathBuf::from(new_path)));
+            }
+            ensure_not_held(&tx, &new_path)?;
+            let mut file = file_by_id(&tx, id)?;
+            ensure_not_held(&tx, &file.path)?;
+
+            let departure = FileDeparture {
+                file_id: id,
+                path: std::mem::replace(&mut file.path, new_path.clone()),
+                moved_to: Some(new_path),
+                departed_at: now,
+                owner: file.owner,
+            };
+            file.updated_at = now;
+            update_file_row(&tx, &file)?;
+            insert_departure(&tx, &departure)?;
+            tx.commit()?;
+            Ok(file)
+        })
+        .await
+    }
+
+    async fn list_files(&self) -> Result<Vec<FileRecord>> {
+        self.call(|conn| {
+            query_all(
+                conn,
+                &format!("SELECT {} FROM files ORDER BY rowid", FILE_COLUMNS),
+                [],
+                file_from_row,
+            )
+        })
+        .await
+    }
+
+    async fn total_size(&self) -> Result<u64> {
+        self.call(|conn| {
+            Ok(
+                conn.query_row("SELECT COALESCE(SUM(size), 0) FROM files", [], |row| {
+                    row.get(0)
+                })?,
+            )
+        })
+        .await
+    }
+
+    // 两个条件分别走 owner 和 path 上的索引；"0" 紧跟在 "/" 之后，区间正好是 root 下的路径
+    async fn owner_usage(&self, owner: uuid::Uuid, root: Option<&str>) -> Result<(usize, u64)> {
+        let root = root.map(str::to_string);
+        self.call(move |conn| {
+            let usage = |sql: &str, params: &[&dyn rusqlite::ToSql]| {
+                conn.query_row(sql, params, |row| {
+                    Ok((row.get::<_, usize>(0)?, row.get::<_, u64>(1)?))
+                })
+            };
+            let (mut files, mut bytes) = usage(
+                "SELECT COUNT(*), COALESCE(SUM(size), 0) FROM files WHERE owner = ?1",
+                &[&owner],
+            )?;
+            if let Some(root) = root {
+                let (unowned, unowned_bytes) = usage(
+                    "SELECT COUNT(*), COALESCE(SUM(size), 0) FROM files \
+                     WHERE owner IS NULL AND path >= ?1 AND path < ?2",
+                    &[&format!("{}/", root), &format!("{}0", root)],
+                )?;
+                files += unowned;
+                bytes += unowned_bytes;
+            }
+            Ok((files, bytes))
+        })
+        .await
+    }
+
+    // 文件本身按 path_key 精确查找，下级文件按 path_key 的区间查找，都走 files_path_key 索引；
+    // 截取的前缀再按 case_key 核对一次，小写后字符数会变的写法不算
+    async fn path_spellings(&self, path: &str) -> Result<Vec<String>> {
+        let key = case_key(path);
+        let chars = path.chars().count();
+        self.call(move |conn| {
+            let mut statement = conn.prepare(
+                "SELECT DISTINCT substr(path, 1, ?2) FROM files \
+                 WHERE path_key = ?1 OR (path_key >= ?3 AND path_key < ?4) ORDER BY 1",
+            )?;
+            let spellings = statement
+                .query_map(
+                    params![key, chars, format!("{}/", key), format!("{}0", key)],
+                    |row| row.get::<_, String>(0),
+                )?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            Ok(spellings
+                .into_iter()
+                .filter(|spelling| case_key(spelling) == key)
+                .collect())
+        })
+        .await
+    }
+
+    async fn list_file_versions(&self, file_id: uuid::Uuid) -> Result<Vec<FileVersionRecord>> {
+        self.call(move |conn| {
+            query_all(
+                conn,
+                &format!(
+                    "SELECT {} FROM file_versions WHERE file_id = ?1 ORDER BY version",
+                    VERSION_COLUMNS
+                ),
+                [file_id],
+                version_from_row,
+            )
+        })
+        .await
+    }
+
+    async fn list_departures(&self) -> Result<Vec<FileDeparture>> {
+        self.call(|conn| {
+            query_all(
+                conn,
+                &format!(
+                    "SELECT {} FROM file_departures ORDER BY rowid",
+                    DEPARTURE_COLUMNS
+                ),
+                [],
+                departure_from_row,
+            )
+        })
+        .await
+    }
+
+    async fn purge_file_history(&self, file_id: uuid::Uuid) -> Result<()> {
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute("DELETE FROM file_versions WHERE file_id = ?1", [file_id])?;
+            tx.execute("DELETE FROM file_departures WHERE file_id = ?1", [file_id])?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn create_sync(&self, new_sync: NewSyncRecord) -> Result<SyncRecord> {
+        let record = SyncRecord::new(new_sync, self.clock.now())?;
+        self.call(move |conn| {
+            // 验证 file_id 存在
+            if !file_exists(conn, "id", record.file_id)? {
+                return Err(Error::NotFound(PathBuf::from(format!(
+                    "file:{}",
+                    record.file_id
+                ))));
+            }
+            insert_sync(conn, &record)?;
+            Ok(record)
+        })
+        .await
+    }
+
+    async fn update_sync_status(&self, id: uuid::Uuid, status: SyncStatus) -> Result<SyncRecord> {
+        let now = self.clock.now();
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let mut sync = tx
+                .query_row(
+                    &format!("SELECT {} FROM syncs WHERE id = ?1", SYNC_COLUMNS),
+                    [id],
+                    sync_from_row,
+                )
+                .optional()?
+                .ok_or_else(|| Error::NotFound(PathBuf::from(format!("sync:{}", id))))?;
+            sync.transition(status, now)?;
+            update_sync(&tx, &sync)?;
+            tx.commit()?;
+            Ok(sync)
+        })
+        .await
+    }
+
+    async fn expire_stuck_syncs(
+        &self,
+        stuck_since: chrono::DateTime<chrono::Utc>,
+    ) -> Result<usize> {
+        let now = self.clock.now();
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let mut stuck = query_all(
+                &tx,
+                &format!(
+                    "SELECT {} FROM syncs WHERE sync_status = ?1 AND last_sync_at < ?2",
+                    SYNC_COLUMNS
+                ),
+                params![SyncStatus::Syncing.as_str(), ts(stuck_since)],
+                sync_from_row,
+            )?;
+            for sync in &mut stuck {
+                sync.transition(SyncStatus::Failed, now)?;
+                update_sync(&tx, sync)?;
+            }
+            tx.commit()?;
+            Ok(stuck.len())
+        })
+        .await
+    }
+
+    async fn list_syncs_by_file(&self, file_id: uuid::Uuid) -> Result<Vec<SyncRecord>> {
+        self.call(move |conn| {
+            query_all(
+                conn,
+                &format!(
+                    "SELECT {} FROM syncs WHERE file_id = ?1 ORDER BY rowid",
+                    SYNC_COLUMNS
+                ),
+                [file_id],
+                sync_from_row,
+            )
+        })
+        .await
+    }
+
+    async fn count_failed_syncs(
+        &self,
+        device_id: uuid::Uuid,
+        since: chrono::DateTime<chrono::Utc>,
+    ) -> Result<usize> {
+        self.call(move |conn| {
+            Ok(conn.query_row(
+                "SELECT COUNT(*) FROM syncs \
+                 WHERE device_id = ?1 AND sync_status = ?2 AND last_sync_at >= ?3",
+                params![device_id, SyncStatus::Failed.as_str(), ts(since)],
+                |row| row.get(0),
+            )?)
+        })
+        .await
+    }
+
+    async fn list_conflicts(&self) -> Result<Vec<SyncRecord>> {
+        self.call(move |conn| {
+            query_all(
+                conn,
+                &format!(
+                    "SELECT {} FROM syncs WHERE sync_status = ?1 ORDER BY rowid",
+                    SYNC_COLUMNS
+                ),
+                [SyncStatus::Conflict.as_str()],
+                sync_from_row,
+            )
+        })
+        .await
+    }
+
+    async fn create_comment(&self, new_comment: NewCommentRecord) -> Result<CommentRecord> {
+        let record = CommentRecord::new(new_comment, self.clock.now());
+        self.call(move |conn| {
+            if !file_exists(conn, "id", record.file_id)? {
+                return Err(Error::NotFound(PathBuf::from(format!(
+                    "file:{}",
+                    record.file_id
+                ))));
+            }
+            insert_comment(conn, &record)?;
+            Ok(record)
+        })
+        .await
+    }
+
+    async fn list_comments_by_file(&self, file_id: uuid::Uuid) -> Result<Vec<CommentRecord>> {
+        self.call(move |conn| {
+            query_all(
+                conn,
+                &format!(
+                    "SELECT {} FROM comments WHERE file_id = ?1 ORDER BY rowid",
+                    COMMENT_COLUMNS
+                ),
+                [file_id],
+                comment_from_row,
+            )
+        })
+        .await
+    }
+
+    async fn list_comments(&self) -> Result<Vec<CommentRecord>> {
+        self.call(|conn| {
+            query_all(
+                conn,
+                &format!("SELECT {} FROM comments ORDER BY rowid", COMMENT_COLUMNS),
+                [],
+                comment_from_row,
+            )
+        })
+        .await
+    }
+
+    async fn delete_comment(&self, id: uuid::Uuid) -> Result<()> {
+        self.call(move |conn| delete_by_id(conn, "comments", "comment", id))
+            .await
+    }
+
+    async fn create_device(&self, new_device: NewDeviceRecord) -> Result<DeviceRecord> {
+        let record = DeviceRecord::new(new_device, self.clock.now());
+        self.call(move |conn| {
+            insert_device(conn, &record)?;
+            Ok(record)
+        })
+        .await
+    }
+
+    async fn get_device(&self, id: uuid::Uuid) -> Result<DeviceRecord> {
+        self.call(move |conn| {
+            conn.query_row(
+                &format!("SELECT {} FROM devices WHERE id = ?1", DEVICE_COLUMNS),
+                [id],
+                device_from_row,
+            )
+            .optional()?
+            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("device:{}", id))))
+        })
+        .await
+    }
+
+    async fn update_device_last_seen(&self, id: uuid::Uuid) -> Result<DeviceRecord> {
+        let now = self.clock.now();
+        self.call(move |conn| {
+            let changed = conn.execute(
+                "UPDATE devices SET last_seen = ?2 WHERE id = ?1",
+                params![id, ts(now)],
+            )?;
+            if changed == 0 {
+                return Err(Error::NotFound(PathBuf::from(format!("device:{}", id))));
+            }
+            Ok(conn.query_row(
+                &format!("SELECT {} FROM devices WHERE id = ?1", DEVICE_COLUMNS),
+                [id],
+                device_from_row,
+            )?)
+        })
+        .await
+    }
+
+    async fn list_devices(&self) -> Result<Vec<DeviceRecord>> {
+        self.call(|conn| {
+            query_all(
+                conn,
+                &format!("SELECT {} FROM devices ORDER BY rowid", DEVICE_COLUMNS),
+                [],
+                device_from_row,
+            )
+        })
+        .await
+    }
+
+    async fn set_device_owner(
+        &self,
+        id: uuid::Uuid,
+        owner: Option<uuid::Uuid>,
+    ) -> Result<DeviceRecord> {
+        self.call(move |conn| {
+            let changed = conn.execute(
+                "UPDATE devices SET owner = ?2 WHERE id = ?1",
+                params![id, owner],
+            )?;
+            if changed == 0 {
+                return Err(Error::NotFound(PathBuf::from(format!("device:{}", id))));
+            }
+            Ok(conn.query_row(
+                &format!("SELECT {} FROM devices WHERE id = ?1", DEVICE_COLUMNS),
+                [id],
+                device_from_row,
+            )?)
+        })
+        .await
+    }
+
+    async fn create_user(&self, new_user: NewUserRecord) -> Result<UserRecord> {
+        let record = UserRecord::new(new_user, self.clock.now());
+        self.call(move |conn| {
+            // username 列按 NOCASE 比较，与 JSON 后端的 eq_ignore_ascii_case 一致
+            let taken: bool = conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM users WHERE username = ?1)",
+                [&record.username],
+                |row| row.get(0),
+            )?;
+            if taken {
+                return Err(Error::AlreadyExists(PathBuf::from(format!(
+                    "user:{}",
+                    record.username
+                ))));
+            }
+            insert_user(conn, &record)?;
+            Ok(record)
+        })
+        .await
+    }
+
+    async fn find_user_by_name(&self, username: &str) -> Option<UserRecord> {
+        let username = username.to_string();
+        self.call(move |conn| {
+            Ok(conn
+                .query_row(
+                    &format!("SELECT {} FROM users WHERE username = ?1", USER_COLUMNS),
+                    [username],
+                    user_from_row,
+                )
+                .optional()?)
+        })
+        .await
+        .ok()
+        .flatten()
+    }
+
+    async fn get_user(&self, id: uuid::Uuid) -> Result<UserRecord> {
+        self.call(move |conn| {
+            conn.query_row(
+                &format!("SELECT {} FROM users WHERE id = ?1", USER_COLUMNS),
+                [id],
+                user_from_row,
+            )
+            .optional()?
+            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("user:{}", id))))
+        })
+        .await
+    }
+
+    async fn set_user_quota(&self, id: uuid::Uuid, quota_bytes: Option<u64>) -> Result<UserRecord> {
+        self.call(move |conn| {
+            let changed = conn.execute(
+                "UPDATE users SET quota_bytes = ?2 WHERE id = ?1",
+                params![id, quota_bytes],
+            )?;
+            if changed == 0 {
+                return Err(Error::NotFound(PathBuf::from(format!("user:{}", id))));
+            }
+            Ok(conn.query_row(
+                &format!("SELECT {} FROM users WHERE id = ?1", USER_COLUMNS),
+                [id],
+                user_from_row,
+            )?)
+        })
+        .await
+    }
+
+    async fn delete_user(&self, id: uuid::Uuid) -> Result<()> {
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let username: String = tx
+                .query_row("SELECT username FROM users WHERE id = ?1", [id], |row| {
+                    row.get(0)
+                })
+                .optional()?
+                .ok_or_else(|| Error::NotFound(PathBuf::from(format!("user:{}", id))))?;
+            tx.execute("DELETE FROM users WHERE id = ?1", [id])?;
+            tx.execute(
+                "DELETE FROM notification_preferences WHERE user = ?1",
+                [&username],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_notification_preferences(&self, user: &str) -> Result<NotificationPreferences> {
+        let user = user.to_string();
+        let now = self.clock.now();
+        self.call(move |conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT user, rules, updated_at FROM notification_preferences WHERE user = ?1",
+                    [&user],
+                    preferences_from_row,
+                )
+                .optional()?
+                .unwrap_or(NotificationPreferences {
+                    user,
+                    rules: Vec::new(),
+                    updated_at: now,
+                }))
+        })
+        .await
+    }
+
+    async fn set_notification_preferences(
+        &self,
+        user: &str,
+        rules: Vec<NotificationRule>,
+    ) -> Result<NotificationPreferences> {
+        let prefs = NotificationPreferences {
+            user: user.to_string(),
+            rules,
+            updated_at: self.clock.now(),
+        };
+        self.call(move |conn| {
+            upsert_preferences(conn, &prefs)?;
+            Ok(prefs)
+        })
+        .await
+    }
+
+    async fn list_notification_preferences(&self) -> Result<Vec<NotificationPreferences>> {
+        self.call(|conn| {
+            query_all(
+                conn,
+                "SELECT user, rules, updated_at FROM notification_preferences ORDER BY rowid",
+                [],
+                preferences_from_row,
+            )
+        })
+        .await
+    }
+
+    async fn create_share(&self, new_share: NewShareRecord) -> Result<ShareRecord> {
+        let record = ShareRecord::new(new_share, self.clock.now());
+        self.call(move |conn| {
+            if !file_exists(conn, "path", &record.path)? {
+                return Err(Error::NotFound(PathBuf::from(&record.path)));
+            }
+            insert_share(conn, &record)?;
+            Ok(record)
+        })
+        .await
+    }
+
+    async fn get_share(&self, id: uuid::Uuid) -> Result<ShareRecord> {
+        self.call(move |conn| {
+            conn.query_row(
+                &format!("SELECT {} FROM shares WHERE id = ?1", SHARE_COLUMNS),
+                [id],
+                share_from_row,
+            )
+            .optional()?
+            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("share:{}", id))))
+        })
+        .await
+    }
+
+    async fn list_shares(&self) -> Result<Vec<ShareRecord>> {
+        self.call(|conn| {
+            query_all(
+                conn,
+                &format!("SELECT {} FROM shares ORDER BY rowid", SHARE_COLUMNS),
+                [],
+                share_from_row,
+            )
+        })
+        .await
+    }
+
+    async fn delete_share(&self, id: uuid::Uuid) -> Result<()> {
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            delete_by_id(&tx, "shares", "share", id)?;
+            tx.execute("DELETE FROM share_accesses WHERE share_id = ?1", [id])?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn record_share_access(&self, access: ShareAccessRecord) -> Result<()> {
+        self.call(move |conn| insert_share_access(conn, &access))
+            .await
+    }
+
+    async fn list_share_accesses(&self, share_id: uuid::Uuid) -> Result<Vec<ShareAccessRecord>> {
+        self.call(move |conn| {
+            query_all(
+                conn,
+                "SELECT share_id, accessed_at, ip, bytes FROM share_accesses \
+                 WHERE share_id = ?1 ORDER BY rowid",
+                [share_id],
+                share_access_from_row,
+            )
+        })
+        .await
+    }
+
+    async fn list_legal_holds(&self) -> Result<Vec<LegalHold>> {
+        self.call(|conn| legal_holds(conn)).await
+    }
+
+    async fn find_legal_hold(&self, path: &str) -> Option<LegalHold> {
+        let path = path.to_string();
+        self.call(move |conn| Ok(legal_holds(conn)?.into_iter().find(|h| h.covers(&path))))
+            .await
+            .ok()
+            .flatten()
+    }
+
+    async fn find_overlapping_legal_hold(&self, path: &str) -> Option<LegalHold> {
+        let path = path.to_string();
+        self.call(move |conn| Ok(legal_holds(conn)?.into_iter().find(|h| h.overlaps(&path))))
+            .await
+            .ok()
+            .flatten()
+    }
+
+    async fn add_legal_hold(&self, path: &str, reason: Option<String>) -> Result<LegalHold> {
+        let hold = LegalHold {
+            path: path.to_string(),
+            reason,
+            created_at: self.clock.now(),
+        };
+        self.call(move |conn| {
+            let exists: bool = conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM legal_holds WHERE path = ?1)",
+                [&hold.path],
+                |row| row.get(0),
+            )?;
+            if exists {
+                return Err(Error::AlreadyExists(PathBuf::from(&hold.path)));
+            }
+            insert_legal_hold(conn, &hold)?;
+            Ok(hold)
+        })
+        .await
+    }
+
+    async fn remove_legal_hold(&self, path: &str) -> Result<()> {
+        let path = path.to_string();
+        self.call(move |conn| {
+            if conn.execute("DELETE FROM legal_holds WHERE path = ?1", [&path])? == 0 {
+                return Err(Error::NotFound(PathBuf::from(path)));
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list_lifecycle_rules(&self) -> Result<Vec<LifecycleRule>> {
+        self.call(|conn| {
+            query_all(
+                conn,
+                &format!(
+                    "SELECT {} FROM lifecycle_rules ORDER BY rowid",
+                    LIFECYCLE_COLUMNS
+                ),
+                [],
+                lifecycle_rule_from_row,
+            )
+        })
+        .await
+    }
+
+    async fn create_lifecycle_rule(&self, new_rule: NewLifecycleRule) -> Result<LifecycleRule> {
+        let rule = LifecycleRule::new(new_rule, self.clock.now());
+        self.call(move |conn| {
+            insert_lifecycle_rule(conn, &rule)?;
+            Ok(rule)
+        })
+        .await
+    }
+
+    async fn delete_lifecycle_rule(&self, id: uuid::Uuid) -> Result<()> {
+        self.call(move |conn| delete_by_id(conn, "lifecycle_rules", "lifecycle", id))
+            .await
+    }
+
+    async fn list_rate_classes(&self) -> Result<Vec<RateClass>> {
+        self.call(|conn| {
+            query_all(
+                conn,
+                &format!(
+                    "SELECT {} FROM rate_classes ORDER BY rowid",
+                    RATE_CLASS_COLUMNS
+                ),
+                [],
+                rate_class_from_row,
+            )
+        })
+        .await
+    }
+
+    async fn create_rate_class(&self, new_class: NewRateClass) -> Result<RateClass> {
+        let class = RateClass::new(new_class, self.clock.now());
+        self.call(move |conn| {
+            insert_rate_class(conn, &class)?;
+            Ok(class)
+        })
+        .await
+    }
+
+    async fn delete_rate_class(&self, id: uuid::Uuid) -> Result<()> {
+        self.call(move |conn| delete_by_id(conn, "rate_classes", "rate-class", id))
+            .await
+    }
+
+    async fn list_mounts(&self) -> Result<Vec<MountRecord>> {
+        self.call(|conn| {
+            query_all(
+                conn,
+                &format!("SELECT {} FROM mounts ORDER BY rowid", MOUNT_COLUMNS),
+                [],
+                mount_from_row,
+            )
+        })
+        .await
+    }
+
+    async fn create_mount(&self, new_mount: NewMountRecord) -> Result<MountRecord> {
+        let mount = MountRecord::new(new_mount, self.clock.now());
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let existing = query_all(
+                &tx,
+                &format!("SELECT {} FROM mounts", MOUNT_COLUMNS),
+                [],
+                mount_from_row,
+            )?;
+            if let Some(existing) = existing.iter().find(|m| m.overlaps(&mount.path)) {
+                return Err(Error::AlreadyExists(PathBuf::from(&existing.path)));
+            }
+            insert_mount(&tx, &mount)?;
+            tx.commit()?;
+            Ok(mount)
+        })
+        .await
+    }
+
+    async fn delete_mount(&self, id: uuid::Uuid) -> Result<()> {
+        self.call(move |conn| delete_by_id(conn, "mounts", "mount", id))
+            .await
+    }
+
+    async fn list_directories(&self) -> Result<Vec<DirectoryRecord>> {
+        self.call(|conn| directories(conn)).await
+    }
+
+    async fn create_directory(&self, path: &str) -> Result<DirectoryRecord> {
+        let directory = DirectoryRecord {
+            path: path.to_string(),
+            created_at: self.clock.now(),
+        };
+        self.call(move |conn| {
+            let exists: bool = conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM directories WHERE path = ?1)",
+                [&directory.path],
+                |row| row.get(0),
+            )?;
+            if exists {
+                return Err(Error::AlreadyExists(PathBuf::from(&directory.path)));
+            }
+            insert_directory(conn, &directory)?;
+            Ok(directory)
+        })
+        .await
+    }
+
+    async fn delete_directories(&self, path: &str) -> Result<usize> {
+        let path = path.to_string();
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let mut removed = 0;
+            for directory in directories(&tx)?.iter().filter(|d| d.is_under(&path)) {
+                removed +=
+                    tx.execute("DELETE FROM directories WHERE path = ?1", [&directory.path])?;
+            }
+            tx.commit()?;
+            Ok(removed)
+        })
+        .await
+    }
+
+    async fn move_directories(&self, path: &str, new_path: &str) -> Result<usize> {
+        let (path, new_path) = (path.to_string(), new_path.to_string());
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let mut moved = 0;
+            for directory in directories(&tx)?.iter().filter(|d| d.is_under(&path)) {
+                let target = format!("{}{}", new_path, &directory.path[path.len()..]);
+                moved += tx.execute(
+                    "UPDATE directories SET path = ?1 WHERE path = ?2",
+                    [&target, &directory.path],
+                )?;
+            }
+            tx.commit()?;
+            Ok(moved)
+        })
+        .await
+    }
+
+    async fn restore_file(&self, record: FileRecord) -> Result<FileRecord> {
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            if file_exists(&tx, "path", &record.path)? || file_exists(&tx, "id", record.id)? {
+                return Err(Error::AlreadyExists(PathBuf::from(&record.path)));
+            }
+            ensure_not_held(&tx, &record.path)?;
+            insert_file(&tx, &record)?;
+            tx.commit()?;
+            Ok(record)
+        })
+        .await
+    }
+
+    async fn list_trash(&self) -> Result<Vec<TrashEntry>> {
+        self.call(|conn| table(conn, "trash", TRASH_COLUMNS, trash_entry_from_row))
+            .await
+    }
+
+    async fn get_trash_entry(&self, id: uuid::Uuid) -> Result<TrashEntry> {
+        self.call(move |conn| {
+            conn.query_row(
+                &format!("SELECT {} FROM trash WHERE id = ?1", TRASH_COLUMNS),
+                [id],
+                trash_entry_from_row,
+            )
+            .optional()?
+            .ok_or_else(|| Error::NotFound(PathBuf::from(format!("trash:{}", id))))
+        })
+        .await
+    }
+
+    async fn create_trash_entry(&self, new_entry: NewTrashEntry) -> Result<TrashEntry> {
+        let entry = TrashEntry::new(new_entry, self.clock.now());
+        self.call(move |conn| {
+            insert_trash_entry(conn, &entry)?;
+            Ok(entry)
+        })
+        .await
+    }
+
+    async fn delete_trash_entry(&self, id: uuid::Uuid) -> Result<()> {
+        self.call(move |conn| delete_by_id(conn, "trash", "trash", id))
+            .await
+    }
+}
@@ -0,0 +1,121 @@
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+
+use crate::db::{FileRecord, FsId, MediaMetadata, NewFileRecord, Repository, RepositoryBackend};
+use crate::error::Result;
+
+/// handler 使用的文件记录操作，测试中可替换为注入故障的实现
+#[async_trait]
+pub trait MetadataStore: Send + Sync {
+    async fn create_file(&self, new_file: NewFileRecord) -> Result<FileRecord>;
+
+    async fn get_file_by_path(&self, path: &str) -> Result<FileRecord>;
+
+    async fn update_file(
+        &self,
+        id: uuid::Uuid,
+        hash: Option<String>,
+        size: u64,
+    ) -> Result<FileRecord>;
+
+    async fn set_file_media(
+        &self,
+        id: uuid::Uuid,
+        media: Option<MediaMetadata>,
+    ) -> Result<FileRecord>;
+
+    async fn update_file_metadata(
+        &self,
+        id: uuid::Uuid,
+        patch: BTreeMap<String, Option<String>>,
+    ) -> Result<FileRecord>;
+
+    async fn set_file_fs_id(&self, id: uuid::Uuid, fs_id: Option<FsId>) -> Result<FileRecord>;
+
+    async fn set_file_owner(&self, id: uuid::Uuid, owner: Option<uuid::Uuid>)
+        -> Result<FileRecord>;
+
+    async fn move_file(&self, id: uuid::Uuid, new_path: &str) -> Result<FileRecord>;
+
+    async fn delete_file(&self, id: uuid::Uuid) -> Result<()>;
+
+    async fn list_files(&self) -> Result<Vec<FileRecord>>;
+
+    async fn total_size(&self) -> Result<u64>;
+
+    async fn owner_usage(&self, owner: uuid::Uuid, root: Option<&str>) -> Result<(usize, u64)>;
+
+    async fn path_spellings(&self, path: &str) -> Result<Vec<String>>;
+}
+
+#[async_trait]
+impl MetadataStore for Repository {
+    async fn create_file(&self, new_file: NewFileRecord) -> Result<FileRecord> {
+        RepositoryBackend::create_file(&**self, new_file).await
+    }
+
+    async fn get_file_by_path(&self, path: &str) -> Result<FileRecord> {
+        RepositoryBackend::get_file_by_path(&**self, path).await
+    }
+
+    async fn update_file(
+        &self,
+        id: uuid::Uuid,
+        hash: Option<String>,
+        size: u64,
+    ) -> Result<FileRecord> {
+        RepositoryBackend::update_file(&**self, id, hash, size).await
+    }
+
+    async fn set_file_media(
+        &self,
+        id: uuid::Uuid,
+        media: Option<MediaMetadata>,
+    ) -> Result<FileRecord> {
+        RepositoryBackend::set_file_media(&**self, id, media).await
+    }
+
+    async fn update_file_metadata(
+        &self,
+        id: uuid::Uuid,
+        patch: BTreeMap<String, Option<String>>,
+    ) -> Result<FileRecord> {
+        RepositoryBackend::update_file_metadata(&**self, id, patch).await
+    }
+
+    async fn set_file_fs_id(&self, id: uuid::Uuid, fs_id: Option<FsId>) -> Result<FileRecord> {
+        RepositoryBackend::set_file_fs_id(&**self, id, fs_id).await
+    }
+
+    async fn set_file_owner(
+        &self,
+        id: uuid::Uuid,
+        owner: Option<uuid::Uuid>,
+    ) -> Result<FileRecord> {
+        RepositoryBackend::set_file_owner(&**self, id, owner).await
+    }
+
+    async fn move_file(&self, id: uuid::Uuid, new_path: &str) -> Result<FileRecord> {
+        RepositoryBackend::move_file(&**self, id, new_path).await
+    }
+
+    async fn delete_file(&self, id: uuid::Uuid) -> Result<()> {
+        RepositoryBackend::delete_file(&**self, id).await
+    }
+
+    async fn list_files(&self) -> Result<Vec<FileRecord>> {
+        RepositoryBackend::list_files(&**self).await
+    }
+
+    async fn total_size(&self) -> Result<u64> {
+        RepositoryBackend::total_size(&**self).await
+    }
+
+    async fn owner_usage(&self, owner: uuid::Uuid, root: Option<&str>) -> Result<(usize, u64)> {
+        RepositoryBackend::owner_usage(&**self, owner, root).await
+    }
+
+    async fn path_spellings(&self, path: &str) -> Result<Vec<String>> {
+        RepositoryBackend::path_spellings(&**self, path).await
+    }
+}
@@ -15,6 +15,8 @@
 // ----------------------------------------
 
 use std::path::PathBuf;
+
+use axum::http::StatusCode;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -28,14 +30,98 @@ pub enum Error {
     #[error("Invalid path: {0}")]
     InvalidPath(String),
 
+    #[error("Path escapes the storage root: {0}")]
+    PathTraversal(String),
+
     #[error("IO error: {0}")]
-    Io(#[from] std::io::Error),
+    Io(std::io::Error),
 
     #[error("Serialization error: {0}")]
     Serialization(#[from] serde_json::Error),
 
+    #[error("Database error: {0}")]
+    Database(rusqlite::Error),
+
     #[error("Configuration error: {0}")]
     Config(String),
+
+    #[error("Path is under legal hold: {0}")]
+    Held(String),
+
+    #[error("Upstream error: {0}")]
+    Upstream(String),
+
+    #[error("Invalid sync status transition: {0}")]
+    InvalidTransition(String),
+
+    #[error("Stored content does not match its hash: {0}")]
+    Corrupted(String),
+
+    #[error("Storage quota exceeded: {0}")]
+    QuotaExceeded(String),
+
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("Busy: {0}")]
+    Busy(String),
+
+    #[error("Not allowed in append-only mode: {0}")]
+    AppendOnly(String),
+
+    #[error("Not allowed by the folder's sync direction: {0}")]
+    SyncDirection(String),
+}
+
+impl Error {
+    /// 对应的 HTTP 状态码，客户端据此区分错误类型而不必匹配消息文本
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::AlreadyExists(_) | Error::Conflict(_) | Error::InvalidTransition(_) => {
+                StatusCode::CONFLICT
+            }
+            Error::InvalidPath(_) | Error::PathTraversal(_) => StatusCode::BAD_REQUEST,
+            Error::Held(_) => StatusCode::LOCKED,
+            Error::AppendOnly(_) | Error::SyncDirection(_) => StatusCode::FORBIDDEN,
+            Error::Upstream(_) => StatusCode::BAD_GATEWAY,
+            Error::QuotaExceeded(_) => StatusCode::INSUFFICIENT_STORAGE,
+            Error::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Error::Busy(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Error::Io(_)
+            | Error::Serialization(_)
+            | Error::Database(_)
+            | Error::Config(_)
+            | Error::Corrupted(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+// 磁盘写满或超出文件系统配额时应清理空间而不是重试，单独归为 QuotaExceeded
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::StorageFull | std::io::ErrorKind::QuotaExceeded => {
+                Error::QuotaExceeded(e.to_string())
+            }
+            _ => Error::Io(e),
+        }
+    }
+}
+
+// 等待锁超时的数据库操作稍后重试即可成功
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        match e.sqlite_error_code() {
+            Some(rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked) => {
+                Error::Busy(e.to_string())
+            }
+            _ => Error::Database(e),
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
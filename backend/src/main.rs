@@ -17,25 +17,29 @@
 // 2. 管理 I/O 事件循环
 // 3. 在多线程上执行异步任务
 //
+// 宏创建的运行时用默认参数，阻塞线程池大小要在创建时确定，
+// 所以这里先同步读取配置，再用 runtime::Builder 手动创建运行时。
+//
 // 思考：为什么 Rust 的 async 不像 Go 一样内置运行时？
 // ----------------------------------------
 
-mod api;
-mod config;
-mod db;
-mod error;
-mod service;
-mod watcher;
-
 use std::sync::Arc;
 
 use axum::Router;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use crate::config::Config;
-use crate::db::Repository;
-use crate::service::storage::{StorageConfig, StorageService};
-use crate::watcher::file_watcher::WatcherService;
+use rustcloud::api;
+use rustcloud::config::Config;
+use rustcloud::db::Repository;
+use rustcloud::service::append_only::AppendOnly;
+use rustcloud::service::expected_writes::ExpectedWrites;
+use rustcloud::service::feed::ChangeFeed;
+use rustcloud::service::lifecycle::LifecycleService;
+use rustcloud::service::rebuild::{set_aside_database, IndexRebuilder, LOST_AND_FOUND};
+use rustcloud::service::shred::Shredder;
+use rustcloud::service::storage::{StorageConfig, StorageService};
+use rustcloud::service::supervisor::Supervisor;
+use rustcloud::watcher::file_watcher::WatcherService;
 
 // [知识点 #081] 初始化与副作用
 // ----------------------------------------
@@ -51,8 +55,7 @@ use crate::watcher::file_watcher::WatcherService;
 //
 // 思考：如何自定义 main 函数的错误处理？
 // ----------------------------------------
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> Result<(), Box<dyn std::error::Error>> {
     // [知识点 #142] 结构化日志配置
     // ----------------------------------------
     // 题目：为什么要配置日志输出到文件？
@@ -102,14 +105,71 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .init();
     }
 
-    let config = Config::from_env_or_default();
+    // 配置有问题时在启动阶段一次报告全部问题后退出；--check-config 只做检查，
+    // --rebuild-index 按存储目录和对象存储重建元数据后退出
+    let check_only = std::env::args().skip(1).any(|arg| arg == "--check-config");
+    let rebuild_only = std::env::args().skip(1).any(|arg| arg == "--rebuild-index");
+    let config = match Config::from_env_or_default().and_then(|config| {
+        config.validate()?;
+        Ok(config)
+    }) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
     tracing::info!("Loaded config: {:?}", config);
+    if check_only {
+        println!("Configuration OK");
+        return Ok(());
+    }
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .max_blocking_threads(config.workers.blocking_threads)
+        .build()?;
+    if rebuild_only {
+        return runtime.block_on(rebuild_index(config));
+    }
+    runtime.block_on(serve(config))
+}
 
-    if !config.storage_path.exists() {
-        std::fs::create_dir_all(&config.storage_path)?;
-        tracing::info!("Created storage directory: {:?}", config.storage_path);
+/// 元数据丢失或损坏时使用：原数据库文件改名保留，按文件树和对象存储重新生成文件记录
+async fn rebuild_index(config: Config) -> Result<(), Box<dyn std::error::Error>> {
+    let kept = set_aside_database(&config.storage_path, chrono::Utc::now()).await?;
+    for path in &kept {
+        println!("Kept the old database as {}", path.display());
     }
+    let repository = Repository::open(&config.storage_path, config.database).await?;
+    let storage = StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: config.chunk_size,
+    })
+    .with_workers(&config.workers)
+    .with_shard_depth(config.shard_depth);
+
+    let summary = IndexRebuilder::new(storage, repository, config.materialize_files)
+        .with_ignore(&config.ignore)
+        .run()
+        .await?;
+    println!(
+        "Rebuilt {} files and {} empty folders from the storage tree",
+        summary.files, summary.directories
+    );
+    if summary.recovered > 0 {
+        println!(
+            "Recovered {} files without a known path into {}/",
+            summary.recovered, LOST_AND_FOUND
+        );
+    }
+    if summary.failed > 0 {
+        println!("{} files could not be read; see the log", summary.failed);
+    }
+    Ok(())
+}
 
+async fn serve(config: Config) -> Result<(), Box<dyn std::error::Error>> {
     // [知识点 #132] 服务初始化顺序
     // ----------------------------------------
     // 题目：为什么先初始化 Repository 和 Storage？
@@ -124,28 +184,106 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     //
     // 思考：如何处理循环依赖？
     // ----------------------------------------
-    let db_path = config.storage_path.join("db.json");
-    let repository = Arc::new(Repository::new(db_path).await?);
-    let storage = Arc::new(StorageService::new(StorageConfig {
-        storage_path: config.storage_path.clone(),
-        chunk_size: 4 * 1024 * 1024,
-    }));
+    let repository = Arc::new(Repository::open(&config.storage_path, config.database).await?);
+    let storage = Arc::new(
+        StorageService::new(StorageConfig {
+            storage_path: config.storage_path.clone(),
+            chunk_size: config.chunk_size,
+        })
+        .with_workers(&config.workers)
+        .with_verify_reads(config.verify_reads)
+        .with_compression(config.compress_objects)
+        .with_shard_depth(config.shard_depth),
+    );
+
+    if config.warm_hash_cache {
+        let records = repository.list_files().await?;
+        let warmed = storage.warm_hashes(&records).await;
+        tracing::info!(
+            "Hash cache warmed with {} of {} files",
+            warmed,
+            records.len()
+        );
+    }
+
+    // 后台服务都登记在 supervisor 下：崩溃后自动重启，退出时按顺序关闭
+    let supervisor = Supervisor::new();
+    let expected_writes = ExpectedWrites::new();
+    let feed = ChangeFeed::new();
+
+    // 分片深度改过时，在后台把旧目录下的对象移到新位置；迁移期间两处都能读到
+    let resharding = storage.clone();
+    supervisor.tasks().spawn(async move {
+        match resharding.reshard().await {
+            Ok(0) => {}
+            Ok(moved) => tracing::info!("Moved {} objects to the new shard layout", moved),
+            Err(e) => tracing::warn!("Re-sharding objects failed, will retry on restart: {}", e),
+        }
+    });
+    // 开启压缩但还没有字典时，用已存储的小对象训练一份；样本不够时下次启动再试
+    if config.compress_objects && storage.dictionary_id().is_none() {
+        let training = storage.clone();
+        supervisor.tasks().spawn(async move {
+            match training.train_dictionary().await {
+                Ok(Some(trained)) => tracing::info!(
+                    "Trained compression dictionary {:08x} from {} objects",
+                    trained.id,
+                    trained.samples
+                ),
+                Ok(None) => {}
+                Err(e) => tracing::warn!("Training a compression dictionary failed: {}", e),
+            }
+        });
+    }
+    let app: Router = api::create_router_with_supervisor(
+        config.clone(),
+        repository.clone(),
+        repository.clone(),
+        storage.clone(),
+        supervisor.clone(),
+        expected_writes.clone(),
+        feed.clone(),
+    )
+    .await;
 
     // 启用文件监控（默认开启，可通过环境变量禁用）
-    let _watcher = if !std::env::var("RUSTCLOUD_NO_WATCH")
+    if !std::env::var("RUSTCLOUD_NO_WATCH")
         .map(|v| v == "true")
         .unwrap_or(false)
     {
-        let mut watcher = WatcherService::new(storage.clone(), repository.clone());
-        watcher.start(&config.storage_path)?;
-        tracing::info!("File watcher started for: {:?}", config.storage_path);
-        Some(watcher)
+        let (storage, repository) = (storage.clone(), repository.clone());
+        let path = config.storage_path.clone();
+        let ignore = config.ignore.clone();
+        supervisor.spawn("file-watcher", move |shutdown| {
+            let mut watcher = WatcherService::new(storage.clone(), repository.clone())
+                .with_expected_writes(expected_writes.clone())
+                .with_feed(feed.clone())
+                .with_ignore(&ignore);
+            let path = path.clone();
+            async move {
+                watcher.start(&path).map_err(std::io::Error::other)?;
+                tracing::info!("File watcher started for: {:?}", path);
+                shutdown.cancelled().await;
+                watcher.shutdown().await;
+                Ok(())
+            }
+        });
     } else {
         tracing::info!("File watcher disabled by RUSTCLOUD_NO_WATCH=true");
-        None
-    };
+    }
 
-    let app: Router = api::create_router_with_services(config.clone(), repository, storage).await;
+    if config.lifecycle_interval_secs > 0 {
+        let shredder = config
+            .shred_deleted
+            .then(|| Shredder::new(storage.clone(), (*repository).clone(), &config.storage_path));
+        let lifecycle = LifecycleService::new((*repository).clone(), config.storage_path.clone())
+            .with_append_only(AppendOnly::new(&config.append_only))
+            .with_shredder(shredder);
+        let interval = std::time::Duration::from_secs(config.lifecycle_interval_secs);
+        supervisor.spawn("lifecycle-scheduler", move |shutdown| {
+            lifecycle.clone().run_scheduler(interval, shutdown)
+        });
+    }
 
     // [知识点 #141] Swagger UI 集成
     // ----------------------------------------
@@ -167,7 +305,41 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing::info!("Server running at http://{}", config.addr());
     tracing::info!("API docs available at http://{}/swagger-ui", config.addr());
 
-    axum::serve(listener, app.into_make_service()).await?;
+    // 带上连接信息，分享访问日志需要对端 IP
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal())
+    .await?;
+
+    // 不再接收请求后再停后台服务，最后一批访问记录和通知不会丢
+    tracing::info!("Shutting down background services");
+    supervisor.shutdown().await;
 
     Ok(())
 }
+
+/// Ctrl+C，或 Unix 上服务管理器发出的 SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    tracing::info!("Shutdown signal received");
+}
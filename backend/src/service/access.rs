@@ -0,0 +1,85 @@
+// [知识点 #155] 访问时间的批量写入
+// ----------------------------------------
+// 题目：每次下载都更新 last_accessed_at，会有什么问题？
+//
+// 讲解：
+// 读操作远多于写操作。如果每次读取都写一次 db.json，
+// 读请求就变成了写请求（写放大），整个文件被反复序列化落盘。
+//
+// 做法和文件系统的 relatime 类似：
+// 1. 读取时只在内存里记下 路径 -> 最近访问时间
+// 2. 后台任务定期把这批记录合并写入数据库，一次落盘
+// 3. 同一文件在一个周期内被访问多少次，都只写一次
+//
+// 代价是进程异常退出时会丢失最后一个周期的访问记录，
+// 对"冷数据"判断这种以天为单位的用途来说可以接受
+//
+// 思考：为什么这里用 std::sync::Mutex 而不是 tokio 的 Mutex？
+// ----------------------------------------
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tokio_util::sync::CancellationToken;
+
+use crate::db::Repository;
+use crate::error::Result;
+
+/// 默认的落盘间隔
+pub const ACCESS_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone)]
+pub struct AccessTracker {
+    repository: Repository,
+    pending: Arc<Mutex<HashMap<String, DateTime<Utc>>>>,
+}
+
+impl AccessTracker {
+    pub fn new(repository: Repository) -> Self {
+        AccessTracker {
+            repository,
+            pending: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// 记录一次访问，只写内存
+    pub fn touch(&self, path: &str) {
+        self.touch_at(path, self.repository.clock().now());
+    }
+
+    pub fn touch_at(&self, path: &str, at: DateTime<Utc>) {
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = pending.entry(path.to_string()).or_insert(at);
+        if at > *entry {
+            *entry = at;
+        }
+    }
+
+    /// 把内存中的访问记录写入数据库，返回更新的文件数
+    pub async fn flush(&self) -> Result<usize> {
+        let batch = {
+            let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+            std::mem::take(&mut *pending)
+        };
+        if batch.is_empty() {
+            return Ok(0);
+        }
+        self.repository.record_file_accesses(batch).await
+    }
+
+    /// 后台定期落盘，收到关闭信号时把最后一批写完再退出
+    pub async fn run_flusher(self, interval: Duration, shutdown: CancellationToken) -> Result<()> {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = shutdown.cancelled() => return self.flush().await.map(|_| ()),
+            }
+            if let Err(e) = self.flush().await {
+                tracing::warn!("Failed to flush access times: {}", e);
+            }
+        }
+    }
+}
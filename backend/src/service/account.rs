@@ -0,0 +1,445 @@
+// [知识点 #185] 个人数据的导出与删除
+// ----------------------------------------
+// 题目：用户说"把我的数据都给我"或者"把我的数据都删掉"，服务端要做哪些事？
+//
+// 讲解：
+// 先要找得到：哪些数据属于这个用户。所有用户共用一个文件命名空间，
+// 所以上传时记下创建者（FileRecord.owner），分享链接记下 created_by，
+// 评论和通知偏好按用户名关联，审计日志的记录带上用户名。
+//
+// 导出可能很大，作为后台任务生成 zip：
+// - account.json：账户信息、通知偏好、文件及其历史版本、分享链接、评论、审计记录
+// - files/<路径>：文件的当前内容
+// - versions/<路径>/v<N>：历史版本的内容
+// 客户端轮询任务状态，完成后下载；归档保留 24 小时。
+//
+// 删除不可撤销，所以分两步：先申请一个 10 分钟内有效的确认令牌，再带着令牌提交。
+// 删除用户的文件（连同历史版本、同步记录和评论）、分享链接、评论、通知偏好和账户本身，
+// 审计日志中该用户的记录去掉用户名和地址，记录本身保留。
+// 法律保留下和只追加目录中的文件不能删除，只去掉创建者，并在结果中列出。
+// 开启 RUSTCLOUD_SHRED_DELETED 时，删除的内容同样会被覆写。
+//
+// 思考：别人上传到共享目录、内容却与这个用户有关的文件，算不算他的个人数据？
+// ----------------------------------------
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use chrono::{DateTime, Duration, Utc};
+use futures_util::StreamExt;
+use serde::Serialize;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::db::{
+    CommentRecord, FileRecord, FileVersionRecord, NotificationPreferences, Repository, ShareRecord,
+    UserInfo, UserRecord,
+};
+use crate::error::{Error, Result};
+use crate::service::clock::Clock;
+use crate::service::firewall::{self, AuditEntry};
+use crate::service::storage::StorageBackend;
+
+/// 导出的归档保留多久
+const EXPORT_TTL_HOURS: i64 = 24;
+
+/// 删除确认令牌的有效期
+const CONFIRMATION_TTL_MINUTES: i64 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportStatus {
+    Running,
+    Ready,
+    Failed,
+}
+
+/// 一次数据导出任务
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportJob {
+    pub id: Uuid,
+    #[serde(skip)]
+    pub user_id: Uuid,
+    pub status: ExportStatus,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime<Utc>>,
+    /// 归档中的文件内容数（当前内容和历史版本）
+    pub files: usize,
+    /// 归档大小
+    pub size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// 删除账户前需要提交的令牌
+#[derive(Debug, Clone, Serialize)]
+pub struct ErasureConfirmation {
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// 删除账户的结果
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ErasureSummary {
+    pub files: usize,
+    pub bytes: u64,
+    pub shares: usize,
+    pub comments: usize,
+    /// 去掉用户名和地址的审计记录数
+    pub audit_entries: usize,
+    /// 因法律保留或只追加目录而保留的文件
+    pub retained: Vec<String>,
+}
+
+/// 归档中的 account.json
+#[derive(Serialize)]
+struct AccountExport {
+    exported_at: DateTime<Utc>,
+    user: UserInfo,
+    notification_preferences: NotificationPreferences,
+    files: Vec<ExportedFile>,
+    shares: Vec<ShareRecord>,
+    comments: Vec<CommentRecord>,
+    audit: Vec<AuditEntry>,
+}
+
+#[derive(Serialize)]
+struct ExportedFile {
+    #[serde(flatten)]
+    record: FileRecord,
+    versions: Vec<FileVersionRecord>,
+}
+
+#[derive(Default)]
+struct AccountState {
+    exports: HashMap<Uuid, ExportJob>,
+    /// 用户 id -> 删除确认令牌
+    confirmations: HashMap<Uuid, ErasureConfirmation>,
+}
+
+/// 账户数据的导出与删除确认，克隆后共享
+#[derive(Clone)]
+pub struct AccountService {
+    repository: Repository,
+    storage: Arc<dyn StorageBackend>,
+    /// 导出的归档；位于 objects/ 下，文件监控不会把它当作用户文件
+    dir: PathBuf,
+    audit_path: PathBuf,
+    clock: Arc<dyn Clock>,
+    state: Arc<Mutex<AccountState>>,
+}
+
+impl AccountService {
+    pub fn new(
+        repository: Repository,
+        storage: Arc<dyn StorageBackend>,
+        storage_path: &Path,
+    ) -> Self {
+        AccountService {
+            clock: repository.clock(),
+            repository,
+            storage,
+            dir: storage_path.join("objects").join("exports"),
+            audit_path: firewall::audit_path(storage_path),
+            state: Arc::new(Mutex::new(AccountState::default())),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, AccountState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn archive_path(&self, id: Uuid) -> PathBuf {
+        self.dir.join(format!("{}.zip", id))
+    }
+
+    /// 在后台开始导出；该用户已有进行中的导出时返回那一个
+    pub async fn start_export(&self, user: &UserRecord) -> ExportJob {
+        self.prune().await;
+        let job = {
+            let mut state = self.state();
+            if let Some(job) = state
+                .exports
+                .values()
+                .find(|job| job.user_id == user.id && job.status == ExportStatus::Running)
+            {
+                return job.clone();
+            }
+            let job = ExportJob {
+                id: Uuid::new_v4(),
+                user_id: user.id,
+                status: ExportStatus::Running,
+                created_at: self.clock.now(),
+                finished_at: None,
+                files: 0,
+                size: 0,
+                error: None,
+            };
+            state.exports.insert(job.id, job.clone());
+            job
+        };
+
+        let service = self.clone();
+        let user = user.clone();
+        let id = job.id;
+        tokio::spawn(
+            async move {
+                let result = service.write_archive(&user, id).await;
+                if let Err(e) = &result {
+                    tracing::warn!("Export {} failed: {}", id, e);
+                }
+                service.finish(id, result);
+            }
+            .in_current_span(),
+        );
+        job
+    }
+
+    /// 属于 user_id 的导出任务
+    pub fn export(&self, user_id: Uuid, id: Uuid) -> Option<ExportJob> {
+        self.state()
+            .exports
+            .get(&id)
+            .filter(|job| job.user_id == user_id)
+            .cloned()
+    }
+
+    /// 已完成的导出归档
+    pub fn export_archive(&self, user_id: Uuid, id: Uuid) -> Option<PathBuf> {
+        self.export(user_id, id)
+            .filter(|job| job.status == ExportStatus::Ready)
+            .map(|job| self.archive_path(job.id))
+    }
+
+    fn finish(&self, id: Uuid, result: Result<(usize, u64)>) {
+        let now = self.clock.now();
+        let mut state = self.state();
+        let Some(job) = state.exports.get_mut(&id) else {
+            // 任务进行中账户被删除了
+            let _ = std::fs::remove_file(self.archive_path(id));
+            return;
+        };
+        job.finished_at = Some(now);
+        match result {
+            Ok((files, size)) => {
+                job.status = ExportStatus::Ready;
+                job.files = files;
+                job.size = size;
+            }
+            Err(e) => {
+                job.status = ExportStatus::Failed;
+                job.error = Some(e.to_string());
+            }
+        }
+    }
+
+    /// 删除过期的任务和归档；重启后内存中没有任务，留下的归档全部删除
+    async fn prune(&self) {
+        let cutoff = self.clock.now() - Duration::hours(EXPORT_TTL_HOURS);
+        let known: Vec<Uuid> = {
+            let mut state = self.state();
+            state
+                .exports
+                .retain(|_, job| job.status == ExportStatus::Running || job.created_at > cutoff);
+            state.exports.keys().copied().collect()
+        };
+        let Ok(mut entries) = tokio::fs::read_dir(&self.dir).await else {
+            return;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            let id = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<Uuid>().ok());
+            if !id.is_some_and(|id| known.contains(&id)) {
+                let _ = tokio::fs::remove_file(&path).await;
+            }
+        }
+    }
+
+    async fn write_archive(&self, user: &UserRecord, id: Uuid) -> Result<(usize, u64)> {
+        // 文件、版本、分享和评论取自同一时刻，导出过程中的修改不会让它们互相对不上
+        let snapshot = self.repository.snapshot().await?;
+        let files: Vec<ExportedFile> = snapshot
+            .files
+            .iter()
+            .filter(|record| record.owner == Some(user.id))
+            .map(|record| ExportedFile {
+                record: record.clone(),
+                versions: snapshot.file_versions(record.id),
+            })
+            .collect();
+        let shares: Vec<ShareRecord> = snapshot
+            .shares
+            .iter()
+            .filter(|share| share.created_by == Some(user.id))
+            .cloned()
+            .collect();
+        let comments: Vec<CommentRecord> = snapshot
+            .comments
+            .iter()
+            .filter(|comment| comment.author.eq_ignore_ascii_case(&user.username))
+            .cloned()
+            .collect();
+        let audit: Vec<AuditEntry> = firewall::read_audit(&self.audit_path)
+            .await?
+            .into_iter()
+            .filter(|entry| {
+                entry
+                    .user
+                    .as_deref()
+                    .is_some_and(|name| name.eq_ignore_ascii_case(&user.username))
+            })
+            .collect();
+
+        // 归档中的路径 -> 对象哈希
+        let mut contents = Vec::new();
+        for file in &files {
+            if let Some(hash) = &file.record.hash {
+                contents.push((format!("files/{}", file.record.path), hash.clone()));
+            }
+            for version in &file.versions {
+                if version.version == file.record.version {
+                    continue;
+                }
+                if let Some(hash) = &version.hash {
+                    let name = format!("versions/{}/v{}", file.record.path, version.version);
+                    contents.push((name, hash.clone()));
+                }
+            }
+        }
+
+        let manifest = serde_json::to_vec_pretty(&AccountExport {
+            exported_at: self.clock.now(),
+            user: user.info(),
+            notification_preferences: self
+                .repository
+                .get_notification_preferences(&user.username)
+                .await?,
+            files,
+            shares,
+            comments,
+            audit,
+        })?;
+
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let target = self.archive_path(id);
+        let partial = target.with_extension("partial");
+        let storage = self.storage.clone();
+        let handle = tokio::runtime::Handle::current();
+        let written = {
+            let partial = partial.clone();
+            tokio::task::spawn_blocking(move || {
+                write_zip(&partial, &manifest, &contents, storage.as_ref(), &handle)
+            })
+            .await
+            .map_err(|e| Error::Io(std::io::Error::other(e)))?
+        };
+        match written {
+            Ok(files) => {
+                tokio::fs::rename(&partial, &target).await?;
+                let size = tokio::fs::metadata(&target).await?.len();
+                Ok((files, size))
+            }
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&partial).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// 申请删除账户，返回需要提交的确认令牌；再次申请时旧令牌失效
+    pub fn request_erasure(&self, user_id: Uuid) -> ErasureConfirmation {
+        let confirmation = ErasureConfirmation {
+            token: Uuid::new_v4().simple().to_string(),
+            expires_at: self.clock.now() + Duration::minutes(CONFIRMATION_TTL_MINUTES),
+        };
+        self.state()
+            .confirmations
+            .insert(user_id, confirmation.clone());
+        confirmation
+    }
+
+    /// 令牌有效时消耗它并返回 true
+    pub fn confirm_erasure(&self, user_id: Uuid, token: &str) -> bool {
+        let now = self.clock.now();
+        let mut state = self.state();
+        let valid = state
+            .confirmations
+            .get(&user_id)
+            .is_some_and(|c| c.token == token && c.expires_at > now);
+        if valid {
+            state.confirmations.remove(&user_id);
+        }
+        valid
+    }
+
+    /// 删除用户的导出任务和归档
+    pub async fn forget(&self, user_id: Uuid) {
+        let ids: Vec<Uuid> = {
+            let mut state = self.state();
+            state.confirmations.remove(&user_id);
+            let ids = state
+                .exports
+                .values()
+                .filter(|job| job.user_id == user_id)
+                .map(|job| job.id)
+                .collect();
+            state.exports.retain(|_, job| job.user_id != user_id);
+            ids
+        };
+        for id in ids {
+            let _ = tokio::fs::remove_file(self.archive_path(id)).await;
+        }
+    }
+}
+
+/// 在阻塞线程中写 zip；对象内容通过 handle 逐段读取，不整个读入内存。
+/// 返回写入的文件内容数，对象缺失的内容跳过并记日志
+fn write_zip(
+    target: &Path,
+    manifest: &[u8],
+    contents: &[(String, String)],
+    storage: &dyn StorageBackend,
+    handle: &tokio::runtime::Handle,
+) -> Result<usize> {
+    use zip::write::SimpleFileOptions;
+    use zip::CompressionMethod;
+
+    let zip_error = |e: zip::result::ZipError| Error::Io(std::io::Error::other(e));
+    let file = std::fs::File::create(target)?;
+    let mut zip = zip::ZipWriter::new(std::io::BufWriter::new(file));
+
+    zip.start_file(
+        "account.json",
+        SimpleFileOptions::default().compression_method(CompressionMethod::Deflated),
+    )
+    .map_err(zip_error)?;
+    zip.write_all(manifest)?;
+
+    // 文件内容大多已经压缩过（图片、视频、压缩包），直接存储
+    let stored = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Stored)
+        .large_file(true);
+    let mut written = 0;
+    for (name, hash) in contents {
+        let mut stream = match handle.block_on(storage.stream_object(hash)) {
+            Ok((_, stream)) => stream,
+            Err(e) => {
+                tracing::warn!("Skipping {} in export: {}", name, e);
+                continue;
+            }
+        };
+        zip.start_file(name.as_str(), stored).map_err(zip_error)?;
+        while let Some(bytes) = handle.block_on(stream.next()) {
+            zip.write_all(&bytes?)?;
+        }
+        written += 1;
+    }
+    zip.finish().map_err(zip_error)?.flush()?;
+    Ok(written)
+}
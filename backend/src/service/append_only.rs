@@ -0,0 +1,67 @@
+// [知识点 #183] 只追加（append-only）的备份目标
+// ----------------------------------------
+// 题目：备份机被勒索软件攻破后，它手里的凭据能不能把备份也一起删掉？
+//
+// 讲解：
+// restic rest-server 和 borg 的 append-only 模式给出的答案是：备份端只能写入新数据，
+// 删除和改写历史必须由另一套权限完成。RustCloud 每次写入都保留一个版本，
+// 所以"覆盖上传"只是产生新版本，并不破坏历史；真正危险的是删除：
+// - 删除文件或目录（包括删除包含只追加目录的上级目录）
+// - 同步引擎的 delete 动作
+// - 会删除或移走文件的生命周期规则
+//
+// 两种开启方式：
+// - 按目录：配置中列出的目录对所有人都只能追加，生命周期规则也会跳过它们
+// - 按令牌：登录时申请只追加的令牌，持有者在任何目录都不能删除，也不能改动生命周期规则；
+//   令牌本身签了名，备份机无法把它改回普通令牌
+//
+// 清理过期备份仍然可以做，只是要换一个普通令牌（或在服务器上操作），
+// 这正是把"写备份"和"删备份"的权限分开的意义
+//
+// 思考：只追加目录中的文件如果被服务器上的进程直接删掉，文件监控应该怎么处理？
+// ----------------------------------------
+
+use crate::db::models::is_same_or_descendant;
+
+/// 配置中的只追加目录
+#[derive(Debug, Clone, Default)]
+pub struct AppendOnly {
+    /// 不含首尾斜杠；空字符串表示整个存储目录
+    folders: Vec<String>,
+}
+
+impl AppendOnly {
+    pub fn new(folders: &[String]) -> Self {
+        AppendOnly {
+            folders: folders
+                .iter()
+                .map(|folder| folder.trim_matches('/').to_string())
+                .collect(),
+        }
+    }
+
+    pub fn folders(&self) -> &[String] {
+        &self.folders
+    }
+
+    /// path 位于某个只追加目录中（自身或子路径）
+    pub fn covers(&self, path: &str) -> bool {
+        self.folders
+            .iter()
+            .any(|folder| folder.is_empty() || is_same_or_descendant(path, folder))
+    }
+
+    /// 删除 path 会波及的只追加目录：path 在其中，或 path 是它的上级目录
+    pub fn overlapping(&self, path: &str) -> Option<&str> {
+        let path = path.trim_matches('/');
+        self.folders
+            .iter()
+            .find(|folder| {
+                folder.is_empty()
+                    || path.is_empty()
+                    || is_same_or_descendant(path, folder)
+                    || is_same_or_descendant(folder, path)
+            })
+            .map(String::as_str)
+    }
+}
@@ -0,0 +1,120 @@
+// [知识点 #166] 无状态令牌：JWT
+// ----------------------------------------
+// 题目：登录之后，服务端怎样认出后续请求是谁发的？
+//
+// 讲解：
+// 一种做法是服务端保存 session 表，客户端只带一个随机 id；
+// JWT 则把"这是谁、什么时候过期"写进令牌本身，再用服务端密钥签名：
+// 1. header.payload.signature 三段，前两段只是 base64，任何人都能读
+// 2. 签名保证内容没被篡改，验证只需要密钥，不查数据库
+// 3. exp 字段由验证方检查，过期后客户端重新登录
+//
+// 代价是令牌签发后无法单独撤销，只能等它过期或更换密钥，
+// 所以有效期不宜太长
+//
+// 口令本身用 Argon2 加盐慢哈希保存，数据库泄露也不能直接还原
+//
+// 思考：令牌里能不能放用户的权限列表？权限变更后会怎样？
+// ----------------------------------------
+
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::config::AuthConfig;
+use crate::db::UserRecord;
+use rustcloud_types::AuthToken;
+
+pub const MIN_PASSWORD_LEN: usize = 8;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    /// 用户 id
+    pub sub: Uuid,
+    pub username: String,
+    pub iat: i64,
+    pub exp: i64,
+    /// 只追加的令牌，见 service::append_only
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub append_only: bool,
+}
+
+#[derive(Clone)]
+pub struct AuthService {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    ttl: chrono::Duration,
+    pub allow_registration: bool,
+    /// 见 api::namespace
+    pub user_namespaces: bool,
+}
+
+impl AuthService {
+    pub fn new(config: &AuthConfig) -> Self {
+        AuthService {
+            encoding: EncodingKey::from_secret(config.jwt_secret.as_bytes()),
+            decoding: DecodingKey::from_secret(config.jwt_secret.as_bytes()),
+            ttl: chrono::Duration::seconds(config.token_ttl_secs as i64),
+            allow_registration: config.allow_registration,
+            user_namespaces: config.user_namespaces,
+        }
+    }
+
+    pub fn hash_password(password: &str) -> Result<String, String> {
+        // uuid v4 的 16 个随机字节作为盐，不必再引入一个随机数生成器
+        let salt = SaltString::encode_b64(Uuid::new_v4().as_bytes()).map_err(|e| e.to_string())?;
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn verify_password(password: &str, password_hash: &str) -> bool {
+        PasswordHash::new(password_hash)
+            .map(|hash| {
+                Argon2::default()
+                    .verify_password(password.as_bytes(), &hash)
+                    .is_ok()
+            })
+            .unwrap_or(false)
+    }
+
+    pub fn issue(
+        &self,
+        user: &UserRecord,
+        append_only: bool,
+        now: DateTime<Utc>,
+    ) -> Result<AuthToken, String> {
+        let expires_at = now + self.ttl;
+        let claims = Claims {
+            sub: user.id,
+            username: user.username.clone(),
+            iat: now.timestamp(),
+            exp: expires_at.timestamp(),
+            append_only,
+        };
+        let token = jsonwebtoken::encode(&Header::default(), &claims, &self.encoding)
+            .map_err(|e| e.to_string())?;
+        Ok(AuthToken {
+            token,
+            expires_at,
+            user: user.info(),
+            append_only,
+        })
+    }
+
+    /// 校验签名与有效期；过期时间按 `now` 判断，与服务端时钟一致
+    pub fn verify(&self, token: &str, now: DateTime<Utc>) -> Option<Claims> {
+        let mut validation = Validation::default();
+        // 由下面按注入的时钟检查
+        validation.validate_exp = false;
+        validation.required_spec_claims.clear();
+        let claims = jsonwebtoken::decode::<Claims>(token, &self.decoding, &validation)
+            .ok()?
+            .claims;
+        (claims.exp > now.timestamp()).then_some(claims)
+    }
+}
@@ -0,0 +1,144 @@
+// [知识点 #156] 令牌桶限速
+// ----------------------------------------
+// 题目：家用服务器和视频会议共用上行带宽，怎样在工作时间给同步让路？
+//
+// 讲解：
+// 令牌桶按固定速率往桶里放"字节额度"，每传输一段数据就取走相应额度：
+// - 额度足够：立即放行
+// - 额度不足：先记账（额度变为负数），再睡眠到额度恢复为 0
+//
+// 同一速率等级的所有请求共享一个桶，限制的是总带宽而不是单个连接，
+// 否则客户端开 8 个并发上传就能绕过限制
+//
+// 限速发生在请求体/响应体的流上，处理函数完全不感知：
+// 上传时处理函数读取请求体的速度被拖慢，TCP 窗口随之收缩，客户端自然降速
+//
+// 思考：桶的容量（允许的突发量）设得太大或太小分别有什么问题？
+// ----------------------------------------
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::body::Body;
+use chrono::{Local, Timelike};
+use futures_util::StreamExt;
+
+use crate::db::{RateClass, Repository};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Upload,
+    Download,
+}
+
+impl Direction {
+    fn limit(&self, class: &RateClass) -> Option<u64> {
+        match self {
+            Direction::Upload => class.upload_bytes_per_sec,
+            Direction::Download => class.download_bytes_per_sec,
+        }
+    }
+}
+
+/// 容量为一秒额度的令牌桶
+#[derive(Debug)]
+pub struct TokenBucket {
+    bytes_per_sec: u64,
+    state: Mutex<BucketState>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        TokenBucket {
+            bytes_per_sec: bytes_per_sec.max(1),
+            state: Mutex::new(BucketState {
+                tokens: bytes_per_sec as f64,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// 取走 n 字节额度，返回调用方需要等待的时间
+    pub fn reserve(&self, n: usize) -> Duration {
+        let rate = self.bytes_per_sec as f64;
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.refilled_at).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * rate).min(rate);
+        state.refilled_at = now;
+
+        state.tokens -= n as f64;
+        if state.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-state.tokens / rate)
+        }
+    }
+}
+
+/// 以 (等级, 方向) 为键，同一等级的并发传输共享额度
+type BucketKey = (uuid::Uuid, Direction);
+
+#[derive(Clone)]
+pub struct BandwidthLimiter {
+    repository: Repository,
+    buckets: Arc<Mutex<HashMap<BucketKey, Arc<TokenBucket>>>>,
+}
+
+impl BandwidthLimiter {
+    pub fn new(repository: Repository) -> Self {
+        BandwidthLimiter {
+            repository,
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// 当前生效的最严格的等级对应的桶；没有限制时返回 None
+    pub async fn bucket_for(
+        &self,
+        device_id: Option<uuid::Uuid>,
+        direction: Direction,
+    ) -> Option<Arc<TokenBucket>> {
+        let hour = self.repository.clock().now().with_timezone(&Local).hour();
+        let classes = self
+            .repository
+            .list_rate_classes()
+            .await
+            .unwrap_or_default();
+        let (class, limit) = classes
+            .iter()
+            .filter(|c| c.applies_to(device_id, hour))
+            .filter_map(|c| direction.limit(c).map(|limit| (c, limit)))
+            .min_by_key(|(_, limit)| *limit)?;
+
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let bucket = buckets
+            .entry((class.id, direction))
+            .or_insert_with(|| Arc::new(TokenBucket::new(limit)));
+        Some(bucket.clone())
+    }
+}
+
+/// 按令牌桶的速率转发 body 中的数据
+pub fn throttle(body: Body, bucket: Arc<TokenBucket>) -> Body {
+    let stream = body.into_data_stream().then(move |chunk| {
+        let bucket = bucket.clone();
+        async move {
+            if let Ok(bytes) = &chunk {
+                let wait = bucket.reserve(bytes.len());
+                if !wait.is_zero() {
+                    tokio::time::sleep(wait).await;
+                }
+            }
+            chunk
+        }
+    });
+    Body::from_stream(stream)
+}
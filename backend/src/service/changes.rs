@@ -0,0 +1,71 @@
+//! 变更日志与设备游标
+//!
+//! 每次成功的写请求都追加一条带序号的记录，设备拉取同步计划时把游标移到最新序号。
+//! 心跳据此告诉设备"上次同步之后别的客户端改过东西"，轻量客户端不必维持长连接，
+//! 收到提示再发起同步即可。
+//!
+//! 日志只保存在内存中：服务端重启后所有设备的游标都未知，按"有变更"处理，
+//! 代价只是每台设备多同步一次。
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use uuid::Uuid;
+
+/// 保留的最近变更条数；游标落在更早位置时视为有变更
+const MAX_ENTRIES: usize = 10_000;
+
+#[derive(Default)]
+struct Journal {
+    /// 最新一条变更的序号，从 1 开始
+    seq: u64,
+    /// (序号, 发起变更的设备)
+    entries: VecDeque<(u64, Option<Uuid>)>,
+    cursors: HashMap<Uuid, u64>,
+}
+
+#[derive(Clone, Default)]
+pub struct ChangeJournal {
+    journal: Arc<Mutex<Journal>>,
+}
+
+impl ChangeJournal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 记录一次变更；origin 为发起请求的设备，匿名请求为 None
+    pub fn record(&self, origin: Option<Uuid>) {
+        let mut journal = self.journal.lock().unwrap();
+        journal.seq += 1;
+        let seq = journal.seq;
+        journal.entries.push_back((seq, origin));
+        if journal.entries.len() > MAX_ENTRIES {
+            journal.entries.pop_front();
+        }
+    }
+
+    /// 设备已经拿到当前的完整状态
+    pub fn mark_synced(&self, device: Uuid) {
+        let mut journal = self.journal.lock().unwrap();
+        let seq = journal.seq;
+        journal.cursors.insert(device, seq);
+    }
+
+    /// 设备上次同步之后，是否有其他客户端发起的变更
+    pub fn has_pending(&self, device: Uuid) -> bool {
+        let journal = self.journal.lock().unwrap();
+        let Some(&cursor) = journal.cursors.get(&device) else {
+            return true;
+        };
+        match journal.entries.front() {
+            Some(&(oldest, _)) if oldest > cursor + 1 => true,
+            _ => journal
+                .entries
+                .iter()
+                .rev()
+                .take_while(|(seq, _)| *seq > cursor)
+                .any(|(_, origin)| *origin != Some(device)),
+        }
+    }
+}
@@ -0,0 +1,221 @@
+// [知识点 #160] 故障注入（chaos testing）
+// ----------------------------------------
+// 题目：如何证明同步在网络和磁盘出错时仍然能收敛？
+//
+// 讲解：
+// 正常的集成测试只覆盖"一切顺利"的路径。故障注入层在真实服务外面
+// 再包一层，按概率制造：
+// - 延迟：每个请求先睡一会儿
+// - 500：请求根本不到达 handler
+// - 断连：handler 已经执行完，响应体发到一半连接被切断
+// - 存储写入失败 / 只写入一部分就失败
+//
+// 随机数使用固定种子，请求顺序相同时注入的故障也相同，失败可以复现。
+// 整个模块只在 chaos feature 下编译，不会进入正式构建
+//
+// 思考：为什么"handler 执行成功但响应丢失"是最容易出 bug 的情况？
+// ----------------------------------------
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use axum::body::{Body, Bytes};
+use axum::extract::{Request, State};
+use axum::http::{header, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+
+use crate::api::routes::ApiResponse;
+use crate::error::Result;
+use crate::service::storage::{StagedUpload, StorageBackend};
+
+/// 故障注入配置，各 *_rate 为触发概率（0.0 ~ 1.0）
+#[derive(Debug, Clone, Default)]
+pub struct ChaosConfig {
+    /// 每个请求处理前的固定延迟
+    pub delay: Duration,
+    /// 请求直接返回 500，不进入 handler
+    pub fail_rate: f64,
+    /// handler 正常执行，但响应体只发出一半就断开连接
+    pub drop_rate: f64,
+    /// 存储写入直接报错
+    pub write_failure_rate: f64,
+    /// 存储只写入前一半内容后报错
+    pub partial_write_rate: f64,
+    /// 只对该方法的请求注入 HTTP 故障，None 表示所有请求
+    pub method: Option<Method>,
+}
+
+struct ChaosState {
+    config: ChaosConfig,
+    rng: u64,
+}
+
+/// 可在测试运行中随时调整的故障注入器，克隆后共享同一份状态
+#[derive(Clone)]
+pub struct Chaos {
+    state: Arc<Mutex<ChaosState>>,
+}
+
+impl Chaos {
+    pub fn new(seed: u64) -> Self {
+        Chaos {
+            state: Arc::new(Mutex::new(ChaosState {
+                config: ChaosConfig::default(),
+                // xorshift 的状态不能为 0
+                rng: seed | 1,
+            })),
+        }
+    }
+
+    pub fn configure(&self, config: ChaosConfig) {
+        self.state.lock().unwrap().config = config;
+    }
+
+    /// 关闭所有故障
+    pub fn disable(&self) {
+        self.configure(ChaosConfig::default());
+    }
+
+    fn delay(&self) -> Duration {
+        self.state.lock().unwrap().config.delay
+    }
+
+    fn targets(&self, method: &Method) -> bool {
+        let state = self.state.lock().unwrap();
+        state.config.method.as_ref().is_none_or(|m| m == method)
+    }
+
+    // 按配置中的概率掷骰子
+    fn roll(&self, rate: impl Fn(&ChaosConfig) -> f64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let rate = rate(&state.config);
+        if rate <= 0.0 {
+            return false;
+        }
+        // xorshift64*
+        let mut x = state.rng;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.rng = x;
+        let sample = x.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11;
+        (sample as f64 / (1u64 << 53) as f64) < rate
+    }
+}
+
+/// HTTP 层故障：作为最外层 middleware 使用
+pub async fn inject_http_faults(
+    State(chaos): State<Chaos>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !chaos.targets(request.method()) {
+        return next.run(request).await;
+    }
+
+    let delay = chaos.delay();
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+
+    if chaos.roll(|c| c.fail_rate) {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error("Injected failure")),
+        )
+            .into_response();
+    }
+
+    let response = next.run(request).await;
+    if !chaos.roll(|c| c.drop_rate) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = axum::body::to_bytes(body, usize::MAX)
+        .await
+        .unwrap_or_default();
+    let half = bytes.slice(..bytes.len() / 2);
+    let chunks: [std::io::Result<Bytes>; 2] = [
+        Ok(half),
+        Err(std::io::Error::new(
+            std::io::ErrorKind::ConnectionAborted,
+            "injected disconnect",
+        )),
+    ];
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from_stream(futures_util::stream::iter(chunks)))
+}
+
+/// 存储层故障：包装真实的存储实现，只影响写入
+pub struct ChaosStorage {
+    inner: Arc<dyn StorageBackend>,
+    chaos: Chaos,
+}
+
+impl ChaosStorage {
+    pub fn new(inner: Arc<dyn StorageBackend>, chaos: Chaos) -> Self {
+        ChaosStorage { inner, chaos }
+    }
+
+    async fn write(&self, content: &[u8]) -> Result<(String, u64)> {
+        if self.chaos.roll(|c| c.write_failure_rate) {
+            return Err(injected_write_error());
+        }
+        if self.chaos.roll(|c| c.partial_write_rate) {
+            self.inner
+                .store_content(&content[..content.len() / 2])
+                .await?;
+            return Err(injected_write_error());
+        }
+        self.inner.store_content(content).await
+    }
+}
+
+fn injected_write_error() -> crate::error::Error {
+    std::io::Error::new(std::io::ErrorKind::WriteZero, "injected write failure").into()
+}
+
+#[async_trait]
+impl StorageBackend for ChaosStorage {
+    fn negotiate_chunk_size(&self, file_size: u64, requested: Option<usize>) -> Option<usize> {
+        self.inner.negotiate_chunk_size(file_size, requested)
+    }
+
+    fn compute_content_hash(&self, content: &[u8]) -> String {
+        self.inner.compute_content_hash(content)
+    }
+
+    async fn compute_hash(&self, path: &Path) -> Result<String> {
+        self.inner.compute_hash(path).await
+    }
+
+    async fn store_file(&self, source: &Path) -> Result<(String, u64)> {
+        let content = tokio::fs::read(source).await?;
+        self.write(&content).await
+    }
+
+    async fn store_content(&self, content: &[u8]) -> Result<(String, u64)> {
+        self.write(content).await
+    }
+
+    async fn begin_upload(&self) -> Result<StagedUpload> {
+        self.inner.begin_upload().await
+    }
+
+    async fn file_exists(&self, hash: &str) -> bool {
+        self.inner.file_exists(hash).await
+    }
+
+    fn object_path(&self, hash: &str) -> PathBuf {
+        self.inner.object_path(hash)
+    }
+
+    async fn object_files(&self, hash: &str) -> Vec<PathBuf> {
+        self.inner.object_files(hash).await
+    }
+}
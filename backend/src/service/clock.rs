@@ -0,0 +1,64 @@
+// [知识点 #162] 可注入的时钟
+// ----------------------------------------
+// 题目：分享 30 天后过期、文件 90 天未访问后归档，这些逻辑怎么测试？
+//
+// 讲解：
+// 代码里到处直接调用 Utc::now()，测试就只能真的等下去，
+// 或者手工改写记录里的时间戳。
+//
+// 把"现在几点"也当作依赖注入进来：
+// - SystemClock：正式运行时使用，就是 Utc::now()
+// - VirtualClock：测试使用，时间只在调用 advance() 时前进
+//
+// Repository 持有时钟，写入记录时用它打时间戳；
+// 过期、离线、生命周期规则也都从同一个时钟读时间，
+// 测试把时钟拨快 31 天，结果和真的过了 31 天一样，而且完全确定
+//
+// 思考：限速用的 Instant 为什么没有换成这个时钟？
+// ----------------------------------------
+
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// 系统时间
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// 手动推进的时间，克隆后共享同一个时刻
+#[derive(Debug, Clone)]
+pub struct VirtualClock {
+    now: Arc<Mutex<DateTime<Utc>>>,
+}
+
+impl VirtualClock {
+    pub fn new(start: DateTime<Utc>) -> Self {
+        VirtualClock {
+            now: Arc::new(Mutex::new(start)),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().unwrap() += by;
+    }
+
+    pub fn set(&self, at: DateTime<Utc>) {
+        *self.now.lock().unwrap() = at;
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+}
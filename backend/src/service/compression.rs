@@ -0,0 +1,175 @@
+// [知识点 #195] 小对象压缩与 zstd 字典
+// ----------------------------------------
+// 题目：大量相似的小文件（配置、JSON、源码）各自压缩，为什么压缩率很低？
+//
+// 讲解：
+// 压缩算法靠"前面出现过的内容"来编码后面的内容。一个几百字节的 JSON 从头压缩，
+// 没有可以引用的前文，字段名、缩进这些各个文件共有的部分只能原样写出。
+//
+// zstd 字典把共有的部分提前准备好：
+// 1. 从已存储的小对象中取样，训练出一份字典
+// 2. 之后压缩每个小对象时都以字典为"前文"，共有部分只需一个引用
+// 3. 压缩帧的帧头记录字典 ID，解压时据此找到同一份字典
+//
+// 存储上：
+// - 压缩的对象与原始对象同样按哈希分片，文件名多一个 zstd- 前缀作为编码标记，没有前缀的是原始字节
+// - 哈希仍是原始内容的哈希，去重、校验和下载都按原始内容进行
+// - 字典存放在 objects/dictionaries/ 下，重新训练不删除旧字典，用旧字典压缩的对象照常可读
+// - 只压缩整体存储的小对象；分块存储的大对象原样存放，按偏移读取不受影响
+//
+// 思考：训练出新字典后，用旧字典压缩的对象要不要重新压缩？
+// ----------------------------------------
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+
+/// 压缩存储的对象文件名前缀
+pub const COMPRESSED_PREFIX: &str = "zstd-";
+
+/// 不超过这个大小的整体对象才压缩
+pub const COMPRESS_MAX_SIZE: u64 = 1024 * 1024; // 1MB
+
+/// 训练时最多取样的对象数
+pub const MAX_SAMPLES: usize = 2000;
+
+/// 样本太少时训练不出有用的字典
+pub const MIN_SAMPLES: usize = 8;
+
+/// zstd 命令行工具的默认字典大小；样本较少时按样本总量的 1/10 缩小
+const DICTIONARY_SIZE: usize = 110 * 1024;
+
+/// zstd 能接受的最小字典
+const MIN_DICTIONARY_SIZE: usize = 256;
+
+const LEVEL: i32 = 3;
+
+/// 记录当前字典 ID 的文件
+const CURRENT_DICTIONARY: &str = "current";
+
+/// 一次训练的结果
+#[derive(Debug, Clone, Serialize)]
+pub struct TrainedDictionary {
+    pub id: u32,
+    pub samples: usize,
+    pub size: usize,
+}
+
+#[derive(Debug, Default)]
+struct Dictionaries {
+    /// 已经读过 current 文件
+    initialized: bool,
+    /// 新对象压缩时使用的字典
+    current: Option<u32>,
+    /// 读取过的字典，按 ID 缓存
+    loaded: HashMap<u32, Arc<Vec<u8>>>,
+}
+
+/// 对象的压缩与解压，克隆后共享字典缓存。
+///
+/// 压缩和解压都是同步的 CPU 操作，字典按需从磁盘读取，应在阻塞线程中调用
+#[derive(Debug, Clone)]
+pub struct ObjectCompression {
+    dir: PathBuf,
+    dictionaries: Arc<Mutex<Dictionaries>>,
+}
+
+impl ObjectCompression {
+    pub fn new(objects_dir: &Path) -> Self {
+        ObjectCompression {
+            dir: objects_dir.join("dictionaries"),
+            dictionaries: Arc::new(Mutex::new(Dictionaries::default())),
+        }
+    }
+
+    /// 字典目录，位于 objects/ 下但不是分片目录
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn dictionary_path(&self, id: u32) -> PathBuf {
+        self.dir.join(format!("{:08x}.dict", id))
+    }
+
+    pub fn current_path(&self) -> PathBuf {
+        self.dir.join(CURRENT_DICTIONARY)
+    }
+
+    /// 当前字典的 ID；还没有训练过时为 None
+    pub fn current(&self) -> io::Result<Option<u32>> {
+        let mut dictionaries = self.dictionaries.lock().unwrap();
+        if !dictionaries.initialized {
+            dictionaries.current = match std::fs::read_to_string(self.current_path()) {
+                Ok(id) => u32::from_str_radix(id.trim(), 16).ok(),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e),
+            };
+            dictionaries.initialized = true;
+        }
+        Ok(dictionaries.current)
+    }
+
+    /// 字典文件已经写好，之后的对象用它压缩
+    pub fn activate(&self, id: u32, dictionary: Vec<u8>) {
+        let mut dictionaries = self.dictionaries.lock().unwrap();
+        dictionaries.loaded.insert(id, Arc::new(dictionary));
+        dictionaries.current = Some(id);
+        dictionaries.initialized = true;
+    }
+
+    fn dictionary(&self, id: u32) -> io::Result<Arc<Vec<u8>>> {
+        if let Some(dictionary) = self.dictionaries.lock().unwrap().loaded.get(&id) {
+            return Ok(dictionary.clone());
+        }
+        let dictionary = Arc::new(std::fs::read(self.dictionary_path(id))?);
+        self.dictionaries
+            .lock()
+            .unwrap()
+            .loaded
+            .insert(id, dictionary.clone());
+        Ok(dictionary)
+    }
+
+    /// 压缩后的内容；没有变小时返回 None，调用方原样存储
+    pub fn compress(&self, content: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let mut compressor = match self.current()? {
+            Some(id) => zstd::bulk::Compressor::with_dictionary(LEVEL, &self.dictionary(id)?)?,
+            None => zstd::bulk::Compressor::new(LEVEL)?,
+        };
+        let compressed = compressor.compress(content)?;
+        Ok((compressed.len() < content.len()).then_some(compressed))
+    }
+
+    /// 解压压缩存储的对象，帧头中的字典 ID 决定使用哪份字典
+    pub fn decompress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let size = content_size(data)
+            .filter(|size| *size <= COMPRESS_MAX_SIZE)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid zstd frame"))?;
+        let mut decompressor = match zstd::zstd_safe::get_dict_id_from_frame(data) {
+            Some(id) => zstd::bulk::Decompressor::with_dictionary(&self.dictionary(id.get())?)?,
+            None => zstd::bulk::Decompressor::new()?,
+        };
+        decompressor.decompress(data, size as usize)
+    }
+}
+
+/// 压缩帧记录的原始大小
+pub fn content_size(data: &[u8]) -> Option<u64> {
+    zstd::zstd_safe::get_frame_content_size(data).ok().flatten()
+}
+
+/// 用样本训练字典，返回字典 ID 和内容；样本总量太小时返回 None
+pub fn train(samples: &[Vec<u8>]) -> io::Result<Option<(u32, Vec<u8>)>> {
+    let total: usize = samples.iter().map(Vec::len).sum();
+    let capacity = DICTIONARY_SIZE.min(total / 10);
+    if samples.len() < MIN_SAMPLES || capacity < MIN_DICTIONARY_SIZE {
+        return Ok(None);
+    }
+    let dictionary = zstd::dict::from_samples(samples, capacity)?;
+    let id = zstd::zstd_safe::get_dict_id_from_dict(&dictionary)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "dictionary has no ID"))?;
+    Ok(Some((id.get(), dictionary)))
+}
@@ -0,0 +1,71 @@
+// [知识点 #187] 按目录的同步方向
+// ----------------------------------------
+// 题目：备份目录和共享素材目录，为什么不能和普通目录一样双向同步？
+//
+// 讲解：
+// 双向同步假设每台设备都既是生产者又是消费者。有些目录天然只有一个方向：
+// - 只上传（backups/）：各设备把备份推上来，但不应把别人的备份拉回本地，
+//   更不能因为本地清理了旧备份就把服务端的也删掉
+// - 只下载（shared-assets/）：由管理员在服务器上维护，客户端只取用，
+//   误改或误删的本地副本不能覆盖回服务端
+//
+// 方向只在客户端的计划里遵守是不够的：旧版本客户端、脚本和直接调用 API 的程序
+// 都绕得过去。所以服务端在写接口上强制：
+// - 只下载目录中不能上传、新建目录、删除或移入移出
+// - 只上传目录中不能删除或移出；删除它的上级目录同样会波及它
+// 客户端再据此裁剪同步计划：只上传目录不下载，只下载目录不上传，
+// 这些条目本来就会被拒绝，提前去掉就不会在每次同步时报错。
+// 目录嵌套时最内层的方向优先；服务器上的进程（文件监控、生命周期规则）不受限制。
+//
+// 思考：只上传目录里，设备 A 上传的文件被设备 B 修改后再上传，B 需要先下载吗？
+// ----------------------------------------
+
+use rustcloud_types::{FolderDirection, SyncDirection};
+
+use crate::db::models::is_same_or_descendant;
+
+/// 配置中的只上传、只下载目录
+#[derive(Debug, Clone, Default)]
+pub struct SyncDirections {
+    folders: Vec<FolderDirection>,
+}
+
+impl SyncDirections {
+    pub fn new(upload_only: &[String], download_only: &[String]) -> Self {
+        let folders = |paths: &[String], direction| {
+            paths
+                .iter()
+                .map(move |folder| FolderDirection {
+                    folder: folder.trim_matches('/').to_string(),
+                    direction,
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut all = folders(upload_only, SyncDirection::UploadOnly);
+        all.extend(folders(download_only, SyncDirection::DownloadOnly));
+        SyncDirections { folders: all }
+    }
+
+    pub fn folders(&self) -> &[FolderDirection] {
+        &self.folders
+    }
+
+    /// 客户端写入 path 会落在只下载目录中时返回该目录
+    pub fn blocks_write(&self, path: &str) -> Option<&FolderDirection> {
+        // 嵌套时最内层的目录决定方向
+        let folder = self
+            .folders
+            .iter()
+            .filter(|folder| folder.covers(path))
+            .max_by_key(|folder| folder.folder.len())?;
+        (folder.direction == SyncDirection::DownloadOnly).then_some(folder)
+    }
+
+    /// 删除 path 会波及的目录：path 在其中，或 path 是它的上级目录
+    pub fn blocks_delete(&self, path: &str) -> Option<&FolderDirection> {
+        let path = path.trim_matches('/');
+        self.folders.iter().find(|folder| {
+            folder.covers(path) || path.is_empty() || is_same_or_descendant(&folder.folder, path)
+        })
+    }
+}
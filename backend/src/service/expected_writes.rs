@@ -0,0 +1,107 @@
+// [知识点 #176] 避免监控回环
+// ----------------------------------------
+// 题目：服务端自己往存储目录写文件，文件监控会怎样？
+//
+// 讲解：
+// 上传、回滚、删除都会改动存储目录下的明文文件，监控随后收到同样的事件，
+// 把服务端刚写的内容当成"外部修改"再处理一遍：重复哈希、重复入库，
+// 与其他写入交错时还可能多出版本，甚至形成 写入 -> 事件 -> 写入 的回环。
+//
+// 做法是写入前先登记"预期的写入"：路径 + 内容哈希（删除则只有路径），带过期时间。
+// 监控处理事件时先查登记表：
+// - 路径和哈希都对得上，说明是自己写的，跳过
+// - 哈希不同，说明写入之后又被外部改过，照常处理
+// - 过期的登记自动失效，漏掉的事件不会让路径永远被忽略
+//
+// 登记必须发生在写入之前，否则事件可能先于登记到达。
+//
+// 思考：为什么比对内容哈希，而不是只看路径？
+// ----------------------------------------
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// 登记的有效期，需覆盖事件去抖的最长等待和排队时间
+pub const EXPECTED_WRITE_TTL: Duration = Duration::from_secs(30);
+
+struct Expected {
+    /// None 表示预期该路径（及其下的所有文件）被删除
+    hash: Option<String>,
+    until: Instant,
+}
+
+/// 服务端自己发起的写入登记表，克隆后共享
+#[derive(Clone)]
+pub struct ExpectedWrites {
+    entries: Arc<Mutex<HashMap<String, Expected>>>,
+    ttl: Duration,
+}
+
+impl Default for ExpectedWrites {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExpectedWrites {
+    pub fn new() -> Self {
+        Self::with_ttl(EXPECTED_WRITE_TTL)
+    }
+
+    pub fn with_ttl(ttl: Duration) -> Self {
+        ExpectedWrites {
+            entries: Arc::new(Mutex::new(HashMap::new())),
+            ttl,
+        }
+    }
+
+    /// 即将把内容为 hash 的文件写到逻辑路径 path
+    pub fn expect_write(&self, path: &str, hash: &str) {
+        self.insert(path, Some(hash.to_string()));
+    }
+
+    /// 即将删除逻辑路径 path 上的文件或目录
+    pub fn expect_removal(&self, path: &str) {
+        self.insert(path, None);
+    }
+
+    fn insert(&self, path: &str, hash: Option<String>) {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, expected| expected.until > now);
+        entries.insert(
+            path.to_string(),
+            Expected {
+                hash,
+                until: now + self.ttl,
+            },
+        );
+    }
+
+    /// 尚未过期的写入登记中 path 的预期内容哈希
+    pub fn expected_hash(&self, path: &str) -> Option<String> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(path)
+            .filter(|expected| expected.until > Instant::now())
+            .and_then(|expected| expected.hash.clone())
+    }
+
+    /// path 本身或它所在的某个目录登记过删除
+    pub fn is_expected_removal(&self, path: &str) -> bool {
+        let now = Instant::now();
+        let entries = self.entries.lock().unwrap();
+        let mut candidate = Some(path);
+        while let Some(current) = candidate {
+            if entries
+                .get(current)
+                .is_some_and(|expected| expected.hash.is_none() && expected.until > now)
+            {
+                return true;
+            }
+            candidate = current.rsplit_once('/').map(|(parent, _)| parent);
+        }
+        false
+    }
+}
@@ -0,0 +1,187 @@
+// [知识点 #171] 跨实例挂载
+// ----------------------------------------
+// 题目：两台自建服务器之间怎样共享一个文件夹，而不用互相同步全部数据？
+//
+// 讲解：
+// 挂载把远程服务器上的一个目录接到本地命名空间的某个路径下：
+// 1. 挂载记录保存在数据库中：本地挂载点、远程地址、远程目录和访问令牌
+// 2. 挂载点下的请求不在本地处理，而是换成远程路径、带上挂载的令牌转发过去
+// 3. 响应中的 "path" 字段从远程路径换回本地路径，客户端看不出差别
+//
+// 本地只保存挂载记录，不缓存远程的元数据和内容，每次读写都实时访问远程。
+// 父目录的列表里补上挂载点本身，浏览时可以像普通目录一样进入。
+//
+// 思考：远程服务器不可用时，挂载点下的请求应该怎样失败？
+// ----------------------------------------
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use rustcloud_types::FileInfo;
+
+use crate::db::{MountRecord, NewMountRecord};
+use crate::error::{Error, Result};
+
+/// 连接远程服务器的超时；内容传输本身不限时
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 已生效的挂载；数据库是权威来源，这里是启动时读入、随增删更新的副本
+#[derive(Clone)]
+pub struct Mounts {
+    mounts: Arc<RwLock<Vec<MountRecord>>>,
+    /// 创建在远程服务器上的分块上传会话 ID -> 挂载 ID
+    uploads: Arc<Mutex<HashMap<String, uuid::Uuid>>>,
+    http: reqwest::Client,
+}
+
+impl Mounts {
+    pub fn new(mounts: Vec<MountRecord>) -> Self {
+        Mounts {
+            mounts: Arc::new(RwLock::new(mounts)),
+            uploads: Arc::new(Mutex::new(HashMap::new())),
+            http: reqwest::Client::builder()
+                .connect_timeout(CONNECT_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mounts.read().unwrap().is_empty()
+    }
+
+    pub fn insert(&self, mount: MountRecord) {
+        self.mounts.write().unwrap().push(mount);
+    }
+
+    pub fn remove(&self, id: uuid::Uuid) {
+        self.mounts.write().unwrap().retain(|m| m.id != id);
+        self.uploads.lock().unwrap().retain(|_, mount| *mount != id);
+    }
+
+    pub fn remember_upload(&self, upload_id: String, mount: &MountRecord) {
+        self.uploads.lock().unwrap().insert(upload_id, mount.id);
+    }
+
+    pub fn forget_upload(&self, upload_id: &str) {
+        self.uploads.lock().unwrap().remove(upload_id);
+    }
+
+    /// 远程会话所属的挂载；本地会话或重启前创建的会话为 None
+    pub fn upload_mount(&self, upload_id: &str) -> Option<MountRecord> {
+        let mount_id = *self.uploads.lock().unwrap().get(upload_id)?;
+        self.mounts
+            .read()
+            .unwrap()
+            .iter()
+            .find(|m| m.id == mount_id)
+            .cloned()
+    }
+
+    /// 路径所在的挂载及其对应的远程路径
+    pub fn resolve(&self, path: &str) -> Option<(MountRecord, String)> {
+        let path = path.trim_matches('/');
+        self.mounts
+            .read()
+            .unwrap()
+            .iter()
+            .find_map(|m| m.remote_for(path).map(|remote| (m.clone(), remote)))
+    }
+
+    /// dir 下一层的挂载点，以目录条目的形式出现在列表中
+    pub fn entries_under(&self, dir: &str) -> Vec<FileInfo> {
+        let dir = dir.trim_matches('/');
+        let prefix = if dir.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", dir)
+        };
+        let mut entries: Vec<FileInfo> = Vec::new();
+        for mount in self.mounts.read().unwrap().iter() {
+            let Some(rest) = mount.path.strip_prefix(&prefix) else {
+                continue;
+            };
+            let child = rest.split('/').next().unwrap_or(rest);
+            let child_path = format!("{}{}", prefix, child);
+            if !child.is_empty() && !entries.iter().any(|e| e.path == child_path) {
+                entries.push(FileInfo::virtual_dir(child.to_string(), child_path));
+            }
+        }
+        entries
+    }
+
+    /// 指向挂载所在服务器的请求，带上挂载的令牌
+    pub fn request(
+        &self,
+        token: Option<&str>,
+        method: reqwest::Method,
+        url: reqwest::Url,
+    ) -> reqwest::RequestBuilder {
+        let request = self.http.request(method, url);
+        match token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    /// 挂载前确认远程目录存在且令牌有效
+    pub async fn probe(&self, mount: &NewMountRecord) -> Result<()> {
+        let mut url = api_url(&mount.url, &["api", "files"])?;
+        url.query_pairs_mut()
+            .append_pair("path", &mount.remote_path);
+        let response = self
+            .request(mount.token.as_deref(), reqwest::Method::GET, url)
+            .timeout(CONNECT_TIMEOUT)
+            .send()
+            .await
+            .map_err(|e| Error::Upstream(e.to_string()))?;
+        let status = response.status();
+        let body: rustcloud_types::ApiResponse = response
+            .json()
+            .await
+            .map_err(|e| Error::Upstream(format!("{}: {}", status, e)))?;
+        match body.data {
+            Some(serde_json::Value::Array(_)) if status.is_success() => Ok(()),
+            _ if status == reqwest::StatusCode::UNAUTHORIZED => Err(Error::Unauthorized(
+                "Remote server rejected the token".to_string(),
+            )),
+            _ => Err(Error::NotFound(mount.remote_path.clone().into())),
+        }
+    }
+}
+
+/// base 加上路径段；各段按 URL 规则转义
+pub fn api_url(base: &str, segments: &[&str]) -> Result<reqwest::Url> {
+    let mut url =
+        reqwest::Url::parse(base).map_err(|e| Error::Config(format!("Invalid URL: {}", e)))?;
+    url.path_segments_mut()
+        .map_err(|_| Error::Config(format!("Invalid URL: {}", base)))?
+        .pop_if_empty()
+        .extend(segments);
+    Ok(url)
+}
+
+/// 把 JSON 中所有 "path" 字段从远程路径换回挂载点下的本地路径
+pub fn localize(value: &mut serde_json::Value, mount: &MountRecord) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, field) in map.iter_mut() {
+                match field {
+                    serde_json::Value::String(path) if key == "path" => {
+                        if let Some(local) = mount.local_for(path) {
+                            *path = local;
+                        }
+                    }
+                    _ => localize(field, mount),
+                }
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                localize(item, mount);
+            }
+        }
+        _ => {}
+    }
+}
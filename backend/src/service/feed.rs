@@ -0,0 +1,73 @@
+// [知识点 #177] 广播通道与实时推送
+// ----------------------------------------
+// 题目：一个文件变更要推给所有在线客户端，用什么通道？
+//
+// 讲解：
+// mpsc 是多发送者、单接收者；这里正好反过来：上传接口和文件监控都会产生变更，
+// 每个 WebSocket 连接都要收到全部变更。tokio::sync::broadcast：
+// - 每个订阅者有自己的读位置，互不影响
+// - 没有订阅者时发送直接丢弃，不占内存
+// - 缓冲区固定大小，慢的订阅者落后太多会收到 Lagged，而不是拖慢发送方
+//
+// 订阅者收到 Lagged 说明漏掉了变更，推送已不可信，
+// 这时应该断开，让客户端重新连接并做一次完整同步。
+//
+// 思考：为什么不为每个连接单独建一个无界队列？
+// ----------------------------------------
+
+use rustcloud_types::{ChangeEvent, ChangeKind};
+use tokio::sync::broadcast;
+
+/// 每个订阅者最多积压的变更数
+const FEED_CAPACITY: usize = 1024;
+
+/// 文件变更的广播源，克隆后共享
+#[derive(Clone)]
+pub struct ChangeFeed {
+    sender: broadcast::Sender<ChangeEvent>,
+}
+
+impl Default for ChangeFeed {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChangeFeed {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(FEED_CAPACITY);
+        ChangeFeed { sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ChangeEvent> {
+        self.sender.subscribe()
+    }
+
+    /// 当前在线的订阅者数
+    pub fn subscribers(&self) -> usize {
+        self.sender.receiver_count()
+    }
+
+    pub fn publish(&self, event: ChangeEvent) {
+        // 没有订阅者时返回错误，直接忽略
+        let _ = self.sender.send(event);
+    }
+
+    pub fn written(&self, kind: ChangeKind, path: &str, hash: Option<String>, version: i32) {
+        self.publish(ChangeEvent {
+            kind,
+            path: path.to_string(),
+            hash,
+            version: Some(version),
+        });
+    }
+
+    pub fn deleted(&self, path: &str) {
+        self.publish(ChangeEvent {
+            kind: ChangeKind::Deleted,
+            path: path.to_string(),
+            hash: None,
+            version: None,
+        });
+    }
+}
@@ -0,0 +1,358 @@
+// [知识点 #172] 访问控制与失败封禁
+// ----------------------------------------
+// 题目：暴露在公网上的实例，怎样挡住撞库和暴力破解？
+//
+// 讲解：
+// 按客户端地址做两层防护：
+// 1. 静态规则：deny 列表中的地址一律拒绝；allow 列表非空时，只放行列表中的地址
+// 2. 动态封禁（类似 fail2ban）：同一地址在窗口期内认证失败达到阈值，
+//    在封禁期内它的所有请求都直接返回 429，不再进入认证逻辑
+//
+// 被拒绝和被封禁的请求写入 audit.jsonl，管理员据此发现攻击来源。
+// 同一地址的同类拦截记录每分钟最多写一条，避免攻击流量把审计日志撑满。
+// 删除时粉碎数据（见 service::shred）和账户数据的导出、删除（见 service::account）
+// 也记在同一个审计日志里；账户删除后，该用户的记录去掉用户名和地址。
+//
+// 封禁只保存在内存中，重启后清空；需要长期拒绝的地址应该写进 deny 列表。
+//
+// 思考：客户端位于同一个 NAT 之后时，按地址封禁会误伤谁？
+// ----------------------------------------
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::config::NetworkConfig;
+use crate::error::{Error, Result};
+
+const AUDIT_FILE: &str = "audit.jsonl";
+
+/// 追加与改写审计日志互斥，改写期间追加的记录不会丢失
+static AUDIT_WRITE: Mutex<()> = Mutex::new(());
+
+/// 同一地址两次同类拦截记录之间的最短间隔
+const AUDIT_THROTTLE_SECS: i64 = 60;
+
+/// 地址或网段，如 "10.0.0.0/8"、"2001:db8::/32"、"127.0.0.1"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNet {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                prefix_matches(u32::from(net).into(), u32::from(ip).into(), 32, self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_matches(u128::from(net), u128::from(ip), 128, self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches(net: u128, ip: u128, bits: u8, prefix: u8) -> bool {
+    if prefix == 0 {
+        return true;
+    }
+    let shift = bits - prefix;
+    net >> shift == ip >> shift
+}
+
+impl FromStr for IpNet {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::Config(format!("Invalid address or network: {}", s));
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let addr = addr.to_canonical();
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|p| *p <= bits)
+                .ok_or_else(invalid)?,
+            None => bits,
+        };
+        Ok(IpNet { addr, prefix })
+    }
+}
+
+/// 对某个地址的判断
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allowed,
+    /// 命中 deny 列表或不在 allow 列表中
+    Denied,
+    /// 封禁到该时间为止
+    Banned(DateTime<Utc>),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Ban {
+    pub ip: IpAddr,
+    pub until: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// 被访问控制规则拒绝
+    Denied,
+    /// 认证失败次数过多，开始封禁
+    Banned,
+    /// 封禁期内的请求被拦截
+    Blocked,
+    /// 删除的数据已被覆写
+    Shredded,
+    /// 用户导出了自己的全部数据
+    Exported,
+    /// 用户删除了账户及其数据
+    Erased,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub at: DateTime<Utc>,
+    /// 后台任务（例如生命周期规则）产生的记录没有客户端地址
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<IpAddr>,
+    /// 发起操作的用户，未启用认证或账户已删除时为空
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    pub event: AuditEvent,
+    pub method: String,
+    pub path: String,
+    /// 补充说明，例如粉碎了多少对象
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+#[derive(Default)]
+struct FirewallState {
+    /// 窗口期内的认证失败时间
+    failures: HashMap<IpAddr, Vec<DateTime<Utc>>>,
+    bans: HashMap<IpAddr, DateTime<Utc>>,
+    last_audited: HashMap<(Option<IpAddr>, AuditEvent), DateTime<Utc>>,
+}
+
+#[derive(Clone)]
+pub struct Firewall {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+    ban_after_failures: u32,
+    failure_window: Duration,
+    ban_duration: Duration,
+    trust_forwarded_for: bool,
+    audit_path: PathBuf,
+    state: Arc<Mutex<FirewallState>>,
+}
+
+impl Firewall {
+    /// 规则写错时拒绝启动，而不是悄悄放行
+    pub fn new(config: &NetworkConfig, storage_path: &std::path::Path) -> Result<Self> {
+        let parse = |entries: &[String]| {
+            entries
+                .iter()
+                .map(|entry| entry.parse())
+                .collect::<Result<Vec<IpNet>>>()
+        };
+        Ok(Firewall {
+            allow: parse(&config.allow)?,
+            deny: parse(&config.deny)?,
+            ban_after_failures: config.ban_after_failures,
+            failure_window: Duration::seconds(config.failure_window_secs as i64),
+            ban_duration: Duration::seconds(config.ban_secs as i64),
+            trust_forwarded_for: config.trust_forwarded_for,
+            audit_path: audit_path(storage_path),
+            state: Arc::new(Mutex::new(FirewallState::default())),
+        })
+    }
+
+    pub fn trust_forwarded_for(&self) -> bool {
+        self.trust_forwarded_for
+    }
+
+    pub fn check(&self, ip: IpAddr, now: DateTime<Utc>) -> Verdict {
+        let ip = ip.to_canonical();
+        if self.deny.iter().any(|net| net.contains(ip))
+            || (!self.allow.is_empty() && !self.allow.iter().any(|net| net.contains(ip)))
+        {
+            return Verdict::Denied;
+        }
+        let mut state = self.state.lock().unwrap();
+        match state.bans.get(&ip) {
+            Some(until) if *until > now => Verdict::Banned(*until),
+            Some(_) => {
+                state.bans.remove(&ip);
+                Verdict::Allowed
+            }
+            None => Verdict::Allowed,
+        }
+    }
+
+    /// 记录一次认证失败；达到阈值时开始封禁并返回封禁截止时间
+    pub fn record_failure(&self, ip: IpAddr, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if self.ban_after_failures == 0 {
+            return None;
+        }
+        let ip = ip.to_canonical();
+        let mut state = self.state.lock().unwrap();
+        let cutoff = now - self.failure_window;
+        let failures = state.failures.entry(ip).or_default();
+        failures.retain(|at| *at > cutoff);
+        failures.push(now);
+        if failures.len() < self.ban_after_failures as usize {
+            return None;
+        }
+        state.failures.remove(&ip);
+        let until = now + self.ban_duration;
+        state.bans.insert(ip, until);
+        Some(until)
+    }
+
+    /// 认证成功后清零失败次数
+    pub fn record_success(&self, ip: IpAddr) {
+        self.state
+            .lock()
+            .unwrap()
+            .failures
+            .remove(&ip.to_canonical());
+    }
+
+    pub fn bans(&self, now: DateTime<Utc>) -> Vec<Ban> {
+        let mut bans: Vec<Ban> = self
+            .state
+            .lock()
+            .unwrap()
+            .bans
+            .iter()
+            .filter(|(_, until)| **until > now)
+            .map(|(ip, until)| Ban {
+                ip: *ip,
+                until: *until,
+            })
+            .collect();
+        bans.sort_by_key(|ban| ban.until);
+        bans
+    }
+
+    /// 提前解除封禁；该地址没有被封禁时返回 false
+    pub fn unban(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        let mut state = self.state.lock().unwrap();
+        state.failures.remove(&ip);
+        state.bans.remove(&ip).is_some()
+    }
+
+    /// 追加一条审计记录；拦截记录按地址限流，其余事件总是记录
+    pub fn audit(&self, entry: AuditEntry) {
+        {
+            let mut state = self.state.lock().unwrap();
+            let throttled = matches!(entry.event, AuditEvent::Denied | AuditEvent::Blocked)
+                && state
+                    .last_audited
+                    .get(&(entry.ip, entry.event))
+                    .is_some_and(|last| entry.at - *last < Duration::seconds(AUDIT_THROTTLE_SECS));
+            if throttled {
+                return;
+            }
+            state.last_audited.insert((entry.ip, entry.event), entry.at);
+        }
+        tracing::warn!(ip = ?entry.ip, event = ?entry.event, "{} {}", entry.method, entry.path);
+        append_audit(&self.audit_path, &entry);
+    }
+
+    /// 最近的 limit 条审计记录，按时间先后
+    pub async fn recent_audit(&self, limit: usize) -> Result<Vec<AuditEntry>> {
+        let entries = read_audit(&self.audit_path).await?;
+        let skip = entries.len().saturating_sub(limit);
+        Ok(entries.into_iter().skip(skip).collect())
+    }
+}
+
+/// 存储目录下的审计日志
+pub fn audit_path(storage_path: &Path) -> PathBuf {
+    storage_path.join(AUDIT_FILE)
+}
+
+/// 审计日志中的全部记录，无法解析的行跳过
+pub async fn read_audit(audit_path: &Path) -> Result<Vec<AuditEntry>> {
+    let content = match tokio::fs::read_to_string(audit_path).await {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// 追加一行审计记录；写入失败只记日志
+pub fn append_audit(audit_path: &Path, entry: &AuditEntry) {
+    let _guard = AUDIT_WRITE.lock().unwrap_or_else(|e| e.into_inner());
+    let written = serde_json::to_vec(entry)
+        .map_err(Error::from)
+        .and_then(|mut line| {
+            use std::io::Write;
+            line.push(b'\n');
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(audit_path)?;
+            Ok(file.write_all(&line)?)
+        });
+    if let Err(e) = written {
+        tracing::warn!("Failed to write audit log: {}", e);
+    }
+}
+
+/// 去掉 username 的记录中的用户名和地址，返回改写的条数；无法解析的行原样保留
+pub fn anonymize_audit(audit_path: &Path, username: &str) -> Result<usize> {
+    let _guard = AUDIT_WRITE.lock().unwrap_or_else(|e| e.into_inner());
+    let content = match std::fs::read_to_string(audit_path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut anonymized = 0;
+    let mut output = String::with_capacity(content.len());
+    for line in content.lines() {
+        match serde_json::from_str::<AuditEntry>(line) {
+            Ok(mut entry)
+                if entry
+                    .user
+                    .as_deref()
+                    .is_some_and(|user| user.eq_ignore_ascii_case(username)) =>
+            {
+                entry.user = None;
+                entry.ip = None;
+                output.push_str(&serde_json::to_string(&entry)?);
+                anonymized += 1;
+            }
+            _ => output.push_str(line),
+        }
+        output.push('\n');
+    }
+    if anonymized > 0 {
+        let tmp = audit_path.with_extension("jsonl.tmp");
+        std::fs::write(&tmp, output)?;
+        std::fs::rename(&tmp, audit_path)?;
+    }
+    Ok(anonymized)
+}
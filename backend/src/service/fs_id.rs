@@ -0,0 +1,60 @@
+// [知识点 #165] inode 与移动检测
+// ----------------------------------------
+// 题目：文件被移到另一个目录，监控收到"删除 a + 新建 b"，怎么知道它们是同一个文件？
+//
+// 讲解：
+// 路径只是目录项里的名字，文件本身由文件系统里的编号标识：
+// - Unix：(st_dev, st_ino)，设备号 + inode 号
+// - Windows：(卷序列号, 文件索引)，NTFS 上相当于 inode
+// 同一卷内的 rename 只改目录项，编号不变；复制得到的是新文件，编号不同
+//
+// 不少场景收不到成对的 rename 事件：跨目录移动、事件队列溢出、
+// 网络盘、编辑器先删后建……记录下每个文件的编号，
+// 出现新路径时先按编号查一下，命中且旧路径已经不存在，就是一次移动，
+// 沿用原来的记录 id 和版本号，而不是删掉历史再从版本 1 开始
+//
+// 编号会被复用：文件删掉后，新文件可能拿到同一个 inode，
+// 所以只在旧路径确实消失时才认定为移动
+//
+// 思考：跨卷移动（先复制再删除）为什么无法这样识别？
+// ----------------------------------------
+
+use std::path::Path;
+
+use crate::db::FsId;
+
+/// 读取文件的文件系统标识，文件不存在或平台不支持时返回 None
+#[cfg(unix)]
+pub fn read(path: &Path) -> Option<FsId> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = std::fs::metadata(path).ok()?;
+    Some(FsId {
+        device: metadata.dev(),
+        inode: metadata.ino(),
+    })
+}
+
+#[cfg(windows)]
+pub fn read(path: &Path) -> Option<FsId> {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Storage::FileSystem::{
+        GetFileInformationByHandle, BY_HANDLE_FILE_INFORMATION,
+    };
+
+    let file = std::fs::File::open(path).ok()?;
+    // SAFETY: 全零是 BY_HANDLE_FILE_INFORMATION 的合法值，句柄在 file 存活期间有效
+    let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { std::mem::zeroed() };
+    if unsafe { GetFileInformationByHandle(file.as_raw_handle() as _, &mut info) } == 0 {
+        return None;
+    }
+    Some(FsId {
+        device: u64::from(info.dwVolumeSerialNumber),
+        inode: (u64::from(info.nFileIndexHigh) << 32) | u64::from(info.nFileIndexLow),
+    })
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn read(_path: &Path) -> Option<FsId> {
+    None
+}
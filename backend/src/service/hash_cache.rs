@@ -0,0 +1,91 @@
+// [知识点 #181] 以 (修改时间, 大小) 缓存哈希
+// ----------------------------------------
+// 题目：文件没变，为什么每次查询文件信息、每个监控事件都要重新读一遍算 SHA-256？
+//
+// 讲解：
+// 读取元数据只要一次 stat，而计算哈希要把整个文件读一遍。
+// 和 make、rsync 的快速检查一样，把 路径 -> (修改时间, 大小, 哈希) 记在内存里：
+// 再次需要哈希时先 stat，修改时间和大小都没变就直接用缓存的值。
+//
+// 只看元数据并不绝对可靠：同一时间粒度内改写成同样大小的内容会被漏掉。
+// 所以文件监控收到外部修改、删除和重命名事件时主动让条目失效，
+// 服务端自己的写入则在写完后重新计算并记下。
+//
+// 启动时可以用元数据中的哈希预热：磁盘上的文件大小与记录一致、
+// 且修改时间不晚于记录的更新时间，说明记录之后没有被改过。
+//
+// 思考：为什么失效时要连同路径下的所有条目一起移除？
+// ----------------------------------------
+
+use std::collections::BTreeMap;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+#[derive(Debug)]
+struct Entry {
+    modified: SystemTime,
+    size: u64,
+    hash: String,
+}
+
+/// 文件哈希缓存，克隆后共享
+#[derive(Debug, Clone, Default)]
+pub struct HashCache {
+    // BTreeMap 中一个目录下的路径排在一起，按前缀失效只需扫描这一段
+    entries: Arc<Mutex<BTreeMap<PathBuf, Entry>>>,
+}
+
+impl HashCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 修改时间和大小都与记下时一致才返回缓存的哈希
+    pub fn get(&self, path: &Path, metadata: &Metadata) -> Option<String> {
+        let modified = metadata.modified().ok()?;
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(path)
+            .filter(|entry| entry.modified == modified && entry.size == metadata.len())
+            .map(|entry| entry.hash.clone())
+    }
+
+    /// metadata 应在读取内容之前取得，读取期间被修改的文件下次不会命中
+    pub fn insert(&self, path: &Path, metadata: &Metadata, hash: &str) {
+        let Ok(modified) = metadata.modified() else {
+            return;
+        };
+        self.entries.lock().unwrap().insert(
+            path.to_path_buf(),
+            Entry {
+                modified,
+                size: metadata.len(),
+                hash: hash.to_string(),
+            },
+        );
+    }
+
+    /// 移除 path 本身及其下所有文件的条目
+    pub fn forget(&self, path: &Path) {
+        let mut entries = self.entries.lock().unwrap();
+        let stale: Vec<PathBuf> = entries
+            .range(path.to_path_buf()..)
+            .map(|(p, _)| p)
+            .take_while(|p| p.starts_with(path))
+            .cloned()
+            .collect();
+        for p in stale {
+            entries.remove(&p);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,87 @@
+// [知识点 #182] 读取时校验内容寻址对象
+// ----------------------------------------
+// 题目：对象文件名就是内容的哈希，读出来的字节一定和哈希对得上吗？
+//
+// 讲解：
+// 磁盘静默损坏（bit rot）、被误改的对象文件、写到一半的硬件故障，
+// 都会让 objects/ab/cdef... 里的字节与文件名不再一致，而读取时不会报任何错。
+// 内容寻址的好处是校验不需要额外的元数据：重新算一遍哈希，与文件名比较即可。
+//
+// 代价是读取时多算一次哈希，所以按大小区别对待：
+// - 小对象：读取前直接校验，损坏的内容不会发给客户端
+// - 大对象：照常发送，同时在后台校验；每个对象在进程生命周期内只校验一次
+//
+// 校验失败的对象记下来等待修复：读取直接返回 Corrupted，不再发送错误内容；
+// 同样内容再次上传时不再因为"对象已存在"跳过写入，而是覆盖损坏的对象文件。
+//
+// 思考：只在读取时校验，很久没人读的对象损坏了怎么发现？
+// ----------------------------------------
+
+use std::collections::{BTreeSet, HashSet};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Default)]
+struct State {
+    /// 后台校验过且没有问题的大对象
+    verified: HashSet<String>,
+    /// 正在后台校验的大对象
+    pending: HashSet<String>,
+    /// 校验失败、等待修复的对象
+    corrupted: BTreeSet<String>,
+}
+
+/// 对象的校验状态，克隆后共享
+#[derive(Debug, Clone, Default)]
+pub struct ObjectIntegrity {
+    state: Arc<Mutex<State>>,
+}
+
+impl ObjectIntegrity {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_corrupted(&self, hash: &str) -> bool {
+        self.state.lock().unwrap().corrupted.contains(hash)
+    }
+
+    /// 大对象需要后台校验时返回 true，并记为校验中；已校验过或正在校验时返回 false
+    pub fn begin_background(&self, hash: &str) -> bool {
+        let mut state = self.state.lock().unwrap();
+        !state.verified.contains(hash) && state.pending.insert(hash.to_string())
+    }
+
+    pub fn verified(&self, hash: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.pending.remove(hash);
+        state.verified.insert(hash.to_string());
+    }
+
+    /// 后台校验没能完成（例如读取出错），下次读取时重新校验
+    pub fn abandoned(&self, hash: &str) {
+        self.state.lock().unwrap().pending.remove(hash);
+    }
+
+    pub fn corrupted(&self, hash: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.pending.remove(hash);
+        state.verified.remove(hash);
+        state.corrupted.insert(hash.to_string());
+    }
+
+    /// 对象文件已用正确的内容重写
+    pub fn repaired(&self, hash: &str) {
+        self.state.lock().unwrap().corrupted.remove(hash);
+    }
+
+    /// 等待修复的对象，按哈希排序
+    pub fn corrupted_objects(&self) -> Vec<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .corrupted
+            .iter()
+            .cloned()
+            .collect()
+    }
+}
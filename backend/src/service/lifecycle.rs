@@ -0,0 +1,271 @@
+// [知识点 #154] 生命周期规则：先规划，再执行
+// ----------------------------------------
+// 题目：自动删除/归档这类"危险"操作，怎样让用户放心开启？
+//
+// 讲解：
+// 把执行拆成两步：
+// 1. plan()：只读地扫描所有文件，算出"将要做什么"
+// 2. apply()：按计划逐条执行
+//
+// 预览接口只调用 plan()，用户可以先看清楚会删掉哪些文件再启用规则；
+// 后台任务周期性地 plan() + apply()
+//
+// 规则约定：
+// - 一个文件只受最具体（目录最长）的那条规则管理
+// - 删除优先于归档
+// - 处于法律保留中的文件和只追加目录中的文件一律跳过
+// - 已经在归档目录中的文件不再归档
+// - 启用命名空间时，用户创建的规则只作用于该用户自己的文件
+//
+// 思考：计划生成之后、执行之前文件又被修改了，应该怎么处理？
+// ----------------------------------------
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+
+use crate::db::{FileRecord, LifecycleRule, Repository};
+use crate::error::Result;
+use crate::service::append_only::AppendOnly;
+use crate::service::namespace::Namespace;
+use crate::service::shred::Shredder;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum LifecycleAction {
+    Delete,
+    Archive { destination: String },
+}
+
+/// 计划中的一条操作
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedAction {
+    pub rule_id: uuid::Uuid,
+    pub file_id: uuid::Uuid,
+    pub path: String,
+    #[serde(flatten)]
+    pub action: LifecycleAction,
+    /// 触发条件对应的天数（未修改天数或未访问天数）
+    pub age_days: i64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct LifecycleReport {
+    pub actions: Vec<PlannedAction>,
+    pub applied: usize,
+    pub failed: usize,
+}
+
+#[derive(Clone)]
+pub struct LifecycleService {
+    repository: Repository,
+    storage_path: PathBuf,
+    append_only: AppendOnly,
+    /// 开启粉碎时，删除动作覆写文件和不再被引用的对象
+    shredder: Option<Shredder>,
+}
+
+impl LifecycleService {
+    pub fn new(repository: Repository, storage_path: PathBuf) -> Self {
+        LifecycleService {
+            repository,
+            storage_path,
+            append_only: AppendOnly::default(),
+            shredder: None,
+        }
+    }
+
+    pub fn with_append_only(mut self, append_only: AppendOnly) -> Self {
+        self.append_only = append_only;
+        self
+    }
+
+    pub fn with_shredder(mut self, shredder: Option<Shredder>) -> Self {
+        self.shredder = shredder;
+        self
+    }
+
+    /// 后台定时执行规则，直到收到关闭信号
+    pub async fn run_scheduler(
+        self,
+        interval: Duration,
+        shutdown: CancellationToken,
+    ) -> Result<()> {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = shutdown.cancelled() => return Ok(()),
+            }
+            match self.run(self.repository.clock().now()).await {
+                Ok(report) if !report.actions.is_empty() => tracing::info!(
+                    "Lifecycle run: {} applied, {} failed",
+                    report.applied,
+                    report.failed
+                ),
+                Ok(_) => {}
+                Err(e) => tracing::warn!("Lifecycle run failed: {}", e),
+            }
+        }
+    }
+
+    /// 只计算不执行，供预览使用
+    pub async fn plan(&self, now: DateTime<Utc>) -> Result<Vec<PlannedAction>> {
+        self.plan_rules(now, None).await
+    }
+
+    /// 只计算某个用户的规则
+    pub async fn plan_for(
+        &self,
+        now: DateTime<Utc>,
+        owner: uuid::Uuid,
+    ) -> Result<Vec<PlannedAction>> {
+        self.plan_rules(now, Some(owner)).await
+    }
+
+    async fn plan_rules(
+        &self,
+        now: DateTime<Utc>,
+        owner: Option<uuid::Uuid>,
+    ) -> Result<Vec<PlannedAction>> {
+        let mut rules = self.repository.list_lifecycle_rules().await?;
+        if let Some(owner) = owner {
+            rules.retain(|rule| rule.owner == Some(owner));
+        }
+        if rules.is_empty() {
+            return Ok(Vec::new());
+        }
+        let holds = self.repository.list_legal_holds().await?;
+
+        let mut files = self.repository.list_files().await?;
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok(files
+            .iter()
+            .filter(|file| !holds.iter().any(|h| h.covers(&file.path)))
+            .filter(|file| !self.append_only.covers(&file.path))
+            .filter_map(|file| {
+                let rule = rules
+                    .iter()
+                    .filter(|r| r.applies_to(&file.path))
+                    .filter(|r| {
+                        r.owner
+                            .is_none_or(|owner| Namespace::for_user(owner).contains(&file.path))
+                    })
+                    .max_by_key(|r| r.folder.len())?;
+                plan_file(rule, file, now)
+            })
+            .collect())
+    }
+
+    /// 规划并执行，单条失败不影响其余操作
+    pub async fn run(&self, now: DateTime<Utc>) -> Result<LifecycleReport> {
+        self.run_rules(now, None).await
+    }
+
+    /// 只执行某个用户的规则
+    pub async fn run_for(&self, now: DateTime<Utc>, owner: uuid::Uuid) -> Result<LifecycleReport> {
+        self.run_rules(now, Some(owner)).await
+    }
+
+    async fn run_rules(
+        &self,
+        now: DateTime<Utc>,
+        owner: Option<uuid::Uuid>,
+    ) -> Result<LifecycleReport> {
+        let actions = self.plan_rules(now, owner).await?;
+        let mut report = LifecycleReport::default();
+
+        for planned in &actions {
+            match self.apply(planned, now).await {
+                Ok(()) => report.applied += 1,
+                Err(e) => {
+                    tracing::warn!("Lifecycle action on {} failed: {}", planned.path, e);
+                    report.failed += 1;
+                }
+            }
+        }
+
+        report.actions = actions;
+        Ok(report)
+    }
+
+    async fn apply(&self, planned: &PlannedAction, now: DateTime<Utc>) -> Result<()> {
+        let source = self.storage_path.join(&planned.path);
+        match &planned.action {
+            LifecycleAction::Delete => match &self.shredder {
+                Some(shredder) => {
+                    let record = self.repository.get_file_by_id(planned.file_id).await?;
+                    let objects = shredder.objects_of(std::slice::from_ref(&record)).await;
+                    self.repository.delete_file(planned.file_id).await?;
+                    let file = source.is_file().then_some(source.as_path());
+                    let shredded = shredder.shred(file, &objects).await?;
+                    shredder.record(now, None, None, "LIFECYCLE", &planned.path, shredded);
+                }
+                None => {
+                    self.repository.delete_file(planned.file_id).await?;
+                    if source.is_file() {
+                        tokio::fs::remove_file(&source).await?;
+                    }
+                }
+            },
+            LifecycleAction::Archive { destination } => {
+                self.repository
+                    .move_file(planned.file_id, destination)
+                    .await?;
+                if source.is_file() {
+                    let target = self.storage_path.join(destination);
+                    if let Some(parent) = target.parent() {
+                        tokio::fs::create_dir_all(parent).await?;
+                    }
+                    tokio::fs::rename(&source, &target).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn plan_file(rule: &LifecycleRule, file: &FileRecord, now: DateTime<Utc>) -> Option<PlannedAction> {
+    let modified_days = (now - file.updated_at).num_days();
+    if rule
+        .delete_after_days
+        .is_some_and(|days| modified_days >= days as i64)
+    {
+        return Some(PlannedAction {
+            rule_id: rule.id,
+            file_id: file.id,
+            path: file.path.clone(),
+            action: LifecycleAction::Delete,
+            age_days: modified_days,
+        });
+    }
+
+    let (days, archive_to) = rule.archive_after_days.zip(rule.archive_to.as_deref())?;
+    let in_archive = file
+        .path
+        .strip_prefix(archive_to)
+        .is_some_and(|rest| rest.starts_with('/'));
+    let idle_days = (now - file.last_used_at()).num_days();
+    if in_archive || idle_days < days as i64 {
+        return None;
+    }
+
+    let relative = file
+        .path
+        .strip_prefix(&rule.folder)
+        .unwrap_or(&file.path)
+        .trim_start_matches('/');
+    Some(PlannedAction {
+        rule_id: rule.id,
+        file_id: file.id,
+        path: file.path.clone(),
+        action: LifecycleAction::Archive {
+            destination: format!("{}/{}", archive_to, relative),
+        },
+        age_days: idle_days,
+    })
+}
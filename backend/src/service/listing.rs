@@ -0,0 +1,155 @@
+// [知识点 #164] 目录列表缓存
+// ----------------------------------------
+// 题目：一个目录里有 10 万个文件，每次打开都要 read_dir + stat 10 万次吗？
+//
+// 讲解：
+// 1. read_dir 和 metadata 都是阻塞的系统调用，直接在 async handler 里调用
+//    会占住 tokio 的工作线程，其他请求跟着变慢；放进 spawn_blocking 执行
+// 2. 目录增删、重命名条目时，目录自身的修改时间（mtime）会变化。
+//    用 (目录, mtime) 作为缓存键：mtime 没变就直接复用上次的结果
+// 3. 文件系统的时间戳精度有限（FAT 是 2 秒），刚修改过的目录，
+//    同一时间片内的下一次修改可能不会改变 mtime。所以 mtime 离现在
+//    太近的目录不缓存，等它"稳定"之后再说
+//
+// 上传通过"写临时文件再 rename"落盘，rename 会更新目录 mtime，缓存随之失效
+//
+// 思考：外部程序原地改写了目录里某个文件的内容，缓存里的 size 会过期吗？
+// ----------------------------------------
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::api::routes::FileInfo;
+use crate::error::{Error, Result};
+
+/// 最多缓存的目录数，超过时淘汰最久未使用的
+const MAX_CACHED_DIRECTORIES: usize = 64;
+
+/// mtime 距今不足该时长的目录不缓存
+const MTIME_SETTLE: Duration = Duration::from_secs(2);
+
+struct CachedListing {
+    modified: SystemTime,
+    files: Arc<Vec<FileInfo>>,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<PathBuf, CachedListing>,
+    clock: u64,
+}
+
+/// 按目录 mtime 失效的列表缓存，克隆后共享
+#[derive(Clone, Default)]
+pub struct DirectoryCache {
+    state: Arc<Mutex<CacheState>>,
+}
+
+impl DirectoryCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 列出 target 下的直接子项，path 为相对 base 的路径
+    pub async fn list(&self, target: &Path, base: &Path) -> Result<Arc<Vec<FileInfo>>> {
+        let metadata = match tokio::fs::metadata(target).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(Error::NotFound(target.to_path_buf()))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if !metadata.is_dir() {
+            return Err(Error::InvalidPath("Not a directory".to_string()));
+        }
+        let modified = metadata.modified()?;
+
+        if let Some(files) = self.lookup(target, modified) {
+            return Ok(files);
+        }
+
+        let (dir, root) = (target.to_path_buf(), base.to_path_buf());
+        let files = tokio::task::spawn_blocking(move || read_directory(&dir, &root))
+            .await
+            .map_err(|e| Error::Io(std::io::Error::other(e)))??;
+        let files = Arc::new(files);
+
+        let settled = SystemTime::now()
+            .duration_since(modified)
+            .is_ok_and(|age| age >= MTIME_SETTLE);
+        if settled {
+            self.insert(target, modified, files.clone());
+        }
+        Ok(files)
+    }
+
+    fn lookup(&self, target: &Path, modified: SystemTime) -> Option<Arc<Vec<FileInfo>>> {
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let now = state.clock;
+        let cached = state.entries.get_mut(target)?;
+        if cached.modified != modified {
+            return None;
+        }
+        cached.last_used = now;
+        Some(cached.files.clone())
+    }
+
+    fn insert(&self, target: &Path, modified: SystemTime, files: Arc<Vec<FileInfo>>) {
+        let mut state = self.state.lock().unwrap();
+        if state.entries.len() >= MAX_CACHED_DIRECTORIES && !state.entries.contains_key(target) {
+            let oldest = state
+                .entries
+                .iter()
+                .min_by_key(|(_, cached)| cached.last_used)
+                .map(|(path, _)| path.clone());
+            if let Some(oldest) = oldest {
+                state.entries.remove(&oldest);
+            }
+        }
+        let last_used = state.clock;
+        state.entries.insert(
+            target.to_path_buf(),
+            CachedListing {
+                modified,
+                files,
+                last_used,
+            },
+        );
+    }
+}
+
+fn read_directory(target: &Path, base: &Path) -> Result<Vec<FileInfo>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(target)? {
+        let entry = entry?;
+        let path = entry.path();
+        let metadata = entry.metadata()?;
+
+        let relative_path = path
+            .strip_prefix(base)
+            .ok()
+            .and_then(|p| p.to_str())
+            .map(|s| s.to_string())
+            .unwrap_or_default();
+
+        files.push(FileInfo {
+            name: entry.file_name().to_string_lossy().to_string(),
+            path: relative_path,
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata.modified().ok().map(|t| {
+                let datetime: chrono::DateTime<chrono::Utc> = t.into();
+                datetime.to_rfc3339()
+            }),
+            hash: None,
+            version: None,
+            deduplicated: false,
+            metadata: BTreeMap::new(),
+        });
+    }
+    Ok(files)
+}
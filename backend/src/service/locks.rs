@@ -0,0 +1,52 @@
+// [知识点 #161] 按路径加锁
+// ----------------------------------------
+// 题目：Repository 内部已经有 Mutex，为什么上传还会出现竞态？
+//
+// 讲解：
+// 一次上传包含多步：查询现有记录 → 写明文文件 → 写对象 → 更新记录。
+// 每一步单独是原子的，但两个请求的步骤可以交错：
+//   A 写文件 → B 写文件 → B 更新记录 → A 更新记录
+// 结果记录里是 A 的哈希，磁盘上却是 B 的内容。
+//
+// 解决办法是让同一路径上的写操作整体串行，不同路径仍然并发：
+// - 每个路径对应一个 tokio::sync::Mutex<()>
+// - 表里只保存 Weak，没有请求持有时自动回收，不会无限增长
+//
+// 思考：为什么这里用 tokio 的 Mutex 而不是 std 的？
+// ----------------------------------------
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+
+use tokio::sync::OwnedMutexGuard;
+
+type PathLock = tokio::sync::Mutex<()>;
+
+/// 同一逻辑路径上的写操作互斥
+#[derive(Clone, Default)]
+pub struct PathLocks {
+    locks: Arc<Mutex<HashMap<String, Weak<PathLock>>>>,
+}
+
+impl PathLocks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 持有返回的 guard 期间，其他请求无法获得同一路径的锁
+    pub async fn lock(&self, path: &str) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.locks.lock().unwrap();
+            locks.retain(|_, lock| lock.strong_count() > 0);
+            match locks.get(path).and_then(Weak::upgrade) {
+                Some(lock) => lock,
+                None => {
+                    let lock = Arc::new(PathLock::new(()));
+                    locks.insert(path.to_string(), Arc::downgrade(&lock));
+                    lock
+                }
+            }
+        };
+        lock.lock_owned().await
+    }
+}
@@ -0,0 +1,198 @@
+// [知识点 #149] 解析二进制格式：JPEG 段与 EXIF/TIFF 目录
+// ----------------------------------------
+// 题目：不引入图像库，如何拿到照片的拍摄时间和尺寸？
+//
+// 讲解：
+// JPEG 由一串段（segment）组成，每段以 0xFF + 标记字节开头，后跟 2 字节大端长度：
+// - APP1 (0xE1) 段以 "Exif\0\0" 开头，其后是一个完整的 TIFF 结构
+// - SOF0..SOF15 (0xC0..0xCF，除 C4/C8/CC) 段记录图像高度和宽度
+// - 遇到 SOS (0xDA) 后就是压缩数据，元数据已全部读完
+//
+// TIFF 结构：
+// 1. 头部 "II"（小端）或 "MM"（大端），随后是魔数 42 和第一个 IFD 的偏移
+// 2. IFD 是一个条目表：条目数 + N 个 12 字节条目（tag、类型、数量、值或偏移）
+// 3. IFD0 中的 0x8769 指向 Exif 子目录，其中 0x9003 是 DateTimeOriginal
+//
+// 所有偏移都来自不可信的输入，每次读取都必须检查边界
+//
+// 思考：HEIC/RAW 格式的元数据放在哪里？
+// ----------------------------------------
+
+use chrono::NaiveDateTime;
+
+use crate::db::{FileRecord, MediaMetadata};
+
+const TAG_DATE_TIME: u16 = 0x0132;
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+
+/// 按扩展名判断是否为支持提取元数据的图片
+pub fn is_photo(path: &str) -> bool {
+    let ext = path.rsplit('.').next().unwrap_or("").to_ascii_lowercase();
+    matches!(ext.as_str(), "jpg" | "jpeg" | "png")
+}
+
+/// 从图片内容中提取尺寸和拍摄时间，非图片或无法解析时返回 None
+pub fn extract_metadata(path: &str, content: &[u8]) -> Option<MediaMetadata> {
+    if !is_photo(path) {
+        return None;
+    }
+    if content.starts_with(&[0xFF, 0xD8]) {
+        Some(parse_jpeg(content))
+    } else if content.starts_with(b"\x89PNG\r\n\x1a\n") {
+        parse_png(content)
+    } else {
+        None
+    }
+}
+
+/// 按日期归档时使用的时间：优先 EXIF 拍摄时间，没有时退回上传时间
+pub fn capture_date(record: &FileRecord) -> Option<NaiveDateTime> {
+    let media = record.media.as_ref()?;
+    Some(
+        media
+            .taken_at
+            .unwrap_or_else(|| record.created_at.naive_utc()),
+    )
+}
+
+fn parse_jpeg(content: &[u8]) -> MediaMetadata {
+    let mut meta = MediaMetadata {
+        width: None,
+        height: None,
+        taken_at: None,
+    };
+
+    let mut pos = 2;
+    while pos + 4 <= content.len() {
+        if content[pos] != 0xFF {
+            break;
+        }
+        let marker = content[pos + 1];
+        // 填充字节
+        if marker == 0xFF {
+            pos += 1;
+            continue;
+        }
+        // 没有长度字段的独立标记
+        if marker == 0x01 || (0xD0..=0xD7).contains(&marker) {
+            pos += 2;
+            continue;
+        }
+        if marker == 0xD9 || marker == 0xDA {
+            break;
+        }
+
+        let len = u16::from_be_bytes([content[pos + 2], content[pos + 3]]) as usize;
+        let Some(data) = content.get(pos + 4..pos + 2 + len) else {
+            break;
+        };
+
+        match marker {
+            0xE1 if data.starts_with(b"Exif\0\0") => {
+                meta.taken_at = parse_exif_date(&data[6..]);
+            }
+            0xC0..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) && data.len() >= 5 => {
+                meta.height = Some(u16::from_be_bytes([data[1], data[2]]) as u32);
+                meta.width = Some(u16::from_be_bytes([data[3], data[4]]) as u32);
+            }
+            _ => {}
+        }
+
+        pos += 2 + len;
+    }
+
+    meta
+}
+
+fn parse_png(content: &[u8]) -> Option<MediaMetadata> {
+    // 签名之后第一个块必须是 IHDR：长度(4) + 类型(4) + 宽(4) + 高(4)
+    if content.get(12..16)? != b"IHDR" {
+        return None;
+    }
+    let width = u32::from_be_bytes(content.get(16..20)?.try_into().ok()?);
+    let height = u32::from_be_bytes(content.get(20..24)?.try_into().ok()?);
+    Some(MediaMetadata {
+        width: Some(width),
+        height: Some(height),
+        taken_at: None,
+    })
+}
+
+/// TIFF 结构的读取器，按头部声明的字节序解码
+struct Tiff<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let little_endian = match data.get(0..2)? {
+            b"II" => true,
+            b"MM" => false,
+            _ => return None,
+        };
+        let tiff = Tiff {
+            data,
+            little_endian,
+        };
+        (tiff.u16_at(2)? == 42).then_some(tiff)
+    }
+
+    fn u16_at(&self, offset: usize) -> Option<u16> {
+        let bytes: [u8; 2] = self.data.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    fn u32_at(&self, offset: usize) -> Option<u32> {
+        let bytes: [u8; 4] = self.data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    /// 在 IFD 中查找 tag，返回条目的起始位置
+    fn find_entry(&self, ifd: usize, tag: u16) -> Option<usize> {
+        let count = self.u16_at(ifd)? as usize;
+        (0..count)
+            .map(|i| ifd + 2 + i * 12)
+            .find(|&entry| self.u16_at(entry) == Some(tag))
+    }
+
+    fn ascii(&self, entry: usize) -> Option<&'a str> {
+        let count = self.u32_at(entry + 4)? as usize;
+        // 不超过 4 字节的值直接内联在条目中
+        let start = if count <= 4 {
+            entry + 8
+        } else {
+            self.u32_at(entry + 8)? as usize
+        };
+        let bytes = self.data.get(start..start.checked_add(count)?)?;
+        std::str::from_utf8(bytes)
+            .ok()
+            .map(|s| s.trim_end_matches('\0'))
+    }
+}
+
+fn parse_exif_date(data: &[u8]) -> Option<NaiveDateTime> {
+    let tiff = Tiff::new(data)?;
+    let ifd0 = tiff.u32_at(4)? as usize;
+
+    let original = tiff
+        .find_entry(ifd0, TAG_EXIF_IFD)
+        .and_then(|entry| tiff.u32_at(entry + 8))
+        .and_then(|exif_ifd| tiff.find_entry(exif_ifd as usize, TAG_DATE_TIME_ORIGINAL))
+        .and_then(|entry| tiff.ascii(entry));
+    let value = original.or_else(|| {
+        tiff.find_entry(ifd0, TAG_DATE_TIME)
+            .and_then(|entry| tiff.ascii(entry))
+    })?;
+
+    NaiveDateTime::parse_from_str(value, "%Y:%m:%d %H:%M:%S").ok()
+}
@@ -1,3 +1,37 @@
+pub mod access;
+pub mod account;
+pub mod append_only;
+pub mod auth;
+pub mod bandwidth;
+pub mod changes;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod clock;
+pub mod compression;
+pub mod direction;
+pub mod expected_writes;
+pub mod federation;
+pub mod feed;
+pub mod firewall;
+pub mod fs_id;
+pub mod hash_cache;
+pub mod integrity;
+pub mod lifecycle;
+pub mod listing;
+pub mod locks;
+pub mod media;
+pub mod namespace;
+pub mod notify;
+pub mod preview;
+pub mod proxy;
+pub mod quota;
+pub mod rebuild;
+pub mod reputation;
+pub mod share;
+pub mod shred;
 pub mod storage;
+pub mod supervisor;
 pub mod sync;
+pub mod trash;
+pub mod uploads;
 pub mod version;
@@ -0,0 +1,127 @@
+// [知识点 #194] 多用户命名空间：把每个用户关进自己的子目录
+// ----------------------------------------
+// 题目：几个人共用一台服务端，怎样让每个人只看到自己的文件？
+//
+// 讲解：
+// 给每条记录加上所有者、在每个查询里按所有者过滤，要改动所有读写文件的代码，
+// 漏掉一处就会泄露别人的文件。这里换一个思路：
+// - 每个用户的文件放在 users/{用户 ID}/ 下，存储目录、对象引用和记录都是普通的路径
+// - 请求进来时把用户给出的路径换成命名空间内的完整路径，响应出去时再换回来
+// - 列表中不在命名空间内的条目直接去掉
+// handler 内部照常使用完整路径，原有的锁、版本、回收站、同步计划都不用区分用户。
+//
+// 目录名用用户 ID 而不是用户名：用户名可以包含 "/" 等字符，ID 不会变也不会冲突。
+//
+// 思考：管理员需要跨用户查看文件时，应该绕过改写，还是另开一组接口？
+// ----------------------------------------
+
+use uuid::Uuid;
+
+/// 各用户命名空间所在的目录
+pub const USERS_DIR: &str = "users";
+
+/// 响应中会被改写的路径字段
+const PATH_KEYS: &[&str] = &[
+    "path",
+    "to",
+    "moved_to",
+    "conflict_path",
+    "copy_path",
+    "folder",
+    "archive_to",
+    "destination",
+];
+
+/// 一个用户能看到的那部分文件树
+#[derive(Debug, Clone)]
+pub struct Namespace {
+    pub user: Uuid,
+    root: String,
+}
+
+impl Namespace {
+    pub fn for_user(user: Uuid) -> Self {
+        Namespace {
+            user,
+            root: format!("{}/{}", USERS_DIR, user),
+        }
+    }
+
+    /// 命名空间根目录的逻辑路径，如 "users/{id}"
+    pub fn root(&self) -> &str {
+        &self.root
+    }
+
+    /// 用户给出的路径在存储中的完整路径；空路径即根目录
+    pub fn scope(&self, path: &str) -> String {
+        let path = path.trim_start_matches('/');
+        if path.is_empty() {
+            self.root.clone()
+        } else {
+            format!("{}/{}", self.root, path)
+        }
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.localize(path).is_some()
+    }
+
+    /// 完整路径换回用户看到的路径；不在命名空间内时返回 None
+    pub fn localize(&self, path: &str) -> Option<String> {
+        let rest = path.strip_prefix(&self.root)?;
+        if rest.is_empty() {
+            return Some(String::new());
+        }
+        rest.strip_prefix('/').map(str::to_string)
+    }
+}
+
+/// 未启用命名空间时所有路径都可见
+pub fn visible(namespace: Option<&Namespace>, path: &str) -> bool {
+    namespace.is_none_or(|namespace| namespace.contains(path))
+}
+
+/// 把 JSON 中的路径换成用户看到的路径，数组中路径不在命名空间内的条目去掉；
+/// 错误信息中的完整路径也一并去掉前缀
+pub fn localize_json(value: &mut serde_json::Value, namespace: &Namespace) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, field) in map.iter_mut() {
+                match field {
+                    serde_json::Value::String(path) if PATH_KEYS.contains(&key.as_str()) => {
+                        if let Some(local) = namespace.localize(path) {
+                            *path = local;
+                        }
+                    }
+                    serde_json::Value::String(message) if key == "error" || key == "message" => {
+                        *message = message.replace(&format!("{}/", namespace.root()), "");
+                    }
+                    serde_json::Value::Array(paths) if key == "paths" => {
+                        paths.retain_mut(|path| {
+                            match path.as_str().map(|p| namespace.localize(p)) {
+                                Some(Some(local)) => {
+                                    *path = serde_json::Value::String(local);
+                                    true
+                                }
+                                Some(None) => false,
+                                None => true,
+                            }
+                        });
+                    }
+                    _ => localize_json(field, namespace),
+                }
+            }
+        }
+        serde_json::Value::Array(items) => {
+            items.retain(|item| {
+                item.get("path")
+                    .and_then(|path| path.as_str())
+                    .is_none_or(|path| namespace.contains(path))
+            });
+            for item in items {
+                localize_json(item, namespace);
+            }
+        }
+        _ => {}
+    }
+}
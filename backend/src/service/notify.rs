@@ -0,0 +1,295 @@
+// [知识点 #150] 事件通知：规则与渠道分离
+// ----------------------------------------
+// 题目：配额快满、同步失败时，怎样把消息推送给用户？
+//
+// 讲解：
+// 通知分三层：
+// 1. 事件（Notification）：业务代码只负责报告"发生了什么"
+// 2. 规则（NotificationRule）：用户决定关心哪些事件、阈值是多少
+// 3. 渠道（NotificationChannel）：webhook 或邮件，负责投递
+//
+// 业务代码调用 notify() 后立即返回，投递在后台任务中完成：
+// 外部服务慢或不可用时不会拖慢上传、同步等请求，失败只记日志
+//
+// 带阈值的规则只在"越过"阈值的那一刻触发，
+// 否则超过阈值之后的每一次上传都会再发一封邮件
+//
+// 思考：投递失败需要重试吗？重试时如何避免重复通知？
+// ----------------------------------------
+
+use std::time::Duration;
+
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::Serialize;
+use tokio_util::task::TaskTracker;
+use tracing::Instrument;
+
+use crate::config::SmtpConfig;
+use crate::db::{NotificationChannel, NotificationEvent, NotificationRule, Repository};
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 业务代码报告的一次事件
+#[derive(Debug, Clone)]
+pub enum Notification {
+    DeviceRegistered {
+        device_name: String,
+    },
+    /// failures 为该设备最近 24 小时的失败次数（含本次）
+    SyncFailures {
+        device_name: String,
+        failures: usize,
+    },
+    /// 一次上传前后的已用空间
+    StorageNearlyFull {
+        used_before: u64,
+        used_after: u64,
+    },
+    ShareAccessed {
+        path: String,
+        ip: Option<String>,
+    },
+    FileChanged {
+        path: String,
+        change: FileChange,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileChange {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl Notification {
+    pub fn event(&self) -> NotificationEvent {
+        match self {
+            Notification::DeviceRegistered { .. } => NotificationEvent::DeviceRegistered,
+            Notification::SyncFailures { .. } => NotificationEvent::SyncFailures,
+            Notification::StorageNearlyFull { .. } => NotificationEvent::StorageNearlyFull,
+            Notification::ShareAccessed { .. } => NotificationEvent::ShareAccessed,
+            Notification::FileChanged { .. } => NotificationEvent::FileChanged,
+        }
+    }
+
+    /// 规则是否应该被本次事件触发
+    pub fn matches(&self, rule: &NotificationRule) -> bool {
+        if !rule.enabled || rule.event != self.event() {
+            return false;
+        }
+        match self {
+            Notification::DeviceRegistered { .. } | Notification::ShareAccessed { .. } => true,
+            Notification::SyncFailures { failures, .. } => {
+                *failures as u64 == rule.threshold.unwrap_or(1)
+            }
+            Notification::StorageNearlyFull {
+                used_before,
+                used_after,
+            } => rule
+                .threshold
+                .is_some_and(|limit| *used_before < limit && *used_after >= limit),
+            Notification::FileChanged { path, .. } => rule.covers_path(path),
+        }
+    }
+
+    pub fn subject(&self) -> String {
+        match self {
+            Notification::DeviceRegistered { device_name } => {
+                format!("New device registered: {}", device_name)
+            }
+            Notification::SyncFailures { device_name, .. } => {
+                format!("Sync failures on {}", device_name)
+            }
+            Notification::StorageNearlyFull { .. } => "Storage nearly full".to_string(),
+            Notification::ShareAccessed { path, .. } => format!("Shared file accessed: {}", path),
+            Notification::FileChanged { path, change } => {
+                let verb = match change {
+                    FileChange::Created => "created",
+                    FileChange::Updated => "updated",
+                    FileChange::Deleted => "deleted",
+                };
+                format!("File {}: {}", verb, path)
+            }
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Notification::DeviceRegistered { device_name } => format!(
+                "A new device named \"{}\" was registered with your RustCloud server.",
+                device_name
+            ),
+            Notification::SyncFailures {
+                device_name,
+                failures,
+            } => format!(
+                "Device \"{}\" has failed to sync {} time(s) in the last 24 hours.",
+                device_name, failures
+            ),
+            Notification::StorageNearlyFull { used_after, .. } => {
+                format!("Stored files now use {} bytes.", used_after)
+            }
+            Notification::ShareAccessed { path, ip } => format!(
+                "The share link for \"{}\" was accessed from {}.",
+                path,
+                ip.as_deref().unwrap_or("an unknown address")
+            ),
+            Notification::FileChanged { .. } => self.subject(),
+        }
+    }
+}
+
+/// webhook 请求体
+#[derive(Debug, Serialize)]
+struct WebhookPayload {
+    event: NotificationEvent,
+    subject: String,
+    message: String,
+    at: String,
+    /// 文件事件附带路径和变更类型，方便自动化脚本直接使用
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    change: Option<FileChange>,
+}
+
+#[derive(Clone)]
+pub struct Notifier {
+    repository: Repository,
+    smtp: Option<SmtpConfig>,
+    http: reqwest::Client,
+    /// 进行中的投递，关闭时等待它们完成
+    tasks: TaskTracker,
+}
+
+impl Notifier {
+    pub fn new(repository: Repository, smtp: Option<SmtpConfig>) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        Notifier {
+            repository,
+            smtp,
+            http,
+            tasks: TaskTracker::new(),
+        }
+    }
+
+    pub fn with_tasks(mut self, tasks: TaskTracker) -> Self {
+        self.tasks = tasks;
+        self
+    }
+
+    pub fn email_enabled(&self) -> bool {
+        self.smtp.is_some()
+    }
+
+    /// 后台投递，不阻塞调用方；投递日志沿用调用方的请求 span
+    pub fn notify(&self, notification: Notification) {
+        let notifier = self.clone();
+        self.tasks.spawn(
+            async move {
+                notifier.dispatch(&notification).await;
+            }
+            .in_current_span(),
+        );
+    }
+
+    /// 按所有用户的规则投递，返回成功投递的数量
+    pub async fn dispatch(&self, notification: &Notification) -> usize {
+        let preferences = self
+            .repository
+            .list_notification_preferences()
+            .await
+            .unwrap_or_default();
+
+        let mut delivered = 0;
+        for prefs in &preferences {
+            for rule in prefs.rules.iter().filter(|r| notification.matches(r)) {
+                let result = match &rule.channel {
+                    NotificationChannel::Webhook { url } => {
+                        self.send_webhook(url, notification).await
+                    }
+                    NotificationChannel::Email { to } => self.send_email(to, notification).await,
+                };
+                match result {
+                    Ok(()) => delivered += 1,
+                    Err(e) => tracing::warn!(
+                        "Failed to deliver {:?} notification for {}: {}",
+                        notification.event(),
+                        prefs.user,
+                        e
+                    ),
+                }
+            }
+        }
+        delivered
+    }
+
+    async fn send_webhook(&self, url: &str, notification: &Notification) -> Result<(), String> {
+        let (path, change) = match notification {
+            Notification::FileChanged { path, change } => (Some(path.clone()), Some(*change)),
+            _ => (None, None),
+        };
+        let payload = WebhookPayload {
+            event: notification.event(),
+            subject: notification.subject(),
+            message: notification.message(),
+            at: self.repository.clock().now().to_rfc3339(),
+            path,
+            change,
+        };
+        self.http
+            .post(url)
+            .json(&payload)
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status())
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    async fn send_email(&self, to: &str, notification: &Notification) -> Result<(), String> {
+        let smtp = self.smtp.as_ref().ok_or("SMTP is not configured")?;
+
+        let email = Message::builder()
+            .from(
+                smtp.from
+                    .parse()
+                    .map_err(|e| format!("Invalid from: {}", e))?,
+            )
+            .to(to
+                .parse()
+                .map_err(|e| format!("Invalid recipient: {}", e))?)
+            .subject(format!("[RustCloud] {}", notification.subject()))
+            .body(notification.message())
+            .map_err(|e| e.to_string())?;
+
+        // 465 端口是隐式 TLS，其余端口使用 STARTTLS
+        let builder = if smtp.port == 465 {
+            AsyncSmtpTransport::<Tokio1Executor>::relay(&smtp.host)
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&smtp.host)
+        }
+        .map_err(|e| e.to_string())?
+        .port(smtp.port);
+
+        let builder = match (&smtp.username, &smtp.password) {
+            (Some(user), Some(pass)) => {
+                builder.credentials(Credentials::new(user.clone(), pass.clone()))
+            }
+            _ => builder,
+        };
+
+        builder
+            .build()
+            .send(email)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,108 @@
+// [知识点 #147] 有界读取：只读文件的头部或尾部
+// ----------------------------------------
+// 题目：如何预览一个 2GB 的日志文件而不把它整个读进内存？
+//
+// 讲解：
+// 预览只需要文件的一小段：
+// 1. 先确定读取窗口：头部从 0 开始，尾部从 size - 窗口大小 开始
+// 2. seek 到窗口起点，最多读取 MAX_PREVIEW_BYTES
+// 3. 在窗口内按行切分，取前 N 行或后 N 行
+//
+// 窗口大小有上限，所以无论文件多大，一次预览的内存和 IO 都是常数级
+// 尾部窗口的第一行可能被截断，直接丢弃即可
+//
+// 思考：如果单行就超过窗口大小（比如压缩后的 JSON），该怎么展示？
+// ----------------------------------------
+
+use std::io::SeekFrom;
+use std::path::Path;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::error::Result;
+
+/// 单次预览最多读取的字节数
+pub const MAX_PREVIEW_BYTES: u64 = 1024 * 1024; // 1MB
+
+/// 未指定行数时返回的行数
+pub const DEFAULT_PREVIEW_LINES: usize = 100;
+
+/// 预览范围：按行取头/尾，或按字节偏移读取
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreviewRange {
+    Head(usize),
+    Tail(usize),
+    Bytes { offset: u64, length: u64 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Preview {
+    /// 返回内容在文件中的起始偏移
+    pub offset: u64,
+    pub content: String,
+    /// 文件大小
+    pub size: u64,
+    /// 预览之外是否还有内容
+    pub truncated: bool,
+}
+
+impl PreviewRange {
+    /// 计算需要读取的窗口 (起点, 长度)
+    fn window(&self, size: u64) -> (u64, u64) {
+        match *self {
+            PreviewRange::Head(_) => (0, size.min(MAX_PREVIEW_BYTES)),
+            PreviewRange::Tail(_) => {
+                let len = size.min(MAX_PREVIEW_BYTES);
+                (size - len, len)
+            }
+            PreviewRange::Bytes { offset, length } => {
+                let start = offset.min(size);
+                (start, length.min(MAX_PREVIEW_BYTES).min(size - start))
+            }
+        }
+    }
+}
+
+/// 从磁盘文件中读取预览，只读取窗口内的数据
+pub async fn preview_file(path: &Path, range: PreviewRange) -> Result<Preview> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let size = file.metadata().await?.len();
+    let (start, len) = range.window(size);
+
+    file.seek(SeekFrom::Start(start)).await?;
+    let mut buf = Vec::with_capacity(len as usize);
+    file.take(len).read_to_end(&mut buf).await?;
+
+    Ok(extract(&buf, start, size, range))
+}
+
+fn extract(window: &[u8], start: u64, size: u64, range: PreviewRange) -> Preview {
+    let (offset, bytes) = match range {
+        PreviewRange::Head(lines) => {
+            let taken: usize = window
+                .split_inclusive(|&b| b == b'\n')
+                .take(lines)
+                .map(|line| line.len())
+                .sum();
+            (start, &window[..taken])
+        }
+        PreviewRange::Tail(lines) => {
+            let mut parts: Vec<&[u8]> = window.split_inclusive(|&b| b == b'\n').collect();
+            // 窗口没有覆盖文件开头时，第一行可能只是半行
+            if start > 0 && !parts.is_empty() {
+                parts.remove(0);
+            }
+            let kept: usize = parts.iter().rev().take(lines).map(|line| line.len()).sum();
+            let skipped = window.len() - kept;
+            (start + skipped as u64, &window[skipped..])
+        }
+        PreviewRange::Bytes { .. } => (start, window),
+    };
+
+    let returned_end = offset + bytes.len() as u64;
+    Preview {
+        offset,
+        content: String::from_utf8_lossy(bytes).into_owned(),
+        size,
+        truncated: offset > 0 || returned_end < size,
+    }
+}
@@ -0,0 +1,348 @@
+// [知识点 #170] 读穿透缓存与异步回写
+// ----------------------------------------
+// 题目：办公室里的边缘节点怎样既快又不和云端不一致？
+//
+// 讲解：
+// 代理模式下本地存储是上游的缓存：
+// 1. 读（read-through）：先向上游确认路径的当前哈希，本地缓存的哈希相同就直接读本地，
+//    不同或没有缓存时从上游拉取一份存下来；上游连不上时直接用本地缓存
+// 2. 写（write-behind）：先按普通上传写入本地，立即返回给客户端，
+//    再把写操作追加到待转发队列，由后台任务按顺序发往上游
+//
+// 待转发队列持久化在 objects/proxy-outbox.json，重启后继续转发；
+// 同一路径的新写入会替换队列中尚未转发的旧写入，只有最后的内容会发往上游。
+// 队列中还有该路径的写入时，本地副本比上游新，读取时不再向上游确认。
+//
+// 思考：两个代理节点同时修改同一个文件，最终上游保留的是哪一份？
+// ----------------------------------------
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+use rustcloud_types::{ApiResponse, FileInfo};
+
+use crate::config::UpstreamConfig;
+use crate::error::{Error, Result};
+use crate::service::storage::StorageBackend;
+
+const OUTBOX_FILE: &str = "proxy-outbox.json";
+
+/// 读取前向上游确认的超时；超时即视为离线，改用本地缓存
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 转发失败后的重试间隔上限
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// 上游对某个路径的说法
+#[derive(Debug)]
+pub enum Upstream {
+    File(FileInfo),
+    Listing(Vec<FileInfo>),
+    Missing,
+}
+
+/// 已在本地完成、尚未转发到上游的写操作
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum PendingWrite {
+    Upload { path: String, hash: String },
+    Delete { path: String, recursive: bool },
+    Folder { path: String },
+}
+
+impl PendingWrite {
+    pub fn path(&self) -> &str {
+        match self {
+            PendingWrite::Upload { path, .. }
+            | PendingWrite::Delete { path, .. }
+            | PendingWrite::Folder { path } => path,
+        }
+    }
+
+    /// 对该路径本身或其子路径生效
+    fn covers(&self, path: &str) -> bool {
+        let own = self.path();
+        own == path
+            || path
+                .strip_prefix(own)
+                .is_some_and(|rest| rest.starts_with('/'))
+    }
+}
+
+/// 转发结果；上游明确拒绝的写入重试也不会成功，丢弃并记录
+enum Forwarded {
+    Done,
+    Rejected(String),
+}
+
+struct ProxyState {
+    outbox: Vec<PendingWrite>,
+    last_error: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct ProxyService {
+    url: String,
+    token: Option<String>,
+    http: reqwest::Client,
+    outbox_path: PathBuf,
+    state: Arc<Mutex<ProxyState>>,
+    wake: Arc<Notify>,
+}
+
+impl ProxyService {
+    /// 读取上次未转发完的队列；文件损坏时从空队列开始
+    pub fn new(config: UpstreamConfig, storage_path: &Path) -> Self {
+        let outbox_path = storage_path.join("objects").join(OUTBOX_FILE);
+        let outbox = match std::fs::read(&outbox_path) {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|e| {
+                tracing::warn!("Ignoring unreadable {}: {}", outbox_path.display(), e);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        ProxyService {
+            url: config.url.trim_end_matches('/').to_string(),
+            token: config.token,
+            http: reqwest::Client::builder()
+                .connect_timeout(LOOKUP_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            outbox_path,
+            state: Arc::new(Mutex::new(ProxyState {
+                outbox,
+                last_error: None,
+            })),
+            wake: Arc::new(Notify::new()),
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn pending(&self) -> Vec<PendingWrite> {
+        self.state.lock().unwrap().outbox.clone()
+    }
+
+    pub fn last_error(&self) -> Option<String> {
+        self.state.lock().unwrap().last_error.clone()
+    }
+
+    /// 本地副本比上游新：该路径或其上级目录还有写入没有转发
+    pub fn is_pending(&self, path: &str) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .outbox
+            .iter()
+            .any(|write| write.covers(path))
+    }
+
+    fn request(&self, method: reqwest::Method, api_path: &str) -> reqwest::RequestBuilder {
+        let request = self
+            .http
+            .request(method, format!("{}{}", self.url, api_path));
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    pub async fn lookup(&self, path: &str) -> Result<Upstream> {
+        let response = self
+            .request(reqwest::Method::GET, &format!("/api/files/{}", path))
+            .timeout(LOOKUP_TIMEOUT)
+            .send()
+            .await
+            .map_err(upstream_error)?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(Upstream::Missing);
+        }
+        let body: ApiResponse = response
+            .error_for_status()
+            .map_err(upstream_error)?
+            .json()
+            .await
+            .map_err(upstream_error)?;
+        match body.data {
+            Some(data @ serde_json::Value::Array(_)) => {
+                Ok(Upstream::Listing(serde_json::from_value(data)?))
+            }
+            Some(data) => Ok(Upstream::File(serde_json::from_value(data)?)),
+            None => Ok(Upstream::Missing),
+        }
+    }
+
+    pub async fn list(&self, dir: &str) -> Result<Vec<FileInfo>> {
+        let response = self
+            .request(reqwest::Method::GET, "/api/files")
+            .query(&[("path", dir)])
+            .timeout(LOOKUP_TIMEOUT)
+            .send()
+            .await
+            .map_err(upstream_error)?;
+        let body: ApiResponse<Vec<FileInfo>> = response
+            .error_for_status()
+            .map_err(upstream_error)?
+            .json()
+            .await
+            .map_err(upstream_error)?;
+        body.data
+            .ok_or_else(|| Error::Upstream(body.error.unwrap_or_default()))
+    }
+
+    /// 文件内容的响应，调用方边收边写入暂存文件
+    pub async fn fetch(&self, path: &str) -> Result<reqwest::Response> {
+        self.request(reqwest::Method::GET, &format!("/api/stream/{}", path))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(upstream_error)
+    }
+
+    /// 追加到待转发队列；同一路径（或被删除目录下）尚未转发的写入被新的写入取代
+    pub fn enqueue(&self, write: PendingWrite) {
+        let mut state = self.state.lock().unwrap();
+        state.outbox.retain(|queued| !write.covers(queued.path()));
+        state.outbox.push(write);
+        self.persist(&state.outbox);
+        drop(state);
+        self.wake.notify_one();
+    }
+
+    fn persist(&self, outbox: &[PendingWrite]) {
+        let written = serde_json::to_vec(outbox)
+            .map_err(Error::from)
+            .and_then(|content| {
+                if let Some(parent) = self.outbox_path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                Ok(std::fs::write(&self.outbox_path, content)?)
+            });
+        if let Err(e) = written {
+            tracing::warn!("Failed to save proxy outbox: {}", e);
+        }
+    }
+
+    /// 后台按顺序转发队列中的写操作；网络错误和上游 5xx 按指数退避重试。
+    /// 收到关闭信号时放弃进行中的转发，它仍在持久化的队列里，下次启动再发
+    pub async fn run_forwarder(
+        self,
+        storage: Arc<dyn StorageBackend>,
+        shutdown: CancellationToken,
+    ) -> Result<()> {
+        let mut delay = Duration::from_secs(1);
+        loop {
+            let next = self.state.lock().unwrap().outbox.first().cloned();
+            let Some(write) = next else {
+                tokio::select! {
+                    _ = self.wake.notified() => continue,
+                    _ = shutdown.cancelled() => return Ok(()),
+                }
+            };
+
+            let outcome = tokio::select! {
+                outcome = self.forward(&write, storage.as_ref()) => outcome,
+                _ = shutdown.cancelled() => return Ok(()),
+            };
+            if let Err(e) = &outcome {
+                tracing::warn!("Forwarding {} upstream failed: {}", write.path(), e);
+            }
+            self.finish(&write, &outcome);
+            if outcome.is_err() {
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = shutdown.cancelled() => return Ok(()),
+                }
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+            } else {
+                delay = Duration::from_secs(1);
+            }
+        }
+    }
+
+    // 成功或被拒绝的写入移出队列；转发期间被新写入取代时，队首已经不是它
+    fn finish(&self, write: &PendingWrite, outcome: &Result<Forwarded>) {
+        let mut state = self.state.lock().unwrap();
+        state.last_error = match outcome {
+            Ok(Forwarded::Done) => None,
+            Ok(Forwarded::Rejected(reason)) => {
+                tracing::warn!("Upstream rejected {:?}, dropping it: {}", write, reason);
+                Some(reason.clone())
+            }
+            Err(e) => {
+                state.last_error = Some(e.to_string());
+                return;
+            }
+        };
+        if state.outbox.first() == Some(write) {
+            state.outbox.remove(0);
+            self.persist(&state.outbox);
+        }
+    }
+
+    async fn forward(
+        &self,
+        write: &PendingWrite,
+        storage: &dyn StorageBackend,
+    ) -> Result<Forwarded> {
+        let (request, tolerated) = match write {
+            PendingWrite::Upload { path, hash } => {
+                let object = match storage.stream_object(hash).await {
+                    Ok((_, object)) => object,
+                    // 内容已被清理，只可能是之后又有写入取代了它
+                    Err(e) => return Ok(Forwarded::Rejected(e.to_string())),
+                };
+                let request = self
+                    .request(reqwest::Method::PUT, &format!("/api/files/{}", path))
+                    .header("X-Content-Sha256", hash)
+                    .body(reqwest::Body::wrap_stream(object));
+                (request, None)
+            }
+            PendingWrite::Delete { path, recursive } => {
+                let request = self
+                    .request(reqwest::Method::DELETE, &format!("/api/files/{}", path))
+                    .query(&[("recursive", recursive)]);
+                (request, Some(reqwest::StatusCode::NOT_FOUND))
+            }
+            PendingWrite::Folder { path } => {
+                let request = self
+                    .request(reqwest::Method::POST, "/api/files")
+                    .json(&serde_json::json!({ "path": path }));
+                (request, Some(reqwest::StatusCode::CONFLICT))
+            }
+        };
+
+        let response = request.send().await.map_err(upstream_error)?;
+        let status = response.status();
+        if status.is_success() || Some(status) == tolerated {
+            return Ok(Forwarded::Done);
+        }
+        let message = response
+            .json::<ApiResponse>()
+            .await
+            .ok()
+            .and_then(|body| body.error)
+            .unwrap_or_else(|| status.to_string());
+        // 401 多半是上游令牌过期，更新配置后还能继续转发
+        let retryable = status.is_server_error()
+            || status == reqwest::StatusCode::UNAUTHORIZED
+            || status == reqwest::StatusCode::REQUEST_TIMEOUT
+            || status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+        if retryable {
+            Err(Error::Upstream(message))
+        } else {
+            Ok(Forwarded::Rejected(message))
+        }
+    }
+}
+
+fn upstream_error(e: reqwest::Error) -> Error {
+    Error::Upstream(e.to_string())
+}
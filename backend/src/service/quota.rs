@@ -0,0 +1,95 @@
+//! 按用户统计用量并检查配额
+//!
+//! 用量是用户名下文件的当前大小之和：自己上传的文件，以及命名空间内没有创建者的文件
+//! （例如由文件监控导入的）。历史版本和回收站中的内容不计入。
+
+use std::sync::Arc;
+
+use rustcloud_types::StorageUsage;
+use tokio::sync::OwnedMutexGuard;
+use uuid::Uuid;
+
+use crate::db::{MetadataStore, Repository};
+use crate::error::{Error, Result};
+use crate::service::locks::PathLocks;
+use crate::service::namespace::Namespace;
+
+#[derive(Clone)]
+pub struct QuotaService {
+    files: Arc<dyn MetadataStore>,
+    repository: Repository,
+    /// 没有单独设置配额的用户使用该值；None 表示不限制
+    default_quota: Option<u64>,
+    /// 启用命名空间时，命名空间内没有创建者的文件也算在该用户名下
+    namespaces: bool,
+    /// 按用户 ID 加锁，见 reserve
+    locks: PathLocks,
+}
+
+impl QuotaService {
+    pub fn new(
+        files: Arc<dyn MetadataStore>,
+        repository: Repository,
+        default_quota: Option<u64>,
+    ) -> Self {
+        QuotaService {
+            files,
+            repository,
+            default_quota,
+            namespaces: false,
+            locks: PathLocks::new(),
+        }
+    }
+
+    pub fn with_namespaces(mut self, namespaces: bool) -> Self {
+        self.namespaces = namespaces;
+        self
+    }
+
+    pub async fn usage(&self, user: Uuid) -> Result<StorageUsage> {
+        let namespace = self.namespaces.then(|| Namespace::for_user(user));
+        let quota_bytes = self.repository.get_user(user).await?.quota_bytes;
+        let root = namespace.as_ref().map(Namespace::root);
+        let (files, used_bytes) = self.files.owner_usage(user, root).await?;
+        Ok(StorageUsage {
+            files,
+            used_bytes,
+            quota_bytes: quota_bytes.or(self.default_quota),
+        })
+    }
+
+    /// 写入 incoming 字节、同时替换掉 replaced 字节的旧内容之后是否仍在配额之内；
+    /// 超出时返回 QuotaExceeded
+    pub async fn check(&self, user: Uuid, incoming: u64, replaced: u64) -> Result<()> {
+        self.check_usage(user, incoming, replaced).await.map(|_| ())
+    }
+
+    /// 与 check 相同，但有配额时返回该用户的锁：持有到新的大小写入记录为止，
+    /// 同一用户的并发写入不会各自通过检查、合起来超出配额。没有配额时不加锁
+    pub async fn reserve(
+        &self,
+        user: Uuid,
+        incoming: u64,
+        replaced: u64,
+    ) -> Result<Option<OwnedMutexGuard<()>>> {
+        let guard = self.locks.lock(&user.to_string()).await;
+        let limited = self.check_usage(user, incoming, replaced).await?;
+        Ok(limited.then_some(guard))
+    }
+
+    /// 返回用户是否有配额
+    async fn check_usage(&self, user: Uuid, incoming: u64, replaced: u64) -> Result<bool> {
+        let usage = self.usage(user).await?;
+        let Some(quota) = usage.quota_bytes else {
+            return Ok(false);
+        };
+        let after = usage.used_bytes.saturating_sub(replaced) + incoming;
+        if after > quota {
+            return Err(Error::QuotaExceeded(format!(
+                "{} of {} bytes used, {} more requested",
+                usage.used_bytes, quota, incoming
+            )));
+        }
+        Ok(true)
+    }
+}
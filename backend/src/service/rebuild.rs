@@ -0,0 +1,240 @@
+// [知识点 #193] 从对象存储重建索引
+// ----------------------------------------
+// 题目：db.json 损坏或被误删，文件还在吗？
+//
+// 讲解：
+// 内容都在磁盘上，丢的只是"哪个路径对应哪个哈希"的索引：
+// - 落盘的文件树：路径就是文件在存储目录中的位置，重新计算哈希和大小即可
+// - 对象存储：对象以哈希命名，能证明内容完整，但不知道它原来叫什么；
+//   分块对象的清单记着整个文件的哈希和大小，据此把分块拼回一个文件
+//
+// 文件树中找不到的对象（旧版本、未落盘模式上传的文件）放进 lost+found/，
+// 以哈希为文件名，由用户辨认后改名；回收站中的内容也移到这里，
+// 否则重建后的回收站没有条目，内容会被当作残留清除。
+//
+// 重建得到的记录都是第 1 版：版本历史、分享、评论等只存在于元数据中，无法找回。
+//
+// 思考：怎样让对象自己带上路径，元数据丢失时也能找回文件名？
+// ----------------------------------------
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use futures_util::StreamExt;
+use rustcloud_types::ignore::{IgnoreRules, IGNORE_FILE};
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+
+use crate::db::{is_database_file, NewFileRecord, Repository};
+use crate::error::Result;
+use crate::service::storage::{is_temp_file, StorageService};
+
+/// 找不到路径的内容放在这个目录下
+pub const LOST_AND_FOUND: &str = "lost+found";
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct RebuildSummary {
+    /// 按存储目录中的文件重建的记录数
+    pub files: usize,
+    /// 只在对象存储中找到、放进 lost+found/ 的文件数
+    pub recovered: usize,
+    /// 重建的空目录数
+    pub directories: usize,
+    pub bytes: u64,
+    /// 读取或记录失败而跳过的文件数
+    pub failed: usize,
+}
+
+/// 把存储目录根部的数据库文件改名保留（db.json → db.json.before-rebuild-20240101T000000），
+/// 之后打开的 repository 是空的；返回改名后的路径
+pub async fn set_aside_database(
+    storage_path: &Path,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<Vec<PathBuf>> {
+    let suffix = format!("before-rebuild-{}", now.format("%Y%m%dT%H%M%S"));
+    let mut moved = Vec::new();
+    let Ok(mut entries) = tokio::fs::read_dir(storage_path).await else {
+        return Ok(moved);
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if is_database_file(&name) && !name.contains(".before-rebuild-") {
+            let target = storage_path.join(format!("{}.{}", name, suffix));
+            tokio::fs::rename(entry.path(), &target).await?;
+            moved.push(target);
+        }
+    }
+    Ok(moved)
+}
+
+/// 按存储目录和对象存储重新生成文件记录，写入（通常是新建的空）repository
+pub struct IndexRebuilder {
+    storage: StorageService,
+    repository: Repository,
+    root: PathBuf,
+    materialize_files: bool,
+    ignore: IgnoreRules,
+}
+
+impl IndexRebuilder {
+    pub fn new(storage: StorageService, repository: Repository, materialize_files: bool) -> Self {
+        let root = storage.storage_path().to_path_buf();
+        IndexRebuilder {
+            storage,
+            repository,
+            root,
+            materialize_files,
+            ignore: IgnoreRules::new(),
+        }
+    }
+
+    /// 跳过与文件监控相同的路径：配置中的规则和存储目录根部的 .rcloudignore
+    pub fn with_ignore(mut self, patterns: &[String]) -> Self {
+        let mut rules = IgnoreRules::new();
+        rules.extend(patterns);
+        if let Ok(content) = std::fs::read_to_string(self.root.join(IGNORE_FILE)) {
+            rules.extend(content.lines());
+        }
+        self.ignore = rules;
+        self
+    }
+
+    pub async fn run(&self) -> Result<RebuildSummary> {
+        let mut summary = RebuildSummary::default();
+        self.rescue_trash().await?;
+        let hashes = self.scan_tree(&mut summary).await?;
+        self.recover_objects(&hashes, &mut summary).await?;
+        Ok(summary)
+    }
+
+    // 回收站中的内容移进文件树，随后和其他文件一起记录
+    async fn rescue_trash(&self) -> Result<()> {
+        let trash = self.root.join("objects").join("trash");
+        let Ok(mut entries) = tokio::fs::read_dir(&trash).await else {
+            return Ok(());
+        };
+        let target = self.root.join(LOST_AND_FOUND);
+        while let Some(entry) = entries.next_entry().await? {
+            tokio::fs::create_dir_all(&target).await?;
+            let name = format!("trash-{}", entry.file_name().to_string_lossy());
+            tokio::fs::rename(entry.path(), target.join(name)).await?;
+        }
+        Ok(())
+    }
+
+    /// 记录文件树中的文件和空目录，返回这些文件的哈希
+    async fn scan_tree(&self, summary: &mut RebuildSummary) -> Result<HashSet<String>> {
+        let mut hashes = HashSet::new();
+        let mut pending = vec![self.root.clone()];
+        while let Some(dir) = pending.pop() {
+            let mut entries = tokio::fs::read_dir(&dir).await?;
+            let mut kept = 0;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                let file_type = entry.file_type().await?;
+                let Some(logical) = self.logical_path(&path, file_type.is_dir()) else {
+                    continue;
+                };
+                kept += 1;
+                if file_type.is_dir() {
+                    pending.push(path);
+                } else if file_type.is_file() {
+                    match self.record_file(&path, logical).await {
+                        Ok((hash, size)) => {
+                            hashes.insert(hash);
+                            summary.files += 1;
+                            summary.bytes += size;
+                        }
+                        Err(e) => {
+                            tracing::warn!("Failed to rebuild {:?}: {}", path, e);
+                            summary.failed += 1;
+                        }
+                    }
+                }
+            }
+            if kept == 0 && dir != self.root {
+                if let Some(logical) = self.logical_path(&dir, true) {
+                    self.repository.create_directory(&logical).await?;
+                    summary.directories += 1;
+                }
+            }
+        }
+        Ok(hashes)
+    }
+
+    async fn record_file(&self, path: &Path, logical: String) -> Result<(String, u64)> {
+        let (hash, size) = self.storage.store_file(path).await?;
+        self.repository
+            .create_file(NewFileRecord {
+                path: logical,
+                hash: Some(hash.clone()),
+                size,
+            })
+            .await?;
+        Ok((hash, size))
+    }
+
+    /// 文件树中没有的对象记录到 lost+found/{哈希}，落盘模式下同时写出明文文件
+    async fn recover_objects(
+        &self,
+        known: &HashSet<String>,
+        summary: &mut RebuildSummary,
+    ) -> Result<()> {
+        for (hash, size) in self.storage.stored_objects().await? {
+            if known.contains(&hash) {
+                continue;
+            }
+            let logical = format!("{}/{}", LOST_AND_FOUND, hash);
+            let recovered = async {
+                if self.materialize_files {
+                    self.write_object(&hash, &self.root.join(&logical)).await?;
+                }
+                self.repository
+                    .create_file(NewFileRecord {
+                        path: logical,
+                        hash: Some(hash.clone()),
+                        size,
+                    })
+                    .await
+            }
+            .await;
+            match recovered {
+                Ok(_) => {
+                    summary.recovered += 1;
+                    summary.bytes += size;
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to recover object {}: {}", hash, e);
+                    summary.failed += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn write_object(&self, hash: &str, target: &Path) -> Result<()> {
+        if let Some(parent) = target.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let (_, mut content) = self.storage.stream_object(hash).await?;
+        let mut file = tokio::fs::File::create(target).await?;
+        while let Some(bytes) = content.next().await {
+            file.write_all(&bytes?).await?;
+        }
+        file.sync_all().await?;
+        Ok(())
+    }
+
+    // 与文件监控一致：对象目录、数据库、临时文件和被忽略的路径不属于用户文件
+    fn logical_path(&self, path: &Path, is_dir: bool) -> Option<String> {
+        if is_temp_file(path) {
+            return None;
+        }
+        let relative = path.strip_prefix(&self.root).ok()?;
+        let logical = rustcloud_types::path::normalize(&relative.to_string_lossy()).ok()?;
+        if is_database_file(&logical) || logical == "objects" || logical.starts_with("objects/") {
+            return None;
+        }
+        (!self.ignore.is_ignored(&logical, is_dir)).then_some(logical)
+    }
+}
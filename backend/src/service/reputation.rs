@@ -0,0 +1,107 @@
+// [知识点 #152] 外部信誉服务集成
+// ----------------------------------------
+// 题目：怎样在不自己做病毒扫描的前提下拦截已知的恶意文件？
+//
+// 讲解：
+// VirusTotal 一类的服务按内容哈希维护信誉库。
+// 上传时已经算出了 SHA-256，只需把哈希发给服务查询：
+// - 返回 malicious：按策略拒绝上传（block）或保存但打标记（flag）
+// - 返回 clean：正常保存
+// - 服务不可用或未知哈希：视为 unknown，不阻塞上传
+//
+// 只发送哈希而不发送文件内容，不会把用户数据泄露给第三方
+// 结论写入文件的自定义元数据，之后可以用搜索接口找出被标记的文件
+//
+// 思考：信誉服务超时时，"放行"和"拒绝"哪个更合理？
+// ----------------------------------------
+
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::config::{ReputationConfig, ReputationPolicy};
+
+/// 记录结论的元数据键
+pub const VERDICT_METADATA_KEY: &str = "reputation.verdict";
+
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    Clean,
+    Malicious,
+    #[serde(other)]
+    Unknown,
+}
+
+impl Verdict {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Verdict::Clean => "clean",
+            Verdict::Malicious => "malicious",
+            Verdict::Unknown => "unknown",
+        }
+    }
+}
+
+/// 服务响应：{"verdict": "clean" | "malicious" | ...}
+#[derive(Debug, Deserialize)]
+struct LookupResponse {
+    verdict: Verdict,
+}
+
+#[derive(Clone)]
+pub struct ReputationService {
+    config: ReputationConfig,
+    http: reqwest::Client,
+}
+
+impl ReputationService {
+    pub fn new(config: ReputationConfig) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(LOOKUP_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        ReputationService { config, http }
+    }
+
+    pub fn policy(&self) -> ReputationPolicy {
+        self.config.policy
+    }
+
+    /// 查询失败时返回 Unknown，不影响上传
+    pub async fn check(&self, hash: &str) -> Verdict {
+        let url = self.config.url.replace("{hash}", hash);
+        let mut request = self.http.get(&url);
+        if let Some(key) = &self.config.api_key {
+            request = request.header("x-apikey", key);
+        }
+
+        let response = match request.send().await {
+            Ok(response) if response.status().is_success() => response,
+            Ok(response) => {
+                if response.status() != reqwest::StatusCode::NOT_FOUND {
+                    tracing::warn!(
+                        "Reputation lookup for {} returned {}",
+                        hash,
+                        response.status()
+                    );
+                }
+                return Verdict::Unknown;
+            }
+            Err(e) => {
+                tracing::warn!("Reputation lookup for {} failed: {}", hash, e);
+                return Verdict::Unknown;
+            }
+        };
+
+        match response.json::<LookupResponse>().await {
+            Ok(body) => body.verdict,
+            Err(e) => {
+                tracing::warn!("Invalid reputation response for {}: {}", hash, e);
+                Verdict::Unknown
+            }
+        }
+    }
+}
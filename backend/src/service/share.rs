@@ -0,0 +1,88 @@
+// [知识点 #158] 用 RAII 守卫统计并发数
+// ----------------------------------------
+// 题目：分享链接被贴到公开论坛后，怎样避免家用服务器被并发下载拖垮？
+//
+// 讲解：
+// 下载次数、累计流量可以从访问记录里统计，但"正在进行的下载"只存在于内存：
+// 1. 开始下载前 try_acquire()，计数未达上限时加一并返回守卫
+// 2. 守卫被 drop 时计数减一
+// 3. 守卫随响应体一起移动，响应体发送完毕（或客户端断开）时才被释放
+//
+// 如果在 handler 返回时就释放计数，限制的只是"生成响应"的那一瞬间，
+// 真正耗时的传输阶段完全不受约束
+//
+// 配额是"软"的：检查发生在下载开始前，正在进行的下载不会被中途切断，
+// 最后一次下载可能让累计流量略微超出上限
+//
+// 思考：服务重启后内存中的计数丢失，会有问题吗？
+// ----------------------------------------
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use axum::body::Body;
+use futures_util::StreamExt;
+use uuid::Uuid;
+
+#[derive(Clone, Default)]
+pub struct ShareStreams {
+    active: Arc<Mutex<HashMap<Uuid, usize>>>,
+}
+
+impl ShareStreams {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 当前正在进行的下载数
+    pub fn active(&self, share_id: Uuid) -> usize {
+        let active = self.active.lock().unwrap_or_else(|e| e.into_inner());
+        active.get(&share_id).copied().unwrap_or(0)
+    }
+
+    /// 未达到上限时占用一个名额，max 为 None 时不限制
+    pub fn try_acquire(&self, share_id: Uuid, max: Option<usize>) -> Option<StreamGuard> {
+        let mut active = self.active.lock().unwrap_or_else(|e| e.into_inner());
+        let count = active.entry(share_id).or_insert(0);
+        if max.is_some_and(|max| *count >= max) {
+            return None;
+        }
+        *count += 1;
+        Some(StreamGuard {
+            streams: self.clone(),
+            share_id,
+        })
+    }
+}
+
+/// 持有期间占用一个下载名额
+pub struct StreamGuard {
+    streams: ShareStreams,
+    share_id: Uuid,
+}
+
+impl StreamGuard {
+    /// 让守卫跟随响应体，直到数据发送完毕
+    pub fn attach(self, body: Body) -> Body {
+        Body::from_stream(body.into_data_stream().map(move |chunk| {
+            let _guard = &self;
+            chunk
+        }))
+    }
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        let mut active = self
+            .streams
+            .active
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if let Some(count) = active.get_mut(&self.share_id) {
+            *count -= 1;
+            if *count == 0 {
+                active.remove(&self.share_id);
+            }
+        }
+    }
+}
@@ -0,0 +1,231 @@
+// [知识点 #184] 删除时粉碎数据
+// ----------------------------------------
+// 题目：文件删掉之后，它的内容还在磁盘上吗？
+//
+// 讲解：
+// 文件系统的删除只是去掉目录项，数据块在被复用之前仍然可以恢复。
+// RustCloud 还多一层：删除记录后，内容寻址的对象文件并不会跟着删除，
+// 因为别的文件或历史版本可能引用同样的内容。
+//
+// 有隐私要求时开启 RUSTCLOUD_SHRED_DELETED：
+// 1. 删除记录之前，记下它和它的历史版本引用的对象
+// 2. 删除记录之后，找出不再被任何文件或版本引用的对象；
+//    分块存储的对象逐块检查，同一块可能被别的对象共用
+// 3. 这些对象文件和明文文件先用零覆写一遍并落盘，再删除
+// 4. 在审计日志中记一条 shredded，写明覆写了多少文件和字节
+//
+// 覆写只对原地写入的存储有效：SSD 的磨损均衡、写时复制文件系统（btrfs、ZFS）
+// 和快照都可能保留旧的数据块。这类环境应当加密存储并在删除时销毁密钥（crypto-shredding）；
+// 服务端目前不做静态加密，需要时使用客户端的端到端加密（rcloud encrypt），服务端只保存密文。
+//
+// 思考：为什么必须等记录删除之后，才判断对象是否还被引用？
+// ----------------------------------------
+
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::db::{FileRecord, Repository};
+use crate::error::{Error, Result};
+use crate::service::firewall::{self, AuditEntry, AuditEvent};
+use crate::service::storage::StorageBackend;
+
+/// 覆写缓冲区大小
+const OVERWRITE_BUFFER: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ShredSummary {
+    /// 覆写后删除的文件数（对象文件、分块、清单和明文文件）
+    pub files: usize,
+    pub bytes: u64,
+}
+
+impl ShredSummary {
+    fn add(&mut self, other: ShredSummary) {
+        self.files += other.files;
+        self.bytes += other.bytes;
+    }
+}
+
+/// 删除时覆写数据，克隆后共享
+#[derive(Clone)]
+pub struct Shredder {
+    storage: Arc<dyn StorageBackend>,
+    repository: Repository,
+    audit_path: PathBuf,
+}
+
+impl Shredder {
+    pub fn new(
+        storage: Arc<dyn StorageBackend>,
+        repository: Repository,
+        storage_path: &Path,
+    ) -> Self {
+        Shredder {
+            storage,
+            repository,
+            audit_path: firewall::audit_path(storage_path),
+        }
+    }
+
+    /// 记录及其历史版本引用的对象，须在删除记录之前取得
+    pub async fn objects_of(&self, records: &[FileRecord]) -> Vec<String> {
+        let mut hashes = Vec::new();
+        for record in records {
+            hashes.extend(record.hash.clone());
+            if let Ok(versions) = self.repository.list_file_versions(record.id).await {
+                hashes.extend(versions.into_iter().filter_map(|version| version.hash));
+            }
+        }
+        hashes.sort();
+        hashes.dedup();
+        hashes
+    }
+
+    /// 记录删除之后调用：覆写并删除存储目录中的明文文件（file 为 None 时跳过），
+    /// 以及 objects 中不再被任何文件或版本引用的部分
+    pub async fn shred(&self, file: Option<&Path>, objects: &[String]) -> Result<ShredSummary> {
+        let mut summary = match file {
+            Some(file) => self.shred_path(file).await?,
+            None => ShredSummary::default(),
+        };
+        summary.add(self.shred_objects(objects).await?);
+        Ok(summary)
+    }
+
+    async fn shred_objects(&self, candidates: &[String]) -> Result<ShredSummary> {
+        let mut summary = ShredSummary::default();
+        if candidates.is_empty() {
+            return Ok(summary);
+        }
+
+        // 回收站中的文件还可能被恢复，它们引用的对象同样不能覆写
+        let trashed: Vec<FileRecord> = self
+            .repository
+            .list_trash()
+            .await?
+            .into_iter()
+            .flat_map(|entry| entry.files)
+            .collect();
+        let mut referenced = HashSet::new();
+        for file in self.repository.list_files().await?.iter().chain(&trashed) {
+            referenced.extend(file.hash.clone());
+            for version in self.repository.list_file_versions(file.id).await? {
+                referenced.extend(version.hash);
+            }
+        }
+        let mut live = HashSet::new();
+        for hash in &referenced {
+            live.extend(self.storage.object_files(hash).await);
+        }
+
+        for hash in candidates {
+            if referenced.contains(hash) {
+                continue;
+            }
+            for file in self.storage.object_files(hash).await {
+                if !live.contains(&file) {
+                    summary.add(shred_file(file).await?);
+                }
+            }
+        }
+        Ok(summary)
+    }
+
+    // 目录逐个覆写其中的文件后整体删除
+    async fn shred_path(&self, path: &Path) -> Result<ShredSummary> {
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || -> Result<ShredSummary> {
+            let summary = overwrite_tree(&path)?;
+            if path.is_dir() {
+                std::fs::remove_dir_all(&path)?;
+            }
+            Ok(summary)
+        })
+        .await
+        .map_err(|e| Error::Io(std::io::Error::other(e)))?
+    }
+
+    /// 在审计日志中记下这次粉碎
+    pub fn record(
+        &self,
+        at: DateTime<Utc>,
+        ip: Option<IpAddr>,
+        user: Option<&str>,
+        method: &str,
+        path: &str,
+        summary: ShredSummary,
+    ) {
+        tracing::info!(
+            "Shredded {}: {} file(s), {} bytes overwritten",
+            path,
+            summary.files,
+            summary.bytes
+        );
+        firewall::append_audit(
+            &self.audit_path,
+            &AuditEntry {
+                at,
+                ip,
+                user: user.map(str::to_string),
+                event: AuditEvent::Shredded,
+                method: method.to_string(),
+                path: path.to_string(),
+                detail: Some(format!(
+                    "{} file(s), {} bytes overwritten",
+                    summary.files, summary.bytes
+                )),
+            },
+        );
+    }
+}
+
+async fn shred_file(path: PathBuf) -> Result<ShredSummary> {
+    tokio::task::spawn_blocking(move || overwrite_tree(&path))
+        .await
+        .map_err(|e| Error::Io(std::io::Error::other(e)))?
+}
+
+// 文件覆写后删除，目录递归处理其中的文件（目录本身留给调用方删除）；
+// 符号链接只删除链接本身，不碰它指向的文件
+fn overwrite_tree(path: &Path) -> Result<ShredSummary> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        // 同一块可能被多个候选对象共用，前面已经处理过
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(ShredSummary::default()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut summary = ShredSummary::default();
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            summary.add(overwrite_tree(&entry?.path())?);
+        }
+        return Ok(summary);
+    }
+    if metadata.is_file() {
+        overwrite(path, metadata.len())?;
+        summary.files = 1;
+        summary.bytes = metadata.len();
+    }
+    std::fs::remove_file(path)?;
+    Ok(summary)
+}
+
+fn overwrite(path: &Path, len: u64) -> std::io::Result<()> {
+    use std::io::Write;
+
+    // 不截断，原地覆写原来的数据块
+    let mut file = std::fs::OpenOptions::new().write(true).open(path)?;
+    let zeros = vec![0u8; OVERWRITE_BUFFER];
+    let mut remaining = len;
+    while remaining > 0 {
+        let n = remaining.min(zeros.len() as u64) as usize;
+        file.write_all(&zeros[..n])?;
+        remaining -= n as u64;
+    }
+    file.sync_all()
+}
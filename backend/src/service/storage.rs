@@ -14,15 +14,196 @@
 // 思考：哈希碰撞时会发生什么？如何处理？
 // ----------------------------------------
 
+use async_trait::async_trait;
+use axum::body::Bytes;
+use futures_util::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use rustcloud_types::delta::{self, Delta, DeltaOp, FileSignature};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use std::io::{Read, SeekFrom};
 use std::path::{Path, PathBuf};
-use tokio::io::AsyncReadExt;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio_util::io::ReaderStream;
 
+use super::compression::{
+    self, ObjectCompression, TrainedDictionary, COMPRESSED_PREFIX, COMPRESS_MAX_SIZE, MAX_SAMPLES,
+};
+use super::hash_cache::HashCache;
+use super::integrity::ObjectIntegrity;
+use crate::config::WorkerConfig;
+use crate::db::FileRecord;
 use crate::error::{Error, Result};
 
 const CHUNK_SIZE: usize = 4 * 1024 * 1024; // 4MB
 
+/// 协商分块大小时允许的范围
+pub const MIN_CHUNK_SIZE: usize = 256 * 1024; // 256KB
+pub const MAX_CHUNK_SIZE: usize = 64 * 1024 * 1024; // 64MB
+
+/// 单个文件的目标最大分块数，超大文件据此放大分块
+const MAX_CHUNKS_PER_FILE: u64 = 1024;
+
+/// 开启读取校验时，不超过这个大小的对象在读取前直接校验，更大的对象在后台校验
+const VERIFY_INLINE_LIMIT: u64 = 16 * 1024 * 1024; // 16MB
+
+/// 读取分块对象时预读的块占用的内存上限
+const CHUNK_PREFETCH_BYTES: u64 = 32 * 1024 * 1024; // 32MB
+
+/// 读取分块对象时最多同时读取的块数
+const MAX_PREFETCH_CHUNKS: u64 = 4;
+
+/// 可选的分片深度：objects/ 下一级目录名取哈希的前几个字符
+pub const SHARD_DEPTHS: [usize; 2] = [2, 4];
+
+/// 默认分片深度，256 个子目录
+pub const DEFAULT_SHARD_DEPTH: usize = 2;
+
+/// 对象内容的字节流，边读边产出
+pub type ObjectStream = BoxStream<'static, std::io::Result<Bytes>>;
+
+/// 临时文件名标记，文件监控据此忽略写入过程中的中间文件
+pub const TEMP_MARKER: &str = ".rcloud-tmp-";
+
+// [知识点 #145] 原子写入：临时文件 + rename
+// ----------------------------------------
+// 题目：为什么不直接 write 到目标路径？
+//
+// 讲解：
+// 直接写目标文件时，进程崩溃或断电会留下截断的半个文件，
+// 之后它会被当作"新版本"计算哈希并同步出去。
+//
+// 原子写入的步骤：
+// 1. 写入同目录下的临时文件（保证与目标在同一文件系统）
+// 2. sync_all 把数据刷到磁盘
+// 3. rename 覆盖目标 —— POSIX 保证 rename 是原子的
+//
+// 读者要么看到旧文件，要么看到完整的新文件，不会看到中间状态
+//
+// 思考：为什么临时文件必须和目标文件在同一个目录？
+// ----------------------------------------
+pub async fn write_atomic(target: &Path, content: &[u8]) -> Result<()> {
+    let tmp = temp_sibling(target);
+    let result = async {
+        let mut file = tokio::fs::File::create(&tmp).await?;
+        file.write_all(content).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&tmp, target).await
+    }
+    .await;
+
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&tmp).await;
+    }
+    Ok(result?)
+}
+
+pub async fn copy_atomic(source: &Path, target: &Path) -> Result<()> {
+    let tmp = temp_sibling(target);
+    let result = async {
+        tokio::fs::copy(source, &tmp).await?;
+        tokio::fs::File::open(&tmp).await?.sync_all().await?;
+        tokio::fs::rename(&tmp, target).await
+    }
+    .await;
+
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&tmp).await;
+    }
+    Ok(result?)
+}
+
+// objects/ 下的分片目录；uploads、trash 等目录的名字长度不同
+fn is_shard_name(name: &str) -> bool {
+    SHARD_DEPTHS.contains(&name.len()) && name.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+pub fn is_temp_file(path: &Path) -> bool {
+    path.file_name()
+        .map(|n| n.to_string_lossy().contains(TEMP_MARKER))
+        .unwrap_or(false)
+}
+
+fn temp_sibling(target: &Path) -> PathBuf {
+    let name = target
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    target.with_file_name(format!(".{}{}{}", name, TEMP_MARKER, uuid::Uuid::new_v4()))
+}
+
+// [知识点 #168] 流式接收上传
+// ----------------------------------------
+// 题目：为什么上传不先把整个请求体读进内存？
+//
+// 讲解：
+// 以 Bytes 提取请求体时，axum 要等全部数据到齐才调用 handler：
+// - 内存占用等于文件大小，并发上传几个大文件就可能耗尽内存
+// - 大小限制只能在读完之后检查，超限的数据早已全部收下
+//
+// StagedUpload 边收边写：每收到一段就写入暂存文件并更新 SHA-256，
+// 累计大小超过限制时立即中止。收完后哈希已经算好，
+// 存入对象存储只需一次同文件系统内的 rename，不再复制内容。
+//
+// 暂存文件放在 objects/ 下，名字带临时标记，文件监控会忽略它；
+// 值被丢弃（请求中止、校验失败、内容已存在）时自动删除
+//
+// 思考：客户端上传到一半断开，暂存文件会留下吗？
+// ----------------------------------------
+/// 流式上传的暂存文件，写入时同步计算哈希
+#[derive(Debug)]
+pub struct StagedUpload {
+    path: PathBuf,
+    file: tokio::fs::File,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl StagedUpload {
+    pub async fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.file.write_all(data).await?;
+        self.hasher.update(data);
+        self.size += data.len() as u64;
+        Ok(())
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 刷盘并返回内容哈希；之后不应再写入
+    pub async fn finish(&mut self) -> Result<String> {
+        self.file.sync_all().await?;
+        Ok(format!("{:x}", self.hasher.clone().finalize()))
+    }
+
+    /// 读取开头最多 limit 个字节，用于识别文件类型和提取媒体信息
+    pub async fn read_prefix(&self, limit: usize) -> Result<Vec<u8>> {
+        let file = tokio::fs::File::open(&self.path).await?;
+        let mut prefix = Vec::new();
+        file.take(limit as u64).read_to_end(&mut prefix).await?;
+        Ok(prefix)
+    }
+
+    /// 把暂存文件原子地移动到 target（必须与存储目录在同一文件系统）
+    pub async fn persist(&self, target: &Path) -> Result<()> {
+        tokio::fs::rename(&self.path, target).await?;
+        Ok(())
+    }
+}
+
+impl Drop for StagedUpload {
+    fn drop(&mut self) {
+        // 已经 persist 或存入对象存储时文件不存在，忽略错误
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct StorageConfig {
     pub storage_path: PathBuf,
@@ -56,46 +237,232 @@ impl Default for StorageConfig {
 #[derive(Debug, Clone)]
 pub struct StorageService {
     config: StorageConfig,
+    /// 同时进行的哈希计算
+    hashing: Arc<Semaphore>,
+    /// 同时进行的对象读写与删除
+    fs_ops: Arc<Semaphore>,
+    /// 存储目录下文件的哈希，修改时间和大小不变时不再重新计算
+    hashes: HashCache,
+    /// 读取对象时是否校验内容与哈希一致
+    verify_reads: bool,
+    /// 校验过和校验失败的对象
+    integrity: ObjectIntegrity,
+    /// 对象目录名取哈希的前几个字符
+    shard_depth: usize,
+    /// 压缩存储的对象总能读取；compress_objects 决定新存入的小对象是否压缩
+    compression: ObjectCompression,
+    compress_objects: bool,
 }
 
 impl StorageService {
     pub fn new(config: StorageConfig) -> Self {
-        StorageService { config }
+        let workers = WorkerConfig::default();
+        StorageService {
+            hashing: Arc::new(Semaphore::new(workers.hash_workers)),
+            fs_ops: Arc::new(Semaphore::new(workers.max_fs_ops)),
+            hashes: HashCache::new(),
+            verify_reads: false,
+            integrity: ObjectIntegrity::new(),
+            shard_depth: DEFAULT_SHARD_DEPTH,
+            compression: ObjectCompression::new(&config.storage_path.join("objects")),
+            compress_objects: false,
+            config,
+        }
+    }
+
+    /// 按配置限制哈希计算与文件操作的并发数
+    pub fn with_workers(mut self, workers: &WorkerConfig) -> Self {
+        self.hashing = Arc::new(Semaphore::new(workers.hash_workers));
+        self.fs_ops = Arc::new(Semaphore::new(workers.max_fs_ops));
+        self
+    }
+
+    /// 读取对象时校验内容是否仍与哈希一致
+    pub fn with_verify_reads(mut self, enabled: bool) -> Self {
+        self.verify_reads = enabled;
+        self
+    }
+
+    /// 新对象按这个深度分片；旧深度下的对象仍可读取，直到 reshard 把它们移走
+    pub fn with_shard_depth(mut self, depth: usize) -> Self {
+        self.shard_depth = depth;
+        self
+    }
+
+    /// 新存入的小对象用 zstd 压缩，训练过字典时使用字典
+    pub fn with_compression(mut self, enabled: bool) -> Self {
+        self.compress_objects = enabled;
+        self
     }
 
     pub fn storage_path(&self) -> &Path {
         &self.config.storage_path
     }
 
+    // [知识点 #146] 自适应分块策略
+    // ----------------------------------------
+    // 题目：为什么不同大小的文件要用不同的分块大小？
+    //
+    // 讲解：
+    // 固定分块大小对两端都不友好：
+    // - 小文件（≤ 基础分块）：分块只会多出一个 manifest，直接整体存储
+    // - 中等文件：使用基础分块（默认 4MB），兼顾去重粒度和请求数
+    // - 超大文件：20GB / 4MB = 5000 个分块，manifest 和请求数都会膨胀，
+    //   按"分块数不超过 MAX_CHUNKS_PER_FILE"放大分块（取 2 的幂）
+    //
+    // 客户端可以提议分块大小，服务端将其限制在 [MIN, MAX] 范围内，
+    // 双方以服务端返回的结果为准
+    //
+    // 思考：分块越大，断点续传时需要重传的数据越多，如何权衡？
+    // ----------------------------------------
+    pub fn chunk_size_for(&self, file_size: u64) -> Option<usize> {
+        let base = self.config.chunk_size;
+        if file_size <= base as u64 {
+            return None;
+        }
+
+        let per_chunk = file_size.div_ceil(MAX_CHUNKS_PER_FILE).next_power_of_two();
+        Some((per_chunk as usize).clamp(base, MAX_CHUNK_SIZE.max(base)))
+    }
+
+    pub fn negotiate_chunk_size(&self, file_size: u64, requested: Option<usize>) -> Option<usize> {
+        match requested {
+            Some(size) => {
+                let size = size.clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE);
+                (file_size > size as u64).then_some(size)
+            }
+            None => self.chunk_size_for(file_size),
+        }
+    }
+
     // [知识点 #122] 异步文件读取与哈希
     // ----------------------------------------
-    // 题目：为什么用 async 函数处理文件？
+    // 题目：为什么哈希计算不直接写在 async 函数里？
     //
     // 讲解：
-    // 文件 I/O 是阻塞操作，但在 tokio 中：
-    // - tokio::fs 在独立线程池执行，不阻塞调度器
-    // - 对于大文件，异步读取允许其他任务并发执行
+    // tokio::fs 会把每次读取交给阻塞线程池，但 SHA-256 本身是纯 CPU 计算，
+    // 在 async 函数里 update 一个几 GB 的文件会一直占住调度器的工作线程，
+    // 同一线程上的其他请求都得等它算完。
+    //
+    // 所以整段"读文件 + 算哈希"放进 spawn_blocking，一次性在阻塞线程上完成：
+    // - 工作线程只负责等结果，继续处理其他请求
+    // - 信号量限制同时计算的数量（hash_workers），大服务器可以用满所有核，
+    //   小机器不会被并发上传的哈希计算拖到无法响应
     //
     // update 方法增量更新哈希，避免一次性读入内存
-    // 这对于大文件很重要
     //
-    // 思考：如何在读取大文件时显示进度？
+    // 思考：信号量许可为什么要在 spawn_blocking 之前获取，而不是在闭包里？
     // ----------------------------------------
     pub async fn compute_hash(&self, path: &Path) -> Result<String> {
-        let mut file = tokio::fs::File::open(path).await?;
-        let mut hasher = Sha256::new();
-        let mut buffer = vec![0u8; self.config.chunk_size];
+        let metadata = match self.caches_hash(path) {
+            true => tokio::fs::metadata(path).await.ok(),
+            false => None,
+        };
+        if let Some(hash) = metadata.as_ref().and_then(|m| self.hashes.get(path, m)) {
+            return Ok(hash);
+        }
+        let hash = self.hash_file(path).await?;
+        if let Some(metadata) = &metadata {
+            self.hashes.insert(path, metadata, &hash);
+        }
+        Ok(hash)
+    }
 
-        loop {
-            let bytes_read = file.read(&mut buffer).await?;
-            if bytes_read == 0 {
-                break;
+    // 对象按内容寻址、临时文件转眼就被移走，缓存它们的哈希没有意义
+    fn caches_hash(&self, path: &Path) -> bool {
+        path.starts_with(&self.config.storage_path)
+            && !path.starts_with(self.config.storage_path.join("objects"))
+            && !is_temp_file(path)
+    }
+
+    /// 文件监控发现 path（或其下的文件）被外部修改、删除或移走，缓存的哈希作废
+    pub fn forget_hash(&self, path: &Path) {
+        self.hashes.forget(path);
+    }
+
+    /// 缓存的哈希条目数
+    pub fn cached_hashes(&self) -> usize {
+        self.hashes.len()
+    }
+
+    /// 用元数据中的哈希预热缓存，返回采信的条目数：
+    /// 磁盘上的文件大小与记录一致、且修改时间不晚于记录的更新时间时才采信
+    pub async fn warm_hashes(&self, records: &[FileRecord]) -> usize {
+        let candidates: Vec<(PathBuf, u64, String, std::time::SystemTime)> = records
+            .iter()
+            .filter_map(|record| {
+                Some((
+                    self.config.storage_path.join(&record.path),
+                    record.size,
+                    record.hash.clone()?,
+                    record.updated_at.into(),
+                ))
+            })
+            .collect();
+        let hashes = self.hashes.clone();
+        // 只做 stat，不读内容；逐个 await 反而比在阻塞线程里一次做完慢
+        tokio::task::spawn_blocking(move || {
+            let mut warmed = 0;
+            for (path, size, hash, updated_at) in candidates {
+                let Ok(metadata) = std::fs::metadata(&path) else {
+                    continue;
+                };
+                let unchanged = metadata
+                    .modified()
+                    .is_ok_and(|modified| modified <= updated_at);
+                if metadata.is_file() && metadata.len() == size && unchanged {
+                    hashes.insert(&path, &metadata, &hash);
+                    warmed += 1;
+                }
             }
-            hasher.update(&buffer[..bytes_read]);
-        }
+            warmed
+        })
+        .await
+        .unwrap_or(0)
+    }
+
+    async fn hash_file(&self, path: &Path) -> Result<String> {
+        let path = path.to_path_buf();
+        let buffer_size = self.config.chunk_size;
+        self.hash_blocking(move || -> Result<String> {
+            let mut file = std::fs::File::open(&path)?;
+            let mut hasher = Sha256::new();
+            let mut buffer = vec![0u8; buffer_size];
 
-        let hash = hasher.finalize();
-        Ok(format!("{:x}", hash))
+            loop {
+                let bytes_read = file.read(&mut buffer)?;
+                if bytes_read == 0 {
+                    break;
+                }
+                hasher.update(&buffer[..bytes_read]);
+            }
+
+            Ok(format!("{:x}", hasher.finalize()))
+        })
+        .await?
+    }
+
+    /// 按块计算文件的签名，块大小随文件大小选择
+    pub async fn signature(&self, path: &Path) -> Result<FileSignature> {
+        let path = path.to_path_buf();
+        self.hash_blocking(move || -> Result<FileSignature> {
+            let file = std::fs::File::open(&path)?;
+            let block_size = delta::block_size_for(file.metadata()?.len());
+            Ok(delta::signature(std::io::BufReader::new(file), block_size)?)
+        })
+        .await?
+    }
+
+    /// 占用一个哈希许可，在阻塞线程池中执行 job
+    async fn hash_blocking<T, F>(&self, job: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let _permit = acquire(&self.hashing).await;
+        tokio::task::spawn_blocking(job)
+            .await
+            .map_err(|e| Error::Io(std::io::Error::other(e)))
     }
 
     // [知识点 #006] 路径规范化与安全
@@ -116,63 +483,354 @@ impl StorageService {
     // 思考：为什么取前两个字符而不是更多？
     // ----------------------------------------
     fn hash_to_path(&self, hash: &str) -> PathBuf {
-        let (prefix, rest) = hash.split_at(2);
+        self.locate(hash, "")
+    }
+
+    /// 压缩存储时对象文件的位置：与原始对象在同一个分片目录，文件名带上前缀
+    fn compressed_path(&self, hash: &str) -> PathBuf {
+        self.locate(hash, COMPRESSED_PREFIX)
+    }
+
+    fn locate(&self, hash: &str, marker: &str) -> PathBuf {
+        let path = self.shard_path(hash, self.shard_depth, marker);
+        if path.exists() {
+            return path;
+        }
+        // 迁移尚未完成时对象可能还在旧深度的目录中
+        SHARD_DEPTHS
+            .iter()
+            .filter(|&&depth| depth != self.shard_depth)
+            .map(|&depth| self.shard_path(hash, depth, marker))
+            .find(|legacy| legacy.exists())
+            .unwrap_or(path)
+    }
+
+    fn shard_path(&self, hash: &str, depth: usize, marker: &str) -> PathBuf {
+        let (prefix, rest) = hash.split_at(depth);
         self.config
             .storage_path
             .join("objects")
             .join(prefix)
-            .join(rest)
+            .join(format!("{}{}", marker, rest))
+    }
+
+    // [知识点 #192] 调整分片深度：在线迁移
+    // ----------------------------------------
+    // 题目：两个字符只有 256 个子目录，上亿个对象时每个目录有几十万个文件，怎么办？
+    //
+    // 讲解：
+    // 有的文件系统单个目录的条目数有上限，条目太多时查找和备份工具也会变慢。
+    // 取四个字符是 65536 个子目录，每个目录的文件数降到原来的 1/256。
+    //
+    // 改了深度之后，已有的对象还在旧位置。停机迁移上亿个文件不现实，于是：
+    // - 查找对象先看新位置，不在时再看旧位置，迁移期间读写都不受影响
+    // - 后台逐个把旧位置的对象 rename 到新位置：同一文件系统内只改目录项，不复制数据；
+    //   新位置已有相同哈希的对象时（迁移期间又上传了一次）直接删除旧的
+    // - 每一步都是原子的，迁移中途停止也没关系，下次启动继续
+    //
+    // 思考：迁移期间读者刚拿到旧路径、对象就被移走了，应该怎样处理？
+    // ----------------------------------------
+    /// 把其他深度目录下的对象移到当前深度的位置，返回移动的对象数；
+    /// 写入中的临时文件留给下次迁移
+    pub async fn reshard(&self) -> Result<usize> {
+        let root = self.config.storage_path.join("objects");
+        let mut shards = match tokio::fs::read_dir(&root).await {
+            Ok(shards) => shards,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+        let mut moved = 0;
+        while let Some(shard) = shards.next_entry().await? {
+            let prefix = shard.file_name().to_string_lossy().to_string();
+            if prefix.len() == self.shard_depth
+                || !is_shard_name(&prefix)
+                || !shard.file_type().await?.is_dir()
+            {
+                continue;
+            }
+
+            let mut objects = tokio::fs::read_dir(shard.path()).await?;
+            while let Some(object) = objects.next_entry().await? {
+                let source = object.path();
+                if is_temp_file(&source) {
+                    continue;
+                }
+                let name = object.file_name().to_string_lossy().to_string();
+                let target = match name.strip_prefix(COMPRESSED_PREFIX) {
+                    Some(rest) => self.shard_path(
+                        &format!("{}{}", prefix, rest),
+                        self.shard_depth,
+                        COMPRESSED_PREFIX,
+                    ),
+                    None => self.shard_path(&format!("{}{}", prefix, name), self.shard_depth, ""),
+                };
+                let _permit = acquire(&self.fs_ops).await;
+                if target.exists() {
+                    tokio::fs::remove_file(&source).await?;
+                } else {
+                    if let Some(parent) = target.parent() {
+                        tokio::fs::create_dir_all(parent).await?;
+                    }
+                    tokio::fs::rename(&source, &target).await?;
+                }
+                moved += 1;
+            }
+            // 还有临时文件时目录不为空，保留
+            let _ = tokio::fs::remove_dir(shard.path()).await;
+        }
+        Ok(moved)
     }
 
     pub async fn store_file(&self, source: &Path) -> Result<(String, u64)> {
         let hash = self.compute_hash(source).await?;
-        let target = self.hash_to_path(&hash);
+        let size = tokio::fs::metadata(source).await?.len();
+        if self.compresses(size) {
+            let content = tokio::fs::read(source).await?;
+            self.store_whole(&hash, &content).await?;
+            return Ok((hash, size));
+        }
 
-        if !target.exists() {
+        let target = self.hash_to_path(&hash);
+        if self.needs_whole_write(&hash) {
             if let Some(parent) = target.parent() {
                 tokio::fs::create_dir_all(parent).await?;
             }
-            tokio::fs::copy(source, &target).await?;
+            let _permit = acquire(&self.fs_ops).await;
+            copy_atomic(source, &target).await?;
+            self.integrity.repaired(&hash);
         }
-
-        let metadata = tokio::fs::metadata(source).await?;
-        Ok((hash, metadata.len()))
+        Ok((hash, size))
     }
 
-    pub async fn store_content(&self, content: &[u8]) -> Result<(String, u64)> {
+    pub fn compute_content_hash(&self, content: &[u8]) -> String {
         let mut hasher = Sha256::new();
         hasher.update(content);
-        let hash = format!("{:x}", hasher.finalize());
+        format!("{:x}", hasher.finalize())
+    }
 
-        let target = self.hash_to_path(&hash);
+    pub async fn store_content(&self, content: &[u8]) -> Result<(String, u64)> {
+        let hash = self.compute_content_hash(content);
+        self.store_whole(&hash, content).await?;
+        Ok((hash, content.len() as u64))
+    }
+
+    fn compresses(&self, size: u64) -> bool {
+        self.compress_objects && size <= COMPRESS_MAX_SIZE
+    }
 
-        if !target.exists() {
+    /// 以已知哈希存入整体对象：开启压缩时小对象压缩存储，压缩后没有变小的原样存储
+    async fn store_whole(&self, hash: &str, content: &[u8]) -> Result<()> {
+        if !self.needs_whole_write(hash) {
+            return Ok(());
+        }
+        if self.compresses(content.len() as u64) {
+            let compression = self.compression.clone();
+            let data = content.to_vec();
+            if let Some(compressed) = self
+                .hash_blocking(move || compression.compress(&data))
+                .await??
+            {
+                let target = self.compressed_path(hash);
+                if let Some(parent) = target.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                let _permit = acquire(&self.fs_ops).await;
+                write_atomic(&target, &compressed).await?;
+                // 损坏的原始对象由压缩的这份取代
+                let raw = self.hash_to_path(hash);
+                if raw.exists() {
+                    tokio::fs::remove_file(&raw).await?;
+                }
+                self.integrity.repaired(hash);
+                return Ok(());
+            }
+        }
+        self.store_object(hash, content).await
+    }
+
+    /// 以已知哈希存入内容，相同对象已存在时跳过
+    async fn store_object(&self, hash: &str, content: &[u8]) -> Result<()> {
+        let target = self.hash_to_path(hash);
+
+        if self.needs_write(hash, &target) {
             if let Some(parent) = target.parent() {
                 tokio::fs::create_dir_all(parent).await?;
             }
-            tokio::fs::write(&target, content).await?;
+            let _permit = acquire(&self.fs_ops).await;
+            write_atomic(&target, content).await?;
+            self.integrity.repaired(hash);
         }
+        Ok(())
+    }
 
-        Ok((hash, content.len() as u64))
+    // 已知损坏的对象不算"已存在"，用这次收到的正确内容覆盖它
+    fn needs_write(&self, hash: &str, target: &Path) -> bool {
+        !target.exists() || self.integrity.is_corrupted(hash)
+    }
+
+    // 整体对象原样存储或压缩存储都算已存在；分块只看原样存储的那份
+    fn needs_whole_write(&self, hash: &str) -> bool {
+        self.needs_write(hash, &self.hash_to_path(hash))
+            && (!self.compressed_path(hash).exists() || self.integrity.is_corrupted(hash))
+    }
+
+    pub async fn begin_upload(&self) -> Result<StagedUpload> {
+        let dir = self.config.storage_path.join("objects");
+        tokio::fs::create_dir_all(&dir).await?;
+        let path = temp_sibling(&dir.join("upload"));
+        let file = tokio::fs::File::create(&path).await?;
+        Ok(StagedUpload {
+            path,
+            file,
+            hasher: Sha256::new(),
+            size: 0,
+        })
+    }
+
+    /// 写完的暂存文件移入对象存储；相同内容已存在时直接丢弃暂存文件
+    pub async fn store_staged(&self, upload: &mut StagedUpload) -> Result<(String, u64)> {
+        let hash = upload.finish().await?;
+        if self.compresses(upload.size()) {
+            let content = tokio::fs::read(upload.path()).await?;
+            self.store_whole(&hash, &content).await?;
+            return Ok((hash, upload.size()));
+        }
+        let target = self.hash_to_path(&hash);
+        if self.needs_whole_write(&hash) {
+            if let Some(parent) = target.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            let _permit = acquire(&self.fs_ops).await;
+            upload.persist(&target).await?;
+            self.integrity.repaired(&hash);
+        }
+        Ok((hash, upload.size()))
     }
 
+    /// 整个对象读入内存，只适合小对象；发送给客户端用 stream_object
     pub async fn retrieve_file(&self, hash: &str) -> Result<Vec<u8>> {
         let path = self.hash_to_path(hash);
         if !path.exists() {
-            return Err(Error::NotFound(path));
+            return match self.read_compressed(hash).await? {
+                Some(content) => self.verified_content(hash, content).await,
+                None => Err(Error::NotFound(path)),
+            };
         }
-        let content = tokio::fs::read(&path).await?;
-        Ok(content)
+        let content = {
+            let _permit = acquire(&self.fs_ops).await;
+            tokio::fs::read(&path).await?
+        };
+        self.verified_content(hash, content).await
+    }
+
+    /// 压缩存储的对象解压后的内容；对象不是压缩存储时返回 None。
+    /// 无法解压时记为损坏
+    async fn read_compressed(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        let data = {
+            let _permit = acquire(&self.fs_ops).await;
+            match tokio::fs::read(self.compressed_path(hash)).await {
+                Ok(data) => data,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+        };
+        let compression = self.compression.clone();
+        match self
+            .hash_blocking(move || compression.decompress(&data))
+            .await?
+        {
+            Ok(content) => Ok(Some(content)),
+            Err(e) => {
+                tracing::warn!("Failed to decompress object {}: {}", hash, e);
+                self.flag_corrupted(hash);
+                Err(Error::Corrupted(hash.to_string()))
+            }
+        }
+    }
+
+    /// 新对象压缩时使用的字典；还没有训练过时为 None
+    pub fn dictionary_id(&self) -> Option<u32> {
+        self.compression.current().ok().flatten()
+    }
+
+    /// 从已存储的小对象中取样训练压缩字典，之后压缩的对象都使用它；
+    /// 样本不足时返回 None
+    pub async fn train_dictionary(&self) -> Result<Option<TrainedDictionary>> {
+        let candidates: Vec<String> = self
+            .stored_objects()
+            .await?
+            .into_iter()
+            .filter(|(_, size)| (1..=COMPRESS_MAX_SIZE).contains(size))
+            .map(|(hash, _)| hash)
+            .take(MAX_SAMPLES)
+            .collect();
+        let mut samples = Vec::with_capacity(candidates.len());
+        for hash in &candidates {
+            // 读不出来的对象不影响训练
+            if let Ok(content) = self.retrieve_chunked(hash).await {
+                samples.push(content);
+            }
+        }
+
+        let count = samples.len();
+        let Some((id, dictionary)) = self
+            .hash_blocking(move || compression::train(&samples))
+            .await??
+        else {
+            return Ok(None);
+        };
+        tokio::fs::create_dir_all(self.compression.dir()).await?;
+        write_atomic(&self.compression.dictionary_path(id), &dictionary).await?;
+        write_atomic(
+            &self.compression.current_path(),
+            format!("{:08x}", id).as_bytes(),
+        )
+        .await?;
+        let size = dictionary.len();
+        self.compression.activate(id, dictionary);
+        Ok(Some(TrainedDictionary {
+            id,
+            samples: count,
+            size,
+        }))
+    }
+
+    /// 对象在磁盘上的位置，供需要按偏移读取的场景使用
+    pub fn object_path(&self, hash: &str) -> PathBuf {
+        self.hash_to_path(hash)
     }
 
     pub async fn file_exists(&self, hash: &str) -> bool {
-        self.hash_to_path(hash).exists()
+        self.hash_to_path(hash).exists() || self.compressed_path(hash).exists()
+    }
+
+    /// 对象在磁盘上的全部文件：整体存储的对象文件、分块清单和清单中的各块
+    pub async fn object_files(&self, hash: &str) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        let whole = self.hash_to_path(hash);
+        if whole.exists() {
+            files.push(whole);
+        }
+        let compressed = self.compressed_path(hash);
+        if compressed.exists() {
+            files.push(compressed);
+        }
+        let manifest_path = self.hash_to_path(&format!("manifest-{}", hash));
+        if let Ok(content) = tokio::fs::read(&manifest_path).await {
+            if let Ok(manifest) = serde_json::from_slice::<ChunkManifest>(&content) {
+                files.extend(manifest.chunks.iter().map(|chunk| self.hash_to_path(chunk)));
+            }
+            files.push(manifest_path);
+        }
+        files
     }
 
     pub async fn delete_file(&self, hash: &str) -> Result<()> {
-        let path = self.hash_to_path(hash);
-        if path.exists() {
-            tokio::fs::remove_file(&path).await?;
+        for path in [self.hash_to_path(hash), self.compressed_path(hash)] {
+            if path.exists() {
+                let _permit = acquire(&self.fs_ops).await;
+                tokio::fs::remove_file(&path).await?;
+            }
         }
         Ok(())
     }
@@ -193,29 +851,47 @@ impl StorageService {
     // 思考：如何确定最优的块大小？
     // ----------------------------------------
     pub async fn store_chunked(&self, source: &Path) -> Result<(String, u64, Vec<String>)> {
+        self.store_chunked_with(source, None).await
+    }
+
+    /// 按指定分块大小存储；为 None 时使用自适应策略
+    pub async fn store_chunked_with(
+        &self,
+        source: &Path,
+        chunk_size: Option<usize>,
+    ) -> Result<(String, u64, Vec<String>)> {
         let metadata = tokio::fs::metadata(source).await?;
         let file_size = metadata.len();
 
-        if file_size <= self.config.chunk_size as u64 {
+        let Some(chunk_size) = self.negotiate_chunk_size(file_size, chunk_size) else {
             let (hash, size) = self.store_file(source).await?;
             return Ok((hash.clone(), size, vec![hash]));
-        }
+        };
 
         let mut file = tokio::fs::File::open(source).await?;
-        let mut buffer = vec![0u8; self.config.chunk_size];
+        let mut buffer = vec![0u8; chunk_size];
         let mut chunks = Vec::new();
         let mut file_hasher = Sha256::new();
 
         loop {
-            let bytes_read = file.read(&mut buffer).await?;
+            let bytes_read = read_full(&mut file, &mut buffer).await?;
             if bytes_read == 0 {
                 break;
             }
 
-            let chunk_data = &buffer[..bytes_read];
-            file_hasher.update(chunk_data);
+            // 缓冲区和整体哈希移进阻塞线程，算完再取回复用
+            let chunk_hash;
+            (buffer, file_hasher, chunk_hash) = self
+                .hash_blocking(move || {
+                    let chunk_data = &buffer[..bytes_read];
+                    file_hasher.update(chunk_data);
+                    let chunk_hash = format!("{:x}", Sha256::digest(chunk_data));
+                    (buffer, file_hasher, chunk_hash)
+                })
+                .await?;
 
-            let (chunk_hash, _) = self.store_content(chunk_data).await?;
+            self.store_object(&chunk_hash, &buffer[..bytes_read])
+                .await?;
             chunks.push(chunk_hash);
         }
 
@@ -224,6 +900,7 @@ impl StorageService {
         let manifest = ChunkManifest {
             file_hash: file_hash.clone(),
             file_size,
+            chunk_size,
             chunks: chunks.clone(),
         };
         let manifest_path = self.hash_to_path(&format!("manifest-{}", file_hash));
@@ -231,33 +908,564 @@ impl StorageService {
             tokio::fs::create_dir_all(parent).await?;
         }
         let manifest_content = serde_json::to_vec(&manifest)?;
-        tokio::fs::write(&manifest_path, manifest_content).await?;
+        write_atomic(&manifest_path, &manifest_content).await?;
+        // 损坏的块在上面已经重写
+        self.integrity.repaired(&file_hash);
 
         Ok((file_hash, file_size, chunks))
     }
 
     pub async fn retrieve_chunked(&self, hash: &str) -> Result<Vec<u8>> {
+        let (size, mut content) = self.object_stream(hash).await?;
+        let mut result = Vec::with_capacity(size as usize);
+        while let Some(bytes) = content.next().await {
+            result.extend_from_slice(&bytes?);
+        }
+        self.verified_content(hash, result).await
+    }
+
+    // [知识点 #180] 流式读取对象
+    // ----------------------------------------
+    // 题目：100 个客户端同时下载 1GB 的文件，服务端要占多少内存？
+    //
+    // 讲解：
+    // 先 tokio::fs::read 到 Vec 再发送，每个下载都要整份内容常驻内存，
+    // 分块存储的对象还要先拼接成一个大 Vec，内存随并发数和文件大小线性增长。
+    //
+    // ReaderStream 把文件包装成 Stream<Item = Bytes>：每次只读一个缓冲区，
+    // 交给响应体发出后再读下一段，慢客户端的背压直接传到读文件这一侧。
+    // 分块存储的对象按清单顺序依次打开各块，同一时刻只占用一个文件句柄和一个缓冲区。
+    // 这样内存占用只与并发数有关，与文件大小无关。
+    //
+    // Range 请求落在分块对象的中间时，按清单中的块大小算出各块的偏移，
+    // 跳过范围之前的块、在第一块内 seek，见 stream_object_range。
+    //
+    // 思考：多个客户端并发读取同一对象的不同范围时，预读的块能否共享？
+    // ----------------------------------------
+    /// 对象的大小和内容流；分块存储的对象按清单顺序产出各块，后面几块提前并发读取。
+    ///
+    /// 开启读取校验时先按 check_object 校验
+    pub async fn stream_object(&self, hash: &str) -> Result<(u64, ObjectStream)> {
+        self.check_object(hash).await?;
+        self.object_stream(hash).await
+    }
+
+    // [知识点 #189] 有序的并发预读：buffered
+    // ----------------------------------------
+    // 题目：分块对象逐块读取，快盘上为什么下载仍然慢？
+    //
+    // 讲解：
+    // 逐块读取时，读完一块、发出去，才开始读下一块，
+    // 每块的打开和读取延迟串行累加，磁盘的并发能力闲置。
+    //
+    // StreamExt::buffered(n) 同时推进最多 n 个 future，
+    // 但按输入顺序产出结果：第 3 块先读完也要等第 2 块，内容顺序不会乱。
+    // 和 buffer_unordered 的区别就在这里——后者谁先完成先产出，适合互不相关的任务。
+    //
+    // 预读的块整块放在内存里，n 由 CHUNK_PREFETCH_BYTES / 块大小 决定，
+    // 再用 MAX_PREFETCH_CHUNKS 封顶；块太大、只能预读一块时退回逐块流式读取。
+    // 响应体消费慢时 buffered 不会继续拉取新块，背压依旧成立。
+    //
+    // 思考：预读的块数应该随客户端的下载速度调整吗？
+    // ----------------------------------------
+    async fn object_stream(&self, hash: &str) -> Result<(u64, ObjectStream)> {
+        // 压缩存储的都是小对象，整个解压后一次产出
+        if !self.hash_to_path(hash).exists() {
+            if let Some(content) = self.read_compressed(hash).await? {
+                let size = content.len() as u64;
+                let content = stream::once(async move { Ok(Bytes::from(content)) });
+                return Ok((size, content.boxed()));
+            }
+        }
+        let (size, parts) = self.object_parts(hash).await?;
+        let depth = prefetch_depth(size, parts.len());
+        let parts: Vec<PathBuf> = parts.iter().map(|part| self.hash_to_path(part)).collect();
+        if depth <= 1 {
+            // 轮到某一块时才打开它
+            let content = stream::iter(parts)
+                .then(
+                    |path| async move { tokio::fs::File::open(path).await.map(ReaderStream::new) },
+                )
+                .try_flatten();
+            return Ok((size, content.boxed()));
+        }
+        // 同时读取接下来的 depth 块，按清单顺序产出
+        let content = stream::iter(parts)
+            .map(|path| async move { tokio::fs::read(path).await.map(Bytes::from) })
+            .buffered(depth);
+        Ok((size, content.boxed()))
+    }
+
+    /// 对象中从 start 起 len 字节的内容流；分块存储的对象按清单算出各块的偏移，
+    /// 只打开与范围相交的块，并从块内对应的位置开始读
+    pub async fn stream_object_range(
+        &self,
+        hash: &str,
+        start: u64,
+        len: u64,
+    ) -> Result<ObjectStream> {
+        self.check_object(hash).await?;
+        let end = start.saturating_add(len);
+        if !self.hash_to_path(hash).exists() {
+            if let Some(content) = self.read_compressed(hash).await? {
+                let size = content.len() as u64;
+                let content =
+                    Bytes::from(content).slice(start.min(size) as usize..end.min(size) as usize);
+                return Ok(stream::once(async move { Ok(content) }).boxed());
+            }
+        }
+        let parts: Vec<(PathBuf, u64, u64)> = self
+            .part_offsets(hash)
+            .await?
+            .into_iter()
+            .filter_map(|(path, offset, part_len)| {
+                let from = start.max(offset);
+                let to = end.min(offset + part_len);
+                (from < to).then_some((path, from - offset, to - from))
+            })
+            .collect();
+        let content = stream::iter(parts)
+            .then(|(path, skip, take)| async move {
+                let mut file = tokio::fs::File::open(path).await?;
+                file.seek(SeekFrom::Start(skip)).await?;
+                Ok::<_, std::io::Error>(ReaderStream::new(file.take(take)))
+            })
+            .try_flatten();
+        Ok(content.boxed())
+    }
+
+    /// 对象各部分的文件、在对象中的偏移和长度。分块对象的偏移按清单中的块大小计算，
+    /// 旧版本清单没有记录块大小时取各块的文件大小
+    async fn part_offsets(&self, hash: &str) -> Result<Vec<(PathBuf, u64, u64)>> {
+        let path = self.hash_to_path(hash);
+        if let Ok(metadata) = tokio::fs::metadata(&path).await {
+            return Ok(vec![(path, 0, metadata.len())]);
+        }
+        let manifest_content =
+            match tokio::fs::read(self.hash_to_path(&format!("manifest-{}", hash))).await {
+                Ok(content) => content,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    return Err(Error::NotFound(path))
+                }
+                Err(e) => return Err(e.into()),
+            };
+        let manifest: ChunkManifest = serde_json::from_slice(&manifest_content)?;
+        let mut parts = Vec::with_capacity(manifest.chunks.len());
+        let mut offset = 0;
+        for chunk in &manifest.chunks {
+            let path = self.hash_to_path(chunk);
+            let len = match manifest.chunk_size {
+                0 => tokio::fs::metadata(&path).await?.len(),
+                chunk_size => (chunk_size as u64).min(manifest.file_size.saturating_sub(offset)),
+            };
+            parts.push((path, offset, len));
+            offset += len;
+        }
+        Ok(parts)
+    }
+
+    /// 对象存储中的全部内容及大小，按哈希排序：整体存储的对象和分块存储的对象各算一个，
+    /// 分块本身不算。不依赖元数据，元数据丢失后据此找回内容
+    pub async fn stored_objects(&self) -> Result<Vec<(String, u64)>> {
+        let root = self.config.storage_path.join("objects");
+        let mut shards = match tokio::fs::read_dir(&root).await {
+            Ok(shards) => shards,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut objects = std::collections::BTreeMap::new();
+        let mut parts = std::collections::HashSet::new();
+        while let Some(shard) = shards.next_entry().await? {
+            let prefix = shard.file_name().to_string_lossy().to_string();
+            if !is_shard_name(&prefix) || !shard.file_type().await?.is_dir() {
+                continue;
+            }
+            let mut entries = tokio::fs::read_dir(shard.path()).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                if is_temp_file(&entry.path()) {
+                    continue;
+                }
+                let file_name = entry.file_name().to_string_lossy().to_string();
+                let name = format!("{}{}", prefix, file_name);
+                if name.starts_with("manifest-") {
+                    let content = tokio::fs::read(entry.path()).await?;
+                    let manifest: ChunkManifest = serde_json::from_slice(&content)?;
+                    parts.extend(manifest.chunks);
+                    objects.insert(manifest.file_hash, manifest.file_size);
+                } else if let Some(rest) = file_name.strip_prefix(COMPRESSED_PREFIX) {
+                    let content = tokio::fs::read(entry.path()).await?;
+                    let size = compression::content_size(&content).unwrap_or_default();
+                    objects.insert(format!("{}{}", prefix, rest), size);
+                } else {
+                    objects.insert(name, entry.metadata().await?.len());
+                }
+            }
+        }
+        // 分块本身不是独立的内容；与某一块内容相同的整体对象也跳过，它的内容在分块对象中
+        Ok(objects
+            .into_iter()
+            .filter(|(hash, _)| !parts.contains(hash))
+            .collect())
+    }
+
+    // 对象的大小和组成它的对象文件（按哈希）：整体存储的对象就是它自己，
+    // 分块存储的对象是清单中的各块。两种都有时（同样内容既整体上传过、又分块上传过）用整体的那份
+    async fn object_parts(&self, hash: &str) -> Result<(u64, Vec<String>)> {
+        let path = self.hash_to_path(hash);
+        if let Ok(metadata) = tokio::fs::metadata(&path).await {
+            return Ok((metadata.len(), vec![hash.to_string()]));
+        }
+        // 压缩存储的对象按原始大小计算；读取和校验前先解压，见 read_compressed
+        if let Ok(content) = tokio::fs::read(self.compressed_path(hash)).await {
+            let size = compression::content_size(&content)
+                .ok_or_else(|| Error::Corrupted(hash.to_string()))?;
+            return Ok((size, Vec::new()));
+        }
         let manifest_path = self.hash_to_path(&format!("manifest-{}", hash));
+        if !manifest_path.exists() {
+            return Err(Error::NotFound(path));
+        }
+        let manifest_content = tokio::fs::read(&manifest_path).await?;
+        let manifest: ChunkManifest = serde_json::from_slice(&manifest_content)?;
+        Ok((manifest.file_size, manifest.chunks))
+    }
 
-        if manifest_path.exists() {
-            let manifest_content = tokio::fs::read(&manifest_path).await?;
-            let manifest: ChunkManifest = serde_json::from_slice(&manifest_content)?;
+    /// 读取前的校验：已知损坏的对象返回 Corrupted；开启读取校验时，
+    /// 小对象当场校验，大对象照常读取，同时在后台校验（每个对象只校验一次）
+    pub async fn check_object(&self, hash: &str) -> Result<()> {
+        if self.integrity.is_corrupted(hash) {
+            return Err(Error::Corrupted(hash.to_string()));
+        }
+        if !self.verify_reads {
+            return Ok(());
+        }
+        let (size, _) = self.object_parts(hash).await?;
+        if size <= VERIFY_INLINE_LIMIT {
+            return self.verify_object(hash).await;
+        }
+        if self.integrity.begin_background(hash) {
+            let storage = self.clone();
+            let hash = hash.to_string();
+            tokio::spawn(async move {
+                match storage.verify_object(&hash).await {
+                    Ok(()) => storage.integrity.verified(&hash),
+                    // 已记为损坏
+                    Err(Error::Corrupted(_)) => {}
+                    Err(e) => {
+                        tracing::warn!("Failed to verify object {}: {}", hash, e);
+                        storage.integrity.abandoned(&hash);
+                    }
+                }
+            });
+        }
+        Ok(())
+    }
 
-            let mut result = Vec::with_capacity(manifest.file_size as usize);
-            for chunk_hash in &manifest.chunks {
-                let chunk_data = self.retrieve_file(chunk_hash).await?;
-                result.extend_from_slice(&chunk_data);
+    /// 重新计算对象内容的哈希并与对象名比较，分块存储的对象逐块比较；
+    /// 不一致时记为损坏并返回 Corrupted
+    pub async fn verify_object(&self, hash: &str) -> Result<()> {
+        if !self.hash_to_path(hash).exists() {
+            if let Some(content) = self.read_compressed(hash).await? {
+                let actual = self
+                    .hash_blocking(move || format!("{:x}", Sha256::digest(&content)))
+                    .await?;
+                if actual != hash {
+                    self.flag_corrupted(hash);
+                    return Err(Error::Corrupted(hash.to_string()));
+                }
+                return Ok(());
+            }
+        }
+        let (_, parts) = self.object_parts(hash).await?;
+        for part in &parts {
+            let actual = self.hash_file(&self.hash_to_path(part)).await?;
+            if actual != *part {
+                if part != hash {
+                    self.flag_corrupted(part);
+                }
+                self.flag_corrupted(hash);
+                return Err(Error::Corrupted(hash.to_string()));
             }
-            Ok(result)
-        } else {
-            self.retrieve_file(hash).await
         }
+        Ok(())
+    }
+
+    // 内容已经整个读入内存，校验只需再算一次哈希
+    async fn verified_content(&self, hash: &str, content: Vec<u8>) -> Result<Vec<u8>> {
+        if self.integrity.is_corrupted(hash) {
+            return Err(Error::Corrupted(hash.to_string()));
+        }
+        if !self.verify_reads {
+            return Ok(content);
+        }
+        let (content, actual) = self
+            .hash_blocking(move || {
+                let actual = format!("{:x}", Sha256::digest(&content));
+                (content, actual)
+            })
+            .await?;
+        if actual != hash {
+            self.flag_corrupted(hash);
+            return Err(Error::Corrupted(hash.to_string()));
+        }
+        Ok(content)
+    }
+
+    /// 记下内容与哈希不一致的对象：之后读取直接返回 Corrupted，
+    /// 同样内容再次存入时覆盖它
+    pub fn flag_corrupted(&self, hash: &str) {
+        if !self.integrity.is_corrupted(hash) {
+            tracing::error!(
+                "Object {} does not match its hash; it will be rewritten by the next upload of the same content",
+                hash
+            );
+        }
+        self.integrity.corrupted(hash);
     }
+
+    /// 等待修复的损坏对象
+    pub fn corrupted_objects(&self) -> Vec<String> {
+        self.integrity.corrupted_objects()
+    }
+}
+
+// 信号量只在 StorageService 内部持有，从不关闭
+async fn acquire(semaphore: &Semaphore) -> SemaphorePermit<'_> {
+    semaphore
+        .acquire()
+        .await
+        .expect("storage semaphores are never closed")
+}
+
+// 分块对象同时预读的块数：预读的块不超过 CHUNK_PREFETCH_BYTES；整体存储的对象只有一块
+fn prefetch_depth(size: u64, parts: usize) -> usize {
+    if parts <= 1 {
+        return 1;
+    }
+    let chunk_size = size.div_ceil(parts as u64).max(1);
+    (CHUNK_PREFETCH_BYTES / chunk_size).clamp(1, MAX_PREFETCH_CHUNKS) as usize
+}
+
+// read 可能只返回部分数据；分块边界必须严格等于分块大小，所以读满缓冲区
+async fn read_full(file: &mut tokio::fs::File, buffer: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        let n = file.read(&mut buffer[filled..]).await?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    Ok(filled)
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct ChunkManifest {
     file_hash: String,
     file_size: u64,
+    /// 旧版本 manifest 没有记录分块大小
+    #[serde(default)]
+    chunk_size: usize,
     chunks: Vec<String>,
 }
+
+// [知识点 #159] 用 trait 对象替换具体服务
+// ----------------------------------------
+// 题目：handler 如何在测试里拿到一个"会出错的磁盘"？
+//
+// 讲解：
+// handler 只依赖 StorageBackend / MetadataStore 这两个 trait，
+// AppData 中保存 Arc<dyn Trait>：
+// - 生产环境注入 StorageService / Repository
+// - 测试注入包装了真实实现的假对象，按需返回 IO 错误、磁盘已满或人为延迟
+//
+// Rust 原生的 async fn in trait 不能用于 dyn，这里用 async-trait
+// 把返回值改写成 Pin<Box<dyn Future + Send>>
+//
+// 思考：泛型 AppData<S: StorageBackend> 与 trait 对象各有什么取舍？
+// ----------------------------------------
+/// handler 使用的内容寻址存储操作
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    fn negotiate_chunk_size(&self, file_size: u64, requested: Option<usize>) -> Option<usize>;
+
+    fn compute_content_hash(&self, content: &[u8]) -> String;
+
+    async fn compute_hash(&self, path: &Path) -> Result<String>;
+
+    /// 把磁盘上已有的文件复制进对象存储
+    async fn store_file(&self, source: &Path) -> Result<(String, u64)>;
+
+    async fn store_content(&self, content: &[u8]) -> Result<(String, u64)>;
+
+    /// 新建流式上传用的暂存文件
+    async fn begin_upload(&self) -> Result<StagedUpload>;
+
+    /// 把写完的暂存文件存入对象存储；默认按普通文件复制进去
+    async fn store_staged(&self, upload: &mut StagedUpload) -> Result<(String, u64)> {
+        upload.finish().await?;
+        self.store_file(upload.path()).await
+    }
+
+    /// 按块计算文件的签名；默认直接在阻塞线程池中计算
+    async fn signature(&self, path: &Path) -> Result<FileSignature> {
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || -> Result<FileSignature> {
+            let file = std::fs::File::open(&path)?;
+            let block_size = delta::block_size_for(file.metadata()?.len());
+            Ok(delta::signature(std::io::BufReader::new(file), block_size)?)
+        })
+        .await
+        .map_err(|e| Error::Io(std::io::Error::other(e)))?
+    }
+
+    /// 用 base 的内容和增量拼出新内容，写入暂存文件；
+    /// 调用前应先用 `Delta::output_len` 确认增量没有引用 base 之外的块
+    async fn apply_delta(&self, base: &Path, delta: &Delta) -> Result<StagedUpload> {
+        let mut upload = self.begin_upload().await?;
+        let mut file = tokio::fs::File::open(base).await?;
+        let block_size = delta.block_size as u64;
+        let mut buffer = vec![0u8; delta.block_size];
+        for op in &delta.ops {
+            match op {
+                DeltaOp::Data(data) => upload.write(data).await?,
+                DeltaOp::Copy { start, count } => {
+                    file.seek(SeekFrom::Start(start * block_size)).await?;
+                    let mut blocks = (&mut file).take(count * block_size);
+                    loop {
+                        let read = blocks.read(&mut buffer).await?;
+                        if read == 0 {
+                            break;
+                        }
+                        upload.write(&buffer[..read]).await?;
+                    }
+                }
+            }
+        }
+        Ok(upload)
+    }
+
+    /// 对象的大小和内容流；默认直接读取对象文件
+    async fn stream_object(&self, hash: &str) -> Result<(u64, ObjectStream)> {
+        let file = tokio::fs::File::open(self.object_path(hash)).await?;
+        let size = file.metadata().await?.len();
+        Ok((size, ReaderStream::new(file).boxed()))
+    }
+
+    /// 对象中从 start 起 len 字节的内容流；默认从对象文件的对应位置读取
+    async fn stream_object_range(&self, hash: &str, start: u64, len: u64) -> Result<ObjectStream> {
+        let mut file = tokio::fs::File::open(self.object_path(hash)).await?;
+        file.seek(SeekFrom::Start(start)).await?;
+        Ok(ReaderStream::new(file.take(len)).boxed())
+    }
+
+    /// 把对象的内容复制到暂存文件，压缩存储和分块存储的对象都能还原；
+    /// 复制出的内容与哈希不一致时返回 Corrupted
+    async fn stage_object(&self, hash: &str) -> Result<StagedUpload> {
+        let (_, mut source) = self.stream_object(hash).await?;
+        let mut upload = self.begin_upload().await?;
+        while let Some(bytes) = source.next().await {
+            upload.write(&bytes?).await?;
+        }
+        if upload.finish().await? != hash {
+            self.flag_corrupted(hash);
+            return Err(Error::Corrupted(hash.to_string()));
+        }
+        Ok(upload)
+    }
+
+    /// 读取对象前的完整性检查；默认不校验
+    async fn check_object(&self, _hash: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// 记下内容与哈希不一致的对象；默认只记日志
+    fn flag_corrupted(&self, hash: &str) {
+        tracing::error!("Object {} does not match its hash", hash);
+    }
+
+    /// 等待修复的损坏对象
+    fn corrupted_objects(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    async fn file_exists(&self, hash: &str) -> bool;
+
+    fn object_path(&self, hash: &str) -> PathBuf;
+
+    /// 对象在磁盘上的全部文件；默认只有对象文件本身
+    async fn object_files(&self, hash: &str) -> Vec<PathBuf> {
+        let path = self.object_path(hash);
+        match path.exists() {
+            true => vec![path],
+            false => Vec::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for StorageService {
+    fn negotiate_chunk_size(&self, file_size: u64, requested: Option<usize>) -> Option<usize> {
+        StorageService::negotiate_chunk_size(self, file_size, requested)
+    }
+
+    fn compute_content_hash(&self, content: &[u8]) -> String {
+        StorageService::compute_content_hash(self, content)
+    }
+
+    async fn compute_hash(&self, path: &Path) -> Result<String> {
+        StorageService::compute_hash(self, path).await
+    }
+
+    async fn store_file(&self, source: &Path) -> Result<(String, u64)> {
+        StorageService::store_file(self, source).await
+    }
+
+    async fn store_content(&self, content: &[u8]) -> Result<(String, u64)> {
+        StorageService::store_content(self, content).await
+    }
+
+    async fn begin_upload(&self) -> Result<StagedUpload> {
+        StorageService::begin_upload(self).await
+    }
+
+    async fn store_staged(&self, upload: &mut StagedUpload) -> Result<(String, u64)> {
+        StorageService::store_staged(self, upload).await
+    }
+
+    async fn signature(&self, path: &Path) -> Result<FileSignature> {
+        StorageService::signature(self, path).await
+    }
+
+    async fn stream_object(&self, hash: &str) -> Result<(u64, ObjectStream)> {
+        StorageService::stream_object(self, hash).await
+    }
+
+    async fn stream_object_range(&self, hash: &str, start: u64, len: u64) -> Result<ObjectStream> {
+        StorageService::stream_object_range(self, hash, start, len).await
+    }
+
+    async fn check_object(&self, hash: &str) -> Result<()> {
+        StorageService::check_object(self, hash).await
+    }
+
+    fn flag_corrupted(&self, hash: &str) {
+        StorageService::flag_corrupted(self, hash)
+    }
+
+    fn corrupted_objects(&self) -> Vec<String> {
+        StorageService::corrupted_objects(self)
+    }
+
+    async fn file_exists(&self, hash: &str) -> bool {
+        StorageService::file_exists(self, hash).await
+    }
+
+    fn object_path(&self, hash: &str) -> PathBuf {
+        StorageService::object_path(self, hash)
+    }
+
+    async fn object_files(&self, hash: &str) -> Vec<PathBuf> {
+        StorageService::object_files(self, hash).await
+    }
+}
@@ -0,0 +1,181 @@
+// [知识点 #174] 结构化并发与任务监督
+// ----------------------------------------
+// 题目：tokio::spawn 之后不管不问的后台任务，有什么隐患？
+//
+// 讲解：
+// 裸 spawn 出去的任务没有"主人"：
+// 1. 任务 panic 或返回错误后就此消失，没人发现，也没人重启
+// 2. 进程退出时任务被直接丢弃，内存中的访问记录来不及落盘
+// 3. 无法回答"后台服务现在还活着吗"
+//
+// 结构化并发要求每个任务都有一个负责等待它结束的父级。这里的 Supervisor：
+// - 每个服务由一个监督循环运行，崩溃（返回错误或 panic）后按指数退避重启
+// - 通过 CancellationToken 通知服务退出，服务自己决定收尾工作
+// - 关闭时按注册的逆序逐个停止：后启动的往往依赖先启动的
+// - 零散的短任务（通知投递）交给 TaskTracker，关闭时等它们跑完
+//
+// 思考：为什么服务体要单独 spawn，而不是直接在监督循环里 await？
+// ----------------------------------------
+
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::{AbortOnDropHandle, TaskTracker};
+
+use crate::error::Result;
+use rustcloud_types::ServiceHealth;
+
+/// 第一次重启前的等待时间，之后每次翻倍
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// 稳定运行超过该时长后再崩溃，退避从头算起
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+/// 关闭时每个服务的收尾时限，超时后强制中止
+pub const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+struct Service {
+    name: &'static str,
+    token: CancellationToken,
+    handle: JoinHandle<()>,
+    health: Arc<Mutex<ServiceHealth>>,
+}
+
+/// 后台服务的所有者，克隆后共享
+#[derive(Clone, Default)]
+pub struct Supervisor {
+    services: Arc<Mutex<Vec<Service>>>,
+    tasks: TaskTracker,
+    backoff: Option<Duration>,
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 固定的重启等待时间，测试用
+    pub fn with_backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = Some(backoff);
+        self
+    }
+
+    /// 短任务的登记处，关闭时等待其中的任务结束
+    pub fn tasks(&self) -> TaskTracker {
+        self.tasks.clone()
+    }
+
+    /// 启动一个长期运行的服务；`service` 每次（重新）启动时调用一次，
+    /// 收到取消信号后应尽快返回 Ok
+    pub fn spawn<F, Fut>(&self, name: &'static str, service: F)
+    where
+        F: Fn(CancellationToken) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let token = CancellationToken::new();
+        let health = Arc::new(Mutex::new(ServiceHealth {
+            name: name.to_string(),
+            state: "running".to_string(),
+            restarts: 0,
+            last_error: None,
+        }));
+        let handle = tokio::spawn(supervise(
+            name,
+            service,
+            token.clone(),
+            health.clone(),
+            self.backoff,
+        ));
+        self.services.lock().unwrap().push(Service {
+            name,
+            token,
+            handle,
+            health,
+        });
+    }
+
+    /// 各服务当前的状态，按启动顺序
+    pub fn health(&self) -> Vec<ServiceHealth> {
+        self.services
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|service| service.health.lock().unwrap().clone())
+            .collect()
+    }
+
+    /// 先等待进行中的短任务，再按启动的逆序逐个停止服务
+    pub async fn shutdown(&self) {
+        self.tasks.close();
+        if tokio::time::timeout(SHUTDOWN_GRACE, self.tasks.wait())
+            .await
+            .is_err()
+        {
+            tracing::warn!(
+                "Background tasks did not finish within {:?}",
+                SHUTDOWN_GRACE
+            );
+        }
+
+        let services = std::mem::take(&mut *self.services.lock().unwrap());
+        for mut service in services.into_iter().rev() {
+            service.token.cancel();
+            match tokio::time::timeout(SHUTDOWN_GRACE, &mut service.handle).await {
+                Ok(_) => tracing::info!("Stopped {}", service.name),
+                Err(_) => {
+                    tracing::warn!("{} did not stop in time, aborting it", service.name);
+                    service.handle.abort();
+                }
+            }
+        }
+    }
+}
+
+async fn supervise<F, Fut>(
+    name: &'static str,
+    service: F,
+    token: CancellationToken,
+    health: Arc<Mutex<ServiceHealth>>,
+    fixed_backoff: Option<Duration>,
+) where
+    F: Fn(CancellationToken) -> Fut,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    let mut backoff = fixed_backoff.unwrap_or(INITIAL_BACKOFF);
+    loop {
+        health.lock().unwrap().state = "running".to_string();
+        let started = Instant::now();
+        // 单独 spawn 才能把 panic 变成 JoinError；监督循环被中止时服务体随之中止
+        let run = AbortOnDropHandle::new(tokio::spawn(service(token.child_token())));
+        let error = match run.await {
+            Ok(Ok(())) if token.is_cancelled() => break,
+            Ok(Ok(())) => "exited unexpectedly".to_string(),
+            Ok(Err(e)) => e.to_string(),
+            Err(e) => e.to_string(),
+        };
+        if token.is_cancelled() {
+            break;
+        }
+
+        if fixed_backoff.is_none() && started.elapsed() > STABLE_AFTER {
+            backoff = INITIAL_BACKOFF;
+        }
+        tracing::error!("{} failed, restarting in {:?}: {}", name, backoff, error);
+        {
+            let mut health = health.lock().unwrap();
+            health.state = "restarting".to_string();
+            health.restarts += 1;
+            health.last_error = Some(error);
+        }
+        tokio::select! {
+            _ = token.cancelled() => break,
+            _ = tokio::time::sleep(backoff) => {}
+        }
+        if fixed_backoff.is_none() {
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+    health.lock().unwrap().state = "stopped".to_string();
+}
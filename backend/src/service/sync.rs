@@ -15,9 +15,17 @@
 // 思考：如何处理网络中断和部分失败？
 // ----------------------------------------
 
+use std::collections::HashSet;
 use std::sync::Arc;
+use std::time::Duration;
 
-use crate::db::{DeviceRecord, FileRecord, NewDeviceRecord, NewSyncRecord, Repository, SyncStatus};
+use rustcloud_types::ConflictInfo;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::db::{
+    DeviceRecord, FileRecord, NewDeviceRecord, NewSyncRecord, Repository, SyncRecord, SyncStatus,
+};
 use crate::error::Result;
 
 // TODO: Phase 2 集成 - 将在实现客户端同步协议时使用
@@ -37,10 +45,16 @@ use crate::error::Result;
 //
 // 思考：服务边界如何划分？什么时候拆分服务？
 // ----------------------------------------
+#[derive(Clone)]
 pub struct SyncEngine {
     repository: Arc<Repository>,
 }
 
+/// 停在 Syncing 超过该时长的记录视为中断
+pub const SYNC_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+/// 检查中断记录的间隔
+pub const SYNC_EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+
 // [知识点 #126] 同步状态机
 // ----------------------------------------
 // 题目：为什么需要 SyncStatus 枚举？
@@ -48,13 +62,18 @@ pub struct SyncEngine {
 // 讲解：
 // 同步是一个异步过程，需要状态跟踪：
 // Pending -> Syncing -> Completed
-//                  \-> Failed
+//                  \-> Failed -> Pending（重试）
+//                  \-> Conflict
 //
 // 状态转换：
 // - Pending：检测到变更，等待同步
 // - Syncing：正在传输数据
 // - Completed：同步完成
 // - Failed：同步失败，需要重试
+// - Conflict：设备基于过期版本上传，终态，只用于列出冲突
+//
+// 其他转换（例如 Completed -> Syncing）由仓库拒绝。进程在传输中途崩溃时记录会
+// 停在 Syncing，后台定期把超过 SYNC_TIMEOUT 没有进展的记录标记为 Failed。
 //
 // 思考：如何实现自动重试和指数退避？
 // ----------------------------------------
@@ -66,6 +85,13 @@ pub struct SyncPlan {
     pub action: SyncAction,
 }
 
+/// 目录的同步动作：Upload 在服务端创建，Download 在本地创建
+#[derive(Debug, Clone)]
+pub struct DirectoryPlan {
+    pub path: String,
+    pub action: SyncAction,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum SyncAction {
     Upload,
@@ -88,6 +114,29 @@ impl SyncEngine {
         SyncEngine { repository }
     }
 
+    /// 定期把中断的同步记录标记为失败；启动时立即检查一次，上次进程崩溃留下的记录随即过期
+    pub async fn run_expiry(
+        self,
+        interval: Duration,
+        timeout: Duration,
+        shutdown: CancellationToken,
+    ) -> Result<()> {
+        let timeout = chrono::Duration::from_std(timeout).unwrap_or(chrono::Duration::MAX);
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = shutdown.cancelled() => return Ok(()),
+            }
+            let stuck_since = self.repository.clock().now() - timeout;
+            match self.repository.expire_stuck_syncs(stuck_since).await {
+                Ok(0) => {}
+                Ok(expired) => tracing::warn!("Marked {} stuck sync(s) as failed", expired),
+                Err(e) => tracing::warn!("Failed to expire stuck syncs: {}", e),
+            }
+        }
+    }
+
     pub async fn register_device(&self, name: &str) -> Result<DeviceRecord> {
         self.repository
             .create_device(NewDeviceRecord {
@@ -120,7 +169,11 @@ impl SyncEngine {
     //
     // 思考：如何实现真正的双向同步？
     // ----------------------------------------
-    pub async fn create_sync_plan(&self, local_files: &[FileRecord]) -> Result<Vec<SyncPlan>> {
+    pub async fn create_sync_plan(
+        &self,
+        local_files: &[FileRecord],
+        device: Option<Uuid>,
+    ) -> Result<Vec<SyncPlan>> {
         let remote_files = self.repository.list_files().await?;
         let mut plans = Vec::new();
 
@@ -136,10 +189,10 @@ impl SyncEngine {
                 });
             } else if let Ok(remote) = self.repository.get_file_by_path(&local.path).await {
                 if remote.hash != local.hash {
-                    let action = if remote.version > local.version {
-                        SyncAction::Download
-                    } else {
-                        SyncAction::Upload
+                    let action = match device {
+                        Some(device) => self.resolve_changed(device, local, &remote).await?,
+                        None if remote.version > local.version => SyncAction::Download,
+                        None => SyncAction::Upload,
                     };
                     plans.push(SyncPlan {
                         file_id: local.id,
@@ -159,27 +212,219 @@ impl SyncEngine {
         Ok(plans)
     }
 
+    /// 本地有、远程没有的空目录上传，远程有记录、本地没有的目录下载。
+    /// 有文件的目录由文件路径隐含，只有空目录需要单独比较
+    pub async fn create_directory_plan(
+        &self,
+        local_files: &[FileRecord],
+        local_dirs: &[String],
+    ) -> Result<Vec<DirectoryPlan>> {
+        // 文件和目录记录取自同一快照，中途移动的目录不会同时出现在新旧两处或都不出现
+        let snapshot = self.repository.snapshot().await?;
+        let remote_dirs = &snapshot.directories;
+        let remote = existing_dirs(
+            snapshot
+                .files
+                .iter()
+                .map(|f| f.path.as_str())
+                .chain(remote_dirs.iter().map(|d| d.path.as_str())),
+        );
+        let local = existing_dirs(
+            local_files
+                .iter()
+                .map(|f| f.path.as_str())
+                .chain(local_dirs.iter().map(String::as_str)),
+        );
+
+        let mut plans: Vec<DirectoryPlan> = local_dirs
+            .iter()
+            .filter(|dir| !remote.contains(dir.as_str()))
+            .map(|dir| DirectoryPlan {
+                path: dir.clone(),
+                action: SyncAction::Upload,
+            })
+            .collect();
+        plans.extend(
+            remote_dirs
+                .iter()
+                .filter(|dir| !local.contains(dir.path.as_str()))
+                .map(|dir| DirectoryPlan {
+                    path: dir.path.clone(),
+                    action: SyncAction::Download,
+                }),
+        );
+        Ok(plans)
+    }
+
+    // [知识点 #178] 基准版本与冲突检测
+    // ----------------------------------------
+    // 题目：本地和远程内容不同，应该上传还是下载？
+    //
+    // 讲解：
+    // 只比较两边的当前状态无法回答：可能是本地改了，也可能是远程改了，还可能两边都改了。
+    // 需要知道"上次同步时设备持有的版本"（基准版本）：
+    // - 远程仍是基准版本：只有本地改过，上传
+    // - 远程比基准新，本地内容仍等于基准：只有远程改过，下载
+    // - 远程比基准新，本地也改过：冲突
+    //
+    // 服务端在设备上传成功或下载文件后记下一条 Completed 同步记录，
+    // 其中的 base_version 就是这台设备的基准。
+    // 冲突时最后写入者胜出会悄悄丢掉一方的修改，所以另存为副本（或拒绝），
+    // 由用户决定保留哪个。
+    //
+    // 思考：从未同步过的设备没有基准版本，它的上传应该怎样处理？
+    // ----------------------------------------
+    async fn resolve_changed(
+        &self,
+        device: Uuid,
+        local: &FileRecord,
+        remote: &FileRecord,
+    ) -> Result<SyncAction> {
+        let Some(base) = self.base_version(device, remote.id).await? else {
+            return Ok(if remote.version > local.version {
+                SyncAction::Download
+            } else {
+                SyncAction::Upload
+            });
+        };
+        if base >= remote.version {
+            return Ok(SyncAction::Upload);
+        }
+        // 本地内容仍是基准版本时只有远程改过；否则上传，由上传时的冲突检测处理
+        let versions = self.repository.list_file_versions(remote.id).await?;
+        let unchanged = versions
+            .iter()
+            .any(|v| v.version == base && v.hash.is_some() && v.hash == local.hash);
+        Ok(if unchanged {
+            SyncAction::Download
+        } else {
+            SyncAction::Upload
+        })
+    }
+
+    /// 记下设备此刻持有 file_id 的 version 版本
+    pub async fn record_base(&self, device_id: Uuid, file_id: Uuid, version: i32) -> Result<()> {
+        let sync = self
+            .repository
+            .create_sync(NewSyncRecord {
+                device_id,
+                file_id,
+                sync_status: SyncStatus::Syncing,
+                base_version: Some(version),
+                conflict_path: None,
+            })
+            .await?;
+        self.repository
+            .update_sync_status(sync.id, SyncStatus::Completed)
+            .await?;
+        Ok(())
+    }
+
+    /// 设备最近一次同步后持有的版本，没有记录时为 None
+    pub async fn base_version(&self, device_id: Uuid, file_id: Uuid) -> Result<Option<i32>> {
+        let syncs = self.repository.list_syncs_by_file(file_id).await?;
+        Ok(syncs
+            .iter()
+            .rev()
+            .filter(|s| s.device_id == device_id && s.sync_status == SyncStatus::Completed)
+            .find_map(|s| s.base_version))
+    }
+
+    /// 设备上传的内容基于过期版本时返回它的基准版本；没有基准的设备不检查
+    pub async fn detect_conflict(
+        &self,
+        device_id: Uuid,
+        current: &FileRecord,
+        hash: &str,
+    ) -> Result<Option<i32>> {
+        if current.hash.as_deref() == Some(hash) {
+            return Ok(None);
+        }
+        Ok(self
+            .base_version(device_id, current.id)
+            .await?
+            .filter(|base| *base < current.version))
+    }
+
+    /// copy_path 为 None 表示上传被拒绝
+    pub async fn record_conflict(
+        &self,
+        device_id: Uuid,
+        file_id: Uuid,
+        base_version: i32,
+        copy_path: Option<String>,
+    ) -> Result<SyncRecord> {
+        let sync = self
+            .repository
+            .create_sync(NewSyncRecord {
+                device_id,
+                file_id,
+                sync_status: SyncStatus::Syncing,
+                base_version: Some(base_version),
+                conflict_path: copy_path,
+            })
+            .await?;
+        self.repository
+            .update_sync_status(sync.id, SyncStatus::Conflict)
+            .await
+    }
+
+    /// 尚未解决的冲突：副本还没被删除，或者被拒绝之后设备还没有重新同步过该文件
+    pub async fn list_conflicts(&self) -> Result<Vec<ConflictInfo>> {
+        let mut open = Vec::new();
+        for conflict in self.repository.list_conflicts().await? {
+            let Ok(file) = self.repository.get_file_by_id(conflict.file_id).await else {
+                continue;
+            };
+            let resolved = match &conflict.conflict_path {
+                Some(copy) => self.repository.get_file_by_path(copy).await.is_err(),
+                None => self
+                    .repository
+                    .list_syncs_by_file(file.id)
+                    .await?
+                    .iter()
+                    .skip_while(|s| s.id != conflict.id)
+                    .any(|s| {
+                        s.device_id == conflict.device_id && s.sync_status == SyncStatus::Completed
+                    }),
+            };
+            if resolved {
+                continue;
+            }
+            let device_name = match self.repository.get_device(conflict.device_id).await {
+                Ok(device) => device.name,
+                Err(_) => conflict.device_id.to_string(),
+            };
+            open.push(ConflictInfo {
+                path: file.path,
+                copy_path: conflict.conflict_path,
+                device_id: conflict.device_id,
+                device_name,
+                base_version: conflict.base_version,
+                detected_at: conflict.last_sync_at,
+            });
+        }
+        Ok(open)
+    }
+
+    /// 记录一次同步：apply 执行实际的操作（删除时由调用方经回收站删除），
+    /// 这里按它的结果把同步记录标为完成或失败
     pub async fn sync_file(
         &self,
         file_id: uuid::Uuid,
         device_id: uuid::Uuid,
-        action: SyncAction,
+        apply: impl std::future::Future<Output = Result<()>>,
     ) -> Result<()> {
         let new_sync = NewSyncRecord {
             device_id,
             file_id,
             sync_status: SyncStatus::Syncing,
+            base_version: None,
+            conflict_path: None,
         };
         let sync_record = self.repository.create_sync(new_sync).await;
 
-        let result = match action {
-            SyncAction::Upload | SyncAction::Download | SyncAction::Skip => {
-                Ok::<(), crate::error::Error>(())
-            }
-            SyncAction::Delete => self.repository.delete_file(file_id).await.map(|_| ()),
-        };
-
-        match result {
+        match apply.await {
             Ok(_) => {
                 let _ = self
                     .repository
@@ -201,3 +446,19 @@ impl SyncEngine {
         self.repository.list_syncs_by_file(file_id).await
     }
 }
+
+/// 路径本身及其所有上级目录；路径为文件时其自身不是目录，多算一项不影响比较
+fn existing_dirs<'a>(paths: impl Iterator<Item = &'a str>) -> HashSet<&'a str> {
+    let mut dirs = HashSet::new();
+    for path in paths {
+        dirs.insert(path);
+        let mut rest = path;
+        while let Some((parent, _)) = rest.rsplit_once('/') {
+            if !dirs.insert(parent) {
+                break;
+            }
+            rest = parent;
+        }
+    }
+    dirs
+}
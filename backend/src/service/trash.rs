@@ -0,0 +1,200 @@
+// [知识点 #191] 回收站：先移走，到期再清除
+// ----------------------------------------
+// 题目：误删了整个目录，怎样在几天之内还能找回来？
+//
+// 讲解：
+// 删除拆成两步：
+// 1. 删除时只把内容 rename 到 objects/trash/{id}，同一文件系统内 rename 是原子的，
+//    再大的目录也只改一个目录项；记录从文件表中移除，原样保存在回收站条目里
+// 2. 保留期满后由后台任务清除：删除移走的内容；开启粉碎时在这一步才覆写
+//
+// 恢复就是反过来：内容 rename 回原路径，记录带着原来的 id 和版本号放回文件表，
+// 历史版本从未删除，ls --versions 看到的仍是完整的历史。
+//
+// 回收站中的记录引用的对象仍算"被引用"：别的删除触发粉碎时不能覆写它们，
+// 否则恢复出来的文件内容已经没了。
+//
+// 思考：原路径在删除之后又被新文件占用，恢复时应该覆盖、改名还是拒绝？
+// ----------------------------------------
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::db::{FileRecord, NewTrashEntry, Repository, TrashEntry};
+use crate::error::{Error, Result};
+use crate::service::shred::{ShredSummary, Shredder};
+
+/// 清除到期条目的间隔
+pub const TRASH_SWEEP_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// 删除的文件先移入回收站，克隆后共享
+#[derive(Clone)]
+pub struct TrashService {
+    repository: Repository,
+    /// 被删除的内容；位于 objects/ 下，文件监控不会把它当作用户文件
+    dir: PathBuf,
+    /// 0 表示不使用回收站，删除立即生效
+    retention_days: u32,
+    /// 开启粉碎时，清除条目时覆写内容和不再被引用的对象
+    shredder: Option<Shredder>,
+}
+
+impl TrashService {
+    pub fn new(repository: Repository, storage_path: &Path, retention_days: u32) -> Self {
+        TrashService {
+            repository,
+            dir: storage_path.join("objects").join("trash"),
+            retention_days,
+            shredder: None,
+        }
+    }
+
+    pub fn with_shredder(mut self, shredder: Option<Shredder>) -> Self {
+        self.shredder = shredder;
+        self
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.retention_days > 0
+    }
+
+    pub fn retention_days(&self) -> u32 {
+        self.retention_days
+    }
+
+    fn content_path(&self, id: Uuid) -> PathBuf {
+        self.dir.join(id.to_string())
+    }
+
+    /// 记下条目并把 source（存在时）移入回收站；移动失败时撤销条目，原内容不变
+    pub async fn put(&self, new_entry: NewTrashEntry, source: &Path) -> Result<TrashEntry> {
+        let entry = self.repository.create_trash_entry(new_entry).await?;
+        if source.exists() {
+            let moved = match tokio::fs::create_dir_all(&self.dir).await {
+                Ok(()) => tokio::fs::rename(source, self.content_path(entry.id)).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = moved {
+                let _ = self.repository.delete_trash_entry(entry.id).await;
+                return Err(e.into());
+            }
+        }
+        Ok(entry)
+    }
+
+    /// 把内容移回 target，放回记录和目录记录，然后删除条目；返回放回的文件记录。
+    ///
+    /// 调用方须先确认原路径未被占用（可以是空目录）；单条记录放回失败时跳过并记录警告
+    pub async fn restore(&self, entry: &TrashEntry, target: &Path) -> Result<Vec<FileRecord>> {
+        let content = self.content_path(entry.id);
+        if content.exists() {
+            if let Some(parent) = target.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            // 删除后在原路径留下的空目录让位给恢复的内容
+            if target.is_dir() {
+                tokio::fs::remove_dir(target).await?;
+            }
+            tokio::fs::rename(&content, target).await?;
+        }
+
+        let mut restored = Vec::with_capacity(entry.files.len());
+        for record in &entry.files {
+            match self.repository.restore_file(record.clone()).await {
+                Ok(record) => restored.push(record),
+                Err(e) => tracing::warn!("Failed to restore {}: {}", record.path, e),
+            }
+        }
+        for directory in &entry.directories {
+            match self.repository.create_directory(&directory.path).await {
+                Ok(_) | Err(Error::AlreadyExists(_)) => {}
+                Err(e) => tracing::warn!("Failed to restore folder {}: {}", directory.path, e),
+            }
+        }
+        self.repository.delete_trash_entry(entry.id).await?;
+        Ok(restored)
+    }
+
+    /// 删除条目和移走的内容，无法再恢复；开启粉碎时返回覆写的统计
+    pub async fn purge(&self, entry: &TrashEntry) -> Result<Option<ShredSummary>> {
+        let content = self.content_path(entry.id);
+        let Some(shredder) = &self.shredder else {
+            self.repository.delete_trash_entry(entry.id).await?;
+            remove_path(&content).await?;
+            return Ok(None);
+        };
+        // 条目删除之后才能判断对象是否还被引用，先记下它们用到的对象
+        let objects = shredder.objects_of(&entry.files).await;
+        self.repository.delete_trash_entry(entry.id).await?;
+        let file = content.exists().then_some(content.as_path());
+        Ok(Some(shredder.shred(file, &objects).await?))
+    }
+
+    /// 清除保留期已满的条目，以及没有对应条目的残留内容；返回清除的条目数
+    pub async fn sweep(&self, now: DateTime<Utc>) -> Result<usize> {
+        let entries = self.repository.list_trash().await?;
+        let mut purged = 0;
+        for entry in &entries {
+            if entry.expires_at(self.retention_days) > now {
+                continue;
+            }
+            match self.purge(entry).await {
+                Ok(shredded) => {
+                    if let (Some(shredder), Some(shredded)) = (&self.shredder, shredded) {
+                        shredder.record(now, None, None, "TRASH", &entry.path, shredded);
+                    }
+                    purged += 1;
+                }
+                Err(e) => tracing::warn!("Failed to purge {} from trash: {}", entry.path, e),
+            }
+        }
+
+        // 条目写入后才移入内容，没有条目的内容是移入过程中崩溃留下的
+        let Ok(mut contents) = tokio::fs::read_dir(&self.dir).await else {
+            return Ok(purged);
+        };
+        while let Ok(Some(content)) = contents.next_entry().await {
+            let id = content
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse().ok());
+            if !id.is_some_and(|id: Uuid| entries.iter().any(|entry| entry.id == id)) {
+                let _ = remove_path(&content.path()).await;
+            }
+        }
+        Ok(purged)
+    }
+
+    /// 后台定时清除到期条目，直到收到关闭信号
+    pub async fn run_sweeper(self, interval: Duration, shutdown: CancellationToken) -> Result<()> {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = shutdown.cancelled() => return Ok(()),
+            }
+            match self.sweep(self.repository.clock().now()).await {
+                Ok(0) => {}
+                Ok(purged) => tracing::info!("Purged {} expired trash entries", purged),
+                Err(e) => tracing::warn!("Trash sweep failed: {}", e),
+            }
+        }
+    }
+}
+
+// 不存在时什么也不做
+async fn remove_path(path: &Path) -> Result<()> {
+    let result = if path.is_dir() {
+        tokio::fs::remove_dir_all(path).await
+    } else {
+        tokio::fs::remove_file(path).await
+    };
+    match result {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,146 @@
+// [知识点 #169] 可续传的分块上传
+// ----------------------------------------
+// 题目：大文件上传到一半断网，怎样才能不从头再来？
+//
+// 讲解：
+// 一次 PUT 传完整个文件时，连接断开就意味着前面的字节全部作废。
+// 分块上传把一次传输拆成一个会话和若干个独立的请求：
+// 1. POST /api/uploads 创建会话，服务端决定分块大小
+// 2. PUT /api/uploads/{id}/chunks/{n} 逐块上传，每块单独成功或失败
+// 3. POST /api/uploads/{id}/complete 按序拼接，走普通上传的落盘流程
+//
+// 会话和已收到的分块都保存在磁盘上，客户端中断后用 GET /api/uploads/{id}
+// 查询已收到哪些分块，只补传缺少的部分；服务端重启也不影响续传。
+//
+// 思考：分块可以并行上传吗？需要注意什么？
+// ----------------------------------------
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::error::{Error, Result};
+use crate::service::clock::Clock;
+
+/// 超过该时长仍未完成的会话在创建新会话时清理
+const SESSION_TTL_HOURS: i64 = 24;
+
+const SESSION_FILE: &str = "session.json";
+
+/// 一次分块上传的目标路径和分块参数
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadSession {
+    pub id: Uuid,
+    pub path: String,
+    pub size: u64,
+    pub chunk_size: u64,
+    pub created_at: DateTime<Utc>,
+}
+
+impl UploadSession {
+    pub fn chunk_count(&self) -> u64 {
+        self.size.div_ceil(self.chunk_size).max(1)
+    }
+
+    /// 第 n 块应有的字节数，最后一块可能不满；n 越界时为 None
+    pub fn chunk_len(&self, n: u64) -> Option<u64> {
+        (n < self.chunk_count()).then(|| {
+            let start = n * self.chunk_size;
+            (self.size - start).min(self.chunk_size)
+        })
+    }
+}
+
+/// 会话保存在 `<dir>/<id>/`：session.json 加上以序号命名的分块文件
+#[derive(Clone)]
+pub struct UploadSessions {
+    dir: PathBuf,
+    clock: Arc<dyn Clock>,
+}
+
+impl UploadSessions {
+    pub fn new(dir: PathBuf, clock: Arc<dyn Clock>) -> Self {
+        UploadSessions { dir, clock }
+    }
+
+    pub async fn create(&self, path: String, size: u64, chunk_size: u64) -> Result<UploadSession> {
+        self.prune().await;
+        let session = UploadSession {
+            id: Uuid::new_v4(),
+            path,
+            size,
+            chunk_size: chunk_size.max(1),
+            created_at: self.clock.now(),
+        };
+        let dir = self.session_dir(session.id);
+        tokio::fs::create_dir_all(&dir).await?;
+        tokio::fs::write(dir.join(SESSION_FILE), serde_json::to_vec(&session)?).await?;
+        Ok(session)
+    }
+
+    pub async fn get(&self, id: Uuid) -> Result<UploadSession> {
+        match tokio::fs::read(self.session_dir(id).join(SESSION_FILE)).await {
+            Ok(content) => Ok(serde_json::from_slice(&content)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Err(Error::NotFound(PathBuf::from(format!("upload:{}", id))))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn chunk_path(&self, session: &UploadSession, n: u64) -> PathBuf {
+        self.session_dir(session.id).join(n.to_string())
+    }
+
+    /// 已完整收到的分块序号，升序
+    pub async fn received(&self, session: &UploadSession) -> Vec<u64> {
+        let mut received = Vec::new();
+        for n in 0..session.chunk_count() {
+            let len = tokio::fs::metadata(self.chunk_path(session, n))
+                .await
+                .map(|m| m.len())
+                .ok();
+            if len.is_some() && len == session.chunk_len(n) {
+                received.push(n);
+            }
+        }
+        received
+    }
+
+    pub async fn remove(&self, id: Uuid) -> Result<()> {
+        match tokio::fs::remove_dir_all(self.session_dir(id)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn session_dir(&self, id: Uuid) -> PathBuf {
+        self.dir.join(id.to_string())
+    }
+
+    // 清理失败只记日志，不影响新会话
+    async fn prune(&self) {
+        let Ok(mut entries) = tokio::fs::read_dir(&self.dir).await else {
+            return;
+        };
+        let cutoff = self.clock.now() - Duration::hours(SESSION_TTL_HOURS);
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let Some(id) = entry.file_name().to_str().and_then(|n| n.parse().ok()) else {
+                continue;
+            };
+            // 读不出会话文件的目录也视为过期
+            let expired = match self.get(id).await {
+                Ok(session) => session.created_at < cutoff,
+                Err(_) => true,
+            };
+            if expired {
+                if let Err(e) = self.remove(id).await {
+                    tracing::warn!("Failed to remove expired upload {}: {}", id, e);
+                }
+            }
+        }
+    }
+}
@@ -16,16 +16,16 @@
 // 思考：如何实现分支和合并？
 // ----------------------------------------
 
-use std::path::Path;
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
 
-use crate::db::{FileRecord, NewFileRecord, Repository};
-use crate::error::Result;
-use crate::service::storage::StorageService;
+use chrono::{DateTime, Utc};
+
+use crate::db::{FileDeparture, FileRecord, FileVersionRecord, Repository};
+use crate::error::{Error, Result};
+use crate::service::storage::{StagedUpload, StorageBackend};
 
-// TODO: Phase 2 集成 - 将在实现版本历史功能时使用
-// 预留 API 端点: GET /api/files/{path}/versions, POST /api/files/{path}/rollback
-#[allow(dead_code)]
 // [知识点 #083] 组合优于继承
 // ----------------------------------------
 // 题目：VersionService 如何访问 StorageService 和 Repository？
@@ -41,90 +41,119 @@ use crate::service::storage::StorageService;
 // 思考：如果服务之间有循环依赖怎么办？
 // ----------------------------------------
 pub struct VersionService {
-    storage: Arc<StorageService>,
-    repository: Arc<Repository>,
+    storage: Arc<dyn StorageBackend>,
+    repository: Repository,
 }
 
 impl VersionService {
-    pub fn new(storage: Arc<StorageService>, repository: Arc<Repository>) -> Self {
+    pub fn new(storage: Arc<dyn StorageBackend>, repository: Repository) -> Self {
         VersionService {
             storage,
             repository,
         }
     }
 
-    pub async fn create_version(&self, path: &Path) -> Result<FileRecord> {
-        let (hash, size) = self.storage.store_file(path).await?;
-
-        let new_file = NewFileRecord {
-            path: path.to_string_lossy().to_string(),
-            hash: Some(hash),
-            size,
-        };
-
-        self.repository.create_file(new_file).await
+    /// 文件的全部版本，按版本号升序，最后一条即当前版本
+    pub async fn history(&self, file: &FileRecord) -> Result<Vec<FileVersionRecord>> {
+        self.repository.list_file_versions(file.id).await
     }
 
-    pub async fn update_version(&self, path: &Path) -> Result<FileRecord> {
-        let existing = self
-            .repository
-            .get_file_by_path(&path.to_string_lossy())
-            .await
-            .ok();
-
-        let (hash, size) = self.storage.store_file(path).await?;
-
-        match existing {
-            Some(record) => {
-                // 检查内容是否变化
-                if record.hash.as_deref() == Some(hash.as_str()) {
-                    return Ok(record);
-                }
-                self.repository
-                    .update_file(record.id, Some(hash), size)
-                    .await
-            }
-            None => {
-                let new_file = NewFileRecord {
-                    path: path.to_string_lossy().to_string(),
-                    hash: Some(hash),
-                    size,
-                };
-                self.repository.create_file(new_file).await
+    // [知识点 #188] 从历史中还原过去的目录
+    // ----------------------------------------
+    // 题目：不做快照，怎样知道上周二下午目录里有哪些文件？
+    //
+    // 讲解：
+    // 一个文件在某一时刻的状态由两件事决定：内容和位置。
+    // - 内容：历史版本带有写入时间，取该时刻之前最后写入的那个版本；
+    //   一个版本都没有说明文件那时还没有创建
+    // - 位置：文件每次被移走或删除都记下一条 FileDeparture（离开前的路径、时间）。
+    //   该时刻之后的第一条离开记录上的路径就是它当时的位置；
+    //   之后再没有离开过，就还在当前路径；最后一条是删除且早于该时刻，那时已经不存在
+    //
+    // 删除文件时保留历史版本，已删除的文件才能出现在过去的列表中。
+    // 这样还原出的只是元数据，内容能否下载取决于对象是否还在（安全删除会覆写对象）。
+    //
+    // 思考：文件监控发现的原地修改只更新哈希、不产生版本，还原出的内容会有什么偏差？
+    // ----------------------------------------
+
+    /// 还原 at 时刻存在的全部文件，path、哈希、大小、版本都是当时的值
+    pub async fn files_as_of(&self, at: DateTime<Utc>) -> Result<Vec<FileRecord>> {
+        // 当前记录、离开记录和版本必须来自同一时刻，否则中途移动的文件会对不上
+        let snapshot = self.repository.snapshot().await?;
+        let current = &snapshot.files;
+        let mut departures: HashMap<uuid::Uuid, Vec<&FileDeparture>> = HashMap::new();
+        let mut departed = Vec::new();
+        for departure in &snapshot.departures {
+            let file_departures = departures.entry(departure.file_id).or_default();
+            if file_departures.is_empty() {
+                departed.push(departure.file_id);
             }
+            file_departures.push(departure);
         }
-    }
-
-    pub async fn get_version(&self, path: &str) -> Result<FileRecord> {
-        self.repository.get_file_by_path(path).await
-    }
-
-    pub async fn get_content(&self, record: &FileRecord) -> Result<Vec<u8>> {
-        match &record.hash {
-            Some(hash) => self.storage.retrieve_chunked(hash).await,
-            None => Err(crate::error::Error::NotFound(std::path::PathBuf::from(
-                &record.path,
-            ))),
+        let current_ids: HashMap<uuid::Uuid, &FileRecord> =
+            current.iter().map(|record| (record.id, record)).collect();
+        let ids = current.iter().map(|record| record.id).chain(
+            departed
+                .into_iter()
+                .filter(|id| !current_ids.contains_key(id)),
+        );
+
+        let mut files = Vec::new();
+        for id in ids {
+            let record = current_ids.get(&id).copied();
+            let path = match departures
+                .get(&id)
+                .and_then(|moves| moves.iter().find(|d| d.departed_at > at))
+            {
+                Some(departure) => departure.path.clone(),
+                None => match record {
+                    Some(record) => record.path.clone(),
+                    // 在 at 之前已被删除
+                    None => continue,
+                },
+            };
+            let versions = snapshot.file_versions(id);
+            let Some(version) = versions.iter().rev().find(|v| v.created_at <= at) else {
+                continue;
+            };
+            files.push(FileRecord {
+                id,
+                path,
+                hash: version.hash.clone(),
+                size: version.size,
+                version: version.version,
+                created_at: versions[0].created_at,
+                updated_at: version.created_at,
+                // 媒体信息描述的是当前内容
+                media: record
+                    .filter(|r| r.version == version.version)
+                    .and_then(|r| r.media.clone()),
+                metadata: record.map(|r| r.metadata.clone()).unwrap_or_default(),
+                last_accessed_at: None,
+                fs_id: None,
+                owner: record.and_then(|r| r.owner),
+            });
         }
+        Ok(files)
     }
 
-    pub async fn delete_version(&self, path: &str) -> Result<()> {
-        let record = self.repository.get_file_by_path(path).await?;
-        self.repository.delete_file(record.id).await
-    }
-
-    pub async fn list_versions(&self) -> Result<Vec<FileRecord>> {
-        self.repository.list_files().await
-    }
-
-    pub async fn has_changes(&self, path: &Path) -> Result<bool> {
-        let current_hash = self.storage.compute_hash(path).await?;
-        let existing = self
-            .repository
-            .get_file_by_path(&path.to_string_lossy())
-            .await
-            .ok();
-
-        Ok(existing.is_none_or(|r| r.hash.as_deref() != Some(current_hash.as_str())))
+    /// 把某个历史版本的内容复制到暂存文件，按普通上传写回即完成回滚。
+    ///
+    /// 对象存储中的内容不会随新版本删除；版本不存在或内容已丢失时返回 NotFound，
+    /// 复制出的内容与版本记录的哈希不一致时返回 Corrupted
+    pub async fn stage(&self, file: &FileRecord, version: i32) -> Result<StagedUpload> {
+        let not_found = || Error::NotFound(PathBuf::from(format!("{}@{}", file.path, version)));
+        let hash = self
+            .history(file)
+            .await?
+            .into_iter()
+            .find(|v| v.version == version)
+            .and_then(|v| v.hash)
+            .ok_or_else(not_found)?;
+        match self.storage.stage_object(&hash).await {
+            Err(Error::NotFound(_)) => Err(not_found()),
+            Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => Err(not_found()),
+            other => other,
+        }
     }
 }
@@ -20,9 +20,18 @@
 // ----------------------------------------
 
 use notify::{Config, Event, RecommendedWatcher, RecursiveMode, Watcher};
-use std::path::Path;
-use std::sync::Arc;
+use rustcloud_types::ignore::{IgnoreRules, IGNORE_FILE};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tokio_util::task::TaskTracker;
+
+use crate::service::expected_writes::ExpectedWrites;
+use crate::service::feed::ChangeFeed;
+use rustcloud_types::ChangeKind;
 
 // [知识点 #065] 通道通信
 // ----------------------------------------
@@ -74,9 +83,25 @@ impl FileWatcher {
     where
         F: Fn(FileEvent) + Send + 'static,
     {
-        let (tx, mut rx) = mpsc::channel::<FileEvent>(100);
+        let (watcher, mut rx) = Self::with_channel(path, 100)?;
+
+        // 在后台任务中处理事件
+        tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                callback(event);
+            }
+        });
+
+        Ok(watcher)
+    }
+
+    /// 事件写入容量为 capacity 的通道；接收方处理不过来时，监控线程阻塞等待而不是丢弃事件
+    pub fn with_channel(
+        path: &Path,
+        capacity: usize,
+    ) -> Result<(Self, mpsc::Receiver<FileEvent>), notify::Error> {
+        let (tx, rx) = mpsc::channel::<FileEvent>(capacity);
 
-        // 创建回调任务的运行时
         let handle_event = move |event: Result<Event, notify::Error>| {
             if let Ok(event) = event {
                 if let Some(file_event) = Self::convert_event(event) {
@@ -88,22 +113,24 @@ impl FileWatcher {
         let mut watcher = RecommendedWatcher::new(handle_event, Config::default())?;
         watcher.watch(path, RecursiveMode::Recursive)?;
 
-        // 在后台任务中处理事件
-        tokio::spawn(async move {
-            while let Some(event) = rx.recv().await {
-                callback(event);
-            }
-        });
-
-        Ok(FileWatcher { watcher })
+        Ok((FileWatcher { watcher }, rx))
     }
 
     fn convert_event(event: Event) -> Option<FileEvent> {
+        use notify::event::{ModifyKind, RenameMode};
         use notify::EventKind;
 
         let path = event.paths.first()?.clone();
 
         match event.kind {
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+                Some(FileEvent::Renamed {
+                    from: path,
+                    to: event.paths[1].clone(),
+                })
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)) => Some(FileEvent::Deleted(path)),
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)) => Some(FileEvent::Created(path)),
             EventKind::Create(_) => Some(FileEvent::Created(path)),
             EventKind::Modify(_) => Some(FileEvent::Modified(path)),
             EventKind::Remove(_) => Some(FileEvent::Deleted(path)),
@@ -132,8 +159,12 @@ impl FileWatcher {
 // ----------------------------------------
 pub struct WatcherService {
     watcher: Option<FileWatcher>,
+    worker: Option<tokio::task::JoinHandle<()>>,
     storage: Arc<crate::service::storage::StorageService>,
     repository: Arc<crate::db::Repository>,
+    expected_writes: ExpectedWrites,
+    feed: ChangeFeed,
+    ignore: Vec<String>,
 }
 
 impl WatcherService {
@@ -143,66 +174,503 @@ impl WatcherService {
     ) -> Self {
         WatcherService {
             watcher: None,
+            worker: None,
             storage,
             repository,
+            expected_writes: ExpectedWrites::new(),
+            feed: ChangeFeed::new(),
+            ignore: Vec::new(),
         }
     }
 
-    pub fn start(&mut self, path: &Path) -> Result<(), notify::Error> {
-        let storage = self.storage.clone();
-        let repository = self.repository.clone();
+    /// 配置中的忽略模式，匹配的路径不存储、不建记录，也不推送变更
+    pub fn with_ignore(mut self, patterns: &[String]) -> Self {
+        self.ignore = patterns.to_vec();
+        self
+    }
 
-        let watcher = FileWatcher::new(path, move |event| {
-            let storage = storage.clone();
-            let repository = repository.clone();
+    /// 与 API 共享的写入登记表，服务端自己写入的文件不再重复处理
+    pub fn with_expected_writes(mut self, expected_writes: ExpectedWrites) -> Self {
+        self.expected_writes = expected_writes;
+        self
+    }
 
-            tokio::spawn(async move {
-                if let Err(e) = Self::handle_event(event, &storage, &repository).await {
-                    tracing::error!("Failed to handle file event: {}", e);
-                }
-            });
-        })?;
+    /// 与 API 共享的变更广播，外部修改也推送给 WebSocket 订阅者
+    pub fn with_feed(mut self, feed: ChangeFeed) -> Self {
+        self.feed = feed;
+        self
+    }
+
+    pub fn start(&mut self, path: &Path) -> Result<(), notify::Error> {
+        let handler = EventHandler::new(path, self.storage.clone(), self.repository.clone())
+            .with_expected_writes(self.expected_writes.clone())
+            .with_feed(self.feed.clone())
+            .with_ignore(&self.ignore);
+        let (watcher, events) = FileWatcher::with_channel(path, EVENT_QUEUE)?;
 
+        self.worker = Some(tokio::spawn(process_events(
+            handler,
+            events,
+            Debouncer::new(DEBOUNCE_WINDOW),
+        )));
         self.watcher = Some(watcher);
         Ok(())
     }
 
-    async fn handle_event(
-        event: FileEvent,
-        storage: &crate::service::storage::StorageService,
-        repository: &crate::db::Repository,
-    ) -> crate::error::Result<()> {
-        match event {
-            FileEvent::Created(path) | FileEvent::Modified(path) => {
-                if path.is_file() {
-                    let (hash, size) = storage.store_file(&path).await?;
-                    tracing::info!("File stored: {:?} (hash: {}, size: {})", path, hash, size);
+    pub fn stop(&mut self) {
+        if let Some(watcher) = &mut self.watcher {
+            watcher.stop();
+        }
+        self.watcher = None;
+    }
+
+    /// 停止监控，并等待已收到的事件处理完
+    pub async fn shutdown(&mut self) {
+        self.stop();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.await;
+        }
+    }
+}
+
+// [知识点 #175] 事件去抖与合并
+// ----------------------------------------
+// 题目：编辑器保存一次文件，为什么会触发好几个事件？
+//
+// 讲解：
+// 一次保存常常是 截断 -> 分几次写入 -> 修改时间，每一步都产生 Modify 事件；
+// 新建文件则是 Create 紧跟若干 Modify。逐个事件立即处理会：
+// - 把同一个文件重复哈希、重复存储好几次
+// - 在写到一半时读取文件，存下不完整的内容
+//
+// 去抖（debounce）的做法：同一路径的事件先暂存，窗口内再来事件就合并并重新计时，
+// 安静下来之后才处理。合并规则是"后来的覆盖先来的"，只有 Create 后接 Modify 仍算 Create。
+// rename 涉及两个路径且顺序敏感，不参与合并，也阻止跨越它的合并。
+//
+// 处理按事件到达的顺序串行进行；通道有界，处理跟不上时监控线程等待，不丢事件。
+//
+// 思考：为什么持续被写入的文件也要设一个最长等待时间？
+// ----------------------------------------
+
+/// 同一路径的事件在该窗口内合并
+pub const DEBOUNCE_WINDOW: Duration = Duration::from_millis(500);
+/// 持续有事件的路径最多等待这么多个窗口
+const MAX_DEBOUNCE_WINDOWS: u32 = 10;
+/// 待处理事件队列的容量
+const EVENT_QUEUE: usize = 1024;
+
+struct PendingEvent {
+    event: FileEvent,
+    first: Instant,
+    last: Instant,
+}
+
+/// 按路径合并短时间内的重复事件，输出顺序与事件首次到达的顺序一致
+pub struct Debouncer {
+    window: Duration,
+    queue: VecDeque<PendingEvent>,
+}
+
+impl Debouncer {
+    pub fn new(window: Duration) -> Self {
+        Debouncer {
+            window,
+            queue: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, event: FileEvent, now: Instant) {
+        if let Some(path) = event.single_path() {
+            for pending in self.queue.iter_mut().rev() {
+                match &pending.event {
+                    FileEvent::Renamed { from, to } if from == path || to == path => break,
+                    queued if queued.single_path() == Some(path) => {
+                        let created = matches!(queued, FileEvent::Created(_));
+                        pending.event = match event {
+                            FileEvent::Modified(path) if created => FileEvent::Created(path),
+                            event => event,
+                        };
+                        pending.last = now;
+                        return;
+                    }
+                    _ => {}
                 }
             }
-            FileEvent::Deleted(path) => {
-                tracing::info!("File deleted: {:?}", path);
-                if let Ok(record) = repository.get_file_by_path(&path.to_string_lossy()).await {
-                    repository.delete_file(record.id).await?;
+        }
+        self.queue.push_back(PendingEvent {
+            event,
+            first: now,
+            last: now,
+        });
+    }
+
+    /// 队首事件安静够一个窗口（或等满最长时间）后返回
+    pub fn pop_ready(&mut self, now: Instant) -> Option<FileEvent> {
+        if self.next_deadline()? <= now {
+            self.queue.pop_front().map(|pending| pending.event)
+        } else {
+            None
+        }
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.queue.front().map(|pending| {
+            (pending.last + self.window).min(pending.first + self.window * MAX_DEBOUNCE_WINDOWS)
+        })
+    }
+
+    /// 不等窗口结束，取出全部事件
+    pub fn drain(&mut self) -> impl Iterator<Item = FileEvent> + '_ {
+        self.queue.drain(..).map(|pending| pending.event)
+    }
+}
+
+impl FileEvent {
+    fn single_path(&self) -> Option<&Path> {
+        match self {
+            FileEvent::Created(path) | FileEvent::Modified(path) | FileEvent::Deleted(path) => {
+                Some(path)
+            }
+            FileEvent::Renamed { .. } => None,
+        }
+    }
+}
+
+/// 去抖后按顺序处理；删除要等宽限期让新建事件认领记录，放到后台执行以免阻塞队列
+async fn process_events(
+    handler: EventHandler,
+    mut events: mpsc::Receiver<FileEvent>,
+    mut debouncer: Debouncer,
+) {
+    let deletions = TaskTracker::new();
+    let handle = |event: FileEvent| {
+        let handler = handler.clone();
+        let deletions = deletions.clone();
+        async move {
+            if matches!(event, FileEvent::Deleted(_)) {
+                deletions.spawn(async move {
+                    if let Err(e) = handler.handle(event).await {
+                        tracing::error!("Failed to handle file event: {}", e);
+                    }
+                });
+            } else if let Err(e) = handler.handle(event).await {
+                tracing::error!("Failed to handle file event: {}", e);
+            }
+        }
+    };
+
+    loop {
+        let deadline = debouncer.next_deadline();
+        tokio::select! {
+            event = events.recv() => match event {
+                Some(event) => debouncer.push(event, Instant::now()),
+                None => break,
+            },
+            _ = async {
+                match deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => std::future::pending().await,
                 }
+            } => {}
+        }
+        while let Some(event) = debouncer.pop_ready(Instant::now()) {
+            handle(event).await;
+        }
+    }
+
+    let remaining: Vec<_> = debouncer.drain().collect();
+    for event in remaining {
+        handle(event).await;
+    }
+    deletions.close();
+    deletions.wait().await;
+}
+
+/// 删除事件等待多久再删记录，留给同一文件的新建事件认领
+const MOVE_GRACE: Duration = Duration::from_secs(2);
+
+/// 把存储目录中的文件事件应用到元数据，克隆后共享
+#[derive(Clone)]
+pub struct EventHandler {
+    roots: Vec<PathBuf>,
+    storage: Arc<crate::service::storage::StorageService>,
+    repository: Arc<crate::db::Repository>,
+    move_grace: Duration,
+    expected_writes: ExpectedWrites,
+    feed: ChangeFeed,
+    /// 串行化记录的认领与移动，避免同一次移动的多个事件互相竞争
+    ops: Arc<tokio::sync::Mutex<()>>,
+    /// 配置中的忽略模式
+    ignore_patterns: Vec<String>,
+    /// 配置中的模式加上根目录 .rcloudignore 中的规则，该文件变化时重新读取
+    ignore: Arc<RwLock<IgnoreRules>>,
+}
+
+impl EventHandler {
+    pub fn new(
+        root: &Path,
+        storage: Arc<crate::service::storage::StorageService>,
+        repository: Arc<crate::db::Repository>,
+    ) -> Self {
+        // 事件路径可能基于传入的路径，也可能已被解析为绝对路径，两种都认
+        let mut roots = vec![root.to_path_buf()];
+        if let Ok(canonical) = std::fs::canonicalize(root) {
+            if canonical != root {
+                roots.push(canonical);
+            }
+        }
+        let handler = EventHandler {
+            roots,
+            storage,
+            repository,
+            move_grace: MOVE_GRACE,
+            expected_writes: ExpectedWrites::new(),
+            feed: ChangeFeed::new(),
+            ops: Arc::new(tokio::sync::Mutex::new(())),
+            ignore_patterns: Vec::new(),
+            ignore: Arc::new(RwLock::new(IgnoreRules::new())),
+        };
+        handler.reload_ignore();
+        handler
+    }
+
+    pub fn with_move_grace(mut self, move_grace: Duration) -> Self {
+        self.move_grace = move_grace;
+        self
+    }
+
+    pub fn with_expected_writes(mut self, expected_writes: ExpectedWrites) -> Self {
+        self.expected_writes = expected_writes;
+        self
+    }
+
+    pub fn with_feed(mut self, feed: ChangeFeed) -> Self {
+        self.feed = feed;
+        self
+    }
+
+    pub fn with_ignore(mut self, patterns: &[String]) -> Self {
+        self.ignore_patterns = patterns.to_vec();
+        self.reload_ignore();
+        self
+    }
+
+    fn reload_ignore(&self) {
+        let mut rules = IgnoreRules::new();
+        rules.extend(&self.ignore_patterns);
+        match std::fs::read_to_string(self.roots[0].join(IGNORE_FILE)) {
+            Ok(content) => rules.extend(content.lines()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!("Failed to read {}: {}", IGNORE_FILE, e),
+        }
+        *self.ignore.write().unwrap_or_else(|e| e.into_inner()) = rules;
+    }
+
+    fn is_ignore_file(&self, path: &Path) -> bool {
+        self.roots.iter().any(|root| path == root.join(IGNORE_FILE))
+    }
+
+    pub async fn handle(&self, event: FileEvent) -> crate::error::Result<()> {
+        use crate::service::storage::is_temp_file;
+
+        let touches_rules = match &event {
+            FileEvent::Created(path) | FileEvent::Modified(path) | FileEvent::Deleted(path) => {
+                self.is_ignore_file(path)
+            }
+            FileEvent::Renamed { from, to } => self.is_ignore_file(from) || self.is_ignore_file(to),
+        };
+        if touches_rules {
+            self.reload_ignore();
+        }
+
+        match event {
+            FileEvent::Created(path) | FileEvent::Modified(path) => self.upsert(&path, None).await,
+            FileEvent::Deleted(path) => {
+                self.storage.forget_hash(&path);
+                self.remove(&path).await
             }
+            // 原子写入：临时文件 rename 成正式文件，相当于正式文件被新建或覆盖
+            FileEvent::Renamed { from, to } if is_temp_file(&from) => self.upsert(&to, None).await,
             FileEvent::Renamed { from, to } => {
                 tracing::info!("File renamed: {:?} -> {:?}", from, to);
-                if let Ok(record) = repository.get_file_by_path(&from.to_string_lossy()).await {
-                    repository.delete_file(record.id).await?;
+                self.storage.forget_hash(&from);
+                self.storage.forget_hash(&to);
+                if to.is_dir() {
+                    self.move_directory(&from, &to).await
+                } else if self.logical_path(&to).is_none() {
+                    // 移到了被忽略的路径，对记录来说就是原路径被删除
+                    self.remove(&from).await
+                } else {
+                    let from = self.logical_path(&from);
+                    self.upsert(&to, from.as_deref()).await
                 }
-                if to.is_file() {
-                    let (hash, size) = storage.store_file(&to).await?;
-                    tracing::info!("File stored: {:?} (hash: {}, size: {})", to, hash, size);
+            }
+        }
+    }
+
+    /// 存储目录下的逻辑路径；对象目录、数据库、临时文件和被忽略的路径不属于用户文件
+    fn logical_path(&self, path: &Path) -> Option<String> {
+        if crate::service::storage::is_temp_file(path) {
+            return None;
+        }
+        let relative = self
+            .roots
+            .iter()
+            .find_map(|root| path.strip_prefix(root).ok())?;
+        let logical = rustcloud_types::path::normalize(&relative.to_string_lossy()).ok()?;
+        if crate::db::is_database_file(&logical)
+            || logical == "objects"
+            || logical.starts_with("objects/")
+        {
+            return None;
+        }
+        let ignore = self.ignore.read().unwrap_or_else(|e| e.into_inner());
+        if ignore.is_ignored(&logical, path.is_dir()) {
+            return None;
+        }
+        Some(logical)
+    }
+
+    fn local_path(&self, logical: &str) -> PathBuf {
+        self.roots[0].join(logical)
+    }
+
+    /// 出现在 path 的文件：已有记录就补上 inode；否则按 inode（或 rename 的源路径）
+    /// 找回移走的记录，保留 id 和版本历史
+    async fn upsert(&self, path: &Path, moved_from: Option<&str>) -> crate::error::Result<()> {
+        if !path.is_file() {
+            return Ok(());
+        }
+        let Some(logical) = self.logical_path(path) else {
+            return Ok(());
+        };
+        // 服务端刚写入的内容，对象和记录都已就绪
+        if let Some(expected) = self.expected_writes.expected_hash(&logical) {
+            if self.storage.compute_hash(path).await? == expected {
+                tracing::debug!("Skipping server-initiated write: {}", logical);
+                return Ok(());
+            }
+        }
+        // 外部修改：即使修改时间和大小恰好没变，缓存的哈希也不再可信
+        self.storage.forget_hash(path);
+        let (hash, size) = self.storage.store_file(path).await?;
+        tracing::info!("File stored: {:?} (hash: {}, size: {})", path, hash, size);
+        let fs_id = crate::service::fs_id::read(path);
+
+        let _ops = self.ops.lock().await;
+        let record = match self.repository.get_file_by_path(&logical).await {
+            Ok(record) => {
+                // 原地修改只存入对象、不改记录，推送新哈希，没有对应的版本号
+                if record.hash.as_deref() != Some(hash.as_str()) {
+                    self.feed.publish(rustcloud_types::ChangeEvent {
+                        kind: ChangeKind::Modified,
+                        path: logical.clone(),
+                        hash: Some(hash),
+                        version: None,
+                    });
                 }
+                record
             }
+            Err(_) => match self.moved_record(moved_from, fs_id).await {
+                Some(record) => {
+                    tracing::info!("File moved: {} -> {}", record.path, logical);
+                    let moved = self.repository.move_file(record.id, &logical).await?;
+                    let moved = if moved.hash.as_deref() != Some(hash.as_str()) {
+                        self.repository
+                            .update_file(moved.id, Some(hash), size)
+                            .await?
+                    } else {
+                        moved
+                    };
+                    self.publish_move(&record.path, &moved);
+                    moved
+                }
+                // 记录由上传接口创建，这里只负责对象存储
+                None => return Ok(()),
+            },
+        };
+        if fs_id.is_some() && record.fs_id != fs_id {
+            self.repository.set_file_fs_id(record.id, fs_id).await?;
         }
         Ok(())
     }
 
-    pub fn stop(&mut self) {
-        if let Some(watcher) = &mut self.watcher {
-            watcher.stop();
+    /// inode 会被复用，只有旧路径上的文件确实不在了才算移动
+    async fn moved_record(
+        &self,
+        moved_from: Option<&str>,
+        fs_id: Option<crate::db::FsId>,
+    ) -> Option<crate::db::FileRecord> {
+        let candidate = match moved_from {
+            Some(from) => self.repository.get_file_by_path(from).await.ok(),
+            None => None,
+        };
+        let candidate = match (candidate, fs_id) {
+            (Some(record), _) => Some(record),
+            (None, Some(fs_id)) => self.repository.find_file_by_fs_id(fs_id).await,
+            (None, None) => None,
+        }?;
+        (!self.local_path(&candidate.path).exists()).then_some(candidate)
+    }
+
+    /// 等待一段时间再删除记录：移动常被报告为"删除 + 新建"，且顺序不定
+    async fn remove(&self, path: &Path) -> crate::error::Result<()> {
+        let Some(logical) = self.logical_path(path) else {
+            return Ok(());
+        };
+        if self.expected_writes.is_expected_removal(&logical) {
+            tracing::debug!("Skipping server-initiated removal: {}", logical);
+            return Ok(());
         }
-        self.watcher = None;
+        if self.repository.get_file_by_path(&logical).await.is_err() {
+            // 删掉的可能是显式创建的目录，它只有目录记录
+            if !path.exists() {
+                self.repository.delete_directories(&logical).await?;
+            }
+            return Ok(());
+        }
+        tokio::time::sleep(self.move_grace).await;
+
+        let _ops = self.ops.lock().await;
+        if path.exists() {
+            return Ok(());
+        }
+        if let Ok(record) = self.repository.get_file_by_path(&logical).await {
+            tracing::info!("File deleted: {:?}", path);
+            self.repository.delete_file(record.id).await?;
+            self.feed.deleted(&logical);
+        }
+        Ok(())
+    }
+
+    /// 订阅者看到的移动是旧路径删除、新路径创建
+    fn publish_move(&self, from: &str, moved: &crate::db::FileRecord) {
+        self.feed.deleted(from);
+        self.feed.written(
+            ChangeKind::Created,
+            &moved.path,
+            moved.hash.clone(),
+            moved.version,
+        );
+    }
+
+    /// 目录整体重命名只会收到一个事件，目录下的记录一起改路径
+    async fn move_directory(&self, from: &Path, to: &Path) -> crate::error::Result<()> {
+        let (Some(from), Some(to)) = (self.logical_path(from), self.logical_path(to)) else {
+            return Ok(());
+        };
+        let prefix = format!("{}/", from);
+
+        let _ops = self.ops.lock().await;
+        for record in self.repository.list_files().await? {
+            if let Some(rest) = record.path.strip_prefix(&prefix) {
+                let target = format!("{}/{}", to, rest);
+                tracing::info!("File moved: {} -> {}", record.path, target);
+                let moved = self.repository.move_file(record.id, &target).await?;
+                self.publish_move(&record.path, &moved);
+            }
+        }
+        self.repository.move_directories(&from, &to).await?;
+        Ok(())
     }
 }
@@ -0,0 +1,54 @@
+//! 从文件和命令读取服务端密钥。测试要修改环境变量，单独放在这个测试二进制中，
+//! 不会与读取环境变量的其他测试并行
+
+use rustcloud::config::Config;
+use tempfile::TempDir;
+
+#[test]
+fn test_config_secrets_from_files_and_commands() {
+    let temp_dir = TempDir::new().unwrap();
+    let secret_file = temp_dir.path().join("jwt_secret");
+    std::fs::write(&secret_file, "jwt-from-file\n").unwrap();
+
+    let vars = [
+        ("RUSTCLOUD_JWT_SECRET_FILE", secret_file.to_str().unwrap()),
+        ("RUSTCLOUD_ADMIN_TOKEN_COMMAND", "echo admin-from-command"),
+        ("RUSTCLOUD_UPSTREAM_URL", "https://upstream.example"),
+        ("RUSTCLOUD_UPSTREAM_TOKEN", "upstream-direct"),
+        ("RUSTCLOUD_UPSTREAM_TOKEN_COMMAND", "echo ignored"),
+    ];
+    for (name, value) in vars {
+        std::env::set_var(name, value);
+    }
+
+    let config = Config::from_env_or_default().unwrap();
+    assert_eq!(config.auth.as_ref().unwrap().jwt_secret, "jwt-from-file");
+    assert_eq!(config.admin_token.as_deref(), Some("admin-from-command"));
+    // 直接给出的值优先于文件和命令
+    assert_eq!(
+        config.upstream.as_ref().unwrap().token.as_deref(),
+        Some("upstream-direct")
+    );
+
+    let logged = format!("{:?}", config);
+    for secret in ["jwt-from-file", "admin-from-command", "upstream-direct"] {
+        assert!(
+            !logged.contains(secret),
+            "{} leaked into {}",
+            secret,
+            logged
+        );
+    }
+    assert!(logged.contains("https://upstream.example"));
+
+    // 指定的来源读取失败时不能当作未设置
+    std::env::set_var(
+        "RUSTCLOUD_JWT_SECRET_FILE",
+        temp_dir.path().join("missing").to_str().unwrap(),
+    );
+    let error = Config::from_env_or_default().unwrap_err().to_string();
+    assert!(error.contains("RUSTCLOUD_JWT_SECRET_FILE"), "{}", error);
+    std::env::set_var("RUSTCLOUD_JWT_SECRET_FILE", "");
+    std::env::set_var("RUSTCLOUD_ADMIN_TOKEN_COMMAND", "exit 3");
+    assert!(Config::from_env_or_default().is_err());
+}
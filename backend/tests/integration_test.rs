@@ -16,9 +16,11 @@
 // ----------------------------------------
 
 use http_body_util::BodyExt;
-use rustcloud::config::Config;
-use rustcloud::db::{NewFileRecord, Repository};
-use rustcloud::service::storage::{StorageConfig, StorageService};
+use rustcloud::config::{
+    Config, ConflictStrategy, DatabaseBackend, NetworkConfig, WebSecurityConfig, WorkerConfig,
+};
+use rustcloud::db::{FileRecord, MetadataStore, NewFileRecord, Repository};
+use rustcloud::service::storage::{StagedUpload, StorageBackend, StorageConfig, StorageService};
 use std::sync::Arc;
 use tempfile::TempDir;
 use tower::ServiceExt;
@@ -42,7 +44,31 @@ fn make_config(temp_dir: &TempDir) -> Config {
         host: "127.0.0.1".to_string(),
         port: 3000,
         storage_path: temp_dir.path().join("storage"),
+        database: DatabaseBackend::Json,
         max_file_size: 100 * 1024 * 1024,
+        chunk_size: 1024,
+        shard_depth: 2,
+        materialize_files: true,
+        warm_hash_cache: false,
+        verify_reads: false,
+        compress_objects: false,
+        conflicts: ConflictStrategy::default(),
+        append_only: Vec::new(),
+        upload_only: Vec::new(),
+        download_only: Vec::new(),
+        shred_deleted: false,
+        trash_retention_days: 0,
+        ignore: Vec::new(),
+        smtp: None,
+        reputation: None,
+        admin_token: None,
+        min_client_version: None,
+        lifecycle_interval_secs: 0,
+        auth: None,
+        upstream: None,
+        network: NetworkConfig::default(),
+        web: WebSecurityConfig::default(),
+        workers: WorkerConfig::default(),
     }
 }
 
@@ -104,6 +130,772 @@ async fn test_repository_delete_file() {
     assert!(result.is_err());
 }
 
+#[tokio::test]
+async fn test_repository_snapshot_is_isolated_from_writes() {
+    use rustcloud::service::clock::SystemClock;
+
+    let temp_dir = TempDir::new().unwrap();
+    let json = Repository::new(temp_dir.path().join("db.json"))
+        .await
+        .unwrap();
+    let sqlite = Repository::sqlite(temp_dir.path().join("db.sqlite"), Arc::new(SystemClock))
+        .await
+        .unwrap();
+
+    for repository in [json, sqlite] {
+        let file = repository
+            .create_file(NewFileRecord {
+                path: "docs/a.txt".to_string(),
+                hash: Some("aaa".to_string()),
+                size: 10,
+            })
+            .await
+            .unwrap();
+        repository.create_directory("docs/empty").await.unwrap();
+        let snapshot = repository.snapshot().await.unwrap();
+
+        // 快照之后的修改不影响已取得的快照
+        repository
+            .update_file(file.id, Some("bbb".to_string()), 20)
+            .await
+            .unwrap();
+        repository.move_file(file.id, "moved/a.txt").await.unwrap();
+        repository.delete_directories("docs").await.unwrap();
+        repository
+            .create_file(NewFileRecord {
+                path: "docs/b.txt".to_string(),
+                hash: Some("ccc".to_string()),
+                size: 30,
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(snapshot.files.len(), 1);
+        assert_eq!(snapshot.files[0].path, "docs/a.txt");
+        assert_eq!(snapshot.files[0].hash.as_deref(), Some("aaa"));
+        assert_eq!(snapshot.file_versions(file.id).len(), 1);
+        assert!(snapshot.departures.is_empty());
+        assert_eq!(snapshot.directories.len(), 1);
+
+        let current = repository.snapshot().await.unwrap();
+        let paths: Vec<&str> = current.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, ["moved/a.txt", "docs/b.txt"]);
+        let versions = current.file_versions(file.id);
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[1].hash.as_deref(), Some("bbb"));
+        assert_eq!(current.departures.len(), 1);
+        assert!(current.directories.is_empty());
+        let listed = repository.list_files().await.unwrap();
+        assert_eq!(listed.len(), current.files.len());
+        assert_eq!(listed[1].hash, current.files[1].hash);
+    }
+}
+
+#[tokio::test]
+async fn test_repository_owner_usage() {
+    use rustcloud::service::clock::SystemClock;
+
+    let temp_dir = TempDir::new().unwrap();
+    let json = Repository::new(temp_dir.path().join("db.json"))
+        .await
+        .unwrap();
+    let sqlite = Repository::sqlite(temp_dir.path().join("db.sqlite"), Arc::new(SystemClock))
+        .await
+        .unwrap();
+
+    let (alice, bob) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+    for repository in [json, sqlite] {
+        let files = [
+            ("users/alice/a.txt", 10, Some(alice)),
+            ("shared/b.txt", 20, Some(alice)),
+            ("users/alice/imported.txt", 40, None),
+            ("users/alice2/c.txt", 80, None),
+            ("users/bob/d.txt", 160, Some(bob)),
+        ];
+        for (path, size, owner) in files {
+            let record = repository
+                .create_file(NewFileRecord {
+                    path: path.to_string(),
+                    hash: None,
+                    size,
+                })
+                .await
+                .unwrap();
+            repository.set_file_owner(record.id, owner).await.unwrap();
+        }
+
+        // 没有创建者的记录只在给出的目录下才算在名下，相同前缀的兄弟目录不算
+        let usage = repository.owner_usage(alice, None).await.unwrap();
+        assert_eq!(usage, (2, 30));
+        let usage = repository
+            .owner_usage(alice, Some("users/alice"))
+            .await
+            .unwrap();
+        assert_eq!(usage, (3, 70));
+        let usage = repository
+            .owner_usage(bob, Some("users/bob"))
+            .await
+            .unwrap();
+        assert_eq!(usage, (1, 160));
+    }
+}
+
+#[tokio::test]
+async fn test_repository_trash_entries_restore_records() {
+    use rustcloud::db::NewTrashEntry;
+    use rustcloud::error::Error;
+    use rustcloud::service::clock::SystemClock;
+
+    let temp_dir = TempDir::new().unwrap();
+    let json = Repository::new(temp_dir.path().join("db.json"))
+        .await
+        .unwrap();
+    let sqlite = Repository::sqlite(temp_dir.path().join("db.sqlite"), Arc::new(SystemClock))
+        .await
+        .unwrap();
+
+    for repository in [json, sqlite] {
+        let file = repository
+            .create_file(NewFileRecord {
+                path: "a.txt".to_string(),
+                hash: Some("aaa".to_string()),
+                size: 10,
+            })
+            .await
+            .unwrap();
+        let file = repository
+            .update_file(file.id, Some("bbb".to_string()), 20)
+            .await
+            .unwrap();
+        let entry = repository
+            .create_trash_entry(NewTrashEntry {
+                path: "a.txt".to_string(),
+                files: vec![file.clone()],
+                directories: Vec::new(),
+                size: 20,
+                deleted_by: None,
+            })
+            .await
+            .unwrap();
+        repository.delete_file(file.id).await.unwrap();
+
+        let listed = repository.list_trash().await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].files[0].id, file.id);
+        assert_eq!(repository.get_trash_entry(entry.id).await.unwrap().size, 20);
+
+        // 放回时保留原来的 id 和版本号，历史版本仍然可见
+        let restored = repository.restore_file(file.clone()).await.unwrap();
+        assert_eq!(restored.id, file.id);
+        assert_eq!(restored.version, 2);
+        assert_eq!(
+            repository.list_file_versions(file.id).await.unwrap().len(),
+            2
+        );
+        assert!(matches!(
+            repository.restore_file(file).await,
+            Err(Error::AlreadyExists(_))
+        ));
+
+        repository.delete_trash_entry(entry.id).await.unwrap();
+        assert!(repository.list_trash().await.unwrap().is_empty());
+        assert!(matches!(
+            repository.get_trash_entry(entry.id).await,
+            Err(Error::NotFound(_))
+        ));
+    }
+}
+
+#[tokio::test]
+async fn test_repository_path_spellings() {
+    use rustcloud::service::clock::SystemClock;
+
+    let temp_dir = TempDir::new().unwrap();
+    let json = Repository::new(temp_dir.path().join("db.json"))
+        .await
+        .unwrap();
+    let sqlite = Repository::sqlite(temp_dir.path().join("db.sqlite"), Arc::new(SystemClock))
+        .await
+        .unwrap();
+
+    for repository in [json, sqlite] {
+        for path in [
+            "Docs/Report.pdf",
+            "Docs/img/a.png",
+            "docs/b.txt",
+            "Docsx/c.txt",
+        ] {
+            repository
+                .create_file(NewFileRecord {
+                    path: path.to_string(),
+                    hash: None,
+                    size: 1,
+                })
+                .await
+                .unwrap();
+        }
+        // 文件本身和上级目录都算，相同前缀的兄弟目录不算
+        assert_eq!(
+            repository.path_spellings("DOCS").await.unwrap(),
+            vec!["Docs", "docs"]
+        );
+        assert_eq!(
+            repository.path_spellings("docs/IMG").await.unwrap(),
+            vec!["Docs/img"]
+        );
+        assert_eq!(
+            repository.path_spellings("docs/report.PDF").await.unwrap(),
+            vec!["Docs/Report.pdf"]
+        );
+        assert!(repository
+            .path_spellings("docs/missing.txt")
+            .await
+            .unwrap()
+            .is_empty());
+
+        // 移动后按新路径查找
+        let file = repository.get_file_by_path("docs/b.txt").await.unwrap();
+        repository.move_file(file.id, "Other/B.txt").await.unwrap();
+        assert_eq!(
+            repository.path_spellings("docs").await.unwrap(),
+            vec!["Docs"]
+        );
+        assert_eq!(
+            repository.path_spellings("other/b.txt").await.unwrap(),
+            vec!["Other/B.txt"]
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_sqlite_repository_upgrades_old_schema() {
+    use rustcloud::service::clock::SystemClock;
+
+    // 版本 5 的库：files 表还没有 owner 列
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().join("db.sqlite");
+    let conn = rusqlite::Connection::open(&db_path).unwrap();
+    conn.execute_batch(
+        "CREATE TABLE files (
+            id BLOB PRIMARY KEY,
+            path TEXT NOT NULL UNIQUE,
+            hash TEXT,
+            size INTEGER NOT NULL,
+            version INTEGER NOT NULL,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL,
+            media TEXT,
+            metadata TEXT NOT NULL DEFAULT '{}',
+            last_accessed_at TEXT,
+            fs_device INTEGER,
+            fs_inode INTEGER
+        );
+        INSERT INTO files (id, path, size, version, created_at, updated_at)
+        VALUES (x'00112233445566778899aabbccddeeff', 'users/alice/a.txt', 10, 1,
+                '2024-01-01T00:00:00.000000000+00:00', '2024-01-01T00:00:00.000000000+00:00');
+        PRAGMA user_version = 5;",
+    )
+    .unwrap();
+    drop(conn);
+
+    let repository = Repository::sqlite(db_path, Arc::new(SystemClock))
+        .await
+        .unwrap();
+    let file = repository
+        .get_file_by_path("users/alice/a.txt")
+        .await
+        .unwrap();
+    assert_eq!(file.size, 10);
+    assert_eq!(file.owner, None);
+    let owner = uuid::Uuid::new_v4();
+    repository
+        .set_file_owner(file.id, Some(owner))
+        .await
+        .unwrap();
+    assert_eq!(repository.owner_usage(owner, None).await.unwrap(), (1, 10));
+    // 升级时补上已有记录的路径键
+    assert_eq!(
+        repository.path_spellings("USERS/Alice").await.unwrap(),
+        vec!["users/alice"]
+    );
+}
+
+#[tokio::test]
+async fn test_sqlite_repository_persists_records() {
+    use rustcloud::db::{
+        FsId, NewCommentRecord, NewDeviceRecord, NewMountRecord, NewShareRecord, NewSyncRecord,
+        NewUserRecord, ShareAccessRecord, ShareLimits, SyncStatus,
+    };
+    use rustcloud::error::Error;
+    use rustcloud::service::clock::SystemClock;
+
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().join("db.sqlite");
+    let repository = Repository::sqlite(db_path.clone(), Arc::new(SystemClock))
+        .await
+        .unwrap();
+
+    let file = repository
+        .create_file(NewFileRecord {
+            path: "docs/a.txt".to_string(),
+            hash: Some("aaa".to_string()),
+            size: 10,
+        })
+        .await
+        .unwrap();
+    assert!(matches!(
+        repository
+            .create_file(NewFileRecord {
+                path: "docs/a.txt".to_string(),
+                hash: None,
+                size: 0,
+            })
+            .await,
+        Err(Error::AlreadyExists(_))
+    ));
+    let file = repository
+        .update_file(file.id, Some("bbb".to_string()), 20)
+        .await
+        .unwrap();
+    assert_eq!(file.version, 2);
+    repository
+        .update_file_metadata(
+            file.id,
+            [("owner".to_string(), Some("li".to_string()))].into(),
+        )
+        .await
+        .unwrap();
+    // 超过 i64::MAX 的文件系统标识也能原样找回
+    let fs_id = FsId {
+        device: u64::MAX - 1,
+        inode: 42,
+    };
+    repository
+        .set_file_fs_id(file.id, Some(fs_id))
+        .await
+        .unwrap();
+    assert_eq!(
+        repository.find_file_by_fs_id(fs_id).await.unwrap().id,
+        file.id
+    );
+    let accessed = chrono::Utc::now();
+    let updated = repository
+        .record_file_accesses([("docs/a.txt".to_string(), accessed)].into())
+        .await
+        .unwrap();
+    assert_eq!(updated, 1);
+    // 更早的访问时间不会覆盖
+    let updated = repository
+        .record_file_accesses(
+            [(
+                "docs/a.txt".to_string(),
+                accessed - chrono::Duration::hours(1),
+            )]
+            .into(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(updated, 0);
+
+    let device = repository
+        .create_device(NewDeviceRecord {
+            name: "laptop".to_string(),
+        })
+        .await
+        .unwrap();
+    let sync = repository
+        .create_sync(NewSyncRecord {
+            device_id: device.id,
+            file_id: file.id,
+            sync_status: SyncStatus::Pending,
+            base_version: None,
+            conflict_path: None,
+        })
+        .await
+        .unwrap();
+    repository
+        .update_sync_status(sync.id, SyncStatus::Syncing)
+        .await
+        .unwrap();
+    repository
+        .update_sync_status(sync.id, SyncStatus::Failed)
+        .await
+        .unwrap();
+    assert_eq!(
+        repository
+            .count_failed_syncs(device.id, chrono::Utc::now() - chrono::Duration::hours(1))
+            .await
+            .unwrap(),
+        1
+    );
+    repository
+        .create_comment(NewCommentRecord {
+            file_id: file.id,
+            author: "li".to_string(),
+            text: "looks good".to_string(),
+        })
+        .await
+        .unwrap();
+    repository
+        .create_user(NewUserRecord {
+            username: "Alice".to_string(),
+            password_hash: "hash".to_string(),
+        })
+        .await
+        .unwrap();
+    assert!(matches!(
+        repository
+            .create_user(NewUserRecord {
+                username: "alice".to_string(),
+                password_hash: "hash".to_string(),
+            })
+            .await,
+        Err(Error::AlreadyExists(_))
+    ));
+    let share = repository
+        .create_share(NewShareRecord {
+            path: "docs/a.txt".to_string(),
+            expires_at: None,
+            limits: ShareLimits {
+                max_downloads: Some(3),
+                ..Default::default()
+            },
+            created_by: None,
+        })
+        .await
+        .unwrap();
+    repository
+        .record_share_access(ShareAccessRecord {
+            share_id: share.id,
+            accessed_at: chrono::Utc::now(),
+            ip: Some("127.0.0.1".to_string()),
+            bytes: 20,
+        })
+        .await
+        .unwrap();
+
+    // 法律保留阻止修改和移动
+    repository.add_legal_hold("docs", None).await.unwrap();
+    assert!(matches!(
+        repository.move_file(file.id, "b.txt").await,
+        Err(Error::Held(_))
+    ));
+    repository.remove_legal_hold("docs").await.unwrap();
+    let file = repository.move_file(file.id, "b.txt").await.unwrap();
+    assert_eq!(file.path, "b.txt");
+
+    // 挂载点不能互相包含
+    let mount = |path: &str| NewMountRecord {
+        path: path.to_string(),
+        url: "https://alice.example.com".to_string(),
+        remote_path: "team".to_string(),
+        token: Some("remote-token".to_string()),
+    };
+    let mount_id = repository
+        .create_mount(mount("shared/alice"))
+        .await
+        .unwrap()
+        .id;
+    assert!(matches!(
+        repository.create_mount(mount("shared")).await,
+        Err(Error::AlreadyExists(_))
+    ));
+    repository.create_mount(mount("shared/bob")).await.unwrap();
+    drop(repository);
+
+    // 重新打开后记录仍在
+    let repository = Repository::sqlite(db_path, Arc::new(SystemClock))
+        .await
+        .unwrap();
+    let file = repository.get_file_by_path("b.txt").await.unwrap();
+    assert_eq!(file.version, 2);
+    assert_eq!(file.hash.as_deref(), Some("bbb"));
+    assert_eq!(file.metadata["owner"], "li");
+    assert_eq!(file.fs_id, Some(fs_id));
+    assert_eq!(
+        file.last_accessed_at.map(|at| at.timestamp_micros()),
+        Some(accessed.timestamp_micros())
+    );
+    assert_eq!(repository.total_size().await.unwrap(), 20);
+    // 历史版本按文件 id 记录，移动后仍在
+    let versions = repository.list_file_versions(file.id).await.unwrap();
+    assert_eq!(
+        versions
+            .iter()
+            .map(|v| (v.version, v.hash.as_deref(), v.size))
+            .collect::<Vec<_>>(),
+        vec![(1, Some("aaa"), 10), (2, Some("bbb"), 20)]
+    );
+    assert!(repository.find_user_by_name("ALICE").await.is_some());
+    let mounts = repository.list_mounts().await.unwrap();
+    assert_eq!(
+        mounts.iter().map(|m| m.path.as_str()).collect::<Vec<_>>(),
+        vec!["shared/alice", "shared/bob"]
+    );
+    assert_eq!(mounts[0].token.as_deref(), Some("remote-token"));
+    repository.delete_mount(mount_id).await.unwrap();
+    assert!(matches!(
+        repository.delete_mount(mount_id).await,
+        Err(Error::NotFound(_))
+    ));
+    assert_eq!(
+        repository.list_share_accesses(share.id).await.unwrap()[0].bytes,
+        20
+    );
+    assert_eq!(
+        repository
+            .get_share(share.id)
+            .await
+            .unwrap()
+            .limits
+            .max_downloads,
+        Some(3)
+    );
+
+    // 删除文件时一并删除同步记录和评论；历史版本保留，并记下删除
+    repository.delete_file(file.id).await.unwrap();
+    assert!(repository
+        .list_syncs_by_file(file.id)
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(repository.list_comments().await.unwrap().is_empty());
+    assert_eq!(
+        repository.list_file_versions(file.id).await.unwrap().len(),
+        2
+    );
+    let departures = repository.list_departures().await.unwrap();
+    assert_eq!(
+        departures
+            .iter()
+            .map(|d| (d.path.as_str(), d.moved_to.as_deref()))
+            .collect::<Vec<_>>(),
+        vec![("docs/a.txt", Some("b.txt")), ("b.txt", None)]
+    );
+    assert!(matches!(
+        repository.get_file_by_id(file.id).await,
+        Err(Error::NotFound(_))
+    ));
+    repository.purge_file_history(file.id).await.unwrap();
+    assert!(repository
+        .list_file_versions(file.id)
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(repository.list_departures().await.unwrap().is_empty());
+
+    repository.create_directory("photos/2024").await.unwrap();
+    repository
+        .create_directory("photos/2024/raw")
+        .await
+        .unwrap();
+    assert!(matches!(
+        repository.create_directory("photos/2024").await,
+        Err(Error::AlreadyExists(_))
+    ));
+    assert_eq!(
+        repository
+            .move_directories("photos/2024", "archive")
+            .await
+            .unwrap(),
+        2
+    );
+    assert_eq!(
+        repository
+            .list_directories()
+            .await
+            .unwrap()
+            .iter()
+            .map(|d| d.path.as_str())
+            .collect::<Vec<_>>(),
+        vec!["archive", "archive/raw"]
+    );
+    assert_eq!(repository.delete_directories("archive").await.unwrap(), 2);
+    assert!(repository.list_directories().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_sync_status_transitions_and_expiry() {
+    use rustcloud::db::{NewDeviceRecord, NewSyncRecord, SyncStatus};
+    use rustcloud::error::Error;
+    use rustcloud::service::clock::{Clock, VirtualClock};
+
+    let temp_dir = TempDir::new().unwrap();
+    let start = chrono::DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+        .unwrap()
+        .with_timezone(&chrono::Utc);
+    let clock = VirtualClock::new(start);
+    let backends = [
+        Repository::with_clock(temp_dir.path().join("db.json"), Arc::new(clock.clone()))
+            .await
+            .unwrap(),
+        Repository::sqlite(temp_dir.path().join("db.sqlite"), Arc::new(clock.clone()))
+            .await
+            .unwrap(),
+    ];
+
+    for repository in backends {
+        let file = repository
+            .create_file(NewFileRecord {
+                path: "a.txt".to_string(),
+                hash: None,
+                size: 0,
+            })
+            .await
+            .unwrap();
+        let device = repository
+            .create_device(NewDeviceRecord {
+                name: "laptop".to_string(),
+            })
+            .await
+            .unwrap();
+        let new_sync = |status| NewSyncRecord {
+            device_id: device.id,
+            file_id: file.id,
+            sync_status: status,
+            base_version: None,
+            conflict_path: None,
+        };
+        assert!(matches!(
+            repository
+                .create_sync(new_sync(SyncStatus::Completed))
+                .await,
+            Err(Error::InvalidTransition(_))
+        ));
+
+        // Pending -> Syncing -> Failed -> Pending -> Syncing -> Completed，每一步记下时间
+        let sync = repository
+            .create_sync(new_sync(SyncStatus::Pending))
+            .await
+            .unwrap();
+        let steps = [
+            SyncStatus::Syncing,
+            SyncStatus::Failed,
+            SyncStatus::Pending,
+            SyncStatus::Syncing,
+            SyncStatus::Completed,
+        ];
+        for status in steps {
+            clock.advance(chrono::Duration::seconds(1));
+            repository
+                .update_sync_status(sync.id, status)
+                .await
+                .unwrap();
+        }
+        let done = repository.list_syncs_by_file(file.id).await.unwrap()[0].clone();
+        assert_eq!(done.sync_status, SyncStatus::Completed);
+        assert_eq!(done.history.len(), 6);
+        assert_eq!(done.history[2].status, SyncStatus::Failed);
+        assert!(done.history.windows(2).all(|w| w[0].at < w[1].at));
+        assert_eq!(done.last_sync_at, done.history[5].at);
+
+        // 完成后不能再改，失败的更新不改变记录
+        assert!(matches!(
+            repository
+                .update_sync_status(sync.id, SyncStatus::Syncing)
+                .await,
+            Err(Error::InvalidTransition(_))
+        ));
+        assert_eq!(
+            repository.list_syncs_by_file(file.id).await.unwrap()[0].history,
+            done.history
+        );
+
+        // 停在 Syncing 的记录超时后变为 Failed，之后仍能重试
+        let stuck = repository
+            .create_sync(new_sync(SyncStatus::Syncing))
+            .await
+            .unwrap();
+        clock.advance(chrono::Duration::minutes(10));
+        let fresh = repository
+            .create_sync(new_sync(SyncStatus::Syncing))
+            .await
+            .unwrap();
+        let cutoff = clock.now() - chrono::Duration::minutes(5);
+        assert_eq!(repository.expire_stuck_syncs(cutoff).await.unwrap(), 1);
+        let syncs = repository.list_syncs_by_file(file.id).await.unwrap();
+        let status = |id| syncs.iter().find(|s| s.id == id).unwrap().sync_status;
+        assert_eq!(status(stuck.id), SyncStatus::Failed);
+        assert_eq!(status(fresh.id), SyncStatus::Syncing);
+        assert_eq!(status(sync.id), SyncStatus::Completed);
+        repository
+            .update_sync_status(stuck.id, SyncStatus::Pending)
+            .await
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_sqlite_backend_imports_existing_db_json() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = make_config(&temp_dir);
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+    let json_path = config.storage_path.join("db.json");
+
+    let json = Repository::new(json_path.clone()).await.unwrap();
+    for i in 0..3 {
+        json.create_file(NewFileRecord {
+            path: format!("notes/{}.txt", i),
+            hash: Some(format!("hash-{}", i)),
+            size: i,
+        })
+        .await
+        .unwrap();
+    }
+    json.create_device(rustcloud::db::NewDeviceRecord {
+        name: "desktop".to_string(),
+    })
+    .await
+    .unwrap();
+    json.add_legal_hold("notes/2.txt", Some("audit".to_string()))
+        .await
+        .unwrap();
+    let before = json.list_files().await.unwrap();
+    drop(json);
+
+    config.database = DatabaseBackend::Sqlite;
+    // 记录只在元数据中，列表不依赖磁盘上的文件
+    config.materialize_files = false;
+    let repository = Repository::open(&config.storage_path, config.database)
+        .await
+        .unwrap();
+    let after = repository.list_files().await.unwrap();
+    assert_eq!(
+        after
+            .iter()
+            .map(|f| (f.id, &f.path, f.version))
+            .collect::<Vec<_>>(),
+        before
+            .iter()
+            .map(|f| (f.id, &f.path, f.version))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        repository.list_file_versions(after[0].id).await.unwrap()[0].hash,
+        before[0].hash
+    );
+    assert_eq!(repository.list_devices().await.unwrap()[0].name, "desktop");
+    assert!(repository.find_legal_hold("notes/2.txt").await.is_some());
+    assert!(!json_path.exists());
+    assert!(config.storage_path.join("db.json.migrated").exists());
+    drop(repository);
+
+    // 再次启动不会重复导入；路由使用同一个数据库
+    let app = rustcloud::api::routes::create_router(config).await;
+    let response = app
+        .oneshot(
+            axum::http::Request::builder()
+                .uri("/api/files?path=notes")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["data"].as_array().unwrap().len(), 3);
+}
+
 #[tokio::test]
 async fn test_storage_compute_hash() {
     let (_temp_dir, _repository, storage) = setup().await;
@@ -158,6 +950,152 @@ async fn test_storage_store_content() {
     assert_eq!(retrieved, content);
 }
 
+#[tokio::test]
+async fn test_write_atomic_replaces_without_leftovers() {
+    use rustcloud::service::storage::{is_temp_file, write_atomic};
+
+    let temp_dir = TempDir::new().unwrap();
+    let target = temp_dir.path().join("atomic.txt");
+
+    write_atomic(&target, b"first").await.unwrap();
+    write_atomic(&target, b"second").await.unwrap();
+    assert_eq!(std::fs::read(&target).unwrap(), b"second");
+
+    // rename 之后不应残留临时文件
+    let leftovers = std::fs::read_dir(temp_dir.path())
+        .unwrap()
+        .filter(|e| is_temp_file(&e.as_ref().unwrap().path()))
+        .count();
+    assert_eq!(leftovers, 0);
+}
+
+#[tokio::test]
+async fn test_storage_chunk_policy() {
+    let (_temp_dir, _repository, storage) = setup().await;
+
+    // 不超过基础分块大小的文件不分块
+    assert_eq!(storage.chunk_size_for(1024), None);
+    // 中等文件使用基础分块
+    assert_eq!(storage.chunk_size_for(100 * 1024), Some(1024));
+    // 超大文件放大分块，使分块数不超过上限
+    assert_eq!(storage.chunk_size_for(10 * 1024 * 1024), Some(16 * 1024));
+
+    // 客户端提议的分块大小会被限制在允许范围内
+    let min = rustcloud::service::storage::MIN_CHUNK_SIZE;
+    assert_eq!(
+        storage.negotiate_chunk_size(10 * 1024 * 1024, Some(1)),
+        Some(min)
+    );
+    assert_eq!(storage.negotiate_chunk_size(min as u64, Some(min)), None);
+}
+
+#[tokio::test]
+async fn test_storage_chunked_with_explicit_size_round_trip() {
+    let (temp_dir, _repository, storage) = setup().await;
+    let min = rustcloud::service::storage::MIN_CHUNK_SIZE;
+
+    let content: Vec<u8> = (0..(min * 3 + 17)).map(|i| (i % 251) as u8).collect();
+    let source = temp_dir.path().join("big.bin");
+    tokio::fs::write(&source, &content).await.unwrap();
+
+    let (hash, size, chunks) = storage
+        .store_chunked_with(&source, Some(min))
+        .await
+        .unwrap();
+    assert_eq!(size, content.len() as u64);
+    assert_eq!(chunks.len(), 4);
+
+    let retrieved = storage.retrieve_chunked(&hash).await.unwrap();
+    assert_eq!(retrieved, content);
+}
+
+#[tokio::test]
+async fn test_preview_head_tail_and_bytes() {
+    use rustcloud::service::preview::{preview_file, PreviewRange, MAX_PREVIEW_BYTES};
+
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("app.log");
+    // 超过预览窗口，尾部窗口的第一行会被截断
+    let content: String = (0..200_000).map(|i| format!("line {}\n", i)).collect();
+    assert!(content.len() as u64 > MAX_PREVIEW_BYTES);
+    tokio::fs::write(&path, &content).await.unwrap();
+
+    let head = preview_file(&path, PreviewRange::Head(2)).await.unwrap();
+    assert_eq!(head.content, "line 0\nline 1\n");
+    assert_eq!(head.offset, 0);
+    assert!(head.truncated);
+
+    let tail = preview_file(&path, PreviewRange::Tail(2)).await.unwrap();
+    assert_eq!(tail.content, "line 199998\nline 199999\n");
+    assert_eq!(
+        tail.offset + tail.content.len() as u64,
+        content.len() as u64
+    );
+
+    let bytes = preview_file(
+        &path,
+        PreviewRange::Bytes {
+            offset: 5,
+            length: 3,
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(bytes.content, "0\nl");
+    assert_eq!(bytes.size, content.len() as u64);
+
+    // 小文件整体在窗口内，tail 不丢弃第一行
+    let small = temp_dir.path().join("small.txt");
+    tokio::fs::write(&small, "a\nb").await.unwrap();
+    let tail = preview_file(&small, PreviewRange::Tail(10)).await.unwrap();
+    assert_eq!(tail.content, "a\nb");
+    assert!(!tail.truncated);
+}
+
+// 最小的 JPEG：APP1 中带 DateTimeOriginal，SOF0 中带 640x480 尺寸
+fn sample_jpeg(taken_at: &str) -> Vec<u8> {
+    let mut tiff = Vec::new();
+    tiff.extend_from_slice(b"II\x2a\x00\x08\x00\x00\x00");
+    // IFD0：一个条目，指向 Exif 子目录（偏移 26）
+    tiff.extend_from_slice(&1u16.to_le_bytes());
+    tiff.extend_from_slice(&[0x69, 0x87, 4, 0, 1, 0, 0, 0]);
+    tiff.extend_from_slice(&26u32.to_le_bytes());
+    tiff.extend_from_slice(&0u32.to_le_bytes());
+    // Exif IFD：DateTimeOriginal，ASCII 值在偏移 44
+    tiff.extend_from_slice(&1u16.to_le_bytes());
+    tiff.extend_from_slice(&[0x03, 0x90, 2, 0, 20, 0, 0, 0]);
+    tiff.extend_from_slice(&44u32.to_le_bytes());
+    tiff.extend_from_slice(&0u32.to_le_bytes());
+    tiff.extend_from_slice(taken_at.as_bytes());
+    tiff.push(0);
+
+    let mut app1 = b"Exif\0\0".to_vec();
+    app1.extend_from_slice(&tiff);
+
+    let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+    jpeg.extend_from_slice(&((app1.len() + 2) as u16).to_be_bytes());
+    jpeg.extend_from_slice(&app1);
+    jpeg.extend_from_slice(&[0xFF, 0xC0, 0x00, 0x11, 8, 0x01, 0xE0, 0x02, 0x80, 3]);
+    jpeg.extend_from_slice(&[0; 9]);
+    jpeg.extend_from_slice(&[0xFF, 0xD9]);
+    jpeg
+}
+
+#[tokio::test]
+async fn test_media_extracts_exif_date_and_dimensions() {
+    use rustcloud::service::media::extract_metadata;
+
+    let meta = extract_metadata("IMG_0001.JPG", &sample_jpeg("2024:05:17 08:30:00")).unwrap();
+    assert_eq!(meta.width, Some(640));
+    assert_eq!(meta.height, Some(480));
+    assert_eq!(meta.taken_at.unwrap().to_string(), "2024-05-17 08:30:00");
+
+    // 非图片扩展名或损坏的内容不提取
+    assert!(extract_metadata("notes.txt", &sample_jpeg("2024:05:17 08:30:00")).is_none());
+    let broken = extract_metadata("broken.jpg", &[0xFF, 0xD8, 0xFF, 0xE1, 0xFF, 0xFF]).unwrap();
+    assert_eq!(broken.taken_at, None);
+}
+
 // [知识点 #134] API 集成测试
 // ----------------------------------------
 // 题目：如何测试 HTTP API？
@@ -203,6 +1141,150 @@ async fn test_api_health_check() {
     let body = response.into_body().collect().await.unwrap().to_bytes();
     let resp: serde_json::Value = serde_json::from_slice(&body).unwrap();
     assert_eq!(resp["success"], true);
+    assert_eq!(resp["data"]["status"], "ok");
+    assert_eq!(resp["data"]["version"], env!("CARGO_PKG_VERSION"));
+    assert!(resp["data"]["time"].is_string());
+}
+
+#[tokio::test]
+async fn test_supervisor_restarts_services_and_stops_them_in_order() {
+    use rustcloud::service::supervisor::Supervisor;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    let temp_dir = TempDir::new().unwrap();
+    let config = make_config(&temp_dir);
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+    let repository = Arc::new(
+        Repository::new(config.storage_path.join("db.json"))
+            .await
+            .unwrap(),
+    );
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+
+    let supervisor = Supervisor::new().with_backoff(Duration::from_millis(10));
+    let stopped = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+    // 第一次返回错误，第二次 panic，第三次起正常运行
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let (flaky_attempts, flaky_stopped) = (attempts.clone(), stopped.clone());
+    supervisor.spawn("flaky", move |shutdown| {
+        let attempt = flaky_attempts.fetch_add(1, Ordering::SeqCst);
+        let stopped = flaky_stopped.clone();
+        async move {
+            match attempt {
+                0 => Err(rustcloud::error::Error::Config("boom".to_string())),
+                1 => panic!("crashed"),
+                _ => {
+                    shutdown.cancelled().await;
+                    stopped.lock().unwrap().push("flaky");
+                    Ok(())
+                }
+            }
+        }
+    });
+    let second_stopped = stopped.clone();
+    supervisor.spawn("second", move |shutdown| {
+        let stopped = second_stopped.clone();
+        async move {
+            shutdown.cancelled().await;
+            stopped.lock().unwrap().push("second");
+            Ok(())
+        }
+    });
+
+    let app = rustcloud::api::create_router_with_supervisor(
+        config,
+        repository.clone(),
+        repository,
+        storage,
+        supervisor.clone(),
+        rustcloud::service::expected_writes::ExpectedWrites::new(),
+        rustcloud::service::feed::ChangeFeed::new(),
+    )
+    .await;
+
+    for _ in 0..200 {
+        if attempts.load(Ordering::SeqCst) >= 3 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let flaky = supervisor
+        .health()
+        .into_iter()
+        .find(|s| s.name == "flaky")
+        .unwrap();
+    assert_eq!(flaky.state, "running");
+    assert_eq!(flaky.restarts, 2);
+    assert!(flaky.last_error.unwrap().contains("crashed"));
+
+    let response = app
+        .oneshot(
+            axum::http::Request::builder()
+                .uri("/api/health")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let resp: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(resp["data"]["status"], "ok");
+    let names: Vec<_> = resp["data"]["services"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["name"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(names, ["flaky", "second", "sync-expiry", "access-flusher"]);
+
+    // 关闭时先等进行中的短任务，再按启动的逆序停止服务
+    let delivered = Arc::new(AtomicBool::new(false));
+    let task_delivered = delivered.clone();
+    supervisor.tasks().spawn(async move {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        task_delivered.store(true, Ordering::SeqCst);
+    });
+    supervisor.shutdown().await;
+    assert!(delivered.load(Ordering::SeqCst));
+    assert_eq!(*stopped.lock().unwrap(), ["second", "flaky"]);
+    assert!(supervisor.health().is_empty());
+}
+
+#[tokio::test]
+async fn test_api_capabilities() {
+    let (_temp_dir, app, _) = stress_app(true).await;
+
+    let response = app
+        .oneshot(
+            axum::http::Request::builder()
+                .uri("/api/capabilities")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::OK);
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let resp: rustcloud::api::routes::ApiResponse<rustcloud::api::routes::Capabilities> =
+        serde_json::from_slice(&body).unwrap();
+    let capabilities = resp.data.unwrap();
+    assert_eq!(capabilities.protocol, rustcloud_types::PROTOCOL_VERSION);
+    assert!(capabilities.min_protocol <= capabilities.protocol);
+    for feature in [
+        rustcloud_types::feature::STREAMED_LISTING,
+        rustcloud_types::feature::RANGE_DOWNLOAD,
+        rustcloud_types::feature::METADATA,
+        rustcloud_types::feature::SYNC_PLAN,
+    ] {
+        assert!(capabilities.supports(feature), "{}", feature);
+    }
+    assert!(capabilities.compression.is_empty());
 }
 
 #[tokio::test]
@@ -277,23 +1359,10 @@ async fn test_api_upload_file() {
     assert_eq!(resp["data"]["version"], 1);
 }
 
-// [知识点 #137] 文件大小限制测试
-// ----------------------------------------
-// 题目：如何测试文件大小限制？
-//
-// 讲解：
-// 测试边界条件是测试的重要部分：
-// - 恰好等于限制（应该通过）
-// - 超过限制（应该失败）
-// - 空文件（边界情况）
-//
-// 思考：如何测试超大文件而不实际创建它？
-// ----------------------------------------
 #[tokio::test]
-async fn test_api_upload_file_too_large() {
+async fn test_api_upload_identical_content_is_deduplicated() {
     let temp_dir = TempDir::new().unwrap();
-    let mut config = make_config(&temp_dir);
-    config.max_file_size = 10; // 设置很小的限制
+    let config = make_config(&temp_dir);
     std::fs::create_dir_all(&config.storage_path).unwrap();
 
     let db_path = config.storage_path.join("db.json");
@@ -305,33 +1374,155 @@ async fn test_api_upload_file_too_large() {
 
     let app = rustcloud::api::create_router_with_services(config, repository, storage).await;
 
-    // 上传超过限制的文件
-    let large_content = "this is more than 10 bytes";
+    let upload = |content: &'static str| {
+        axum::http::Request::builder()
+            .method("PUT")
+            .uri("/api/files/dedup.txt")
+            .header("Content-Type", "text/plain")
+            .body(axum::body::Body::from(content))
+            .unwrap()
+    };
+
+    let response = app.clone().oneshot(upload("same")).await.unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::OK);
+
+    // 相同内容再次上传：不递增版本，并标记 deduplicated
+    let response = app.clone().oneshot(upload("same")).await.unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::OK);
+    let resp_body = response.into_body().collect().await.unwrap().to_bytes();
+    let resp: serde_json::Value = serde_json::from_slice(&resp_body).unwrap();
+    assert_eq!(resp["data"]["version"], 1);
+    assert_eq!(resp["data"]["deduplicated"], true);
+
+    // 内容变化后正常递增版本
+    let response = app.oneshot(upload("changed")).await.unwrap();
+    let resp_body = response.into_body().collect().await.unwrap().to_bytes();
+    let resp: serde_json::Value = serde_json::from_slice(&resp_body).unwrap();
+    assert_eq!(resp["data"]["version"], 2);
+    assert_eq!(resp["data"]["deduplicated"], false);
+}
+
+#[tokio::test]
+async fn test_api_upload_without_materialized_copy() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = make_config(&temp_dir);
+    config.materialize_files = false;
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+
+    let db_path = config.storage_path.join("db.json");
+    let repository = Arc::new(Repository::new(db_path).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+    let storage_path = config.storage_path.clone();
+
+    let app =
+        rustcloud::api::create_router_with_services(config, repository, storage.clone()).await;
+
     let response = app
+        .clone()
         .oneshot(
             axum::http::Request::builder()
                 .method("PUT")
-                .uri("/api/files/large.txt")
-                .header("Content-Type", "text/plain")
-                .body(axum::body::Body::from(large_content))
+                .uri("/api/files/docs/note.txt")
+                .body(axum::body::Body::from("only in objects"))
                 .unwrap(),
         )
         .await
         .unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::OK);
+    let resp_body = response.into_body().collect().await.unwrap().to_bytes();
+    let resp: serde_json::Value = serde_json::from_slice(&resp_body).unwrap();
+    let hash = resp["data"]["hash"].as_str().unwrap().to_string();
 
-    assert_eq!(response.status(), axum::http::StatusCode::PAYLOAD_TOO_LARGE);
+    // 只写入对象存储，不落明文副本
+    assert!(!storage_path.join("docs/note.txt").exists());
+    assert!(storage.file_exists(&hash).await);
 
+    // 目录树来自元数据
+    let response = app
+        .clone()
+        .oneshot(
+            axum::http::Request::builder()
+                .uri("/api/files?path=docs")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
     let resp_body = response.into_body().collect().await.unwrap().to_bytes();
     let resp: serde_json::Value = serde_json::from_slice(&resp_body).unwrap();
-    assert_eq!(resp["success"], false);
-    assert!(resp["error"].as_str().unwrap().contains("too large"));
+    assert_eq!(resp["data"][0]["path"], "docs/note.txt");
+
+    let response = app
+        .oneshot(
+            axum::http::Request::builder()
+                .method("DELETE")
+                .uri("/api/files/docs/note.txt")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::OK);
 }
 
 #[tokio::test]
-async fn test_api_upload_file_within_limit() {
+async fn test_api_preview_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = make_config(&temp_dir);
+    std::fs::create_dir_all(config.storage_path.join("logs")).unwrap();
+    std::fs::write(
+        config.storage_path.join("logs/app.log"),
+        "one\ntwo\nthree\n",
+    )
+    .unwrap();
+
+    let db_path = config.storage_path.join("db.json");
+    let repository = Arc::new(Repository::new(db_path).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+
+    let app = rustcloud::api::create_router_with_services(config, repository, storage).await;
+
+    let response = app
+        .clone()
+        .oneshot(
+            axum::http::Request::builder()
+                .uri("/api/preview/logs/app.log?lines=1&tail=true")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::OK);
+    let resp_body = response.into_body().collect().await.unwrap().to_bytes();
+    let resp: serde_json::Value = serde_json::from_slice(&resp_body).unwrap();
+    assert_eq!(resp["data"]["content"], "three\n");
+    assert_eq!(resp["data"]["offset"], 8);
+    assert_eq!(resp["data"]["truncated"], true);
+
+    // 目录不能预览
+    let response = app
+        .oneshot(
+            axum::http::Request::builder()
+                .uri("/api/preview/logs")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_api_stream_supports_range() {
     let temp_dir = TempDir::new().unwrap();
     let mut config = make_config(&temp_dir);
-    config.max_file_size = 100; // 设置足够大的限制
+    config.materialize_files = false;
     std::fs::create_dir_all(&config.storage_path).unwrap();
 
     let db_path = config.storage_path.join("db.json");
@@ -343,56 +1534,6571 @@ async fn test_api_upload_file_within_limit() {
 
     let app = rustcloud::api::create_router_with_services(config, repository, storage).await;
 
-    // 上传小于限制的文件
-    let small_content = "hello";
+    // 未落盘模式下内容只在对象存储中，类型仍按逻辑路径推断
     let response = app
+        .clone()
         .oneshot(
             axum::http::Request::builder()
                 .method("PUT")
-                .uri("/api/files/small.txt")
-                .header("Content-Type", "text/plain")
-                .body(axum::body::Body::from(small_content))
+                .uri("/api/files/media/clip.mp4")
+                .body(axum::body::Body::from("0123456789"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::OK);
+
+    let response = app
+        .clone()
+        .oneshot(
+            axum::http::Request::builder()
+                .uri("/api/stream/media/clip.mp4")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "video/mp4");
+    assert_eq!(response.headers()["accept-ranges"], "bytes");
+    assert_eq!(
+        response.headers()["content-disposition"],
+        "inline; filename=\"clip.mp4\""
+    );
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&body[..], b"0123456789");
+
+    let response = app
+        .oneshot(
+            axum::http::Request::builder()
+                .uri("/api/stream/media/clip.mp4")
+                .header("range", "bytes=2-5")
+                .body(axum::body::Body::empty())
                 .unwrap(),
         )
         .await
         .unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()["content-range"], "bytes 2-5/10");
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&body[..], b"2345");
+}
+
+#[tokio::test]
+async fn test_api_raw_download() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = make_config(&temp_dir);
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+    let app = rustcloud::api::routes::create_router(config).await;
 
+    let get = |uri: &str| {
+        let request = axum::http::Request::builder()
+            .uri(uri)
+            .body(axum::body::Body::empty())
+            .unwrap();
+        app.clone().oneshot(request)
+    };
+    for (path, content) in [
+        ("docs/report.pdf", "%PDF-1.7 report"),
+        ("docs/raw", "named raw"),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(
+                axum::http::Request::builder()
+                    .method("PUT")
+                    .uri(format!("/api/files/{}", path))
+                    .body(axum::body::Body::from(content))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+    }
+
+    // 不带后缀返回文件信息
+    let response = get("/api/files/docs/report.pdf").await.unwrap();
+    assert_eq!(response.headers()["content-type"], "application/json");
+
+    let response = get("/api/files/docs/report.pdf/raw").await.unwrap();
     assert_eq!(response.status(), axum::http::StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "application/pdf");
+    assert_eq!(response.headers()["content-length"], "15");
+    assert_eq!(
+        response.headers()["content-disposition"],
+        "attachment; filename=\"report.pdf\""
+    );
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&body[..], b"%PDF-1.7 report");
+
+    // 名为 raw 的文件仍按普通路径返回信息
+    let response = get("/api/files/docs/raw").await.unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["data"]["path"], "docs/raw");
+    assert_eq!(json["data"]["size"], 9);
+
+    let response = get("/api/files/docs/missing.txt/raw").await.unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::NOT_FOUND);
 }
 
-// [知识点 #138] 文件监控测试
-// ----------------------------------------
-// 题目：如何测试异步事件驱动的代码？
-//
-// 讲解：
-// 文件监控是异步事件驱动的，测试时需要：
-// 1. 使用 AtomicBool 等同步原语共享状态
-// 2. 等待足够时间让事件传播
-// 3. 注意文件系统事件可能有延迟
-//
-// 思考：如何避免测试中的竞态条件？
-// ----------------------------------------
 #[tokio::test]
-async fn test_file_watcher_detects_creation() {
-    use rustcloud::watcher::file_watcher::{FileEvent, FileWatcher};
-    use std::sync::atomic::{AtomicBool, Ordering};
+async fn test_api_conditional_get() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = make_config(&temp_dir);
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+    let app = rustcloud::api::routes::create_router(config).await;
+
+    let response = app
+        .clone()
+        .oneshot(
+            axum::http::Request::builder()
+                .method("PUT")
+                .uri("/api/files/notes.txt")
+                .body(axum::body::Body::from("v1"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::OK);
+
+    let get = |uri: &str, headers: &[(&str, &str)]| {
+        let mut request = axum::http::Request::builder().uri(uri);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        app.clone()
+            .oneshot(request.body(axum::body::Body::empty()).unwrap())
+    };
+    let etag = format!("\"{}\"", rustcloud_client::sha256_hex(b"v1"));
+
+    let response = get("/api/files/notes.txt/raw", &[]).await.unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::OK);
+    assert_eq!(response.headers()["etag"], etag.as_str());
+
+    // 列表中任一 ETag 命中即返回 304，弱比较忽略 W/ 前缀
+    let weak = format!("\"other\", W/{}", etag);
+    for uri in ["/api/files/notes.txt/raw", "/api/stream/notes.txt"] {
+        let response = get(uri, &[("if-none-match", weak.as_str())]).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::NOT_MODIFIED, "{}", uri);
+        assert_eq!(response.headers()["etag"], etag.as_str());
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(body.is_empty());
+    }
+
+    // ETag 不匹配时忽略 If-Modified-Since，返回完整内容
+    let response = get(
+        "/api/files/notes.txt/raw",
+        &[
+            ("if-none-match", "\"stale\""),
+            ("if-modified-since", "Fri, 01 Jan 2100 00:00:00 GMT"),
+        ],
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&body[..], b"v1");
+
+    let response = get(
+        "/api/files/notes.txt/raw",
+        &[("if-modified-since", "Fri, 01 Jan 2100 00:00:00 GMT")],
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::NOT_MODIFIED);
+
+    // 续传：If-Range 与当前 ETag 相同时只返回剩余部分，不同时返回完整内容
+    let response = get(
+        "/api/files/notes.txt/raw",
+        &[("range", "bytes=1-"), ("if-range", etag.as_str())],
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()["content-range"], "bytes 1-1/2");
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&body[..], b"1");
+
+    let response = get(
+        "/api/files/notes.txt/raw",
+        &[("range", "bytes=1-"), ("if-range", "\"stale\"")],
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&body[..], b"v1");
+}
 
+#[tokio::test]
+async fn test_api_photos_by_date() {
     let temp_dir = TempDir::new().unwrap();
-    let detected = Arc::new(AtomicBool::new(false));
-    let detected_clone = detected.clone();
+    let config = make_config(&temp_dir);
+    std::fs::create_dir_all(&config.storage_path).unwrap();
 
-    let _watcher = FileWatcher::new(temp_dir.path(), move |event| {
-        if matches!(event, FileEvent::Created(_)) {
-            detected_clone.store(true, Ordering::SeqCst);
+    let db_path = config.storage_path.join("db.json");
+    let repository = Arc::new(Repository::new(db_path).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+
+    let app = rustcloud::api::create_router_with_services(config, repository, storage).await;
+
+    for (path, taken_at) in [
+        ("camera/a.jpg", "2024:05:17 08:30:00"),
+        ("camera/b.jpg", "2023:12:31 23:59:59"),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(
+                axum::http::Request::builder()
+                    .method("PUT")
+                    .uri(format!("/api/files/{}", path))
+                    .body(axum::body::Body::from(sample_jpeg(taken_at)))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+    }
+
+    let get = |uri: &'static str| {
+        let app = app.clone();
+        async move {
+            let response = app
+                .oneshot(
+                    axum::http::Request::builder()
+                        .uri(uri)
+                        .body(axum::body::Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
         }
-    });
+    };
 
-    // 创建文件
-    let test_file = temp_dir.path().join("watched.txt");
-    std::fs::write(&test_file, "content").unwrap();
+    let resp = get("/api/photos?from=2024-01-01").await;
+    let photos = resp["data"].as_array().unwrap();
+    assert_eq!(photos.len(), 1);
+    assert_eq!(photos[0]["path"], "camera/a.jpg");
+    assert_eq!(photos[0]["media"]["width"], 640);
 
-    // 等待事件处理
-    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+    let resp = get("/api/photos/by-date").await;
+    assert_eq!(resp["data"][0]["path"], "by-date/2023");
+    assert_eq!(resp["data"][1]["path"], "by-date/2024");
 
-    assert!(detected.load(Ordering::SeqCst));
+    let resp = get("/api/photos/by-date/2024").await;
+    assert_eq!(resp["data"][0]["name"], "05");
+
+    let resp = get("/api/photos/by-date/2024/05").await;
+    assert_eq!(resp["data"].as_array().unwrap().len(), 1);
+    assert_eq!(resp["data"][0]["path"], "camera/a.jpg");
+}
+
+#[tokio::test]
+async fn test_api_file_comments_and_activity() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = make_config(&temp_dir);
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+
+    let db_path = config.storage_path.join("db.json");
+    let repository = Arc::new(Repository::new(db_path).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+
+    let app = rustcloud::api::create_router_with_services(config, repository, storage).await;
+
+    let send = |method: &'static str, uri: String, body: axum::body::Body| {
+        let app = app.clone();
+        async move {
+            let response = app
+                .oneshot(
+                    axum::http::Request::builder()
+                        .method(method)
+                        .uri(uri)
+                        .header("content-type", "application/json")
+                        .body(body)
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            (
+                status,
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            )
+        }
+    };
+
+    let (status, _) = send(
+        "PUT",
+        "/api/files/report.txt".to_string(),
+        axum::body::Body::from("draft"),
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+
+    // 未跟踪的文件不能评论
+    let (status, _) = send(
+        "POST",
+        "/api/comments".to_string(),
+        axum::body::Body::from(r#"{"path":"missing.txt","text":"hi"}"#),
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::NOT_FOUND);
+
+    let (status, resp) = send(
+        "POST",
+        "/api/comments".to_string(),
+        axum::body::Body::from(
+            r#"{"path":"report.txt","author":"alice","text":"this is the final version"}"#,
+        ),
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::CREATED);
+    let comment_id = resp["data"]["id"].as_str().unwrap().to_string();
+
+    let (_, resp) = send(
+        "GET",
+        "/api/comments?path=report.txt".to_string(),
+        axum::body::Body::empty(),
+    )
+    .await;
+    assert_eq!(resp["data"][0]["author"], "alice");
+
+    let (_, resp) = send(
+        "GET",
+        "/api/activity".to_string(),
+        axum::body::Body::empty(),
+    )
+    .await;
+    let kinds: Vec<&str> = resp["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["kind"].as_str().unwrap())
+        .collect();
+    assert_eq!(kinds, vec!["comment", "file_updated"]);
+
+    let uri = format!("/api/comments/{}", comment_id);
+    let (status, _) = send("DELETE", uri.clone(), axum::body::Body::empty()).await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    let (status, _) = send("DELETE", uri, axum::body::Body::empty()).await;
+    assert_eq!(status, axum::http::StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_api_upload_rejects_checksum_mismatch() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = make_config(&temp_dir);
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+
+    let db_path = config.storage_path.join("db.json");
+    let repository = Arc::new(Repository::new(db_path).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+
+    let app =
+        rustcloud::api::create_router_with_services(config, repository.clone(), storage).await;
+
+    let response = app
+        .oneshot(
+            axum::http::Request::builder()
+                .method("PUT")
+                .uri("/api/files/corrupt.txt")
+                .header("X-Content-Sha256", "0".repeat(64))
+                .body(axum::body::Body::from("hello world"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(
+        response.status(),
+        axum::http::StatusCode::UNPROCESSABLE_ENTITY
+    );
+    // 校验失败的内容不应产生记录
+    assert!(repository.get_file_by_path("corrupt.txt").await.is_err());
+}
+
+// [知识点 #137] 文件大小限制测试
+// ----------------------------------------
+// 题目：如何测试文件大小限制？
+//
+// 讲解：
+// 测试边界条件是测试的重要部分：
+// - 恰好等于限制（应该通过）
+// - 超过限制（应该失败）
+// - 空文件（边界情况）
+//
+// 思考：如何测试超大文件而不实际创建它？
+// ----------------------------------------
+#[tokio::test]
+async fn test_api_upload_file_too_large() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = make_config(&temp_dir);
+    config.max_file_size = 10; // 设置很小的限制
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+
+    let db_path = config.storage_path.join("db.json");
+    let repository = Arc::new(Repository::new(db_path).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+
+    let app = rustcloud::api::create_router_with_services(config, repository, storage).await;
+
+    // 上传超过限制的文件
+    let large_content = "this is more than 10 bytes";
+    let response = app
+        .oneshot(
+            axum::http::Request::builder()
+                .method("PUT")
+                .uri("/api/files/large.txt")
+                .header("Content-Type", "text/plain")
+                .body(axum::body::Body::from(large_content))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), axum::http::StatusCode::PAYLOAD_TOO_LARGE);
+
+    let resp_body = response.into_body().collect().await.unwrap().to_bytes();
+    let resp: serde_json::Value = serde_json::from_slice(&resp_body).unwrap();
+    assert_eq!(resp["success"], false);
+    assert!(resp["error"].as_str().unwrap().contains("too large"));
+}
+
+#[tokio::test]
+async fn test_api_upload_file_within_limit() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = make_config(&temp_dir);
+    config.max_file_size = 100; // 设置足够大的限制
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+
+    let db_path = config.storage_path.join("db.json");
+    let repository = Arc::new(Repository::new(db_path).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+
+    let app = rustcloud::api::create_router_with_services(config, repository, storage).await;
+
+    // 上传小于限制的文件
+    let small_content = "hello";
+    let response = app
+        .oneshot(
+            axum::http::Request::builder()
+                .method("PUT")
+                .uri("/api/files/small.txt")
+                .header("Content-Type", "text/plain")
+                .body(axum::body::Body::from(small_content))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), axum::http::StatusCode::OK);
+}
+
+// 暂存文件都已移走或删除，objects/ 下不应留下任何临时文件
+fn staged_leftovers(storage_path: &std::path::Path) -> Vec<std::path::PathBuf> {
+    std::fs::read_dir(storage_path.join("objects"))
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| rustcloud::service::storage::is_temp_file(p))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[tokio::test]
+async fn test_api_upload_streams_binary_body_without_length() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = make_config(&temp_dir);
+    config.materialize_files = false;
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+    let storage_path = config.storage_path.clone();
+
+    let repository = Arc::new(Repository::new(storage_path.join("db.json")).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: storage_path.clone(),
+        chunk_size: 1024,
+    }));
+    let app =
+        rustcloud::api::create_router_with_services(config, repository.clone(), storage).await;
+
+    // 超过 axum 默认 2MB 上限、含非 UTF-8 字节，分多段发送且不带 Content-Length
+    let content: Vec<u8> = (0..3 * 1024 * 1024u32).map(|i| (i % 251) as u8).collect();
+    let chunks: Vec<Result<Vec<u8>, std::io::Error>> =
+        content.chunks(64 * 1024).map(|c| Ok(c.to_vec())).collect();
+    let response = app
+        .oneshot(
+            axum::http::Request::builder()
+                .method("PUT")
+                .uri("/api/files/blob.bin")
+                .body(axum::body::Body::from_stream(futures_util::stream::iter(
+                    chunks,
+                )))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::OK);
+
+    let record = repository.get_file_by_path("blob.bin").await.unwrap();
+    assert_eq!(record.size, content.len() as u64);
+    let hash = record.hash.unwrap();
+    let stored = StorageService::new(StorageConfig {
+        storage_path: storage_path.clone(),
+        chunk_size: 1024,
+    })
+    .retrieve_file(&hash)
+    .await
+    .unwrap();
+    assert_eq!(stored, content);
+    assert!(staged_leftovers(&storage_path).is_empty());
+}
+
+#[tokio::test]
+async fn test_api_upload_rejected_as_soon_as_limit_is_exceeded() {
+    use futures_util::StreamExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = make_config(&temp_dir);
+    config.max_file_size = 1024;
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+    let storage_path = config.storage_path.clone();
+
+    let repository = Arc::new(Repository::new(storage_path.join("db.json")).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: storage_path.clone(),
+        chunk_size: 1024,
+    }));
+    let app =
+        rustcloud::api::create_router_with_services(config, repository.clone(), storage).await;
+
+    // 发出 2KB 之后请求体永远不结束：只有边收边检查才能返回
+    let body = futures_util::stream::iter(vec![Ok::<_, std::io::Error>(vec![0u8; 2048])])
+        .chain(futures_util::stream::pending());
+    let response = tokio::time::timeout(
+        std::time::Duration::from_secs(5),
+        app.oneshot(
+            axum::http::Request::builder()
+                .method("PUT")
+                .uri("/api/files/endless.bin")
+                .body(axum::body::Body::from_stream(body))
+                .unwrap(),
+        ),
+    )
+    .await
+    .expect("upload should be rejected before the body ends")
+    .unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::PAYLOAD_TOO_LARGE);
+    assert!(repository.get_file_by_path("endless.bin").await.is_err());
+    assert!(staged_leftovers(&storage_path).is_empty());
+}
+
+#[tokio::test]
+async fn test_api_multipart_upload() {
+    let (temp_dir, repository, storage) = setup().await;
+    let config = make_config(&temp_dir);
+    let app =
+        rustcloud::api::create_router_with_services(config, repository.clone(), storage).await;
+
+    let content: &[u8] = b"\x00\xff\xfe binary \x80 payload";
+    let mut body = Vec::new();
+    body.extend_from_slice(
+        b"--XBOUNDARY\r\nContent-Disposition: form-data; name=\"comment\"\r\n\r\nignored\r\n",
+    );
+    body.extend_from_slice(
+        b"--XBOUNDARY\r\nContent-Disposition: form-data; name=\"file\"; filename=\"photo.raw\"\r\n\
+          Content-Type: application/octet-stream\r\n\r\n",
+    );
+    body.extend_from_slice(content);
+    body.extend_from_slice(b"\r\n--XBOUNDARY--\r\n");
+
+    let response = app
+        .oneshot(
+            axum::http::Request::builder()
+                .method("POST")
+                .uri("/api/files/uploads/photo.raw/multipart?on_conflict=fail")
+                .header("Content-Type", "multipart/form-data; boundary=XBOUNDARY")
+                .body(axum::body::Body::from(body))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::OK);
+
+    let resp_body = response.into_body().collect().await.unwrap().to_bytes();
+    let resp: serde_json::Value = serde_json::from_slice(&resp_body).unwrap();
+    assert_eq!(resp["data"]["path"], "uploads/photo.raw");
+
+    let record = repository
+        .get_file_by_path("uploads/photo.raw")
+        .await
+        .unwrap();
+    assert_eq!(record.size, content.len() as u64);
+    assert_eq!(
+        std::fs::read(temp_dir.path().join("storage/uploads/photo.raw")).unwrap(),
+        content
+    );
+}
+
+#[tokio::test]
+async fn test_api_resumable_chunked_upload() {
+    let (temp_dir, repository, storage) = setup().await;
+    let config = make_config(&temp_dir);
+    let app =
+        rustcloud::api::create_router_with_services(config, repository.clone(), storage).await;
+
+    let send = |method: &str, uri: String, body: Vec<u8>| {
+        let request = axum::http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("Content-Type", "application/json")
+            .body(axum::body::Body::from(body))
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            (status, json)
+        }
+    };
+
+    // 服务端分块大小 1024：1024 + 1024 + 452
+    let content: Vec<u8> = (0..2500u32).map(|i| (i % 251) as u8).collect();
+    let (status, created) = send(
+        "POST",
+        "/api/uploads".to_string(),
+        serde_json::to_vec(&serde_json::json!({ "path": "big/blob.bin", "size": 2500 })).unwrap(),
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::CREATED);
+    assert_eq!(created["data"]["chunk_size"], 1024);
+    assert_eq!(created["data"]["chunk_count"], 3);
+    let id = created["data"]["id"].as_str().unwrap().to_string();
+    let chunk = |n: usize| content[n * 1024..((n + 1) * 1024).min(content.len())].to_vec();
+
+    // 分块可以乱序到达
+    let (status, _) = send("PUT", format!("/api/uploads/{}/chunks/2", id), chunk(2)).await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    let (status, _) = send("PUT", format!("/api/uploads/{}/chunks/0", id), chunk(0)).await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    let (_, current) = send("GET", format!("/api/uploads/{}", id), Vec::new()).await;
+    assert_eq!(current["data"]["received"], serde_json::json!([0, 2]));
+
+    let (status, resp) = send("POST", format!("/api/uploads/{}/complete", id), Vec::new()).await;
+    assert_eq!(status, axum::http::StatusCode::BAD_REQUEST);
+    assert_eq!(resp["error"], "Missing chunks: 1");
+
+    let (status, _) = send("PUT", format!("/api/uploads/{}/chunks/1", id), vec![0; 10]).await;
+    assert_eq!(status, axum::http::StatusCode::BAD_REQUEST);
+    let (status, _) = send("PUT", format!("/api/uploads/{}/chunks/3", id), chunk(2)).await;
+    assert_eq!(status, axum::http::StatusCode::BAD_REQUEST);
+    let (status, _) = send("PUT", format!("/api/uploads/{}/chunks/1", id), chunk(1)).await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+
+    let (status, resp) = send("POST", format!("/api/uploads/{}/complete", id), Vec::new()).await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    assert_eq!(resp["data"]["path"], "big/blob.bin");
+    let record = repository.get_file_by_path("big/blob.bin").await.unwrap();
+    assert_eq!(record.size, content.len() as u64);
+    assert_eq!(
+        std::fs::read(temp_dir.path().join("storage/big/blob.bin")).unwrap(),
+        content
+    );
+
+    // 完成后会话即被删除
+    let (status, _) = send("GET", format!("/api/uploads/{}", id), Vec::new()).await;
+    assert_eq!(status, axum::http::StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_api_version_history_and_rollback() {
+    let (temp_dir, repository, storage) = setup().await;
+    let config = make_config(&temp_dir);
+    let app =
+        rustcloud::api::create_router_with_services(config, repository.clone(), storage).await;
+
+    let send = |method: &str, uri: &str, body: &'static str| {
+        let request = axum::http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(axum::body::Body::from(body))
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            (status, json)
+        }
+    };
+
+    for content in ["first", "second", "third"] {
+        let (status, _) = send("PUT", "/api/files/notes/todo.txt", content).await;
+        assert_eq!(status, axum::http::StatusCode::OK);
+    }
+    let (status, resp) = send("GET", "/api/files/notes/todo.txt/versions", "").await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    let versions = resp["data"].as_array().unwrap();
+    assert_eq!(
+        versions.iter().map(|v| &v["version"]).collect::<Vec<_>>(),
+        [1, 2, 3]
+    );
+    assert_eq!(versions[0]["size"], 5);
+
+    // 回滚写入一个新版本，内容与旧版本相同，历史不被改写
+    let (status, resp) = send("POST", "/api/files/notes/todo.txt/rollback/1", "").await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    assert_eq!(resp["data"]["version"], 4);
+    assert_eq!(resp["data"]["hash"], versions[0]["hash"]);
+    assert_eq!(
+        std::fs::read_to_string(temp_dir.path().join("storage/notes/todo.txt")).unwrap(),
+        "first"
+    );
+    let record = repository.get_file_by_path("notes/todo.txt").await.unwrap();
+    assert_eq!(record.version, 4);
+    assert_eq!(
+        repository
+            .list_file_versions(record.id)
+            .await
+            .unwrap()
+            .len(),
+        4
+    );
+
+    let (status, _) = send("POST", "/api/files/notes/todo.txt/rollback/9", "").await;
+    assert_eq!(status, axum::http::StatusCode::NOT_FOUND);
+    let (status, _) = send("POST", "/api/files/notes/todo.txt/rollback/latest", "").await;
+    assert_eq!(status, axum::http::StatusCode::BAD_REQUEST);
+    let (status, _) = send("POST", "/api/files/notes/missing.txt/rollback/1", "").await;
+    assert_eq!(status, axum::http::StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_api_move_keeps_version_history() {
+    let (temp_dir, repository, storage) = setup().await;
+    let config = make_config(&temp_dir);
+    let app =
+        rustcloud::api::create_router_with_services(config, repository.clone(), storage).await;
+
+    let send = |method: &str, uri: &str, body: &'static str| {
+        let request = axum::http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(axum::body::Body::from(body))
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            (status, json)
+        }
+    };
+
+    for content in ["first", "second"] {
+        let (status, _) = send("PUT", "/api/files/notes/todo.txt", content).await;
+        assert_eq!(status, axum::http::StatusCode::OK);
+    }
+    let (status, _) = send("PUT", "/api/files/notes/done.txt", "done").await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    let original = repository.get_file_by_path("notes/todo.txt").await.unwrap();
+
+    // 记录原地改路径，id 和版本号不变，磁盘上的文件跟着移动
+    let (status, resp) = send(
+        "POST",
+        "/api/files/notes/todo.txt/move",
+        r#"{"to": "archive/2024/todo.txt"}"#,
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::OK, "{}", resp);
+    assert_eq!(resp["data"][0]["path"], "archive/2024/todo.txt");
+    assert_eq!(resp["data"][0]["version"], 2);
+    let storage_path = temp_dir.path().join("storage");
+    assert!(!storage_path.join("notes/todo.txt").exists());
+    assert_eq!(
+        std::fs::read_to_string(storage_path.join("archive/2024/todo.txt")).unwrap(),
+        "second"
+    );
+    let moved = repository
+        .get_file_by_path("archive/2024/todo.txt")
+        .await
+        .unwrap();
+    assert_eq!(moved.id, original.id);
+    let (status, resp) = send("GET", "/api/files/archive/2024/todo.txt/versions", "").await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    assert_eq!(resp["data"].as_array().unwrap().len(), 2);
+
+    // 目录整体移动，只改大小写的重命名也可以
+    let (status, resp) = send("POST", "/api/files/notes/move", r#"{"to": "Notes"}"#).await;
+    assert_eq!(status, axum::http::StatusCode::OK, "{}", resp);
+    assert_eq!(resp["data"][0]["path"], "Notes/done.txt");
+    assert!(repository.get_file_by_path("Notes/done.txt").await.is_ok());
+
+    // 目标已存在（只差大小写也算）、移到自身之下、源不存在
+    let (status, _) = send(
+        "POST",
+        "/api/files/Notes/done.txt/move",
+        r#"{"to": "ARCHIVE/2024/TODO.txt"}"#,
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::CONFLICT);
+    let (status, _) = send(
+        "POST",
+        "/api/files/archive/move",
+        r#"{"to": "archive/old"}"#,
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::BAD_REQUEST);
+    let (status, _) = send(
+        "POST",
+        "/api/files/missing.txt/move",
+        r#"{"to": "found.txt"}"#,
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::NOT_FOUND);
+    let (status, _) = send(
+        "POST",
+        "/api/files/Notes/done.txt/move",
+        r#"{"to": "../x"}"#,
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::BAD_REQUEST);
+}
+
+// objects/ 下各级目录中的文件总数
+fn object_files(dir: &std::path::Path) -> usize {
+    std::fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .map(|p| if p.is_dir() { object_files(&p) } else { 1 })
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+#[tokio::test]
+async fn test_api_hashes_lists_compact_state() {
+    let (temp_dir, repository, storage) = setup().await;
+    let config = make_config(&temp_dir);
+    let app = rustcloud::api::create_router_with_services(config, repository, storage).await;
+
+    let send = |uri: &str, headers: &[(&str, &str)]| {
+        let mut request = axum::http::Request::builder().method("GET").uri(uri);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let request = request.body(axum::body::Body::empty()).unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let headers = response.headers().clone();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            (status, headers, body)
+        }
+    };
+    let upload = |path: String, content: &'static str| {
+        let request = axum::http::Request::builder()
+            .method("PUT")
+            .uri(format!("/api/files/{}", path))
+            .body(axum::body::Body::from(content))
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            assert_eq!(response.status(), axum::http::StatusCode::OK);
+        }
+    };
+
+    upload("docs/b.txt".to_string(), "bee").await;
+    upload("docs/a.txt".to_string(), "one").await;
+    upload("docs/a.txt".to_string(), "two").await;
+    upload("notes/c.txt".to_string(), "sea").await;
+
+    // 只有路径、哈希、版本和大小，按路径排序
+    let (status, headers, body) = send("/api/hashes?prefix=docs/", &[]).await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        json["data"],
+        serde_json::json!([
+            {
+                "path": "docs/a.txt",
+                "hash": rustcloud_client::sha256_hex(b"two"),
+                "version": 2,
+                "size": 3
+            },
+            {
+                "path": "docs/b.txt",
+                "hash": rustcloud_client::sha256_hex(b"bee"),
+                "version": 1,
+                "size": 3
+            }
+        ])
+    );
+    assert!(headers.get("content-encoding").is_none());
+
+    // 状态没变时 If-None-Match 得到 304，变了之后 ETag 跟着变
+    let etag = headers["etag"].to_str().unwrap().to_string();
+    let (status, _, body) = send("/api/hashes?prefix=docs/", &[("if-none-match", &etag)]).await;
+    assert_eq!(status, axum::http::StatusCode::NOT_MODIFIED);
+    assert!(body.is_empty());
+    upload("docs/b.txt".to_string(), "bees").await;
+    let (status, headers, _) = send("/api/hashes?prefix=docs/", &[("if-none-match", &etag)]).await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    assert_ne!(headers["etag"], etag.as_str());
+
+    // 列表足够大且客户端接受时 gzip 压缩
+    for i in 0..20 {
+        upload(format!("bulk/{}.txt", i), "bulk").await;
+    }
+    let (status, headers, body) = send("/api/hashes", &[("accept-encoding", "gzip, br")]).await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    assert_eq!(headers["content-encoding"], "gzip");
+    let mut decoded = Vec::new();
+    std::io::Read::read_to_end(&mut flate2::read::GzDecoder::new(&body[..]), &mut decoded).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&decoded).unwrap();
+    assert_eq!(json["data"].as_array().unwrap().len(), 23);
+}
+
+#[tokio::test]
+async fn test_api_dirs_track_empty_directories() {
+    let (temp_dir, repository, storage) = setup().await;
+    let mut config = make_config(&temp_dir);
+    config.materialize_files = false;
+    let storage_path = config.storage_path.clone();
+    let app = rustcloud::api::create_router_with_services(config, repository, storage).await;
+
+    let send = |method: &str, uri: &str, body: Option<serde_json::Value>| {
+        let mut request = axum::http::Request::builder().method(method).uri(uri);
+        let body = match body {
+            Some(body) => {
+                request = request.header("content-type", "application/json");
+                axum::body::Body::from(body.to_string())
+            }
+            None => axum::body::Body::empty(),
+        };
+        let request = request.body(body).unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let json: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+            (status, json)
+        }
+    };
+    let names = |json: &serde_json::Value| {
+        let mut names: Vec<String> = json["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| f["path"].as_str().unwrap().to_string())
+            .collect();
+        names.sort();
+        names
+    };
+
+    let (status, json) = send("POST", "/api/dirs/projects/empty", None).await;
+    assert_eq!(status, axum::http::StatusCode::CREATED);
+    assert_eq!(json["data"]["is_dir"], true);
+    let (status, _) = send("POST", "/api/dirs/projects/empty", None).await;
+    assert_eq!(status, axum::http::StatusCode::CONFLICT);
+
+    // 磁盘上的目录不在了，记录仍让空目录出现在列表中
+    std::fs::remove_dir_all(storage_path.join("projects")).unwrap();
+    let (_, json) = send("GET", "/api/files?path=projects", None).await;
+    assert_eq!(names(&json), vec!["projects/empty"]);
+    let (_, json) = send("GET", "/api/files?path=projects/empty", None).await;
+    assert_eq!(json["success"], true);
+    assert!(json["data"].as_array().unwrap().is_empty());
+
+    // 同步计划：本地的空目录在服务端创建，服务端记录的目录在本地创建
+    let (_, json) = send(
+        "POST",
+        "/api/sync/plan",
+        Some(serde_json::json!({ "local_files": [], "local_dirs": ["local/new"] })),
+    )
+    .await;
+    let mut items: Vec<(String, String, bool)> = json["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| {
+            (
+                item["path"].as_str().unwrap().to_string(),
+                item["action"].as_str().unwrap().to_string(),
+                item["is_dir"].as_bool().unwrap(),
+            )
+        })
+        .collect();
+    items.sort();
+    assert_eq!(
+        items,
+        vec![
+            ("local/new".to_string(), "upload".to_string(), true),
+            ("projects/empty".to_string(), "download".to_string(), true),
+        ]
+    );
+    // 不发送 local_dirs 的早期客户端得到的计划中没有目录
+    let (_, json) = send(
+        "POST",
+        "/api/sync/plan",
+        Some(serde_json::json!({ "local_files": [] })),
+    )
+    .await;
+    assert!(json["data"].as_array().unwrap().is_empty());
+
+    // 移动和删除带上目录记录
+    let (status, _) = send(
+        "POST",
+        "/api/files/projects/move",
+        Some(serde_json::json!({ "to": "archive" })),
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    let (_, json) = send("GET", "/api/files", None).await;
+    assert!(names(&json).contains(&"archive".to_string()));
+    let (_, json) = send("GET", "/api/files?path=archive", None).await;
+    assert_eq!(names(&json), vec!["archive/empty"]);
+
+    let (status, _) = send("DELETE", "/api/files/archive/empty", None).await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    let (_, json) = send("GET", "/api/files?path=archive/empty", None).await;
+    assert_eq!(json["success"], false);
+}
+
+#[tokio::test]
+async fn test_api_copy_points_at_the_same_object() {
+    let (temp_dir, repository, storage) = setup().await;
+    let config = make_config(&temp_dir);
+    let app =
+        rustcloud::api::create_router_with_services(config, repository.clone(), storage.clone())
+            .await;
+
+    let send = |method: &str, uri: &str, body: &'static str| {
+        let request = axum::http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(axum::body::Body::from(body))
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            (status, json)
+        }
+    };
+
+    for content in ["draft", "final"] {
+        let (status, _) = send("PUT", "/api/files/reports/q3.txt", content).await;
+        assert_eq!(status, axum::http::StatusCode::OK);
+    }
+    let objects = || object_files(&temp_dir.path().join("storage/objects"));
+    let stored = objects();
+
+    // 新记录引用同一个对象，历史从版本 1 开始
+    let (status, resp) = send(
+        "POST",
+        "/api/files/reports/q3.txt/copy",
+        r#"{"to": "archive/q3.txt"}"#,
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::OK, "{}", resp);
+    assert_eq!(resp["data"]["path"], "archive/q3.txt");
+    assert_eq!(resp["data"]["version"], 1);
+    assert_eq!(resp["data"]["deduplicated"], true);
+    let original = repository.get_file_by_path("reports/q3.txt").await.unwrap();
+    let copy = repository.get_file_by_path("archive/q3.txt").await.unwrap();
+    assert_ne!(copy.id, original.id);
+    assert_eq!(copy.hash, original.hash);
+    assert_eq!(objects(), stored);
+    assert_eq!(
+        std::fs::read_to_string(temp_dir.path().join("storage/archive/q3.txt")).unwrap(),
+        "final"
+    );
+
+    // 之后修改副本不影响原文件
+    let (status, _) = send("PUT", "/api/files/archive/q3.txt", "annotated").await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    let original = repository.get_file_by_path("reports/q3.txt").await.unwrap();
+    assert_eq!(original.version, 2);
+    assert_eq!(
+        std::fs::read_to_string(temp_dir.path().join("storage/reports/q3.txt")).unwrap(),
+        "final"
+    );
+
+    let (status, _) = send(
+        "POST",
+        "/api/files/reports/q3.txt/copy",
+        r#"{"to": "Archive/Q3.txt"}"#,
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::CONFLICT);
+    let (status, _) = send("POST", "/api/files/reports/copy", r#"{"to": "backup"}"#).await;
+    assert_eq!(status, axum::http::StatusCode::BAD_REQUEST);
+    let (status, _) = send(
+        "POST",
+        "/api/files/reports/q4.txt/copy",
+        r#"{"to": "q4.txt"}"#,
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::NOT_FOUND);
+}
+
+// [知识点 #138] 文件监控测试
+// ----------------------------------------
+// 题目：如何测试异步事件驱动的代码？
+//
+// 讲解：
+// 文件监控是异步事件驱动的，测试时需要：
+// 1. 使用 AtomicBool 等同步原语共享状态
+// 2. 等待足够时间让事件传播
+// 3. 注意文件系统事件可能有延迟
+//
+// 思考：如何避免测试中的竞态条件？
+// ----------------------------------------
+#[tokio::test]
+async fn test_file_watcher_detects_creation() {
+    use rustcloud::watcher::file_watcher::{FileEvent, FileWatcher};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let temp_dir = TempDir::new().unwrap();
+    let detected = Arc::new(AtomicBool::new(false));
+    let detected_clone = detected.clone();
+
+    let _watcher = FileWatcher::new(temp_dir.path(), move |event| {
+        if matches!(event, FileEvent::Created(_)) {
+            detected_clone.store(true, Ordering::SeqCst);
+        }
+    });
+
+    // 创建文件
+    let test_file = temp_dir.path().join("watched.txt");
+    std::fs::write(&test_file, "content").unwrap();
+
+    // 等待事件处理
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    assert!(detected.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn test_watcher_debouncer_coalesces_bursts_in_order() {
+    use rustcloud::watcher::file_watcher::{Debouncer, FileEvent};
+    use std::path::PathBuf;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    let ms = Duration::from_millis;
+    let path = |name: &str| PathBuf::from(format!("/storage/{}", name));
+    let t0 = Instant::now();
+    let mut debouncer = Debouncer::new(ms(500));
+
+    // 编辑器保存：新建后多次修改，只处理一次，并且仍算新建
+    debouncer.push(FileEvent::Created(path("a")), t0);
+    debouncer.push(FileEvent::Modified(path("a")), t0 + ms(100));
+    debouncer.push(FileEvent::Modified(path("b")), t0 + ms(150));
+    debouncer.push(FileEvent::Modified(path("a")), t0 + ms(300));
+    assert!(debouncer.pop_ready(t0 + ms(700)).is_none());
+    assert!(matches!(
+        debouncer.pop_ready(t0 + ms(800)),
+        Some(FileEvent::Created(p)) if p == path("a")
+    ));
+    assert!(matches!(
+        debouncer.pop_ready(t0 + ms(800)),
+        Some(FileEvent::Modified(p)) if p == path("b")
+    ));
+    assert!(debouncer.next_deadline().is_none());
+
+    // 写完又删掉，只剩删除；rename 前后的事件不合并
+    debouncer.push(FileEvent::Modified(path("c")), t0);
+    debouncer.push(FileEvent::Deleted(path("c")), t0);
+    debouncer.push(
+        FileEvent::Renamed {
+            from: path("d"),
+            to: path("c"),
+        },
+        t0,
+    );
+    debouncer.push(FileEvent::Modified(path("c")), t0);
+    let events: Vec<_> = debouncer.drain().collect();
+    assert_eq!(events.len(), 3);
+    assert!(matches!(&events[0], FileEvent::Deleted(p) if *p == path("c")));
+    assert!(matches!(&events[1], FileEvent::Renamed { .. }));
+    assert!(matches!(&events[2], FileEvent::Modified(p) if *p == path("c")));
+
+    // 持续写入的文件最多等 10 个窗口
+    for i in 0..100 {
+        debouncer.push(FileEvent::Modified(path("log")), t0 + ms(i * 100));
+    }
+    assert_eq!(debouncer.next_deadline(), Some(t0 + ms(5000)));
+    assert!(debouncer.pop_ready(t0 + ms(5000)).is_some());
+}
+
+async fn watched_storage(
+    temp_dir: &TempDir,
+) -> (
+    Arc<Repository>,
+    rustcloud::watcher::file_watcher::EventHandler,
+) {
+    let storage_path = temp_dir.path().join("storage");
+    std::fs::create_dir_all(&storage_path).unwrap();
+    let repository = Arc::new(Repository::new(storage_path.join("db.json")).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: storage_path.clone(),
+        chunk_size: 1024,
+    }));
+    let handler = rustcloud::watcher::file_watcher::EventHandler::new(
+        &storage_path,
+        storage,
+        repository.clone(),
+    )
+    .with_move_grace(std::time::Duration::from_millis(200));
+    (repository, handler)
+}
+
+#[tokio::test]
+async fn test_watcher_detects_move_reported_as_delete_and_create() {
+    use rustcloud::watcher::file_watcher::FileEvent;
+
+    let temp_dir = TempDir::new().unwrap();
+    let (repository, handler) = watched_storage(&temp_dir).await;
+    let root = temp_dir.path().join("storage");
+
+    let old_path = root.join("report.txt");
+    std::fs::write(&old_path, "quarterly numbers").unwrap();
+    repository
+        .create_file(NewFileRecord {
+            path: "report.txt".to_string(),
+            hash: None,
+            size: 17,
+        })
+        .await
+        .unwrap();
+    handler
+        .handle(FileEvent::Modified(old_path.clone()))
+        .await
+        .unwrap();
+    let record = repository.get_file_by_path("report.txt").await.unwrap();
+    assert!(record.fs_id.is_some());
+
+    std::fs::create_dir_all(root.join("archive")).unwrap();
+    let new_path = root.join("archive/report.txt");
+    std::fs::rename(&old_path, &new_path).unwrap();
+
+    // 删除事件先到，新建事件在宽限期内认领同一个 inode
+    let deleting = tokio::spawn({
+        let handler = handler.clone();
+        async move { handler.handle(FileEvent::Deleted(old_path)).await }
+    });
+    handler
+        .handle(FileEvent::Created(new_path.clone()))
+        .await
+        .unwrap();
+    deleting.await.unwrap().unwrap();
+
+    let moved = repository
+        .get_file_by_path("archive/report.txt")
+        .await
+        .unwrap();
+    assert_eq!(moved.id, record.id);
+    assert_eq!(moved.fs_id, record.fs_id);
+    // 内容哈希从无到有，记为一个新版本；历史仍然接在原记录上
+    assert_eq!(moved.version, record.version + 1);
+    assert!(repository.get_file_by_path("report.txt").await.is_err());
+    assert_eq!(repository.list_files().await.unwrap().len(), 1);
+
+    // 复制出来的是新 inode，不会被当成移动
+    let copy_path = root.join("copy.txt");
+    std::fs::copy(&new_path, &copy_path).unwrap();
+    handler.handle(FileEvent::Created(copy_path)).await.unwrap();
+    assert!(repository.get_file_by_path("copy.txt").await.is_err());
+    assert_eq!(
+        repository
+            .get_file_by_path("archive/report.txt")
+            .await
+            .unwrap()
+            .id,
+        record.id
+    );
+}
+
+#[tokio::test]
+async fn test_watcher_skips_ignored_paths() {
+    use rustcloud::watcher::file_watcher::{EventHandler, FileEvent};
+
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path().join("storage");
+    std::fs::create_dir_all(root.join("build")).unwrap();
+    std::fs::write(root.join(".rcloudignore"), "# build output\nbuild/\n").unwrap();
+    let repository = Arc::new(Repository::new(root.join("db.json")).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: root.clone(),
+        chunk_size: 1024,
+    }));
+    let handler = EventHandler::new(&root, storage.clone(), repository.clone())
+        .with_move_grace(std::time::Duration::ZERO)
+        .with_ignore(&["*.tmp".to_string()]);
+    let created = |name: &str, content: &str| {
+        let path = root.join(name);
+        std::fs::write(&path, content).unwrap();
+        let handler = handler.clone();
+        async move { handler.handle(FileEvent::Created(path)).await.unwrap() }
+    };
+    let stored = |content: &str| {
+        let hash = storage.compute_content_hash(content.as_bytes());
+        let storage = storage.clone();
+        async move { storage.file_exists(&hash).await }
+    };
+
+    // 配置中的模式和 .rcloudignore 中的规则都生效
+    created("cache.tmp", "temporary").await;
+    created("build/out.bin", "build output").await;
+    created("notes.txt", "notes").await;
+    assert!(!stored("temporary").await);
+    assert!(!stored("build output").await);
+    assert!(stored("notes").await);
+
+    // 移进被忽略的路径，相当于原路径被删除
+    repository
+        .create_file(NewFileRecord {
+            path: "draft.txt".to_string(),
+            hash: None,
+            size: 0,
+        })
+        .await
+        .unwrap();
+    std::fs::write(root.join("draft.tmp"), "draft").unwrap();
+    handler
+        .handle(FileEvent::Renamed {
+            from: root.join("draft.txt"),
+            to: root.join("draft.tmp"),
+        })
+        .await
+        .unwrap();
+    assert!(repository.get_file_by_path("draft.txt").await.is_err());
+
+    // 修改 .rcloudignore 后立即按新规则处理
+    std::fs::write(root.join(".rcloudignore"), "!*.tmp\n").unwrap();
+    handler
+        .handle(FileEvent::Modified(root.join(".rcloudignore")))
+        .await
+        .unwrap();
+    created("cache.tmp", "temporary again").await;
+    created("build/out.bin", "new build output").await;
+    assert!(stored("temporary again").await);
+    assert!(stored("new build output").await);
+}
+
+#[tokio::test]
+async fn test_watcher_skips_server_initiated_writes() {
+    use rustcloud::service::expected_writes::ExpectedWrites;
+    use rustcloud::watcher::file_watcher::{EventHandler, FileEvent};
+
+    let temp_dir = TempDir::new().unwrap();
+    let config = make_config(&temp_dir);
+    let root = config.storage_path.clone();
+    std::fs::create_dir_all(&root).unwrap();
+    let repository = Arc::new(Repository::new(root.join("db.json")).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: root.clone(),
+        chunk_size: 1024,
+    }));
+    let expected = ExpectedWrites::new();
+    let app = rustcloud::api::create_router_with_supervisor(
+        config,
+        repository.clone(),
+        repository.clone(),
+        storage.clone(),
+        rustcloud::service::supervisor::Supervisor::new(),
+        expected.clone(),
+        rustcloud::service::feed::ChangeFeed::new(),
+    )
+    .await;
+    let handler = EventHandler::new(&root, storage, repository.clone())
+        .with_expected_writes(expected.clone());
+
+    let send = |method: &'static str, uri: &'static str, body: &'static str| {
+        let app = app.clone();
+        async move {
+            let request = axum::http::Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(axum::body::Body::from(body))
+                .unwrap();
+            app.oneshot(request).await.unwrap().status()
+        }
+    };
+
+    let status = send("PUT", "/api/files/docs/notes.txt", "server copy").await;
+    assert!(status.is_success());
+    let record = repository.get_file_by_path("docs/notes.txt").await.unwrap();
+    assert_eq!(expected.expected_hash("docs/notes.txt"), record.hash);
+
+    // 监控收到服务端自己写入引起的事件：不再重新入库（inode 也不会被重新读取）
+    let record = repository.set_file_fs_id(record.id, None).await.unwrap();
+    let path = root.join("docs/notes.txt");
+    handler
+        .handle(FileEvent::Created(path.clone()))
+        .await
+        .unwrap();
+    let unchanged = repository.get_file_by_path("docs/notes.txt").await.unwrap();
+    assert_eq!(unchanged.fs_id, None);
+    assert_eq!(unchanged.version, record.version);
+
+    // 写入之后又被外部改过，内容对不上，照常处理
+    std::fs::write(&path, "edited outside").unwrap();
+    handler
+        .handle(FileEvent::Modified(path.clone()))
+        .await
+        .unwrap();
+    let touched = repository.get_file_by_path("docs/notes.txt").await.unwrap();
+    assert!(touched.fs_id.is_some());
+
+    // 删除目录时登记整个子树
+    let status = send("DELETE", "/api/files/docs?recursive=true", "").await;
+    assert!(status.is_success());
+    assert!(expected.is_expected_removal("docs/notes.txt"));
+    assert!(!expected.is_expected_removal("other.txt"));
+
+    // 过期的登记不再生效
+    let expired = ExpectedWrites::with_ttl(std::time::Duration::ZERO);
+    expired.expect_write("a.txt", "abc");
+    expired.expect_removal("b");
+    assert_eq!(expired.expected_hash("a.txt"), None);
+    assert!(!expired.is_expected_removal("b/c.txt"));
+}
+
+#[tokio::test]
+async fn test_watcher_rename_events_preserve_lineage() {
+    use rustcloud::watcher::file_watcher::FileEvent;
+
+    let temp_dir = TempDir::new().unwrap();
+    let (repository, handler) = watched_storage(&temp_dir).await;
+    let root = temp_dir.path().join("storage");
+
+    std::fs::create_dir_all(root.join("photos")).unwrap();
+    let mut ids = Vec::new();
+    for name in ["photos/a.jpg", "photos/b.jpg"] {
+        std::fs::write(root.join(name), name).unwrap();
+        let record = repository
+            .create_file(NewFileRecord {
+                path: name.to_string(),
+                hash: None,
+                size: name.len() as u64,
+            })
+            .await
+            .unwrap();
+        ids.push(record.id);
+    }
+
+    // 目录整体重命名只有一个事件
+    std::fs::rename(root.join("photos"), root.join("album")).unwrap();
+    handler
+        .handle(FileEvent::Renamed {
+            from: root.join("photos"),
+            to: root.join("album"),
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        repository.get_file_by_path("album/a.jpg").await.unwrap().id,
+        ids[0]
+    );
+    assert_eq!(
+        repository.get_file_by_path("album/b.jpg").await.unwrap().id,
+        ids[1]
+    );
+
+    // 没有记录 inode 的旧记录也能按 rename 的源路径找回
+    std::fs::rename(root.join("album/a.jpg"), root.join("album/cover.jpg")).unwrap();
+    handler
+        .handle(FileEvent::Renamed {
+            from: root.join("album/a.jpg"),
+            to: root.join("album/cover.jpg"),
+        })
+        .await
+        .unwrap();
+    let cover = repository
+        .get_file_by_path("album/cover.jpg")
+        .await
+        .unwrap();
+    assert_eq!(cover.id, ids[0]);
+    assert!(cover.fs_id.is_some());
+    assert!(repository.get_file_by_path("album/a.jpg").await.is_err());
+
+    // 对象目录和数据库文件不是用户文件
+    handler
+        .handle(FileEvent::Created(root.join("db.json")))
+        .await
+        .unwrap();
+    assert_eq!(repository.list_files().await.unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn test_api_file_metadata_and_search() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = make_config(&temp_dir);
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+
+    let db_path = config.storage_path.join("db.json");
+    let repository = Arc::new(Repository::new(db_path).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+
+    let app = rustcloud::api::create_router_with_services(config, repository, storage).await;
+
+    let send = |method: &'static str, uri: String, body: axum::body::Body| {
+        let app = app.clone();
+        async move {
+            let response = app
+                .oneshot(
+                    axum::http::Request::builder()
+                        .method(method)
+                        .uri(uri)
+                        .header("content-type", "application/json")
+                        .body(body)
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            (
+                status,
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            )
+        }
+    };
+
+    for path in ["docs/a.txt", "docs/b.txt"] {
+        let (status, _) = send(
+            "PUT",
+            format!("/api/files/{}", path),
+            axum::body::Body::from("content"),
+        )
+        .await;
+        assert_eq!(status, axum::http::StatusCode::OK);
+    }
+
+    let (status, resp) = send(
+        "PATCH",
+        "/api/metadata/docs/a.txt".to_string(),
+        axum::body::Body::from(r#"{"project":"apollo","status":"draft"}"#),
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    assert_eq!(resp["data"]["metadata"]["project"], "apollo");
+
+    // 不存在的文件返回 404
+    let (status, _) = send(
+        "PATCH",
+        "/api/metadata/docs/missing.txt".to_string(),
+        axum::body::Body::from(r#"{"project":"apollo"}"#),
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::NOT_FOUND);
+
+    let (status, _) = send(
+        "PATCH",
+        "/api/metadata/docs/a.txt".to_string(),
+        axum::body::Body::from(r#"{"":"empty key"}"#),
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::BAD_REQUEST);
+
+    let (_, resp) = send(
+        "GET",
+        "/api/files?path=docs".to_string(),
+        axum::body::Body::empty(),
+    )
+    .await;
+    let files = resp["data"].as_array().unwrap();
+    let a = files.iter().find(|f| f["name"] == "a.txt").unwrap();
+    assert_eq!(a["metadata"]["status"], "draft");
+    let b = files.iter().find(|f| f["name"] == "b.txt").unwrap();
+    assert!(b.get("metadata").is_none());
+
+    let (status, resp) = send(
+        "GET",
+        "/api/search?q=docs&meta_key=project&meta_value=apollo".to_string(),
+        axum::body::Body::empty(),
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    assert_eq!(resp["data"]["total"], 1);
+    assert_eq!(resp["data"]["files"][0]["path"], "docs/a.txt");
+
+    let (_, resp) = send(
+        "GET",
+        "/api/search?q=DOCS".to_string(),
+        axum::body::Body::empty(),
+    )
+    .await;
+    assert_eq!(resp["data"]["total"], 2);
+
+    // null 删除键
+    let (_, resp) = send(
+        "PATCH",
+        "/api/metadata/docs/a.txt".to_string(),
+        axum::body::Body::from(r#"{"status":null}"#),
+    )
+    .await;
+    assert!(resp["data"]["metadata"].get("status").is_none());
+    assert_eq!(resp["data"]["metadata"]["project"], "apollo");
+}
+
+#[tokio::test]
+async fn test_notification_threshold_matching() {
+    use rustcloud::db::{NotificationChannel, NotificationEvent, NotificationRule};
+    use rustcloud::service::notify::Notification;
+
+    let rule = NotificationRule {
+        event: NotificationEvent::StorageNearlyFull,
+        channel: NotificationChannel::Webhook {
+            url: "http://127.0.0.1/hook".to_string(),
+        },
+        threshold: Some(1000),
+        path: None,
+        enabled: true,
+    };
+
+    // 只在越过阈值时触发
+    let crossing = Notification::StorageNearlyFull {
+        used_before: 900,
+        used_after: 1100,
+    };
+    let already_over = Notification::StorageNearlyFull {
+        used_before: 1100,
+        used_after: 1200,
+    };
+    assert!(crossing.matches(&rule));
+    assert!(!already_over.matches(&rule));
+
+    let disabled = NotificationRule {
+        enabled: false,
+        ..rule.clone()
+    };
+    assert!(!crossing.matches(&disabled));
+
+    let sync_rule = NotificationRule {
+        event: NotificationEvent::SyncFailures,
+        threshold: Some(3),
+        ..rule
+    };
+    let failures = |failures| Notification::SyncFailures {
+        device_name: "laptop".to_string(),
+        failures,
+    };
+    assert!(!failures(2).matches(&sync_rule));
+    assert!(failures(3).matches(&sync_rule));
+    assert!(!failures(4).matches(&sync_rule));
+}
+
+#[tokio::test]
+async fn test_api_notification_webhook_on_device_registered() {
+    // 本地 webhook 接收端
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<serde_json::Value>();
+    let receiver = axum::Router::new().route(
+        "/hook",
+        axum::routing::post(move |axum::Json(body): axum::Json<serde_json::Value>| {
+            let tx = tx.clone();
+            async move {
+                tx.send(body).unwrap();
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let hook_url = format!("http://{}/hook", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, receiver).await.unwrap() });
+
+    let temp_dir = TempDir::new().unwrap();
+    let config = make_config(&temp_dir);
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+
+    let db_path = config.storage_path.join("db.json");
+    let repository = Arc::new(Repository::new(db_path).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+
+    let app = rustcloud::api::create_router_with_services(config, repository, storage).await;
+
+    let send = |method: &'static str, uri: &'static str, body: String| {
+        let app = app.clone();
+        async move {
+            let response = app
+                .oneshot(
+                    axum::http::Request::builder()
+                        .method(method)
+                        .uri(uri)
+                        .header("content-type", "application/json")
+                        .body(axum::body::Body::from(body))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            response.status()
+        }
+    };
+
+    // 没有配置 SMTP 时不能订阅邮件
+    let status = send(
+        "PUT",
+        "/api/notifications/preferences/alice",
+        r#"{"rules":[{"event":"device_registered","channel":{"type":"email","to":"alice@example.com"}}]}"#.to_string(),
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::BAD_REQUEST);
+
+    let status = send(
+        "PUT",
+        "/api/notifications/preferences/alice",
+        r#"{"rules":[{"event":"storage_nearly_full","channel":{"type":"webhook","url":"http://127.0.0.1/x"}}]}"#.to_string(),
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::BAD_REQUEST);
+
+    let status = send(
+        "PUT",
+        "/api/notifications/preferences/alice",
+        format!(
+            r#"{{"rules":[{{"event":"device_registered","channel":{{"type":"webhook","url":"{}"}}}}]}}"#,
+            hook_url
+        ),
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+
+    let status = send("POST", "/api/devices", r#"{"name":"laptop"}"#.to_string()).await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+
+    let payload = tokio::time::timeout(std::time::Duration::from_secs(5), rx.recv())
+        .await
+        .expect("webhook was not delivered")
+        .unwrap();
+    assert_eq!(payload["event"], "device_registered");
+    assert!(payload["subject"].as_str().unwrap().contains("laptop"));
+}
+
+#[tokio::test]
+async fn test_api_share_access_stats() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = make_config(&temp_dir);
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+
+    let db_path = config.storage_path.join("db.json");
+    let repository = Arc::new(Repository::new(db_path).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+
+    let app = rustcloud::api::create_router_with_services(config, repository, storage).await;
+
+    let send = |request: axum::http::Request<axum::body::Body>| {
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            (status, body)
+        }
+    };
+    let json = |method: &str, uri: &str, body: &str| {
+        axum::http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(axum::body::Body::from(body.to_string()))
+            .unwrap()
+    };
+
+    let (status, _) = send(json("PUT", "/api/files/report.pdf", "0123456789")).await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+
+    let (status, _) = send(json("POST", "/api/shares", r#"{"path":"missing.pdf"}"#)).await;
+    assert_eq!(status, axum::http::StatusCode::NOT_FOUND);
+
+    let (status, body) = send(json("POST", "/api/shares", r#"{"path":"report.pdf"}"#)).await;
+    assert_eq!(status, axum::http::StatusCode::CREATED);
+    let share: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let id = share["data"]["id"].as_str().unwrap().to_string();
+
+    let (status, body) = send(
+        axum::http::Request::builder()
+            .uri(format!("/api/shares/{}/download", id))
+            .header("x-forwarded-for", "203.0.113.7, 10.0.0.1")
+            .body(axum::body::Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    assert_eq!(&body[..], b"0123456789");
+
+    // Range 请求只计实际返回的字节
+    let (status, body) = send(
+        axum::http::Request::builder()
+            .uri(format!("/api/shares/{}/download", id))
+            .header("x-forwarded-for", "198.51.100.2")
+            .header("range", "bytes=0-3")
+            .body(axum::body::Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::PARTIAL_CONTENT);
+    assert_eq!(&body[..], b"0123");
+
+    let (status, body) = send(json("GET", &format!("/api/shares/{}/stats", id), "")).await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    let stats: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(stats["data"]["access_count"], 2);
+    assert_eq!(stats["data"]["bytes_served"], 14);
+    assert_eq!(stats["data"]["unique_ips"], 2);
+    assert_eq!(stats["data"]["recent"][0]["ip"], "198.51.100.2");
+
+    // 撤销后链接失效
+    let (status, _) = send(json("DELETE", &format!("/api/shares/{}", id), "")).await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    let (status, _) = send(json("GET", &format!("/api/shares/{}/download", id), "")).await;
+    assert_eq!(status, axum::http::StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_api_upload_reputation_policy() {
+    use rustcloud::config::{ReputationConfig, ReputationPolicy};
+
+    let temp_dir = TempDir::new().unwrap();
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: temp_dir.path().join("storage"),
+        chunk_size: 1024,
+    }));
+
+    // 本地信誉服务：只认识一个恶意哈希和一个干净哈希
+    let bad_hash = storage.compute_content_hash(b"known bad payload");
+    let good_hash = storage.compute_content_hash(b"known good payload");
+    let lookup = axum::Router::new().route(
+        "/lookup/{hash}",
+        axum::routing::get(
+            move |axum::extract::Path(hash): axum::extract::Path<String>| {
+                let (bad, good) = (bad_hash.clone(), good_hash.clone());
+                async move {
+                    if hash == bad {
+                        Ok(axum::Json(serde_json::json!({"verdict": "malicious"})))
+                    } else if hash == good {
+                        Ok(axum::Json(serde_json::json!({"verdict": "clean"})))
+                    } else {
+                        Err(axum::http::StatusCode::NOT_FOUND)
+                    }
+                }
+            },
+        ),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let lookup_url = format!("http://{}/lookup/{{hash}}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, lookup).await.unwrap() });
+
+    let upload = |app: axum::Router, path: &'static str, body: &'static str| async move {
+        let response = app
+            .oneshot(
+                axum::http::Request::builder()
+                    .method("PUT")
+                    .uri(format!("/api/files/{}", path))
+                    .body(axum::body::Body::from(body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (
+            status,
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+        )
+    };
+
+    for (policy, dir) in [
+        (ReputationPolicy::Block, "block"),
+        (ReputationPolicy::Flag, "flag"),
+    ] {
+        let mut config = make_config(&temp_dir);
+        config.storage_path = temp_dir.path().join(dir);
+        config.reputation = Some(ReputationConfig {
+            url: lookup_url.clone(),
+            api_key: None,
+            policy,
+        });
+        std::fs::create_dir_all(&config.storage_path).unwrap();
+        let repository = Arc::new(
+            Repository::new(config.storage_path.join("db.json"))
+                .await
+                .unwrap(),
+        );
+        let storage = Arc::new(StorageService::new(StorageConfig {
+            storage_path: config.storage_path.clone(),
+            chunk_size: 1024,
+        }));
+        let app =
+            rustcloud::api::create_router_with_services(config, repository.clone(), storage).await;
+
+        let (status, resp) = upload(app.clone(), "good.bin", "known good payload").await;
+        assert_eq!(status, axum::http::StatusCode::OK);
+        assert_eq!(resp["data"]["metadata"]["reputation.verdict"], "clean");
+
+        let (status, resp) = upload(app.clone(), "other.bin", "never seen").await;
+        assert_eq!(status, axum::http::StatusCode::OK);
+        assert_eq!(resp["data"]["metadata"]["reputation.verdict"], "unknown");
+
+        let (status, resp) = upload(app.clone(), "bad.bin", "known bad payload").await;
+        match policy {
+            ReputationPolicy::Block => {
+                assert_eq!(status, axum::http::StatusCode::FORBIDDEN);
+                assert!(repository.get_file_by_path("bad.bin").await.is_err());
+            }
+            ReputationPolicy::Flag => {
+                assert_eq!(status, axum::http::StatusCode::OK);
+                assert_eq!(resp["data"]["metadata"]["reputation.verdict"], "malicious");
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_api_legal_hold_blocks_changes() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = make_config(&temp_dir);
+    config.admin_token = Some("secret".to_string());
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+
+    let db_path = config.storage_path.join("db.json");
+    let repository = Arc::new(Repository::new(db_path).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+
+    let app =
+        rustcloud::api::create_router_with_services(config, repository.clone(), storage).await;
+
+    let send = |method: &'static str,
+                uri: &'static str,
+                body: &'static str,
+                token: Option<&'static str>| {
+        let app = app.clone();
+        async move {
+            let mut request = axum::http::Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("content-type", "application/json");
+            if let Some(token) = token {
+                request = request.header("x-admin-token", token);
+            }
+            app.oneshot(request.body(axum::body::Body::from(body)).unwrap())
+                .await
+                .unwrap()
+                .status()
+        }
+    };
+
+    assert_eq!(
+        send("PUT", "/api/files/legal/contract.pdf", "v1", None).await,
+        axum::http::StatusCode::OK
+    );
+    assert_eq!(
+        send("PUT", "/api/files/notes.txt", "v1", None).await,
+        axum::http::StatusCode::OK
+    );
+    assert_eq!(
+        send(
+            "POST",
+            "/api/holds",
+            r#"{"path":"legal","reason":"case 42"}"#,
+            None
+        )
+        .await,
+        axum::http::StatusCode::CREATED
+    );
+
+    // 保留范围内的修改、新增、删除都被拒绝
+    assert_eq!(
+        send("PUT", "/api/files/legal/contract.pdf", "v2", None).await,
+        axum::http::StatusCode::LOCKED
+    );
+    assert_eq!(
+        send("PUT", "/api/files/legal/new.pdf", "new", None).await,
+        axum::http::StatusCode::LOCKED
+    );
+    assert_eq!(
+        send("DELETE", "/api/files/legal", "", None).await,
+        axum::http::StatusCode::LOCKED
+    );
+    let record = repository
+        .get_file_by_path("legal/contract.pdf")
+        .await
+        .unwrap();
+    assert!(repository.delete_file(record.id).await.is_err());
+    assert_eq!(record.version, 1);
+
+    // 范围外不受影响
+    assert_eq!(
+        send("PUT", "/api/files/notes.txt", "v2", None).await,
+        axum::http::StatusCode::OK
+    );
+
+    // 解除保留需要管理员令牌
+    assert_eq!(
+        send("DELETE", "/api/holds/legal", "", None).await,
+        axum::http::StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        send("DELETE", "/api/holds/legal", "", Some("wrong")).await,
+        axum::http::StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        send("DELETE", "/api/holds/legal", "", Some("secret")).await,
+        axum::http::StatusCode::OK
+    );
+    assert_eq!(
+        send("DELETE", "/api/files/legal?recursive=true", "", None).await,
+        axum::http::StatusCode::OK
+    );
+}
+
+#[tokio::test]
+async fn test_lifecycle_plan_and_run() {
+    use rustcloud::db::NewLifecycleRule;
+    use rustcloud::service::lifecycle::{LifecycleAction, LifecycleService};
+
+    let (temp_dir, repository, _storage) = setup().await;
+    let storage_path = temp_dir.path().join("storage");
+
+    for path in [
+        "Downloads/setup.exe",
+        "Camera/2024/img.jpg",
+        "Camera/keep.jpg",
+        "Archive/Camera/old.jpg",
+        "Documents/cv.pdf",
+    ] {
+        repository
+            .create_file(NewFileRecord {
+                path: path.to_string(),
+                hash: None,
+                size: 1,
+            })
+            .await
+            .unwrap();
+        let file = storage_path.join(path);
+        std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+        std::fs::write(&file, "x").unwrap();
+    }
+    repository
+        .add_legal_hold("Camera/keep.jpg", None)
+        .await
+        .unwrap();
+
+    repository
+        .create_lifecycle_rule(NewLifecycleRule {
+            folder: "Downloads".to_string(),
+            delete_after_days: Some(30),
+            archive_after_days: None,
+            archive_to: None,
+            owner: None,
+        })
+        .await
+        .unwrap();
+    repository
+        .create_lifecycle_rule(NewLifecycleRule {
+            folder: "Camera".to_string(),
+            delete_after_days: None,
+            archive_after_days: Some(10),
+            archive_to: Some("Archive/Camera".to_string()),
+            owner: None,
+        })
+        .await
+        .unwrap();
+
+    let service = LifecycleService::new((*repository).clone(), storage_path.clone());
+
+    // 规则尚未到期
+    let now = chrono::Utc::now();
+    assert!(service.plan(now).await.unwrap().is_empty());
+
+    let later = now + chrono::Duration::days(40);
+    let plan = service.plan(later).await.unwrap();
+    let summary: Vec<(&str, &LifecycleAction)> =
+        plan.iter().map(|a| (a.path.as_str(), &a.action)).collect();
+    assert_eq!(
+        summary,
+        vec![
+            (
+                "Camera/2024/img.jpg",
+                &LifecycleAction::Archive {
+                    destination: "Archive/Camera/2024/img.jpg".to_string()
+                }
+            ),
+            ("Downloads/setup.exe", &LifecycleAction::Delete),
+        ]
+    );
+
+    // 预览不修改任何内容
+    assert!(repository
+        .get_file_by_path("Downloads/setup.exe")
+        .await
+        .is_ok());
+
+    let report = service.run(later).await.unwrap();
+    assert_eq!((report.applied, report.failed), (2, 0));
+    assert!(repository
+        .get_file_by_path("Downloads/setup.exe")
+        .await
+        .is_err());
+    assert!(!storage_path.join("Downloads/setup.exe").exists());
+    assert!(repository
+        .get_file_by_path("Archive/Camera/2024/img.jpg")
+        .await
+        .is_ok());
+    assert!(storage_path.join("Archive/Camera/2024/img.jpg").is_file());
+    assert!(repository.get_file_by_path("Camera/keep.jpg").await.is_ok());
+}
+
+#[tokio::test]
+async fn test_access_tracker_batches_updates() {
+    use rustcloud::service::access::AccessTracker;
+
+    let (_temp_dir, repository, _storage) = setup().await;
+    let record = repository
+        .create_file(NewFileRecord {
+            path: "a.txt".to_string(),
+            hash: None,
+            size: 1,
+        })
+        .await
+        .unwrap();
+    assert!(record.last_accessed_at.is_none());
+
+    let tracker = AccessTracker::new((*repository).clone());
+    let t0 = chrono::Utc::now();
+    let t1 = t0 + chrono::Duration::minutes(5);
+    // 同一周期内的多次访问合并为一次写入，保留最新时间
+    tracker.touch_at("a.txt", t1);
+    tracker.touch_at("a.txt", t0);
+    tracker.touch_at("untracked.txt", t0);
+    assert_eq!(tracker.flush().await.unwrap(), 1);
+    assert_eq!(tracker.flush().await.unwrap(), 0);
+
+    let record = repository.get_file_by_path("a.txt").await.unwrap();
+    assert_eq!(record.last_accessed_at, Some(t1));
+    assert_eq!(record.last_used_at(), t1);
+
+    // 更早的访问不会让时间倒退
+    tracker.touch_at("a.txt", t0);
+    assert_eq!(tracker.flush().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_api_cold_report_uses_access_times() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = make_config(&temp_dir);
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+
+    let db_path = config.storage_path.join("db.json");
+    let repository = Arc::new(Repository::new(db_path).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+
+    let app =
+        rustcloud::api::create_router_with_services(config, repository.clone(), storage).await;
+
+    let send = |method: &'static str, uri: &'static str, body: &'static str| {
+        let app = app.clone();
+        async move {
+            let response = app
+                .oneshot(
+                    axum::http::Request::builder()
+                        .method(method)
+                        .uri(uri)
+                        .body(axum::body::Body::from(body))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            (status, body)
+        }
+    };
+
+    send("PUT", "/api/files/read.txt", "read me").await;
+    send("PUT", "/api/files/unread.txt", "ignore me").await;
+    let (status, _) = send("GET", "/api/stream/read.txt", "").await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+
+    let (status, body) = send("GET", "/api/reports/cold?days=0", "").await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(report["data"]["total_files"], 2);
+    assert_eq!(report["data"]["total_bytes"], 16);
+
+    // 报告前会把内存中的访问记录落盘
+    let read = repository.get_file_by_path("read.txt").await.unwrap();
+    let unread = repository.get_file_by_path("unread.txt").await.unwrap();
+    assert!(read.last_accessed_at.is_some());
+    assert!(unread.last_accessed_at.is_none());
+}
+
+#[tokio::test]
+async fn test_api_duplicates_report() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = make_config(&temp_dir);
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+
+    let db_path = config.storage_path.join("db.json");
+    let repository = Arc::new(Repository::new(db_path).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+
+    let app = rustcloud::api::create_router_with_services(config, repository, storage).await;
+
+    let send = |method: &'static str, uri: String, body: &'static str| {
+        let app = app.clone();
+        async move {
+            let response = app
+                .oneshot(
+                    axum::http::Request::builder()
+                        .method(method)
+                        .uri(uri)
+                        .body(axum::body::Body::from(body))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        }
+    };
+
+    for (path, content) in [
+        ("a/photo.jpg", "big duplicated content"),
+        ("b/photo-copy.jpg", "big duplicated content"),
+        ("c/photo-2.jpg", "big duplicated content"),
+        ("notes.txt", "tiny"),
+        ("notes-copy.txt", "tiny"),
+        ("unique.txt", "only one of these"),
+    ] {
+        send("PUT", format!("/api/files/{}", path), content).await;
+    }
+
+    let report = send("GET", "/api/reports/duplicates".to_string(), "").await;
+    let data = &report["data"];
+    assert_eq!(data["total_groups"], 2);
+    assert_eq!(data["reclaimable_bytes"], 22 * 2 + 4);
+    // 可节省空间多的组排在前面
+    assert_eq!(
+        data["groups"][0]["paths"],
+        serde_json::json!(["a/photo.jpg", "b/photo-copy.jpg", "c/photo-2.jpg"])
+    );
+    assert_eq!(data["groups"][0]["reclaimable_bytes"], 44);
+
+    let report = send("GET", "/api/reports/duplicates?min_size=10".to_string(), "").await;
+    assert_eq!(report["data"]["total_groups"], 1);
+}
+
+#[tokio::test]
+async fn test_api_largest_and_stale_reports() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = make_config(&temp_dir);
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+
+    let db_path = config.storage_path.join("db.json");
+    let repository = Arc::new(Repository::new(db_path).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+
+    let app = rustcloud::api::create_router_with_services(config, repository, storage).await;
+
+    let send = |method: &'static str, uri: &'static str, body: &'static str| {
+        let app = app.clone();
+        async move {
+            let response = app
+                .oneshot(
+                    axum::http::Request::builder()
+                        .method(method)
+                        .uri(uri)
+                        .body(axum::body::Body::from(body))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        }
+    };
+
+    send("PUT", "/api/files/videos/2024/trip.mp4", "0123456789").await;
+    send("PUT", "/api/files/videos/clip.mp4", "01234567").await;
+    send("PUT", "/api/files/docs/a.txt", "012").await;
+    send("PUT", "/api/files/readme.md", "01").await;
+
+    let report = send("GET", "/api/reports/largest?top=2", "").await;
+    let data = &report["data"];
+    assert_eq!(data["total_files"], 4);
+    assert_eq!(data["total_bytes"], 23);
+    assert_eq!(data["files"].as_array().unwrap().len(), 2);
+    assert_eq!(data["files"][0]["path"], "videos/2024/trip.mp4");
+    assert_eq!(data["files"][1]["path"], "videos/clip.mp4");
+    assert_eq!(data["folders"][0]["folder"], "videos");
+    assert_eq!(data["folders"][0]["files"], 2);
+    assert_eq!(data["folders"][0]["bytes"], 18);
+    assert_eq!(data["folders"][2]["folder"], "/");
+
+    let report = send("GET", "/api/reports/largest?depth=2", "").await;
+    let folders: Vec<&str> = report["data"]["folders"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|f| f["folder"].as_str().unwrap())
+        .collect();
+    assert_eq!(folders, vec!["videos/2024", "videos", "docs", "/"]);
+
+    // 刚写入的文件不算陈旧
+    let report = send("GET", "/api/reports/stale?months=1", "").await;
+    assert_eq!(report["data"]["total_files"], 0);
+    let report = send("GET", "/api/reports/stale?months=0", "").await;
+    assert_eq!(report["data"]["total_files"], 4);
+}
+
+#[test]
+fn test_rate_class_matching() {
+    use rustcloud::db::{NewRateClass, RateClass};
+
+    let device = uuid::Uuid::new_v4();
+    let class = |device_id, start_hour, end_hour| {
+        RateClass::new(
+            NewRateClass {
+                name: "work".to_string(),
+                device_id,
+                start_hour,
+                end_hour,
+                upload_bytes_per_sec: Some(1),
+                download_bytes_per_sec: None,
+            },
+            chrono::Utc::now(),
+        )
+    };
+
+    let always = class(None, None, None);
+    assert!(always.applies_to(None, 3));
+    assert!(always.applies_to(Some(device), 23));
+
+    let work_hours = class(None, Some(9), Some(17));
+    assert!(work_hours.applies_to(None, 9));
+    assert!(work_hours.applies_to(None, 16));
+    assert!(!work_hours.applies_to(None, 17));
+    assert!(!work_hours.applies_to(None, 8));
+
+    // 跨越午夜的时段
+    let night = class(None, Some(22), Some(6));
+    assert!(night.applies_to(None, 23));
+    assert!(night.applies_to(None, 2));
+    assert!(!night.applies_to(None, 12));
+
+    let laptop = class(Some(device), None, None);
+    assert!(laptop.applies_to(Some(device), 12));
+    assert!(!laptop.applies_to(Some(uuid::Uuid::new_v4()), 12));
+    assert!(!laptop.applies_to(None, 12));
+}
+
+#[tokio::test]
+async fn test_api_bandwidth_class_throttles_downloads() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = make_config(&temp_dir);
+    config.admin_token = Some("secret".to_string());
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+
+    let db_path = config.storage_path.join("db.json");
+    let repository = Arc::new(Repository::new(db_path).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+
+    let app = rustcloud::api::create_router_with_services(config, repository, storage).await;
+
+    let send = |method: &'static str, uri: &'static str, device: Option<String>, body: String| {
+        let app = app.clone();
+        async move {
+            let mut request = axum::http::Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("content-type", "application/json");
+            if let Some(device) = device {
+                request = request.header("x-device-id", device);
+            }
+            if method != "GET" {
+                request = request.header("x-admin-token", "secret");
+            }
+            let response = app
+                .oneshot(request.body(axum::body::Body::from(body)).unwrap())
+                .await
+                .unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            (status, body)
+        }
+    };
+
+    let content = "x".repeat(5000);
+    send("PUT", "/api/files/big.bin", None, content.clone()).await;
+
+    let (status, _) = send(
+        "POST",
+        "/api/bandwidth/classes",
+        None,
+        r#"{"name":"bad","download_bytes_per_sec":0}"#.to_string(),
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::BAD_REQUEST);
+
+    // 客户端不能自己增删速率等级
+    let response = app
+        .clone()
+        .oneshot(
+            axum::http::Request::builder()
+                .method("POST")
+                .uri("/api/bandwidth/classes")
+                .header("content-type", "application/json")
+                .body(axum::body::Body::from(
+                    r#"{"name":"mine","download_bytes_per_sec":100}"#,
+                ))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::FORBIDDEN);
+
+    // 只限制另一台设备，不影响未声明设备的请求
+    let other = uuid::Uuid::new_v4();
+    let body = serde_json::json!({
+        "name": "other-device",
+        "device_id": other,
+        "download_bytes_per_sec": 100,
+    });
+    let (status, _) = send("POST", "/api/bandwidth/classes", None, body.to_string()).await;
+    assert_eq!(status, axum::http::StatusCode::CREATED);
+
+    let started = std::time::Instant::now();
+    let (status, body) = send("GET", "/api/stream/big.bin", None, String::new()).await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    assert_eq!(body.len(), 5000);
+    assert!(started.elapsed() < std::time::Duration::from_secs(1));
+
+    let body = r#"{"name":"everyone","download_bytes_per_sec":2000}"#.to_string();
+    let (status, _) = send("POST", "/api/bandwidth/classes", None, body).await;
+    assert_eq!(status, axum::http::StatusCode::CREATED);
+
+    // 桶里只有一秒的额度，剩余部分按 2000 B/s 发送
+    let started = std::time::Instant::now();
+    let (status, body) = send("GET", "/api/stream/big.bin", None, String::new()).await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    assert_eq!(&body[..], content.as_bytes());
+    assert!(started.elapsed() >= std::time::Duration::from_secs(1));
+
+    let (_, body) = send("GET", "/api/bandwidth/classes", None, String::new()).await;
+    let classes: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(classes["data"].as_array().unwrap().len(), 2);
+
+    let delete = format!(
+        "/api/bandwidth/classes/{}",
+        classes["data"][1]["id"].as_str().unwrap()
+    );
+    let response = app
+        .clone()
+        .oneshot(
+            axum::http::Request::builder()
+                .method("DELETE")
+                .uri(&delete)
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::FORBIDDEN);
+}
+
+/// 收集格式化后的日志，供断言使用
+#[derive(Clone, Default)]
+struct CapturedLogs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl std::io::Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl CapturedLogs {
+    fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+#[tokio::test]
+async fn test_api_request_span_carries_device_identity() {
+    let logs = CapturedLogs::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(move || writer.clone())
+        .with_ansi(false)
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let temp_dir = TempDir::new().unwrap();
+    let config = make_config(&temp_dir);
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+
+    let db_path = config.storage_path.join("db.json");
+    let repository = Arc::new(Repository::new(db_path).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+    let device = repository
+        .create_device(rustcloud::db::NewDeviceRecord {
+            name: "work-laptop".to_string(),
+        })
+        .await
+        .unwrap();
+
+    let app = rustcloud::api::create_router_with_services(config, repository, storage).await;
+
+    let upload = |device_id: String, path: &'static str| {
+        app.clone().oneshot(
+            axum::http::Request::builder()
+                .method("PUT")
+                .uri(path)
+                .header("x-device-id", device_id)
+                .body(axum::body::Body::from("hello"))
+                .unwrap(),
+        )
+    };
+
+    let response = upload(device.id.to_string(), "/api/files/from-laptop.txt")
+        .await
+        .unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::OK);
+
+    let output = logs.contents();
+    let line = output
+        .lines()
+        .find(|l| l.contains("/api/files/from-laptop.txt") && l.contains("request completed"))
+        .unwrap();
+    assert!(line.contains(&format!("device_id={}", device.id)));
+    assert!(line.contains("device=work-laptop"));
+    assert!(line.contains("status=200"));
+
+    // 未注册的设备 id 不会出现在日志中
+    let stranger = uuid::Uuid::new_v4();
+    upload(stranger.to_string(), "/api/files/anonymous.txt")
+        .await
+        .unwrap();
+    let output = logs.contents();
+    let line = output
+        .lines()
+        .find(|l| l.contains("/api/files/anonymous.txt") && l.contains("request completed"))
+        .unwrap();
+    assert!(!line.contains("device_id="));
+    assert!(!output.contains(&stranger.to_string()));
+}
+
+#[tokio::test]
+async fn test_api_upload_on_conflict_modes() {
+    let (temp_dir, repository, storage) = setup().await;
+    let config = make_config(&temp_dir);
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+    let app =
+        rustcloud::api::create_router_with_services(config, repository.clone(), storage).await;
+
+    let upload = |uri: &'static str, body: &'static str| {
+        let app = app.clone();
+        async move {
+            let response = app
+                .oneshot(
+                    axum::http::Request::builder()
+                        .method("PUT")
+                        .uri(uri)
+                        .body(axum::body::Body::from(body))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let json: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+            (status, json)
+        }
+    };
+
+    let (status, _) = upload("/api/files/docs/report.pdf", "v1").await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+
+    let (status, json) = upload("/api/files/docs/report.pdf?on_conflict=fail", "v2").await;
+    assert_eq!(status, axum::http::StatusCode::CONFLICT);
+    assert_eq!(json["success"], false);
+
+    let (status, json) = upload("/api/files/docs/report.pdf?on_conflict=rename", "v2").await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    assert_eq!(json["data"]["path"], "docs/report (1).pdf");
+
+    let (_, json) = upload("/api/files/docs/report.pdf?on_conflict=rename", "v3").await;
+    assert_eq!(json["data"]["path"], "docs/report (2).pdf");
+
+    // 隐藏文件和无扩展名文件
+    upload("/api/files/.bashrc", "a").await;
+    let (_, json) = upload("/api/files/.bashrc?on_conflict=rename", "b").await;
+    assert_eq!(json["data"]["path"], ".bashrc (1)");
+
+    // 默认覆盖，原文件未被 fail/rename 改动
+    let original = repository
+        .get_file_by_path("docs/report.pdf")
+        .await
+        .unwrap();
+    assert_eq!(original.version, 1);
+    let (status, json) = upload("/api/files/docs/report.pdf", "v4").await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    assert_eq!(json["data"]["version"], 2);
+
+    // 路径不存在时 fail 正常写入
+    let (status, _) = upload("/api/files/new.txt?on_conflict=fail", "new").await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+
+    let (status, _) = upload("/api/files/new.txt?on_conflict=skip", "new").await;
+    assert_eq!(status, axum::http::StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_api_delete_directory_requires_recursive() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = make_config(&temp_dir);
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+
+    let db_path = config.storage_path.join("db.json");
+    let repository = Arc::new(Repository::new(db_path).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+
+    let app =
+        rustcloud::api::create_router_with_services(config, repository.clone(), storage).await;
+
+    let send = |method: &'static str, uri: &'static str, body: &'static str| {
+        let app = app.clone();
+        async move {
+            let response = app
+                .oneshot(
+                    axum::http::Request::builder()
+                        .method(method)
+                        .uri(uri)
+                        .header("content-type", "application/json")
+                        .body(axum::body::Body::from(body))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let json: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+            (status, json)
+        }
+    };
+
+    send("PUT", "/api/files/photos/a.jpg", "12345").await;
+    send("PUT", "/api/files/photos/2024/b.jpg", "123").await;
+    send("PUT", "/api/files/notes.txt", "hi").await;
+
+    // 非空目录缺少 recursive 时拒绝，并告知会删除多少内容
+    let (status, json) = send("DELETE", "/api/files/photos", "").await;
+    assert_eq!(status, axum::http::StatusCode::CONFLICT);
+    assert_eq!(json["data"]["files"], 2);
+    assert_eq!(json["data"]["bytes"], 8);
+    assert!(repository.get_file_by_path("photos/a.jpg").await.is_ok());
+
+    let (status, json) = send("DELETE", "/api/files/photos?recursive=true", "").await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    assert_eq!(json["data"]["files"], 2);
+    assert_eq!(json["data"]["bytes"], 8);
+    assert!(repository
+        .get_file_by_path("photos/2024/b.jpg")
+        .await
+        .is_err());
+
+    // 单个文件和空目录不需要 recursive
+    let (status, json) = send("DELETE", "/api/files/notes.txt", "").await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    assert_eq!(json["data"]["files"], 1);
+    assert_eq!(json["data"]["bytes"], 2);
+
+    send("POST", "/api/files", r#"{"path":"empty"}"#).await;
+    let (status, json) = send("DELETE", "/api/files/empty", "").await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    assert_eq!(json["data"]["files"], 0);
+}
+
+#[tokio::test]
+async fn test_api_share_link_quotas() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = make_config(&temp_dir);
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+
+    let db_path = config.storage_path.join("db.json");
+    let repository = Arc::new(Repository::new(db_path).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+
+    let app = rustcloud::api::create_router_with_services(config, repository, storage).await;
+
+    let request = |method: &str, uri: String, body: String| {
+        axum::http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(axum::body::Body::from(body))
+            .unwrap()
+    };
+    let create_share = |limits: serde_json::Value| {
+        let app = app.clone();
+        let mut body = serde_json::json!({ "path": "clip.mp4" });
+        body.as_object_mut()
+            .unwrap()
+            .extend(limits.as_object().unwrap().clone());
+        let request = request("POST", "/api/shares".to_string(), body.to_string());
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            (
+                status,
+                json["data"]["id"].as_str().unwrap_or_default().to_string(),
+            )
+        }
+    };
+    let download = |id: &str| {
+        app.clone().oneshot(request(
+            "GET",
+            format!("/api/shares/{}/download", id),
+            String::new(),
+        ))
+    };
+
+    app.clone()
+        .oneshot(request(
+            "PUT",
+            "/api/files/clip.mp4".to_string(),
+            "1234".to_string(),
+        ))
+        .await
+        .unwrap();
+
+    let (status, _) = create_share(serde_json::json!({ "max_downloads": 0 })).await;
+    assert_eq!(status, axum::http::StatusCode::BAD_REQUEST);
+
+    // 下载次数上限
+    let (status, id) = create_share(serde_json::json!({ "max_downloads": 2 })).await;
+    assert_eq!(status, axum::http::StatusCode::CREATED);
+    for _ in 0..2 {
+        let response = download(&id).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        response.into_body().collect().await.unwrap();
+    }
+    let response = download(&id).await.unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::GONE);
+
+    // 流量上限：检查在下载开始前，最后一次可以略微超出
+    let (_, id) = create_share(serde_json::json!({ "max_bytes": 5 })).await;
+    for _ in 0..2 {
+        let response = download(&id).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        response.into_body().collect().await.unwrap();
+    }
+    let response = download(&id).await.unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::GONE);
+
+    // 并发上限：响应体未读完之前一直占用名额
+    let (_, id) = create_share(serde_json::json!({ "max_concurrent": 1 })).await;
+    let first = download(&id).await.unwrap();
+    assert_eq!(first.status(), axum::http::StatusCode::OK);
+    let second = download(&id).await.unwrap();
+    assert_eq!(second.status(), axum::http::StatusCode::TOO_MANY_REQUESTS);
+
+    let stats = app
+        .clone()
+        .oneshot(request(
+            "GET",
+            format!("/api/shares/{}/stats", id),
+            String::new(),
+        ))
+        .await
+        .unwrap();
+    let body = stats.into_body().collect().await.unwrap().to_bytes();
+    let stats: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(stats["data"]["active_streams"], 1);
+
+    let body = first.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&body[..], b"1234");
+    let third = download(&id).await.unwrap();
+    assert_eq!(third.status(), axum::http::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_api_folder_webhook_fires_only_under_prefix() {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<serde_json::Value>();
+    let receiver = axum::Router::new().route(
+        "/hook",
+        axum::routing::post(move |axum::Json(body): axum::Json<serde_json::Value>| {
+            let tx = tx.clone();
+            async move {
+                tx.send(body).unwrap();
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let hook_url = format!("http://{}/hook", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, receiver).await.unwrap() });
+
+    let temp_dir = TempDir::new().unwrap();
+    let config = make_config(&temp_dir);
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+
+    let db_path = config.storage_path.join("db.json");
+    let repository = Arc::new(Repository::new(db_path).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+
+    let app = rustcloud::api::create_router_with_services(config, repository, storage).await;
+
+    let send = |method: &'static str, uri: &'static str, body: String| {
+        let app = app.clone();
+        async move {
+            let response = app
+                .oneshot(
+                    axum::http::Request::builder()
+                        .method(method)
+                        .uri(uri)
+                        .header("content-type", "application/json")
+                        .body(axum::body::Body::from(body))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            response.status()
+        }
+    };
+
+    // path 只能用于 file_changed
+    let status = send(
+        "PUT",
+        "/api/notifications/preferences/bob",
+        r#"{"rules":[{"event":"device_registered","path":"inbox","channel":{"type":"webhook","url":"http://127.0.0.1/x"}}]}"#.to_string(),
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::BAD_REQUEST);
+
+    let status = send(
+        "PUT",
+        "/api/notifications/preferences/bob",
+        format!(
+            r#"{{"rules":[{{"event":"file_changed","path":"inbox/","channel":{{"type":"webhook","url":"{}"}}}}]}}"#,
+            hook_url
+        ),
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+
+    // 范围外和前缀相似的路径都不触发
+    send("PUT", "/api/files/notes.txt", "x".to_string()).await;
+    send("PUT", "/api/files/inbox-old/a.txt", "x".to_string()).await;
+    send("PUT", "/api/files/inbox/scan.pdf", "v1".to_string()).await;
+
+    async fn recv(
+        rx: &mut tokio::sync::mpsc::UnboundedReceiver<serde_json::Value>,
+    ) -> serde_json::Value {
+        tokio::time::timeout(std::time::Duration::from_secs(5), rx.recv())
+            .await
+            .expect("webhook was not delivered")
+            .unwrap()
+    }
+    let payload = recv(&mut rx).await;
+    assert_eq!(payload["event"], "file_changed");
+    assert_eq!(payload["path"], "inbox/scan.pdf");
+    assert_eq!(payload["change"], "created");
+
+    send("PUT", "/api/files/inbox/scan.pdf", "v2".to_string()).await;
+    assert_eq!(recv(&mut rx).await["change"], "updated");
+
+    send("DELETE", "/api/files/inbox/scan.pdf", String::new()).await;
+    assert_eq!(recv(&mut rx).await["change"], "deleted");
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn test_client_library_plans_sync_against_server() {
+    use rustcloud_client::sync::{self, LocalFile, LocalTree};
+    use rustcloud_client::{sha256_hex, Client, HttpConfig};
+
+    let temp_dir = TempDir::new().unwrap();
+    let config = make_config(&temp_dir);
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+
+    let db_path = config.storage_path.join("db.json");
+    let repository = Arc::new(Repository::new(db_path).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+    let app = rustcloud::api::create_router_with_services(config, repository, storage).await;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let client = Client::new(&base_url, &HttpConfig::default(), None).unwrap();
+    assert!(client.health().await.unwrap());
+
+    let synced = b"already on the server".to_vec();
+    client
+        .upload_file("docs/synced.txt", &synced)
+        .await
+        .unwrap();
+
+    let fresh = b"only local".to_vec();
+    let local_files = vec![
+        LocalFile {
+            path: "docs/synced.txt".to_string(),
+            hash: sha256_hex(&synced),
+            size: synced.len() as u64,
+        },
+        LocalFile {
+            path: "docs/fresh.txt".to_string(),
+            hash: sha256_hex(&fresh),
+            size: fresh.len() as u64,
+        },
+    ];
+
+    let local = LocalTree {
+        files: local_files,
+        empty_dirs: vec!["docs/empty".to_string()],
+    };
+    let pending = sync::plan(&client, local).await.unwrap();
+    let action = |path: &str| {
+        pending
+            .items
+            .iter()
+            .find(|item| item.path == path)
+            .map(|item| item.action.clone())
+    };
+    assert_eq!(action("docs/synced.txt").as_deref(), Some("skip"));
+    assert_eq!(action("docs/fresh.txt").as_deref(), Some("upload"));
+    // 空目录在服务端创建，不计入传输估算
+    assert_eq!(action("docs/empty").as_deref(), Some("upload"));
+    assert_eq!(pending.estimate.upload_files, 1);
+    assert_eq!(pending.estimate.upload_bytes, fresh.len() as u64);
+    assert_eq!(pending.estimate.saved_bytes, synced.len() as u64);
+}
+
+#[tokio::test]
+async fn test_websocket_pushes_file_changes() {
+    use rustcloud::service::feed::ChangeFeed;
+    use rustcloud::watcher::file_watcher::{EventHandler, FileEvent};
+    use rustcloud_client::{ChangeKind, Client, HttpConfig};
+
+    let temp_dir = TempDir::new().unwrap();
+    let config = make_config(&temp_dir);
+    let root = config.storage_path.clone();
+    std::fs::create_dir_all(&root).unwrap();
+    let repository = Arc::new(Repository::new(root.join("db.json")).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: root.clone(),
+        chunk_size: 1024,
+    }));
+    let feed = ChangeFeed::new();
+    let app = rustcloud::api::create_router_with_supervisor(
+        config,
+        repository.clone(),
+        repository.clone(),
+        storage.clone(),
+        rustcloud::service::supervisor::Supervisor::new(),
+        rustcloud::service::expected_writes::ExpectedWrites::new(),
+        feed.clone(),
+    )
+    .await;
+    let handler = EventHandler::new(&root, storage, repository.clone()).with_feed(feed.clone());
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let client = Client::new(&base_url, &HttpConfig::default(), None).unwrap();
+    assert!(client.supports(rustcloud_types::feature::CHANGE_FEED).await);
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let watching = tokio::spawn({
+        let client = client.clone();
+        async move { client.watch(|change| tx.send(change).unwrap()).await }
+    });
+    for _ in 0..200 {
+        if feed.subscribers() > 0 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    async fn next(
+        rx: &mut tokio::sync::mpsc::UnboundedReceiver<rustcloud_client::ChangeEvent>,
+    ) -> rustcloud_client::ChangeEvent {
+        tokio::time::timeout(std::time::Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    let created = client.upload_file("docs/a.txt", b"one").await.unwrap();
+    let change = next(&mut rx).await;
+    assert_eq!(change.kind, ChangeKind::Created);
+    assert_eq!(change.path, "docs/a.txt");
+    assert_eq!(change.hash, created.hash);
+    assert_eq!(change.version, Some(1));
+
+    client.upload_file("docs/a.txt", b"two").await.unwrap();
+    let change = next(&mut rx).await;
+    assert_eq!(change.kind, ChangeKind::Modified);
+    assert_eq!(change.version, Some(2));
+
+    // 文件监控发现的外部修改也会推送，没有对应的版本号
+    std::fs::write(root.join("docs/a.txt"), "edited on disk").unwrap();
+    handler
+        .handle(FileEvent::Modified(root.join("docs/a.txt")))
+        .await
+        .unwrap();
+    let change = next(&mut rx).await;
+    assert_eq!(change.kind, ChangeKind::Modified);
+    assert_eq!(change.version, None);
+
+    let response = reqwest::Client::new()
+        .delete(format!("{}/api/files/docs/a.txt", base_url))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    let change = next(&mut rx).await;
+    assert_eq!(change.kind, ChangeKind::Deleted);
+    assert_eq!(change.hash, None);
+
+    // 订阅者落后超过缓冲区时被断开，提示重新同步
+    for i in 0..2000 {
+        feed.deleted(&format!("burst/{}", i));
+    }
+    let error = tokio::time::timeout(std::time::Duration::from_secs(5), watching)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap_err();
+    assert!(error.to_string().contains("resync"), "{}", error);
+}
+
+// 可注入故障的对象存储：未设置故障时委托给真实实现
+struct FaultyStorage {
+    inner: StorageService,
+    fault: std::sync::Mutex<Option<std::io::ErrorKind>>,
+    // 设置后每次写入都要等待放行，模拟慢速磁盘
+    gate: Option<Arc<tokio::sync::Notify>>,
+}
+
+impl FaultyStorage {
+    fn new(storage_path: std::path::PathBuf) -> Self {
+        FaultyStorage {
+            inner: StorageService::new(StorageConfig {
+                storage_path,
+                chunk_size: 1024,
+            }),
+            fault: std::sync::Mutex::new(None),
+            gate: None,
+        }
+    }
+
+    async fn before_write(&self) -> rustcloud::error::Result<()> {
+        if let Some(gate) = &self.gate {
+            gate.notified().await;
+        }
+        match *self.fault.lock().unwrap() {
+            Some(kind) => Err(std::io::Error::from(kind).into()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for FaultyStorage {
+    fn negotiate_chunk_size(&self, file_size: u64, requested: Option<usize>) -> Option<usize> {
+        self.inner.negotiate_chunk_size(file_size, requested)
+    }
+
+    fn compute_content_hash(&self, content: &[u8]) -> String {
+        self.inner.compute_content_hash(content)
+    }
+
+    async fn compute_hash(&self, path: &std::path::Path) -> rustcloud::error::Result<String> {
+        self.inner.compute_hash(path).await
+    }
+
+    async fn store_file(
+        &self,
+        source: &std::path::Path,
+    ) -> rustcloud::error::Result<(String, u64)> {
+        self.before_write().await?;
+        self.inner.store_file(source).await
+    }
+
+    async fn store_content(&self, content: &[u8]) -> rustcloud::error::Result<(String, u64)> {
+        self.before_write().await?;
+        self.inner.store_content(content).await
+    }
+
+    async fn begin_upload(&self) -> rustcloud::error::Result<StagedUpload> {
+        self.inner.begin_upload().await
+    }
+
+    async fn file_exists(&self, hash: &str) -> bool {
+        self.inner.file_exists(hash).await
+    }
+
+    fn object_path(&self, hash: &str) -> std::path::PathBuf {
+        self.inner.object_path(hash)
+    }
+}
+
+// 读取正常、写入一律失败的文件记录
+struct ReadOnlyMetadata {
+    inner: Repository,
+}
+
+impl ReadOnlyMetadata {
+    fn refuse<T>() -> rustcloud::error::Result<T> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "database is read-only",
+        )
+        .into())
+    }
+}
+
+#[async_trait::async_trait]
+impl MetadataStore for ReadOnlyMetadata {
+    async fn create_file(&self, _new_file: NewFileRecord) -> rustcloud::error::Result<FileRecord> {
+        Self::refuse()
+    }
+
+    async fn get_file_by_path(&self, path: &str) -> rustcloud::error::Result<FileRecord> {
+        self.inner.get_file_by_path(path).await
+    }
+
+    async fn update_file(
+        &self,
+        _id: uuid::Uuid,
+        _hash: Option<String>,
+        _size: u64,
+    ) -> rustcloud::error::Result<FileRecord> {
+        Self::refuse()
+    }
+
+    async fn set_file_media(
+        &self,
+        _id: uuid::Uuid,
+        _media: Option<rustcloud::db::MediaMetadata>,
+    ) -> rustcloud::error::Result<FileRecord> {
+        Self::refuse()
+    }
+
+    async fn update_file_metadata(
+        &self,
+        _id: uuid::Uuid,
+        _patch: std::collections::BTreeMap<String, Option<String>>,
+    ) -> rustcloud::error::Result<FileRecord> {
+        Self::refuse()
+    }
+
+    async fn set_file_fs_id(
+        &self,
+        _id: uuid::Uuid,
+        _fs_id: Option<rustcloud::db::FsId>,
+    ) -> rustcloud::error::Result<FileRecord> {
+        Self::refuse()
+    }
+
+    async fn set_file_owner(
+        &self,
+        _id: uuid::Uuid,
+        _owner: Option<uuid::Uuid>,
+    ) -> rustcloud::error::Result<FileRecord> {
+        Self::refuse()
+    }
+
+    async fn move_file(
+        &self,
+        _id: uuid::Uuid,
+        _new_path: &str,
+    ) -> rustcloud::error::Result<FileRecord> {
+        Self::refuse()
+    }
+
+    async fn delete_file(&self, _id: uuid::Uuid) -> rustcloud::error::Result<()> {
+        Self::refuse()
+    }
+
+    async fn list_files(&self) -> rustcloud::error::Result<Vec<FileRecord>> {
+        self.inner.list_files().await
+    }
+
+    async fn total_size(&self) -> rustcloud::error::Result<u64> {
+        self.inner.total_size().await
+    }
+
+    async fn owner_usage(
+        &self,
+        owner: uuid::Uuid,
+        root: Option<&str>,
+    ) -> rustcloud::error::Result<(usize, u64)> {
+        self.inner.owner_usage(owner, root).await
+    }
+
+    async fn path_spellings(&self, path: &str) -> rustcloud::error::Result<Vec<String>> {
+        self.inner.path_spellings(path).await
+    }
+}
+
+fn put_request(path: &str, content: &'static str) -> axum::http::Request<axum::body::Body> {
+    axum::http::Request::builder()
+        .method("PUT")
+        .uri(format!("/api/files/{}", path))
+        .body(axum::body::Body::from(content))
+        .unwrap()
+}
+
+async fn versions_count(app: &axum::Router) -> usize {
+    let response = app
+        .clone()
+        .oneshot(
+            axum::http::Request::builder()
+                .uri("/api/versions")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let resp: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    resp["data"].as_array().unwrap().len()
+}
+
+#[tokio::test]
+async fn test_api_upload_surfaces_storage_failures() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = make_config(&temp_dir);
+    config.materialize_files = false;
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+
+    let repository = Arc::new(
+        Repository::new(config.storage_path.join("db.json"))
+            .await
+            .unwrap(),
+    );
+    let storage = Arc::new(FaultyStorage::new(config.storage_path.clone()));
+    let app = rustcloud::api::create_router_with_backends(
+        config,
+        repository.clone(),
+        repository,
+        storage.clone(),
+    )
+    .await;
+
+    *storage.fault.lock().unwrap() = Some(std::io::ErrorKind::PermissionDenied);
+    let response = app
+        .clone()
+        .oneshot(put_request("a.txt", "one"))
+        .await
+        .unwrap();
+    assert_eq!(
+        response.status(),
+        axum::http::StatusCode::INTERNAL_SERVER_ERROR
+    );
+
+    // 磁盘写满单独报告，客户端不应盲目重试
+    *storage.fault.lock().unwrap() = Some(std::io::ErrorKind::StorageFull);
+    let response = app
+        .clone()
+        .oneshot(put_request("a.txt", "one"))
+        .await
+        .unwrap();
+    assert_eq!(
+        response.status(),
+        axum::http::StatusCode::INSUFFICIENT_STORAGE
+    );
+
+    *storage.fault.lock().unwrap() = Some(std::io::ErrorKind::QuotaExceeded);
+    let response = app
+        .clone()
+        .oneshot(put_request("a.txt", "one"))
+        .await
+        .unwrap();
+    assert_eq!(
+        response.status(),
+        axum::http::StatusCode::INSUFFICIENT_STORAGE
+    );
+
+    // 失败的写入不留下记录
+    assert_eq!(versions_count(&app).await, 0);
+
+    *storage.fault.lock().unwrap() = None;
+    let response = app
+        .clone()
+        .oneshot(put_request("a.txt", "one"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::OK);
+    assert_eq!(versions_count(&app).await, 1);
+}
+
+#[tokio::test]
+async fn test_api_upload_records_only_after_slow_storage_completes() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = make_config(&temp_dir);
+    config.materialize_files = false;
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+
+    let repository = Arc::new(
+        Repository::new(config.storage_path.join("db.json"))
+            .await
+            .unwrap(),
+    );
+    let gate = Arc::new(tokio::sync::Notify::new());
+    let mut storage = FaultyStorage::new(config.storage_path.clone());
+    storage.gate = Some(gate.clone());
+    let app = rustcloud::api::create_router_with_backends(
+        config,
+        repository.clone(),
+        repository,
+        Arc::new(storage),
+    )
+    .await;
+
+    let upload = tokio::spawn(app.clone().oneshot(put_request("slow.txt", "payload")));
+
+    // 存储尚未完成时，文件不可见
+    tokio::task::yield_now().await;
+    assert_eq!(versions_count(&app).await, 0);
+
+    gate.notify_one();
+    let response = upload.await.unwrap().unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::OK);
+    assert_eq!(versions_count(&app).await, 1);
+}
+
+#[tokio::test]
+async fn test_api_upload_reports_metadata_write_failure() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = make_config(&temp_dir);
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+
+    let repository = Arc::new(
+        Repository::new(config.storage_path.join("db.json"))
+            .await
+            .unwrap(),
+    );
+    let files = Arc::new(ReadOnlyMetadata {
+        inner: (*repository).clone(),
+    });
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+    let app = rustcloud::api::create_router_with_backends(config, repository, files, storage).await;
+
+    let response = app
+        .clone()
+        .oneshot(put_request("a.txt", "one"))
+        .await
+        .unwrap();
+    assert_eq!(
+        response.status(),
+        axum::http::StatusCode::INTERNAL_SERVER_ERROR
+    );
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let resp: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert!(resp["error"]
+        .as_str()
+        .unwrap()
+        .contains("Failed to update record"));
+    assert_eq!(versions_count(&app).await, 0);
+}
+
+async fn stress_app(materialize_files: bool) -> (TempDir, axum::Router, Arc<Repository>) {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = make_config(&temp_dir);
+    config.materialize_files = materialize_files;
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+
+    let repository = Arc::new(
+        Repository::new(config.storage_path.join("db.json"))
+            .await
+            .unwrap(),
+    );
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+    let app =
+        rustcloud::api::create_router_with_services(config, repository.clone(), storage).await;
+    (temp_dir, app, repository)
+}
+
+fn stress_content(i: usize) -> Vec<u8> {
+    format!("writer {} ", i).repeat(200 + i).into_bytes()
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn test_concurrent_uploads_to_same_path() {
+    for materialize_files in [true, false] {
+        let (_temp_dir, app, repository) = stress_app(materialize_files).await;
+        const WRITERS: usize = 32;
+
+        let uploads: Vec<_> = (0..WRITERS)
+            .map(|i| {
+                let app = app.clone();
+                tokio::spawn(async move {
+                    app.oneshot(
+                        axum::http::Request::builder()
+                            .method("PUT")
+                            .uri("/api/files/contended.txt")
+                            .body(axum::body::Body::from(stress_content(i)))
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap()
+                    .status()
+                })
+            })
+            .collect();
+        for upload in uploads {
+            assert_eq!(upload.await.unwrap(), axum::http::StatusCode::OK);
+        }
+
+        // 只有一条记录，每次内容不同的上传都计入版本
+        let records = repository.list_files().await.unwrap();
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(record.version, WRITERS as i32);
+
+        // 读到的内容与记录的哈希一致
+        let response = app
+            .clone()
+            .oneshot(
+                axum::http::Request::builder()
+                    .uri("/api/stream/contended.txt")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let hash = format!("{:x}", <sha2::Sha256 as sha2::Digest>::digest(&body));
+        assert_eq!(record.hash.as_deref(), Some(hash.as_str()));
+        assert_eq!(record.size, body.len() as u64);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn test_concurrent_downloads_never_see_partial_content() {
+    let (_temp_dir, app, _repository) = stress_app(true).await;
+    let put = |i: usize| {
+        axum::http::Request::builder()
+            .method("PUT")
+            .uri("/api/files/busy.txt")
+            .body(axum::body::Body::from(stress_content(i)))
+            .unwrap()
+    };
+    assert_eq!(
+        app.clone().oneshot(put(0)).await.unwrap().status(),
+        axum::http::StatusCode::OK
+    );
+
+    let valid: std::collections::HashSet<Vec<u8>> = (0..16).map(stress_content).collect();
+    let writers: Vec<_> = (1..16)
+        .map(|i| {
+            let app = app.clone();
+            tokio::spawn(async move { app.oneshot(put(i)).await.unwrap().status() })
+        })
+        .collect();
+    let readers: Vec<_> = (0..32)
+        .map(|_| {
+            let app = app.clone();
+            tokio::spawn(async move {
+                let response = app
+                    .oneshot(
+                        axum::http::Request::builder()
+                            .uri("/api/stream/busy.txt")
+                            .body(axum::body::Body::empty())
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                response
+                    .into_body()
+                    .collect()
+                    .await
+                    .unwrap()
+                    .to_bytes()
+                    .to_vec()
+            })
+        })
+        .collect();
+
+    for writer in writers {
+        assert_eq!(writer.await.unwrap(), axum::http::StatusCode::OK);
+    }
+    for reader in readers {
+        let content = reader.await.unwrap();
+        assert!(
+            valid.contains(&content),
+            "read {} bytes of torn content",
+            content.len()
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn test_concurrent_rename_uploads_get_distinct_paths() {
+    let (_temp_dir, app, repository) = stress_app(true).await;
+    const WRITERS: usize = 16;
+
+    let uploads: Vec<_> = (0..WRITERS)
+        .map(|i| {
+            let app = app.clone();
+            tokio::spawn(async move {
+                let response = app
+                    .oneshot(
+                        axum::http::Request::builder()
+                            .method("PUT")
+                            .uri("/api/files/report.txt?on_conflict=rename")
+                            .body(axum::body::Body::from(stress_content(i)))
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), axum::http::StatusCode::OK);
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                let resp: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                resp["data"]["path"].as_str().unwrap().to_string()
+            })
+        })
+        .collect();
+
+    let mut paths = Vec::new();
+    for upload in uploads {
+        paths.push(upload.await.unwrap());
+    }
+    paths.sort();
+    paths.dedup();
+    assert_eq!(paths.len(), WRITERS);
+
+    let records = repository.list_files().await.unwrap();
+    assert_eq!(records.len(), WRITERS);
+    assert!(records.iter().all(|r| r.version == 1));
+}
+
+async fn clock_app() -> (
+    TempDir,
+    rustcloud::service::clock::VirtualClock,
+    impl Fn(
+        &str,
+        &str,
+        &str,
+    ) -> std::pin::Pin<
+        Box<dyn std::future::Future<Output = (axum::http::StatusCode, serde_json::Value)>>,
+    >,
+) {
+    use rustcloud::service::clock::VirtualClock;
+
+    let temp_dir = TempDir::new().unwrap();
+    let config = make_config(&temp_dir);
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+
+    let start = chrono::DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+        .unwrap()
+        .with_timezone(&chrono::Utc);
+    let clock = VirtualClock::new(start);
+    let repository = Arc::new(
+        Repository::with_clock(config.storage_path.join("db.json"), Arc::new(clock.clone()))
+            .await
+            .unwrap(),
+    );
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+    let app = rustcloud::api::create_router_with_services(config, repository, storage).await;
+
+    let send = move |method: &str, uri: &str, body: &str| {
+        let request = axum::http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(axum::body::Body::from(body.to_string()))
+            .unwrap();
+        let app = app.clone();
+        Box::pin(async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            (status, serde_json::from_slice(&body).unwrap_or_default())
+        }) as std::pin::Pin<Box<dyn std::future::Future<Output = _>>>
+    };
+    (temp_dir, clock, send)
+}
+
+#[tokio::test]
+async fn test_virtual_clock_share_expiry() {
+    let (_temp_dir, clock, send) = clock_app().await;
+
+    let (status, _) = send("PUT", "/api/files/report.pdf", "0123456789").await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    let (status, share) = send(
+        "POST",
+        "/api/shares",
+        r#"{"path":"report.pdf","expires_in_hours":24}"#,
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::CREATED);
+    assert_eq!(share["data"]["expires_at"], "2024-01-02T00:00:00Z");
+    let download = format!(
+        "/api/shares/{}/download",
+        share["data"]["id"].as_str().unwrap()
+    );
+
+    clock.advance(chrono::Duration::hours(23));
+    let (status, _) = send("GET", &download, "").await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+
+    clock.advance(chrono::Duration::hours(1));
+    let (status, _) = send("GET", &download, "").await;
+    assert_eq!(status, axum::http::StatusCode::GONE);
+}
+
+#[tokio::test]
+async fn test_virtual_clock_device_goes_offline_without_heartbeat() {
+    let (_temp_dir, clock, send) = clock_app().await;
+
+    let (_, device) = send("POST", "/api/devices", r#"{"name":"laptop"}"#).await;
+    let id = device["data"]["id"].as_str().unwrap().to_string();
+    let (_, devices) = send("GET", "/api/devices", "").await;
+    assert_eq!(devices["data"][0]["name"], "laptop");
+    assert_eq!(devices["data"][0]["online"], true);
+
+    clock.advance(chrono::Duration::minutes(10));
+    let (_, devices) = send("GET", "/api/devices", "").await;
+    assert_eq!(devices["data"][0]["online"], false);
+
+    let (status, _) = send("POST", &format!("/api/devices/{}/heartbeat", id), "").await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    let (_, devices) = send("GET", "/api/devices", "").await;
+    assert_eq!(devices["data"][0]["online"], true);
+    assert_eq!(devices["data"][0]["last_seen"], "2024-01-01T00:10:00Z");
+}
+
+#[tokio::test]
+async fn test_virtual_clock_lifecycle_retention() {
+    let (_temp_dir, clock, send) = clock_app().await;
+
+    send("PUT", "/api/files/logs/old.log", "old").await;
+    let (status, _) = send(
+        "POST",
+        "/api/lifecycle/rules",
+        r#"{"folder":"logs","delete_after_days":30}"#,
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::CREATED);
+
+    clock.advance(chrono::Duration::days(20));
+    send("PUT", "/api/files/logs/new.log", "new").await;
+    let (_, plan) = send("GET", "/api/lifecycle/preview", "").await;
+    assert_eq!(plan["data"], serde_json::json!([]));
+
+    clock.advance(chrono::Duration::days(10));
+    let (_, plan) = send("GET", "/api/lifecycle/preview", "").await;
+    let paths: Vec<&str> = plan["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|a| a["path"].as_str().unwrap())
+        .collect();
+    assert_eq!(paths, vec!["logs/old.log"]);
+    assert_eq!(plan["data"][0]["age_days"], 30);
+
+    let (status, report) = send("POST", "/api/lifecycle/run", "").await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    assert_eq!(report["data"]["applied"], 1);
+    let (status, _) = send("GET", "/api/files/logs/old.log", "").await;
+    assert_eq!(status, axum::http::StatusCode::NOT_FOUND);
+    let (status, _) = send("GET", "/api/files/logs/new.log", "").await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_virtual_clock_list_files_as_of() {
+    let (_temp_dir, clock, send) = clock_app().await;
+    let names = |resp: &serde_json::Value| -> Vec<(String, Option<i64>)> {
+        let mut names: Vec<_> = resp["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| (f["name"].as_str().unwrap().to_string(), f["size"].as_i64()))
+            .collect();
+        names.sort();
+        names
+    };
+
+    send("PUT", "/api/files/notes/todo.txt", "one").await;
+    send("PUT", "/api/files/notes/draft.txt", "draft").await;
+    clock.advance(chrono::Duration::hours(1));
+    send("PUT", "/api/files/notes/todo.txt", "two!!").await;
+    let (status, _) = send("DELETE", "/api/files/notes/draft.txt", "").await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    let (status, _) = send(
+        "POST",
+        "/api/files/notes/todo.txt/move",
+        r#"{"to": "archive/todo.txt"}"#,
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    clock.advance(chrono::Duration::hours(1));
+    send("PUT", "/api/files/notes/later.txt", "later").await;
+
+    // 半小时时：删除的文件还在，移走的文件在原路径，内容是当时的版本
+    let (status, resp) = send(
+        "GET",
+        "/api/files?path=notes&as_of=2024-01-01T00:30:00Z",
+        "",
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::OK, "{}", resp);
+    assert_eq!(
+        names(&resp),
+        vec![
+            ("draft.txt".to_string(), Some(5)),
+            ("todo.txt".to_string(), Some(3))
+        ]
+    );
+    let todo = resp["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|f| f["name"] == "todo.txt")
+        .unwrap();
+    assert_eq!(todo["version"], 1);
+    let (_, resp) = send("GET", "/api/files?as_of=2024-01-01T00:30:00Z", "").await;
+    assert_eq!(names(&resp), vec![("notes".to_string(), Some(0))]);
+    assert_eq!(resp["data"][0]["is_dir"], true);
+
+    // 一个半小时时：移动之后、新文件之前
+    let (_, resp) = send("GET", "/api/files?as_of=2024-01-01T01:30:00Z", "").await;
+    assert_eq!(names(&resp), vec![("archive".to_string(), Some(0))]);
+    let (_, resp) = send(
+        "GET",
+        "/api/files?path=archive&as_of=2024-01-01T01:30:00Z",
+        "",
+    )
+    .await;
+    assert_eq!(names(&resp), vec![("todo.txt".to_string(), Some(5))]);
+    assert_eq!(resp["data"][0]["version"], 2);
+
+    // 创建之前什么都没有；时间格式错误
+    let (_, resp) = send("GET", "/api/files?as_of=2023-12-31T00:00:00Z", "").await;
+    assert_eq!(resp["data"], serde_json::json!([]));
+    let (status, _) = send("GET", "/api/files?as_of=yesterday", "").await;
+    assert_eq!(status, axum::http::StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_api_upload_normalizes_windows_paths() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = make_config(&temp_dir);
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+    let repository = Arc::new(
+        Repository::new(config.storage_path.join("db.json"))
+            .await
+            .unwrap(),
+    );
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+    let app =
+        rustcloud::api::create_router_with_services(config, repository.clone(), storage).await;
+
+    let send = |method: &str, uri: &str, body: &str| {
+        let request = axum::http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(axum::body::Body::from(body.to_string()))
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let json: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+            (status, json)
+        }
+    };
+
+    // 反斜杠分隔符统一为 "/"
+    let (status, body) = send("PUT", "/api/files/Docs%5CReport.txt", "v1").await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    assert_eq!(body["data"]["path"], "Docs/Report.txt");
+
+    // 只差大小写时更新已有文件，沿用原来的写法
+    let (status, body) = send("PUT", "/api/files/docs/REPORT.txt", "v2").await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    assert_eq!(body["data"]["path"], "Docs/Report.txt");
+    assert_eq!(body["data"]["version"], 2);
+    let (_, body) = send("PUT", "/api/files/DOCS/summary.txt", "s").await;
+    assert_eq!(body["data"]["path"], "Docs/summary.txt");
+    assert_eq!(repository.list_files().await.unwrap().len(), 2);
+
+    let (status, _) = send("PUT", "/api/files/docs/report.txt?on_conflict=fail", "v3").await;
+    assert_eq!(status, axum::http::StatusCode::CONFLICT);
+    let (_, body) = send("PUT", "/api/files/docs/report.txt?on_conflict=rename", "v3").await;
+    assert_eq!(body["data"]["path"], "Docs/Report (1).txt");
+
+    // Windows 上无法创建的名字直接拒绝
+    for uri in [
+        "/api/files/docs/CON",
+        "/api/files/docs/nul.txt",
+        "/api/files/docs/a%3Ab.txt",
+        "/api/files/docs/trailing.",
+    ] {
+        let (status, body) = send("PUT", uri, "x").await;
+        assert_eq!(status, axum::http::StatusCode::BAD_REQUEST, "{}", uri);
+        assert!(body["error"].as_str().unwrap().starts_with("Invalid path"));
+    }
+    let (status, _) = send("POST", "/api/files", r#"{"path":"../outside"}"#).await;
+    assert_eq!(status, axum::http::StatusCode::BAD_REQUEST);
+    assert_eq!(repository.list_files().await.unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn test_api_rejects_path_traversal() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = make_config(&temp_dir);
+    let storage_path = config.storage_path.clone();
+    std::fs::create_dir_all(&storage_path).unwrap();
+    let outside = temp_dir.path().join("outside");
+    std::fs::create_dir_all(&outside).unwrap();
+    std::fs::write(outside.join("secret.txt"), "top secret").unwrap();
+    let app = rustcloud::api::routes::create_router(config).await;
+
+    let send = |method: &str, uri: &str| {
+        let request = axum::http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(axum::body::Body::from("x"))
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let json: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+            (status, json)
+        }
+    };
+
+    // 编码过的 ".."、反斜杠、绝对路径和盘符在解码后同样被拒绝
+    for (method, uri) in [
+        ("GET", "/api/files/../outside/secret.txt"),
+        ("GET", "/api/files/%2e%2e/outside/secret.txt"),
+        ("GET", "/api/stream/docs%2F..%2F..%2Foutside%2Fsecret.txt"),
+        ("GET", "/api/preview/docs/..%5C..%5Coutside%5Csecret.txt"),
+        ("GET", "/api/files/%2Fetc%2Fpasswd"),
+        ("GET", "/api/files/C:%5CWindows"),
+        ("GET", "/api/files?path=..%2Foutside"),
+        ("GET", "/api/files?path=%2Fetc"),
+        ("PUT", "/api/files/%2e%2e%2Foutside%2Fplanted.txt"),
+        ("DELETE", "/api/files/..%2Foutside?recursive=true"),
+        ("PATCH", "/api/metadata/%2E%2E/outside/secret.txt"),
+    ] {
+        let (status, body) = send(method, uri).await;
+        assert_eq!(status, axum::http::StatusCode::BAD_REQUEST, "{}", uri);
+        assert!(
+            body["error"]
+                .as_str()
+                .unwrap()
+                .starts_with("Path escapes the storage root"),
+            "{}: {}",
+            uri,
+            body
+        );
+    }
+
+    // 存储目录中指向外部的符号链接不能用来读写外部文件
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(&outside, storage_path.join("link")).unwrap();
+        for (method, uri) in [
+            ("GET", "/api/stream/link/secret.txt"),
+            ("GET", "/api/files?path=link"),
+            ("PUT", "/api/files/link/planted.txt"),
+        ] {
+            let (status, _) = send(method, uri).await;
+            assert_eq!(status, axum::http::StatusCode::BAD_REQUEST, "{}", uri);
+        }
+    }
+    assert!(!outside.join("planted.txt").exists());
+    assert!(outside.join("secret.txt").exists());
+
+    // 名字中含 ".." 但不是上级目录的文件照常可用
+    let (status, _) = send("PUT", "/api/files/docs/notes..txt").await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    let (status, _) = send("GET", "/api/files/docs/notes..txt").await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_api_list_files_streams_large_directory() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = make_config(&temp_dir);
+    let big = config.storage_path.join("big");
+    std::fs::create_dir_all(&big).unwrap();
+    for i in 0..2500 {
+        std::fs::write(big.join(format!("file-{:05}.txt", i)), i.to_string()).unwrap();
+    }
+    let app = rustcloud::api::routes::create_router(config).await;
+
+    let response = app
+        .clone()
+        .oneshot(
+            axum::http::Request::builder()
+                .uri("/api/files?path=big&stream=true")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "application/x-ndjson");
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let mut names: Vec<String> = body
+        .split(|&b| b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let info: rustcloud::api::routes::FileInfo = serde_json::from_slice(line).unwrap();
+            info.name
+        })
+        .collect();
+    names.sort();
+    assert_eq!(names.len(), 2500);
+    assert_eq!(names[0], "file-00000.txt");
+    assert_eq!(names[2499], "file-02499.txt");
+
+    // 不带 stream 参数时仍是普通 JSON
+    let response = app
+        .oneshot(
+            axum::http::Request::builder()
+                .uri("/api/files?path=big")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["data"].as_array().unwrap().len(), 2500);
+}
+
+#[tokio::test]
+async fn test_directory_cache_invalidated_by_mtime() {
+    use rustcloud::service::listing::DirectoryCache;
+    use std::time::{Duration, SystemTime};
+
+    let temp_dir = TempDir::new().unwrap();
+    let dir = temp_dir.path().join("docs");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("a.txt"), "a").unwrap();
+
+    let set_mtime = |at: SystemTime| {
+        std::fs::File::open(&dir).unwrap().set_modified(at).unwrap();
+    };
+    let past = SystemTime::now() - Duration::from_secs(3600);
+    set_mtime(past);
+
+    let cache = DirectoryCache::new();
+    let first = cache.list(&dir, temp_dir.path()).await.unwrap();
+    assert_eq!(first.len(), 1);
+    assert_eq!(first[0].path, "docs/a.txt");
+
+    // mtime 没变时直接复用缓存（这里故意把 mtime 改回去来观察缓存）
+    std::fs::write(dir.join("b.txt"), "b").unwrap();
+    set_mtime(past);
+    let cached = cache.list(&dir, temp_dir.path()).await.unwrap();
+    assert!(Arc::ptr_eq(&first, &cached));
+
+    // mtime 变化后重新读取
+    set_mtime(past + Duration::from_secs(60));
+    let fresh = cache.list(&dir, temp_dir.path()).await.unwrap();
+    assert_eq!(fresh.len(), 2);
+
+    // 刚修改过的目录不缓存，新增条目立刻可见
+    std::fs::write(dir.join("c.txt"), "c").unwrap();
+    let recent = cache.list(&dir, temp_dir.path()).await.unwrap();
+    assert_eq!(recent.len(), 3);
+    std::fs::write(dir.join("d.txt"), "d").unwrap();
+    assert_eq!(cache.list(&dir, temp_dir.path()).await.unwrap().len(), 4);
+
+    assert!(cache
+        .list(&temp_dir.path().join("missing"), temp_dir.path())
+        .await
+        .is_err());
+}
+
+/// 启用认证、使用虚拟时钟的应用；send 的最后一个参数是 Bearer 令牌
+async fn auth_app() -> (
+    TempDir,
+    rustcloud::service::clock::VirtualClock,
+    impl Fn(
+        &str,
+        &str,
+        &str,
+        Option<&str>,
+    ) -> std::pin::Pin<
+        Box<dyn std::future::Future<Output = (axum::http::StatusCode, serde_json::Value)>>,
+    >,
+) {
+    auth_app_with(|_| {}).await
+}
+
+async fn auth_app_with(
+    configure: impl FnOnce(&mut Config),
+) -> (
+    TempDir,
+    rustcloud::service::clock::VirtualClock,
+    impl Fn(
+        &str,
+        &str,
+        &str,
+        Option<&str>,
+    ) -> std::pin::Pin<
+        Box<dyn std::future::Future<Output = (axum::http::StatusCode, serde_json::Value)>>,
+    >,
+) {
+    use rustcloud::service::clock::VirtualClock;
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = make_config(&temp_dir);
+    config.auth = Some(rustcloud::config::AuthConfig {
+        jwt_secret: "test-secret".to_string(),
+        token_ttl_secs: 3600,
+        allow_registration: true,
+        user_namespaces: false,
+        default_quota_bytes: None,
+    });
+    configure(&mut config);
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+
+    let clock = VirtualClock::new(chrono::Utc::now());
+    let repository = Arc::new(
+        Repository::with_clock(config.storage_path.join("db.json"), Arc::new(clock.clone()))
+            .await
+            .unwrap(),
+    );
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+    let app = rustcloud::api::create_router_with_services(config, repository, storage).await;
+
+    let send = move |method: &str, uri: &str, body: &str, token: Option<&str>| {
+        let mut request = axum::http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json");
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {}", token));
+        }
+        let request = request
+            .body(axum::body::Body::from(body.to_string()))
+            .unwrap();
+        let app = app.clone();
+        Box::pin(async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            (status, serde_json::from_slice(&body).unwrap_or_default())
+        }) as std::pin::Pin<Box<dyn std::future::Future<Output = _>>>
+    };
+    (temp_dir, clock, send)
+}
+
+#[tokio::test]
+async fn test_auth_register_login_and_protected_routes() {
+    use axum::http::StatusCode;
+
+    let (_temp_dir, clock, send) = auth_app().await;
+
+    // 公开接口照常可用，其余接口需要令牌
+    let (status, _) = send("GET", "/api/health", "", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, capabilities) = send("GET", "/api/capabilities", "", None).await;
+    assert!(capabilities["data"]["features"]
+        .as_array()
+        .unwrap()
+        .contains(&serde_json::json!(rustcloud_types::feature::AUTH)));
+    for (method, uri) in [
+        ("GET", "/api/files"),
+        ("PUT", "/api/files/a.txt"),
+        ("GET", "/api/devices"),
+        ("POST", "/api/sync/plan"),
+    ] {
+        let (status, _) = send(method, uri, "", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED, "{} {}", method, uri);
+    }
+
+    let (status, _) = send(
+        "POST",
+        "/api/auth/register",
+        r#"{"username":"alice","password":"short"}"#,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, registered) = send(
+        "POST",
+        "/api/auth/register",
+        r#"{"username":"alice","password":"correct horse"}"#,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(registered["data"]["user"]["username"], "alice");
+    assert!(registered["data"]["user"].get("password_hash").is_none());
+    let (status, _) = send(
+        "POST",
+        "/api/auth/register",
+        r#"{"username":"Alice","password":"another one"}"#,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (status, _) = send(
+        "POST",
+        "/api/auth/login",
+        r#"{"username":"alice","password":"wrong password"}"#,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, login) = send(
+        "POST",
+        "/api/auth/login",
+        r#"{"username":"alice","password":"correct horse"}"#,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let token = login["data"]["token"].as_str().unwrap().to_string();
+
+    let (status, _) = send("PUT", "/api/files/a.txt", "hello", Some(&token)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, me) = send("GET", "/api/auth/me", "", Some(&token)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(me["data"]["username"], "alice");
+
+    // 作者取自令牌，不能冒充他人
+    let (status, comment) = send(
+        "POST",
+        "/api/comments",
+        r#"{"path":"a.txt","author":"mallory","text":"hi"}"#,
+        Some(&token),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(comment["data"]["author"], "alice");
+
+    // 分享链接不需要登录
+    let (_, share) = send("POST", "/api/shares", r#"{"path":"a.txt"}"#, Some(&token)).await;
+    let download = format!(
+        "/api/shares/{}/download",
+        share["data"]["id"].as_str().unwrap()
+    );
+    let (status, _) = send("GET", &download, "", None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // 篡改过的令牌与过期令牌都被拒绝
+    let mut tampered = token.clone();
+    tampered.pop();
+    let (status, _) = send("GET", "/api/files", "", Some(&tampered)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    clock.advance(chrono::Duration::hours(2));
+    let (status, _) = send("GET", "/api/files", "", Some(&token)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_user_namespaces_and_quotas() {
+    use axum::http::StatusCode;
+
+    let (temp_dir, _clock, send) = auth_app_with(|config| {
+        let auth = config.auth.as_mut().unwrap();
+        auth.user_namespaces = true;
+        auth.default_quota_bytes = Some(10);
+    })
+    .await;
+    let mut tokens = Vec::new();
+    for name in ["alice", "bob"] {
+        let body = format!(r#"{{"username":"{}","password":"correct horse"}}"#, name);
+        let (_, registered) = send("POST", "/api/auth/register", &body, None).await;
+        tokens.push(registered["data"]["token"].as_str().unwrap().to_string());
+    }
+    let (alice, bob) = (tokens[0].as_str(), tokens[1].as_str());
+
+    // 两人使用同样的路径，各自写进自己的目录
+    let (status, uploaded) = send("PUT", "/api/files/docs/a.txt", "alice", Some(alice)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(uploaded["data"]["path"], "docs/a.txt");
+    let (status, _) = send("PUT", "/api/files/docs/a.txt", "bob", Some(bob)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, me) = send("GET", "/api/auth/me", "", Some(alice)).await;
+    let alice_id = me["data"]["id"].as_str().unwrap().to_string();
+    let on_disk = temp_dir
+        .path()
+        .join("storage/users")
+        .join(&alice_id)
+        .join("docs/a.txt");
+    assert_eq!(std::fs::read_to_string(on_disk).unwrap(), "alice");
+
+    // 列表、搜索和设备都只包含自己的
+    let (_, listing) = send("GET", "/api/files", "", Some(bob)).await;
+    let names: Vec<&str> = listing["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|f| f["path"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["docs"]);
+    let (_, found) = send("GET", "/api/search?q=a.txt", "", Some(bob)).await;
+    assert_eq!(found["data"]["total"], 1);
+    assert_eq!(found["data"]["files"][0]["path"], "docs/a.txt");
+    let (_, found) = send("GET", "/api/search?q=users", "", Some(bob)).await;
+    assert_eq!(found["data"]["total"], 0);
+    let (_, device) = send("POST", "/api/devices", r#"{"name":"laptop"}"#, Some(alice)).await;
+    let heartbeat = format!(
+        "/api/devices/{}/heartbeat",
+        device["data"]["id"].as_str().unwrap()
+    );
+    let (_, devices) = send("GET", "/api/devices", "", Some(bob)).await;
+    assert!(devices["data"].as_array().unwrap().is_empty());
+    let (status, _) = send("POST", &heartbeat, "", Some(bob)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send("POST", &heartbeat, "", Some(alice)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // 别人的目录无法通过路径访问
+    let escape = format!("/api/files/users/{}/docs/a.txt", alice_id);
+    let (status, _) = send("GET", &escape, "", Some(bob)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // 按 id 访问的记录同样不能越界
+    let (_, comment) = send(
+        "POST",
+        "/api/comments",
+        r#"{"path":"docs/a.txt","text":"mine"}"#,
+        Some(alice),
+    )
+    .await;
+    let syncs = format!(
+        "/api/syncs/{}",
+        comment["data"]["file_id"].as_str().unwrap()
+    );
+    let comment = format!("/api/comments/{}", comment["data"]["id"].as_str().unwrap());
+    let (status, _) = send("DELETE", &comment, "", Some(bob)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send("DELETE", &comment, "", Some(alice)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send("GET", &syncs, "", Some(bob)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send("GET", &syncs, "", Some(alice)).await;
+    assert_eq!(status, StatusCode::OK);
+    let delete = format!(
+        r#"{{"file_id":"{}","device_id":"{}","action":"delete"}}"#,
+        syncs.trim_start_matches("/api/syncs/"),
+        uuid::Uuid::new_v4()
+    );
+    let (status, _) = send("POST", "/api/sync/execute", &delete, Some(bob)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send("GET", "/api/files/docs/a.txt", "", Some(alice)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send("GET", "/api/notifications/preferences/alice", "", Some(bob)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = send(
+        "PUT",
+        "/api/notifications/preferences/alice",
+        r#"{"rules":[]}"#,
+        Some(bob),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = send("GET", "/api/notifications/preferences/bob", "", Some(bob)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // 配额按用户统计：alice 已用 5 字节，再写 6 字节超出
+    let (status, usage) = send("GET", "/api/me/usage", "", Some(alice)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(usage["data"]["files"], 1);
+    assert_eq!(usage["data"]["used_bytes"], 5);
+    assert_eq!(usage["data"]["quota_bytes"], 10);
+    let (status, _) = send("PUT", "/api/files/b.txt", "123456", Some(alice)).await;
+    assert_eq!(status, StatusCode::INSUFFICIENT_STORAGE);
+    // 覆盖时旧内容不再计入
+    let (status, _) = send("PUT", "/api/files/docs/a.txt", "0123456789", Some(alice)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, usage) = send("GET", "/api/me/usage", "", Some(bob)).await;
+    assert_eq!(usage["data"]["used_bytes"], 3);
+
+    // 回滚写入的内容同样计入：回到 5 字节的版本后再写 5 字节，已经用满
+    let (status, _) = send("POST", "/api/files/docs/a.txt/rollback/1", "", Some(alice)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send("PUT", "/api/files/c.txt", "12345", Some(alice)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send("POST", "/api/files/docs/a.txt/rollback/2", "", Some(alice)).await;
+    assert_eq!(status, StatusCode::INSUFFICIENT_STORAGE);
+
+    // 并发上传不同路径，合起来也不能超出配额
+    let uploads = ["x", "y", "z"].map(|name| {
+        let uri = format!("/api/files/{}.txt", name);
+        send("PUT", &uri, "123456", Some(bob))
+    });
+    let statuses: Vec<StatusCode> = futures_util::future::join_all(uploads)
+        .await
+        .into_iter()
+        .map(|(status, _)| status)
+        .collect();
+    assert_eq!(
+        statuses.iter().filter(|s| **s == StatusCode::OK).count(),
+        1,
+        "{:?}",
+        statuses
+    );
+}
+
+#[tokio::test]
+async fn test_user_namespaces_keep_trash_private() {
+    use axum::http::StatusCode;
+
+    let (_temp_dir, _clock, send) = auth_app_with(|config| {
+        config.auth.as_mut().unwrap().user_namespaces = true;
+        config.trash_retention_days = 30;
+    })
+    .await;
+    let mut tokens = Vec::new();
+    for name in ["alice", "bob"] {
+        let body = format!(r#"{{"username":"{}","password":"correct horse"}}"#, name);
+        let (_, registered) = send("POST", "/api/auth/register", &body, None).await;
+        tokens.push(registered["data"]["token"].as_str().unwrap().to_string());
+    }
+    let (alice, bob) = (tokens[0].as_str(), tokens[1].as_str());
+
+    let (status, _) = send("PUT", "/api/files/docs/a.txt", "alice", Some(alice)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send("DELETE", "/api/files/docs/a.txt", "", Some(alice)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, trash) = send("GET", "/api/trash", "", Some(alice)).await;
+    let items = trash["data"].as_array().unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0]["path"], "docs/a.txt");
+    let restore = format!("/api/trash/{}/restore", items[0]["id"].as_str().unwrap());
+    let (_, trash) = send("GET", "/api/trash", "", Some(bob)).await;
+    assert!(trash["data"].as_array().unwrap().is_empty());
+    let (status, _) = send("POST", &restore, "", Some(bob)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (_, conflicts) = send("GET", "/api/conflicts", "", Some(bob)).await;
+    assert!(conflicts["data"].as_array().unwrap().is_empty());
+    let (status, _) = send("POST", &restore, "", Some(alice)).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_lifecycle_rules_are_scoped_per_user() {
+    use axum::http::StatusCode;
+
+    let (_temp_dir, _clock, send) = auth_app_with(|config| {
+        config.auth.as_mut().unwrap().user_namespaces = true;
+    })
+    .await;
+    let mut tokens = Vec::new();
+    for name in ["alice", "bob"] {
+        let body = format!(r#"{{"username":"{}","password":"correct horse"}}"#, name);
+        let (_, registered) = send("POST", "/api/auth/register", &body, None).await;
+        tokens.push(registered["data"]["token"].as_str().unwrap().to_string());
+    }
+    let (alice, bob) = (tokens[0].as_str(), tokens[1].as_str());
+    for token in [alice, bob] {
+        let (status, _) = send("PUT", "/api/files/docs/a.txt", "data", Some(token)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    let (_, me) = send("GET", "/api/auth/me", "", Some(alice)).await;
+    let alice_id = me["data"]["id"].as_str().unwrap().to_string();
+
+    // bob 的规则即使写上 alice 的完整路径，也只落在自己的目录下
+    for folder in ["docs".to_string(), format!("users/{}/docs", alice_id)] {
+        let body = format!(
+            r#"{{"folder":"{}","delete_after_days":0,"archive_after_days":1,"archive_to":"old"}}"#,
+            folder
+        );
+        let (status, rule) = send("POST", "/api/lifecycle/rules", &body, Some(bob)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(rule["data"]["folder"], folder.as_str());
+        assert_eq!(rule["data"]["archive_to"], "old");
+    }
+    let (_, rules) = send("GET", "/api/lifecycle/rules", "", Some(alice)).await;
+    assert!(rules["data"].as_array().unwrap().is_empty());
+    let (_, rules) = send("GET", "/api/lifecycle/rules", "", Some(bob)).await;
+    let rule = format!(
+        "/api/lifecycle/rules/{}",
+        rules["data"][0]["id"].as_str().unwrap()
+    );
+    let (status, _) = send("DELETE", &rule, "", Some(alice)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (_, preview) = send("GET", "/api/lifecycle/preview", "", Some(alice)).await;
+    assert!(preview["data"].as_array().unwrap().is_empty());
+    let (_, preview) = send("GET", "/api/lifecycle/preview", "", Some(bob)).await;
+    assert_eq!(preview["data"].as_array().unwrap().len(), 1);
+    assert_eq!(preview["data"][0]["path"], "docs/a.txt");
+
+    // alice 触发执行也不会运行 bob 的规则；bob 执行只删掉自己的文件
+    let (_, report) = send("POST", "/api/lifecycle/run", "", Some(alice)).await;
+    assert_eq!(report["data"]["applied"], 0);
+    let (_, report) = send("POST", "/api/lifecycle/run", "", Some(bob)).await;
+    assert_eq!(report["data"]["applied"], 1);
+    let (status, _) = send("GET", "/api/files/docs/a.txt", "", Some(bob)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send("GET", "/api/files/docs/a.txt", "", Some(alice)).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_network_acl_and_failed_login_bans() {
+    use axum::extract::ConnectInfo;
+    use std::net::SocketAddr;
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = make_config(&temp_dir);
+    config.auth = Some(rustcloud::config::AuthConfig {
+        jwt_secret: "test-secret".to_string(),
+        token_ttl_secs: 3600,
+        allow_registration: true,
+        user_namespaces: false,
+        default_quota_bytes: None,
+    });
+    config.admin_token = Some("admin-secret".to_string());
+    config.network = NetworkConfig {
+        allow: vec!["203.0.113.0/24".to_string(), "::1".to_string()],
+        deny: vec!["203.0.113.66".to_string()],
+        ban_after_failures: 3,
+        ..NetworkConfig::default()
+    };
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+    let app = rustcloud::api::routes::create_router(config).await;
+
+    let send = |ip: [u8; 4], method: &str, uri: &str, body: &str, admin: Option<&str>| {
+        let mut request = axum::http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .extension(ConnectInfo(SocketAddr::from((ip, 40000))));
+        if let Some(token) = admin {
+            request = request
+                .header("authorization", format!("Bearer {}", token))
+                .header("x-admin-token", "admin-secret");
+        }
+        let request = request
+            .body(axum::body::Body::from(body.to_string()))
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let retry_after = response.headers().get("retry-after").cloned();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let json: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+            (status, json, retry_after)
+        }
+    };
+    let alice = r#"{"username":"alice","password":"correct horse"}"#;
+    let wrong = r#"{"username":"alice","password":"wrong password"}"#;
+    let client = [203, 0, 113, 7];
+
+    let (status, _, _) = send(client, "POST", "/api/auth/register", alice, None).await;
+    assert_eq!(status, axum::http::StatusCode::CREATED);
+
+    // deny 优先于 allow；不在 allow 中的地址同样被拒绝
+    for ip in [[203, 0, 113, 66], [198, 51, 100, 1]] {
+        let (status, _, _) = send(ip, "GET", "/api/health", "", None).await;
+        assert_eq!(status, axum::http::StatusCode::FORBIDDEN, "{:?}", ip);
+    }
+
+    // 连续失败达到阈值后封禁，封禁期内正确的口令也被拦截
+    for _ in 0..3 {
+        let (status, _, _) = send(client, "POST", "/api/auth/login", wrong, None).await;
+        assert_eq!(status, axum::http::StatusCode::UNAUTHORIZED);
+    }
+    let (status, _, retry_after) = send(client, "POST", "/api/auth/login", alice, None).await;
+    assert_eq!(status, axum::http::StatusCode::TOO_MANY_REQUESTS);
+    assert!(retry_after.is_some());
+
+    // 其他地址不受影响；成功登录清零失败次数
+    let other = [203, 0, 113, 8];
+    for _ in 0..2 {
+        send(other, "POST", "/api/auth/login", wrong, None).await;
+    }
+    let (status, body, _) = send(other, "POST", "/api/auth/login", alice, None).await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    let token = body["data"]["token"].as_str().unwrap().to_string();
+    let admin = Some(token.as_str());
+    let (status, _, _) = send(other, "POST", "/api/auth/login", wrong, None).await;
+    assert_eq!(status, axum::http::StatusCode::UNAUTHORIZED);
+
+    // 管理员查看封禁、审计记录并提前解封
+    let (status, body, _) = send(other, "GET", "/api/security/bans", "", admin).await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    assert_eq!(body["data"].as_array().unwrap().len(), 1);
+    assert_eq!(body["data"][0]["ip"], "203.0.113.7");
+    let (_, body, _) = send(other, "GET", "/api/security/audit", "", admin).await;
+    let events: Vec<_> = body["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| (e["ip"].as_str().unwrap(), e["event"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        events,
+        [
+            ("203.0.113.66", "denied"),
+            ("198.51.100.1", "denied"),
+            ("203.0.113.7", "banned"),
+            ("203.0.113.7", "blocked"),
+        ]
+    );
+    let (status, _, _) = send(other, "DELETE", "/api/security/bans/203.0.113.7", "", admin).await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    let (status, _, _) = send(client, "POST", "/api/auth/login", alice, None).await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_security_headers_and_cross_site_writes() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = make_config(&temp_dir);
+    config.web = WebSecurityConfig {
+        trusted_origins: vec!["https://files.example.com".to_string()],
+        ..WebSecurityConfig::default()
+    };
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+    let app = rustcloud::api::routes::create_router(config).await;
+
+    let send = |method: &str, uri: &str, headers: &[(&str, &str)]| {
+        let mut request = axum::http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("host", "cloud.local:3000");
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let request = request.body(axum::body::Body::from("hello")).unwrap();
+        app.clone().oneshot(request)
+    };
+
+    let response = send("GET", "/api/health", &[]).await.unwrap();
+    let headers = response.headers();
+    assert_eq!(headers["x-content-type-options"], "nosniff");
+    assert_eq!(headers["x-frame-options"], "DENY");
+    assert!(headers["strict-transport-security"]
+        .to_str()
+        .unwrap()
+        .starts_with("max-age="));
+    assert!(headers["content-security-policy"]
+        .to_str()
+        .unwrap()
+        .contains("frame-ancestors 'none'"));
+
+    // 其他站点的页面不能写入；读取不受限制
+    let response = send(
+        "PUT",
+        "/api/files/a.txt",
+        &[("sec-fetch-site", "cross-site")],
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::FORBIDDEN);
+    assert_eq!(response.headers()["x-frame-options"], "DENY");
+    let response = send(
+        "PUT",
+        "/api/files/a.txt",
+        &[("origin", "https://evil.example")],
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::FORBIDDEN);
+    let response = send("PUT", "/api/files/a.txt", &[("origin", "null")])
+        .await
+        .unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::FORBIDDEN);
+    let response = send(
+        "GET",
+        "/api/files/a.txt",
+        &[("sec-fetch-site", "cross-site")],
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::NOT_FOUND);
+
+    // 同源页面、可信来源和不带浏览器头的客户端可以写入
+    for headers in [
+        &[("sec-fetch-site", "same-origin")][..],
+        &[("origin", "http://cloud.local:3000")],
+        &[
+            ("origin", "https://files.example.com"),
+            ("sec-fetch-site", "cross-site"),
+        ],
+        &[],
+    ] {
+        let response = send("PUT", "/api/files/a.txt", headers).await.unwrap();
+        assert_eq!(
+            response.status(),
+            axum::http::StatusCode::OK,
+            "{:?}",
+            headers
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_config_validation_reports_every_problem() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = make_config(&temp_dir);
+    config.port = 0;
+    config.chunk_size = 4 * 1024 * 1024;
+    config.validate().unwrap();
+    assert!(config.storage_path.is_dir());
+
+    // 端口被占用、存储路径是文件、分块过小、分片深度不支持、规则写错，一次全部报告
+    let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    config.port = taken.local_addr().unwrap().port();
+    let not_a_dir = temp_dir.path().join("file");
+    std::fs::write(&not_a_dir, b"").unwrap();
+    config.storage_path = not_a_dir;
+    config.chunk_size = 16;
+    config.shard_depth = 3;
+    config.network.deny = vec!["10.0.0.0/8".to_string(), "10.0.0.300".to_string()];
+    config.web.trusted_origins = vec!["files.example.com".to_string()];
+    config.upstream = Some(rustcloud::config::UpstreamConfig {
+        url: "ftp://cloud.example.com".to_string(),
+        token: None,
+    });
+    config.min_client_version = Some("latest".to_string());
+
+    let error = config.validate().unwrap_err().to_string();
+    assert!(error.contains("8 problem(s) found"), "{}", error);
+    for hint in [
+        "RUSTCLOUD_STORAGE_PATH",
+        "RUSTCLOUD_PORT",
+        "RUSTCLOUD_CHUNK_SIZE",
+        "RUSTCLOUD_SHARD_DEPTH",
+        "\"10.0.0.300\" in RUSTCLOUD_DENY_IPS",
+        "RUSTCLOUD_TRUSTED_ORIGINS",
+        "RUSTCLOUD_UPSTREAM_URL",
+        "RUSTCLOUD_MIN_CLIENT_VERSION",
+    ] {
+        assert!(error.contains(hint), "missing {}: {}", hint, error);
+    }
+}
+
+#[tokio::test]
+async fn test_outdated_clients_are_rejected() {
+    use axum::http::StatusCode;
+
+    let (temp_dir, repository, storage) = setup().await;
+    let mut config = make_config(&temp_dir);
+    config.min_client_version = Some("1.4.0".to_string());
+    let app = rustcloud::api::create_router_with_services(config, repository, storage).await;
+    let send_with = |method: &'static str,
+                     uri: &'static str,
+                     version: Option<&'static str>,
+                     origin: Option<&'static str>| {
+        let app = app.clone();
+        async move {
+            let mut request = axum::http::Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("content-type", "application/json");
+            if let Some(version) = version {
+                request = request.header(rustcloud_types::CLIENT_VERSION_HEADER, version);
+            }
+            if let Some(origin) = origin {
+                request = request.header("origin", origin);
+            }
+            let response = app
+                .oneshot(request.body(axum::body::Body::empty()).unwrap())
+                .await
+                .unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            (
+                status,
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap_or_default(),
+            )
+        }
+    };
+
+    let send =
+        |uri: &'static str, version: Option<&'static str>| send_with("GET", uri, version, None);
+
+    let (status, json) = send("/api/files", Some("1.3.9")).await;
+    assert_eq!(status, StatusCode::UPGRADE_REQUIRED);
+    let error = json["error"].as_str().unwrap();
+    assert!(error.contains("upgrade to 1.4.0 or newer"), "{}", error);
+    assert_eq!(json["data"]["min_client_version"], "1.4.0");
+    let (status, _) = send("/api/files", Some("garbage")).await;
+    assert_eq!(status, StatusCode::UPGRADE_REQUIRED);
+
+    // 按数字比较，不是按字符串；只读请求没有版本头也不受影响
+    for version in [Some("1.4.0"), Some("1.10.0"), Some("2.0.0-beta"), None] {
+        let (status, _) = send("/api/files", version).await;
+        assert_eq!(status, StatusCode::OK, "{:?}", version);
+    }
+
+    // 同步和写操作缺少版本头视为旧客户端，浏览器发起的请求除外
+    let (status, json) = send_with("POST", "/api/sync/plan", None, None).await;
+    assert_eq!(status, StatusCode::UPGRADE_REQUIRED);
+    assert_eq!(json["data"]["min_client_version"], "1.4.0");
+    let (status, _) = send_with("POST", "/api/files", None, None).await;
+    assert_eq!(status, StatusCode::UPGRADE_REQUIRED);
+    let (status, _) = send_with("POST", "/api/sync/plan", Some("1.4.0"), None).await;
+    assert_ne!(status, StatusCode::UPGRADE_REQUIRED);
+    let (status, _) = send_with(
+        "POST",
+        "/api/sync/plan",
+        None,
+        Some("http://localhost:8080"),
+    )
+    .await;
+    assert_ne!(status, StatusCode::UPGRADE_REQUIRED);
+
+    // 旧客户端仍能通过握手得知最低版本
+    let (status, json) = send("/api/capabilities", Some("0.1.0")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["data"]["min_client_version"], "1.4.0");
+    let (status, _) = send("/api/health", Some("0.1.0")).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_heartbeat_reports_pending_changes_from_other_devices() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = make_config(&temp_dir);
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+
+    let db_path = config.storage_path.join("db.json");
+    let repository = Arc::new(Repository::new(db_path).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+    let app = rustcloud::api::create_router_with_services(config, repository, storage).await;
+
+    let send = |method: &'static str, uri: String, device: Option<String>, body: String| {
+        let app = app.clone();
+        async move {
+            let mut request = axum::http::Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("content-type", "application/json");
+            if let Some(device) = device {
+                request = request.header("x-device-id", device);
+            }
+            let response = app
+                .oneshot(request.body(axum::body::Body::from(body)).unwrap())
+                .await
+                .unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            (
+                status,
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap_or_default(),
+            )
+        }
+    };
+    let register = |name: &'static str| async move {
+        let body = serde_json::json!({ "name": name }).to_string();
+        let (_, json) = send("POST", "/api/devices".to_string(), None, body).await;
+        json["data"]["id"].as_str().unwrap().to_string()
+    };
+    let pending = |device: String| async move {
+        let uri = format!("/api/devices/{}/heartbeat", device);
+        let (status, json) = send("POST", uri, None, String::new()).await;
+        assert_eq!(status, axum::http::StatusCode::OK);
+        assert_eq!(json["data"]["id"], device.as_str());
+        json["data"]["pending_changes"].as_bool().unwrap()
+    };
+    let sync = |device: String| async move {
+        let body = r#"{"local_files":[]}"#.to_string();
+        let (status, _) = send("POST", "/api/sync/plan".to_string(), Some(device), body).await;
+        assert_eq!(status, axum::http::StatusCode::OK);
+    };
+
+    let laptop = register("laptop").await;
+    let phone = register("phone").await;
+
+    // 从未同步过的设备总是应该同步
+    assert!(pending(laptop.clone()).await);
+    sync(laptop.clone()).await;
+    sync(phone.clone()).await;
+    assert!(!pending(laptop.clone()).await);
+
+    // 自己的改动不算待同步，读请求也不算
+    let (status, _) = send(
+        "PUT",
+        "/api/files/notes.txt".to_string(),
+        Some(laptop.clone()),
+        "v1".to_string(),
+    )
+    .await;
+    assert!(status.is_success());
+    send(
+        "GET",
+        "/api/files".to_string(),
+        Some(phone.clone()),
+        String::new(),
+    )
+    .await;
+    assert!(!pending(laptop.clone()).await);
+    assert!(pending(phone.clone()).await);
+
+    sync(phone.clone()).await;
+    assert!(!pending(phone.clone()).await);
+
+    // 未声明设备的改动对所有设备都是新的
+    send(
+        "DELETE",
+        "/api/files/notes.txt".to_string(),
+        None,
+        String::new(),
+    )
+    .await;
+    assert!(pending(laptop.clone()).await);
+    assert!(pending(phone.clone()).await);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_storage_hashes_through_limited_workers() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = make_config(&temp_dir);
+    config.port = 0;
+    config.chunk_size = 4 * 1024 * 1024;
+
+    // 0 个工作者或哈希任务多于阻塞线程都无法启动
+    config.workers = WorkerConfig {
+        blocking_threads: 2,
+        hash_workers: 4,
+        max_fs_ops: 0,
+    };
+    let error = config.validate().unwrap_err().to_string();
+    assert!(error.contains("2 problem(s) found"), "{}", error);
+    assert!(error.contains("RUSTCLOUD_MAX_FS_OPS"), "{}", error);
+    assert!(error.contains("RUSTCLOUD_HASH_WORKERS"), "{}", error);
+
+    config.workers = WorkerConfig {
+        blocking_threads: 2,
+        hash_workers: 1,
+        max_fs_ops: 1,
+    };
+    config.validate().unwrap();
+
+    // 只有一个哈希许可时，并发存储仍然得到正确的哈希和分块
+    let storage = Arc::new(
+        StorageService::new(StorageConfig {
+            storage_path: config.storage_path.clone(),
+            chunk_size: 1024,
+        })
+        .with_workers(&config.workers),
+    );
+    let mut tasks = Vec::new();
+    for i in 0..8u8 {
+        let storage = storage.clone();
+        let source = temp_dir.path().join(format!("source-{}", i));
+        let content: Vec<u8> = (0..3000).map(|n| (n % 251) as u8 ^ i).collect();
+        std::fs::write(&source, &content).unwrap();
+        tasks.push(tokio::spawn(async move {
+            let hashed = storage.compute_hash(&source).await.unwrap();
+            let (hash, size, chunks) = storage.store_chunked(&source).await.unwrap();
+            (content, hashed, hash, size, chunks)
+        }));
+    }
+    for task in tasks {
+        let (content, hashed, hash, size, chunks) = task.await.unwrap();
+        assert_eq!(hashed, storage.compute_content_hash(&content));
+        assert_eq!(hash, hashed);
+        assert_eq!(size, 3000);
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0], storage.compute_content_hash(&content[..1024]));
+        assert_eq!(storage.retrieve_chunked(&hash).await.unwrap(), content);
+    }
+}
+
+#[tokio::test]
+async fn test_uploads_based_on_stale_versions_become_conflicts() {
+    for strategy in [ConflictStrategy::Copy, ConflictStrategy::Reject] {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = make_config(&temp_dir);
+        config.conflicts = strategy;
+        std::fs::create_dir_all(&config.storage_path).unwrap();
+        let storage_path = config.storage_path.clone();
+
+        let db_path = config.storage_path.join("db.json");
+        let repository = Arc::new(Repository::new(db_path).await.unwrap());
+        let storage = Arc::new(StorageService::new(StorageConfig {
+            storage_path: config.storage_path.clone(),
+            chunk_size: 1024,
+        }));
+        let app = rustcloud::api::create_router_with_services(config, repository, storage).await;
+
+        let send = |method: &'static str, uri: &str, device: Option<&str>, body: &str| {
+            let app = app.clone();
+            let mut request = axum::http::Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("content-type", "application/json");
+            if let Some(device) = device {
+                request = request.header("x-device-id", device);
+            }
+            let request = request
+                .body(axum::body::Body::from(body.to_string()))
+                .unwrap();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                (
+                    status,
+                    serde_json::from_slice::<serde_json::Value>(&body).unwrap_or_default(),
+                )
+            }
+        };
+        let (_, laptop) = send("POST", "/api/devices", None, r#"{"name":"laptop"}"#).await;
+        let laptop = laptop["data"]["id"].as_str().unwrap().to_string();
+        let (_, phone) = send("POST", "/api/devices", None, r#"{"name":"phone"}"#).await;
+        let phone = phone["data"]["id"].as_str().unwrap().to_string();
+
+        // 两台设备都持有 v1
+        let (status, _) = send("PUT", "/api/files/docs/report.txt", Some(&laptop), "v1").await;
+        assert_eq!(status, axum::http::StatusCode::OK);
+        let (status, _) = send("GET", "/api/files/docs/report.txt/raw", Some(&phone), "").await;
+        assert_eq!(status, axum::http::StatusCode::OK);
+
+        // 笔记本改成 v2 后，手机基于 v1 的修改是冲突；本地没改过的手机应该下载
+        let (status, _) = send(
+            "PUT",
+            "/api/files/docs/report.txt",
+            Some(&laptop),
+            "laptop edit",
+        )
+        .await;
+        assert_eq!(status, axum::http::StatusCode::OK);
+        let plan = serde_json::json!({ "local_files": [{
+            "id": uuid::Uuid::new_v4(),
+            "path": "docs/report.txt",
+            "hash": rustcloud_client::sha256_hex(b"v1"),
+            "size": 2,
+            "version": 2,
+            "created_at": chrono::Utc::now(),
+            "updated_at": chrono::Utc::now(),
+        }]});
+        let (_, json) = send("POST", "/api/sync/plan", Some(&phone), &plan.to_string()).await;
+        assert_eq!(json["data"][0]["action"], "download");
+
+        let (status, json) = send(
+            "PUT",
+            "/api/files/docs/report.txt",
+            Some(&phone),
+            "phone edit",
+        )
+        .await;
+        let (_, conflicts) = send("GET", "/api/conflicts", None, "").await;
+        let conflict = &conflicts["data"][0];
+        assert_eq!(conflicts["data"].as_array().unwrap().len(), 1);
+        assert_eq!(conflict["path"], "docs/report.txt");
+        assert_eq!(conflict["device_name"], "phone");
+        assert_eq!(conflict["base_version"], 1);
+        // 原文件保留笔记本的修改
+        assert_eq!(
+            std::fs::read_to_string(storage_path.join("docs/report.txt")).unwrap(),
+            "laptop edit"
+        );
+
+        match strategy {
+            ConflictStrategy::Copy => {
+                let copy = "docs/report (conflicted copy from phone).txt";
+                assert_eq!(status, axum::http::StatusCode::OK);
+                assert_eq!(json["data"]["path"], copy);
+                assert_eq!(conflict["copy_path"], copy);
+                assert_eq!(
+                    std::fs::read_to_string(storage_path.join(copy)).unwrap(),
+                    "phone edit"
+                );
+
+                // 再次冲突时副本编号递增
+                send(
+                    "PUT",
+                    "/api/files/docs/report.txt",
+                    Some(&laptop),
+                    "laptop again",
+                )
+                .await;
+                let (_, json) = send(
+                    "PUT",
+                    "/api/files/docs/report.txt",
+                    Some(&phone),
+                    "phone again",
+                )
+                .await;
+                assert_eq!(
+                    json["data"]["path"],
+                    "docs/report (conflicted copy 2 from phone).txt"
+                );
+
+                // 删除副本即视为已解决
+                let uri = format!("/api/files/{}", copy.replace(' ', "%20"));
+                let (status, _) = send("DELETE", &uri, None, "").await;
+                assert_eq!(status, axum::http::StatusCode::OK);
+                let (_, conflicts) = send("GET", "/api/conflicts", None, "").await;
+                assert_eq!(conflicts["data"].as_array().unwrap().len(), 1);
+                assert_eq!(
+                    conflicts["data"][0]["copy_path"],
+                    "docs/report (conflicted copy 2 from phone).txt"
+                );
+            }
+            ConflictStrategy::Reject => {
+                assert_eq!(status, axum::http::StatusCode::CONFLICT);
+                assert!(json["error"].as_str().unwrap().contains("since version 1"));
+                assert!(conflict["copy_path"].is_null());
+
+                // 取回最新版本后再上传就不是冲突了
+                send("GET", "/api/files/docs/report.txt/raw", Some(&phone), "").await;
+                let (_, conflicts) = send("GET", "/api/conflicts", None, "").await;
+                assert!(conflicts["data"].as_array().unwrap().is_empty());
+                let (status, json) = send(
+                    "PUT",
+                    "/api/files/docs/report.txt",
+                    Some(&phone),
+                    "phone edit",
+                )
+                .await;
+                assert_eq!(status, axum::http::StatusCode::OK);
+                assert_eq!(json["data"]["version"], 3);
+            }
+        }
+
+        // 未声明设备的上传仍然直接覆盖
+        let (status, json) = send("PUT", "/api/files/docs/report.txt", None, "anonymous").await;
+        assert_eq!(status, axum::http::StatusCode::OK);
+        assert_eq!(json["data"]["path"], "docs/report.txt");
+    }
+}
+
+#[tokio::test]
+async fn test_delta_upload_rebuilds_file_from_signature() {
+    use rustcloud_types::delta::{self, FileSignature};
+
+    let temp_dir = TempDir::new().unwrap();
+    let config = make_config(&temp_dir);
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+    let storage_path = config.storage_path.clone();
+
+    let db_path = config.storage_path.join("db.json");
+    let repository = Arc::new(Repository::new(db_path).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+    let app = rustcloud::api::create_router_with_services(config, repository, storage).await;
+
+    let send = |method: &'static str, uri: &str, headers: &[(&str, String)], body: Vec<u8>| {
+        let app = app.clone();
+        let mut request = axum::http::Request::builder().method(method).uri(uri);
+        for (name, value) in headers {
+            request = request.header(*name, value);
+        }
+        let request = request.body(axum::body::Body::from(body)).unwrap();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            (
+                status,
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap_or_default(),
+            )
+        }
+    };
+
+    let original: Vec<u8> = (0..200_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    let (status, _) = send("PUT", "/api/files/big.bin", &[], original.clone()).await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+
+    let (status, json) = send("GET", "/api/files/big.bin/signature", &[], Vec::new()).await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    let signature: FileSignature = serde_json::from_value(json["data"].clone()).unwrap();
+    assert_eq!(signature.hash, rustcloud_client::sha256_hex(&original));
+    assert_eq!(signature.size, original.len() as u64);
+
+    // 中间插入一段、末尾改几个字节：只有改动附近的数据需要发送
+    let mut edited = original.clone();
+    edited.splice(100_000..100_000, b"inserted in the middle".iter().copied());
+    let end = edited.len();
+    edited[end - 3..].copy_from_slice(b"END");
+    let delta = delta::diff(&signature, &edited);
+    assert!(delta.literal_len() < 20_000, "{}", delta.literal_len());
+
+    let if_match = ("if-match", format!("\"{}\"", signature.hash));
+    let content_hash = ("x-content-sha256", rustcloud_client::sha256_hex(&edited));
+
+    // 缺少前提条件时拒绝
+    let (status, _) = send(
+        "PATCH",
+        "/api/files/big.bin/delta",
+        std::slice::from_ref(&content_hash),
+        delta.encode(),
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::PRECONDITION_REQUIRED);
+
+    let (status, json) = send(
+        "PATCH",
+        "/api/files/big.bin/delta",
+        &[if_match.clone(), content_hash.clone()],
+        delta.encode(),
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::OK, "{}", json);
+    assert_eq!(json["data"]["version"], 2);
+    assert_eq!(json["data"]["hash"], content_hash.1);
+    assert_eq!(std::fs::read(storage_path.join("big.bin")).unwrap(), edited);
+
+    // 签名之后内容已经变了
+    let (status, _) = send(
+        "PATCH",
+        "/api/files/big.bin/delta",
+        &[if_match, content_hash.clone()],
+        delta.encode(),
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::PRECONDITION_FAILED);
+
+    // 引用了旧内容中不存在的块
+    let current = ("if-match", format!("\"{}\"", content_hash.1));
+    let mut bogus = delta::Delta::new(signature.block_size);
+    bogus.push_copy(10_000);
+    let (status, _) = send(
+        "PATCH",
+        "/api/files/big.bin/delta",
+        &[current, content_hash],
+        bogus.encode(),
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::BAD_REQUEST);
+    assert_eq!(std::fs::read(storage_path.join("big.bin")).unwrap(), edited);
+}
+
+#[tokio::test]
+async fn test_chunked_objects_are_streamed_without_assembling() {
+    use futures_util::StreamExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = make_config(&temp_dir);
+    config.materialize_files = false;
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+
+    let db_path = config.storage_path.join("db.json");
+    let repository = Arc::new(Repository::new(db_path).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: config.storage_path.clone(),
+        chunk_size: 1024,
+    }));
+
+    let source = temp_dir.path().join("video.mp4");
+    let content: Vec<u8> = (0..5000u32).map(|n| (n % 251) as u8).collect();
+    std::fs::write(&source, &content).unwrap();
+    let (hash, size, chunks) = storage.store_chunked(&source).await.unwrap();
+    assert_eq!(chunks.len(), 5);
+    // 只有清单和各块，没有整个文件的对象
+    assert!(!storage.object_path(&hash).exists());
+
+    let (streamed_size, mut stream) = storage.stream_object(&hash).await.unwrap();
+    assert_eq!(streamed_size, 5000);
+    let mut pieces = 0;
+    let mut streamed = Vec::new();
+    while let Some(bytes) = stream.next().await {
+        streamed.extend_from_slice(&bytes.unwrap());
+        pieces += 1;
+    }
+    assert_eq!(streamed, content);
+    assert!(pieces >= chunks.len());
+
+    repository
+        .create_file(NewFileRecord {
+            path: "media/video.mp4".to_string(),
+            hash: Some(hash.clone()),
+            size,
+        })
+        .await
+        .unwrap();
+    let app = rustcloud::api::create_router_with_services(config, repository, storage).await;
+    let get = |headers: &[(&str, &str)]| {
+        let mut request = axum::http::Request::builder().uri("/api/files/media/video.mp4/raw");
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        app.clone()
+            .oneshot(request.body(axum::body::Body::empty()).unwrap())
+    };
+
+    let response = get(&[]).await.unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::OK);
+    assert_eq!(response.headers()["content-length"], "5000");
+    assert_eq!(response.headers()["content-type"], "video/mp4");
+    let etag = response.headers()["etag"].to_str().unwrap().to_string();
+    assert_eq!(etag, format!("\"{}\"", hash));
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(body.as_ref(), content.as_slice());
+
+    let response = get(&[("if-none-match", &etag)]).await.unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::NOT_MODIFIED);
+
+    // Range 跨越块边界、只给起点、只给长度
+    for (range, expected, content_range) in [
+        ("bytes=1000-2100", 1000..2101, "bytes 1000-2100/5000"),
+        ("bytes=4990-", 4990..5000, "bytes 4990-4999/5000"),
+        ("bytes=-100", 4900..5000, "bytes 4900-4999/5000"),
+        ("bytes=4000-9999", 4000..5000, "bytes 4000-4999/5000"),
+    ] {
+        let response = get(&[("range", range)]).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()["content-range"], content_range);
+        assert_eq!(
+            response.headers()["content-length"],
+            expected.len().to_string().as_str()
+        );
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body.as_ref(), &content[expected]);
+    }
+    let response = get(&[("range", "bytes=5000-")]).await.unwrap();
+    assert_eq!(
+        response.status(),
+        axum::http::StatusCode::RANGE_NOT_SATISFIABLE
+    );
+    assert_eq!(response.headers()["content-range"], "bytes */5000");
+    // If-Range 与 ETag 不符时返回完整内容
+    let response = get(&[("range", "bytes=0-9"), ("if-range", "\"stale\"")])
+        .await
+        .unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::OK);
+    assert_eq!(response.headers()["content-length"], "5000");
+}
+
+#[tokio::test]
+async fn test_chunked_objects_are_prefetched_in_order() {
+    use futures_util::StreamExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let storage = StorageService::new(StorageConfig {
+        storage_path: temp_dir.path().join("storage"),
+        chunk_size: 1024,
+    });
+
+    // 每块内容不同，顺序错了一定能发现
+    let source = temp_dir.path().join("large.bin");
+    let content: Vec<u8> = (0..40 * 1024 + 100u32)
+        .map(|n| ((n / 1024) as u8).wrapping_mul(31) ^ (n % 251) as u8)
+        .collect();
+    std::fs::write(&source, &content).unwrap();
+    let (hash, _, chunks) = storage.store_chunked(&source).await.unwrap();
+    assert_eq!(chunks.len(), 41);
+
+    let (_, stream) = storage.stream_object(&hash).await.unwrap();
+    let pieces: Vec<_> = stream.collect().await;
+    assert_eq!(pieces.len(), chunks.len());
+    let streamed: Vec<u8> = pieces
+        .into_iter()
+        .flat_map(|bytes| bytes.unwrap())
+        .collect();
+    assert_eq!(streamed, content);
+    assert_eq!(storage.retrieve_chunked(&hash).await.unwrap(), content);
+
+    // 中间缺一块：前面的块照常产出，轮到缺失的块时报错，不会跳过它
+    std::fs::remove_file(storage.object_path(&chunks[20])).unwrap();
+    let (_, stream) = storage.stream_object(&hash).await.unwrap();
+    let pieces: Vec<_> = stream.collect().await;
+    assert!(pieces[..20].iter().all(|bytes| bytes.is_ok()));
+    assert!(pieces[20].is_err());
+    assert!(storage.retrieve_chunked(&hash).await.is_err());
+}
+
+#[tokio::test]
+async fn test_storage_reshards_objects_to_new_depth() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = StorageConfig {
+        storage_path: temp_dir.path().join("storage"),
+        chunk_size: 1024,
+    };
+    let objects = config.storage_path.join("objects");
+    let shallow = StorageService::new(config.clone());
+    let (small, _) = shallow.store_content(b"small object").await.unwrap();
+    let source = temp_dir.path().join("large.bin");
+    let content: Vec<u8> = (0..5000u32).map(|n| (n % 251) as u8).collect();
+    std::fs::write(&source, &content).unwrap();
+    let (large, _, chunks) = shallow.store_chunked(&source).await.unwrap();
+    assert!(objects.join(&small[..2]).join(&small[2..]).exists());
+
+    // 改为四个字符之后，迁移之前旧位置的对象照常可读，新对象写到新位置
+    let deep = StorageService::new(config).with_shard_depth(4);
+    assert_eq!(deep.retrieve_file(&small).await.unwrap(), b"small object");
+    assert_eq!(deep.retrieve_chunked(&large).await.unwrap(), content);
+    let (fresh, _) = deep.store_content(b"fresh object").await.unwrap();
+    assert!(objects.join(&fresh[..4]).join(&fresh[4..]).exists());
+    // 迁移期间又上传了一份已有的内容
+    std::fs::create_dir_all(objects.join(&small[..4])).unwrap();
+    std::fs::write(objects.join(&small[..4]).join(&small[4..]), "small object").unwrap();
+
+    // 小对象、清单和各分块；新位置已有的小对象不再移动，旧的直接删除
+    let moved = deep.reshard().await.unwrap();
+    assert_eq!(moved, 2 + chunks.len());
+    assert_eq!(deep.reshard().await.unwrap(), 0);
+    assert!(!objects.join(&small[..2]).exists());
+    let manifest = format!("manifest-{}", large);
+    assert!(objects.join(&manifest[..4]).join(&manifest[4..]).exists());
+    for chunk in &chunks {
+        assert_eq!(
+            deep.object_path(chunk),
+            objects.join(&chunk[..4]).join(&chunk[4..])
+        );
+    }
+    assert_eq!(deep.retrieve_file(&small).await.unwrap(), b"small object");
+    assert_eq!(deep.retrieve_chunked(&large).await.unwrap(), content);
+
+    // 改回两个字符同样可以迁移回去
+    assert!(shallow.reshard().await.unwrap() > 0);
+    assert!(objects.join(&fresh[..2]).join(&fresh[2..]).exists());
+    assert_eq!(shallow.retrieve_chunked(&large).await.unwrap(), content);
+}
+
+#[tokio::test]
+async fn test_compressed_objects_read_back_unchanged() {
+    use futures_util::StreamExt;
+    use rustcloud::error::Error;
+
+    let temp_dir = TempDir::new().unwrap();
+    let config = StorageConfig {
+        storage_path: temp_dir.path().join("storage"),
+        chunk_size: 1024,
+    };
+    let objects = config.storage_path.join("objects");
+    let storage = StorageService::new(config.clone()).with_compression(true);
+    let compressed = |hash: &str| {
+        objects
+            .join(&hash[..2])
+            .join(format!("zstd-{}", &hash[2..]))
+    };
+    let manifest = |n: u32| {
+        format!(
+            r#"{{"name":"service-{n}","replicas":{},"image":"registry.example.com/team/service-{n}:1.{}","env":{{"LOG_LEVEL":"info","REGION":"eu-west-{}"}}}}"#,
+            n % 5,
+            n % 7,
+            n % 3
+        )
+        .into_bytes()
+    };
+    let read_back = |storage: StorageService, hash: String| async move {
+        let whole = storage.retrieve_file(&hash).await.unwrap();
+        assert_eq!(storage.retrieve_chunked(&hash).await.unwrap(), whole);
+        let (size, mut stream) = storage.stream_object(&hash).await.unwrap();
+        let mut streamed = Vec::new();
+        while let Some(bytes) = stream.next().await {
+            streamed.extend_from_slice(&bytes.unwrap());
+        }
+        assert_eq!(size, whole.len() as u64);
+        assert_eq!(streamed, whole);
+        storage.verify_object(&hash).await.unwrap();
+        whole
+    };
+
+    // 还没有字典时按普通 zstd 压缩；压缩后没有变小的原样存储
+    let repetitive = "hello world\n".repeat(200).into_bytes();
+    let (hash, _) = storage.store_content(&repetitive).await.unwrap();
+    assert!(compressed(&hash).exists());
+    assert!(!storage.object_path(&hash).exists());
+    assert!(std::fs::metadata(compressed(&hash)).unwrap().len() < repetitive.len() as u64);
+    assert_eq!(read_back(storage.clone(), hash.clone()).await, repetitive);
+    let (tiny, _) = storage.store_content(b"x").await.unwrap();
+    assert!(storage.object_path(&tiny).exists());
+    assert!(!compressed(&tiny).exists());
+
+    // 用已存储的小对象训练字典
+    assert!(storage.train_dictionary().await.unwrap().is_none());
+    for n in 0..200 {
+        storage.store_content(&manifest(n)).await.unwrap();
+    }
+    // 压缩的对象同样按哈希分片，不会集中在一个目录
+    assert!(!objects.join("zs").exists());
+    let trained = storage.train_dictionary().await.unwrap().unwrap();
+    assert!(trained.samples >= 200);
+    assert_eq!(storage.dictionary_id(), Some(trained.id));
+
+    // 之后的小对象用字典压缩，三种写入方式读回的内容都与原文一致
+    let content = manifest(1000);
+    let (hash, size) = storage.store_content(&content).await.unwrap();
+    assert_eq!(size, content.len() as u64);
+    let on_disk = std::fs::read(compressed(&hash)).unwrap();
+    assert!(on_disk.len() * 2 < content.len(), "{} bytes", on_disk.len());
+    assert_eq!(read_back(storage.clone(), hash.clone()).await, content);
+
+    let source = temp_dir.path().join("service.json");
+    std::fs::write(&source, manifest(1001)).unwrap();
+    let (from_file, _) = storage.store_file(&source).await.unwrap();
+    assert!(compressed(&from_file).exists());
+    assert_eq!(read_back(storage.clone(), from_file).await, manifest(1001));
+
+    let mut upload = storage.begin_upload().await.unwrap();
+    upload.write(&manifest(1002)).await.unwrap();
+    let (staged, _) = storage.store_staged(&mut upload).await.unwrap();
+    assert!(compressed(&staged).exists());
+    assert_eq!(
+        read_back(storage.clone(), staged.clone()).await,
+        manifest(1002)
+    );
+
+    // 再次存入同样内容不会多出一份；关闭压缩后照常读取，列出时按原始大小
+    storage.store_content(&content).await.unwrap();
+    assert!(!storage.object_path(&hash).exists());
+    let plain = StorageService::new(config.clone()).with_compression(false);
+    assert_eq!(read_back(plain.clone(), hash.clone()).await, content);
+    let stored = plain.stored_objects().await.unwrap();
+    assert!(stored.contains(&(hash.clone(), content.len() as u64)));
+    assert!(!stored.iter().any(|(name, _)| name.starts_with("zstd-")));
+
+    // 内容被篡改时按损坏处理
+    std::fs::write(compressed(&hash), b"not zstd").unwrap();
+    assert!(matches!(
+        plain.retrieve_file(&hash).await,
+        Err(Error::Corrupted(_))
+    ));
+
+    plain.delete_file(&hash).await.unwrap();
+    assert!(!plain.file_exists(&hash).await);
+
+    // 调整分片深度时压缩的对象跟着哈希迁移
+    let deep = StorageService::new(config).with_shard_depth(4);
+    assert!(deep.reshard().await.unwrap() > 0);
+    let moved = objects
+        .join(&staged[..4])
+        .join(format!("zstd-{}", &staged[4..]));
+    assert!(moved.exists());
+    assert_eq!(read_back(deep, staged).await, manifest(1002));
+}
+
+#[tokio::test]
+async fn test_compressed_objects_served_without_plain_files() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = make_config(&temp_dir);
+    config.materialize_files = false;
+    config.compress_objects = true;
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+    let storage_path = config.storage_path.clone();
+    let app = rustcloud::api::routes::create_router(config).await;
+    let get = |uri: &str, range: Option<&str>| {
+        let mut request = axum::http::Request::builder().uri(uri);
+        if let Some(range) = range {
+            request = request.header("range", range);
+        }
+        let request = request.body(axum::body::Body::empty()).unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            (status, body)
+        }
+    };
+
+    let content = "hello world\n".repeat(200);
+    let response = app
+        .clone()
+        .oneshot(
+            axum::http::Request::builder()
+                .method("PUT")
+                .uri("/api/files/notes.txt")
+                .body(axum::body::Body::from(content.clone()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::OK);
+    let hash = rustcloud_client::sha256_hex(content.as_bytes());
+    let objects = storage_path.join("objects");
+    assert!(objects
+        .join(&hash[..2])
+        .join(format!("zstd-{}", &hash[2..]))
+        .exists());
+    assert!(!storage_path.join("notes.txt").exists());
+
+    // 下载、续传、预览和签名都按原始内容
+    let (status, body) = get("/api/files/notes.txt/raw", None).await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    assert_eq!(body, content.as_bytes());
+    let (status, body) = get("/api/stream/notes.txt", Some("bytes=6-10")).await;
+    assert_eq!(status, axum::http::StatusCode::PARTIAL_CONTENT);
+    assert_eq!(&body[..], b"world");
+    let (status, body) = get("/api/preview/notes.txt?lines=1", None).await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    let preview: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(preview["data"]["content"], "hello world\n");
+    let (status, body) = get("/api/files/notes.txt/signature", None).await;
+    assert_eq!(status, axum::http::StatusCode::OK);
+    let signature: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(signature["data"]["hash"], hash);
+
+    // 还原用的暂存文件读完即删除
+    let leftovers: Vec<_> = std::fs::read_dir(&objects)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .filter(|name| name.contains(rustcloud::service::storage::TEMP_MARKER))
+        .collect();
+    assert!(leftovers.is_empty(), "{:?}", leftovers);
+}
+
+#[tokio::test]
+async fn test_hash_cache_skips_unchanged_files_and_follows_events() {
+    use rustcloud::watcher::file_watcher::{EventHandler, FileEvent};
+
+    let (temp_dir, repository, storage) = setup().await;
+    let root = temp_dir.path().join("storage");
+    let docs = root.join("docs");
+    std::fs::create_dir_all(&docs).unwrap();
+    let file = docs.join("a.txt");
+    std::fs::write(&file, "hello").unwrap();
+
+    let hello = storage.compute_hash(&file).await.unwrap();
+    assert_eq!(hello, storage.compute_content_hash(b"hello"));
+    assert_eq!(storage.cached_hashes(), 1);
+
+    // 大小和修改时间都没变时不重新读取，仍然返回缓存的哈希
+    let modified = std::fs::metadata(&file).unwrap().modified().unwrap();
+    std::fs::write(&file, "jello").unwrap();
+    std::fs::File::options()
+        .write(true)
+        .open(&file)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+    assert_eq!(storage.compute_hash(&file).await.unwrap(), hello);
+
+    // 监控事件让条目失效
+    let handler = EventHandler::new(&root, storage.clone(), repository.clone())
+        .with_move_grace(std::time::Duration::ZERO);
+    handler
+        .handle(FileEvent::Modified(file.clone()))
+        .await
+        .unwrap();
+    assert_eq!(
+        storage.compute_hash(&file).await.unwrap(),
+        storage.compute_content_hash(b"jello")
+    );
+    std::fs::remove_dir_all(&docs).unwrap();
+    handler.handle(FileEvent::Deleted(docs)).await.unwrap();
+    assert_eq!(storage.cached_hashes(), 0);
+
+    // 预热只采信大小一致、且记录之后没再修改过的文件
+    for (name, content, size) in [
+        ("b.txt", "bbb", 3),
+        ("c.txt", "ccc", 99),
+        ("d.txt", "ddd", 3),
+    ] {
+        std::fs::write(root.join(name), content).unwrap();
+        repository
+            .create_file(NewFileRecord {
+                path: name.to_string(),
+                hash: Some(format!("recorded-{}", name)),
+                size,
+            })
+            .await
+            .unwrap();
+    }
+    std::fs::File::options()
+        .write(true)
+        .open(root.join("d.txt"))
+        .unwrap()
+        .set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(3600))
+        .unwrap();
+    let warm = StorageService::new(StorageConfig {
+        storage_path: root.clone(),
+        chunk_size: 1024,
+    });
+    let records = repository.list_files().await.unwrap();
+    assert_eq!(warm.warm_hashes(&records).await, 1);
+    assert_eq!(
+        warm.compute_hash(&root.join("b.txt")).await.unwrap(),
+        "recorded-b.txt"
+    );
+    assert_eq!(
+        warm.compute_hash(&root.join("d.txt")).await.unwrap(),
+        warm.compute_content_hash(b"ddd")
+    );
+}
+
+#[tokio::test]
+async fn test_verify_reads_rejects_corrupted_objects_until_reuploaded() {
+    use rustcloud::error::Error;
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = make_config(&temp_dir);
+    config.materialize_files = false;
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+
+    let db_path = config.storage_path.join("db.json");
+    let repository = Arc::new(Repository::new(db_path).await.unwrap());
+    let storage = Arc::new(
+        StorageService::new(StorageConfig {
+            storage_path: config.storage_path.clone(),
+            chunk_size: 1024,
+        })
+        .with_verify_reads(true),
+    );
+
+    let content = b"report contents".to_vec();
+    let (hash, size) = storage.store_content(&content).await.unwrap();
+    repository
+        .create_file(NewFileRecord {
+            path: "docs/report.txt".to_string(),
+            hash: Some(hash.clone()),
+            size,
+        })
+        .await
+        .unwrap();
+    assert_eq!(storage.retrieve_file(&hash).await.unwrap(), content);
+
+    // 对象文件在磁盘上被改坏，大小不变
+    std::fs::write(storage.object_path(&hash), b"report CONTENTS").unwrap();
+    assert!(matches!(
+        storage.retrieve_file(&hash).await,
+        Err(Error::Corrupted(h)) if h == hash
+    ));
+    assert_eq!(storage.corrupted_objects(), vec![hash.clone()]);
+
+    let app =
+        rustcloud::api::create_router_with_services(config, repository, storage.clone()).await;
+    let get = |uri: &'static str| {
+        app.clone().oneshot(
+            axum::http::Request::builder()
+                .uri(uri)
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+    };
+    let response = get("/api/files/docs/report.txt/raw").await.unwrap();
+    assert_eq!(
+        response.status(),
+        axum::http::StatusCode::INTERNAL_SERVER_ERROR
+    );
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(!body.windows(8).any(|w| w == b"CONTENTS"));
+
+    let health: serde_json::Value = serde_json::from_slice(
+        &get("/api/health")
+            .await
+            .unwrap()
+            .into_body()
+            .collect()
+            .await
+            .unwrap()
+            .to_bytes(),
+    )
+    .unwrap();
+    assert_eq!(health["data"]["status"], "degraded");
+    assert_eq!(health["data"]["corrupted_objects"][0], hash.as_str());
+
+    // 再次存入同样的内容时覆盖损坏的对象
+    storage.store_content(&content).await.unwrap();
+    assert!(storage.corrupted_objects().is_empty());
+    let response = get("/api/files/docs/report.txt/raw").await.unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(body.as_ref(), content.as_slice());
+
+    // 分块存储的对象逐块校验，损坏的块和整个对象都记下
+    let source = temp_dir.path().join("video.mp4");
+    let video: Vec<u8> = (0..5000u32).map(|n| (n % 251) as u8).collect();
+    std::fs::write(&source, &video).unwrap();
+    let (video_hash, _, chunks) = storage.store_chunked(&source).await.unwrap();
+    std::fs::write(storage.object_path(&chunks[2]), vec![0u8; 1024]).unwrap();
+    assert!(matches!(
+        storage.stream_object(&video_hash).await,
+        Err(Error::Corrupted(_))
+    ));
+    let corrupted = storage.corrupted_objects();
+    assert!(corrupted.contains(&video_hash) && corrupted.contains(&chunks[2]));
+    storage.store_chunked(&source).await.unwrap();
+    assert!(storage.corrupted_objects().is_empty());
+    assert_eq!(storage.retrieve_chunked(&video_hash).await.unwrap(), video);
+}
+
+#[test]
+fn test_error_variants_map_to_status_codes() {
+    use axum::http::StatusCode;
+    use rustcloud::error::Error;
+
+    let full: Error = std::io::Error::from(std::io::ErrorKind::StorageFull).into();
+    assert!(matches!(full, Error::QuotaExceeded(_)));
+    assert_eq!(full.status_code(), StatusCode::INSUFFICIENT_STORAGE);
+    let other: Error = std::io::Error::from(std::io::ErrorKind::PermissionDenied).into();
+    assert!(matches!(other, Error::Io(_)));
+    assert_eq!(other.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+
+    let busy: Error =
+        rusqlite::Error::SqliteFailure(rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_BUSY), None)
+            .into();
+    assert!(matches!(busy, Error::Busy(_)));
+    assert_eq!(busy.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+
+    assert_eq!(
+        Error::Conflict("changed".into()).status_code(),
+        StatusCode::CONFLICT
+    );
+    assert_eq!(
+        Error::Unauthorized("token".into()).status_code(),
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        Error::Corrupted("abc".into()).status_code(),
+        StatusCode::INTERNAL_SERVER_ERROR
+    );
+    assert_eq!(
+        Error::NotFound("missing.txt".into()).status_code(),
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn test_append_only_folders_keep_history_and_refuse_deletes() {
+    use axum::http::StatusCode;
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = make_config(&temp_dir);
+    config.append_only = vec!["/backups/".to_string()];
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+    let app = rustcloud::api::routes::create_router(config).await;
+    let send = |method: &str, uri: &str, body: &str| {
+        let request = axum::http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(axum::body::Body::from(body.to_string()))
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let json: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+            (status, json)
+        }
+    };
+
+    // 上传和覆盖都允许，覆盖产生新版本
+    let (status, _) = send("PUT", "/api/files/backups/snap.bin", "first").await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, json) = send("PUT", "/api/files/backups/snap.bin", "second").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["data"]["version"], 2);
+
+    // 删除文件、目录本身或它的上级目录都被拒绝
+    for uri in [
+        "/api/files/backups/snap.bin",
+        "/api/files/backups?recursive=true",
+    ] {
+        let (status, json) = send("DELETE", uri, "").await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{}", uri);
+        assert!(json["error"].as_str().unwrap().contains("append-only"));
+    }
+    let (status, _) = send("PUT", "/api/files/scratch.txt", "temp").await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send("DELETE", "/api/files/scratch.txt", "").await;
+    assert_eq!(status, StatusCode::OK);
+
+    // 生命周期规则不处理只追加目录
+    let (status, _) = send(
+        "POST",
+        "/api/lifecycle/rules",
+        r#"{"folder":"backups","delete_after_days":0}"#,
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (_, json) = send("POST", "/api/lifecycle/run", "").await;
+    assert_eq!(json["data"]["applied"], 0);
+    let (status, _) = send("GET", "/api/files/backups/snap.bin/raw", "").await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_sync_directions_refuse_writes_against_the_direction() {
+    use axum::http::StatusCode;
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = make_config(&temp_dir);
+    config.upload_only = vec!["backups".to_string()];
+    config.download_only = vec!["/shared-assets/".to_string()];
+    std::fs::create_dir_all(&config.storage_path).unwrap();
+    let app = rustcloud::api::routes::create_router(config).await;
+    let send = |method: &str, uri: &str, body: &str| {
+        let request = axum::http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(axum::body::Body::from(body.to_string()))
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let json: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+            (status, json)
+        }
+    };
+
+    let (status, json) = send("GET", "/api/sync/directions", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        json["data"],
+        serde_json::json!([
+            {"folder": "backups", "direction": "upload-only"},
+            {"folder": "shared-assets", "direction": "download-only"},
+        ])
+    );
+
+    // 只上传目录：可以上传和移入，不能删除或移出，删除上级目录也不行
+    let (status, _) = send("PUT", "/api/files/backups/db.dump", "v1").await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send("PUT", "/api/files/notes.txt", "notes").await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(
+        "POST",
+        "/api/files/notes.txt/move",
+        r#"{"to": "backups/notes.txt"}"#,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    for (method, uri, body) in [
+        ("DELETE", "/api/files/backups/db.dump", ""),
+        ("DELETE", "/api/files/backups?recursive=true", ""),
+        (
+            "POST",
+            "/api/files/backups/db.dump/move",
+            r#"{"to": "db.dump"}"#,
+        ),
+    ] {
+        let (status, json) = send(method, uri, body).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{} {}", method, uri);
+        assert!(
+            json["error"]
+                .as_str()
+                .unwrap()
+                .contains("backups is upload-only"),
+            "{}",
+            json
+        );
+    }
+
+    // 只下载目录：任何写入都被拒绝
+    for (method, uri, body) in [
+        ("PUT", "/api/files/shared-assets/logo.png", "png"),
+        ("POST", "/api/files", r#"{"path": "shared-assets/icons"}"#),
+        (
+            "POST",
+            "/api/uploads",
+            r#"{"path": "shared-assets/video.mp4", "size": 10}"#,
+        ),
+        ("DELETE", "/api/files/shared-assets/logo.png", ""),
+        (
+            "POST",
+            "/api/files/backups/notes.txt/move",
+            r#"{"to": "shared-assets/notes.txt"}"#,
+        ),
+    ] {
+        let (status, json) = send(method, uri, body).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{} {}", method, uri);
+        assert!(json["error"].as_str().unwrap().contains("sync direction"));
+    }
+    let (status, _) = send("GET", "/api/files/backups/db.dump/raw", "").await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_append_only_tokens_can_upload_but_not_delete() {
+    use axum::http::StatusCode;
+
+    let (_temp_dir, _clock, send) = auth_app().await;
+    let (status, registered) = send(
+        "POST",
+        "/api/auth/register",
+        r#"{"username":"backup","password":"correct horse"}"#,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert!(registered["data"].get("append_only").is_none());
+    let owner = registered["data"]["token"].as_str().unwrap().to_string();
+
+    let (status, login) = send(
+        "POST",
+        "/api/auth/login",
+        r#"{"username":"backup","password":"correct horse","append_only":true}"#,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(login["data"]["append_only"], true);
+    let backup = login["data"]["token"].as_str().unwrap().to_string();
+
+    let (status, _) = send("PUT", "/api/files/repo/data.bin", "chunk", Some(&backup)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send("PUT", "/api/files/repo/data.bin", "chunk 2", Some(&backup)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send("DELETE", "/api/files/repo/data.bin", "", Some(&backup)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = send(
+        "POST",
+        "/api/lifecycle/rules",
+        r#"{"folder":"repo","delete_after_days":0}"#,
+        Some(&backup),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // 普通令牌照常可以清理
+    let (status, _) = send("DELETE", "/api/files/repo/data.bin", "", Some(&owner)).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_account_export_and_erasure() {
+    use axum::http::StatusCode;
+    use std::io::Read;
+
+    let (temp_dir, _clock, send) = auth_app().await;
+    let storage_path = temp_dir.path().join("storage");
+    let mut tokens = Vec::new();
+    for name in ["alice", "bob"] {
+        let body = format!(r#"{{"username":"{}","password":"correct horse"}}"#, name);
+        let (status, registered) = send("POST", "/api/auth/register", &body, None).await;
+        assert_eq!(status, StatusCode::CREATED);
+        tokens.push(registered["data"]["token"].as_str().unwrap().to_string());
+    }
+    let (alice, bob) = (tokens[0].as_str(), tokens[1].as_str());
+
+    for (path, content, token) in [
+        ("notes/diary.txt", "dear diary", alice),
+        ("notes/diary.txt", "dear diary, again", alice),
+        ("shared/bob.txt", "bob's file", bob),
+    ] {
+        let (status, _) = send("PUT", &format!("/api/files/{}", path), content, Some(token)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    let (status, _) = send(
+        "POST",
+        "/api/comments",
+        r#"{"path":"shared/bob.txt","text":"nice"}"#,
+        Some(alice),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (_, share) = send(
+        "POST",
+        "/api/shares",
+        r#"{"path":"notes/diary.txt"}"#,
+        Some(alice),
+    )
+    .await;
+    let share_id = share["data"]["id"].as_str().unwrap().to_string();
+
+    // 导出在后台进行，完成后才能下载
+    let (status, job) = send("POST", "/api/account/export", "", Some(alice)).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let id = job["data"]["id"].as_str().unwrap().to_string();
+    let job_uri = format!("/api/account/export/{}", id);
+    let (status, _) = send("GET", &job_uri, "", Some(bob)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let mut ready = serde_json::Value::Null;
+    for _ in 0..100 {
+        let (_, job) = send("GET", &job_uri, "", Some(alice)).await;
+        if job["data"]["status"] != "running" {
+            ready = job;
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(ready["data"]["status"], "ready", "{}", ready);
+    assert_eq!(ready["data"]["files"], 2);
+    let (status, _) = send("GET", &format!("{}/archive", job_uri), "", Some(alice)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let archive = storage_path
+        .join("objects/exports")
+        .join(format!("{}.zip", id));
+    let mut zip = zip::ZipArchive::new(std::fs::File::open(&archive).unwrap()).unwrap();
+    let mut read = |name: &str| {
+        let mut content = String::new();
+        zip.by_name(name)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    };
+    assert_eq!(read("files/notes/diary.txt"), "dear diary, again");
+    assert_eq!(read("versions/notes/diary.txt/v1"), "dear diary");
+    let account: serde_json::Value = serde_json::from_str(&read("account.json")).unwrap();
+    assert_eq!(account["user"]["username"], "alice");
+    assert_eq!(account["files"].as_array().unwrap().len(), 1);
+    assert_eq!(account["shares"][0]["id"], share_id.as_str());
+    assert_eq!(account["comments"][0]["text"], "nice");
+    assert!(account["audit"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|entry| entry["event"] == "exported"));
+    drop(zip);
+
+    // 删除必须带着刚申请的确认令牌
+    let (status, _) = send("DELETE", "/api/account", "", Some(alice)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, confirmation) = send("POST", "/api/account/erasure", "", Some(alice)).await;
+    assert_eq!(status, StatusCode::OK);
+    let token = confirmation["data"]["token"].as_str().unwrap();
+    let (status, _) = send(
+        "DELETE",
+        &format!("/api/account?confirmation={}", token),
+        "",
+        Some(bob),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, summary) = send(
+        "DELETE",
+        &format!("/api/account?confirmation={}", token),
+        "",
+        Some(alice),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", summary);
+    assert_eq!(summary["data"]["files"], 1);
+    assert_eq!(summary["data"]["shares"], 1);
+    assert_eq!(summary["data"]["comments"], 1);
+    assert!(summary["data"]["audit_entries"].as_u64().unwrap() >= 1);
+
+    let (status, _) = send("GET", "/api/auth/me", "", Some(alice)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(!storage_path.join("notes/diary.txt").exists());
+    assert!(!archive.exists());
+    let (status, _) = send("GET", "/api/files/notes/diary.txt", "", Some(bob)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send("GET", "/api/files/shared/bob.txt", "", Some(bob)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // 审计日志保留记录，但不再出现用户名
+    let audit = std::fs::read_to_string(storage_path.join("audit.jsonl")).unwrap();
+    assert!(audit.contains("\"erased\""));
+    assert!(!audit.contains("alice"));
+}
+
+#[tokio::test]
+async fn test_shredding_overwrites_unreferenced_objects_and_audits() {
+    use axum::http::StatusCode;
+    use rustcloud_client::sha256_hex;
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = make_config(&temp_dir);
+    config.shred_deleted = true;
+    config.lifecycle_interval_secs = 0;
+    let storage_path = config.storage_path.clone();
+    std::fs::create_dir_all(&storage_path).unwrap();
+    let app = rustcloud::api::routes::create_router(config).await;
+    let send = |method: &str, uri: &str, body: &str| {
+        let request = axum::http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(axum::body::Body::from(body.to_string()))
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let json: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+            (status, json)
+        }
+    };
+    let object = |content: &[u8]| {
+        let hash = sha256_hex(content);
+        storage_path
+            .join("objects")
+            .join(&hash[..2])
+            .join(&hash[2..])
+    };
+
+    // a.txt 有两个版本，第一个版本的内容同时被 docs/b.txt 使用
+    for (path, content) in [
+        ("a.txt", "first secret"),
+        ("a.txt", "second secret"),
+        ("docs/b.txt", "first secret"),
+    ] {
+        let (status, _) = send("PUT", &format!("/api/files/{}", path), content).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    assert!(object(b"first secret").exists());
+    assert!(object(b"second secret").exists());
+
+    let (status, _) = send("DELETE", "/api/files/a.txt", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!storage_path.join("a.txt").exists());
+    assert!(!object(b"second secret").exists());
+    // 仍被 docs/b.txt 引用的对象保留
+    assert!(object(b"first secret").exists());
+    let (status, _) = send("GET", "/api/files/docs/b.txt/raw", "").await;
+    assert_eq!(status, StatusCode::OK);
+
+    let audit = std::fs::read_to_string(storage_path.join("audit.jsonl")).unwrap();
+    let entries: Vec<serde_json::Value> = audit
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["event"], "shredded");
+    assert_eq!(entries[0]["method"], "DELETE");
+    assert_eq!(entries[0]["path"], "a.txt");
+    assert!(entries[0].get("ip").is_none());
+    // 明文文件和一个对象
+    assert_eq!(
+        entries[0]["detail"],
+        format!("2 file(s), {} bytes overwritten", 2 * "second secret".len())
+    );
+
+    // 生命周期规则的删除同样粉碎
+    let (status, _) = send(
+        "POST",
+        "/api/lifecycle/rules",
+        r#"{"folder":"docs","delete_after_days":0}"#,
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (_, json) = send("POST", "/api/lifecycle/run", "").await;
+    assert_eq!(json["data"]["applied"], 1);
+    assert!(!storage_path.join("docs/b.txt").exists());
+    assert!(!object(b"first secret").exists());
+    let audit = std::fs::read_to_string(storage_path.join("audit.jsonl")).unwrap();
+    let last: serde_json::Value = serde_json::from_str(audit.lines().last().unwrap()).unwrap();
+    assert_eq!(last["method"], "LIFECYCLE");
+    assert_eq!(last["path"], "docs/b.txt");
+}
+
+#[tokio::test]
+async fn test_deleted_files_move_to_trash_and_restore() {
+    use axum::http::StatusCode;
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = make_config(&temp_dir);
+    config.trash_retention_days = 30;
+    let storage_path = config.storage_path.clone();
+    std::fs::create_dir_all(&storage_path).unwrap();
+    let app = rustcloud::api::routes::create_router(config).await;
+    let send = |method: &str, uri: &str, body: &str| {
+        let request = axum::http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(axum::body::Body::from(body.to_string()))
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let json: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+            (status, json)
+        }
+    };
+
+    let (_, json) = send("GET", "/api/capabilities", "").await;
+    assert!(json["data"]["features"]
+        .as_array()
+        .unwrap()
+        .contains(&serde_json::json!("trash")));
+
+    for (path, content) in [
+        ("docs/a.txt", "first"),
+        ("docs/a.txt", "second"),
+        ("docs/b.txt", "other"),
+    ] {
+        let (status, _) = send("PUT", &format!("/api/files/{}", path), content).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    let (_, json) = send("GET", "/api/files/docs/a.txt", "").await;
+    let id = json["data"]["id"].clone();
+
+    let (status, json) = send("DELETE", "/api/files/docs?recursive=true", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["data"]["files"], 2);
+    let trash_id = json["data"]["trash_id"].as_str().unwrap().to_string();
+    assert!(!storage_path.join("docs").exists());
+    assert!(storage_path
+        .join("objects/trash")
+        .join(&trash_id)
+        .join("a.txt")
+        .exists());
+    let (status, _) = send("GET", "/api/files/docs/a.txt", "").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (_, json) = send("GET", "/api/trash", "").await;
+    let items = json["data"].as_array().unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0]["id"], trash_id);
+    assert_eq!(items[0]["path"], "docs");
+    assert_eq!(items[0]["files"], 2);
+    assert_eq!(items[0]["size"], ("second".len() + "other".len()) as u64);
+
+    // 原路径被占用时拒绝，条目留在回收站
+    let (status, _) = send("PUT", "/api/files/docs/new.txt", "new").await;
+    assert_eq!(status, StatusCode::OK);
+    let restore = format!("/api/trash/{}/restore", trash_id);
+    let (status, _) = send("POST", &restore, "").await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let (status, _) = send("DELETE", "/api/files/docs/new.txt", "").await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, json) = send("GET", "/api/trash", "").await;
+    let newest = json["data"][0]["id"].as_str().unwrap().to_string();
+    assert_eq!(json["data"][1]["id"], trash_id);
+
+    let (status, json) = send("POST", &restore, "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["data"]["path"], "docs");
+    let (_, json) = send("GET", "/api/files/docs/a.txt", "").await;
+    assert_eq!(json["data"]["id"], id);
+    assert_eq!(json["data"]["version"], 2);
+    assert_eq!(
+        std::fs::read_to_string(storage_path.join("docs/a.txt")).unwrap(),
+        "second"
+    );
+    let (_, json) = send("GET", "/api/files/docs/a.txt/versions", "").await;
+    assert_eq!(json["data"].as_array().unwrap().len(), 2);
+    let (status, _) = send("POST", &restore, "").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // 立即清除后内容不再保留
+    let (status, _) = send("DELETE", &format!("/api/trash/{}", newest), "").await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, json) = send("GET", "/api/trash", "").await;
+    assert!(json["data"].as_array().unwrap().is_empty());
+    assert!(!storage_path.join("objects/trash").join(&newest).exists());
+}
+
+#[tokio::test]
+async fn test_sync_deletions_move_to_trash() {
+    use axum::http::StatusCode;
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = make_config(&temp_dir);
+    config.trash_retention_days = 30;
+    let storage_path = config.storage_path.clone();
+    std::fs::create_dir_all(&storage_path).unwrap();
+    let repository = Arc::new(Repository::new(storage_path.join("db.json")).await.unwrap());
+    let storage = Arc::new(StorageService::new(StorageConfig {
+        storage_path: storage_path.clone(),
+        chunk_size: 1024,
+    }));
+    let app =
+        rustcloud::api::create_router_with_services(config, repository.clone(), storage).await;
+    let send = |method: &str, uri: &str, body: String| {
+        let request = axum::http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(axum::body::Body::from(body))
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let json: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+            (status, json)
+        }
+    };
+    let (_, device) = send("POST", "/api/devices", r#"{"name":"laptop"}"#.into()).await;
+    let device_id = device["data"]["id"].clone();
+    let execute = |file_id: uuid::Uuid| {
+        serde_json::json!({"file_id": file_id, "device_id": device_id, "action": "delete"})
+            .to_string()
+    };
+
+    for path in ["docs/a.txt", "legal/b.txt"] {
+        let (status, _) = send("PUT", &format!("/api/files/{}", path), "content".into()).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    let id = repository.get_file_by_path("docs/a.txt").await.unwrap().id;
+
+    let (status, _) = send("POST", "/api/sync/execute", execute(id)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!storage_path.join("docs/a.txt").exists());
+    let (status, _) = send("GET", "/api/files/docs/a.txt", String::new()).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (_, json) = send("GET", "/api/trash", String::new()).await;
+    let items = json["data"].as_array().unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0]["path"], "docs/a.txt");
+    let restore = format!("/api/trash/{}/restore", items[0]["id"].as_str().unwrap());
+    let (status, _) = send("POST", &restore, String::new()).await;
+    assert_eq!(status, StatusCode::OK);
+    let restored = repository.get_file_by_path("docs/a.txt").await.unwrap();
+    assert_eq!(restored.id, id);
+    assert_eq!(
+        std::fs::read_to_string(storage_path.join("docs/a.txt")).unwrap(),
+        "content"
+    );
+
+    // 保留中的文件返回 423，未知的文件返回 404
+    let (status, _) = send("POST", "/api/holds", r#"{"path":"legal"}"#.into()).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let held = repository.get_file_by_path("legal/b.txt").await.unwrap();
+    let (status, _) = send("POST", "/api/sync/execute", execute(held.id)).await;
+    assert_eq!(status, StatusCode::LOCKED);
+    assert!(storage_path.join("legal/b.txt").exists());
+    let (status, _) = send("POST", "/api/sync/execute", execute(uuid::Uuid::new_v4())).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_trash_sweep_purges_expired_entries() {
+    use rustcloud::db::NewTrashEntry;
+    use rustcloud::service::trash::TrashService;
+
+    let (temp_dir, repository, _) = setup().await;
+    let storage_path = temp_dir.path().join("storage");
+    let trash = TrashService::new((*repository).clone(), &storage_path, 7);
+    let file = storage_path.join("a.txt");
+    std::fs::create_dir_all(&storage_path).unwrap();
+    std::fs::write(&file, "content").unwrap();
+
+    let entry = trash
+        .put(
+            NewTrashEntry {
+                path: "a.txt".to_string(),
+                files: Vec::new(),
+                directories: Vec::new(),
+                size: 7,
+                deleted_by: None,
+            },
+            &file,
+        )
+        .await
+        .unwrap();
+    let content = storage_path
+        .join("objects/trash")
+        .join(entry.id.to_string());
+    assert!(!file.exists());
+    assert!(content.exists());
+    // 崩溃留下的、没有条目的内容
+    let orphan = storage_path.join("objects/trash/orphan");
+    std::fs::write(&orphan, "left over").unwrap();
+
+    assert_eq!(trash.sweep(entry.deleted_at).await.unwrap(), 0);
+    assert!(content.exists());
+    assert!(!orphan.exists());
+
+    let expired = entry.deleted_at + chrono::Duration::days(7);
+    assert_eq!(trash.sweep(expired).await.unwrap(), 1);
+    assert!(!content.exists());
+    assert!(repository.list_trash().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_rebuild_index_from_storage_tree_and_objects() {
+    use axum::http::StatusCode;
+    use rustcloud::db::{JSON_DB_FILE, SQLITE_DB_FILE};
+    use rustcloud::service::rebuild::{set_aside_database, IndexRebuilder};
+    use rustcloud_client::sha256_hex;
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = make_config(&temp_dir);
+    config.trash_retention_days = 30;
+    let storage_path = config.storage_path.clone();
+    std::fs::create_dir_all(&storage_path).unwrap();
+    let app = rustcloud::api::routes::create_router(config).await;
+    let send = |method: &str, uri: &str, body: &str| {
+        let request = axum::http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(axum::body::Body::from(body.to_string()))
+            .unwrap();
+        let app = app.clone();
+        async move { app.oneshot(request).await.unwrap().status() }
+    };
+    for (path, content) in [
+        ("a.txt", "first"),
+        ("a.txt", "second"),
+        ("docs/b.txt", "other"),
+        ("old/c.txt", "deleted"),
+    ] {
+        let status = send("PUT", &format!("/api/files/{}", path), content).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    assert_eq!(
+        send("POST", "/api/dirs/empty", "").await,
+        StatusCode::CREATED
+    );
+    assert_eq!(
+        send("DELETE", "/api/files/old?recursive=true", "").await,
+        StatusCode::OK
+    );
+    // 分块上传、没有落盘的对象
+    let storage = StorageService::new(StorageConfig {
+        storage_path: storage_path.clone(),
+        chunk_size: 1024,
+    });
+    let large: Vec<u8> = (0..5000u32).map(|n| (n % 251) as u8).collect();
+    std::fs::write(temp_dir.path().join("large.bin"), &large).unwrap();
+    let (large_hash, _, _) = storage
+        .store_chunked(&temp_dir.path().join("large.bin"))
+        .await
+        .unwrap();
+
+    // 数据库损坏
+    std::fs::write(storage_path.join(JSON_DB_FILE), "{ not json").unwrap();
+    let kept = set_aside_database(&storage_path, chrono::Utc::now())
+        .await
+        .unwrap();
+    assert_eq!(kept.len(), 1);
+    assert!(!storage_path.join(JSON_DB_FILE).exists());
+    assert!(!storage_path.join(SQLITE_DB_FILE).exists());
+    let repository = Repository::new(storage_path.join(JSON_DB_FILE))
+        .await
+        .unwrap();
+    let summary = IndexRebuilder::new(storage, repository.clone(), true)
+        .run()
+        .await
+        .unwrap();
+    assert_eq!(summary.files, 3);
+    assert_eq!(summary.directories, 1);
+    // 旧版本和分块对象
+    assert_eq!(summary.recovered, 2);
+    assert_eq!(summary.failed, 0);
+
+    let a = repository.get_file_by_path("a.txt").await.unwrap();
+    assert_eq!(a.hash, Some(sha256_hex(b"second")));
+    assert_eq!(a.version, 1);
+    repository.get_file_by_path("docs/b.txt").await.unwrap();
+    let directories = repository.list_directories().await.unwrap();
+    assert_eq!(directories.len(), 1);
+    assert_eq!(directories[0].path, "empty");
+
+    // 回收站中的内容移进了 lost+found/
+    let trashed = repository
+        .list_files()
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|r| r.path.starts_with("lost+found/trash-"))
+        .unwrap();
+    assert!(trashed.path.ends_with("/c.txt"));
+    let trash = storage_path.join("objects/trash");
+    assert_eq!(std::fs::read_dir(trash).unwrap().count(), 0);
+
+    let first = format!("lost+found/{}", sha256_hex(b"first"));
+    repository.get_file_by_path(&first).await.unwrap();
+    assert_eq!(
+        std::fs::read_to_string(storage_path.join(&first)).unwrap(),
+        "first"
+    );
+    let recovered = repository
+        .get_file_by_path(&format!("lost+found/{}", large_hash))
+        .await
+        .unwrap();
+    assert_eq!(recovered.size, large.len() as u64);
+    assert_eq!(
+        std::fs::read(storage_path.join(&recovered.path)).unwrap(),
+        large
+    );
 }
@@ -0,0 +1,154 @@
+//! 对象存储与分块的性质测试：任意内容写入后必须原样读回，且相同内容只存一份
+
+use proptest::prelude::*;
+use rustcloud::service::storage::{StorageConfig, StorageService};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use tempfile::TempDir;
+
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+fn storage_with_chunk_size(chunk_size: usize) -> (TempDir, StorageService) {
+    let temp_dir = TempDir::new().unwrap();
+    let storage = StorageService::new(StorageConfig {
+        storage_path: temp_dir.path().join("storage"),
+        chunk_size,
+    });
+    (temp_dir, storage)
+}
+
+fn sha256_hex(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+// objects/ 下的内容对象数量；manifest 存在 objects/ma/ 下，十六进制哈希不会以 "ma" 开头
+fn count_objects(storage: &StorageService) -> usize {
+    let objects = storage.storage_path().join("objects");
+    let Ok(prefixes) = std::fs::read_dir(&objects) else {
+        return 0;
+    };
+    prefixes
+        .flatten()
+        .filter(|dir| dir.file_name() != "ma")
+        .map(|dir| std::fs::read_dir(dir.path()).unwrap().count())
+        .sum()
+}
+
+fn write_source(dir: &Path, name: &str, content: &[u8]) -> std::path::PathBuf {
+    let path = dir.join(name);
+    std::fs::write(&path, content).unwrap();
+    path
+}
+
+/// 分块大小与恰好落在分块边界附近的长度（k * chunk - 1 / k * chunk / k * chunk + 1）
+fn chunk_and_content() -> impl Strategy<Value = (usize, Vec<u8>)> {
+    (1usize..=64, 0usize..6, -1isize..=1).prop_flat_map(|(chunk, k, delta)| {
+        let len = (k * chunk) as isize + delta;
+        let len = len.max(0) as usize;
+        (Just(chunk), prop::collection::vec(any::<u8>(), len))
+    })
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn prop_store_content_round_trips(content in prop::collection::vec(any::<u8>(), 0..4096)) {
+        let (_dir, storage) = storage_with_chunk_size(1024);
+        let (hash, size, retrieved) = block_on(async {
+            let (hash, size) = storage.store_content(&content).await.unwrap();
+            let retrieved = storage.retrieve_file(&hash).await.unwrap();
+            (hash, size, retrieved)
+        });
+
+        prop_assert_eq!(&hash, &sha256_hex(&content));
+        prop_assert_eq!(size, content.len() as u64);
+        prop_assert_eq!(retrieved, content);
+    }
+
+    #[test]
+    fn prop_store_content_deduplicates(content in prop::collection::vec(any::<u8>(), 0..2048)) {
+        let (_dir, storage) = storage_with_chunk_size(1024);
+        let (first, second) = block_on(async {
+            let first = storage.store_content(&content).await.unwrap();
+            let second = storage.store_content(&content).await.unwrap();
+            (first, second)
+        });
+
+        prop_assert_eq!(first, second);
+        prop_assert_eq!(count_objects(&storage), 1);
+    }
+
+    #[test]
+    fn prop_chunked_round_trips_across_boundaries((chunk_size, content) in chunk_and_content()) {
+        let (dir, storage) = storage_with_chunk_size(chunk_size);
+        let source = write_source(dir.path(), "source.bin", &content);
+        let ((hash, size, chunks), retrieved) = block_on(async {
+            let stored = storage.store_chunked(&source).await.unwrap();
+            let retrieved = storage.retrieve_chunked(&stored.0).await.unwrap();
+            (stored, retrieved)
+        });
+
+        prop_assert_eq!(&hash, &sha256_hex(&content));
+        prop_assert_eq!(size, content.len() as u64);
+        prop_assert_eq!(&retrieved, &content);
+
+        if content.len() <= chunk_size {
+            // 不超过一个分块时整体存储
+            prop_assert_eq!(chunks, vec![hash]);
+        } else {
+            prop_assert_eq!(chunks.len(), content.len().div_ceil(chunk_size));
+            for (chunk_hash, piece) in chunks.iter().zip(content.chunks(chunk_size)) {
+                prop_assert_eq!(chunk_hash, &sha256_hex(piece));
+            }
+        }
+    }
+
+    #[test]
+    fn prop_chunks_shared_between_files(
+        (chunk_size, prefix) in chunk_and_content(),
+        suffix in prop::collection::vec(any::<u8>(), 1..128),
+    ) {
+        prop_assume!(prefix.len() > chunk_size);
+        // 第二个文件与第一个文件共享完整分块的前缀
+        let aligned = prefix.len() / chunk_size * chunk_size;
+        let mut extended = prefix[..aligned].to_vec();
+        extended.extend_from_slice(&suffix);
+
+        let (dir, storage) = storage_with_chunk_size(chunk_size);
+        let first = write_source(dir.path(), "first.bin", &prefix);
+        let second = write_source(dir.path(), "second.bin", &extended);
+        let (first_chunks, second_chunks, objects_after_first, objects_after_second, again) =
+            block_on(async {
+                let (_, _, first_chunks) = storage.store_chunked(&first).await.unwrap();
+                let objects_after_first = count_objects(&storage);
+                let (_, _, second_chunks) = storage.store_chunked(&second).await.unwrap();
+                let objects_after_second = count_objects(&storage);
+                // 同一文件再次写入不产生新对象
+                storage.store_chunked(&second).await.unwrap();
+                (
+                    first_chunks,
+                    second_chunks,
+                    objects_after_first,
+                    objects_after_second,
+                    count_objects(&storage),
+                )
+            });
+
+        let shared = aligned / chunk_size;
+        prop_assert_eq!(&first_chunks[..shared], &second_chunks[..shared]);
+
+        let mut distinct: Vec<&String> = first_chunks.iter().chain(&second_chunks).collect();
+        distinct.sort();
+        distinct.dedup();
+        prop_assert!(objects_after_first <= first_chunks.len());
+        prop_assert_eq!(objects_after_second, distinct.len());
+        prop_assert_eq!(again, objects_after_second);
+    }
+}
@@ -0,0 +1,176 @@
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use rustcloud_client::{sha256_hex, Client};
+use crate::format::format_size;
+
+pub struct BenchmarkOptions {
+    pub sizes: Vec<u64>,
+    pub count: usize,
+    pub concurrency: usize,
+    pub remote_dir: String,
+    pub keep: bool,
+}
+
+pub async fn run(client: &Client, options: BenchmarkOptions) -> Result<()> {
+    if !client.health().await? {
+        anyhow::bail!("Cannot connect to server at {}", client.base_url());
+    }
+
+    let max_size = options.sizes.iter().copied().max().unwrap_or(0);
+    let hash_speed = measure_hashing(max_size.max(64 * 1024 * 1024));
+    println!("Hashing (SHA-256): {}/s", format_size(hash_speed as u64));
+    println!();
+
+    let local_dir = std::env::temp_dir().join(format!("rcloud-bench-{}", uuid::Uuid::new_v4()));
+    tokio::fs::create_dir_all(&local_dir).await?;
+
+    println!(
+        "{:<10} {:<10} {:>12} {:>10} {:>10} {:>10}",
+        "Size", "Op", "Throughput", "p50", "p90", "p99"
+    );
+    println!("{}", "-".repeat(67));
+
+    let mut remote_paths = Vec::new();
+    let result = async {
+        for &size in &options.sizes {
+            let paths: Vec<String> = (0..options.count)
+                .map(|i| format!("{}/{}-{}.bin", options.remote_dir, size, i))
+                .collect();
+            remote_paths.extend(paths.iter().cloned());
+
+            let upload = run_phase(
+                client,
+                &paths,
+                options.concurrency,
+                move |client, path| async move {
+                    let content = synthetic_data(size);
+                    client.upload_file(&path, &content).await?;
+                    Ok(())
+                },
+            )
+            .await?;
+            print_row(size, "upload", &upload, size * paths.len() as u64);
+
+            let dir = local_dir.clone();
+            let download = run_phase(client, &paths, options.concurrency, move |client, path| {
+                let target = dir.join(path.replace('/', "_"));
+                async move {
+                    client.download_to(&path, &target, |_| {}).await?;
+                    Ok(())
+                }
+            })
+            .await?;
+            print_row(size, "download", &download, size * paths.len() as u64);
+        }
+        Ok::<(), anyhow::Error>(())
+    }
+    .await;
+
+    let _ = tokio::fs::remove_dir_all(&local_dir).await;
+    if !options.keep {
+        for path in &remote_paths {
+            let _ = client.delete_file(path).await;
+        }
+    }
+
+    result
+}
+
+struct PhaseResult {
+    wall: Duration,
+    latencies: Vec<Duration>,
+}
+
+// 以有界并发执行一批传输，记录每次操作的耗时和整体耗时
+async fn run_phase<F, Fut>(
+    client: &Client,
+    paths: &[String],
+    concurrency: usize,
+    op: F,
+) -> Result<PhaseResult>
+where
+    F: Fn(Client, String) -> Fut + Clone + Send + 'static,
+    Fut: std::future::Future<Output = Result<()>> + Send,
+{
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    let mut tasks = JoinSet::new();
+    let started = Instant::now();
+
+    for path in paths {
+        let permit = semaphore.clone().acquire_owned().await?;
+        let client = client.clone();
+        let path = path.clone();
+        let op = op.clone();
+        tasks.spawn(async move {
+            let _permit = permit;
+            let begin = Instant::now();
+            op(client, path).await.map(|_| begin.elapsed())
+        });
+    }
+
+    let mut latencies = Vec::with_capacity(paths.len());
+    while let Some(joined) = tasks.join_next().await {
+        latencies.push(joined??);
+    }
+    latencies.sort();
+
+    Ok(PhaseResult {
+        wall: started.elapsed(),
+        latencies,
+    })
+}
+
+fn print_row(size: u64, op: &str, result: &PhaseResult, total_bytes: u64) {
+    let secs = result.wall.as_secs_f64().max(f64::EPSILON);
+    println!(
+        "{:<10} {:<10} {:>10}/s {:>10} {:>10} {:>10}",
+        format_size(size),
+        op,
+        format_size((total_bytes as f64 / secs) as u64),
+        format_duration(percentile(&result.latencies, 0.50)),
+        format_duration(percentile(&result.latencies, 0.90)),
+        format_duration(percentile(&result.latencies, 0.99)),
+    );
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn measure_hashing(size: u64) -> f64 {
+    let data = synthetic_data(size);
+    let started = Instant::now();
+    let _ = sha256_hex(&data);
+    data.len() as f64 / started.elapsed().as_secs_f64().max(f64::EPSILON)
+}
+
+// 每次生成不同的可打印伪随机内容，避免被服务端去重短路
+fn synthetic_data(size: u64) -> Vec<u8> {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+    let mut state = uuid::Uuid::new_v4().as_u128() as u64 | 1;
+    (0..size)
+        .map(|_| {
+            // xorshift64
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            ALPHABET[(state % ALPHABET.len() as u64) as usize]
+        })
+        .collect()
+}
+
+fn format_duration(d: Duration) -> String {
+    if d.as_secs() >= 1 {
+        format!("{:.2}s", d.as_secs_f64())
+    } else {
+        format!("{:.1}ms", d.as_secs_f64() * 1000.0)
+    }
+}
@@ -1,18 +1,129 @@
 use anyhow::Result;
 
 use crate::config;
+use crate::credentials;
+use crate::schedule::parse_schedule;
 
-pub fn run(server: Option<&str>, device_name: Option<&str>) -> Result<()> {
+#[derive(Debug, Default)]
+pub struct ConfigUpdate {
+    pub server: Option<String>,
+    pub add_mirror: Option<String>,
+    pub remove_mirror: Option<String>,
+    pub add_ignore: Option<String>,
+    pub remove_ignore: Option<String>,
+    pub device_name: Option<String>,
+    pub proxy: Option<String>,
+    pub ca_cert: Option<String>,
+    pub connect_timeout: Option<u64>,
+    pub read_timeout: Option<u64>,
+    /// 空字符串表示清除
+    pub admin_token: Option<String>,
+    /// 空字符串表示清除
+    pub sync_schedule: Option<String>,
+    pub pre_sync_hook: Option<String>,
+    pub post_sync_hook: Option<String>,
+}
+
+/// `server` 是本次调用实际连接的服务端（可能来自全局 --server），令牌按它保存
+pub fn run(update: ConfigUpdate, server: &str) -> Result<()> {
     let mut cfg = config::load()?;
+    let token_server = update.server.clone().unwrap_or_else(|| server.to_string());
 
-    if let Some(s) = server {
-        cfg.server = s.to_string();
+    if let Some(s) = update.server {
         println!("Server set to: {}", s);
+        cfg.server = s;
+    }
+
+    if let Some(mirror) = update.add_mirror {
+        let mirror = mirror.trim_end_matches('/').to_string();
+        if cfg.mirrors.contains(&mirror) {
+            println!("Mirror already configured: {}", mirror);
+        } else {
+            println!("Mirror added: {}", mirror);
+            cfg.mirrors.push(mirror);
+        }
+    }
+
+    if let Some(mirror) = update.remove_mirror {
+        let mirror = mirror.trim_end_matches('/');
+        let before = cfg.mirrors.len();
+        cfg.mirrors.retain(|m| m != mirror);
+        if cfg.mirrors.len() == before {
+            anyhow::bail!("No such mirror: {}", mirror);
+        }
+        println!("Mirror removed: {}", mirror);
+    }
+
+    if let Some(pattern) = update.add_ignore {
+        if cfg.ignore.contains(&pattern) {
+            println!("Ignore pattern already configured: {}", pattern);
+        } else {
+            println!("Ignoring: {}", pattern);
+            cfg.ignore.push(pattern);
+        }
     }
 
-    if let Some(name) = device_name {
-        cfg.device_name = Some(name.to_string());
+    if let Some(pattern) = update.remove_ignore {
+        let before = cfg.ignore.len();
+        cfg.ignore.retain(|p| *p != pattern);
+        if cfg.ignore.len() == before {
+            anyhow::bail!("No such ignore pattern: {}", pattern);
+        }
+        println!("Ignore pattern removed: {}", pattern);
+    }
+
+    if let Some(name) = update.device_name {
         println!("Device name set to: {}", name);
+        cfg.device_name = Some(name);
+    }
+
+    if let Some(proxy) = update.proxy {
+        println!("Proxy set to: {}", proxy);
+        cfg.http.proxy = Some(proxy);
+    }
+
+    if let Some(ca_cert) = update.ca_cert {
+        println!("CA certificate set to: {}", ca_cert);
+        cfg.http.ca_cert = Some(ca_cert.into());
+    }
+
+    if let Some(secs) = update.connect_timeout {
+        println!("Connect timeout set to: {}s", secs);
+        cfg.http.connect_timeout_secs = secs;
+    }
+
+    if let Some(secs) = update.read_timeout {
+        println!("Read timeout set to: {}s", secs);
+        cfg.http.read_timeout_secs = secs;
+    }
+
+    if let Some(token) = update.admin_token {
+        if token.is_empty() {
+            credentials::delete(&token_server, credentials::ADMIN_TOKEN)?;
+            println!("Admin token cleared");
+        } else {
+            let location = credentials::set(&token_server, credentials::ADMIN_TOKEN, &token)?;
+            println!("Admin token saved to {}", location);
+        }
+    }
+
+    if let Some(expression) = update.sync_schedule {
+        if expression.trim().is_empty() {
+            println!("Sync schedule cleared");
+            cfg.sync_schedule = None;
+        } else {
+            let schedule = parse_schedule(&expression).map_err(anyhow::Error::msg)?;
+            println!("Sync schedule set to: {}", schedule);
+            cfg.sync_schedule = Some(schedule.to_string());
+        }
+    }
+
+    if let Some(command) = update.pre_sync_hook {
+        set_hook("Pre-sync", &mut cfg.hooks.pre_sync, command);
+    }
+
+    if let Some(command) = update.post_sync_hook {
+        set_hook("Post-sync", &mut cfg.hooks.post_sync, command);
     }
 
     config::save(&cfg)?;
@@ -20,3 +131,14 @@ pub fn run(server: Option<&str>, device_name: Option<&str>) -> Result<()> {
 
     Ok(())
 }
+
+/// 命令为空时清除钩子
+fn set_hook(name: &str, hook: &mut Option<String>, command: String) {
+    if command.trim().is_empty() {
+        println!("{} hook cleared", name);
+        *hook = None;
+    } else {
+        println!("{} hook set to: {}", name, command);
+        *hook = Some(command);
+    }
+}
@@ -0,0 +1,45 @@
+use anyhow::Result;
+use chrono::Local;
+
+use crate::locale::LocaleFormat;
+use crate::output::say;
+use rustcloud_client::{feature, Client, ConflictInfo};
+
+/// `rcloud conflicts`：列出尚未解决的同步冲突
+pub async fn run(client: &Client) -> Result<()> {
+    client.require(feature::CONFLICTS).await?;
+    let conflicts = client.list_conflicts().await?;
+    if conflicts.is_empty() {
+        say!("No conflicts");
+        return Ok(());
+    }
+
+    let locale = LocaleFormat::current();
+    for conflict in &conflicts {
+        println!("{}", describe(conflict, locale));
+    }
+    say!(
+        "\n{} conflict(s). Merge each copy into the original and delete the copy to resolve it",
+        conflicts.len()
+    );
+    Ok(())
+}
+
+fn describe(conflict: &ConflictInfo, locale: LocaleFormat) -> String {
+    let base = conflict
+        .base_version
+        .map(|v| format!(" (edited from v{})", v))
+        .unwrap_or_default();
+    let outcome = match &conflict.copy_path {
+        Some(copy) => format!("saved as {}", copy),
+        None => "upload rejected".to_string(),
+    };
+    format!(
+        "{}  {}  {}{}: {}",
+        locale.datetime(&conflict.detected_at.with_timezone(&Local)),
+        conflict.path,
+        conflict.device_name,
+        base,
+        outcome
+    )
+}
@@ -0,0 +1,17 @@
+use anyhow::Result;
+
+use crate::format::format_size;
+use crate::output::say;
+use rustcloud_client::Client;
+
+/// `rcloud cp`：在服务端复制文件，副本与原文件共用存储，不占用上传流量
+pub async fn run(client: &Client, from: &str, to: &str) -> Result<()> {
+    let info = client.copy_file(from, to).await?;
+    say!(
+        "Copied {} -> {} ({})",
+        from,
+        info.path,
+        format_size(info.size)
+    );
+    Ok(())
+}
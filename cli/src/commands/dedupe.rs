@@ -0,0 +1,34 @@
+use anyhow::Result;
+
+use rustcloud_client::Client;
+use crate::format::format_size;
+
+/// `rcloud dedupe --report`：列出服务器上内容相同的文件
+pub async fn report(client: &Client, min_size: u64) -> Result<()> {
+    let report = client.duplicates_report(min_size).await?;
+
+    if report.groups.is_empty() {
+        println!("No duplicate files found");
+        return Ok(());
+    }
+
+    for group in &report.groups {
+        println!(
+            "{} copies of {} ({} reclaimable, sha256 {})",
+            group.paths.len(),
+            format_size(group.size),
+            format_size(group.reclaimable_bytes),
+            &group.hash[..group.hash.len().min(12)]
+        );
+        for path in &group.paths {
+            println!("  {}", path);
+        }
+    }
+
+    println!(
+        "\n{} group(s), {} reclaimable by keeping one copy of each",
+        report.total_groups,
+        format_size(report.reclaimable_bytes)
+    );
+    Ok(())
+}
@@ -0,0 +1,433 @@
+use std::path::Path;
+use std::time::Instant;
+
+use anyhow::Result;
+use rustcloud_client::atomic::is_temp_file;
+use rustcloud_client::{feature, Client, ServiceHealth};
+
+use crate::config::{self, Config};
+use crate::credentials;
+use crate::encryption::PASSPHRASE_ENV;
+use crate::schedule::parse_schedule;
+
+/// 时钟偏差超过该值时提醒，分享过期和定时同步都依赖双方时间一致
+const MAX_CLOCK_SKEW_SECS: i64 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Level {
+    Ok,
+    Warn,
+    Fail,
+}
+
+struct Check {
+    level: Level,
+    name: &'static str,
+    detail: String,
+}
+
+#[derive(Default)]
+struct Report {
+    checks: Vec<Check>,
+}
+
+impl Report {
+    fn ok(&mut self, name: &'static str, detail: impl Into<String>) {
+        self.push(Level::Ok, name, detail.into(), None);
+    }
+
+    fn warn(&mut self, name: &'static str, detail: impl Into<String>, fix: impl Into<String>) {
+        self.push(Level::Warn, name, detail.into(), Some(fix.into()));
+    }
+
+    fn fail(&mut self, name: &'static str, detail: impl Into<String>, fix: impl Into<String>) {
+        self.push(Level::Fail, name, detail.into(), Some(fix.into()));
+    }
+
+    fn push(&mut self, level: Level, name: &'static str, detail: String, fix: Option<String>) {
+        let tag = match level {
+            Level::Ok => "ok",
+            Level::Warn => "warn",
+            Level::Fail => "FAIL",
+        };
+        println!("[{:>4}] {}: {}", tag, name, detail);
+        if let Some(fix) = fix {
+            println!("       fix: {}", fix);
+        }
+        self.checks.push(Check {
+            level,
+            name,
+            detail,
+        });
+    }
+
+    fn worst(&self) -> Level {
+        self.checks
+            .iter()
+            .map(|c| c.level)
+            .max()
+            .unwrap_or(Level::Ok)
+    }
+}
+
+/// `rcloud doctor`：逐项检查客户端环境，每个问题附带修复建议；有失败项时返回错误
+pub async fn run(client: Result<Client>, sync_path: Option<&str>) -> Result<()> {
+    let mut report = Report::default();
+
+    let cfg = check_config(&mut report);
+    let sync_path = sync_path
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(|| cfg.sync_path.clone());
+    check_sync_path(&mut report, &sync_path);
+
+    match client {
+        Ok(client) => check_server(&mut report, &client, &cfg).await,
+        Err(e) => report.fail(
+            "client",
+            format!("{:#}", e),
+            "Check --server, the proxy and the CA certificate settings",
+        ),
+    }
+
+    let failed: Vec<_> = report
+        .checks
+        .iter()
+        .filter(|c| c.level == Level::Fail)
+        .map(|c| format!("{} ({})", c.name, c.detail))
+        .collect();
+    let warnings = report
+        .checks
+        .iter()
+        .filter(|c| c.level == Level::Warn)
+        .count();
+    println!();
+    match report.worst() {
+        Level::Ok => println!("All checks passed"),
+        Level::Warn => println!("{} warning(s), no failures", warnings),
+        Level::Fail => anyhow::bail!("{} check(s) failed: {}", failed.len(), failed.join("; ")),
+    }
+    Ok(())
+}
+
+fn check_config(report: &mut Report) -> Config {
+    let path = config::config_path()
+        .map(|p| p.display().to_string())
+        .unwrap_or_default();
+    let cfg = match config::load() {
+        Ok(cfg) => {
+            report.ok("config", format!("loaded {}", path));
+            cfg
+        }
+        Err(e) => {
+            report.fail(
+                "config",
+                format!("{} is invalid: {:#}", path, e),
+                format!("Fix or delete {} and run `rcloud config` again", path),
+            );
+            return Config::default();
+        }
+    };
+
+    if let Err(e) = credentials::get(&cfg.server, credentials::ADMIN_TOKEN) {
+        report.fail(
+            "credentials",
+            format!("{:#}", e),
+            format!(
+                "Set {} to the passphrase used when saving, or store the token again with `rcloud config --admin-token`",
+                PASSPHRASE_ENV
+            ),
+        );
+    }
+
+    if let Some(ca_cert) = &cfg.http.ca_cert {
+        if !ca_cert.is_file() {
+            report.fail(
+                "ca certificate",
+                format!("{} does not exist", ca_cert.display()),
+                "Point `rcloud config --ca-cert` at an existing PEM file",
+            );
+        }
+    }
+    if cfg.http.insecure {
+        report.warn(
+            "tls",
+            "certificate verification is disabled",
+            "Trust the server certificate with --ca-cert instead of --insecure",
+        );
+    }
+    if cfg.http.connect_timeout_secs == 0 || cfg.http.read_timeout_secs == 0 {
+        report.fail(
+            "timeouts",
+            "a timeout of 0 seconds makes every request fail",
+            "Set `rcloud config --connect-timeout` and `--read-timeout` to at least 1",
+        );
+    }
+    if let Some(expression) = &cfg.sync_schedule {
+        if let Err(e) = parse_schedule(expression) {
+            report.fail(
+                "schedule",
+                e,
+                "Set a valid cron expression with `rcloud config --sync-schedule`",
+            );
+        }
+    }
+    cfg
+}
+
+fn check_sync_path(report: &mut Report, sync_path: &Path) {
+    if !sync_path.exists() {
+        let creatable = sync_path
+            .ancestors()
+            .skip(1)
+            .find(|p| p.exists())
+            .is_some_and(is_writable);
+        if creatable {
+            report.ok(
+                "sync path",
+                format!("{} will be created on first sync", sync_path.display()),
+            );
+        } else {
+            report.fail(
+                "sync path",
+                format!(
+                    "{} does not exist and cannot be created",
+                    sync_path.display()
+                ),
+                "Create the directory or pass a writable --path",
+            );
+        }
+        return;
+    }
+    if !sync_path.is_dir() {
+        report.fail(
+            "sync path",
+            format!("{} is not a directory", sync_path.display()),
+            "Pass a directory with --path",
+        );
+        return;
+    }
+    if !is_writable(sync_path) {
+        report.fail(
+            "sync path",
+            format!("{} is not writable", sync_path.display()),
+            "Fix the directory permissions so downloads can be written",
+        );
+        return;
+    }
+    report.ok("sync path", format!("{} is writable", sync_path.display()));
+
+    // 中断的下载会留下临时文件，同步时跳过，但不会自动清理
+    let leftovers = count_temp_files(sync_path);
+    if leftovers > 0 {
+        report.warn(
+            "pending transfers",
+            format!("{} interrupted transfer(s) left temporary files", leftovers),
+            format!(
+                "Delete the *{}* files under {} once no rcloud process is running",
+                rustcloud_client::atomic::TEMP_MARKER,
+                sync_path.display()
+            ),
+        );
+    }
+}
+
+fn is_writable(dir: &Path) -> bool {
+    let probe = dir.join(format!(".rcloud-doctor-{}", std::process::id()));
+    let writable = std::fs::write(&probe, b"").is_ok();
+    let _ = std::fs::remove_file(&probe);
+    writable
+}
+
+fn count_temp_files(dir: &Path) -> usize {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| {
+            let path = entry.path();
+            if path.is_dir() {
+                count_temp_files(&path)
+            } else {
+                usize::from(is_temp_file(&path))
+            }
+        })
+        .sum()
+}
+
+async fn check_server(report: &mut Report, client: &Client, cfg: &Config) {
+    let started = Instant::now();
+    let sent_at = chrono::Utc::now();
+    let status = match client.server_status().await {
+        Ok(status) => status,
+        Err(e) => {
+            report.fail(
+                "connectivity",
+                format!("cannot reach {}: {:#}", client.base_url(), e),
+                "Check that the server is running and `rcloud config --server` is correct",
+            );
+            return;
+        }
+    };
+    let elapsed = started.elapsed();
+    report.ok(
+        "connectivity",
+        format!(
+            "{} answered in {} ms",
+            client.base_url(),
+            elapsed.as_millis()
+        ),
+    );
+
+    let compatibility = client.check_compatibility().await;
+    match &compatibility {
+        Ok(Some(capabilities)) if capabilities.protocol > 0 => report.ok(
+            "protocol",
+            format!(
+                "{} (features: {})",
+                capabilities.protocol,
+                capabilities.features.join(", ")
+            ),
+        ),
+        Ok(Some(_)) => report.warn(
+            "protocol",
+            "the server does not report its capabilities",
+            "Upgrade the server so the client can adapt to it",
+        ),
+        Ok(None) => report.warn(
+            "protocol",
+            "could not fetch the server capabilities",
+            "Check the server logs for errors on /api/capabilities",
+        ),
+        Err(e) => report.fail("protocol", format!("{:#}", e), "Upgrade rcloud"),
+    }
+
+    if let Ok(Some(capabilities)) = &compatibility {
+        if capabilities.supports(feature::AUTH) {
+            match client.current_user().await {
+                Ok(Some(user)) => report.ok("login", format!("logged in as {}", user.username)),
+                Ok(None) => report.fail(
+                    "login",
+                    "the server requires login and no valid token is stored",
+                    "Run `rcloud login <username>`",
+                ),
+                Err(e) => report.warn(
+                    "login",
+                    format!("could not check the stored token: {:#}", e),
+                    "Run `rcloud login <username>` again",
+                ),
+            }
+        }
+    }
+
+    match status {
+        Some(status) => {
+            check_version(report, &status.version);
+            // 以请求发出与收到响应的中点作为本地时间
+            let local = sent_at + chrono::Duration::from_std(elapsed / 2).unwrap_or_default();
+            let skew = (status.time - local).num_seconds();
+            if skew.abs() > MAX_CLOCK_SKEW_SECS {
+                report.warn(
+                    "clock",
+                    format!("local clock differs from the server by {}s", skew),
+                    "Enable time synchronisation (NTP) on this machine or the server",
+                );
+            } else {
+                report.ok("clock", format!("in sync with the server ({}s)", skew));
+            }
+            check_services(report, &status.services);
+            check_objects(report, &status.corrupted_objects);
+        }
+        None => report.warn(
+            "server version",
+            "the server does not report its version",
+            "Upgrade the server to match this client",
+        ),
+    }
+
+    match &cfg.device_id {
+        Some(device_id) => match client.list_devices().await {
+            Ok(devices) if devices.iter().any(|d| d.id.to_string() == *device_id) => {
+                report.ok("device", format!("{} is registered", device_id))
+            }
+            Ok(_) => report.fail(
+                "device",
+                format!("{} is not registered on this server", device_id),
+                "Remove device_id from the config and register this device again",
+            ),
+            Err(e) => report.warn(
+                "device",
+                format!("could not list devices: {:#}", e),
+                "Check that the server is up to date",
+            ),
+        },
+        None => report.ok("device", "no device id configured"),
+    }
+}
+
+/// 0.x 版本之间次版本号不同即视为不兼容，1.0 之后按主版本号判断
+fn check_version(report: &mut Report, server: &str) {
+    let client = env!("CARGO_PKG_VERSION");
+    let series = |version: &str| -> Option<(u64, u64)> {
+        let mut parts = version.split('.').map(|p| p.parse::<u64>().ok());
+        let major = parts.next()??;
+        let minor = parts.next()??;
+        Some(if major == 0 { (0, minor) } else { (major, 0) })
+    };
+    match (series(client), series(server)) {
+        (Some(ours), Some(theirs)) if ours == theirs => {
+            report.ok("server version", format!("{} (client {})", server, client))
+        }
+        _ => report.warn(
+            "server version",
+            format!(
+                "server {} may not be compatible with client {}",
+                server, client
+            ),
+            "Upgrade the older of the two so their versions match",
+        ),
+    }
+}
+
+/// 服务端后台服务在崩溃重启时接口仍然可用，但同步到的数据可能滞后
+fn check_services(report: &mut Report, services: &[ServiceHealth]) {
+    if services.is_empty() {
+        return;
+    }
+    let unhealthy: Vec<_> = services
+        .iter()
+        .filter(|s| s.state != "running")
+        .map(|s| match &s.last_error {
+            Some(error) => format!(
+                "{} {} after {} restart(s): {}",
+                s.name, s.state, s.restarts, error
+            ),
+            None => format!("{} {}", s.name, s.state),
+        })
+        .collect();
+    if unhealthy.is_empty() {
+        report.ok("server services", format!("{} running", services.len()));
+    } else {
+        report.warn(
+            "server services",
+            unhealthy.join("; "),
+            "Check the server logs; crashed services are restarted automatically",
+        );
+    }
+}
+
+/// 服务端读取时校验失败的对象，内容与哈希不一致，下载会被拒绝
+fn check_objects(report: &mut Report, corrupted: &[String]) {
+    if corrupted.is_empty() {
+        return;
+    }
+    report.fail(
+        "server storage",
+        format!(
+            "{} object(s) failed verification: {}",
+            corrupted.len(),
+            corrupted.join(", ")
+        ),
+        "Upload the affected files again from a good copy to repair them",
+    );
+}
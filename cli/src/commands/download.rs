@@ -1,14 +1,25 @@
 use anyhow::Result;
 use std::path::PathBuf;
 
-use crate::client::Client;
+use rustcloud_client::atomic::temp_sibling;
+use rustcloud_client::Client;
+use rustcloud_types::path;
 
-pub async fn run(server: &str, remote_path: &str, local_path: Option<&str>) -> Result<()> {
-    let client = Client::new(server);
-    
-    println!("Downloading {}...", remote_path);
-    
-    let content = client.download_file(remote_path).await?;
+use crate::output::{progress, say};
+use crate::progress_bar::ProgressBar;
+use crate::vault::Vault;
+use crate::xattrs;
+
+pub async fn run(
+    client: &Client,
+    remote_path: &str,
+    local_path: Option<&str>,
+    resume: bool,
+    vault: Option<&Vault>,
+) -> Result<()> {
+    // 接受 Windows 风格的 "docs\a.txt"
+    let remote_path = &path::normalize(remote_path)?;
+    progress!("Downloading {}...", remote_path);
     
     let local = local_path
         .map(PathBuf::from)
@@ -19,12 +30,69 @@ pub async fn run(server: &str, remote_path: &str, local_path: Option<&str>) -> R
     if let Some(parent) = local.parent() {
         tokio::fs::create_dir_all(parent).await?;
     }
+
+    if let Some(vault) = vault {
+        if resume {
+            anyhow::bail!("--resume is not supported with end-to-end encryption");
+        }
+        return download_encrypted(client, remote_path, &local, vault).await;
+    }
     
-    tokio::fs::write(&local, &content).await?;
+    // 取不到文件信息时照常下载，由下载报告原因（例如服务端缺少下载接口）
+    let info = client.get_file_info(remote_path).await.ok();
+    let bar = ProgressBar::new(remote_path, info.as_ref().map_or(0, |info| info.size));
+    let transfer = bar.transfer();
+    let on_progress = |received| transfer.update(received);
+    let size = if resume {
+        client
+            .download_resumable(remote_path, &local, on_progress)
+            .await?
+    } else {
+        client.download_to(remote_path, &local, on_progress).await?
+    };
+    drop(bar);
+
+    let info = match info {
+        Some(info) => info,
+        None => client.get_file_info(remote_path).await?,
+    };
+    let restored = xattrs::restore(&local, &info.metadata);
     
-    println!("Downloaded successfully!");
-    println!("  Saved to: {:?}", local);
-    println!("  Size: {} bytes", content.len());
+    say!("Downloaded successfully!");
+    say!("  Saved to: {:?}", local);
+    say!("  Size: {} bytes", size);
+    if restored > 0 {
+        say!("  Attributes: {}", restored);
+    }
     
     Ok(())
 }
+
+/// 下载密文到临时文件，解密后再写入目标
+async fn download_encrypted(
+    client: &Client,
+    remote_path: &str,
+    local: &std::path::Path,
+    vault: &Vault,
+) -> Result<()> {
+    let sealed = temp_sibling(local);
+    let downloaded = async {
+        let remote = vault.encrypt_path(remote_path)?;
+        let total = client.get_file_info(&remote).await.map_or(0, |info| info.size);
+        let bar = ProgressBar::new(remote_path, total);
+        let transfer = bar.transfer();
+        client
+            .download_to(&remote, &sealed, |received| transfer.update(received))
+            .await?;
+        Ok::<_, anyhow::Error>(tokio::fs::read(&sealed).await?)
+    }
+    .await;
+    let _ = tokio::fs::remove_file(&sealed).await;
+    let plain = vault.decrypt(&downloaded?)?;
+    tokio::fs::write(local, &plain).await?;
+
+    say!("Downloaded and decrypted successfully!");
+    say!("  Saved to: {:?}", local);
+    say!("  Size: {} bytes", plain.len());
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use anyhow::Result;
+
+use rustcloud_client::{feature, Client};
+use crate::format::format_size;
+
+/// `rcloud du --top N`：按大小列出文件，并按目录汇总
+pub async fn run(client: &Client, top: usize, depth: usize, stale: Option<u32>) -> Result<()> {
+    let report = client.usage_report(top, depth, stale).await?;
+
+    if report.total_files == 0 {
+        match stale {
+            Some(months) => println!("No files unused for {} month(s)", months),
+            None => println!("No files on the server"),
+        }
+        return Ok(());
+    }
+
+    println!("Largest files:");
+    for file in &report.files {
+        println!("{:>10}  {}", format_size(file.size), file.path);
+    }
+
+    println!("\nBy folder:");
+    for folder in &report.folders {
+        println!(
+            "{:>10}  {} ({} files)",
+            format_size(folder.bytes),
+            folder.folder,
+            folder.files
+        );
+    }
+
+    let scope = match stale {
+        Some(months) => format!(" unused for {} month(s)", months),
+        None => String::new(),
+    };
+    println!(
+        "\n{} files{}, {} total",
+        report.total_files,
+        scope,
+        format_size(report.total_bytes)
+    );
+    quota_line(client).await;
+    Ok(())
+}
+
+// 服务端开启认证时附带当前用户的配额；未登录或服务端不支持时不显示
+async fn quota_line(client: &Client) {
+    if !client.supports(feature::QUOTAS).await {
+        return;
+    }
+    let Ok(usage) = client.storage_usage().await else {
+        return;
+    };
+    match usage.quota_bytes {
+        Some(quota) => println!(
+            "Quota: {} of {} used",
+            format_size(usage.used_bytes),
+            format_size(quota)
+        ),
+        None => println!("Quota: {} used, no limit", format_size(usage.used_bytes)),
+    }
+}
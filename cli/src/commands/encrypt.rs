@@ -0,0 +1,50 @@
+use anyhow::Result;
+
+use crate::config;
+use crate::output::say;
+use crate::vault::{self, Vault};
+use rustcloud_client::Client;
+
+/// `rcloud encrypt init`：生成加密清单保存到服务端，本机随即开始加密上传
+pub async fn init(client: &Client, encrypt_names: bool) -> Result<()> {
+    if vault::fetch_manifest(client).await?.is_some() {
+        anyhow::bail!(
+            "{} already has an encryption manifest; run `rcloud encrypt enable` to use it",
+            client.base_url()
+        );
+    }
+    let vault = Vault::create(&vault::passphrase(true)?, encrypt_names)?;
+    vault::publish_manifest(client, vault.manifest()).await?;
+    set_enabled(true)?;
+    say!(
+        "End-to-end encryption enabled{}",
+        if encrypt_names {
+            " (contents and names)"
+        } else {
+            " (contents)"
+        }
+    );
+    say!("Keep the passphrase safe: files cannot be recovered without it");
+    Ok(())
+}
+
+/// `rcloud encrypt enable`：另一台设备用同一口令解锁服务端的清单
+pub async fn enable(client: &Client) -> Result<()> {
+    vault::unlock(client).await?;
+    set_enabled(true)?;
+    say!("End-to-end encryption enabled on this device");
+    Ok(())
+}
+
+/// `rcloud encrypt disable`：本机不再加密，服务端上的密文和清单保持不变
+pub fn disable() -> Result<()> {
+    set_enabled(false)?;
+    say!("End-to-end encryption disabled on this device; encrypted files stay encrypted on the server");
+    Ok(())
+}
+
+fn set_enabled(enabled: bool) -> Result<()> {
+    let mut cfg = config::load()?;
+    cfg.end_to_end = enabled;
+    config::save(&cfg)
+}
@@ -0,0 +1,60 @@
+use anyhow::Result;
+use rustcloud_client::Client;
+
+use crate::credentials;
+
+/// 非交互环境（脚本、测试）从该环境变量读取口令
+const PASSWORD_ENV: &str = "RCLOUD_PASSWORD";
+
+fn read_password(confirm: bool) -> Result<String> {
+    if let Ok(password) = std::env::var(PASSWORD_ENV) {
+        return Ok(password);
+    }
+    let password = rpassword::prompt_password("Password: ")?;
+    if confirm && rpassword::prompt_password("Confirm password: ")? != password {
+        anyhow::bail!("Passwords do not match");
+    }
+    Ok(password)
+}
+
+/// `rcloud login`：登录（或先注册）并把令牌保存到凭据存储
+pub async fn run(
+    client: &Client,
+    server: &str,
+    username: &str,
+    register: bool,
+    append_only: bool,
+) -> Result<()> {
+    let password = read_password(register)?;
+    let token = if register {
+        client.register(username, &password).await?
+    } else if append_only {
+        client.login_append_only(username, &password).await?
+    } else {
+        client.login(username, &password).await?
+    };
+
+    let location = credentials::set(server, credentials::AUTH_TOKEN, &token.token)?;
+    if register {
+        println!("Registered {}", token.user.username);
+    }
+    println!(
+        "Logged in as {} until {}",
+        token.user.username,
+        token
+            .expires_at
+            .with_timezone(&chrono::Local)
+            .format("%Y-%m-%d %H:%M")
+    );
+    if token.append_only {
+        println!("  Append-only: this token can upload but not delete");
+    }
+    println!("  Token saved to {}", location);
+    Ok(())
+}
+
+pub fn logout(server: &str) -> Result<()> {
+    credentials::delete(server, credentials::AUTH_TOKEN)?;
+    println!("Logged out of {}", server);
+    Ok(())
+}
@@ -1,34 +1,294 @@
 use anyhow::Result;
+use chrono::{DateTime, Local, Utc};
+use unicode_width::UnicodeWidthStr;
 
-use crate::client::Client;
+use crate::format::format_size;
+use crate::locale::LocaleFormat;
+use crate::vault::Vault;
+use rustcloud_client::{Client, FileInfo, FileVersionRecord};
+
+/// 长格式中哈希前缀的长度
+const HASH_PREFIX_LEN: usize = 8;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SortKey {
+    #[default]
+    Name,
+    Size,
+    Time,
+    Version,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LsOptions {
+    /// 每行一个条目，附带大小、修改时间、版本和哈希前缀
+    pub long: bool,
+    /// 显示以 `.` 开头的条目
+    pub all: bool,
+    /// 大小以 KB/MB/GB 显示
+    pub human: bool,
+    pub sort: SortKey,
+    pub reverse: bool,
+    /// 列出过去某一时刻的目录
+    pub as_of: Option<DateTime<Utc>>,
+}
+
+pub async fn run(
+    client: &Client,
+    path: Option<&str>,
+    options: LsOptions,
+    vault: Option<&Vault>,
+) -> Result<()> {
+    let path = match (path, vault) {
+        (Some(path), Some(vault)) if !path.is_empty() => Some(vault.encrypt_path(path)?),
+        (path, _) => path.map(str::to_string),
+    };
+
+    // 排序和按列排版都需要先拿到全部条目
+    let mut files = Vec::new();
+    let mut collect = |mut file: FileInfo| {
+        // 加密清单这类没有加密的名字原样显示
+        if let Some(name) = vault.and_then(|vault| vault.decrypt_path(&file.name).ok()) {
+            file.name = name;
+        }
+        if options.all || !file.name.starts_with('.') {
+            files.push(file);
+        }
+    };
+    match options.as_of {
+        Some(at) => client
+            .list_files_as_of(path.as_deref(), at)
+            .await?
+            .into_iter()
+            .for_each(collect),
+        None => {
+            client
+                .list_files_streaming(path.as_deref(), &mut collect)
+                .await?;
+        }
+    }
 
-pub async fn run(server: &str, path: Option<&str>) -> Result<()> {
-    let client = Client::new(server);
-    
-    let files = client.list_files(path).await?;
-    
     if files.is_empty() {
         println!("No files found.");
         return Ok(());
     }
-    
-    println!("{:<40} {:<10} {:<20}", "Name", "Size", "Type");
-    println!("{}", "-".repeat(70));
-    
-    for file in files {
-        let file_type = if file.is_dir { "DIR" } else { "FILE" };
-        let size = format_size(file.size);
-        println!("{:<40} {:<10} {:<20}", file.name, size, file_type);
+
+    sort(&mut files, options.sort, options.reverse);
+    let lines = if options.long {
+        long_lines(&files, options.human, LocaleFormat::current())
+    } else {
+        grid_lines(&files, terminal_width())
+    };
+    for line in lines {
+        println!("{}", line);
     }
-    
     Ok(())
 }
 
-fn format_size(bytes: u64) -> String {
-    if bytes == 0 { return "0 B".to_string(); }
-    const K: u64 = 1024;
-    const SIZES: [&str; 4] = ["B", "KB", "MB", "GB"];
-    let i = (bytes as f64).log(K as f64).floor() as usize;
-    let i = i.min(SIZES.len() - 1);
-    format!("{:.1} {}", bytes as f64 / K.pow(i as u32) as f64, SIZES[i])
+/// `ls --versions`：一个文件的全部版本，旧的在前，当前版本标在最后一行
+pub async fn versions(client: &Client, path: &str, human: bool) -> Result<()> {
+    let versions = client.list_file_versions(path).await?;
+    for line in version_lines(&versions, human, LocaleFormat::current()) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+fn version_lines(
+    versions: &[FileVersionRecord],
+    human: bool,
+    locale: LocaleFormat,
+) -> Vec<String> {
+    let rows: Vec<[String; 4]> = versions
+        .iter()
+        .map(|v| {
+            let size = if human {
+                format_size(v.size)
+            } else {
+                locale.number(v.size)
+            };
+            let hash = v
+                .hash
+                .as_deref()
+                .map(|h| h.chars().take(HASH_PREFIX_LEN).collect())
+                .unwrap_or_else(|| "-".to_string());
+            [
+                format!("v{}", v.version),
+                locale.datetime(&v.created_at.with_timezone(&Local)),
+                size,
+                hash,
+            ]
+        })
+        .collect();
+
+    let mut widths = [0usize; 3];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.width());
+        }
+    }
+    let last = rows.len().saturating_sub(1);
+    rows.iter()
+        .enumerate()
+        .map(|(i, [version, time, size, hash])| {
+            let line = format!(
+                "{}  {}  {}  {}",
+                pad_left(version, widths[0]),
+                pad(time, widths[1]),
+                pad_left(size, widths[2]),
+                hash
+            );
+            if i == last {
+                format!("{}  (current)", line)
+            } else {
+                line
+            }
+        })
+        .collect()
+}
+
+fn modified(file: &FileInfo) -> Option<DateTime<Local>> {
+    file.modified
+        .as_deref()
+        .and_then(|m| DateTime::parse_from_rfc3339(m).ok())
+        .map(|m| m.with_timezone(&Local))
+}
+
+fn display_name(file: &FileInfo) -> String {
+    if file.is_dir {
+        format!("{}/", file.name)
+    } else {
+        file.name.clone()
+    }
+}
+
+/// 同一键值按名称排序；名称先不区分大小写比较，结果稳定
+fn sort(files: &mut [FileInfo], key: SortKey, reverse: bool) {
+    files.sort_by(|a, b| {
+        let by_name = a
+            .name
+            .to_lowercase()
+            .cmp(&b.name.to_lowercase())
+            .then_with(|| a.name.cmp(&b.name));
+        // 大小、时间、版本都是大的在前
+        let ordering = match key {
+            SortKey::Name => std::cmp::Ordering::Equal,
+            SortKey::Size => b.size.cmp(&a.size),
+            SortKey::Time => modified(b).cmp(&modified(a)),
+            SortKey::Version => b.version.cmp(&a.version),
+        };
+        ordering.then(by_name)
+    });
+    if reverse {
+        files.reverse();
+    }
+}
+
+fn pad(text: &str, width: usize) -> String {
+    format!("{}{}", text, " ".repeat(width.saturating_sub(text.width())))
+}
+
+fn pad_left(text: &str, width: usize) -> String {
+    format!("{}{}", " ".repeat(width.saturating_sub(text.width())), text)
+}
+
+/// 类似 `ls -l`：各列按最宽的值对齐，名称放在最后，不会被截断
+fn long_lines(files: &[FileInfo], human: bool, locale: LocaleFormat) -> Vec<String> {
+    let rows: Vec<[String; 5]> = files
+        .iter()
+        .map(|file| {
+            let size = if file.is_dir {
+                "-".to_string()
+            } else if human {
+                format_size(file.size)
+            } else {
+                locale.number(file.size)
+            };
+            let time = modified(file)
+                .map(|m| locale.datetime(&m))
+                .unwrap_or_else(|| "-".to_string());
+            let version = file
+                .version
+                .map(|v| format!("v{}", v))
+                .unwrap_or_else(|| "-".to_string());
+            let hash = file
+                .hash
+                .as_deref()
+                .map(|h| h.chars().take(HASH_PREFIX_LEN).collect())
+                .unwrap_or_else(|| "-".to_string());
+            [size, time, version, hash, display_name(file)]
+        })
+        .collect();
+
+    let mut widths = [0usize; 4];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.width());
+        }
+    }
+    rows.iter()
+        .map(|[size, time, version, hash, name]| {
+            format!(
+                "{}  {}  {}  {}  {}",
+                pad_left(size, widths[0]),
+                pad(time, widths[1]),
+                pad_left(version, widths[2]),
+                pad(hash, widths[3]),
+                name
+            )
+        })
+        .collect()
+}
+
+/// 类似 `ls -C`：按列优先排成能放进终端宽度的最多列数
+fn grid_lines(files: &[FileInfo], width: usize) -> Vec<String> {
+    const GAP: usize = 2;
+    let names: Vec<String> = files.iter().map(display_name).collect();
+
+    let mut layout = (1, vec![names.iter().map(|n| n.width()).max().unwrap_or(0)]);
+    for columns in (2..=names.len()).rev() {
+        let rows = names.len().div_ceil(columns);
+        // 列数多于实际需要时（最后几列为空）跳过
+        if (columns - 1) * rows >= names.len() {
+            continue;
+        }
+        let column_widths: Vec<usize> = names
+            .chunks(rows)
+            .map(|column| column.iter().map(|n| n.width()).max().unwrap_or(0))
+            .collect();
+        let total = column_widths.iter().sum::<usize>() + GAP * (columns - 1);
+        if total <= width {
+            layout = (columns, column_widths);
+            break;
+        }
+    }
+
+    let (columns, column_widths) = layout;
+    let rows = names.len().div_ceil(columns);
+    (0..rows)
+        .map(|row| {
+            let cells: Vec<&String> = (0..columns)
+                .filter_map(|column| names.get(column * rows + row))
+                .collect();
+            let mut line = String::new();
+            for (i, name) in cells.iter().enumerate() {
+                if i + 1 == cells.len() {
+                    line.push_str(name);
+                } else {
+                    line.push_str(&pad(name, column_widths[i] + GAP));
+                }
+            }
+            line
+        })
+        .collect()
+}
+
+/// COLUMNS 优先，其次是实际终端宽度；输出被重定向时为 0，即每行一个，便于脚本处理
+fn terminal_width() -> usize {
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|c| c.parse().ok())
+        .filter(|&c| c > 0)
+        .or_else(|| terminal_size::terminal_size().map(|(w, _)| w.0 as usize))
+        .unwrap_or(0)
 }
@@ -0,0 +1,11 @@
+use anyhow::Result;
+
+use crate::output::say;
+use rustcloud_client::Client;
+
+/// `rcloud mkdir`：在服务端创建目录，空目录也会同步到其他设备
+pub async fn run(client: &Client, path: &str) -> Result<()> {
+    let info = client.create_folder(path).await?;
+    say!("Created {}/", info.path);
+    Ok(())
+}
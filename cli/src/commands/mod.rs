@@ -4,3 +4,19 @@ pub mod config;
 pub mod ls;
 pub mod upload;
 pub mod download;
+pub mod benchmark;
+pub mod preview;
+pub mod photos;
+pub mod dedupe;
+pub mod du;
+pub mod doctor;
+pub mod login;
+pub mod share;
+pub mod rollback;
+pub mod mv;
+pub mod cp;
+pub mod mkdir;
+pub mod watch;
+pub mod conflicts;
+pub mod encrypt;
+pub mod trash;
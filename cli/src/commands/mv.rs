@@ -0,0 +1,32 @@
+use anyhow::Result;
+
+use crate::output::say;
+use crate::picker;
+use rustcloud_client::Client;
+
+/// `rcloud mv`：在服务端移动或重命名文件/目录，历史版本保留，不重新上传。
+/// 未给出源路径时交互式选择文件；未给出目标时选择目录，移动后保留原名
+pub async fn run(client: &Client, from: Option<&str>, to: Option<&str>) -> Result<()> {
+    let from = match from {
+        Some(from) => from.to_string(),
+        None => picker::pick_file(client, "File to move:").await?,
+    };
+    let to = match to {
+        Some(to) => to.to_string(),
+        None => {
+            let folder = picker::pick_folder(client, "Move into folder:").await?;
+            let name = from.rsplit('/').next().unwrap_or(&from);
+            match folder.as_str() {
+                "" => name.to_string(),
+                folder => format!("{}/{}", folder, name),
+            }
+        }
+    };
+
+    let moved = client.move_file(&from, &to).await?;
+    match moved.as_slice() {
+        [info] if info.path == to => say!("Moved {} -> {}", from, to),
+        _ => say!("Moved {} -> {} ({} files)", from, to, moved.len()),
+    }
+    Ok(())
+}
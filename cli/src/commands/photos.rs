@@ -0,0 +1,165 @@
+use anyhow::Result;
+use chrono::{Datelike, NaiveDateTime};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use rustcloud_client::atomic::is_temp_file;
+use rustcloud_client::{is_connection_error, sha256_hex, Client};
+use crate::exif;
+use crate::exit::{self, Status};
+use crate::output::{progress, say};
+
+const PHOTO_EXTENSIONS: [&str; 7] = ["jpg", "jpeg", "png", "gif", "webp", "heic", "heif"];
+
+pub struct ImportOptions {
+    pub dir: PathBuf,
+    /// 远程根目录，图片按 <root>/YYYY/MM/ 归档
+    pub remote_root: String,
+    /// 确认已在服务端后删除本地文件
+    pub delete_after: bool,
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Default)]
+struct ImportSummary {
+    uploaded: usize,
+    skipped: usize,
+    deleted: usize,
+    failed: usize,
+}
+
+pub async fn import(client: &Client, options: ImportOptions) -> Result<()> {
+    if !client.health().await? {
+        return Err(exit::coded(
+            Status::Connection,
+            format!("Cannot connect to server at {}", client.base_url()),
+        ));
+    }
+    if !options.dir.is_dir() {
+        anyhow::bail!("Not a directory: {}", options.dir.display());
+    }
+
+    let remote = client.list_hashes(None).await?;
+    let mut known_hashes: HashSet<String> = remote.iter().filter_map(|r| r.hash.clone()).collect();
+    let mut taken_paths: HashSet<String> = remote.into_iter().map(|r| r.path).collect();
+
+    let mut photos = Vec::new();
+    collect_photos(&options.dir, &mut photos)?;
+    photos.sort();
+    say!(
+        "Found {} photo(s) in {}",
+        photos.len(),
+        options.dir.display()
+    );
+
+    let mut summary = ImportSummary::default();
+    for path in photos {
+        let content = tokio::fs::read(&path).await?;
+        let hash = sha256_hex(&content);
+
+        // 相同内容已经在服务端（无论在哪个路径）就不再上传
+        if known_hashes.contains(&hash) {
+            progress!("[SKIP] {} (already on server)", path.display());
+            summary.skipped += 1;
+            if options.delete_after && !options.dry_run {
+                tokio::fs::remove_file(&path).await?;
+                summary.deleted += 1;
+            }
+            continue;
+        }
+
+        let date = exif::capture_date(&content).or_else(|| modified_date(&path));
+        let remote_path = remote_path_for(&options.remote_root, date, &path, &hash, &taken_paths);
+        progress!("[UPLOAD] {} -> {}", path.display(), remote_path);
+
+        if !options.dry_run {
+            // upload_file 已校验服务端哈希，成功返回即代表内容完整
+            if let Err(e) = client.upload_file(&remote_path, &content).await {
+                if is_connection_error(&e) {
+                    return Err(e);
+                }
+                eprintln!("[FAILED] {}: {}", path.display(), e);
+                summary.failed += 1;
+                continue;
+            }
+            if options.delete_after {
+                tokio::fs::remove_file(&path).await?;
+                summary.deleted += 1;
+            }
+        }
+
+        known_hashes.insert(hash);
+        taken_paths.insert(remote_path);
+        summary.uploaded += 1;
+    }
+
+    say!(
+        "\nImport completed{}:",
+        if options.dry_run { " (dry run)" } else { "" }
+    );
+    say!("  Uploaded: {}", summary.uploaded);
+    say!("  Skipped:  {}", summary.skipped);
+    say!("  Deleted:  {}", summary.deleted);
+    say!("  Failed:   {}", summary.failed);
+
+    if summary.failed > 0 {
+        anyhow::bail!("{} photo(s) failed to upload", summary.failed);
+    }
+    Ok(())
+}
+
+fn collect_photos(dir: &Path, photos: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_photos(&path, photos)?;
+        } else if !is_temp_file(&path) && is_photo(&path) {
+            photos.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn is_photo(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .is_some_and(|ext| PHOTO_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// 没有 EXIF 拍摄时间时退回文件修改时间
+fn modified_date(path: &Path) -> Option<NaiveDateTime> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    Some(chrono::DateTime::<chrono::Local>::from(modified).naive_local())
+}
+
+/// <root>/YYYY/MM/<name>，同名但内容不同时在文件名后追加哈希前缀
+fn remote_path_for(
+    root: &str,
+    date: Option<NaiveDateTime>,
+    local: &Path,
+    hash: &str,
+    taken: &HashSet<String>,
+) -> String {
+    let root = root.trim_matches('/');
+    let dir = match date {
+        Some(date) => format!("{}/{}/{:02}", root, date.year(), date.month()),
+        None => format!("{}/unsorted", root),
+    };
+    let name = local
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "photo".to_string());
+
+    let candidate = format!("{}/{}", dir, name);
+    if !taken.contains(&candidate) {
+        return candidate;
+    }
+    let stem = local
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    match local.extension() {
+        Some(ext) => format!("{}/{}-{}.{}", dir, stem, &hash[..8], ext.to_string_lossy()),
+        None => format!("{}/{}-{}", dir, stem, &hash[..8]),
+    }
+}
@@ -0,0 +1,15 @@
+use anyhow::Result;
+use std::io::Write;
+
+use rustcloud_client::Client;
+
+/// `rcloud head` / `rcloud tail`：打印远程文件的开头或末尾若干行
+pub async fn run(client: &Client, remote_path: &str, lines: usize, tail: bool) -> Result<()> {
+    let preview = client.preview(remote_path, lines, tail).await?;
+
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(preview.content.as_bytes())?;
+    stdout.flush()?;
+
+    Ok(())
+}
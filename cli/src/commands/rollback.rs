@@ -0,0 +1,20 @@
+use anyhow::Result;
+
+use crate::output::say;
+use rustcloud_client::Client;
+
+/// `rcloud rollback`：把远程文件恢复为某个历史版本，之前的版本都保留
+pub async fn run(client: &Client, remote_path: &str, version: i32) -> Result<()> {
+    let info = client.rollback(remote_path, version).await?;
+    if info.deduplicated {
+        say!("{} already has the content of v{}", remote_path, version);
+    } else {
+        say!(
+            "Rolled back {} to v{} (now v{})",
+            remote_path,
+            version,
+            info.version.unwrap_or_default()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,37 @@
+use anyhow::Result;
+
+use rustcloud_client::Client;
+use rustcloud_types::path;
+
+use crate::picker;
+
+/// `rcloud share`：为远程文件创建公开下载链接；未给出路径时交互式选择
+pub async fn run(
+    client: &Client,
+    remote_path: Option<&str>,
+    expires_in_hours: Option<i64>,
+    max_downloads: Option<u64>,
+) -> Result<()> {
+    let remote_path = match remote_path {
+        Some(remote_path) => path::normalize(remote_path)?,
+        None => picker::pick_file(client, "File to share:").await?,
+    };
+
+    let share = client
+        .create_share(&remote_path, expires_in_hours, max_downloads)
+        .await?;
+    println!("Shared {}", share.path);
+    println!("  Link: {}", client.share_url(&share));
+    if let Some(expires_at) = share.expires_at {
+        println!(
+            "  Expires: {}",
+            expires_at
+                .with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M")
+        );
+    }
+    if let Some(max_downloads) = max_downloads {
+        println!("  Max downloads: {}", max_downloads);
+    }
+    Ok(())
+}
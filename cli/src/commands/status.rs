@@ -1,28 +1,39 @@
 use anyhow::Result;
 
-use crate::client::Client;
+use rustcloud_client::{feature, Client};
 use crate::config;
 use crate::sync::SyncEngine;
 
-pub async fn run(server: &str, path: Option<&str>) -> Result<()> {
-    let client = Client::new(server);
-    
+pub async fn run(client: &Client, path: Option<&str>) -> Result<()> {
     if !client.health().await? {
-        anyhow::bail!("Cannot connect to server at {}", server);
+        anyhow::bail!("Cannot connect to server at {}", client.base_url());
     }
     
     let cfg = config::load()?;
     let sync_path = path
         .map(std::path::PathBuf::from)
         .unwrap_or(cfg.sync_path);
+    // 心跳顺带返回是否有待拉取的变更，旧服务端没有这个字段
+    let pending = match &cfg.device_id {
+        Some(device_id) if client.supports(feature::PENDING_CHANGES).await => {
+            Some(client.heartbeat(device_id).await?.pending_changes)
+        }
+        _ => None,
+    };
     
-    let engine = SyncEngine::new(client, sync_path);
+    let engine = SyncEngine::new(client.clone(), sync_path).with_ignore(&cfg.ignore);
     let status = engine.status().await?;
     
     println!("Sync Status:");
     println!("  Local path:  {:?}", status.local_path);
     println!("  Local files: {}", status.local_count);
     println!("  Remote files: {}", status.remote_count);
+    if let Some(pending) = pending {
+        println!(
+            "  Remote changes: {}",
+            if pending { "pending, run `rcloud sync`" } else { "none" }
+        );
+    }
     
     Ok(())
 }
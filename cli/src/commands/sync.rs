@@ -1,14 +1,42 @@
 use anyhow::Result;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::task::JoinSet;
 
-use crate::client::Client;
+use rustcloud_client::Client;
 use crate::config;
-use crate::sync::SyncEngine;
+use crate::exit::{self, Status};
+use crate::format::format_size;
+use crate::hooks::{self, SyncOutcome};
+use crate::output::say;
+use crate::schedule::Schedule;
+use crate::sync::{SyncEngine, SyncReport};
+use crate::vault::{self, Vault};
 
-pub async fn run(server: &str, path: Option<&str>, dry_run: bool) -> Result<()> {
-    let client = Client::new(server);
-    
+#[derive(Debug, Clone, Copy)]
+pub struct SyncOptions {
+    pub dry_run: bool,
+    /// 执行前要求用户确认
+    pub confirm: bool,
+    /// 预计传输量超过该值时中止
+    pub max_transfer: Option<u64>,
+    /// 同时执行的上传、下载条目数
+    pub jobs: usize,
+}
+
+/// 先与主服务器双向同步，再把本地目录并行推送到各镜像服务器
+pub async fn run(
+    client: &Client,
+    mirrors: &[Client],
+    path: Option<&str>,
+    options: SyncOptions,
+) -> Result<()> {
     if !client.health().await? {
-        anyhow::bail!("Cannot connect to server at {}", server);
+        return Err(exit::coded(
+            Status::Connection,
+            format!("Cannot connect to server at {}", client.base_url()),
+        ));
     }
     
     let cfg = config::load()?;
@@ -18,19 +46,248 @@ pub async fn run(server: &str, path: Option<&str>, dry_run: bool) -> Result<()>
     
     if !sync_path.exists() {
         std::fs::create_dir_all(&sync_path)?;
-        println!("Created sync directory: {:?}", sync_path);
+        say!("Created sync directory: {:?}", sync_path);
     }
     
-    let engine = SyncEngine::new(client, sync_path);
-    
-    println!("Starting sync{}...", if dry_run { " (dry run)" } else { "" });
-    let report = engine.sync(dry_run).await?;
-    
-    println!("\nSync completed:");
-    println!("  Uploaded:  {}", report.uploaded);
-    println!("  Downloaded: {}", report.downloaded);
-    println!("  Deleted:    {}", report.deleted);
-    println!("  Skipped:    {}", report.skipped);
-    
+    if let Some(command) = &cfg.hooks.pre_sync {
+        hooks::run_pre_sync(command, &sync_path, options.dry_run).await?;
+    }
+
+    let vault = match cfg.end_to_end {
+        true => Some(Arc::new(vault::unlock(client).await?)),
+        false => None,
+    };
+    let result = sync_once(client, &sync_path, &cfg.ignore, options, vault.clone()).await;
+    // 主服务器同步出错不影响镜像，用户取消时一起取消
+    let failed_mirrors = match &result {
+        Ok(None) => 0,
+        _ => push_mirrors(mirrors, &sync_path, &cfg.ignore, options, vault).await,
+    };
+
+    if let Some(command) = &cfg.hooks.post_sync {
+        let outcome = match &result {
+            Ok(Some(report)) => SyncOutcome::Success {
+                report: report.clone(),
+            },
+            Ok(None) => SyncOutcome::Cancelled,
+            Err(e) => SyncOutcome::Failure {
+                error: format!("{:#}", e),
+            },
+        };
+        hooks::run_post_sync(command, &sync_path, options.dry_run, &outcome).await;
+    }
+    match result? {
+        Some(report) if report.failed > 0 => {
+            anyhow::bail!("{} item(s) failed to sync", report.failed)
+        }
+        _ if failed_mirrors > 0 => {
+            anyhow::bail!("Sync to {} mirror server(s) failed", failed_mirrors)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// 规划并执行一次同步，用户取消时返回 None
+async fn sync_once(
+    client: &Client,
+    sync_path: &Path,
+    ignore: &[String],
+    options: SyncOptions,
+    vault: Option<Arc<Vault>>,
+) -> Result<Option<SyncReport>> {
+    let mut engine = SyncEngine::new(client.clone(), sync_path.to_path_buf())
+        .with_jobs(options.jobs)
+        .with_ignore(ignore);
+    if let Some(vault) = vault {
+        engine = engine.encrypted(vault);
+    }
+
+    say!("Starting sync{}...", if options.dry_run { " (dry run)" } else { "" });
+    let pending = engine.plan().await?;
+
+    let estimate = &pending.estimate;
+    say!("\nEstimated transfer:");
+    say!(
+        "  Upload:   {} file(s), {}",
+        estimate.upload_files,
+        format_size(estimate.upload_bytes)
+    );
+    say!(
+        "  Download: {} file(s), {}",
+        estimate.download_files,
+        format_size(estimate.download_bytes)
+    );
+    say!("  Unchanged (not sent): {}", format_size(estimate.saved_bytes));
+
+    check_transfer_limit(estimate.total_bytes(), options)?;
+
+    if options.confirm && !options.dry_run && !prompt_continue()? {
+        say!("Sync cancelled");
+        return Ok(None);
+    }
+    say!();
+
+    let report = engine.execute(pending, options.dry_run).await?;
+
+    say!("\nSync completed:");
+    say!("  Uploaded:  {}", report.uploaded);
+    say!("  Downloaded: {}", report.downloaded);
+    say!("  Deleted:    {}", report.deleted);
+    say!("  Skipped:    {}", report.skipped);
+    say!("  Attributes: {}", report.attributes);
+    if report.directories > 0 {
+        say!("  Folders:    {}", report.directories);
+    }
+    if report.conflicts > 0 {
+        say!("  Conflicts:  {} (see `rcloud conflicts`)", report.conflicts);
+    }
+    if report.failed > 0 {
+        say!("  Failed:     {}", report.failed);
+        for failure in &report.errors {
+            say!("    {}: {}", failure.path, failure.error);
+        }
+    }
+
+    Ok(Some(report))
+}
+
+/// 每个镜像独立规划和执行，互不等待；返回失败（含部分条目失败）的镜像数
+async fn push_mirrors(
+    mirrors: &[Client],
+    sync_path: &Path,
+    ignore: &[String],
+    options: SyncOptions,
+    vault: Option<Arc<Vault>>,
+) -> usize {
+    let engines: Vec<SyncEngine> = mirrors
+        .iter()
+        .map(|mirror| {
+            let engine = SyncEngine::new(mirror.clone(), sync_path.to_path_buf())
+                .labeled(mirror.base_url())
+                .with_jobs(options.jobs)
+                .with_ignore(ignore);
+            match &vault {
+                Some(vault) => engine.encrypted(vault.clone()),
+                None => engine,
+            }
+        })
+        .collect();
+    let Some(first) = engines.first() else {
+        return 0;
+    };
+    // 主服务器可能刚下载了新文件，重新扫描一次，所有镜像共用
+    let local = match first.scan() {
+        Ok(local) => local,
+        Err(e) => {
+            eprintln!("Mirror sync skipped: {:#}", e);
+            return mirrors.len();
+        }
+    };
+
+    let mut tasks = JoinSet::new();
+    for (index, engine) in engines.into_iter().enumerate() {
+        let local = local.clone();
+        let manifest = vault.as_ref().map(|vault| vault.manifest().clone());
+        let mirror = mirrors[index].clone();
+        tasks.spawn(async move {
+            let result = async {
+                // 镜像上也要有加密清单，否则只能从主服务器恢复
+                if let Some(manifest) = &manifest {
+                    if !options.dry_run {
+                        vault::publish_manifest(&mirror, manifest).await?;
+                    }
+                }
+                let pending = engine.plan_push(local).await?;
+                check_transfer_limit(pending.estimate.total_bytes(), options)?;
+                engine.execute(pending, options.dry_run).await
+            }
+            .await;
+            (index, result)
+        });
+    }
+    let mut results = tasks.join_all().await;
+    results.sort_by_key(|(index, _)| *index);
+
+    let mut failed = 0;
+    for (index, result) in results {
+        let server = mirrors[index].base_url();
+        match result {
+            Ok(report) => {
+                say!(
+                    "Mirror {}: uploaded {}, skipped {}{}",
+                    server,
+                    report.uploaded,
+                    report.skipped,
+                    if report.failed > 0 {
+                        format!(", failed {}", report.failed)
+                    } else {
+                        String::new()
+                    }
+                );
+                if report.failed > 0 {
+                    failed += 1;
+                }
+            }
+            Err(e) => {
+                eprintln!("Mirror {} failed: {:#}", server, e);
+                failed += 1;
+            }
+        }
+    }
+    failed
+}
+
+fn check_transfer_limit(total: u64, options: SyncOptions) -> Result<()> {
+    if let Some(limit) = options.max_transfer {
+        if total > limit {
+            anyhow::bail!(
+                "Estimated transfer {} exceeds --max-transfer {}, aborting",
+                format_size(total),
+                format_size(limit)
+            );
+        }
+    }
     Ok(())
 }
+
+/// 按计划反复同步直到 Ctrl-C；错过的触发点不补跑，单次失败只打印错误，等下一次
+pub async fn run_scheduled(
+    client: &Client,
+    mirrors: &[Client],
+    path: Option<&str>,
+    options: SyncOptions,
+    schedule: &Schedule,
+) -> Result<()> {
+    say!("Scheduled sync: {} (press Ctrl-C to stop)", schedule);
+    loop {
+        let Some(next) = schedule.next_after(chrono::Local::now()) else {
+            anyhow::bail!("Schedule {} has no upcoming runs", schedule);
+        };
+        say!("\nNext sync at {}", next.format("%Y-%m-%d %H:%M:%S"));
+
+        let scheduled = async {
+            let wait = (next - chrono::Local::now()).to_std().unwrap_or_default();
+            tokio::time::sleep(wait).await;
+            run(client, mirrors, path, options).await
+        };
+        tokio::select! {
+            result = scheduled => {
+                if let Err(e) = result {
+                    eprintln!("Sync failed: {:#}", e);
+                }
+            }
+            _ = tokio::signal::ctrl_c() => {
+                say!("\nScheduled sync stopped");
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn prompt_continue() -> Result<bool> {
+    print!("Proceed? [y/N] ");
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes"))
+}
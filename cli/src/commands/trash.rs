@@ -0,0 +1,47 @@
+use anyhow::Result;
+use chrono::Local;
+use uuid::Uuid;
+
+use crate::format::format_size;
+use crate::locale::LocaleFormat;
+use crate::output::say;
+use rustcloud_client::{feature, Client, TrashItem};
+
+/// `rcloud trash list`：列出回收站中还能恢复的条目
+pub async fn list(client: &Client) -> Result<()> {
+    client.require(feature::TRASH).await?;
+    let items = client.list_trash().await?;
+    if items.is_empty() {
+        say!("Trash is empty");
+        return Ok(());
+    }
+
+    let locale = LocaleFormat::current();
+    for item in &items {
+        println!("{}", describe(item, locale));
+    }
+    say!(
+        "\n{} item(s). Run `rcloud trash restore <id>` to put one back",
+        items.len()
+    );
+    Ok(())
+}
+
+/// `rcloud trash restore <id>`：恢复到删除前的路径
+pub async fn restore(client: &Client, id: Uuid) -> Result<()> {
+    client.require(feature::TRASH).await?;
+    let item = client.restore_trash(id).await?;
+    say!("Restored {}", item.path);
+    Ok(())
+}
+
+fn describe(item: &TrashItem, locale: LocaleFormat) -> String {
+    format!(
+        "{}  {}  {}  {} (until {})",
+        item.id,
+        locale.datetime(&item.deleted_at.with_timezone(&Local)),
+        format_size(item.size),
+        item.path,
+        locale.datetime(&item.expires_at.with_timezone(&Local)),
+    )
+}
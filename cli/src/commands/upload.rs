@@ -1,33 +1,112 @@
 use anyhow::Result;
 use std::path::Path;
+use std::sync::Arc;
 
-use crate::client::Client;
+use rustcloud_client::{feature, Client};
+use rustcloud_types::path as logical_path;
 
-pub async fn run(server: &str, local_path: &str, remote_path: Option<&str>) -> Result<()> {
-    let client = Client::new(server);
-    
+use crate::output::{progress, say};
+use crate::progress_bar::ProgressBar;
+use crate::vault::{self, Vault};
+use crate::{uploads, xattrs};
+
+pub async fn run(
+    client: &Client,
+    local_path: &str,
+    remote_path: Option<&str>,
+    on_conflict: Option<&str>,
+    vault: Option<&Vault>,
+) -> Result<()> {
     let path = Path::new(local_path);
     if !path.exists() {
         anyhow::bail!("File not found: {}", local_path);
     }
     
-    let content = tokio::fs::read(path).await?;
     let remote = remote_path.unwrap_or(
         path.file_name()
             .and_then(|n| n.to_str())
             .unwrap_or("file")
     );
+    let remote = &logical_path::normalize(remote)?;
     
-    println!("Uploading {} -> {}...", local_path, remote);
+    progress!("Uploading {} -> {}...", local_path, remote);
+
+    if let Some(vault) = vault {
+        return upload_encrypted(client, path, remote, on_conflict, vault).await;
+    }
     
-    let info = client.upload_file(remote, &content).await?;
+    // 超过服务端分块大小的文件按块上传，中断后再次运行只补传缺少的分块
+    let size = tokio::fs::metadata(path).await?.len();
+    let chunked = client.supports(feature::RESUMABLE_UPLOAD).await
+        && client.chunk_policy(size).await?.is_some();
+    let bar = ProgressBar::new(remote, size);
+    let info = if chunked {
+        let content = uploads::Content::Plain(path);
+        uploads::upload(client, content, remote, on_conflict, &bar.transfer()).await?
+    } else {
+        let content = tokio::fs::read(path).await?;
+        let transfer = bar.transfer();
+        client
+            .upload_file_with_progress(
+                remote,
+                &content,
+                on_conflict,
+                Arc::new(move |sent| transfer.update(sent)),
+            )
+            .await?
+    };
+    drop(bar);
     
-    println!("Uploaded successfully!");
-    println!("  Path: {}", info.path);
-    println!("  Size: {} bytes", info.size);
-    if let Some(hash) = info.hash {
-        println!("  Hash: {}...", &hash[..12]);
+    if info.deduplicated {
+        say!("Content unchanged, server kept existing version.");
+    } else {
+        say!("Uploaded successfully!");
+    }
+    say!("  Path: {}", info.path);
+    say!("  Size: {} bytes", info.size);
+    if let Some(version) = info.version {
+        say!("  Version: {}", version);
+    }
+    if let Some(hash) = &info.hash {
+        say!("  Hash: {}...", &hash[..12]);
+    }
+
+    let attributes = xattrs::read(path);
+    for name in &attributes.ignored {
+        eprintln!("Warning: extended attribute {} not synced", name);
+    }
+    let patch = xattrs::metadata_patch(&attributes.preserved, &info.metadata);
+    if !patch.is_empty() {
+        client.update_metadata(&info.path, &patch).await?;
+        say!("  Attributes: {}", attributes.preserved.len());
     }
     
     Ok(())
 }
+
+/// 逐块加密后上传；扩展属性是明文，不写进服务端元数据
+async fn upload_encrypted(
+    client: &Client,
+    path: &Path,
+    remote: &str,
+    on_conflict: Option<&str>,
+    vault: &Vault,
+) -> Result<()> {
+    let size = vault::encrypted_len(tokio::fs::metadata(path).await?.len());
+    let bar = ProgressBar::new(remote, size);
+    let remote = vault.encrypt_path(remote)?;
+    let info = vault::upload(client, vault, path, &remote, on_conflict, &bar.transfer()).await?;
+    drop(bar);
+
+    if info.deduplicated {
+        say!("Content unchanged, server kept existing version.");
+    } else {
+        say!("Uploaded successfully (end-to-end encrypted)!");
+    }
+    say!("  Path: {}", vault.decrypt_path(&info.path)?);
+    say!("  Size: {} bytes encrypted", info.size);
+    if let Some(version) = info.version {
+        say!("  Version: {}", version);
+    }
+    Ok(())
+}
@@ -0,0 +1,42 @@
+use anyhow::Result;
+
+use crate::output::say;
+use rustcloud_client::{feature, ChangeEvent, ChangeKind, Client};
+
+/// `rcloud watch`：订阅服务端推送的文件变更并逐行打印，直到连接断开或按 Ctrl+C
+pub async fn run(client: &Client) -> Result<()> {
+    if !client.supports(feature::CHANGE_FEED).await {
+        anyhow::bail!(
+            "The server does not push file changes; upgrade it or use `rcloud sync --schedule`"
+        );
+    }
+    say!(
+        "Watching {} for changes (Ctrl+C to stop)",
+        client.base_url()
+    );
+
+    tokio::select! {
+        result = client.watch(|change| println!("{}", describe(&change))) => {
+            result.map_err(|e| e.context("Lost the change feed; run `rcloud sync` to catch up"))?;
+            say!("The server closed the change feed");
+        }
+        _ = tokio::signal::ctrl_c() => {}
+    }
+    Ok(())
+}
+
+fn describe(change: &ChangeEvent) -> String {
+    let kind = match change.kind {
+        ChangeKind::Created => "created",
+        ChangeKind::Modified => "modified",
+        ChangeKind::Deleted => "deleted",
+    };
+    let mut line = format!("{:<8}  {}", kind, change.path);
+    if let Some(version) = change.version {
+        line.push_str(&format!("  v{}", version));
+    }
+    if let Some(hash) = &change.hash {
+        line.push_str(&format!("  {}", &hash[..hash.len().min(12)]));
+    }
+    line
+}
@@ -0,0 +1,160 @@
+//! 令牌、口令等敏感信息的存放位置
+//!
+//! 优先写入系统钥匙串：macOS Keychain、Windows 凭据管理器（DPAPI），
+//! 以及启用 `secret-service` feature 构建时 Linux 的 Secret Service。
+//! 钥匙串不可用时退回到配置目录下的 `credentials.toml`，Unix 上权限为 0600，
+//! 值经 [`crate::encryption`] 加密后写入。
+//! 无论哪种方式，敏感信息都不会写进 `config.toml`。
+//!
+//! 设置环境变量 `RCLOUD_NO_KEYRING=1` 可强制使用文件（无图形会话的服务器、测试）。
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+use crate::encryption;
+
+/// 钥匙串条目的服务名
+const SERVICE: &str = "rustcloud";
+
+/// 连接服务端时附带的管理员令牌
+pub const ADMIN_TOKEN: &str = "admin_token";
+
+/// `rcloud login` 得到的 JWT
+pub const AUTH_TOKEN: &str = "auth_token";
+
+/// 当前构建是否带有可用的钥匙串后端；其余平台上 keyring 只有内存中的 mock 实现
+const KEYRING_SUPPORTED: bool = cfg!(any(
+    target_os = "macos",
+    target_os = "windows",
+    all(target_os = "linux", feature = "secret-service")
+));
+
+/// 敏感信息实际保存到了哪里
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    Keyring,
+    File(PathBuf),
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Location::Keyring => write!(f, "the system keyring"),
+            Location::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// 同一个名字按服务端区分，切换服务端不会带上另一台的令牌
+fn account(server: &str, name: &str) -> String {
+    format!("{}@{}", name, server.trim_end_matches('/'))
+}
+
+fn keyring_enabled() -> bool {
+    KEYRING_SUPPORTED && std::env::var_os("RCLOUD_NO_KEYRING").is_none_or(|v| v.is_empty())
+}
+
+pub fn get(server: &str, name: &str) -> Result<Option<String>> {
+    let account = account(server, name);
+    if keyring_enabled() {
+        match keyring::Entry::new(SERVICE, &account).and_then(|e| e.get_password()) {
+            Ok(secret) => return Ok(Some(secret)),
+            Err(keyring::Error::NoEntry) => {}
+            Err(e) => tracing::warn!("Keyring unavailable, using the credentials file: {}", e),
+        }
+    }
+    let Some(stored) = read_file()?.remove(&account) else {
+        return Ok(None);
+    };
+    let secret = encryption::open(&account, &stored)?;
+    // 加密功能之前写下的明文，读到时顺手加密
+    if !encryption::is_sealed(&stored) {
+        let mut secrets = read_file()?;
+        secrets.insert(account.clone(), encryption::seal(&account, &secret)?);
+        write_file(&secrets)?;
+    }
+    Ok(Some(secret))
+}
+
+/// 保存后从另一处删除旧值，同一个名字只在一个地方出现
+pub fn set(server: &str, name: &str, secret: &str) -> Result<Location> {
+    let account = account(server, name);
+    if keyring_enabled() {
+        match keyring::Entry::new(SERVICE, &account).and_then(|e| e.set_password(secret)) {
+            Ok(()) => {
+                remove_from_file(&account)?;
+                return Ok(Location::Keyring);
+            }
+            Err(e) => eprintln!(
+                "Warning: could not use the system keyring ({}), storing in the credentials file",
+                e
+            ),
+        }
+    }
+    let mut secrets = read_file()?;
+    let sealed = encryption::seal(&account, secret)?;
+    secrets.insert(account, sealed);
+    write_file(&secrets)?;
+    Ok(Location::File(file_path()?))
+}
+
+pub fn delete(server: &str, name: &str) -> Result<()> {
+    let account = account(server, name);
+    if keyring_enabled() {
+        match keyring::Entry::new(SERVICE, &account).and_then(|e| e.delete_credential()) {
+            Ok(()) | Err(keyring::Error::NoEntry) => {}
+            Err(e) => tracing::warn!("Could not remove {} from the keyring: {}", account, e),
+        }
+    }
+    remove_from_file(&account)
+}
+
+fn file_path() -> Result<PathBuf> {
+    Ok(crate::config::config_path()?.with_file_name("credentials.toml"))
+}
+
+fn read_file() -> Result<BTreeMap<String, String>> {
+    let path = file_path()?;
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+}
+
+fn remove_from_file(account: &str) -> Result<()> {
+    let mut secrets = read_file()?;
+    if secrets.remove(account).is_some() {
+        write_file(&secrets)?;
+    }
+    Ok(())
+}
+
+fn write_file(secrets: &BTreeMap<String, String>) -> Result<()> {
+    let path = file_path()?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let content = toml::to_string_pretty(secrets)?;
+
+    #[cfg(unix)]
+    {
+        use std::io::Write;
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+        // 先以 0600 创建再写入，内容不会有一刻对其他用户可读
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&path)?;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+        file.write_all(content.as_bytes())?;
+    }
+    #[cfg(not(unix))]
+    std::fs::write(&path, content)?;
+
+    Ok(())
+}
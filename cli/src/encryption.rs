@@ -0,0 +1,166 @@
+//! 配置文件中敏感字段的加密
+//!
+//! 没有钥匙串时，令牌和带口令的代理地址仍需落盘。落盘前用 ChaCha20-Poly1305 加密，
+//! 读取时透明解密，备份 dotfiles 时不会把明文带走。
+//!
+//! 密钥来源二选一，写在密文里，解密时按同样的方式派生：
+//! - 设置了 `RCLOUD_PASSPHRASE`：口令经 Argon2id 派生，换机器也能解密
+//! - 否则：本机 machine id 加当前用户名经 HKDF 派生，只能在本机由同一用户解密
+//!
+//! 密文格式为 `enc:v1:<m|p>:<base64(salt | nonce | ciphertext)>`，
+//! 并以字段名作为附加数据，密文不能被挪到别的字段使用。
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use sha2::Sha256;
+
+const PREFIX: &str = "enc:v1:";
+pub const PASSPHRASE_ENV: &str = "RCLOUD_PASSPHRASE";
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeySource {
+    Machine,
+    Passphrase,
+}
+
+impl KeySource {
+    fn tag(self) -> &'static str {
+        match self {
+            KeySource::Machine => "m",
+            KeySource::Passphrase => "p",
+        }
+    }
+
+    fn from_tag(tag: &str) -> Option<Self> {
+        match tag {
+            "m" => Some(KeySource::Machine),
+            "p" => Some(KeySource::Passphrase),
+            _ => None,
+        }
+    }
+}
+
+pub fn is_sealed(value: &str) -> bool {
+    value.starts_with(PREFIX)
+}
+
+fn passphrase() -> Option<String> {
+    std::env::var(PASSPHRASE_ENV).ok().filter(|p| !p.is_empty())
+}
+
+/// machine id 不是秘密，但不会出现在 dotfiles 备份里；加上用户名，同机的其他用户也解不开
+fn machine_secret() -> Result<Vec<u8>> {
+    let id = machine_uid::get().map_err(|e| {
+        anyhow!(
+            "Cannot read the machine id ({}); set {} to encrypt with a passphrase instead",
+            e,
+            PASSPHRASE_ENV
+        )
+    })?;
+    let user = std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_default();
+    Ok(format!("{}\n{}", id.trim(), user).into_bytes())
+}
+
+fn derive_key(source: KeySource, salt: &[u8]) -> Result<Key> {
+    let mut key = Key::default();
+    match source {
+        KeySource::Machine => {
+            // machine id 本身熵足够，不需要慢哈希
+            hkdf::Hkdf::<Sha256>::new(Some(salt), &machine_secret()?)
+                .expand(b"rustcloud config encryption", &mut key)
+                .map_err(|e| anyhow!("Key derivation failed: {}", e))?;
+        }
+        KeySource::Passphrase => {
+            let passphrase = passphrase().ok_or_else(|| {
+                anyhow!(
+                    "This value was encrypted with a passphrase; set {} to decrypt it",
+                    PASSPHRASE_ENV
+                )
+            })?;
+            argon2::Argon2::default()
+                .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+                .map_err(|e| anyhow!("Key derivation failed: {}", e))?;
+        }
+    }
+    Ok(key)
+}
+
+/// 加密 `value`；`field` 作为附加数据，解密时必须一致
+pub fn seal(field: &str, value: &str) -> Result<String> {
+    let source = if passphrase().is_some() {
+        KeySource::Passphrase
+    } else {
+        KeySource::Machine
+    };
+
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let cipher = ChaCha20Poly1305::new(&derive_key(source, &salt)?);
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: value.as_bytes(),
+                aad: field.as_bytes(),
+            },
+        )
+        .map_err(|_| anyhow!("Failed to encrypt {}", field))?;
+
+    let mut blob = Vec::with_capacity(SALT_LEN + NONCE_LEN + ciphertext.len());
+    blob.extend_from_slice(&salt);
+    blob.extend_from_slice(&nonce);
+    blob.extend_from_slice(&ciphertext);
+    Ok(format!(
+        "{}{}:{}",
+        PREFIX,
+        source.tag(),
+        base64::engine::general_purpose::STANDARD.encode(blob)
+    ))
+}
+
+/// 解密 `seal` 的结果；未加密的旧值原样返回，升级前写下的配置照常可用
+pub fn open(field: &str, value: &str) -> Result<String> {
+    let Some(rest) = value.strip_prefix(PREFIX) else {
+        return Ok(value.to_string());
+    };
+    let (tag, encoded) = rest
+        .split_once(':')
+        .ok_or_else(|| anyhow!("Malformed encrypted value for {}", field))?;
+    let source = KeySource::from_tag(tag)
+        .ok_or_else(|| anyhow!("Unknown key source '{}' for {}", tag, field))?;
+    let blob = base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .with_context(|| format!("Malformed encrypted value for {}", field))?;
+    if blob.len() < SALT_LEN + NONCE_LEN {
+        bail!("Malformed encrypted value for {}", field);
+    }
+    let (salt, rest) = blob.split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(source, salt)?);
+    let plain = cipher
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: field.as_bytes(),
+            },
+        )
+        .map_err(|_| match source {
+            KeySource::Machine => anyhow!(
+                "Cannot decrypt {}: it was encrypted on another machine or by another user",
+                field
+            ),
+            KeySource::Passphrase => anyhow!("Cannot decrypt {}: wrong {}", field, PASSPHRASE_ENV),
+        })?;
+    String::from_utf8(plain).with_context(|| format!("Decrypted {} is not valid UTF-8", field))
+}
@@ -0,0 +1,78 @@
+use chrono::NaiveDateTime;
+
+const TAG_DATE_TIME: u16 = 0x0132;
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+
+/// 读取 JPEG 中 EXIF 记录的拍摄时间（DateTimeOriginal，缺失时用 DateTime）
+pub fn capture_date(content: &[u8]) -> Option<NaiveDateTime> {
+    if !content.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    // 逐段查找 APP1/Exif，遇到 SOS 说明元数据已经结束
+    let mut pos = 2;
+    while pos + 4 <= content.len() && content[pos] == 0xFF {
+        let marker = content[pos + 1];
+        if marker == 0xDA || marker == 0xD9 {
+            return None;
+        }
+        let len = u16::from_be_bytes([content[pos + 2], content[pos + 3]]) as usize;
+        let data = content.get(pos + 4..pos + 2 + len)?;
+        if marker == 0xE1 && data.starts_with(b"Exif\0\0") {
+            return tiff_date(&data[6..]);
+        }
+        pos += 2 + len;
+    }
+    None
+}
+
+fn tiff_date(tiff: &[u8]) -> Option<NaiveDateTime> {
+    let le = match tiff.get(0..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let u16_at = |at: usize| -> Option<u16> {
+        let b: [u8; 2] = tiff.get(at..at + 2)?.try_into().ok()?;
+        Some(if le {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        })
+    };
+    let u32_at = |at: usize| -> Option<usize> {
+        let b: [u8; 4] = tiff.get(at..at + 4)?.try_into().ok()?;
+        Some(if le {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        } as usize)
+    };
+    let find = |ifd: usize, tag: u16| -> Option<usize> {
+        (0..u16_at(ifd)? as usize)
+            .map(|i| ifd + 2 + i * 12)
+            .find(|&entry| u16_at(entry) == Some(tag))
+    };
+    let ascii = |entry: usize| -> Option<&str> {
+        let count = u32_at(entry + 4)?;
+        let start = if count <= 4 {
+            entry + 8
+        } else {
+            u32_at(entry + 8)?
+        };
+        let bytes = tiff.get(start..start.checked_add(count)?)?;
+        std::str::from_utf8(bytes)
+            .ok()
+            .map(|s| s.trim_end_matches('\0'))
+    };
+
+    let ifd0 = u32_at(4)?;
+    let original = find(ifd0, TAG_EXIF_IFD)
+        .and_then(|entry| u32_at(entry + 8))
+        .and_then(|exif| find(exif, TAG_DATE_TIME_ORIGINAL))
+        .and_then(ascii);
+    let value = original.or_else(|| find(ifd0, TAG_DATE_TIME).and_then(ascii))?;
+
+    NaiveDateTime::parse_from_str(value, "%Y:%m:%d %H:%M:%S").ok()
+}
@@ -0,0 +1,82 @@
+//! 进程退出码
+//!
+//! 脚本和 CI 据此判断结果，取值保持稳定；完整列表显示在 `rcloud --help` 末尾。
+
+use std::process::ExitCode;
+
+use rustcloud_client::{is_connection_error, server_error_kind, Conflict, ServerErrorKind};
+
+pub const HELP: &str = "\
+Exit codes:
+  0   Success
+  1   Failure, including syncs and imports where some items failed
+  2   Could not connect to the server
+  3   Conflicts: a remote path already exists (and nothing else failed)
+  4   Login required or expired
+  64  Invalid command-line arguments";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Success = 0,
+    Failure = 1,
+    Connection = 2,
+    Conflict = 3,
+    Auth = 4,
+    Usage = 64,
+}
+
+impl From<Status> for ExitCode {
+    fn from(status: Status) -> Self {
+        ExitCode::from(status as u8)
+    }
+}
+
+/// 指定了退出码的错误，消息照常显示
+#[derive(Debug)]
+pub struct Coded {
+    pub status: Status,
+    pub message: String,
+}
+
+impl std::fmt::Display for Coded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Coded {}
+
+pub fn coded(status: Status, message: impl Into<String>) -> anyhow::Error {
+    Coded {
+        status,
+        message: message.into(),
+    }
+    .into()
+}
+
+pub fn status_of(error: &anyhow::Error) -> Status {
+    if let Some(coded) = error.downcast_ref::<Coded>() {
+        coded.status
+    } else if error.downcast_ref::<Conflict>().is_some() {
+        Status::Conflict
+    } else if server_error_kind(error) == Some(ServerErrorKind::Unauthorized) {
+        Status::Auth
+    } else if is_connection_error(error) {
+        Status::Connection
+    } else {
+        Status::Failure
+    }
+}
+
+/// 服务端返回的错误类型对应的处理建议，显示在错误信息之后
+pub fn hint(error: &anyhow::Error) -> Option<&'static str> {
+    match server_error_kind(error)? {
+        ServerErrorKind::Unauthorized => {
+            Some("Your login has expired; run `rcloud login <username>` again")
+        }
+        ServerErrorKind::Busy => Some("The server is busy; try again in a moment"),
+        ServerErrorKind::QuotaExceeded => {
+            Some("The server is out of storage space; free some space before uploading again")
+        }
+    }
+}
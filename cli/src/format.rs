@@ -0,0 +1,46 @@
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+
+/// 把字节数格式化为人类可读的形式，如 `1.5 MB`
+pub fn format_size(bytes: u64) -> String {
+    if bytes == 0 {
+        return "0 B".to_string();
+    }
+    const K: u64 = 1024;
+    const SIZES: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let i = (bytes as f64).log(K as f64).floor() as usize;
+    let i = i.min(SIZES.len() - 1);
+    format!("{:.1} {}", bytes as f64 / K.pow(i as u32) as f64, SIZES[i])
+}
+
+/// 解析时间参数：RFC 3339（`2024-06-01T12:00:00Z`），或本地时间的 `2024-06-01`、`2024-06-01 12:00`
+pub fn parse_timestamp(s: &str) -> Result<DateTime<Utc>, String> {
+    let s = s.trim();
+    if let Ok(at) = DateTime::parse_from_rfc3339(s) {
+        return Ok(at.with_timezone(&Utc));
+    }
+    let local = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M"))
+        .or_else(|_| NaiveDate::parse_from_str(s, "%Y-%m-%d").map(|d| d.and_time(NaiveTime::MIN)))
+        .map_err(|_| format!("Invalid time: {} (expected e.g. 2024-06-01T00:00:00Z)", s))?;
+    local
+        .and_local_timezone(Local)
+        .earliest()
+        .map(|at| at.with_timezone(&Utc))
+        .ok_or_else(|| format!("Invalid local time: {}", s))
+}
+
+/// 解析 `4K`、`16M`、`1GB` 这类大小参数
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (number, unit) = s.split_at(s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len()));
+    let base: u64 = number.parse().map_err(|_| format!("Invalid size: {}", s))?;
+    let multiplier = match unit.to_ascii_uppercase().trim_end_matches('B') {
+        "" => 1,
+        "K" => 1024,
+        "M" => 1024 * 1024,
+        "G" => 1024 * 1024 * 1024,
+        _ => return Err(format!("Invalid size unit: {}", s)),
+    };
+    base.checked_mul(multiplier)
+        .ok_or_else(|| format!("Size too large: {}", s))
+}
@@ -0,0 +1,143 @@
+//! 同步前后执行的用户命令
+//!
+//! - pre_sync：在扫描本地文件之前执行，退出码非 0 时中止本次同步
+//! - post_sync：同步结束后执行，成功、失败、取消都会触发；
+//!   完整报告以 JSON 写入 stdin，常用字段同时放在 `RCLOUD_*` 环境变量中
+//!
+//! 命令交给系统 shell 解释（Unix 上是 `sh -c`，Windows 上是 `cmd /C`），
+//! 工作目录为同步目录，输出直接显示在终端
+
+use std::path::Path;
+use std::process::Stdio;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+use crate::sync::SyncReport;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SyncHooks {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_sync: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_sync: Option<String>,
+}
+
+impl SyncHooks {
+    pub fn is_empty(&self) -> bool {
+        self.pre_sync.is_none() && self.post_sync.is_none()
+    }
+}
+
+/// 一次同步的结果，post_sync 从 stdin 读到的就是它的 JSON
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum SyncOutcome {
+    Success { report: SyncReport },
+    Cancelled,
+    Failure { error: String },
+}
+
+impl SyncOutcome {
+    fn status(&self) -> &'static str {
+        match self {
+            SyncOutcome::Success { .. } => "success",
+            SyncOutcome::Cancelled => "cancelled",
+            SyncOutcome::Failure { .. } => "failure",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct HookPayload<'a> {
+    path: &'a Path,
+    dry_run: bool,
+    #[serde(flatten)]
+    outcome: &'a SyncOutcome,
+}
+
+pub async fn run_pre_sync(command: &str, sync_path: &Path, dry_run: bool) -> Result<()> {
+    let status = shell(command, sync_path, dry_run)
+        .env("RCLOUD_HOOK", "pre_sync")
+        .status()
+        .await
+        .with_context(|| format!("Failed to run pre-sync hook: {}", command))?;
+    if !status.success() {
+        anyhow::bail!("Pre-sync hook failed ({}), sync aborted", status);
+    }
+    Ok(())
+}
+
+/// 钩子本身失败只打印警告，不改变同步的结果
+pub async fn run_post_sync(command: &str, sync_path: &Path, dry_run: bool, outcome: &SyncOutcome) {
+    if let Err(e) = post_sync(command, sync_path, dry_run, outcome).await {
+        eprintln!("Warning: post-sync hook: {:#}", e);
+    }
+}
+
+async fn post_sync(
+    command: &str,
+    sync_path: &Path,
+    dry_run: bool,
+    outcome: &SyncOutcome,
+) -> Result<()> {
+    let payload = serde_json::to_vec(&HookPayload {
+        path: sync_path,
+        dry_run,
+        outcome,
+    })?;
+
+    let mut hook = shell(command, sync_path, dry_run);
+    hook.env("RCLOUD_HOOK", "post_sync")
+        .env("RCLOUD_SYNC_STATUS", outcome.status())
+        .stdin(Stdio::piped());
+    match outcome {
+        SyncOutcome::Success { report } => {
+            hook.env("RCLOUD_UPLOADED", report.uploaded.to_string())
+                .env("RCLOUD_DOWNLOADED", report.downloaded.to_string())
+                .env("RCLOUD_DELETED", report.deleted.to_string())
+                .env("RCLOUD_SKIPPED", report.skipped.to_string());
+        }
+        SyncOutcome::Failure { error } => {
+            hook.env("RCLOUD_SYNC_ERROR", error);
+        }
+        SyncOutcome::Cancelled => {}
+    }
+
+    let mut child = hook
+        .spawn()
+        .with_context(|| format!("failed to run {}", command))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // 钩子不读 stdin 就退出时会得到 BrokenPipe，不算错误
+        match stdin.write_all(&payload).await {
+            Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => return Err(e.into()),
+            _ => {}
+        }
+    }
+    let status = child.wait().await?;
+    if !status.success() {
+        anyhow::bail!("exited with {}", status);
+    }
+    Ok(())
+}
+
+fn shell(command: &str, sync_path: &Path, dry_run: bool) -> tokio::process::Command {
+    #[cfg(windows)]
+    let mut shell = {
+        let mut shell = tokio::process::Command::new("cmd");
+        shell.arg("/C").arg(command);
+        shell
+    };
+    #[cfg(not(windows))]
+    let mut shell = {
+        let mut shell = tokio::process::Command::new("sh");
+        shell.arg("-c").arg(command);
+        shell
+    };
+    shell
+        .current_dir(sync_path)
+        .env("RCLOUD_SYNC_PATH", sync_path)
+        .env("RCLOUD_DRY_RUN", if dry_run { "1" } else { "0" });
+    shell
+}
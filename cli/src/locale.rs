@@ -0,0 +1,79 @@
+//! 按区域设置格式化日期和数字
+//!
+//! 只覆盖命令行输出用得到的两项：日期中年月日的顺序和分隔符，以及千位分隔符。
+//! 区域取自 LC_ALL / LC_TIME / LANG，未设置时使用系统区域设置；无法识别时按 ISO 8601 输出。
+
+use chrono::{DateTime, TimeZone};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocaleFormat {
+    /// strftime 格式的日期部分
+    date: &'static str,
+    /// None 表示不分组
+    thousands: Option<char>,
+}
+
+impl Default for LocaleFormat {
+    fn default() -> Self {
+        LocaleFormat {
+            date: "%Y-%m-%d",
+            thousands: None,
+        }
+    }
+}
+
+impl LocaleFormat {
+    pub fn current() -> Self {
+        let name = ["LC_ALL", "LC_TIME", "LANG"]
+            .iter()
+            .find_map(|var| std::env::var(var).ok().filter(|v| !v.is_empty()))
+            .or_else(sys_locale::get_locale)
+            .unwrap_or_default();
+        Self::for_name(&name)
+    }
+
+    /// 接受 "zh_CN.UTF-8"、"de_DE@euro"、"en-US" 等写法
+    pub fn for_name(name: &str) -> Self {
+        let name = name.split(['.', '@']).next().unwrap_or_default();
+        let mut parts = name.split(['_', '-']);
+        let language = parts.next().unwrap_or_default().to_ascii_lowercase();
+        let region = parts.next().unwrap_or_default().to_ascii_uppercase();
+
+        let (date, thousands) = match (language.as_str(), region.as_str()) {
+            ("en", "US") => ("%m/%d/%Y", Some(',')),
+            ("en", _) => ("%d/%m/%Y", Some(',')),
+            ("de" | "da" | "tr", _) => ("%d.%m.%Y", Some('.')),
+            ("ru" | "uk" | "pl" | "cs" | "fi" | "nb" | "no", _) => ("%d.%m.%Y", Some('\u{a0}')),
+            ("fr", _) => ("%d/%m/%Y", Some('\u{a0}')),
+            ("es" | "it" | "pt", _) => ("%d/%m/%Y", Some('.')),
+            ("nl", _) => ("%d-%m-%Y", Some('.')),
+            ("zh" | "ja", _) => ("%Y/%m/%d", Some(',')),
+            ("ko", _) => ("%Y. %m. %d.", Some(',')),
+            ("sv" | "lt", _) => ("%Y-%m-%d", Some('\u{a0}')),
+            _ => return Self::default(),
+        };
+        LocaleFormat { date, thousands }
+    }
+
+    pub fn datetime<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> String
+    where
+        Tz::Offset: std::fmt::Display,
+    {
+        format!("{} {}", time.format(self.date), time.format("%H:%M"))
+    }
+
+    pub fn number(&self, value: u64) -> String {
+        let digits = value.to_string();
+        let Some(separator) = self.thousands else {
+            return digits;
+        };
+        let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+        for (i, digit) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                grouped.push(separator);
+            }
+            grouped.push(digit);
+        }
+        grouped
+    }
+}
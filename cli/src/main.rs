@@ -1,14 +1,28 @@
 use clap::{Parser, Subcommand};
 use anyhow::Result;
 
-mod client;
 mod commands;
 mod config;
+mod credentials;
+mod encryption;
+mod exif;
+mod exit;
+mod format;
+mod hooks;
+mod locale;
+mod output;
+mod picker;
+mod progress_bar;
+mod schedule;
 mod sync;
+mod uploads;
+mod vault;
+mod xattrs;
 
 #[derive(Parser)]
 #[command(name = "rcloud")]
 #[command(about = "RustCloud CLI - File sync client", long_about = None)]
+#[command(after_help = exit::HELP)]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
@@ -18,6 +32,18 @@ struct Cli {
 
     #[arg(short, long, global = true)]
     verbose: bool,
+
+    #[arg(long, global = true, help = "Skip TLS certificate verification")]
+    insecure: bool,
+
+    #[arg(long, global = true, help = "Extra CA certificate (PEM) to trust")]
+    ca_cert: Option<String>,
+
+    #[arg(short, long, global = true, help = "Only print errors, warnings and requested data")]
+    quiet: bool,
+
+    #[arg(long, global = true, help = "Keep summaries but hide per-file progress")]
+    no_progress: bool,
 }
 
 #[derive(Subcommand)]
@@ -29,6 +55,21 @@ enum Commands {
         
         #[arg(short, long)]
         dry_run: bool,
+
+        #[arg(long, help = "Ask for confirmation after showing the transfer estimate")]
+        confirm: bool,
+
+        #[arg(long, value_parser = format::parse_size, help = "Abort if the estimated transfer exceeds this size (e.g. 500M)")]
+        max_transfer: Option<u64>,
+
+        #[arg(short, long, default_value_t = sync::DEFAULT_JOBS, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..), help = "Number of files to upload or download at the same time")]
+        jobs: usize,
+
+        #[arg(long, value_parser = schedule::parse_schedule, conflicts_with = "confirm", help = "Keep running and sync on a cron schedule (e.g. \"*/15 * * * *\")")]
+        schedule: Option<schedule::Schedule>,
+
+        #[arg(long, conflicts_with_all = ["confirm", "schedule"], help = "Keep running and sync on the schedule saved in the config")]
+        daemon: bool,
     },
 
     #[command(about = "Show sync status")]
@@ -37,19 +78,91 @@ enum Commands {
         path: Option<String>,
     },
 
+    #[command(about = "Print file changes on the server as they happen")]
+    Watch,
+
+    #[command(about = "List files edited on two devices that still need merging")]
+    Conflicts,
+
+    #[command(about = "List and restore deleted files kept on the server")]
+    Trash {
+        #[command(subcommand)]
+        command: TrashCommand,
+    },
+
     #[command(about = "Configure client")]
     Config {
         #[arg(short, long)]
         server: Option<String>,
+
+        #[arg(long, help = "Also push every sync to this server (log in to it separately)")]
+        add_mirror: Option<String>,
+
+        #[arg(long, help = "Stop pushing syncs to this mirror server")]
+        remove_mirror: Option<String>,
+
+        #[arg(long, help = "Skip paths matching this gitignore-style pattern when syncing")]
+        add_ignore: Option<String>,
+
+        #[arg(long, help = "Remove a pattern added with --add-ignore")]
+        remove_ignore: Option<String>,
         
         #[arg(short, long)]
         device_name: Option<String>,
+
+        #[arg(long, help = "Proxy URL for all requests")]
+        proxy: Option<String>,
+
+        #[arg(long, help = "Extra CA certificate (PEM) to trust")]
+        ca_cert: Option<String>,
+
+        #[arg(long, help = "Connect timeout in seconds")]
+        connect_timeout: Option<u64>,
+
+        #[arg(long, help = "Read timeout in seconds")]
+        read_timeout: Option<u64>,
+
+        #[arg(long, help = "Admin token, kept in the system keyring rather than the config file (empty to clear)")]
+        admin_token: Option<String>,
+
+        #[arg(long, help = "Cron schedule for `sync --daemon` (empty to clear)")]
+        sync_schedule: Option<String>,
+
+        #[arg(long, help = "Command to run before each sync; a non-zero exit aborts it (empty to clear)")]
+        pre_sync_hook: Option<String>,
+
+        #[arg(long, help = "Command to run after each sync, with the report as JSON on stdin (empty to clear)")]
+        post_sync_hook: Option<String>,
     },
 
-    #[command(about = "List remote files")]
+    #[command(about = "List remote files", disable_help_flag = true)]
     Ls {
         #[arg(short, long)]
         path: Option<String>,
+
+        #[arg(short, long, help = "Show size, modified time, version and hash prefix")]
+        long: bool,
+
+        #[arg(short, long, help = "Include entries starting with '.'")]
+        all: bool,
+
+        #[arg(short = 'h', long, help = "Show sizes as KB/MB/GB")]
+        human_readable: bool,
+
+        #[arg(long, value_enum, default_value_t, help = "Sort by name, size, time or version")]
+        sort: commands::ls::SortKey,
+
+        #[arg(short, long, help = "Reverse the sort order")]
+        reverse: bool,
+
+        #[arg(long, requires = "path", help = "List the version history of the file at --path")]
+        versions: bool,
+
+        #[arg(long, value_parser = format::parse_timestamp, conflicts_with = "versions", help = "List the directory as it was at this time (e.g. 2024-06-01T00:00:00Z)")]
+        as_of: Option<chrono::DateTime<chrono::Utc>>,
+
+        #[arg(long, action = clap::ArgAction::Help, help = "Print help")]
+        help: Option<bool>,
     },
 
     #[command(about = "Upload a file")]
@@ -59,47 +172,586 @@ enum Commands {
         
         #[arg(short, long)]
         remote_path: Option<String>,
+
+        #[arg(long, conflicts_with = "remote_path", help = "Pick the remote folder interactively")]
+        pick: bool,
+
+        #[arg(long, value_parser = ["overwrite", "rename", "fail"], help = "What to do if the remote path already exists")]
+        on_conflict: Option<String>,
     },
 
     #[command(about = "Download a file")]
     Download {
-        #[arg(short, long)]
-        remote_path: String,
+        #[arg(short, long, help = "Remote file; picked interactively if omitted")]
+        remote_path: Option<String>,
         
         #[arg(short, long)]
         local_path: Option<String>,
+
+        #[arg(
+            long,
+            help = "Continue from data left by an interrupted --resume download, and keep it if interrupted again"
+        )]
+        resume: bool,
+    },
+
+    #[command(about = "Create a public download link for a remote file")]
+    Share {
+        #[arg(help = "Remote file; picked interactively if omitted")]
+        remote_path: Option<String>,
+
+        #[arg(long, help = "Expire the link after this many hours")]
+        expires_in_hours: Option<i64>,
+
+        #[arg(long, help = "Stop serving the link after this many downloads")]
+        max_downloads: Option<u64>,
+    },
+
+    #[command(about = "Restore a remote file to an earlier version")]
+    Rollback {
+        remote_path: String,
+
+        #[arg(long, help = "Version to restore, as listed by `ls --versions`")]
+        version: i32,
+    },
+
+    #[command(about = "Move or rename a remote file or directory, keeping its version history")]
+    Mv {
+        #[arg(help = "Remote file or folder; picked interactively if omitted")]
+        from: Option<String>,
+
+        #[arg(help = "New path; a destination folder is picked interactively if omitted")]
+        to: Option<String>,
+    },
+
+    #[command(about = "Copy a remote file on the server without uploading its content again")]
+    Cp { from: String, to: String },
+
+    #[command(about = "Create a remote folder, including missing parent folders")]
+    Mkdir { remote_path: String },
+
+    #[command(about = "Photo backup")]
+    Photos {
+        #[command(subcommand)]
+        command: PhotosCommand,
+    },
+
+    #[command(about = "End-to-end encryption of uploaded files")]
+    Encrypt {
+        #[command(subcommand)]
+        command: EncryptCommand,
+    },
+
+    #[command(about = "Print the first lines of a remote file")]
+    Head {
+        remote_path: String,
+
+        #[arg(short = 'n', long, default_value_t = 10)]
+        lines: usize,
+    },
+
+    #[command(about = "Print the last lines of a remote file")]
+    Tail {
+        remote_path: String,
+
+        #[arg(short = 'n', long, default_value_t = 10)]
+        lines: usize,
+    },
+
+    #[command(about = "Find files with identical content on the server")]
+    Dedupe {
+        #[arg(long, help = "List duplicate groups and potential savings")]
+        report: bool,
+
+        #[arg(long, default_value = "1", value_parser = format::parse_size, help = "Ignore files smaller than this (e.g. 1M)")]
+        min_size: u64,
+    },
+
+    #[command(about = "Show the largest files and folders on the server")]
+    Du {
+        #[arg(long, default_value_t = 20, help = "Number of files to list")]
+        top: usize,
+
+        #[arg(long, default_value_t = 1, help = "Folder levels to roll up sizes to")]
+        depth: usize,
+
+        #[arg(long, value_name = "MONTHS", help = "Only files not modified or read for this many months")]
+        stale: Option<u32>,
+    },
+
+    #[command(about = "Log in to the server and store the access token")]
+    Login {
+        username: String,
+
+        #[arg(long, help = "Create the account first")]
+        register: bool,
+
+        #[arg(
+            long,
+            conflicts_with = "register",
+            help = "Request a token that can upload but never delete, for backups"
+        )]
+        append_only: bool,
+    },
+
+    #[command(about = "Forget the stored access token")]
+    Logout,
+
+    #[command(about = "Diagnose configuration, connectivity and the sync directory")]
+    Doctor {
+        #[arg(short, long)]
+        path: Option<String>,
+    },
+
+    #[command(about = "Benchmark transfer and hashing throughput")]
+    Benchmark {
+        #[arg(long, value_delimiter = ',', default_value = "4K,1M,16M", value_parser = format::parse_size)]
+        sizes: Vec<u64>,
+
+        #[arg(long, default_value_t = 8, help = "Files per size")]
+        count: usize,
+
+        #[arg(short, long, default_value_t = 4)]
+        concurrency: usize,
+
+        #[arg(long, default_value = ".rcloud-bench")]
+        remote_dir: String,
+
+        #[arg(long, help = "Keep benchmark files on the server")]
+        keep: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum PhotosCommand {
+    #[command(about = "Upload images into <remote-root>/YYYY/MM/ by capture date")]
+    Import {
+        dir: String,
+
+        #[arg(long, default_value = "Photos")]
+        remote_root: String,
+
+        #[arg(long, help = "Delete local copies once they are confirmed on the server")]
+        delete_after: bool,
+
+        #[arg(short, long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum TrashCommand {
+    #[command(about = "List deleted files and folders that can still be restored")]
+    List,
+
+    #[command(about = "Put a deleted file or folder back at its original path")]
+    Restore { id: uuid::Uuid },
+}
+
+#[derive(Subcommand)]
+enum EncryptCommand {
+    #[command(about = "Create the encryption key on the server and encrypt from this device")]
+    Init {
+        #[arg(long, help = "Encrypt file and folder names as well as contents")]
+        names: bool,
     },
+
+    #[command(about = "Unlock the server's existing key and encrypt from this device")]
+    Enable,
+
+    #[command(about = "Stop encrypting from this device")]
+    Disable,
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
+async fn main() -> std::process::ExitCode {
+    // clap 默认以 2 退出，与连接失败冲突；--help 和 --version 仍以 0 退出
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) => {
+            let _ = e.print();
+            return if e.use_stderr() {
+                exit::Status::Usage.into()
+            } else {
+                exit::Status::Success.into()
+            };
+        }
+    };
 
+    match run(cli).await {
+        Ok(()) => exit::Status::Success.into(),
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            if let Some(hint) = exit::hint(&e) {
+                eprintln!("{}", hint);
+            }
+            exit::status_of(&e).into()
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    output::init(cli.quiet, cli.no_progress);
     if cli.verbose {
         tracing_subscriber::fmt::init();
     }
 
-    let config = config::load()?;
+    // 配置文件损坏时 doctor 仍要能运行，由它报告具体问题
+    let config = match config::load() {
+        Ok(config) => config,
+        Err(_) if matches!(cli.command, Commands::Doctor { .. }) => config::Config::default(),
+        Err(e) => return Err(e),
+    };
     let server = cli.server.unwrap_or(config.server);
 
+    let mut http = config.http;
+    // 服务端要求的最低版本指的是 rcloud 的版本
+    http.client_version = Some(env!("CARGO_PKG_VERSION").to_string());
+    if cli.insecure {
+        http.insecure = true;
+    }
+    if let Some(ca_cert) = cli.ca_cert {
+        http.ca_cert = Some(ca_cert.into());
+    }
+    // 令牌按服务器分别保存；读不到凭据时照常运行，只是不带令牌
+    let with_credentials = |server: &str| {
+        let stored = |name| {
+            credentials::get(server, name).unwrap_or_else(|e| {
+                eprintln!("Warning: could not read stored credentials: {:#}", e);
+                None
+            })
+        };
+        let mut http = http.clone();
+        http.admin_token = stored(credentials::ADMIN_TOKEN);
+        http.auth_token = stored(credentials::AUTH_TOKEN);
+        http
+    };
+    let http = with_credentials(&server);
+
+    // config 命令不需要连接服务器，其余命令按需构建客户端
+    let device_id = config.device_id;
+    let end_to_end = config.end_to_end;
+    let client = || rustcloud_client::Client::new(&server, &http, device_id.as_deref());
+    // 镜像服务器在同步时才连接，连不上只影响它自己
+    let mirrors = || {
+        config
+            .mirrors
+            .iter()
+            .map(|mirror| {
+                let http = with_credentials(mirror);
+                rustcloud_client::Client::new(mirror, &http, device_id.as_deref())
+            })
+            .collect::<Result<Vec<_>>>()
+    };
+    // 先协商协议版本，不兼容时给出明确提示，而不是随后某个请求的 404
+    let connect = || async {
+        let client = client()?;
+        if let Some(capabilities) = client.check_compatibility().await? {
+            if capabilities.protocol == 0 {
+                eprintln!(
+                    "Warning: the server does not report its capabilities (older version); upgrade it if commands fail"
+                );
+            }
+            // 要求登录的服务端，在第一个请求失败前就说明原因
+            if capabilities.supports(rustcloud_client::feature::AUTH)
+                && client.current_user().await?.is_none()
+            {
+                let message = if http.auth_token.is_some() {
+                    "Your login has expired; run `rcloud login <username>` again"
+                } else {
+                    "This server requires login; run `rcloud login <username>` first"
+                };
+                return Err(exit::coded(exit::Status::Auth, message));
+            }
+        }
+        Ok::<_, anyhow::Error>(client)
+    };
+
     match cli.command {
-        Commands::Sync { path, dry_run } => {
-            commands::sync::run(&server, path.as_deref(), dry_run).await?;
+        Commands::Sync {
+            path,
+            dry_run,
+            confirm,
+            max_transfer,
+            jobs,
+            schedule,
+            daemon,
+        } => {
+            let options = commands::sync::SyncOptions {
+                dry_run,
+                confirm,
+                max_transfer,
+                jobs,
+            };
+            let schedule = match (schedule, daemon) {
+                (Some(schedule), _) => Some(schedule),
+                (None, true) => {
+                    let expression = config.sync_schedule.as_deref().ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "No sync schedule configured; set one with `rcloud config --sync-schedule` or pass --schedule"
+                        )
+                    })?;
+                    Some(schedule::parse_schedule(expression).map_err(anyhow::Error::msg)?)
+                }
+                (None, false) => None,
+            };
+            let client = connect().await?;
+            let mirrors = mirrors()?;
+            match schedule {
+                Some(schedule) => {
+                    commands::sync::run_scheduled(
+                        &client,
+                        &mirrors,
+                        path.as_deref(),
+                        options,
+                        &schedule,
+                    )
+                    .await?
+                }
+                None => commands::sync::run(&client, &mirrors, path.as_deref(), options).await?,
+            }
         }
         Commands::Status { path } => {
-            commands::status::run(&server, path.as_deref()).await?;
+            commands::status::run(&connect().await?, path.as_deref()).await?;
+        }
+        Commands::Watch => {
+            commands::watch::run(&connect().await?).await?;
+        }
+        Commands::Conflicts => {
+            commands::conflicts::run(&connect().await?).await?;
+        }
+        Commands::Trash { command } => match command {
+            TrashCommand::List => commands::trash::list(&connect().await?).await?,
+            TrashCommand::Restore { id } => {
+                commands::trash::restore(&connect().await?, id).await?;
+            }
+        },
+        Commands::Config {
+            server: new_server,
+            add_mirror,
+            remove_mirror,
+            add_ignore,
+            remove_ignore,
+            device_name,
+            proxy,
+            ca_cert,
+            connect_timeout,
+            read_timeout,
+            admin_token,
+            sync_schedule,
+            pre_sync_hook,
+            post_sync_hook,
+        } => {
+            commands::config::run(
+                commands::config::ConfigUpdate {
+                    server: new_server,
+                    add_mirror,
+                    remove_mirror,
+                    add_ignore,
+                    remove_ignore,
+                    device_name,
+                    proxy,
+                    ca_cert,
+                    connect_timeout,
+                    read_timeout,
+                    admin_token,
+                    sync_schedule,
+                    pre_sync_hook,
+                    post_sync_hook,
+                },
+                &server,
+            )?;
+        }
+        Commands::Ls {
+            path,
+            long,
+            all,
+            human_readable,
+            sort,
+            reverse,
+            versions,
+            as_of,
+            help: _,
+        } => {
+            let client = connect().await?;
+            let vault = match end_to_end {
+                true => Some(vault::unlock(&client).await?),
+                false => None,
+            };
+            if versions {
+                let path = path.unwrap_or_default();
+                let path = match &vault {
+                    Some(vault) => vault.encrypt_path(&path)?,
+                    None => path,
+                };
+                commands::ls::versions(&client, &path, human_readable).await?;
+            } else {
+                let options = commands::ls::LsOptions {
+                    long,
+                    all,
+                    human: human_readable,
+                    sort,
+                    reverse,
+                    as_of,
+                };
+                commands::ls::run(&client, path.as_deref(), options, vault.as_ref()).await?;
+            }
+        }
+        Commands::Upload {
+            path,
+            remote_path,
+            pick,
+            on_conflict,
+        } => {
+            let client = connect().await?;
+            let vault = match end_to_end {
+                true => Some(vault::unlock(&client).await?),
+                false => None,
+            };
+            let remote_path = if pick {
+                let mut folder = picker::pick_folder(&client, "Upload into:").await?;
+                if let Some(vault) = &vault {
+                    folder = vault.decrypt_path(&folder)?;
+                }
+                let name = std::path::Path::new(&path)
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .unwrap_or("file");
+                Some(if folder.is_empty() {
+                    name.to_string()
+                } else {
+                    format!("{}/{}", folder, name)
+                })
+            } else {
+                remote_path
+            };
+            commands::upload::run(
+                &client,
+                &path,
+                remote_path.as_deref(),
+                on_conflict.as_deref(),
+                vault.as_ref(),
+            )
+            .await?;
+        }
+        Commands::Download {
+            remote_path,
+            local_path,
+            resume,
+        } => {
+            let client = connect().await?;
+            let vault = match end_to_end {
+                true => Some(vault::unlock(&client).await?),
+                false => None,
+            };
+            let remote_path = match (remote_path, &vault) {
+                (Some(remote_path), _) => remote_path,
+                (None, None) => picker::pick_file(&client, "File to download:").await?,
+                (None, Some(vault)) => {
+                    vault.decrypt_path(&picker::pick_file(&client, "File to download:").await?)?
+                }
+            };
+            commands::download::run(
+                &client,
+                &remote_path,
+                local_path.as_deref(),
+                resume,
+                vault.as_ref(),
+            )
+            .await?;
+        }
+        Commands::Share {
+            remote_path,
+            expires_in_hours,
+            max_downloads,
+        } => {
+            commands::share::run(
+                &connect().await?,
+                remote_path.as_deref(),
+                expires_in_hours,
+                max_downloads,
+            )
+            .await?;
+        }
+        Commands::Rollback { remote_path, version } => {
+            commands::rollback::run(&connect().await?, &remote_path, version).await?;
+        }
+        Commands::Mv { from, to } => {
+            commands::mv::run(&connect().await?, from.as_deref(), to.as_deref()).await?;
+        }
+        Commands::Cp { from, to } => {
+            commands::cp::run(&connect().await?, &from, &to).await?;
+        }
+        Commands::Mkdir { remote_path } => {
+            commands::mkdir::run(&connect().await?, &remote_path).await?;
+        }
+        Commands::Photos {
+            command:
+                PhotosCommand::Import {
+                    dir,
+                    remote_root,
+                    delete_after,
+                    dry_run,
+                },
+        } => {
+            let options = commands::photos::ImportOptions {
+                dir: dir.into(),
+                remote_root,
+                delete_after,
+                dry_run,
+            };
+            commands::photos::import(&connect().await?, options).await?;
+        }
+        Commands::Encrypt { command } => match command {
+            EncryptCommand::Init { names } => {
+                commands::encrypt::init(&connect().await?, names).await?;
+            }
+            EncryptCommand::Enable => commands::encrypt::enable(&connect().await?).await?,
+            EncryptCommand::Disable => commands::encrypt::disable()?,
+        },
+        Commands::Head { remote_path, lines } => {
+            commands::preview::run(&connect().await?, &remote_path, lines, false).await?;
+        }
+        Commands::Tail { remote_path, lines } => {
+            commands::preview::run(&connect().await?, &remote_path, lines, true).await?;
+        }
+        Commands::Dedupe { report, min_size } => {
+            if !report {
+                anyhow::bail!("Only --report is supported for now");
+            }
+            commands::dedupe::report(&connect().await?, min_size).await?;
+        }
+        Commands::Du { top, depth, stale } => {
+            commands::du::run(&connect().await?, top, depth, stale).await?;
         }
-        Commands::Config { server: new_server, device_name } => {
-            commands::config::run(new_server.as_deref(), device_name.as_deref())?;
+        Commands::Login {
+            username,
+            register,
+            append_only,
+        } => {
+            commands::login::run(&client()?, &server, &username, register, append_only).await?;
         }
-        Commands::Ls { path } => {
-            commands::ls::run(&server, path.as_deref()).await?;
+        Commands::Logout => {
+            commands::login::logout(&server)?;
         }
-        Commands::Upload { path, remote_path } => {
-            commands::upload::run(&server, &path, remote_path.as_deref()).await?;
+        Commands::Doctor { path } => {
+            commands::doctor::run(client(), path.as_deref()).await?;
         }
-        Commands::Download { remote_path, local_path } => {
-            commands::download::run(&server, &remote_path, local_path.as_deref()).await?;
+        Commands::Benchmark {
+            sizes,
+            count,
+            concurrency,
+            remote_dir,
+            keep,
+        } => {
+            let options = commands::benchmark::BenchmarkOptions {
+                sizes,
+                count,
+                concurrency,
+                remote_dir,
+                keep,
+            };
+            commands::benchmark::run(&connect().await?, options).await?;
         }
     }
 
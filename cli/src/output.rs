@@ -0,0 +1,52 @@
+//! 输出级别
+//!
+//! - `--quiet`：只输出错误、警告和命令本身要给出的数据（如 ls 的列表、share 的链接）
+//! - `--no-progress`：保留开始和汇总信息，去掉逐项进度和传输进度条
+//!
+//! 两个宏都经 [`progress_bar::suspend`](crate::progress_bar::suspend) 打印，不会和进度条混在同一行
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+static NO_PROGRESS: AtomicBool = AtomicBool::new(false);
+
+pub fn init(quiet: bool, no_progress: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+    NO_PROGRESS.store(no_progress, Ordering::Relaxed);
+}
+
+pub fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+pub fn show_progress() -> bool {
+    !quiet() && !NO_PROGRESS.load(Ordering::Relaxed)
+}
+
+/// 提示信息，--quiet 时不输出
+macro_rules! say {
+    () => {
+        $crate::output::say!("")
+    };
+    ($($arg:tt)*) => {
+        if !$crate::output::quiet() {
+            let line = format!($($arg)*);
+            $crate::progress_bar::suspend(|| println!("{}", line));
+        }
+    };
+}
+
+/// 逐项进度，--quiet 或 --no-progress 时不输出
+macro_rules! progress {
+    () => {
+        $crate::output::progress!("")
+    };
+    ($($arg:tt)*) => {
+        if $crate::output::show_progress() {
+            let line = format!($($arg)*);
+            $crate::progress_bar::suspend(|| println!("{}", line));
+        }
+    };
+}
+
+pub(crate) use {progress, say};
@@ -0,0 +1,72 @@
+//! 交互式选择远程路径
+//!
+//! 命令行没给出远程路径时，通过搜索接口取回候选项，在终端里模糊筛选（类似 fzf）。
+//! 非交互环境（脚本、管道）下直接报错，不会卡在等待输入上。
+
+use std::collections::BTreeSet;
+use std::io::IsTerminal;
+
+use anyhow::Result;
+use inquire::Select;
+use rustcloud_client::Client;
+
+/// 与服务端搜索接口的上限一致
+const MAX_CANDIDATES: usize = 1000;
+const PAGE_SIZE: usize = 15;
+
+fn ensure_interactive(what: &str) -> Result<()> {
+    if !std::io::stdin().is_terminal() || !std::io::stderr().is_terminal() {
+        anyhow::bail!(
+            "No {} given; pass it explicitly or run in a terminal to pick one",
+            what
+        );
+    }
+    Ok(())
+}
+
+async fn candidates(client: &Client) -> Result<Vec<String>> {
+    let result = client.search("", MAX_CANDIDATES).await?;
+    if result.total > result.files.len() {
+        eprintln!(
+            "Showing the first {} of {} files; type to narrow the list",
+            result.files.len(),
+            result.total
+        );
+    }
+    Ok(result.files.into_iter().map(|f| f.path).collect())
+}
+
+/// 所有文件的上级目录，根目录记作 "/"
+fn folders(paths: &[String]) -> Vec<String> {
+    let mut folders = BTreeSet::from(["/".to_string()]);
+    for path in paths {
+        let mut parent = path.as_str();
+        while let Some((dir, _)) = parent.rsplit_once('/') {
+            folders.insert(dir.to_string());
+            parent = dir;
+        }
+    }
+    folders.into_iter().collect()
+}
+
+/// 选择一个远程文件
+pub async fn pick_file(client: &Client, prompt: &str) -> Result<String> {
+    ensure_interactive("remote path")?;
+    let files = candidates(client).await?;
+    if files.is_empty() {
+        anyhow::bail!("There are no files on the server");
+    }
+    Ok(Select::new(prompt, files)
+        .with_page_size(PAGE_SIZE)
+        .prompt()?)
+}
+
+/// 选择一个远程目录，根目录返回空字符串
+pub async fn pick_folder(client: &Client, prompt: &str) -> Result<String> {
+    ensure_interactive("remote folder")?;
+    let folders = folders(&candidates(client).await?);
+    let folder = Select::new(prompt, folders)
+        .with_page_size(PAGE_SIZE)
+        .prompt()?;
+    Ok(folder.trim_start_matches('/').to_string())
+}
@@ -0,0 +1,212 @@
+//! 传输进度条
+//!
+//! 上传、下载和同步时在 stderr 上画一行：已传输的字节数、速度、预计剩余时间，
+//! 同步时还有已完成的文件数。只在 stderr 是终端、且没有 `--quiet` / `--no-progress` 时显示，
+//! 脚本和管道里的输出与以前相同。
+//!
+//! 同一时间只画一个进度条；其余输出经 [`suspend`] 先擦掉进度行，打印后再重画，
+//! 所以逐项进度（`[UPLOAD] ...`）出现在进度条上方，进度条始终在最后一行。
+
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use unicode_width::UnicodeWidthChar;
+
+use crate::format::format_size;
+use crate::output;
+
+/// 两次重画之间的最短间隔，每收到一段数据都重画会让终端闪烁
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+const BAR_WIDTH: usize = 20;
+
+static ACTIVE: Mutex<Option<State>> = Mutex::new(None);
+
+struct State {
+    label: String,
+    total: u64,
+    done: u64,
+    /// 同步时的 (已完成, 总数)
+    files: Option<(usize, usize)>,
+    started: Instant,
+    drawn: Option<Instant>,
+}
+
+fn active() -> MutexGuard<'static, Option<State>> {
+    ACTIVE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// 进度条句柄，克隆后共享同一个进度条；最后一个句柄释放时画出最终状态并换行。
+/// 不显示进度条时所有操作都是空操作
+#[derive(Clone, Default)]
+pub struct ProgressBar {
+    owner: Option<Arc<Owner>>,
+}
+
+struct Owner;
+
+impl Drop for Owner {
+    fn drop(&mut self) {
+        if let Some(mut state) = active().take() {
+            state.drawn = None;
+            draw(&mut state);
+            eprintln!();
+        }
+    }
+}
+
+impl ProgressBar {
+    pub fn new(label: &str, total: u64) -> Self {
+        if !output::show_progress() || !std::io::stderr().is_terminal() {
+            return ProgressBar::default();
+        }
+        let mut active = active();
+        // 同时同步多个服务器时只有第一个画进度条
+        if active.is_some() {
+            return ProgressBar::default();
+        }
+        let mut state = State {
+            label: label.to_string(),
+            total,
+            done: 0,
+            files: None,
+            started: Instant::now(),
+            drawn: None,
+        };
+        draw(&mut state);
+        *active = Some(state);
+        ProgressBar {
+            owner: Some(Arc::new(Owner)),
+        }
+    }
+
+    pub fn with_files(self, total: usize) -> Self {
+        self.update(|state| state.files = Some((0, total)));
+        self
+    }
+
+    pub fn file_done(&self) {
+        self.update(|state| {
+            if let Some((done, _)) = &mut state.files {
+                *done += 1;
+            }
+        });
+    }
+
+    /// 一次传输的进度，回调报告的是这次传输的累计字节数
+    pub fn transfer(&self) -> Transfer {
+        Transfer {
+            bar: self.clone(),
+            sent: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    fn update(&self, change: impl FnOnce(&mut State)) {
+        if self.owner.is_none() {
+            return;
+        }
+        if let Some(state) = active().as_mut() {
+            change(state);
+            draw(state);
+        }
+    }
+}
+
+/// 单个文件的传输进度，把累计字节数换算成进度条的增量
+#[derive(Clone)]
+pub struct Transfer {
+    bar: ProgressBar,
+    sent: Arc<AtomicU64>,
+}
+
+impl Transfer {
+    /// 重试时累计字节数从 0 重新开始，进度条随之回退
+    pub fn update(&self, sent: u64) {
+        let previous = self.sent.swap(sent, Ordering::Relaxed);
+        self.bar
+            .update(|state| state.done = (state.done + sent).saturating_sub(previous));
+    }
+}
+
+/// 擦掉进度行后执行 f（通常是打印一行），再把进度条画回来
+pub fn suspend<R>(f: impl FnOnce() -> R) -> R {
+    let mut active = active();
+    if active.is_some() {
+        eprint!("\r\x1b[K");
+    }
+    let result = f();
+    if let Some(state) = active.as_mut() {
+        state.drawn = None;
+        draw(state);
+    }
+    result
+}
+
+fn draw(state: &mut State) {
+    let now = Instant::now();
+    if state
+        .drawn
+        .is_some_and(|drawn| now.duration_since(drawn) < REDRAW_INTERVAL)
+    {
+        return;
+    }
+    state.drawn = Some(now);
+
+    let width = terminal_size::terminal_size()
+        .map(|(w, _)| w.0 as usize)
+        .unwrap_or(80);
+    let mut stderr = std::io::stderr().lock();
+    let _ = write!(
+        stderr,
+        "\r{}\x1b[K",
+        truncate(&render(state), width.saturating_sub(1))
+    );
+    let _ = stderr.flush();
+}
+
+fn render(state: &State) -> String {
+    // 加密后的内容比估算的大一点，进度不超过总量
+    let done = state.done.min(state.total);
+    let filled = if state.total == 0 {
+        BAR_WIDTH
+    } else {
+        (done as f64 / state.total as f64 * BAR_WIDTH as f64) as usize
+    };
+    let elapsed = state.started.elapsed().as_secs_f64();
+    let speed = if elapsed > 0.0 {
+        state.done as f64 / elapsed
+    } else {
+        0.0
+    };
+
+    let mut line = format!(
+        "{} [{}{}] {}/{} {}/s",
+        state.label,
+        "#".repeat(filled),
+        "-".repeat(BAR_WIDTH - filled),
+        format_size(done),
+        format_size(state.total),
+        format_size(speed as u64)
+    );
+    if speed > 0.0 && done < state.total {
+        let eta = ((state.total - done) as f64 / speed) as u64;
+        line.push_str(&format!(" ETA {}:{:02}", eta / 60, eta % 60));
+    }
+    if let Some((done, total)) = state.files {
+        line.push_str(&format!(" ({}/{} files)", done, total));
+    }
+    line
+}
+
+// 超出终端宽度会折行，之后的 \r 只能回到最后一行的开头
+fn truncate(line: &str, width: usize) -> &str {
+    let mut used = 0;
+    for (i, c) in line.char_indices() {
+        used += c.width().unwrap_or(0);
+        if used > width {
+            return &line[..i];
+        }
+    }
+    line
+}
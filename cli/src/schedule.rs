@@ -0,0 +1,36 @@
+//! 定时同步使用的 cron 表达式
+//!
+//! 标准五段 "分 时 日 月 周"，如 `*/15 * * * *`；也接受带秒的六段写法。
+//! 按本地时区计算，与系统 crontab 的习惯一致
+
+use chrono::{DateTime, Local};
+use croner::Cron;
+
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    expression: String,
+    cron: Cron,
+}
+
+impl Schedule {
+    /// 严格晚于 after 的下一次触发时间
+    pub fn next_after(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        self.cron.find_next_occurrence(&after, false).ok()
+    }
+}
+
+impl std::fmt::Display for Schedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+/// 解析 cron 表达式，供 clap 和配置文件共用
+pub fn parse_schedule(s: &str) -> Result<Schedule, String> {
+    let expression = s.trim().to_string();
+    let cron = Cron::new(&expression)
+        .with_seconds_optional()
+        .parse()
+        .map_err(|e| format!("Invalid schedule '{}': {}", expression, e))?;
+    Ok(Schedule { expression, cron })
+}
@@ -1,100 +1,527 @@
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
-use sha2::{Digest, Sha256};
+use std::sync::Arc;
 use anyhow::Result;
+use futures_util::stream::{self, StreamExt};
 
-use crate::client::Client;
+use rustcloud_client::atomic::{is_temp_file, temp_sibling};
+use rustcloud_client::sync::{self, LocalFile, LocalTree, PendingSync};
+use rustcloud_client::{
+    feature, is_connection_error, server_error_kind, sha256_hex, Client, HashEntry,
+    ServerErrorKind, SyncPlanItem,
+};
+use rustcloud_types::ignore::{IgnoreRules, IGNORE_FILE};
+use rustcloud_types::path::{self as logical_path, case_key};
+
+use crate::output::progress;
+use crate::progress_bar::{self, ProgressBar, Transfer};
+use crate::vault::{self, Vault};
+use crate::xattrs::{self, IgnoredAttributes};
+
+/// Windows 未启用长路径时的路径长度上限
+const MAX_PATH: usize = 260;
+
+/// 默认同时执行的条目数
+pub const DEFAULT_JOBS: usize = 4;
 
 pub struct SyncEngine {
     client: Client,
     local_path: PathBuf,
-}
-
-#[derive(Debug, Clone)]
-pub struct LocalFile {
-    pub path: String,
-    pub hash: String,
-    pub size: u64,
+    /// 同时同步多个服务器时，逐项进度后面标出目标服务器
+    label: Option<String>,
+    /// 端到端加密时，服务端上的路径和内容都是密文
+    vault: Option<Arc<Vault>>,
+    /// 同时执行的上传、下载条目数
+    jobs: usize,
+    /// 配置中的忽略模式，同步目录中 .rcloudignore 的规则排在它们之后
+    ignore: Vec<String>,
 }
 
 impl SyncEngine {
     pub fn new(client: Client, local_path: PathBuf) -> Self {
-        SyncEngine { client, local_path }
+        // 规范化过的根目录可能带 "\\?\" 前缀，去掉后相对路径才能正确计算
+        let local_path = PathBuf::from(
+            logical_path::strip_long_path_prefix(&local_path.to_string_lossy()).into_owned(),
+        );
+        SyncEngine {
+            client,
+            local_path,
+            label: None,
+            vault: None,
+            jobs: 1,
+            ignore: Vec::new(),
+        }
     }
 
-    pub async fn sync(&self, dry_run: bool) -> Result<SyncReport> {
-        println!("Scanning local files...");
-        let _local_files = self.scan_local_files()?;
-        
-        println!("Fetching remote versions...");
-        let remote_files = self.client.list_versions().await?;
-        
-        println!("Creating sync plan...");
-        let plan = self.client.create_sync_plan(&remote_files).await?;
-        
+    pub fn labeled(mut self, label: &str) -> Self {
+        self.label = Some(label.to_string());
+        self
+    }
+
+    pub fn encrypted(mut self, vault: Arc<Vault>) -> Self {
+        self.vault = Some(vault);
+        self
+    }
+
+    pub fn with_jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    pub fn with_ignore(mut self, patterns: &[String]) -> Self {
+        self.ignore = patterns.to_vec();
+        self
+    }
+
+    fn target(&self) -> String {
+        self.label
+            .as_deref()
+            .map(|label| format!(" -> {}", label))
+            .unwrap_or_default()
+    }
+
+    /// 逻辑路径对应的本地文件；Windows 上超长路径加长路径前缀
+    fn local_file(&self, remote: &str) -> PathBuf {
+        let path = self.local_path.join(remote);
+        if cfg!(windows) && path.is_absolute() && path.as_os_str().len() >= MAX_PATH {
+            PathBuf::from(logical_path::with_long_path_prefix(&path.to_string_lossy()))
+        } else {
+            path
+        }
+    }
+
+    /// 服务端路径对应的本地逻辑路径
+    fn local_name(&self, remote: &str) -> Result<String> {
+        match &self.vault {
+            Some(vault) => vault.decrypt_path(remote),
+            None => Ok(remote.to_string()),
+        }
+    }
+
+    /// 扫描本地后交给客户端库生成计划
+    pub async fn plan(&self) -> Result<PendingSync> {
+        let local = self.scan()?;
+        progress!("Creating sync plan...");
+        let pending = sync::plan(&self.client, local).await?;
+        let rules = self.ignore_rules()?;
+        Ok(self.without_ignored(self.without_manifest(pending), &rules))
+    }
+
+    // 远程有、但本地忽略的路径也不下载，否则会覆盖本地的构建产物或缓存
+    fn without_ignored(&self, mut pending: PendingSync, rules: &IgnoreRules) -> PendingSync {
+        let PendingSync {
+            items,
+            estimate,
+            remote,
+        } = &mut pending;
+        items.retain(|item| {
+            let ignored = self
+                .local_name(&item.path)
+                .is_ok_and(|name| rules.is_ignored(&name, item.is_dir));
+            if ignored && item.action == "download" && !item.is_dir {
+                estimate.download_files = estimate.download_files.saturating_sub(1);
+                let size = remote.get(&item.path).map_or(0, |record| record.size);
+                estimate.download_bytes = estimate.download_bytes.saturating_sub(size);
+            }
+            !ignored
+        });
+        pending
+    }
+
+    /// 每次扫描都重新读取 .rcloudignore，守护进程模式下修改后下一轮即生效
+    fn ignore_rules(&self) -> Result<IgnoreRules> {
+        let mut rules = IgnoreRules::new();
+        rules.extend(&self.ignore);
+        match std::fs::read_to_string(self.local_path.join(IGNORE_FILE)) {
+            Ok(content) => rules.extend(content.lines()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(rules)
+    }
+
+    // 加密清单只在服务端，不下载到同步目录
+    fn without_manifest(&self, mut pending: PendingSync) -> PendingSync {
+        if self.vault.is_some() {
+            pending.items.retain(|item| item.path != vault::MANIFEST_PATH);
+        }
+        pending
+    }
+
+    pub fn scan(&self) -> Result<LocalTree> {
+        progress!("Scanning local files...");
+        self.scan_local_files()
+    }
+
+    /// 镜像服务器只接收上传：远程多出的文件和目录不下载，也不删除本地文件
+    pub async fn plan_push(&self, local: LocalTree) -> Result<PendingSync> {
+        let mut pending = self.without_manifest(sync::plan(&self.client, local).await?);
+        pending
+            .items
+            .retain(|item| matches!(item.action.as_str(), "upload" | "skip"));
+        pending.estimate.download_files = 0;
+        pending.estimate.download_bytes = 0;
+        Ok(pending)
+    }
+
+    pub async fn execute(&self, pending: PendingSync, dry_run: bool) -> Result<SyncReport> {
         let mut report = SyncReport::default();
-        
-        for item in plan {
-            match item.action.as_str() {
-                "upload" => {
-                    println!("[UPLOAD] {}", item.path);
-                    if !dry_run {
-                        let local_path = self.local_path.join(&item.path);
-                        if local_path.exists() {
-                            let content = tokio::fs::read(&local_path).await?;
-                            self.client.upload_file(&item.path, &content).await?;
-                            report.uploaded += 1;
+        let mut ignored = IgnoredAttributes::default();
+        // 加密时不同步扩展属性：属性值会以明文写进服务端元数据
+        let push_attributes = self.vault.is_none() && self.client.supports(feature::METADATA).await;
+        if self.vault.is_none() && !push_attributes {
+            eprintln!(
+                "Warning: the server does not support metadata, extended attributes are not synced"
+            );
+        }
+
+        // 计划中的条目路径互不相同，可以并行执行；逐项进度标出这是第几个传输
+        let transfers = pending
+            .items
+            .iter()
+            .filter(|item| item.action != "skip")
+            .count();
+        let mut started = 0;
+        let bar = if dry_run {
+            ProgressBar::default()
+        } else {
+            let estimate = &pending.estimate;
+            ProgressBar::new(&format!("Syncing{}", self.target()), estimate.total_bytes())
+                .with_files(estimate.upload_files + estimate.download_files)
+        };
+        let pending = &pending;
+        // 先收集成 Vec 再建流：流的类型里带着借用参数的闭包时，
+        // 镜像同步把 execute 放进 tokio::spawn 会因生命周期推断失败而无法编译
+        let items: Vec<_> = pending
+            .items
+            .iter()
+            .map(|item| {
+                let step = Step {
+                    label: match item.action.as_str() {
+                        "skip" => String::new(),
+                        _ => {
+                            started += 1;
+                            format!(" ({}/{})", started, transfers)
                         }
-                    } else {
+                    },
+                    bar: bar.clone(),
+                };
+                async move {
+                    let mut tally = ItemTally::default();
+                    let result = self
+                        .execute_item(item, pending, dry_run, push_attributes, &step, &mut tally)
+                        .await;
+                    (item, tally, result)
+                }
+            })
+            .collect();
+        let mut outcomes = stream::iter(items).buffer_unordered(self.jobs);
+
+        while let Some((item, tally, result)) = outcomes.next().await {
+            // 出错的条目也可能已经完成了一部分（例如冲突时已上传），计数照样并入
+            report.merge(tally.report);
+            ignored.merge(tally.ignored);
+            match result {
+                Ok(()) => {}
+                // 服务端不可达时后面的条目也会失败，直接中止，正在执行的条目随之取消
+                Err(e) if is_connection_error(&e) => return Err(e),
+                // 空间不足或登录失效时其余上传同样会失败
+                Err(e)
+                    if matches!(
+                        server_error_kind(&e),
+                        Some(ServerErrorKind::QuotaExceeded | ServerErrorKind::Unauthorized)
+                    ) =>
+                {
+                    return Err(e)
+                }
+                // 单个条目失败不影响其余条目，结束后按失败数决定退出码
+                Err(e) => {
+                    let path = self
+                        .local_name(&item.path)
+                        .unwrap_or_else(|_| item.path.clone());
+                    progress_bar::suspend(|| {
+                        eprintln!("[FAILED] {}{}: {:#}", path, self.target(), e)
+                    });
+                    report.failed += 1;
+                    report.errors.push(SyncFailure {
+                        path,
+                        error: format!("{:#}", e),
+                    });
+                }
+            }
+        }
+        drop(bar);
+        ignored.warn();
+        
+        Ok(report)
+    }
+
+    async fn execute_item(
+        &self,
+        item: &SyncPlanItem,
+        pending: &PendingSync,
+        dry_run: bool,
+        push_attributes: bool,
+        step: &Step,
+        tally: &mut ItemTally,
+    ) -> Result<()> {
+        let ItemTally { report, ignored } = tally;
+        let name = self.local_name(&item.path)?;
+        if item.is_dir {
+            return self
+                .execute_directory(item, &name, dry_run, step, report)
+                .await;
+        }
+        match item.action.as_str() {
+            "upload" => {
+                progress!("[UPLOAD] {}{}{}", name, self.target(), step.label);
+                if !dry_run {
+                    let local_path = self.local_file(&name);
+                    if local_path.exists() {
+                        let transfer = step.bar.transfer();
+                        let info = match &self.vault {
+                            // 改动处之后的密文块都会变，增量上传没有意义；大文件逐块加密、分块上传
+                            Some(vault) => {
+                                vault::upload(
+                                    &self.client,
+                                    vault,
+                                    &local_path,
+                                    &item.path,
+                                    None,
+                                    &transfer,
+                                )
+                                .await?
+                            }
+                            // 远程已有这个文件时只上传改动的部分
+                            // 增量上传只发送改动的部分，完成后按整个文件计入进度
+                            None if pending.remote.contains_key(&item.path) => {
+                                let content = tokio::fs::read(&local_path).await?;
+                                let info = self.client.upload_delta(&item.path, &content).await?;
+                                transfer.update(content.len() as u64);
+                                info
+                            }
+                            None => {
+                                let content = tokio::fs::read(&local_path).await?;
+                                self.client
+                                    .upload_file_with_progress(
+                                        &item.path,
+                                        &content,
+                                        None,
+                                        Arc::new(move |sent| transfer.update(sent)),
+                                    )
+                                    .await?
+                            }
+                        };
                         report.uploaded += 1;
+                        step.bar.file_done();
+                        // 远程在上次同步后也改过，服务端把本地内容另存为冲突副本：
+                        // 本地文件改成同样的名字，再取回远程的版本
+                        let local_path = if info.path != item.path {
+                            let copy_name = self.local_name(&info.path)?;
+                            progress_bar::suspend(|| {
+                                eprintln!(
+                                    "[CONFLICT] {}{} changed on the server too; your version was saved as {}",
+                                    name,
+                                    self.target(),
+                                    copy_name
+                                )
+                            });
+                            let copy = self.local_file(&copy_name);
+                            tokio::fs::rename(&local_path, &copy).await?;
+                            self.settle_encrypted_copy(&info.path, &copy_name, &copy)
+                                .await?;
+                            // 取回远程版本不在传输估算里，不计入进度
+                            self.download(
+                                &item.path,
+                                &local_path,
+                                &ProgressBar::default().transfer(),
+                            )
+                            .await?;
+                            report.conflicts += 1;
+                            copy
+                        } else {
+                            local_path
+                        };
+                        let remote = pending.remote.get(&info.path);
+                        if push_attributes
+                            && self
+                                .push_attributes(&info.path, &local_path, remote, ignored)
+                                .await?
+                        {
+                            report.attributes += 1;
+                        }
                     }
+                } else {
+                    report.uploaded += 1;
                 }
-                "download" => {
-                    println!("[DOWNLOAD] {}", item.path);
-                    if !dry_run {
-                        let content = self.client.download_file(&item.path).await?;
-                        let local_path = self.local_path.join(&item.path);
-                        if let Some(parent) = local_path.parent() {
-                            tokio::fs::create_dir_all(parent).await?;
+            }
+            "download" => {
+                progress!("[DOWNLOAD] {}{}{}", name, self.target(), step.label);
+                if !dry_run {
+                    let local_path = self.local_file(&name);
+                    if let Some(parent) = local_path.parent() {
+                        tokio::fs::create_dir_all(parent).await?;
+                    }
+                    self.download(&item.path, &local_path, &step.bar.transfer())
+                        .await?;
+                    report.downloaded += 1;
+                    step.bar.file_done();
+                    if let Some(remote) = pending.remote.get(&item.path) {
+                        if self.vault.is_none()
+                            && xattrs::restore(&local_path, &remote.metadata) > 0
+                        {
+                            report.attributes += 1;
                         }
-                        tokio::fs::write(&local_path, content).await?;
-                        report.downloaded += 1;
-                    } else {
-                        report.downloaded += 1;
                     }
+                } else {
+                    report.downloaded += 1;
                 }
-                "delete" => {
-                    println!("[DELETE] {}", item.path);
-                    if !dry_run {
-                        let local_path = self.local_path.join(&item.path);
-                        if local_path.exists() {
-                            if local_path.is_dir() {
-                                tokio::fs::remove_dir_all(&local_path).await?;
-                            } else {
-                                tokio::fs::remove_file(&local_path).await?;
-                            }
+            }
+            "delete" => {
+                progress!("[DELETE] {}{}{}", name, self.target(), step.label);
+                if !dry_run {
+                    let local_path = self.local_file(&name);
+                    if local_path.exists() {
+                        if local_path.is_dir() {
+                            tokio::fs::remove_dir_all(&local_path).await?;
+                        } else {
+                            tokio::fs::remove_file(&local_path).await?;
                         }
-                        report.deleted += 1;
-                    } else {
-                        report.deleted += 1;
                     }
+                    report.deleted += 1;
+                } else {
+                    report.deleted += 1;
                 }
-                "skip" => {
-                    report.skipped += 1;
+            }
+            "skip" => {
+                report.skipped += 1;
+                // 内容没变，属性（例如 Finder 标签）仍可能改过
+                let local_path = self.local_file(&name);
+                if push_attributes && !dry_run && local_path.is_file() {
+                    let remote = pending.remote.get(&item.path);
+                    if self
+                        .push_attributes(&item.path, &local_path, remote, ignored)
+                        .await?
+                    {
+                        report.attributes += 1;
+                    }
                 }
-                _ => {}
             }
+            _ => {}
         }
-        
-        Ok(report)
+        Ok(())
     }
 
-    fn scan_local_files(&self) -> Result<Vec<LocalFile>> {
-        let mut files = Vec::new();
-        self.scan_dir(&self.local_path, &mut files)?;
-        Ok(files)
+    /// 空目录在缺少的一侧创建：upload 在服务端，download 在本地
+    async fn execute_directory(
+        &self,
+        item: &SyncPlanItem,
+        name: &str,
+        dry_run: bool,
+        step: &Step,
+        report: &mut SyncReport,
+    ) -> Result<()> {
+        progress!("[MKDIR] {}/{}{}", name, self.target(), step.label);
+        if !dry_run {
+            match item.action.as_str() {
+                "upload" => {
+                    self.client.create_folder(&item.path).await?;
+                }
+                "download" => tokio::fs::create_dir_all(self.local_file(name)).await?,
+                _ => return Ok(()),
+            }
+        }
+        report.directories += 1;
+        Ok(())
+    }
+
+    /// 下载到本地；加密时先下载密文，解密后再原子地写入
+    async fn download(&self, remote: &str, local_path: &Path, transfer: &Transfer) -> Result<()> {
+        let on_progress = |received| transfer.update(received);
+        let Some(vault) = &self.vault else {
+            self.client
+                .download_to(remote, local_path, on_progress)
+                .await?;
+            return Ok(());
+        };
+        let sealed = temp_sibling(local_path);
+        let downloaded = async {
+            self.client
+                .download_to(remote, &sealed, on_progress)
+                .await?;
+            Ok::<_, anyhow::Error>(tokio::fs::read(&sealed).await?)
+        }
+        .await;
+        let _ = tokio::fs::remove_file(&sealed).await;
+        let plain = vault.decrypt(&downloaded?)?;
+
+        let tmp = temp_sibling(local_path);
+        tokio::fs::write(&tmp, &plain).await?;
+        if let Err(e) = tokio::fs::rename(&tmp, local_path).await {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            return Err(e.into());
+        }
+        Ok(())
     }
 
-    fn scan_dir(&self, dir: &Path, files: &mut Vec<LocalFile>) -> Result<()> {
+    // 文件名加密时，服务端的冲突副本名是"密文 + 说明"，与本地副本名加密后的结果不同，
+    // 下次同步会被当成两个文件。按本地副本名重新上传，再删掉服务端起名的那份
+    async fn settle_encrypted_copy(&self, remote: &str, name: &str, copy: &Path) -> Result<()> {
+        let Some(vault) = self.vault.as_ref().filter(|vault| vault.encrypts_names()) else {
+            return Ok(());
+        };
+        let transfer = ProgressBar::default().transfer();
+        let path = vault.encrypt_path(name)?;
+        vault::upload(&self.client, vault, copy, &path, None, &transfer).await?;
+        self.client.delete_file(remote).await?;
+        Ok(())
+    }
+
+    /// 把可保留的扩展属性同步到服务端元数据，没有变化时不发请求
+    async fn push_attributes(
+        &self,
+        path: &str,
+        local_path: &Path,
+        remote: Option<&HashEntry>,
+        ignored: &mut IgnoredAttributes,
+    ) -> Result<bool> {
+        let attributes = xattrs::read(local_path);
+        ignored.record(&attributes);
+
+        let empty = BTreeMap::new();
+        let remote = remote.map_or(&empty, |record| &record.metadata);
+        let patch = xattrs::metadata_patch(&attributes.preserved, remote);
+        if patch.is_empty() {
+            return Ok(false);
+        }
+        self.client.update_metadata(path, &patch).await?;
+        Ok(true)
+    }
+
+    fn scan_local_files(&self) -> Result<LocalTree> {
+        let rules = self.ignore_rules()?;
+        let mut tree = LocalTree::default();
+        self.scan_dir(&self.local_path, &rules, &mut tree)?;
+        let LocalTree { files, empty_dirs } = &mut tree;
+
+        // 只差大小写的两个文件在 Windows 上无法共存，只同步先扫描到的那个
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut seen: HashMap<String, String> = HashMap::new();
+        files.retain(|file| match seen.get(&case_key(&file.path)) {
+            Some(kept) => {
+                eprintln!("Skipping {}: differs only in case from {}", file.path, kept);
+                false
+            }
+            None => {
+                seen.insert(case_key(&file.path), file.path.clone());
+                true
+            }
+        });
+        empty_dirs.sort();
+        Ok(tree)
+    }
+
+    fn scan_dir(&self, dir: &Path, rules: &IgnoreRules, tree: &mut LocalTree) -> Result<()> {
         if !dir.exists() {
             return Ok(());
         }
@@ -102,19 +529,54 @@ impl SyncEngine {
         for entry in std::fs::read_dir(dir)? {
             let entry = entry?;
             let path = entry.path();
+            let relative = path
+                .strip_prefix(&self.local_path)?
+                .to_string_lossy()
+                .replace('\\', "/");
+            // 被忽略的目录不再进入
+            if rules.is_ignored(&relative, path.is_dir()) {
+                continue;
+            }
             
             if path.is_dir() {
-                self.scan_dir(&path, files)?;
+                let before = (tree.files.len(), tree.empty_dirs.len());
+                self.scan_dir(&path, rules, tree)?;
+                // 没有扫描到任何内容的目录单独记下，否则同步后就丢了
+                if (tree.files.len(), tree.empty_dirs.len()) == before {
+                    match logical_path::normalize(&relative) {
+                        Ok(relative) => tree.empty_dirs.push(match &self.vault {
+                            Some(vault) => vault.encrypt_path(&relative)?,
+                            None => relative,
+                        }),
+                        Err(e) => eprintln!("Skipping {}: {}", path.display(), e),
+                    }
+                }
+            } else if is_temp_file(&path) {
+                // 中断的下载留下的临时文件，不参与同步
+                continue;
             } else {
-                let relative = path.strip_prefix(&self.local_path)?
-                    .to_string_lossy()
-                    .replace('\\', "/");
-                
-                let content = std::fs::read(&path)?;
-                let hash = format!("{:x}", Sha256::digest(&content));
-                let size = content.len() as u64;
+                let relative = match logical_path::normalize(&relative) {
+                    Ok(relative) => relative,
+                    Err(e) => {
+                        // 其他平台上无法落盘的名字，跳过而不是让整个同步失败
+                        eprintln!("Skipping {}: {}", path.display(), e);
+                        continue;
+                    }
+                };
+
+                // 加密是确定性的：内容没变时密文和哈希也不变，服务端照常比较
+                let (relative, hash, size) = match &self.vault {
+                    Some(vault) => {
+                        let (hash, size) = vault.encrypted_sha256(&path)?;
+                        (vault.encrypt_path(&relative)?, hash, size)
+                    }
+                    None => {
+                        let content = std::fs::read(&path)?;
+                        (relative, sha256_hex(&content), content.len() as u64)
+                    }
+                };
                 
-                files.push(LocalFile {
+                tree.files.push(LocalFile {
                     path: relative,
                     hash,
                     size,
@@ -126,7 +588,7 @@ impl SyncEngine {
     }
 
     pub async fn status(&self) -> Result<SyncStatus> {
-        let local_files = self.scan_local_files()?;
+        let local_files = self.scan_local_files()?.files;
         let remote_files = self.client.list_files(None).await?;
         
         let local_count = local_files.len();
@@ -140,12 +602,55 @@ impl SyncEngine {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default, serde::Serialize)]
 pub struct SyncReport {
     pub uploaded: usize,
     pub downloaded: usize,
     pub deleted: usize,
     pub skipped: usize,
+    /// 扩展属性有更新的文件数
+    pub attributes: usize,
+    /// 与远程修改冲突、另存为副本的文件数
+    pub conflicts: usize,
+    /// 在服务端或本地创建的空目录数
+    pub directories: usize,
+    /// 执行出错的条目数
+    pub failed: usize,
+    /// 出错条目的路径和错误信息，按完成顺序
+    pub errors: Vec<SyncFailure>,
+}
+
+impl SyncReport {
+    fn merge(&mut self, other: SyncReport) {
+        self.uploaded += other.uploaded;
+        self.downloaded += other.downloaded;
+        self.deleted += other.deleted;
+        self.skipped += other.skipped;
+        self.attributes += other.attributes;
+        self.conflicts += other.conflicts;
+        self.directories += other.directories;
+        self.failed += other.failed;
+        self.errors.extend(other.errors);
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SyncFailure {
+    pub path: String,
+    pub error: String,
+}
+
+/// 条目在逐项进度中的序号，以及整次同步共用的进度条
+struct Step {
+    label: String,
+    bar: ProgressBar,
+}
+
+/// 单个条目执行期间的计数，执行完再并入总报告
+#[derive(Default)]
+struct ItemTally {
+    report: SyncReport,
+    ignored: IgnoredAttributes,
 }
 
 pub struct SyncStatus {
@@ -0,0 +1,179 @@
+//! 尚未完成的分块上传
+//!
+//! 记录在配置目录下的 `uploads.toml`，以服务端和远端路径为键，保存会话 ID 和本地文件哈希。
+//! 再次上传内容相同的文件时据此找回会话，只补传服务端缺少的分块；内容变了则重新开始。
+
+use std::collections::BTreeMap;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use rustcloud_client::{file_sha256, ChecksumMismatch, Client, FileInfo};
+
+use crate::output::progress;
+use crate::progress_bar::Transfer;
+use crate::vault::{EncryptedFile, Vault};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PendingUpload {
+    id: String,
+    hash: String,
+}
+
+fn key(server: &str, remote: &str) -> String {
+    format!("{}/{}", server.trim_end_matches('/'), remote)
+}
+
+/// 同一目标、同一内容上次未完成的会话 ID
+pub fn find(server: &str, remote: &str, hash: &str) -> Option<String> {
+    let pending = read_file().ok()?;
+    pending
+        .get(&key(server, remote))
+        .filter(|upload| upload.hash == hash)
+        .map(|upload| upload.id.clone())
+}
+
+pub fn remember(server: &str, remote: &str, hash: &str, id: &str) -> Result<()> {
+    let mut pending = read_file()?;
+    pending.insert(
+        key(server, remote),
+        PendingUpload {
+            id: id.to_string(),
+            hash: hash.to_string(),
+        },
+    );
+    write_file(&pending)
+}
+
+pub fn forget(server: &str, remote: &str) -> Result<()> {
+    let mut pending = read_file()?;
+    if pending.remove(&key(server, remote)).is_some() {
+        write_file(&pending)?;
+    }
+    Ok(())
+}
+
+/// 分块上传的内容
+pub enum Content<'a> {
+    /// 本地文件原样上传
+    Plain(&'a Path),
+    /// 逐块加密后上传，只在读到某一段时才加密这一段
+    Encrypted(&'a Path, &'a Vault),
+}
+
+enum Reader<'a> {
+    Plain(tokio::fs::File),
+    Encrypted(EncryptedFile<'a>),
+}
+
+impl Reader<'_> {
+    async fn read_at(&mut self, offset: u64, len: u64) -> Result<Vec<u8>> {
+        match self {
+            Reader::Plain(file) => {
+                let mut chunk = vec![0u8; len as usize];
+                file.seek(SeekFrom::Start(offset)).await?;
+                file.read_exact(&mut chunk).await?;
+                Ok(chunk)
+            }
+            Reader::Encrypted(file) => file.read_at(offset, len).await,
+        }
+    }
+}
+
+/// 按服务端的分块大小上传，同一内容上次未完成的会话只补传缺少的分块
+pub async fn upload(
+    client: &Client,
+    content: Content<'_>,
+    remote: &str,
+    on_conflict: Option<&str>,
+    transfer: &Transfer,
+) -> Result<FileInfo> {
+    let (mut reader, size, hash) = match content {
+        Content::Plain(path) => {
+            let size = tokio::fs::metadata(path).await?.len();
+            let hash = file_sha256(path).await?;
+            (
+                Reader::Plain(tokio::fs::File::open(path).await?),
+                size,
+                hash,
+            )
+        }
+        Content::Encrypted(path, vault) => {
+            let mut file = EncryptedFile::open(vault, path).await?;
+            let hash = file.sha256().await?;
+            let size = file.size();
+            (Reader::Encrypted(file), size, hash)
+        }
+    };
+    let server = client.base_url();
+
+    let resumed = match find(server, remote, &hash) {
+        Some(id) => client
+            .get_upload(&id)
+            .await?
+            .filter(|status| status.size == size),
+        None => None,
+    };
+    let status = match resumed {
+        Some(status) => {
+            progress!(
+                "Resuming upload: {} of {} chunks already on server",
+                status.received.len(),
+                status.chunk_count
+            );
+            status
+        }
+        None => {
+            let status = client.create_upload(remote, size, on_conflict).await?;
+            remember(server, remote, &hash, &status.id)?;
+            status
+        }
+    };
+
+    let mut sent = 0;
+    for n in 0..status.chunk_count {
+        let offset = n * status.chunk_size;
+        let len = (size - offset).min(status.chunk_size);
+        if !status.received.contains(&n) {
+            let chunk = reader.read_at(offset, len).await?;
+            client.upload_chunk(&status.id, n, chunk).await?;
+            progress!("  Chunk {}/{}", n + 1, status.chunk_count);
+        }
+        sent += len;
+        transfer.update(sent);
+    }
+
+    let result = client
+        .complete_upload(&status.id, remote, &hash, on_conflict)
+        .await;
+    // 成功，或文件在上传过程中被修改导致整体哈希不符时，都不再续传这个会话
+    let stale = matches!(&result, Err(e) if e.downcast_ref::<ChecksumMismatch>().is_some());
+    if result.is_ok() || stale {
+        forget(server, remote)?;
+    }
+    result
+}
+
+fn file_path() -> Result<PathBuf> {
+    Ok(crate::config::config_path()?.with_file_name("uploads.toml"))
+}
+
+fn read_file() -> Result<BTreeMap<String, PendingUpload>> {
+    let path = file_path()?;
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+}
+
+fn write_file(pending: &BTreeMap<String, PendingUpload>) -> Result<()> {
+    let path = file_path()?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, toml::to_string_pretty(pending)?)?;
+    Ok(())
+}
@@ -0,0 +1,533 @@
+//! 端到端加密：文件内容（可选连同文件名）在离开本机前用口令派生的密钥加密，
+//! 服务端只保存密文，并在元数据中把文件标记为已加密。
+//!
+//! 口令经 Argon2id 派生主密钥，盐和口令校验值写在服务端的加密清单 `.rcloud-e2e.json` 中：
+//! 其他设备取回清单、输入同一口令即可派生出相同的密钥。清单本身不含任何密钥。
+//!
+//! 同步按哈希决定传输哪些文件，所以加密必须是确定性的：同样的明文总是得到同样的密文。
+//! nonce 取 HMAC(密钥, 明文) 的前 12 字节（SIV 构造），内容不同 nonce 就不同；
+//! 代价是服务端能看出两个文件内容相同，但看不到内容本身。
+//!
+//! 内容按 1 MiB 分块加密，密文为 `RCE2 | 块 0 | 块 1 | ...`，每块是 `nonce | ciphertext`，
+//! nonce 由块序号和块内容算出，块序号和是否最后一块写进附加数据，块被调换或截断都会解密失败。
+//! 任意偏移的密文都能单独算出，大文件可以边读边加密、按服务端分块上传；
+//! 早期整个文件一次加密的 `RCE1 | nonce | ciphertext` 仍能解密。
+//! 文件名逐段加密，用小写 base32 编码，服务端按大小写不敏感比较路径时也不会冲突。
+
+use std::collections::BTreeMap;
+use std::io::{Read, SeekFrom};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use rustcloud_client::atomic::temp_sibling;
+use rustcloud_client::{feature, is_connection_error, Client, FileInfo};
+
+use crate::progress_bar::Transfer;
+use crate::uploads;
+
+/// 服务端上的加密清单，同步时不参与规划
+pub const MANIFEST_PATH: &str = ".rcloud-e2e.json";
+pub const PASSPHRASE_ENV: &str = "RCLOUD_E2E_PASSPHRASE";
+/// 服务端元数据中标记加密文件的键和值
+pub const METADATA_KEY: &str = "encryption";
+pub const METADATA_VALUE: &str = "rcloud-e2e-v1";
+
+const MANIFEST_VERSION: u32 = 1;
+const MAGIC: &[u8; 4] = b"RCE1";
+const CHUNKED_MAGIC: &[u8; 4] = b"RCE2";
+/// 每块明文的长度，最后一块可以更短
+const BLOCK_LEN: u64 = 1024 * 1024;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+/// 每块密文比明文多出的字节数
+const BLOCK_OVERHEAD: u64 = (NONCE_LEN + TAG_LEN) as u64;
+const SALT_LEN: usize = 16;
+/// 加密后写入清单，能解开说明口令正确
+const CHECK_PLAINTEXT: &[u8] = b"rustcloud end-to-end encryption";
+/// 多数文件系统的单个文件名上限（字节）
+const MAX_NAME_LEN: usize = 255;
+const BASE32: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    /// base64 编码的 Argon2id 盐
+    pub salt: String,
+    /// 用内容密钥加密的固定文本，base64 编码
+    pub check: String,
+    /// 文件名是否也加密
+    pub encrypt_names: bool,
+}
+
+/// 一种用途的密钥：加密密钥和生成 nonce 用的 HMAC 密钥
+struct Cipher {
+    aead: ChaCha20Poly1305,
+    siv: [u8; 32],
+}
+
+impl Cipher {
+    fn derive(master: &[u8], purpose: &str) -> Result<Self> {
+        let hkdf = hkdf::Hkdf::<Sha256>::new(None, master);
+        let mut key = Key::default();
+        let mut siv = [0u8; 32];
+        hkdf.expand(
+            format!("rustcloud e2e {} key", purpose).as_bytes(),
+            &mut key,
+        )
+        .and_then(|_| {
+            hkdf.expand(
+                format!("rustcloud e2e {} nonce", purpose).as_bytes(),
+                &mut siv,
+            )
+        })
+        .map_err(|e| anyhow!("Key derivation failed: {}", e))?;
+        Ok(Cipher {
+            aead: ChaCha20Poly1305::new(&key),
+            siv,
+        })
+    }
+
+    fn seal(&self, plain: &[u8]) -> Vec<u8> {
+        self.seal_with(plain, MAGIC, &[])
+    }
+
+    fn open(&self, sealed: &[u8]) -> Option<Vec<u8>> {
+        self.open_with(sealed, MAGIC)
+    }
+
+    /// nonce 取 HMAC(prefix | 明文)，同一位置、同样的明文总是得到同样的密文
+    fn seal_with(&self, plain: &[u8], aad: &[u8], prefix: &[u8]) -> Vec<u8> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.siv).expect("any key length");
+        mac.update(prefix);
+        mac.update(plain);
+        let tag = mac.finalize().into_bytes();
+        let nonce = Nonce::from_slice(&tag[..NONCE_LEN]);
+        let ciphertext = self
+            .aead
+            .encrypt(nonce, Payload { msg: plain, aad })
+            .expect("in-memory encryption cannot fail");
+        let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        sealed.extend_from_slice(nonce);
+        sealed.extend_from_slice(&ciphertext);
+        sealed
+    }
+
+    fn open_with(&self, sealed: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        self.aead
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .ok()
+    }
+}
+
+/// 明文长度对应的块数；空文件也有一个空的最后一块
+fn block_count(plain_len: u64) -> u64 {
+    plain_len.div_ceil(BLOCK_LEN).max(1)
+}
+
+/// 第 index 块的附加数据，同时参与 nonce 的计算
+fn block_aad(index: u64, last: bool) -> Vec<u8> {
+    let mut aad = CHUNKED_MAGIC.to_vec();
+    aad.extend_from_slice(&index.to_be_bytes());
+    aad.push(last as u8);
+    aad
+}
+
+/// 明文长度为 plain_len 的文件加密后的长度
+pub fn encrypted_len(plain_len: u64) -> u64 {
+    CHUNKED_MAGIC.len() as u64 + plain_len + block_count(plain_len) * BLOCK_OVERHEAD
+}
+
+/// 解锁后的密钥
+pub struct Vault {
+    manifest: Manifest,
+    content: Cipher,
+    names: Cipher,
+}
+
+impl Vault {
+    /// 用新的随机盐建立加密，清单保存到服务端后其他设备才能解锁
+    pub fn create(passphrase: &str, encrypt_names: bool) -> Result<Vault> {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let engine = base64::engine::general_purpose::STANDARD;
+        let mut manifest = Manifest {
+            version: MANIFEST_VERSION,
+            salt: engine.encode(salt),
+            check: String::new(),
+            encrypt_names,
+        };
+        let mut vault = Self::derive(&manifest, passphrase, &salt)?;
+        manifest.check = engine.encode(vault.content.seal(CHECK_PLAINTEXT));
+        vault.manifest = manifest;
+        Ok(vault)
+    }
+
+    /// 按清单派生密钥，口令错误时报错
+    pub fn unlock(manifest: &Manifest, passphrase: &str) -> Result<Vault> {
+        if manifest.version != MANIFEST_VERSION {
+            bail!(
+                "Unsupported encryption manifest version {}; upgrade rcloud",
+                manifest.version
+            );
+        }
+        let engine = base64::engine::general_purpose::STANDARD;
+        let salt = engine
+            .decode(&manifest.salt)
+            .context("Malformed encryption manifest")?;
+        let check = engine
+            .decode(&manifest.check)
+            .context("Malformed encryption manifest")?;
+        let vault = Self::derive(manifest, passphrase, &salt)?;
+        match vault.content.open(&check) {
+            Some(plain) if plain == CHECK_PLAINTEXT => Ok(vault),
+            _ => bail!("Wrong encryption passphrase"),
+        }
+    }
+
+    fn derive(manifest: &Manifest, passphrase: &str, salt: &[u8]) -> Result<Vault> {
+        let mut master = [0u8; 32];
+        argon2::Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, &mut master)
+            .map_err(|e| anyhow!("Key derivation failed: {}", e))?;
+        Ok(Vault {
+            manifest: manifest.clone(),
+            content: Cipher::derive(&master, "content")?,
+            names: Cipher::derive(&master, "names")?,
+        })
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    pub fn encrypts_names(&self) -> bool {
+        self.manifest.encrypt_names
+    }
+
+    fn seal_block(&self, index: u64, last: bool, plain: &[u8]) -> Vec<u8> {
+        let aad = block_aad(index, last);
+        self.content.seal_with(plain, &aad, &aad)
+    }
+
+    /// 逐块读取本地文件，算出加密后内容的哈希和长度，不把整个文件读进内存
+    pub fn encrypted_sha256(&self, path: &Path) -> Result<(String, u64)> {
+        let mut file = std::fs::File::open(path)?;
+        let plain_len = file.metadata()?.len();
+        let count = block_count(plain_len);
+        let mut hasher = Sha256::new();
+        hasher.update(CHUNKED_MAGIC);
+        let mut block = Vec::new();
+        for index in 0..count {
+            let len = (plain_len - index * BLOCK_LEN).min(BLOCK_LEN);
+            block.resize(len as usize, 0);
+            file.read_exact(&mut block)?;
+            hasher.update(self.seal_block(index, index + 1 == count, &block));
+        }
+        Ok((format!("{:x}", hasher.finalize()), encrypted_len(plain_len)))
+    }
+
+    pub fn decrypt(&self, sealed: &[u8]) -> Result<Vec<u8>> {
+        let damaged =
+            || anyhow!("Cannot decrypt: the file was damaged or encrypted with another passphrase");
+        if let Some(body) = sealed.strip_prefix(MAGIC.as_slice()) {
+            return self.content.open(body).ok_or_else(damaged);
+        }
+        let body = sealed
+            .strip_prefix(CHUNKED_MAGIC.as_slice())
+            .ok_or_else(|| anyhow!("The file on the server is not end-to-end encrypted"))?;
+        let blocks: Vec<&[u8]> = body.chunks((BLOCK_LEN + BLOCK_OVERHEAD) as usize).collect();
+        // 空文件也有一块，没有块说明密文被截断了
+        if blocks.is_empty() {
+            return Err(damaged());
+        }
+        let mut plain = Vec::with_capacity(body.len());
+        for (index, block) in blocks.iter().enumerate() {
+            let aad = block_aad(index as u64, index + 1 == blocks.len());
+            plain.extend(self.content.open_with(block, &aad).ok_or_else(damaged)?);
+        }
+        Ok(plain)
+    }
+
+    /// 本地的逻辑路径对应的服务端路径；不加密文件名时原样返回
+    pub fn encrypt_path(&self, path: &str) -> Result<String> {
+        if !self.encrypts_names() {
+            return Ok(path.to_string());
+        }
+        let segments = path
+            .split('/')
+            .map(|segment| {
+                let encoded = base32(&self.names.seal(segment.as_bytes()));
+                if encoded.len() > MAX_NAME_LEN {
+                    bail!("Name too long to encrypt: {}", segment);
+                }
+                Ok(encoded)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(segments.join("/"))
+    }
+
+    /// 服务端路径对应的本地逻辑路径。
+    ///
+    /// 服务端另存的冲突副本在加密名后面加了 " (conflicted copy ...)"，
+    /// 解密后把这段说明放回扩展名之前
+    pub fn decrypt_path(&self, path: &str) -> Result<String> {
+        if !self.encrypts_names() {
+            return Ok(path.to_string());
+        }
+        let segments = path
+            .split('/')
+            .map(|segment| {
+                let (encoded, label) = match segment.split_once(' ') {
+                    Some((encoded, label)) => (encoded, Some(label)),
+                    None => (segment, None),
+                };
+                let name = unbase32(encoded)
+                    .and_then(|sealed| self.names.open(&sealed))
+                    .and_then(|plain| String::from_utf8(plain).ok())
+                    .ok_or_else(|| anyhow!("{} is not an encrypted name", segment))?;
+                Ok(match (label, name.rsplit_once('.')) {
+                    (None, _) => name,
+                    (Some(label), Some((stem, ext))) if !stem.is_empty() => {
+                        format!("{} {}.{}", stem, label, ext)
+                    }
+                    (Some(label), _) => format!("{} {}", name, label),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(segments.join("/"))
+    }
+}
+
+/// 按块加密的本地文件，任意偏移的密文都能单独算出，用于分块上传
+pub struct EncryptedFile<'a> {
+    vault: &'a Vault,
+    file: tokio::fs::File,
+    plain_len: u64,
+    /// 最近加密的一块；服务端分块通常比加密块小，相邻的几次读取落在同一块里
+    cached: Option<(u64, Vec<u8>)>,
+}
+
+impl<'a> EncryptedFile<'a> {
+    pub async fn open(vault: &'a Vault, path: &Path) -> Result<Self> {
+        let file = tokio::fs::File::open(path).await?;
+        let plain_len = file.metadata().await?.len();
+        Ok(EncryptedFile {
+            vault,
+            file,
+            plain_len,
+            cached: None,
+        })
+    }
+
+    /// 加密后的长度
+    pub fn size(&self) -> u64 {
+        encrypted_len(self.plain_len)
+    }
+
+    /// 整个密文的哈希，逐块计算
+    pub async fn sha256(&mut self) -> Result<String> {
+        let mut hasher = Sha256::new();
+        hasher.update(CHUNKED_MAGIC);
+        for index in 0..block_count(self.plain_len) {
+            hasher.update(self.block(index).await?);
+        }
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    /// 密文中从 offset 开始的 len 个字节，只读取并加密覆盖这段范围的块
+    pub async fn read_at(&mut self, offset: u64, len: u64) -> Result<Vec<u8>> {
+        let header = CHUNKED_MAGIC.len() as u64;
+        let end = (offset + len).min(self.size());
+        let mut out = Vec::with_capacity(end.saturating_sub(offset) as usize);
+        let mut pos = offset;
+        if pos < header.min(end) {
+            out.extend_from_slice(&CHUNKED_MAGIC[pos as usize..header.min(end) as usize]);
+            pos = header;
+        }
+        while pos < end {
+            let index = (pos - header) / (BLOCK_LEN + BLOCK_OVERHEAD);
+            let start = header + index * (BLOCK_LEN + BLOCK_OVERHEAD);
+            let sealed = match self.cached.take() {
+                Some((cached, sealed)) if cached == index => sealed,
+                _ => self.block(index).await?,
+            };
+            let to = (end - start).min(sealed.len() as u64);
+            out.extend_from_slice(&sealed[(pos - start) as usize..to as usize]);
+            pos = start + to;
+            self.cached = Some((index, sealed));
+        }
+        Ok(out)
+    }
+
+    async fn block(&mut self, index: u64) -> Result<Vec<u8>> {
+        let offset = index * BLOCK_LEN;
+        let mut plain = vec![0u8; (self.plain_len - offset).min(BLOCK_LEN) as usize];
+        self.file.seek(SeekFrom::Start(offset)).await?;
+        self.file.read_exact(&mut plain).await?;
+        let last = index + 1 == block_count(self.plain_len);
+        Ok(self.vault.seal_block(index, last, &plain))
+    }
+}
+
+fn base32(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(5) * 8);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &byte in data {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32[(buffer >> bits) as usize & 31] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32[(buffer << (5 - bits)) as usize & 31] as char);
+    }
+    out
+}
+
+fn unbase32(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in text.bytes() {
+        let value = BASE32.iter().position(|&b| b == c.to_ascii_lowercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// 口令从环境变量读取，没有设置时在终端询问
+pub fn passphrase(confirm: bool) -> Result<String> {
+    if let Some(passphrase) = std::env::var(PASSPHRASE_ENV).ok().filter(|p| !p.is_empty()) {
+        return Ok(passphrase);
+    }
+    let passphrase = rpassword::prompt_password("Encryption passphrase: ")?;
+    if passphrase.is_empty() {
+        bail!("The encryption passphrase cannot be empty");
+    }
+    if confirm && rpassword::prompt_password("Confirm passphrase: ")? != passphrase {
+        bail!("Passphrases do not match");
+    }
+    Ok(passphrase)
+}
+
+/// 服务端上的加密清单，不存在时返回 None
+pub async fn fetch_manifest(client: &Client) -> Result<Option<Manifest>> {
+    match client.get_file_info(MANIFEST_PATH).await {
+        Ok(_) => {}
+        Err(e) if is_connection_error(&e) => return Err(e),
+        Err(_) => return Ok(None),
+    }
+    let tmp = temp_sibling(&std::env::temp_dir().join(MANIFEST_PATH));
+    let result = async {
+        client.download_to(MANIFEST_PATH, &tmp, |_| {}).await?;
+        let content = tokio::fs::read(&tmp).await?;
+        serde_json::from_slice(&content).context("Malformed encryption manifest")
+    }
+    .await;
+    let _ = tokio::fs::remove_file(&tmp).await;
+    result.map(Some)
+}
+
+/// 把清单保存到还没有清单的服务端，例如新加入的镜像；
+/// 已有的清单来自另一个口令时报错，否则两边的文件无法用同一个口令解密
+pub async fn publish_manifest(client: &Client, manifest: &Manifest) -> Result<()> {
+    match fetch_manifest(client).await? {
+        Some(existing) if existing.salt == manifest.salt => Ok(()),
+        Some(_) => bail!(
+            "{} is already encrypted with a different passphrase",
+            client.base_url()
+        ),
+        None => {
+            let content = serde_json::to_vec_pretty(manifest)?;
+            client
+                .upload_file_with(MANIFEST_PATH, &content, Some("fail"))
+                .await?;
+            Ok(())
+        }
+    }
+}
+
+/// 取回服务端的加密清单并用口令解锁
+pub async fn unlock(client: &Client) -> Result<Vault> {
+    let manifest = fetch_manifest(client).await?.ok_or_else(|| {
+        anyhow!("The server has no encryption manifest; run `rcloud encrypt init` first")
+    })?;
+    Vault::unlock(&manifest, &passphrase(false)?)
+}
+
+/// 逐块加密后上传到 remote（已加密的路径）并标记为已加密。
+/// 超过服务端分块大小时按块上传，中断后可以续传；否则整个上传
+pub async fn upload(
+    client: &Client,
+    vault: &Vault,
+    path: &Path,
+    remote: &str,
+    on_conflict: Option<&str>,
+    transfer: &Transfer,
+) -> Result<FileInfo> {
+    let size = encrypted_len(tokio::fs::metadata(path).await?.len());
+    let info = if client.supports(feature::RESUMABLE_UPLOAD).await
+        && client.chunk_policy(size).await?.is_some()
+    {
+        let content = uploads::Content::Encrypted(path, vault);
+        uploads::upload(client, content, remote, on_conflict, transfer).await?
+    } else {
+        let content = EncryptedFile::open(vault, path)
+            .await?
+            .read_at(0, size)
+            .await?;
+        let transfer = transfer.clone();
+        client
+            .upload_file_with_progress(
+                remote,
+                &content,
+                on_conflict,
+                Arc::new(move |sent| transfer.update(sent)),
+            )
+            .await?
+    };
+    mark_encrypted(client, &info.path, &info.metadata).await?;
+    Ok(info)
+}
+
+/// 在服务端元数据中把文件标记为已加密；已经标记过或服务端不支持元数据时跳过
+pub async fn mark_encrypted(
+    client: &Client,
+    path: &str,
+    metadata: &BTreeMap<String, String>,
+) -> Result<()> {
+    if metadata.get(METADATA_KEY).map(String::as_str) == Some(METADATA_VALUE)
+        || !client.supports(feature::METADATA).await
+    {
+        return Ok(());
+    }
+    let patch = BTreeMap::from([(METADATA_KEY.to_string(), Some(METADATA_VALUE.to_string()))]);
+    client.update_metadata(path, &patch).await?;
+    Ok(())
+}
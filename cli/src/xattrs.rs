@@ -0,0 +1,171 @@
+//! 扩展属性（xattr）与 Windows 备用数据流（ADS）的同步策略
+//!
+//! 内容哈希只覆盖文件数据，属性从不参与，同一内容在任何平台上都得到同一个哈希。
+//! - 保留：macOS Finder 标签和 Linux `user.*` 属性，以 base64 存入服务端元数据的
+//!   `xattr:<name>` 键，下载时写回本地文件
+//! - 忽略：隔离标记、资源分支、安全标签等只对本机有意义的属性，以及 Windows 的
+//!   Zone.Identifier 数据流；按属性名汇总后输出警告，不会悄无声息地丢失
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+/// 服务端元数据中保存属性的键前缀
+pub const METADATA_PREFIX: &str = "xattr:";
+
+/// 与服务端元数据的键、值长度上限一致
+const MAX_KEY_LEN: usize = 128;
+const MAX_VALUE_LEN: usize = 4096;
+
+const FINDER_TAGS: &str = "com.apple.metadata:_kMDItemUserTags";
+
+/// Windows 给下载文件附加的"来自网络"标记
+#[cfg(windows)]
+const ZONE_IDENTIFIER: &str = "Zone.Identifier";
+
+/// 是否随文件同步到其他设备
+pub fn is_preserved(name: &str) -> bool {
+    name == FINDER_TAGS || name.starts_with("user.")
+}
+
+/// 一个文件上读到的属性
+#[derive(Debug, Default)]
+pub struct Attributes {
+    /// 要写入服务端的元数据，键已带前缀
+    pub preserved: BTreeMap<String, String>,
+    /// 没有同步的属性名
+    pub ignored: Vec<String>,
+}
+
+impl Attributes {
+    fn add(&mut self, name: String, value: &[u8]) {
+        let key = format!("{}{}", METADATA_PREFIX, name);
+        let value = STANDARD.encode(value);
+        if is_preserved(&name) && key.len() <= MAX_KEY_LEN && value.len() <= MAX_VALUE_LEN {
+            self.preserved.insert(key, value);
+        } else {
+            self.ignored.push(name);
+        }
+    }
+}
+
+#[cfg(unix)]
+pub fn read(path: &Path) -> Attributes {
+    let mut attributes = Attributes::default();
+    let Ok(names) = xattr::list(path) else {
+        return attributes;
+    };
+    for name in names {
+        let name = name.to_string_lossy().into_owned();
+        match xattr::get(path, &name) {
+            Ok(Some(value)) => attributes.add(name, &value),
+            _ => attributes.ignored.push(name),
+        }
+    }
+    attributes
+}
+
+#[cfg(windows)]
+pub fn read(path: &Path) -> Attributes {
+    let mut attributes = Attributes::default();
+    let mut stream = path.as_os_str().to_owned();
+    stream.push(":");
+    stream.push(ZONE_IDENTIFIER);
+    if Path::new(&stream).exists() {
+        attributes.ignored.push(ZONE_IDENTIFIER.to_string());
+    }
+    attributes
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn read(_path: &Path) -> Attributes {
+    Attributes::default()
+}
+
+/// 把服务端元数据中保存的属性写回本地文件，返回写入的个数
+pub fn restore(path: &Path, metadata: &BTreeMap<String, String>) -> usize {
+    let mut restored = 0;
+    for (key, value) in metadata {
+        let Some(name) = key.strip_prefix(METADATA_PREFIX) else {
+            continue;
+        };
+        let Ok(value) = STANDARD.decode(value) else {
+            eprintln!("Warning: {} has a malformed value for {}", path.display(), name);
+            continue;
+        };
+        match set(path, name, &value) {
+            Ok(()) => restored += 1,
+            Err(e) => eprintln!(
+                "Warning: could not restore {} on {}: {}",
+                name,
+                path.display(),
+                e
+            ),
+        }
+    }
+    restored
+}
+
+#[cfg(unix)]
+fn set(path: &Path, name: &str, value: &[u8]) -> std::io::Result<()> {
+    xattr::set(path, name, value)
+}
+
+#[cfg(not(unix))]
+fn set(_path: &Path, _name: &str, _value: &[u8]) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "extended attributes are not supported on this platform",
+    ))
+}
+
+/// 让远程元数据中的属性与本地一致的补丁，None 表示删除
+pub fn metadata_patch(
+    local: &BTreeMap<String, String>,
+    remote: &BTreeMap<String, String>,
+) -> BTreeMap<String, Option<String>> {
+    let mut patch: BTreeMap<String, Option<String>> = local
+        .iter()
+        .filter(|(key, value)| remote.get(*key) != Some(value))
+        .map(|(key, value)| (key.clone(), Some(value.clone())))
+        .collect();
+    for key in remote.keys() {
+        if key.starts_with(METADATA_PREFIX) && !local.contains_key(key) {
+            patch.insert(key.clone(), None);
+        }
+    }
+    patch
+}
+
+/// 按属性名统计被忽略的次数，结束时每个属性只警告一次
+#[derive(Debug, Default)]
+pub struct IgnoredAttributes {
+    counts: HashMap<String, usize>,
+}
+
+impl IgnoredAttributes {
+    pub fn record(&mut self, attributes: &Attributes) {
+        for name in &attributes.ignored {
+            *self.counts.entry(name.clone()).or_default() += 1;
+        }
+    }
+
+    pub fn merge(&mut self, other: IgnoredAttributes) {
+        for (name, count) in other.counts {
+            *self.counts.entry(name).or_default() += count;
+        }
+    }
+
+    pub fn warn(&self) {
+        let mut counts: Vec<_> = self.counts.iter().collect();
+        counts.sort();
+        for (name, count) in counts {
+            eprintln!(
+                "Warning: extended attribute {} not synced ({} file(s))",
+                name, count
+            );
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! rcloud sync 在注入故障的服务端上反复执行，最终必须与本地目录一致
+
+mod common;
+
+use std::path::Path;
+use std::time::Duration;
+
+use common::{stderr, stdout, Server};
+use rustcloud::service::chaos::ChaosConfig;
+use rustcloud_client::sha256_hex;
+use tempfile::TempDir;
+
+fn write_local_tree(root: &Path) -> Vec<(String, Vec<u8>)> {
+    let files: Vec<(String, Vec<u8>)> = (0..20)
+        .map(|i| {
+            let path = format!("dir{}/file-{}.bin", i % 4, i);
+            // 大小跨越分块边界，内容各不相同
+            let content = (0..(i * 300 + 7)).map(|b| (b * 31 + i) as u8).collect();
+            (path, content)
+        })
+        .collect();
+    for (path, content) in &files {
+        let target = root.join(path);
+        std::fs::create_dir_all(target.parent().unwrap()).unwrap();
+        std::fs::write(target, content).unwrap();
+    }
+    files
+}
+
+async fn assert_server_matches(server: &Server, files: &[(String, Vec<u8>)]) {
+    let records = server.repository.list_files().await.unwrap();
+    assert_eq!(records.len(), files.len());
+    for (path, content) in files {
+        let record = records.iter().find(|r| &r.path == path).unwrap();
+        let hash = sha256_hex(content);
+        assert_eq!(record.hash.as_deref(), Some(hash.as_str()), "{}", path);
+        // 响应丢失后的重试命中去重，不会多出版本
+        assert_eq!(record.version, 1, "{}", path);
+        let stored = std::fs::read(server.storage.object_path(&hash)).unwrap();
+        assert_eq!(&stored, content, "{}", path);
+    }
+}
+
+#[tokio::test]
+async fn test_sync_converges_despite_injected_faults() {
+    let server = Server::start(0x5eed).await;
+    let home = TempDir::new().unwrap();
+    let local = TempDir::new().unwrap();
+    let files = write_local_tree(local.path());
+
+    server.chaos.configure(ChaosConfig {
+        delay: Duration::from_millis(2),
+        fail_rate: 0.1,
+        drop_rate: 0.1,
+        write_failure_rate: 0.1,
+        partial_write_rate: 0.1,
+        method: None,
+    });
+
+    let mut failed_runs = 0;
+    let mut converged = false;
+    for _ in 0..200 {
+        if server
+            .sync(home.path(), local.path())
+            .await
+            .status
+            .success()
+        {
+            converged = true;
+            break;
+        }
+        failed_runs += 1;
+    }
+    assert!(converged, "sync never completed");
+    assert!(failed_runs > 0, "no faults were injected");
+
+    // 故障关闭后再同步一次，不应再有需要上传的文件
+    server.chaos.disable();
+    let output = server.sync(home.path(), local.path()).await;
+    assert!(output.status.success());
+    assert!(
+        stdout(&output).contains("Upload:   0 file(s)"),
+        "{}",
+        stdout(&output)
+    );
+    assert_server_matches(&server, &files).await;
+}
+
+#[tokio::test]
+async fn test_sync_retry_after_lost_responses_does_not_duplicate_versions() {
+    let server = Server::start(7).await;
+    let home = TempDir::new().unwrap();
+    let local = TempDir::new().unwrap();
+    let files = write_local_tree(local.path());
+
+    // 服务端处理了每一次上传，但客户端一个上传响应都没收到；
+    // 这一轮每个上传都记为失败，下一轮重新规划时它们已是 skip
+    server.chaos.configure(ChaosConfig {
+        drop_rate: 1.0,
+        method: Some(axum::http::Method::PUT),
+        ..ChaosConfig::default()
+    });
+    let output = server.sync(home.path(), local.path()).await;
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert_eq!(
+        stderr(&output).matches("[FAILED]").count(),
+        files.len(),
+        "{}",
+        stderr(&output)
+    );
+
+    server.chaos.disable();
+    let output = server.sync(home.path(), local.path()).await;
+    assert!(output.status.success());
+    assert!(
+        stdout(&output).contains("Upload:   0 file(s)"),
+        "{}",
+        stdout(&output)
+    );
+    assert_server_matches(&server, &files).await;
+}
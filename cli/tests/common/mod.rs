@@ -0,0 +1,134 @@
+//! 集成测试共用：在随机端口启动后端，并在隔离的 HOME 下运行真实的 rcloud 二进制
+#![allow(dead_code)]
+
+use std::path::Path;
+use std::process::Output;
+use std::sync::Arc;
+
+use rustcloud::config::{
+    Config, ConflictStrategy, DatabaseBackend, NetworkConfig, WebSecurityConfig, WorkerConfig,
+};
+use rustcloud::db::Repository;
+use rustcloud::service::chaos::{inject_http_faults, Chaos, ChaosStorage};
+use rustcloud::service::storage::{StorageConfig, StorageService};
+use tempfile::TempDir;
+
+pub struct Server {
+    pub url: String,
+    pub repository: Arc<Repository>,
+    pub storage: StorageService,
+    /// 默认不注入任何故障
+    pub chaos: Chaos,
+    pub dir: TempDir,
+}
+
+impl Server {
+    pub async fn start(seed: u64) -> Server {
+        Self::start_with(seed, |_| {}).await
+    }
+
+    /// 启动前调整服务端配置，例如启用认证
+    pub async fn start_with(seed: u64, configure: impl FnOnce(&mut Config)) -> Server {
+        let dir = TempDir::new().unwrap();
+        let mut config = Config {
+            host: "127.0.0.1".to_string(),
+            port: 0,
+            storage_path: dir.path().join("storage"),
+            database: DatabaseBackend::Json,
+            max_file_size: 100 * 1024 * 1024,
+            chunk_size: 1024,
+            shard_depth: 2,
+            materialize_files: true,
+            warm_hash_cache: false,
+            verify_reads: false,
+            compress_objects: false,
+            conflicts: ConflictStrategy::default(),
+            append_only: Vec::new(),
+            upload_only: Vec::new(),
+            download_only: Vec::new(),
+            shred_deleted: false,
+            trash_retention_days: 0,
+            ignore: Vec::new(),
+            smtp: None,
+            reputation: None,
+            admin_token: None,
+            min_client_version: None,
+            lifecycle_interval_secs: 0,
+            auth: None,
+            upstream: None,
+            network: NetworkConfig::default(),
+            web: WebSecurityConfig::default(),
+            workers: WorkerConfig::default(),
+        };
+        configure(&mut config);
+        std::fs::create_dir_all(&config.storage_path).unwrap();
+
+        let repository = Arc::new(
+            Repository::open(&config.storage_path, config.database)
+                .await
+                .unwrap(),
+        );
+        let storage = StorageService::new(StorageConfig {
+            storage_path: config.storage_path.clone(),
+            chunk_size: 1024,
+        });
+        let chaos = Chaos::new(seed);
+        let app = rustcloud::api::create_router_with_backends(
+            config,
+            repository.clone(),
+            repository.clone(),
+            Arc::new(ChaosStorage::new(Arc::new(storage.clone()), chaos.clone())),
+        )
+        .await
+        .layer(axum::middleware::from_fn_with_state(
+            chaos.clone(),
+            inject_http_faults,
+        ));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        Server {
+            url,
+            repository,
+            storage,
+            chaos,
+            dir,
+        }
+    }
+
+    pub fn command(&self, home: &Path, args: &[&str]) -> tokio::process::Command {
+        rcloud_command(&self.url, home, args)
+    }
+
+    pub async fn rcloud(&self, home: &Path, args: &[&str]) -> Output {
+        self.command(home, args).output().await.unwrap()
+    }
+
+    pub async fn sync(&self, home: &Path, dir: &Path) -> Output {
+        self.rcloud(home, &["sync", "--path", dir.to_str().unwrap()])
+            .await
+    }
+}
+
+/// 在独立的 HOME 下运行 rcloud，避免读到开发机上的配置
+pub fn rcloud_command(url: &str, home: &Path, args: &[&str]) -> tokio::process::Command {
+    let mut command = tokio::process::Command::new(env!("CARGO_BIN_EXE_rcloud"));
+    command
+        .env("HOME", home)
+        .env("XDG_CONFIG_HOME", home.join(".config"))
+        // 测试绝不碰开发机上的真实钥匙串
+        .env("RCLOUD_NO_KEYRING", "1")
+        .args(["--server", url])
+        .args(args);
+    command
+}
+
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).to_string()
+}
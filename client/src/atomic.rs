@@ -0,0 +1,28 @@
+use std::path::{Path, PathBuf};
+
+/// 临时文件名标记，扫描本地文件时据此跳过
+pub const TEMP_MARKER: &str = ".rcloud-tmp-";
+
+/// 与目标同目录的临时文件路径，保证 rename 不跨文件系统
+pub fn temp_sibling(target: &Path) -> PathBuf {
+    let name = target
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    target.with_file_name(format!(".{}{}{}", name, TEMP_MARKER, uuid::Uuid::new_v4()))
+}
+
+/// 断点续传的部分文件，名字固定，下次下载同一目标时据此继续
+pub fn partial_sibling(target: &Path) -> PathBuf {
+    let name = target
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    target.with_file_name(format!(".{}{}partial", name, TEMP_MARKER))
+}
+
+pub fn is_temp_file(path: &Path) -> bool {
+    path.file_name()
+        .map(|n| n.to_string_lossy().contains(TEMP_MARKER))
+        .unwrap_or(false)
+}
@@ -0,0 +1,1683 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+#[cfg(feature = "native")]
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+
+#[cfg(feature = "native")]
+use crate::atomic::{partial_sibling, temp_sibling};
+
+use rustcloud_types::delta::{self, FileSignature};
+use rustcloud_types::{compare_versions, CLIENT_VERSION_HEADER};
+
+pub use rustcloud_types::{
+    direction_of, feature, ApiResponse, AuthToken, Capabilities, ChangeEvent, ChangeKind,
+    ConflictInfo, DeviceHeartbeat, DeviceRecord, FileInfo, FileRecord, FileVersionRecord,
+    FolderDirection, HashEntry, HealthStatus, ServiceHealth, StorageUsage, SyncDirection,
+    TrashItem, UploadStatus, UserInfo, PROTOCOL_VERSION,
+};
+
+/// 校验和不一致时的最大传输次数
+const MAX_TRANSFER_ATTEMPTS: usize = 3;
+
+/// 小于这个大小的文件直接整体上传，增量省下的流量抵不过多一次往返
+const DELTA_MIN_SIZE: usize = 64 * 1024;
+
+/// 带进度的上传按这个大小分段交给连接，每段报告一次进度
+#[cfg(feature = "native")]
+const UPLOAD_PIECE_SIZE: usize = 64 * 1024;
+
+/// 上传进度回调，参数是本次请求已交给连接的字节数；重试时从 0 重新计数
+pub type UploadProgress = Arc<dyn Fn(u64) + Send + Sync>;
+
+/// 传输前后内容哈希不一致
+#[derive(Debug)]
+pub struct ChecksumMismatch {
+    pub path: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl std::fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Checksum mismatch for {}: expected {}, got {}",
+            self.path, self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for ChecksumMismatch {}
+
+/// 目标路径已存在，服务端按 on_conflict=fail 拒绝了上传
+#[derive(Debug)]
+pub struct Conflict {
+    pub path: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for Conflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.message.is_empty() {
+            write!(f, "Conflict at {}", self.path)
+        } else {
+            f.write_str(&self.message)
+        }
+    }
+}
+
+impl std::error::Error for Conflict {}
+
+/// 服务端按错误类型返回的状态码，调用方据此区别处理而不必匹配消息文本
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerErrorKind {
+    /// 401：未登录或令牌过期
+    Unauthorized,
+    /// 503：服务端暂时忙，稍后重试
+    Busy,
+    /// 507：服务端磁盘已满或超出配额，重试无用
+    QuotaExceeded,
+}
+
+#[derive(Debug)]
+pub struct ServerError {
+    pub kind: ServerErrorKind,
+    pub message: String,
+}
+
+impl ServerError {
+    fn from_status(status: reqwest::StatusCode, message: Option<&str>) -> Option<Self> {
+        let kind = match status {
+            reqwest::StatusCode::UNAUTHORIZED => ServerErrorKind::Unauthorized,
+            reqwest::StatusCode::SERVICE_UNAVAILABLE => ServerErrorKind::Busy,
+            reqwest::StatusCode::INSUFFICIENT_STORAGE => ServerErrorKind::QuotaExceeded,
+            _ => return None,
+        };
+        Some(ServerError {
+            kind,
+            message: message.unwrap_or_default().to_string(),
+        })
+    }
+}
+
+impl std::fmt::Display for ServerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !self.message.is_empty() {
+            return f.write_str(&self.message);
+        }
+        f.write_str(match self.kind {
+            ServerErrorKind::Unauthorized => "Not authorized",
+            ServerErrorKind::Busy => "Server is busy",
+            ServerErrorKind::QuotaExceeded => "Server is out of storage space",
+        })
+    }
+}
+
+impl std::error::Error for ServerError {}
+
+/// 错误链中服务端返回的错误类型
+pub fn server_error_kind(error: &anyhow::Error) -> Option<ServerErrorKind> {
+    error
+        .chain()
+        .find_map(|e| e.downcast_ref::<ServerError>())
+        .map(|e| e.kind)
+}
+
+/// 连不上服务端或请求超时，而不是服务端返回了错误
+pub fn is_connection_error(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .filter_map(|e| e.downcast_ref::<reqwest::Error>())
+        .any(|e| {
+            #[cfg(not(target_arch = "wasm32"))]
+            if e.is_connect() {
+                return true;
+            }
+            e.is_timeout()
+        })
+}
+
+// 有进度回调时把内容切成段，连接每取走一段报告一次累计字节数；
+// 分段发送时照样声明长度，超过服务端上限的文件在发送前就被拒绝
+#[cfg(feature = "native")]
+fn with_upload_body(
+    request: reqwest::RequestBuilder,
+    content: &[u8],
+    on_progress: Option<&UploadProgress>,
+) -> reqwest::RequestBuilder {
+    let Some(on_progress) = on_progress.cloned() else {
+        return request.body(content.to_vec());
+    };
+    let pieces: Vec<Vec<u8>> = content
+        .chunks(UPLOAD_PIECE_SIZE)
+        .map(<[u8]>::to_vec)
+        .collect();
+    let mut sent = 0u64;
+    let pieces = futures_util::stream::iter(pieces.into_iter().map(move |piece| {
+        sent += piece.len() as u64;
+        on_progress(sent);
+        Ok::<_, std::io::Error>(piece)
+    }));
+    request
+        .header(reqwest::header::CONTENT_LENGTH, content.len())
+        .body(reqwest::Body::wrap_stream(pieces))
+}
+
+// 浏览器中请求体由 fetch 一次发出，无法逐段报告
+#[cfg(not(feature = "native"))]
+fn with_upload_body(
+    request: reqwest::RequestBuilder,
+    content: &[u8],
+    _on_progress: Option<&UploadProgress>,
+) -> reqwest::RequestBuilder {
+    request.body(content.to_vec())
+}
+
+pub fn sha256_hex(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+/// 分段读取计算文件的 SHA-256，内存占用与文件大小无关
+#[cfg(feature = "native")]
+pub async fn file_sha256(path: &Path) -> Result<String> {
+    use tokio::io::AsyncReadExt;
+
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1024 * 1024];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// 上传响应的统一检查：409 为冲突，422 或记录的哈希与本地不一致时为校验失败
+fn check_uploaded(
+    path: &str,
+    local_hash: &str,
+    status: reqwest::StatusCode,
+    result: ApiResponse<FileInfo>,
+) -> Result<FileInfo> {
+    if status == reqwest::StatusCode::UNPROCESSABLE_ENTITY {
+        return Err(ChecksumMismatch {
+            path: path.to_string(),
+            expected: local_hash.to_string(),
+            actual: result.error.unwrap_or_default(),
+        }
+        .into());
+    }
+    if status == reqwest::StatusCode::CONFLICT {
+        return Err(Conflict {
+            path: path.to_string(),
+            message: result.error.unwrap_or_default(),
+        }
+        .into());
+    }
+    if let Some(e) = ServerError::from_status(status, result.error.as_deref()) {
+        return Err(e.into());
+    }
+
+    let info = result.data.ok_or_else(|| {
+        anyhow::anyhow!(
+            "Failed to upload file: {}",
+            result.error.unwrap_or_default()
+        )
+    })?;
+    match info.hash.as_deref() {
+        Some(hash) if hash == local_hash => Ok(info),
+        other => Err(ChecksumMismatch {
+            path: path.to_string(),
+            expected: local_hash.to_string(),
+            actual: other.unwrap_or("<none>").to_string(),
+        }
+        .into()),
+    }
+}
+
+/// HTTP 客户端选项，对应配置文件中的 [http] 表
+///
+/// 浏览器中连接由 fetch 管理，未启用 `native` feature 时超时、代理与证书选项被忽略。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpConfig {
+    /// 建立连接的超时时间（秒）
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout_secs: u64,
+    /// 两次读取之间的最长等待时间（秒），防止服务端失联时无限挂起
+    #[serde(default = "default_read_timeout")]
+    pub read_timeout_secs: u64,
+    /// 显式代理地址；未设置时使用系统代理环境变量（HTTP_PROXY 等）
+    #[serde(default)]
+    pub proxy: Option<String>,
+    /// 额外信任的 CA 证书（PEM），用于自签名的局域网服务器
+    #[serde(default)]
+    pub ca_cert: Option<PathBuf>,
+    /// 跳过 TLS 证书校验
+    #[serde(default)]
+    pub insecure: bool,
+    /// 管理员令牌，以 `x-admin-token` 随请求发送；由调用方从凭据存储读取，不写入配置文件
+    #[serde(skip)]
+    pub admin_token: Option<String>,
+    /// 登录得到的 JWT，以 `Authorization: Bearer` 随请求发送；同样不写入配置文件
+    #[serde(skip)]
+    pub auth_token: Option<String>,
+    /// 调用方（如 rcloud）的版本号，以 `x-client-version` 随请求发送；未设置时使用本库的版本
+    #[serde(skip)]
+    pub client_version: Option<String>,
+}
+
+fn default_connect_timeout() -> u64 {
+    10
+}
+
+fn default_read_timeout() -> u64 {
+    60
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            connect_timeout_secs: default_connect_timeout(),
+            read_timeout_secs: default_read_timeout(),
+            proxy: None,
+            ca_cert: None,
+            insecure: false,
+            admin_token: None,
+            auth_token: None,
+            client_version: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    base_url: String,
+    http: reqwest::Client,
+    /// 第一次成功获取后缓存，克隆的客户端共享
+    capabilities: Arc<OnceLock<Capabilities>>,
+    /// 上一次 `list_hashes` 的结果，状态没变时服务端只返回 304
+    hashes: Arc<Mutex<Option<HashListing>>>,
+    /// 随请求发送的版本号，与服务端要求的最低版本比较
+    version: String,
+    /// 设备、版本与认证头，WebSocket 握手时同样需要
+    #[cfg(feature = "native")]
+    headers: reqwest::header::HeaderMap,
+    /// 自定义 CA 或跳过证书校验时 WebSocket 使用的 TLS 设置，None 为系统默认
+    #[cfg(feature = "native")]
+    tls: Option<native_tls::TlsConnector>,
+}
+
+#[derive(Debug)]
+struct HashListing {
+    prefix: String,
+    etag: String,
+    entries: Vec<HashEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FilePreview {
+    pub path: String,
+    pub size: u64,
+    pub offset: u64,
+    pub truncated: bool,
+    pub content: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DuplicateGroup {
+    pub hash: String,
+    pub size: u64,
+    pub paths: Vec<String>,
+    pub reclaimable_bytes: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DuplicatesReport {
+    pub total_groups: usize,
+    pub reclaimable_bytes: u64,
+    pub groups: Vec<DuplicateGroup>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UsageFile {
+    pub path: String,
+    pub size: u64,
+    pub last_used_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FolderUsage {
+    pub folder: String,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UsageReport {
+    pub total_files: usize,
+    pub total_bytes: u64,
+    pub files: Vec<UsageFile>,
+    pub folders: Vec<FolderUsage>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchResult {
+    pub files: Vec<FileInfo>,
+    /// 截断前的匹配总数
+    pub total: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ShareLink {
+    pub id: String,
+    pub path: String,
+    #[serde(default)]
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncPlanItem {
+    pub file_id: String,
+    pub path: String,
+    pub action: String,
+    /// 目录条目：upload 在服务端创建，download 在本地创建
+    #[serde(default)]
+    pub is_dir: bool,
+}
+
+impl Client {
+    pub fn new(base_url: &str, options: &HttpConfig, device_id: Option<&str>) -> Result<Self> {
+        let mut builder = reqwest::Client::builder();
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        // 服务端据此拒绝过旧的客户端
+        let version = options
+            .client_version
+            .clone()
+            .unwrap_or_else(|| env!("CARGO_PKG_VERSION").to_string());
+        headers.insert(
+            CLIENT_VERSION_HEADER,
+            reqwest::header::HeaderValue::from_str(&version)?,
+        );
+        // 服务端按设备匹配速率等级
+        if let Some(device_id) = device_id {
+            headers.insert(
+                "x-device-id",
+                reqwest::header::HeaderValue::from_str(device_id)?,
+            );
+        }
+        if let Some(token) = &options.admin_token {
+            let mut value = reqwest::header::HeaderValue::from_str(token)?;
+            value.set_sensitive(true);
+            headers.insert("x-admin-token", value);
+        }
+        if let Some(token) = &options.auth_token {
+            let mut value =
+                reqwest::header::HeaderValue::from_str(&format!("Bearer {}", token))?;
+            value.set_sensitive(true);
+            headers.insert(reqwest::header::AUTHORIZATION, value);
+        }
+        builder = builder.default_headers(headers.clone());
+
+        #[cfg(feature = "native")]
+        {
+            builder = Self::apply_transport_options(builder, options)?;
+        }
+        #[cfg(not(feature = "native"))]
+        let _ = options;
+
+        Ok(Client {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            http: builder.build()?,
+            capabilities: Arc::new(OnceLock::new()),
+            hashes: Arc::new(Mutex::new(None)),
+            version,
+            #[cfg(feature = "native")]
+            headers,
+            #[cfg(feature = "native")]
+            tls: Self::websocket_tls(options)?,
+        })
+    }
+
+    #[cfg(feature = "native")]
+    fn websocket_tls(options: &HttpConfig) -> Result<Option<native_tls::TlsConnector>> {
+        if options.ca_cert.is_none() && !options.insecure {
+            return Ok(None);
+        }
+        let mut builder = native_tls::TlsConnector::builder();
+        if let Some(ca_cert) = &options.ca_cert {
+            let pem = std::fs::read(ca_cert).map_err(|e| {
+                anyhow::anyhow!("Failed to read CA certificate {:?}: {}", ca_cert, e)
+            })?;
+            builder.add_root_certificate(native_tls::Certificate::from_pem(&pem)?);
+        }
+        if options.insecure {
+            builder.danger_accept_invalid_certs(true);
+        }
+        Ok(Some(builder.build()?))
+    }
+
+    #[cfg(feature = "native")]
+    fn apply_transport_options(
+        mut builder: reqwest::ClientBuilder,
+        options: &HttpConfig,
+    ) -> Result<reqwest::ClientBuilder> {
+        builder = builder
+            .connect_timeout(std::time::Duration::from_secs(options.connect_timeout_secs))
+            .read_timeout(std::time::Duration::from_secs(options.read_timeout_secs));
+
+        if let Some(proxy) = &options.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+        }
+
+        if let Some(ca_cert) = &options.ca_cert {
+            let pem = std::fs::read(ca_cert).map_err(|e| {
+                anyhow::anyhow!("Failed to read CA certificate {:?}: {}", ca_cert, e)
+            })?;
+            builder = builder.add_root_certificate(reqwest::Certificate::from_pem(&pem)?);
+        }
+
+        if options.insecure {
+            tracing::warn!("TLS certificate verification disabled");
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+
+        Ok(builder)
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub async fn health(&self) -> Result<bool> {
+        let url = format!("{}/api/health", self.base_url);
+        let resp = self.http.get(&url).send().await?;
+        let result: ApiResponse<serde_json::Value> = resp.json().await?;
+        Ok(result.success)
+    }
+
+    /// 服务端版本与时间；早期版本的健康检查只返回 "ok"，此时为 None
+    pub async fn server_status(&self) -> Result<Option<HealthStatus>> {
+        let url = format!("{}/api/health", self.base_url);
+        let resp = self.http.get(&url).send().await?;
+        let result: ApiResponse<serde_json::Value> = resp.json().await?;
+        Ok(result.data.and_then(|data| serde_json::from_value(data).ok()))
+    }
+
+    /// 服务端的协议版本与可选功能；没有该接口的早期版本视为协议 0
+    pub async fn capabilities(&self) -> Result<Capabilities> {
+        if let Some(capabilities) = self.capabilities.get() {
+            return Ok(capabilities.clone());
+        }
+        let url = format!("{}/api/capabilities", self.base_url);
+        let resp = self.http.get(&url).send().await?;
+        let capabilities = if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            Capabilities::default()
+        } else {
+            let result: ApiResponse<Capabilities> = resp.error_for_status()?.json().await?;
+            result
+                .data
+                .ok_or_else(|| anyhow::anyhow!("No data in response"))?
+        };
+        Ok(self.capabilities.get_or_init(|| capabilities).clone())
+    }
+
+    /// 服务端要求更新的客户端时返回明确的错误；获取不到能力信息时不报错，交给后续请求
+    pub async fn check_compatibility(&self) -> Result<Option<Capabilities>> {
+        let Ok(capabilities) = self.capabilities().await else {
+            return Ok(None);
+        };
+        if capabilities.min_protocol > PROTOCOL_VERSION {
+            anyhow::bail!(
+                "Server {} requires protocol {} or newer, but this client speaks protocol {}; please upgrade the client",
+                capabilities.version,
+                capabilities.min_protocol,
+                PROTOCOL_VERSION
+            );
+        }
+        if let Some(minimum) = &capabilities.min_client_version {
+            if compare_versions(&self.version, minimum) == Some(std::cmp::Ordering::Less) {
+                anyhow::bail!(
+                    "Server {} requires client version {} or newer, but this client is version {}; please upgrade the client",
+                    capabilities.version,
+                    minimum,
+                    self.version
+                );
+            }
+        }
+        Ok(Some(capabilities))
+    }
+
+    /// 能力信息未知（获取失败或早期版本）时乐观地认为支持
+    pub async fn supports(&self, feature: &str) -> bool {
+        self.capabilities()
+            .await
+            .map_or(true, |capabilities| {
+                capabilities.protocol == 0 || capabilities.supports(feature)
+            })
+    }
+
+    /// 服务端明确不支持时给出可读的错误，而不是随后请求的 404
+    pub async fn require(&self, feature: &str) -> Result<()> {
+        if self.supports(feature).await {
+            return Ok(());
+        }
+        let version = self
+            .capabilities()
+            .await
+            .map(|c| c.version)
+            .unwrap_or_default();
+        let version = if version.is_empty() {
+            "an older version".to_string()
+        } else {
+            format!("version {}", version)
+        };
+        anyhow::bail!(
+            "The server ({}) does not support '{}'; please upgrade the server",
+            version,
+            feature
+        )
+    }
+
+    /// 注册新用户，成功后直接返回登录令牌
+    pub async fn register(&self, username: &str, password: &str) -> Result<AuthToken> {
+        self.authenticate("register", username, password, false).await
+    }
+
+    pub async fn login(&self, username: &str, password: &str) -> Result<AuthToken> {
+        self.authenticate("login", username, password, false).await
+    }
+
+    /// 登录并申请只追加的令牌（只能上传，不能删除），供备份使用；
+    /// 服务端不支持时不会悄悄退回普通令牌
+    pub async fn login_append_only(&self, username: &str, password: &str) -> Result<AuthToken> {
+        let token = self.authenticate("login", username, password, true).await?;
+        if !token.append_only {
+            anyhow::bail!("The server does not support append-only tokens");
+        }
+        Ok(token)
+    }
+
+    async fn authenticate(
+        &self,
+        action: &str,
+        username: &str,
+        password: &str,
+        append_only: bool,
+    ) -> Result<AuthToken> {
+        let url = format!("{}/api/auth/{}", self.base_url, action);
+        let resp = self
+            .http
+            .post(&url)
+            .json(&serde_json::json!({
+                "username": username,
+                "password": password,
+                "append_only": append_only,
+            }))
+            .send()
+            .await?;
+        let result: ApiResponse<AuthToken> = resp.json().await?;
+        result.data.ok_or_else(|| {
+            anyhow::anyhow!(
+                "{} failed: {}",
+                if action == "login" { "Login" } else { "Registration" },
+                result.error.unwrap_or_default()
+            )
+        })
+    }
+
+    /// 当前令牌对应的用户；令牌缺失、无效或过期时为 None
+    pub async fn current_user(&self) -> Result<Option<UserInfo>> {
+        let url = format!("{}/api/auth/me", self.base_url);
+        let resp = self.http.get(&url).send().await?;
+        if resp.status() == reqwest::StatusCode::UNAUTHORIZED {
+            return Ok(None);
+        }
+        let result: ApiResponse<UserInfo> = resp.json().await?;
+        Ok(result.data)
+    }
+
+    pub async fn list_files(&self, path: Option<&str>) -> Result<Vec<FileInfo>> {
+        let url = format!("{}/api/files", self.base_url);
+        let mut req = self.http.get(&url);
+
+        if let Some(p) = path {
+            req = req.query(&[("path", p)]);
+        }
+
+        let resp = req.send().await?;
+        let result: ApiResponse<Vec<FileInfo>> = resp.json().await?;
+        result
+            .data
+            .ok_or_else(|| anyhow::anyhow!("No data in response"))
+    }
+
+    /// 列出 at 时刻的目录，包括之后被删除或移走的文件
+    pub async fn list_files_as_of(
+        &self,
+        path: Option<&str>,
+        at: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Vec<FileInfo>> {
+        self.require(feature::TIME_TRAVEL).await?;
+        let url = format!("{}/api/files", self.base_url);
+        let mut req = self.http.get(&url).query(&[(
+            "as_of",
+            at.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true),
+        )]);
+        if let Some(p) = path {
+            req = req.query(&[("path", p)]);
+        }
+
+        let resp = req.send().await?;
+        let result: ApiResponse<Vec<FileInfo>> = resp.json().await?;
+        result.data.ok_or_else(|| {
+            anyhow::anyhow!("Failed to list files: {}", result.error.unwrap_or_default())
+        })
+    }
+
+    /// 以 NDJSON 流式列出目录，每解析出一条就回调一次，返回条目数
+    #[cfg(feature = "native")]
+    pub async fn list_files_streaming(
+        &self,
+        path: Option<&str>,
+        mut on_entry: impl FnMut(FileInfo),
+    ) -> Result<usize> {
+        use futures_util::StreamExt;
+
+        if !self.supports(feature::STREAMED_LISTING).await {
+            let files = self.list_files(path).await?;
+            let count = files.len();
+            files.into_iter().for_each(on_entry);
+            return Ok(count);
+        }
+
+        let url = format!("{}/api/files", self.base_url);
+        let mut req = self.http.get(&url).query(&[("stream", "true")]);
+        if let Some(p) = path {
+            req = req.query(&[("path", p)]);
+        }
+
+        let resp = req.send().await?;
+        let is_ndjson = resp
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .is_some_and(|v| v.as_bytes().starts_with(b"application/x-ndjson"));
+        if !is_ndjson {
+            // 出错时服务端仍返回普通的 JSON 响应；不认识 stream 参数的早期版本直接返回整个列表
+            let result: ApiResponse<Vec<FileInfo>> = resp.json().await?;
+            let Some(files) = result.data.filter(|_| result.success) else {
+                anyhow::bail!(
+                    "Failed to list files: {}",
+                    result.error.unwrap_or_default()
+                );
+            };
+            let count = files.len();
+            files.into_iter().for_each(on_entry);
+            return Ok(count);
+        }
+
+        let mut stream = resp.bytes_stream();
+        let mut pending = Vec::new();
+        let mut count = 0;
+        while let Some(chunk) = stream.next().await {
+            pending.extend_from_slice(&chunk?);
+            while let Some(end) = pending.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = pending.drain(..=end).collect();
+                on_entry(serde_json::from_slice(&line)?);
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    pub async fn list_devices(&self) -> Result<Vec<DeviceRecord>> {
+        let url = format!("{}/api/devices", self.base_url);
+        let resp = self.http.get(&url).send().await?;
+        let result: ApiResponse<Vec<DeviceRecord>> = resp.json().await?;
+        result
+            .data
+            .ok_or_else(|| anyhow::anyhow!("No data in response"))
+    }
+
+    pub async fn register_device(&self, name: &str) -> Result<DeviceRecord> {
+        let url = format!("{}/api/devices", self.base_url);
+        let resp = self
+            .http
+            .post(&url)
+            .json(&serde_json::json!({ "name": name }))
+            .send()
+            .await?;
+        let result: ApiResponse<DeviceRecord> = resp.json().await?;
+        result
+            .data
+            .ok_or_else(|| anyhow::anyhow!("Failed to register device"))
+    }
+
+    /// 订阅服务端推送的文件变更，直到连接关闭；服务端因为本端落后太多而断开时返回错误，
+    /// 此时漏掉了部分变更，调用方应做一次完整同步
+    #[cfg(feature = "native")]
+    pub async fn watch(&self, mut on_change: impl FnMut(ChangeEvent)) -> Result<()> {
+        use futures_util::StreamExt;
+        use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+        use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+        use tokio_tungstenite::tungstenite::Message;
+
+        // http -> ws，https -> wss
+        let url = format!("{}/api/ws", self.base_url).replacen("http", "ws", 1);
+        let mut request = url.into_client_request()?;
+        request.headers_mut().extend(self.headers.clone());
+        let connector = self
+            .tls
+            .clone()
+            .map(tokio_tungstenite::Connector::NativeTls);
+        let (mut socket, _) =
+            tokio_tungstenite::connect_async_tls_with_config(request, None, false, connector)
+                .await?;
+
+        while let Some(message) = socket.next().await {
+            match message? {
+                Message::Text(text) => on_change(serde_json::from_str(&text)?),
+                Message::Close(Some(frame)) if frame.code != CloseCode::Normal => {
+                    anyhow::bail!("Server closed the change feed: {}", frame.reason)
+                }
+                Message::Close(_) => break,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// 上报在线；服务端支持时顺带告知上次同步后是否有其他设备的变更
+    pub async fn heartbeat(&self, device_id: &str) -> Result<DeviceHeartbeat> {
+        let url = format!("{}/api/devices/{}/heartbeat", self.base_url, device_id);
+        let resp = self.http.post(&url).send().await?;
+        let result: ApiResponse<DeviceHeartbeat> = resp.json().await?;
+        result
+            .data
+            .ok_or_else(|| anyhow::anyhow!("Failed to send heartbeat"))
+    }
+
+    /// 尚未解决的同步冲突
+    pub async fn list_conflicts(&self) -> Result<Vec<ConflictInfo>> {
+        let url = format!("{}/api/conflicts", self.base_url);
+        let resp = self.http.get(&url).send().await?;
+        let result: ApiResponse<Vec<ConflictInfo>> = resp.json().await?;
+        result
+            .data
+            .ok_or_else(|| anyhow::anyhow!("Failed to list conflicts"))
+    }
+
+    /// 回收站中可以恢复的条目，最近删除的在前
+    pub async fn list_trash(&self) -> Result<Vec<TrashItem>> {
+        let url = format!("{}/api/trash", self.base_url);
+        let resp = self.http.get(&url).send().await?;
+        let result: ApiResponse<Vec<TrashItem>> = resp.json().await?;
+        result.data.ok_or_else(|| {
+            anyhow::anyhow!("Failed to list trash: {}", result.error.unwrap_or_default())
+        })
+    }
+
+    /// 把回收站中的条目恢复到原路径；原路径已被占用时失败
+    pub async fn restore_trash(&self, id: uuid::Uuid) -> Result<TrashItem> {
+        let url = format!("{}/api/trash/{}/restore", self.base_url, id);
+        let resp = self.http.post(&url).send().await?;
+        let result: ApiResponse<TrashItem> = resp.json().await?;
+        result.data.ok_or_else(|| {
+            anyhow::anyhow!("Failed to restore: {}", result.error.unwrap_or_default())
+        })
+    }
+
+    /// 上传并校验服务端记录的哈希与本地一致，不一致时重传
+    pub async fn upload_file(&self, path: &str, content: &[u8]) -> Result<FileInfo> {
+        self.upload_file_with(path, content, None).await
+    }
+
+    /// on_conflict：目标已存在时 overwrite / rename / fail，None 使用服务端默认（覆盖）
+    pub async fn upload_file_with(
+        &self,
+        path: &str,
+        content: &[u8],
+        on_conflict: Option<&str>,
+    ) -> Result<FileInfo> {
+        self.upload_checked(path, content, on_conflict, None).await
+    }
+
+    /// 与 upload_file_with 相同，请求体分段发送并逐段报告进度
+    pub async fn upload_file_with_progress(
+        &self,
+        path: &str,
+        content: &[u8],
+        on_conflict: Option<&str>,
+        on_progress: UploadProgress,
+    ) -> Result<FileInfo> {
+        self.upload_checked(path, content, on_conflict, Some(&on_progress))
+            .await
+    }
+
+    async fn upload_checked(
+        &self,
+        path: &str,
+        content: &[u8],
+        on_conflict: Option<&str>,
+        on_progress: Option<&UploadProgress>,
+    ) -> Result<FileInfo> {
+        let local_hash = sha256_hex(content);
+        let mut last_error = None;
+
+        for attempt in 1..=MAX_TRANSFER_ATTEMPTS {
+            match self
+                .upload_once(path, content, &local_hash, on_conflict, on_progress)
+                .await
+            {
+                Ok(info) => return Ok(info),
+                Err(e) if e.downcast_ref::<ChecksumMismatch>().is_some() => {
+                    tracing::warn!("Upload attempt {} failed: {}", attempt, e);
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("Failed to upload file")))
+    }
+
+    async fn upload_once(
+        &self,
+        path: &str,
+        content: &[u8],
+        local_hash: &str,
+        on_conflict: Option<&str>,
+        on_progress: Option<&UploadProgress>,
+    ) -> Result<FileInfo> {
+        let url = format!("{}/api/files/{}", self.base_url, path);
+        let mut req = self.http.put(&url).header("X-Content-Sha256", local_hash);
+        req = with_upload_body(req, content, on_progress);
+        if let Some(mode) = on_conflict {
+            req = req.query(&[("on_conflict", mode)]);
+        }
+        let resp = req.send().await?;
+        let status = resp.status();
+        let result: ApiResponse<FileInfo> = resp.json().await?;
+        check_uploaded(path, local_hash, status, result)
+    }
+
+    /// 服务端当前内容的块签名
+    pub async fn file_signature(&self, path: &str) -> Result<FileSignature> {
+        let url = format!("{}/api/files/{}/signature", self.base_url, path);
+        let resp = self.http.get(&url).send().await?;
+        let result: ApiResponse<FileSignature> = resp.json().await?;
+        result.data.ok_or_else(|| {
+            anyhow::anyhow!(
+                "Failed to get signature: {}",
+                result.error.unwrap_or_default()
+            )
+        })
+    }
+
+    /// 覆盖服务端已有的文件时只发送改动的部分；服务端不支持、文件较小、
+    /// 改动太多或服务端内容在此期间变化时退回整体上传
+    pub async fn upload_delta(&self, path: &str, content: &[u8]) -> Result<FileInfo> {
+        if content.len() < DELTA_MIN_SIZE || !self.supports(feature::DELTA_SYNC).await {
+            return self.upload_file(path, content).await;
+        }
+        let signature = match self.file_signature(path).await {
+            Ok(signature) => signature,
+            Err(e) => {
+                tracing::debug!("No signature for {}, uploading it whole: {}", path, e);
+                return self.upload_file(path, content).await;
+            }
+        };
+        let delta = delta::diff(&signature, content);
+        // 新数据超过一半时，增量省下的不多
+        if delta.literal_len() * 2 > content.len() as u64 {
+            return self.upload_file(path, content).await;
+        }
+
+        let local_hash = sha256_hex(content);
+        let url = format!("{}/api/files/{}/delta", self.base_url, path);
+        let resp = self
+            .http
+            .patch(&url)
+            .header("If-Match", format!("\"{}\"", signature.hash))
+            .header("X-Content-Sha256", &local_hash)
+            .body(delta.encode())
+            .send()
+            .await?;
+        let status = resp.status();
+        // 取签名之后内容又变了，或拼出的内容与本地不一致
+        if status == reqwest::StatusCode::PRECONDITION_FAILED
+            || status == reqwest::StatusCode::UNPROCESSABLE_ENTITY
+        {
+            return self.upload_file(path, content).await;
+        }
+        let result: ApiResponse<FileInfo> = resp.json().await?;
+        let info = check_uploaded(path, &local_hash, status, result)?;
+        tracing::info!(
+            "Uploaded {} as a delta: {} of {} bytes sent",
+            path,
+            delta.literal_len(),
+            content.len()
+        );
+        Ok(info)
+    }
+
+    /// 服务端对该大小的文件采用的分块大小，不分块时为 None
+    pub async fn chunk_policy(&self, size: u64) -> Result<Option<u64>> {
+        #[derive(Deserialize)]
+        struct ChunkPolicy {
+            chunk_size: Option<u64>,
+        }
+
+        let url = format!("{}/api/chunk-policy", self.base_url);
+        let resp = self.http.get(&url).query(&[("size", size)]).send().await?;
+        let result: ApiResponse<ChunkPolicy> = resp.error_for_status()?.json().await?;
+        Ok(result.data.and_then(|policy| policy.chunk_size))
+    }
+
+    /// 创建分块上传会话，分块大小由服务端决定
+    pub async fn create_upload(
+        &self,
+        path: &str,
+        size: u64,
+        on_conflict: Option<&str>,
+    ) -> Result<UploadStatus> {
+        let url = format!("{}/api/uploads", self.base_url);
+        let mut req = self
+            .http
+            .post(&url)
+            .json(&serde_json::json!({ "path": path, "size": size }));
+        if let Some(mode) = on_conflict {
+            req = req.query(&[("on_conflict", mode)]);
+        }
+        let resp = req.send().await?;
+        let status = resp.status();
+        let result: ApiResponse<UploadStatus> = resp.json().await?;
+        if status == reqwest::StatusCode::CONFLICT {
+            return Err(Conflict {
+                path: path.to_string(),
+                message: result.error.unwrap_or_default(),
+            }
+            .into());
+        }
+        if let Some(e) = ServerError::from_status(status, result.error.as_deref()) {
+            return Err(e.into());
+        }
+        result.data.ok_or_else(|| {
+            anyhow::anyhow!(
+                "Failed to start upload: {}",
+                result.error.unwrap_or_default()
+            )
+        })
+    }
+
+    /// 会话已完成、过期或不存在时返回 None
+    pub async fn get_upload(&self, id: &str) -> Result<Option<UploadStatus>> {
+        let url = format!("{}/api/uploads/{}", self.base_url, id);
+        let resp = self.http.get(&url).send().await?;
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let result: ApiResponse<UploadStatus> = resp.error_for_status()?.json().await?;
+        Ok(result.data)
+    }
+
+    /// 上传第 n 块，服务端按 X-Content-Sha256 校验分块内容
+    pub async fn upload_chunk(&self, id: &str, n: u64, content: Vec<u8>) -> Result<UploadStatus> {
+        let url = format!("{}/api/uploads/{}/chunks/{}", self.base_url, id, n);
+        let hash = sha256_hex(&content);
+        let resp = self
+            .http
+            .put(&url)
+            .header("X-Content-Sha256", &hash)
+            .body(content)
+            .send()
+            .await?;
+        let status = resp.status();
+        let result: ApiResponse<UploadStatus> = resp.json().await?;
+        if status == reqwest::StatusCode::UNPROCESSABLE_ENTITY {
+            return Err(ChecksumMismatch {
+                path: format!("chunk {} of upload {}", n, id),
+                expected: hash,
+                actual: result.error.unwrap_or_default(),
+            }
+            .into());
+        }
+        if let Some(e) = ServerError::from_status(status, result.error.as_deref()) {
+            return Err(e.into());
+        }
+        result.data.ok_or_else(|| {
+            anyhow::anyhow!(
+                "Failed to upload chunk {}: {}",
+                n,
+                result.error.unwrap_or_default()
+            )
+        })
+    }
+
+    /// 拼接所有分块并写入 path，local_hash 为整个文件的哈希
+    pub async fn complete_upload(
+        &self,
+        id: &str,
+        path: &str,
+        local_hash: &str,
+        on_conflict: Option<&str>,
+    ) -> Result<FileInfo> {
+        let url = format!("{}/api/uploads/{}/complete", self.base_url, id);
+        let mut req = self.http.post(&url).header("X-Content-Sha256", local_hash);
+        if let Some(mode) = on_conflict {
+            req = req.query(&[("on_conflict", mode)]);
+        }
+        let resp = req.send().await?;
+        let status = resp.status();
+        let result: ApiResponse<FileInfo> = resp.json().await?;
+        check_uploaded(path, local_hash, status, result)
+    }
+
+    pub async fn get_file_info(&self, path: &str) -> Result<FileInfo> {
+        let url = format!("{}/api/files/{}", self.base_url, path);
+        let resp = self.http.get(&url).send().await?;
+        let result: ApiResponse<FileInfo> = resp.json().await?;
+        result.data.ok_or_else(|| {
+            anyhow::anyhow!(
+                "Failed to get file info: {}",
+                result.error.unwrap_or_default()
+            )
+        })
+    }
+
+    /// 文件的全部历史版本，按版本号升序
+    pub async fn list_file_versions(&self, path: &str) -> Result<Vec<FileVersionRecord>> {
+        self.require(feature::VERSION_HISTORY).await?;
+        let url = format!("{}/api/files/{}/versions", self.base_url, path);
+        let resp = self.http.get(&url).send().await?;
+        let result: ApiResponse<Vec<FileVersionRecord>> = resp.json().await?;
+        result.data.ok_or_else(|| {
+            anyhow::anyhow!(
+                "Failed to list versions: {}",
+                result.error.unwrap_or_default()
+            )
+        })
+    }
+
+    /// 把文件恢复为某个历史版本的内容，作为新版本写入；返回新版本的信息
+    pub async fn rollback(&self, path: &str, version: i32) -> Result<FileInfo> {
+        self.require(feature::VERSION_HISTORY).await?;
+        let url = format!("{}/api/files/{}/rollback/{}", self.base_url, path, version);
+        let resp = self.http.post(&url).send().await?;
+        let result: ApiResponse<FileInfo> = resp.json().await?;
+        result.data.ok_or_else(|| {
+            anyhow::anyhow!("Failed to roll back: {}", result.error.unwrap_or_default())
+        })
+    }
+
+    /// 把文件或目录移动到 to，历史版本跟着移动；返回移动后的文件
+    pub async fn move_file(&self, from: &str, to: &str) -> Result<Vec<FileInfo>> {
+        self.require(feature::MOVE).await?;
+        let url = format!("{}/api/files/{}/move", self.base_url, from);
+        let resp = self
+            .http
+            .post(&url)
+            .json(&serde_json::json!({ "to": to }))
+            .send()
+            .await?;
+        let result: ApiResponse<Vec<FileInfo>> = resp.json().await?;
+        result
+            .data
+            .ok_or_else(|| anyhow::anyhow!("Failed to move: {}", result.error.unwrap_or_default()))
+    }
+
+    /// 在服务端复制文件，副本引用同一个对象，不重新上传内容
+    pub async fn copy_file(&self, from: &str, to: &str) -> Result<FileInfo> {
+        self.require(feature::COPY).await?;
+        let url = format!("{}/api/files/{}/copy", self.base_url, from);
+        let resp = self
+            .http
+            .post(&url)
+            .json(&serde_json::json!({ "to": to }))
+            .send()
+            .await?;
+        let result: ApiResponse<FileInfo> = resp.json().await?;
+        result
+            .data
+            .ok_or_else(|| anyhow::anyhow!("Failed to copy: {}", result.error.unwrap_or_default()))
+    }
+
+    /// 合并更新文件的自定义元数据，值为 None 的键被删除
+    pub async fn update_metadata(
+        &self,
+        path: &str,
+        patch: &BTreeMap<String, Option<String>>,
+    ) -> Result<FileInfo> {
+        self.require(feature::METADATA).await?;
+        let url = format!("{}/api/metadata/{}", self.base_url, path);
+        let resp = self.http.patch(&url).json(patch).send().await?;
+        let result: ApiResponse<FileInfo> = resp.json().await?;
+        result.data.ok_or_else(|| {
+            anyhow::anyhow!(
+                "Failed to update metadata: {}",
+                result.error.unwrap_or_default()
+            )
+        })
+    }
+
+    /// 读取文件开头或末尾的若干行，不下载整个文件
+    pub async fn preview(&self, path: &str, lines: usize, tail: bool) -> Result<FilePreview> {
+        let url = format!("{}/api/preview/{}", self.base_url, path);
+        let resp = self
+            .http
+            .get(&url)
+            .query(&[("lines", lines.to_string()), ("tail", tail.to_string())])
+            .send()
+            .await?;
+        let result: ApiResponse<FilePreview> = resp.json().await?;
+        result.data.ok_or_else(|| {
+            anyhow::anyhow!(
+                "Failed to preview file: {}",
+                result.error.unwrap_or_default()
+            )
+        })
+    }
+
+    /// 内容相同的文件分组
+    pub async fn duplicates_report(&self, min_size: u64) -> Result<DuplicatesReport> {
+        let url = format!("{}/api/reports/duplicates", self.base_url);
+        let resp = self
+            .http
+            .get(&url)
+            .query(&[("min_size", min_size.to_string())])
+            .send()
+            .await?;
+        let result: ApiResponse<DuplicatesReport> = resp.json().await?;
+        result.data.ok_or_else(|| {
+            anyhow::anyhow!(
+                "Failed to load duplicates report: {}",
+                result.error.unwrap_or_default()
+            )
+        })
+    }
+
+    /// 按路径子串（不区分大小写）搜索文件，最多返回 limit 条
+    pub async fn search(&self, query: &str, limit: usize) -> Result<SearchResult> {
+        let url = format!("{}/api/search", self.base_url);
+        let resp = self
+            .http
+            .get(&url)
+            .query(&[("q", query.to_string()), ("limit", limit.to_string())])
+            .send()
+            .await?;
+        let result: ApiResponse<SearchResult> = resp.json().await?;
+        result
+            .data
+            .ok_or_else(|| anyhow::anyhow!("Search failed: {}", result.error.unwrap_or_default()))
+    }
+
+    pub async fn create_share(
+        &self,
+        path: &str,
+        expires_in_hours: Option<i64>,
+        max_downloads: Option<u64>,
+    ) -> Result<ShareLink> {
+        let url = format!("{}/api/shares", self.base_url);
+        let resp = self
+            .http
+            .post(&url)
+            .json(&serde_json::json!({
+                "path": path,
+                "expires_in_hours": expires_in_hours,
+                "max_downloads": max_downloads,
+            }))
+            .send()
+            .await?;
+        let result: ApiResponse<ShareLink> = resp.json().await?;
+        result.data.ok_or_else(|| {
+            anyhow::anyhow!(
+                "Failed to create share: {}",
+                result.error.unwrap_or_default()
+            )
+        })
+    }
+
+    /// 分享链接的公开下载地址
+    pub fn share_url(&self, share: &ShareLink) -> String {
+        format!("{}/api/shares/{}/download", self.base_url, share.id)
+    }
+
+    /// 最大文件报告；指定 stale_months 时只统计长期未使用的文件
+    pub async fn usage_report(
+        &self,
+        top: usize,
+        depth: usize,
+        stale_months: Option<u32>,
+    ) -> Result<UsageReport> {
+        let mut query = vec![("top", top.to_string()), ("depth", depth.to_string())];
+        let url = match stale_months {
+            Some(months) => {
+                query.push(("months", months.to_string()));
+                format!("{}/api/reports/stale", self.base_url)
+            }
+            None => format!("{}/api/reports/largest", self.base_url),
+        };
+        let resp = self.http.get(&url).query(&query).send().await?;
+        let result: ApiResponse<UsageReport> = resp.json().await?;
+        result.data.ok_or_else(|| {
+            anyhow::anyhow!(
+                "Failed to load usage report: {}",
+                result.error.unwrap_or_default()
+            )
+        })
+    }
+
+    /// 当前用户已用的空间和配额，需要服务端支持 feature::QUOTAS
+    pub async fn storage_usage(&self) -> Result<StorageUsage> {
+        let url = format!("{}/api/me/usage", self.base_url);
+        let resp = self.http.get(&url).send().await?;
+        let result: ApiResponse<StorageUsage> = resp.json().await?;
+        result.data.ok_or_else(|| {
+            anyhow::anyhow!(
+                "Failed to load storage usage: {}",
+                result.error.unwrap_or_default()
+            )
+        })
+    }
+
+    // 流式下载：边接收边写入临时文件并增量计算哈希，内存占用与文件大小无关。
+    // 哈希与服务端报告一致后才 rename 到目标路径，不一致则丢弃并重新下载。
+    #[cfg(feature = "native")]
+    pub async fn download_to(
+        &self,
+        path: &str,
+        target: &Path,
+        mut on_progress: impl FnMut(u64),
+    ) -> Result<u64> {
+        let url = self.download_url(path).await?;
+        let expected = self
+            .get_file_info(path)
+            .await?
+            .hash
+            .ok_or_else(|| anyhow::anyhow!("Server reported no hash for {}", path))?;
+        // 本地已有文件时带上它的哈希，内容相同服务端返回 304，不再传输
+        let local_hash = match tokio::fs::metadata(target).await {
+            Ok(meta) if meta.is_file() => file_sha256(target).await.ok().map(|h| (h, meta.len())),
+            _ => None,
+        };
+        let mut last_error = None;
+
+        for attempt in 1..=MAX_TRANSFER_ATTEMPTS {
+            let tmp = temp_sibling(target);
+            let etag = local_hash.as_ref().map(|(hash, _)| hash.as_str());
+            match self
+                .stream_to_file(&url, etag, &tmp, &mut on_progress)
+                .await
+            {
+                Ok(None) => {
+                    let _ = tokio::fs::remove_file(&tmp).await;
+                    let size = local_hash.map(|(_, size)| size).unwrap_or_default();
+                    on_progress(size);
+                    return Ok(size);
+                }
+                Ok(Some((actual, size))) if actual == expected => {
+                    if let Err(e) = tokio::fs::rename(&tmp, target).await {
+                        let _ = tokio::fs::remove_file(&tmp).await;
+                        return Err(e.into());
+                    }
+                    return Ok(size);
+                }
+                Ok(Some((actual, _))) => {
+                    let _ = tokio::fs::remove_file(&tmp).await;
+                    let e = ChecksumMismatch {
+                        path: path.to_string(),
+                        expected: expected.clone(),
+                        actual,
+                    };
+                    tracing::warn!("Download attempt {} failed: {}", attempt, e);
+                    last_error = Some(e);
+                }
+                Err(e) => {
+                    let _ = tokio::fs::remove_file(&tmp).await;
+                    return Err(e);
+                }
+            }
+        }
+
+        Err(last_error
+            .map(anyhow::Error::from)
+            .unwrap_or_else(|| anyhow::anyhow!("Failed to download file")))
+    }
+
+    // 断点续传：内容写入目标旁名字固定的部分文件，传输中断时保留；
+    // 再次调用时带上 Range 从已有长度继续，If-Range 保证服务端内容没有变化。
+    // 已有的部分内容来自其他版本时，拼接后的哈希不符，丢弃后从头下载
+    #[cfg(feature = "native")]
+    pub async fn download_resumable(
+        &self,
+        path: &str,
+        target: &Path,
+        mut on_progress: impl FnMut(u64),
+    ) -> Result<u64> {
+        let url = self.download_url(path).await?;
+        let expected = self
+            .get_file_info(path)
+            .await?
+            .hash
+            .ok_or_else(|| anyhow::anyhow!("Server reported no hash for {}", path))?;
+        let partial = partial_sibling(target);
+        let mut last_error = None;
+
+        for attempt in 1..=MAX_TRANSFER_ATTEMPTS {
+            let (actual, size) = self
+                .append_to_file(&url, &expected, &partial, &mut on_progress)
+                .await?;
+            if actual == expected {
+                tokio::fs::rename(&partial, target).await?;
+                return Ok(size);
+            }
+            let _ = tokio::fs::remove_file(&partial).await;
+            let e = ChecksumMismatch {
+                path: path.to_string(),
+                expected: expected.clone(),
+                actual,
+            };
+            tracing::warn!("Download attempt {} failed: {}", attempt, e);
+            last_error = Some(e);
+        }
+
+        Err(last_error
+            .map(anyhow::Error::from)
+            .unwrap_or_else(|| anyhow::anyhow!("Failed to download file")))
+    }
+
+    // 旧服务端没有 /raw，退回到 /api/stream
+    #[cfg(feature = "native")]
+    async fn download_url(&self, path: &str) -> Result<String> {
+        if self.supports(feature::RAW_DOWNLOAD).await {
+            Ok(format!("{}/api/files/{}/raw", self.base_url, path))
+        } else {
+            self.require(feature::RANGE_DOWNLOAD).await?;
+            Ok(format!("{}/api/stream/{}", self.base_url, path))
+        }
+    }
+
+    // 在部分文件后面追加剩余内容，返回整个文件的哈希和大小；出错时已写入的内容留在部分文件中
+    #[cfg(feature = "native")]
+    async fn append_to_file(
+        &self,
+        url: &str,
+        etag: &str,
+        partial: &Path,
+        on_progress: &mut impl FnMut(u64),
+    ) -> Result<(String, u64)> {
+        use futures_util::StreamExt;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut hasher = Sha256::new();
+        let mut received = 0u64;
+        if let Ok(mut file) = tokio::fs::File::open(partial).await {
+            let mut buffer = vec![0u8; 1024 * 1024];
+            loop {
+                let read = file.read(&mut buffer).await?;
+                if read == 0 {
+                    break;
+                }
+                hasher.update(&buffer[..read]);
+                received += read as u64;
+            }
+        }
+
+        let mut request = self.http.get(url);
+        if received > 0 {
+            request = request
+                .header(reqwest::header::RANGE, format!("bytes={}-", received))
+                .header(reqwest::header::IF_RANGE, format!("\"{}\"", etag));
+        }
+        let resp = request.send().await?;
+        let mut file = match resp.status() {
+            reqwest::StatusCode::PARTIAL_CONTENT => {
+                tokio::fs::OpenOptions::new()
+                    .append(true)
+                    .open(partial)
+                    .await?
+            }
+            // 部分文件不比服务端内容短：可能已经完整，交给调用方按哈希判断
+            reqwest::StatusCode::RANGE_NOT_SATISFIABLE => {
+                return Ok((format!("{:x}", hasher.finalize()), received));
+            }
+            _ => {
+                resp.error_for_status_ref()?;
+                hasher = Sha256::new();
+                received = 0;
+                tokio::fs::File::create(partial).await?
+            }
+        };
+        on_progress(received);
+
+        let mut stream = resp.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    file.flush().await?;
+                    return Err(e.into());
+                }
+            };
+            hasher.update(&chunk);
+            file.write_all(&chunk).await?;
+            received += chunk.len() as u64;
+            on_progress(received);
+        }
+        file.sync_all().await?;
+
+        Ok((format!("{:x}", hasher.finalize()), received))
+    }
+
+    // 返回 None 表示服务端确认内容与 etag 相同（304）
+    #[cfg(feature = "native")]
+    async fn stream_to_file(
+        &self,
+        url: &str,
+        etag: Option<&str>,
+        tmp: &Path,
+        on_progress: &mut impl FnMut(u64),
+    ) -> Result<Option<(String, u64)>> {
+        use futures_util::StreamExt;
+        use tokio::io::AsyncWriteExt;
+
+        let mut request = self.http.get(url);
+        if let Some(etag) = etag {
+            request = request.header(reqwest::header::IF_NONE_MATCH, format!("\"{}\"", etag));
+        }
+        let resp = request.send().await?.error_for_status()?;
+        if resp.status() == reqwest::StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+
+        let mut file = tokio::fs::File::create(tmp).await?;
+        let mut hasher = Sha256::new();
+        let mut received = 0u64;
+        let mut stream = resp.bytes_stream();
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            hasher.update(&chunk);
+            file.write_all(&chunk).await?;
+            received += chunk.len() as u64;
+            on_progress(received);
+        }
+        file.sync_all().await?;
+
+        Ok(Some((format!("{:x}", hasher.finalize()), received)))
+    }
+
+    /// 创建目录，缺少的上级目录一并创建；早期版本的服务端不记录空目录
+    pub async fn create_folder(&self, path: &str) -> Result<FileInfo> {
+        let resp = if self.supports(feature::DIRECTORIES).await {
+            let url = format!("{}/api/dirs/{}", self.base_url, path);
+            self.http.post(&url).send().await?
+        } else {
+            let url = format!("{}/api/files", self.base_url);
+            self.http
+                .post(&url)
+                .json(&serde_json::json!({ "path": path }))
+                .send()
+                .await?
+        };
+        let result: ApiResponse<FileInfo> = resp.json().await?;
+        result.data.ok_or_else(|| {
+            anyhow::anyhow!(
+                "Failed to create folder: {}",
+                result.error.unwrap_or_default()
+            )
+        })
+    }
+
+    pub async fn delete_file(&self, path: &str) -> Result<bool> {
+        let url = format!("{}/api/files/{}", self.base_url, path);
+        let resp = self.http.delete(&url).send().await?;
+        let result: ApiResponse<bool> = resp.json().await?;
+        Ok(result.success)
+    }
+
+    /// local_dirs 是本地的空目录，服务端据此在计划中加入需要创建的目录
+    pub async fn create_sync_plan(
+        &self,
+        local_files: &[FileRecord],
+        local_dirs: &[String],
+    ) -> Result<Vec<SyncPlanItem>> {
+        let url = format!("{}/api/sync/plan", self.base_url);
+        let resp = self
+            .http
+            .post(&url)
+            .json(&serde_json::json!({ "local_files": local_files, "local_dirs": local_dirs }))
+            .send()
+            .await?;
+        let result: ApiResponse<Vec<SyncPlanItem>> = resp.json().await?;
+        result
+            .data
+            .ok_or_else(|| anyhow::anyhow!("Failed to create sync plan"))
+    }
+
+    /// 服务端配置的只上传、只下载目录；不支持的服务端视为全部双向同步
+    pub async fn sync_directions(&self) -> Result<Vec<FolderDirection>> {
+        if !self.supports(feature::SYNC_DIRECTIONS).await {
+            return Ok(Vec::new());
+        }
+        let url = format!("{}/api/sync/directions", self.base_url);
+        let resp = self.http.get(&url).send().await?;
+        let result: ApiResponse<Vec<FolderDirection>> = resp.json().await?;
+        result.data.ok_or_else(|| {
+            anyhow::anyhow!(
+                "Failed to get sync directions: {}",
+                result.error.unwrap_or_default()
+            )
+        })
+    }
+
+    pub async fn execute_sync(&self, file_id: &str, device_id: &str, action: &str) -> Result<bool> {
+        let url = format!("{}/api/sync/execute", self.base_url);
+        let resp = self
+            .http
+            .post(&url)
+            .json(&serde_json::json!({
+                "file_id": file_id,
+                "device_id": device_id,
+                "action": action
+            }))
+            .send()
+            .await?;
+        let result: ApiResponse<bool> = resp.json().await?;
+        Ok(result.success)
+    }
+
+    /// 服务端文件的路径、哈希、版本和大小，比 list_versions 小得多，适合比对整个状态。
+    /// 记下 ETag，状态没变时服务端只返回 304；早期版本的服务端退回 list_versions
+    pub async fn list_hashes(&self, prefix: Option<&str>) -> Result<Vec<HashEntry>> {
+        let prefix = prefix.unwrap_or_default();
+        if !self.supports(feature::HASH_LISTING).await {
+            return Ok(self
+                .list_versions()
+                .await?
+                .iter()
+                .filter(|record| record.path.starts_with(prefix))
+                .map(HashEntry::from)
+                .collect());
+        }
+
+        let url = format!("{}/api/hashes", self.base_url);
+        let mut req = self.http.get(&url).query(&[("prefix", prefix)]);
+        if let Some(cached) = self.cached_hashes(prefix, |cached| cached.etag.clone()) {
+            req = req.header(reqwest::header::IF_NONE_MATCH, cached);
+        }
+        // 浏览器自己协商压缩并解压，不允许脚本设置 Accept-Encoding
+        #[cfg(feature = "native")]
+        {
+            req = req.header(reqwest::header::ACCEPT_ENCODING, "gzip");
+        }
+
+        let resp = req.send().await?;
+        if resp.status() == reqwest::StatusCode::NOT_MODIFIED {
+            return self
+                .cached_hashes(prefix, |cached| cached.entries.clone())
+                .ok_or_else(|| anyhow::anyhow!("Server returned 304 for an unknown listing"));
+        }
+        let etag = resp
+            .headers()
+            .get(reqwest::header::ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_string);
+        #[cfg(feature = "native")]
+        let gzip = resp
+            .headers()
+            .get(reqwest::header::CONTENT_ENCODING)
+            .is_some_and(|encoding| encoding == "gzip");
+        let body = resp.bytes().await?.to_vec();
+        #[cfg(feature = "native")]
+        let body = if gzip {
+            use std::io::Read;
+            let mut decoded = Vec::new();
+            flate2::read::GzDecoder::new(&body[..]).read_to_end(&mut decoded)?;
+            decoded
+        } else {
+            body
+        };
+
+        let result: ApiResponse<Vec<HashEntry>> = serde_json::from_slice(&body)?;
+        let entries = result.data.ok_or_else(|| {
+            anyhow::anyhow!(
+                "Failed to list hashes: {}",
+                result.error.unwrap_or_default()
+            )
+        })?;
+        if let Some(etag) = etag {
+            *self.hashes.lock().unwrap_or_else(|e| e.into_inner()) = Some(HashListing {
+                prefix: prefix.to_string(),
+                etag,
+                entries: entries.clone(),
+            });
+        }
+        Ok(entries)
+    }
+
+    fn cached_hashes<T>(&self, prefix: &str, read: impl FnOnce(&HashListing) -> T) -> Option<T> {
+        self.hashes
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .filter(|cached| cached.prefix == prefix)
+            .map(read)
+    }
+
+    pub async fn list_versions(&self) -> Result<Vec<FileRecord>> {
+        let url = format!("{}/api/versions", self.base_url);
+        let resp = self.http.get(&url).send().await?;
+        let result: ApiResponse<Vec<FileRecord>> = resp.json().await?;
+        result
+            .data
+            .ok_or_else(|| anyhow::anyhow!("No data in response"))
+    }
+}
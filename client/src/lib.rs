@@ -0,0 +1,10 @@
+//! RustCloud 客户端核心：HTTP API 封装与同步计划逻辑
+//!
+//! 不依赖本地文件系统，浏览器（`wasm` feature）与桌面端（默认 `native` feature）
+//! 共用同一套同步判定；扫描本地文件与执行传输由调用方负责。
+
+pub mod atomic;
+pub mod client;
+pub mod sync;
+
+pub use client::*;
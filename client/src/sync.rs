@@ -0,0 +1,149 @@
+//! 同步计划：根据本地文件清单与服务端版本生成计划并估算传输量
+//!
+//! 本地文件的来源（目录扫描、浏览器 File API 等）由调用方决定。
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+
+use crate::client::{
+    direction_of, Client, FileRecord, FolderDirection, HashEntry, SyncDirection, SyncPlanItem,
+};
+
+#[derive(Debug, Clone)]
+pub struct LocalFile {
+    pub path: String,
+    pub hash: String,
+    pub size: u64,
+}
+
+/// 本地的文件和空目录
+#[derive(Debug, Clone, Default)]
+pub struct LocalTree {
+    pub files: Vec<LocalFile>,
+    /// 不含文件也不含子目录的目录；其余目录由文件路径隐含
+    pub empty_dirs: Vec<String>,
+}
+
+/// 已生成但尚未执行的同步计划
+pub struct PendingSync {
+    pub items: Vec<SyncPlanItem>,
+    pub estimate: TransferEstimate,
+    /// 生成计划时的远程状态，按路径索引
+    pub remote: HashMap<String, HashEntry>,
+}
+
+/// 执行计划前的传输量估算，内容未变化的文件计入节省量
+#[derive(Debug, Default)]
+pub struct TransferEstimate {
+    pub upload_files: usize,
+    pub upload_bytes: u64,
+    pub download_files: usize,
+    pub download_bytes: u64,
+    pub saved_bytes: u64,
+}
+
+impl TransferEstimate {
+    pub fn total_bytes(&self) -> u64 {
+        self.upload_bytes + self.download_bytes
+    }
+}
+
+/// 拉取远程版本、请求服务端生成计划，并估算需要传输的字节数
+pub async fn plan(client: &Client, local: LocalTree) -> Result<PendingSync> {
+    let LocalTree {
+        files: local_files,
+        empty_dirs,
+    } = local;
+    client.require(crate::feature::SYNC_PLAN).await?;
+    let remote: HashMap<String, HashEntry> = client
+        .list_hashes(None)
+        .await?
+        .into_iter()
+        .map(|entry| (entry.path.clone(), entry))
+        .collect();
+
+    let records = plan_records(&local_files, &remote);
+    let mut items = client.create_sync_plan(&records, &empty_dirs).await?;
+    retain_allowed(&mut items, &client.sync_directions().await?);
+
+    let local: HashMap<String, LocalFile> = local_files
+        .into_iter()
+        .map(|file| (file.path.clone(), file))
+        .collect();
+    let estimate = estimate(&items, &local, &remote);
+
+    Ok(PendingSync {
+        items,
+        estimate,
+        remote,
+    })
+}
+
+/// 去掉目录同步方向不允许的条目：只上传的目录不下载，只下载的目录不上传，两者都不删除。
+/// 服务端同样会拒绝这些写入，提前去掉就不会在每次同步时报错
+pub fn retain_allowed(items: &mut Vec<SyncPlanItem>, directions: &[FolderDirection]) {
+    items.retain(|item| {
+        !matches!(
+            (direction_of(directions, &item.path), item.action.as_str()),
+            (Some(_), "delete")
+                | (Some(SyncDirection::UploadOnly), "download")
+                | (Some(SyncDirection::DownloadOnly), "upload")
+        )
+    });
+}
+
+/// 提交给服务端的本地记录：已存在的路径沿用远程版本号，内容不同时服务端会判定为上传。
+/// 服务端按路径匹配远程记录，id 只用于标识计划中的条目
+pub fn plan_records(
+    local_files: &[LocalFile],
+    remote: &HashMap<String, HashEntry>,
+) -> Vec<FileRecord> {
+    let now = chrono::Utc::now();
+    local_files
+        .iter()
+        .map(|local| {
+            let known = remote.get(&local.path);
+            FileRecord {
+                id: uuid::Uuid::new_v4(),
+                path: local.path.clone(),
+                hash: Some(local.hash.clone()),
+                size: local.size,
+                version: known.map(|r| r.version).unwrap_or(0),
+                created_at: now,
+                updated_at: now,
+                media: None,
+                metadata: Default::default(),
+                last_accessed_at: None,
+                fs_id: None,
+                owner: None,
+            }
+        })
+        .collect()
+}
+
+pub fn estimate(
+    items: &[SyncPlanItem],
+    local: &HashMap<String, LocalFile>,
+    remote: &HashMap<String, HashEntry>,
+) -> TransferEstimate {
+    let mut estimate = TransferEstimate::default();
+    // 创建目录不传输内容，不计入估算
+    for item in items.iter().filter(|item| !item.is_dir) {
+        match item.action.as_str() {
+            "upload" => {
+                estimate.upload_files += 1;
+                estimate.upload_bytes += local.get(&item.path).map(|f| f.size).unwrap_or(0);
+            }
+            "download" => {
+                estimate.download_files += 1;
+                estimate.download_bytes += remote.get(&item.path).map(|r| r.size).unwrap_or(0);
+            }
+            "skip" => {
+                estimate.saved_bytes += local.get(&item.path).map(|f| f.size).unwrap_or(0);
+            }
+            _ => {}
+        }
+    }
+    estimate
+}
@@ -0,0 +1,390 @@
+// [知识点 #179] 滚动哈希与增量同步
+// ----------------------------------------
+// 题目：1GB 的文件只改了几个字节，为什么不必重新上传 1GB？
+//
+// 讲解：
+// rsync 的做法是让持有旧内容的一方（服务端）把文件切成固定大小的块，
+// 每块算两个校验和发给另一方（客户端）：
+// - 弱校验和：可以"滚动"，窗口右移一个字节时 O(1) 更新，用来快速筛选
+// - 强校验和：SHA-256，弱校验和碰上之后再确认是不是同一块
+//
+// 客户端在新内容上逐字节滑动窗口，窗口内容与某个旧块相同就记一条"复制第 N 块"，
+// 否则把这个字节当作新数据。插入或删除几个字节后，后面的块只是错开了位置，
+// 滑动窗口仍然能重新对齐，所以只有改动附近的数据需要传输。
+//
+// 服务端按这些指令用旧内容拼出新内容，最后用整个文件的 SHA-256 校验结果，
+// 弱/强校验和偶然碰撞也不会写入错误的内容。
+//
+// 思考：块大小取多大合适？太小和太大各有什么代价？
+// ----------------------------------------
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// 块大小的下限和上限，实际取值随文件大小变化
+pub const MIN_BLOCK_SIZE: usize = 2 * 1024;
+pub const MAX_BLOCK_SIZE: usize = 128 * 1024;
+
+/// 编码后的增量以此开头
+const MAGIC: &[u8; 4] = b"RCD1";
+const OP_COPY: u8 = 1;
+const OP_DATA: u8 = 2;
+
+/// 块大小取文件大小的平方根（向上取 2 的幂）：块数与单块大小同步增长，
+/// 签名和增量中的新数据都不会太大
+pub fn block_size_for(size: u64) -> usize {
+    ((size as f64).sqrt() as usize)
+        .next_power_of_two()
+        .clamp(MIN_BLOCK_SIZE, MAX_BLOCK_SIZE)
+}
+
+/// 一个块的校验和
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct BlockSignature {
+    /// 可滚动的弱校验和
+    pub weak: u32,
+    /// SHA-256 的前 16 字节（十六进制）
+    pub strong: String,
+}
+
+/// `GET /api/files/{path}/signature` 的响应：服务端当前内容按块的校验和
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct FileSignature {
+    /// 整个文件的 SHA-256，提交增量时作为 If-Match
+    pub hash: String,
+    pub size: u64,
+    pub block_size: usize,
+    /// 按顺序排列，最后一块可能不足 block_size
+    pub blocks: Vec<BlockSignature>,
+}
+
+/// rsync 的弱校验和：a 是窗口内字节之和，b 是按位置加权的和，各取低 16 位
+#[derive(Debug, Clone, Copy)]
+struct Rolling {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl Rolling {
+    fn new(window: &[u8]) -> Self {
+        let len = window.len() as u32;
+        let mut rolling = Rolling { a: 0, b: 0, len };
+        for (i, &byte) in window.iter().enumerate() {
+            rolling.a = rolling.a.wrapping_add(byte as u32);
+            rolling.b = rolling
+                .b
+                .wrapping_add((len - i as u32).wrapping_mul(byte as u32));
+        }
+        rolling
+    }
+
+    fn digest(&self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+
+    /// 窗口右移一个字节：移出 out，移入 incoming
+    fn roll(&mut self, out: u8, incoming: u8) {
+        self.a = self
+            .a
+            .wrapping_sub(out as u32)
+            .wrapping_add(incoming as u32);
+        self.b = self
+            .b
+            .wrapping_sub(self.len.wrapping_mul(out as u32))
+            .wrapping_add(self.a);
+    }
+}
+
+pub fn weak_checksum(block: &[u8]) -> u32 {
+    Rolling::new(block).digest()
+}
+
+pub fn strong_checksum(block: &[u8]) -> String {
+    Sha256::digest(block)[..16]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// 边读边计算签名，内存占用只有一个块
+pub fn signature<R: Read>(mut reader: R, block_size: usize) -> io::Result<FileSignature> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; block_size];
+    let mut blocks = Vec::new();
+    let mut size = 0u64;
+    loop {
+        let read = read_block(&mut reader, &mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        let block = &buffer[..read];
+        hasher.update(block);
+        blocks.push(BlockSignature {
+            weak: weak_checksum(block),
+            strong: strong_checksum(block),
+        });
+        size += read as u64;
+        if read < block_size {
+            break;
+        }
+    }
+    Ok(FileSignature {
+        hash: format!("{:x}", hasher.finalize()),
+        size,
+        block_size,
+        blocks,
+    })
+}
+
+// 读满一个块，只有到达末尾时才会少于缓冲区大小
+fn read_block<R: Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// 拼出新内容的一条指令
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeltaOp {
+    /// 复制旧内容中从 start 开始的 count 个连续块
+    Copy { start: u64, count: u64 },
+    /// 旧内容中没有的新数据
+    Data(Vec<u8>),
+}
+
+/// 无法解码或与旧内容对不上的增量
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeltaError {
+    BadMagic,
+    Truncated,
+    InvalidBlockSize(u64),
+    UnknownOp(u8),
+    BlockOutOfRange(u64),
+}
+
+impl fmt::Display for DeltaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeltaError::BadMagic => write!(f, "not a delta"),
+            DeltaError::Truncated => write!(f, "delta is truncated"),
+            DeltaError::InvalidBlockSize(size) => write!(f, "invalid block size {}", size),
+            DeltaError::UnknownOp(op) => write!(f, "unknown delta operation {}", op),
+            DeltaError::BlockOutOfRange(block) => {
+                write!(f, "block {} is beyond the end of the base file", block)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DeltaError {}
+
+/// 相对于某个签名的增量：按顺序执行 ops 得到新内容
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delta {
+    pub block_size: usize,
+    pub ops: Vec<DeltaOp>,
+}
+
+impl Delta {
+    pub fn new(block_size: usize) -> Self {
+        Delta {
+            block_size,
+            ops: Vec::new(),
+        }
+    }
+
+    /// 紧接上一条复制的块合并成一条
+    pub fn push_copy(&mut self, block: u64) {
+        if let Some(DeltaOp::Copy { start, count }) = self.ops.last_mut() {
+            if *start + *count == block {
+                *count += 1;
+                return;
+            }
+        }
+        self.ops.push(DeltaOp::Copy {
+            start: block,
+            count: 1,
+        });
+    }
+
+    pub fn push_data(&mut self, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        if let Some(DeltaOp::Data(pending)) = self.ops.last_mut() {
+            pending.extend_from_slice(data);
+            return;
+        }
+        self.ops.push(DeltaOp::Data(data.to_vec()));
+    }
+
+    /// 需要传输的新数据字节数
+    pub fn literal_len(&self) -> u64 {
+        self.ops
+            .iter()
+            .map(|op| match op {
+                DeltaOp::Data(data) => data.len() as u64,
+                DeltaOp::Copy { .. } => 0,
+            })
+            .sum()
+    }
+
+    /// 应用到大小为 base_size 的旧内容后新内容的大小；引用了不存在的块时报错
+    pub fn output_len(&self, base_size: u64) -> Result<u64, DeltaError> {
+        let block_size = self.block_size as u64;
+        let mut total = 0u64;
+        for op in &self.ops {
+            total += match op {
+                DeltaOp::Data(data) => data.len() as u64,
+                DeltaOp::Copy { start, count } => {
+                    let out_of_range = DeltaError::BlockOutOfRange(start.saturating_add(*count));
+                    let from = start
+                        .checked_mul(block_size)
+                        .filter(|from| *from < base_size)
+                        .ok_or(DeltaError::BlockOutOfRange(*start))?;
+                    let to = start
+                        .checked_add(*count)
+                        .and_then(|end| end.checked_mul(block_size))
+                        .ok_or(out_of_range.clone())?;
+                    // 只有最后一块可以不足 block_size
+                    if to > base_size + block_size - 1 {
+                        return Err(out_of_range);
+                    }
+                    to.min(base_size) - from
+                }
+            };
+        }
+        Ok(total)
+    }
+
+    /// 编码为 `PATCH /api/files/{path}/delta` 的请求体
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(16 + self.literal_len() as usize);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&(self.block_size as u64).to_le_bytes());
+        for op in &self.ops {
+            match op {
+                DeltaOp::Copy { start, count } => {
+                    bytes.push(OP_COPY);
+                    bytes.extend_from_slice(&start.to_le_bytes());
+                    bytes.extend_from_slice(&count.to_le_bytes());
+                }
+                DeltaOp::Data(data) => {
+                    bytes.push(OP_DATA);
+                    bytes.extend_from_slice(&(data.len() as u64).to_le_bytes());
+                    bytes.extend_from_slice(data);
+                }
+            }
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DeltaError> {
+        let rest = bytes.strip_prefix(MAGIC).ok_or(DeltaError::BadMagic)?;
+        let (block_size, mut rest) = take_u64(rest)?;
+        if !(1..=MAX_BLOCK_SIZE as u64).contains(&block_size) {
+            return Err(DeltaError::InvalidBlockSize(block_size));
+        }
+        let mut delta = Delta::new(block_size as usize);
+        while let Some((&op, tail)) = rest.split_first() {
+            rest = match op {
+                OP_COPY => {
+                    let (start, tail) = take_u64(tail)?;
+                    let (count, tail) = take_u64(tail)?;
+                    delta.ops.push(DeltaOp::Copy { start, count });
+                    tail
+                }
+                OP_DATA => {
+                    let (len, tail) = take_u64(tail)?;
+                    let len = usize::try_from(len).map_err(|_| DeltaError::Truncated)?;
+                    if tail.len() < len {
+                        return Err(DeltaError::Truncated);
+                    }
+                    let (data, tail) = tail.split_at(len);
+                    delta.ops.push(DeltaOp::Data(data.to_vec()));
+                    tail
+                }
+                other => return Err(DeltaError::UnknownOp(other)),
+            };
+        }
+        Ok(delta)
+    }
+}
+
+fn take_u64(bytes: &[u8]) -> Result<(u64, &[u8]), DeltaError> {
+    if bytes.len() < 8 {
+        return Err(DeltaError::Truncated);
+    }
+    let (head, tail) = bytes.split_at(8);
+    Ok((u64::from_le_bytes(head.try_into().unwrap()), tail))
+}
+
+/// 对照旧内容的签名，计算新内容的增量
+pub fn diff(signature: &FileSignature, content: &[u8]) -> Delta {
+    let block_size = signature.block_size;
+    let mut delta = Delta::new(block_size);
+    let mut by_weak: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (i, block) in signature.blocks.iter().enumerate() {
+        by_weak.entry(block.weak).or_default().push(i);
+    }
+    let block_len = |i: usize| {
+        let start = (i * block_size) as u64;
+        (signature.size - start).min(block_size as u64) as usize
+    };
+    let find = |window: &[u8], weak: u32| {
+        let candidates = by_weak.get(&weak)?;
+        let mut strong = None;
+        candidates.iter().copied().find(|&i| {
+            block_len(i) == window.len()
+                && signature.blocks[i].strong
+                    == *strong.get_or_insert_with(|| strong_checksum(window))
+        })
+    };
+
+    // 完整的块靠滑动窗口匹配；不足一块的末尾只和旧内容的最后一块比较
+    let mut literal_start = 0;
+    let mut pos = 0;
+    let mut rolling: Option<Rolling> = None;
+    while block_size > 0 && pos + block_size <= content.len() {
+        let window = &content[pos..pos + block_size];
+        let sum = rolling.unwrap_or_else(|| Rolling::new(window));
+        if let Some(block) = find(window, sum.digest()) {
+            delta.push_data(&content[literal_start..pos]);
+            delta.push_copy(block as u64);
+            pos += block_size;
+            literal_start = pos;
+            rolling = None;
+            continue;
+        }
+        rolling = content.get(pos + block_size).map(|&incoming| {
+            let mut next = sum;
+            next.roll(content[pos], incoming);
+            next
+        });
+        pos += 1;
+    }
+    let tail = &content[pos..];
+    if !tail.is_empty() && tail.len() < block_size {
+        if let Some(block) = find(tail, weak_checksum(tail)) {
+            delta.push_data(&content[literal_start..pos]);
+            delta.push_copy(block as u64);
+            literal_start = content.len();
+        }
+    }
+    delta.push_data(&content[literal_start..]);
+    delta
+}
@@ -0,0 +1,206 @@
+// [知识点 #186] gitignore 风格的忽略规则
+// ----------------------------------------
+// 题目：node_modules/、*.tmp 这类文件为什么不该同步，又该怎样描述它们？
+//
+// 讲解：
+// 构建产物、缓存和编辑器的临时文件体积大、变化快，同步它们只会浪费流量，
+// 还会在设备之间互相覆盖。沿用大家熟悉的 .gitignore 写法，每行一个模式：
+// - `*` 匹配除 "/" 外的任意字符，`?` 匹配一个字符，`[a-z]` / `[!0-9]` 匹配字符集合
+// - 不含 "/" 的模式匹配任意层级的名字：`*.tmp` 同时匹配 a.tmp 和 docs/b.tmp
+// - 含 "/" 的模式从根目录开始匹配，开头的 "/" 可以省略：`/build`、`docs/*.pdf`
+// - `**` 匹配任意层目录：`**/cache`、`logs/**`
+// - 以 "/" 结尾的模式只匹配目录：`node_modules/`
+// - 以 "!" 开头表示重新包含：`*.log` 之后写 `!keep.log`
+// - 空行和 "#" 开头的行被忽略，`\#`、`\!` 表示字面字符
+//
+// 后面的规则优先。与 git 相同，目录被忽略之后，其下的文件不能再用 "!" 找回：
+// 扫描时根本不会进入这个目录。匹配区分大小写。
+//
+// 思考：为什么 "!" 不能重新包含被忽略目录下的文件？如果允许，扫描要付出什么代价？
+// ----------------------------------------
+
+/// 同步目录（或服务端存储目录）根部的忽略规则文件
+pub const IGNORE_FILE: &str = ".rcloudignore";
+
+/// 一组按顺序生效的忽略模式
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    /// 按 "/" 切分后的各段；不带 "/" 的模式只有一段，与路径的最后一段比较
+    segments: Vec<Vec<char>>,
+    anchored: bool,
+    dir_only: bool,
+    negated: bool,
+}
+
+impl IgnoreRules {
+    pub fn new() -> Self {
+        IgnoreRules::default()
+    }
+
+    /// 解析 .rcloudignore 的内容
+    pub fn parse(content: &str) -> Self {
+        let mut rules = IgnoreRules::new();
+        rules.extend(content.lines());
+        rules
+    }
+
+    pub fn extend<I, S>(&mut self, patterns: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        for pattern in patterns {
+            self.add(pattern.as_ref());
+        }
+    }
+
+    /// 添加一行模式；空行和注释不产生规则
+    pub fn add(&mut self, line: &str) {
+        let mut pattern = line.trim_end();
+        if pattern.is_empty() || pattern.starts_with('#') {
+            return;
+        }
+        let negated = pattern.starts_with('!');
+        // "!" 表示重新包含，"\!" 和 "\#" 去掉转义后按字面匹配
+        if negated || pattern.starts_with("\\!") || pattern.starts_with("\\#") {
+            pattern = &pattern[1..];
+        }
+        let dir_only = pattern.ends_with('/');
+        let pattern = pattern.trim_end_matches('/');
+        let anchored = pattern.contains('/');
+        let pattern = pattern.trim_start_matches('/');
+        if pattern.is_empty() {
+            return;
+        }
+        self.rules.push(Rule {
+            segments: pattern
+                .split('/')
+                .filter(|segment| !segment.is_empty())
+                .map(|segment| segment.chars().collect())
+                .collect(),
+            anchored,
+            dir_only,
+            negated,
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// 逻辑路径是否被忽略；任何一级父目录被忽略时，路径本身也被忽略
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        if self.rules.is_empty() {
+            return false;
+        }
+        let segments: Vec<Vec<char>> = path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| segment.chars().collect())
+            .collect();
+        (1..segments.len()).any(|end| self.matches(&segments[..end], true))
+            || self.matches(&segments, is_dir)
+    }
+
+    fn matches(&self, path: &[Vec<char>], is_dir: bool) -> bool {
+        let mut ignored = false;
+        for rule in &self.rules {
+            // 只有能改变当前结论的规则才需要比较
+            if rule.negated != ignored || (rule.dir_only && !is_dir) {
+                continue;
+            }
+            if rule.matches(path) {
+                ignored = !rule.negated;
+            }
+        }
+        ignored
+    }
+}
+
+impl Rule {
+    fn matches(&self, path: &[Vec<char>]) -> bool {
+        if self.anchored {
+            match_segments(&self.segments, path)
+        } else {
+            path.last()
+                .is_some_and(|name| glob(&self.segments[0], name))
+        }
+    }
+}
+
+fn match_segments(pattern: &[Vec<char>], path: &[Vec<char>]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        // 末尾的 "**" 匹配目录下的内容，至少一段
+        Some((first, rest)) if first[..] == ['*', '*'] => {
+            if rest.is_empty() {
+                return !path.is_empty();
+            }
+            (0..=path.len()).any(|skip| match_segments(rest, &path[skip..]))
+        }
+        Some((first, rest)) => path
+            .split_first()
+            .is_some_and(|(name, path)| glob(first, name) && match_segments(rest, path)),
+    }
+}
+
+/// 单段的通配符匹配
+fn glob(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skip| glob(rest, &name[skip..])),
+        Some(('?', rest)) => !name.is_empty() && glob(rest, &name[1..]),
+        Some(('[', rest)) => match (char_class(rest), name.split_first()) {
+            (Some((class, rest)), Some((c, name))) => class.contains(*c) && glob(rest, name),
+            (Some(_), None) => false,
+            // 没有闭合的 "[" 按字面字符处理
+            (None, _) => name.first() == Some(&'[') && glob(rest, &name[1..]),
+        },
+        Some(('\\', [escaped, rest @ ..])) => {
+            name.first() == Some(escaped) && glob(rest, &name[1..])
+        }
+        Some((c, rest)) => name.first() == Some(c) && glob(rest, &name[1..]),
+    }
+}
+
+struct CharClass {
+    negated: bool,
+    ranges: Vec<(char, char)>,
+}
+
+impl CharClass {
+    fn contains(&self, c: char) -> bool {
+        self.ranges.iter().any(|&(low, high)| low <= c && c <= high) != self.negated
+    }
+}
+
+/// 解析 "[" 之后的字符集合，返回集合和 "]" 之后的模式
+fn char_class(pattern: &[char]) -> Option<(CharClass, &[char])> {
+    let (negated, mut rest) = match pattern.split_first() {
+        Some(('!' | '^', rest)) => (true, rest),
+        _ => (false, pattern),
+    };
+    let mut ranges = Vec::new();
+    // 紧跟在 "[" 后的 "]" 是字面字符
+    let mut first = true;
+    loop {
+        match rest {
+            [']', after @ ..] if !first => return Some((CharClass { negated, ranges }, after)),
+            [low, '-', high, after @ ..] if *high != ']' => {
+                ranges.push((*low, *high));
+                rest = after;
+            }
+            [c, after @ ..] => {
+                ranges.push((*c, *c));
+                rest = after;
+            }
+            [] => return None,
+        }
+        first = false;
+    }
+}
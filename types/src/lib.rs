@@ -0,0 +1,555 @@
+//! 后端与客户端共用的 API 数据结构
+//!
+//! 服务端序列化、客户端反序列化的都是这里的同一份定义，字段不会各自漂移。
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+pub mod delta;
+pub mod ignore;
+pub mod path;
+
+/// 所有 JSON 接口的统一外层结构；服务端以 `serde_json::Value` 承载 data，
+/// 客户端按接口反序列化为具体类型
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ApiResponse<T = serde_json::Value> {
+    pub success: bool,
+    pub data: Option<T>,
+    pub error: Option<String>,
+}
+
+impl ApiResponse {
+    pub fn success<T: Serialize>(data: T) -> Self {
+        ApiResponse {
+            success: true,
+            data: Some(serde_json::to_value(data).unwrap_or(serde_json::Value::Null)),
+            error: None,
+        }
+    }
+
+    pub fn error(msg: &str) -> Self {
+        ApiResponse {
+            success: false,
+            data: None,
+            error: Some(msg.to_string()),
+        }
+    }
+
+    /// 失败时附带结构化信息，方便客户端据此决定下一步
+    pub fn error_with_data<T: Serialize>(msg: &str, data: T) -> Self {
+        ApiResponse {
+            success: false,
+            data: Some(serde_json::to_value(data).unwrap_or(serde_json::Value::Null)),
+            error: Some(msg.to_string()),
+        }
+    }
+}
+
+/// 客户端与服务端约定的协议版本，请求或响应格式有不兼容的变化时加一
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// 客户端在每个请求中声明自己的版本号，服务端据此拒绝过旧的客户端
+pub const CLIENT_VERSION_HEADER: &str = "x-client-version";
+
+/// 按点分隔的数字比较版本号（"1.10.0" 比 "1.9.2" 新，缺少的部分按 0 处理），
+/// "-beta" 之类的后缀忽略；任一方无法解析时返回 None
+pub fn compare_versions(a: &str, b: &str) -> Option<std::cmp::Ordering> {
+    fn parse(version: &str) -> Option<Vec<u64>> {
+        let version = version.trim().trim_start_matches('v');
+        let release = version.split(['-', '+']).next()?;
+        release.split('.').map(|part| part.parse().ok()).collect()
+    }
+    let (mut a, mut b) = (parse(a)?, parse(b)?);
+    let len = a.len().max(b.len());
+    a.resize(len, 0);
+    b.resize(len, 0);
+    Some(a.cmp(&b))
+}
+
+/// `GET /api/capabilities` 中可能出现的可选功能
+pub mod feature {
+    /// 上传时校验 `x-content-hash`
+    pub const CONTENT_HASH: &str = "content_hash";
+    /// `GET /api/files?stream=true` 以 NDJSON 流式返回目录列表
+    pub const STREAMED_LISTING: &str = "streamed_listing";
+    /// `GET /api/stream/{path}` 支持 Range 的流式下载
+    pub const RANGE_DOWNLOAD: &str = "range_download";
+    /// `PATCH /api/metadata/{path}` 更新自定义元数据
+    pub const METADATA: &str = "metadata";
+    /// `POST /api/sync/plan` 由服务端计算同步计划
+    pub const SYNC_PLAN: &str = "sync_plan";
+    /// 大文件在服务端按块存储（`GET /api/chunk-policy`）
+    pub const CHUNKED_STORAGE: &str = "chunked_storage";
+    /// `POST /api/uploads` 分块上传，中断后只补传缺少的分块
+    pub const RESUMABLE_UPLOAD: &str = "resumable_upload";
+    /// `GET /api/files/{path}/versions` 历史版本，`POST /api/files/{path}/rollback/{version}` 回滚
+    pub const VERSION_HISTORY: &str = "version_history";
+    /// `GET /api/files/{path}/raw` 以附件形式下载原始内容
+    pub const RAW_DOWNLOAD: &str = "raw_download";
+    /// `POST /api/devices/{id}/heartbeat` 的响应带 `pending_changes`
+    pub const PENDING_CHANGES: &str = "pending_changes";
+    /// `GET /api/ws` 以 WebSocket 推送文件变更（每条消息是一个 `ChangeEvent`）
+    pub const CHANGE_FEED: &str = "change_feed";
+    /// 上传基于过期版本时按冲突处理，`GET /api/conflicts` 列出未解决的冲突
+    pub const CONFLICTS: &str = "conflicts";
+    /// `GET /api/files/{path}/signature` 取块签名，`PATCH /api/files/{path}/delta` 只上传改动的部分
+    pub const DELTA_SYNC: &str = "delta_sync";
+    /// `POST /api/files/{path}/move` 移动或重命名，保留历史版本
+    pub const MOVE: &str = "move";
+    /// `POST /api/files/{path}/copy` 复制文件，副本与原文件共用同一个对象
+    pub const COPY: &str = "copy";
+    /// `GET /api/sync/directions` 列出只上传、只下载的目录
+    pub const SYNC_DIRECTIONS: &str = "sync_directions";
+    /// `GET /api/files?as_of=<RFC 3339>` 列出某一时刻的目录，包括之后被删除或移走的文件
+    pub const TIME_TRAVEL: &str = "time_travel";
+    /// `GET /api/hashes?prefix=` 只列出路径、哈希、版本和大小，支持 ETag 和 gzip
+    pub const HASH_LISTING: &str = "hash_listing";
+    /// `POST /api/dirs/{path}` 创建目录；空目录有记录，会出现在列表和同步计划中
+    pub const DIRECTORIES: &str = "directories";
+    /// 删除的文件先进入回收站：`GET /api/trash` 列出，`POST /api/trash/{id}/restore` 恢复
+    pub const TRASH: &str = "trash";
+    /// `GET /api/me/usage` 返回当前用户已用的空间和配额，超出配额的上传返回 507
+    pub const QUOTAS: &str = "quotas";
+    /// 除健康检查、登录注册和公开分享外，所有接口都需要 `Authorization: Bearer <JWT>`
+    pub const AUTH: &str = "auth";
+}
+
+/// 服务端的版本、协议范围和可选功能，客户端据此调整行为
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Capabilities {
+    /// 服务端版本号
+    pub version: String,
+    /// 服务端实现的协议版本
+    pub protocol: u32,
+    /// 服务端仍然支持的最低客户端协议版本
+    pub min_protocol: u32,
+    /// 服务端接受的最低客户端版本号，更旧的客户端的请求返回 426
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_client_version: Option<String>,
+    #[serde(default)]
+    pub features: Vec<String>,
+    /// 支持的传输压缩编码，为空表示只接受原始内容
+    #[serde(default)]
+    pub compression: Vec<String>,
+}
+
+impl Capabilities {
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+}
+
+/// `GET /api/health` 的内容，客户端据此检查版本兼容和时钟偏差
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct HealthStatus {
+    pub status: String,
+    /// 服务端版本号
+    pub version: String,
+    /// 服务端当前时间
+    #[cfg_attr(feature = "openapi", schema(value_type = String, format = DateTime))]
+    pub time: DateTime<Utc>,
+    /// 后台服务的运行状况，旧版服务端不返回
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub services: Vec<ServiceHealth>,
+    /// 读取时校验失败、等待修复的对象哈希，旧版服务端不返回
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub corrupted_objects: Vec<String>,
+}
+
+/// 由服务端监督的一个后台服务
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ServiceHealth {
+    pub name: String,
+    /// running、restarting 或 stopped
+    pub state: String,
+    /// 崩溃后被重启的次数
+    pub restarts: u32,
+    /// 最近一次崩溃的原因
+    pub last_error: Option<String>,
+}
+
+/// 已注册的用户，不含口令哈希
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UserInfo {
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub id: Uuid,
+    pub username: String,
+    #[cfg_attr(feature = "openapi", schema(value_type = String, format = DateTime))]
+    pub created_at: DateTime<Utc>,
+}
+
+/// 注册或登录成功后签发的访问令牌
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AuthToken {
+    /// JWT，放在 `Authorization: Bearer` 请求头中
+    pub token: String,
+    #[cfg_attr(feature = "openapi", schema(value_type = String, format = DateTime))]
+    pub expires_at: DateTime<Utc>,
+    pub user: UserInfo,
+    /// 只追加的令牌：可以上传，不能删除
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub append_only: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct FileInfo {
+    pub name: String,
+    pub path: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: Option<String>,
+    pub hash: Option<String>,
+    pub version: Option<i32>,
+    /// 上传内容与现有记录相同，未写入新版本
+    #[serde(default)]
+    pub deduplicated: bool,
+    /// 客户端自定义的键值对
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl FileInfo {
+    pub fn from_record(record: &FileRecord) -> Self {
+        FileInfo {
+            name: record
+                .path
+                .rsplit('/')
+                .next()
+                .unwrap_or(&record.path)
+                .to_string(),
+            path: record.path.clone(),
+            is_dir: false,
+            size: record.size,
+            modified: Some(record.updated_at.to_rfc3339()),
+            hash: record.hash.clone(),
+            version: Some(record.version),
+            deduplicated: false,
+            metadata: record.metadata.clone(),
+        }
+    }
+
+    /// 只存在于元数据中的目录（没有对应的磁盘目录）
+    pub fn virtual_dir(name: String, path: String) -> Self {
+        FileInfo {
+            name,
+            path,
+            is_dir: true,
+            size: 0,
+            modified: None,
+            hash: None,
+            version: None,
+            deduplicated: false,
+            metadata: BTreeMap::new(),
+        }
+    }
+}
+
+/// 分块上传会话的状态，创建、查询和上传分块后都会返回
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UploadStatus {
+    pub id: String,
+    /// 完成后写入的逻辑路径
+    pub path: String,
+    pub size: u64,
+    /// 除最后一块外每块的字节数
+    pub chunk_size: u64,
+    pub chunk_count: u64,
+    /// 服务端已完整收到的分块序号，升序
+    pub received: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Deleted,
+}
+
+/// `/api/ws` 推送的文件变更；删除事件没有哈希和版本
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangeEvent {
+    pub kind: ChangeKind,
+    pub path: String,
+    #[serde(default)]
+    pub hash: Option<String>,
+    #[serde(default)]
+    pub version: Option<i32>,
+}
+
+/// 目录的同步方向，未列出的目录双向同步
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SyncDirection {
+    /// 客户端只上传，不下载远程的修改，也不能删除（例如 backups/）
+    UploadOnly,
+    /// 客户端只下载，不能上传、删除或移动（例如 shared-assets/）
+    DownloadOnly,
+}
+
+/// `GET /api/sync/directions` 的条目
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FolderDirection {
+    /// 不含首尾斜杠；空字符串表示整个存储目录
+    pub folder: String,
+    pub direction: SyncDirection,
+}
+
+impl FolderDirection {
+    /// path 是这个目录本身或位于其中
+    pub fn covers(&self, path: &str) -> bool {
+        self.folder.is_empty()
+            || path
+                .strip_prefix(self.folder.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+}
+
+/// path 的同步方向：嵌套时最内层的目录优先，不在任何目录中时为 None
+pub fn direction_of(folders: &[FolderDirection], path: &str) -> Option<SyncDirection> {
+    folders
+        .iter()
+        .filter(|folder| folder.covers(path))
+        .max_by_key(|folder| folder.folder.len())
+        .map(|folder| folder.direction)
+}
+
+/// `GET /api/conflicts` 的条目：设备基于 base_version 修改了 path，而服务端已有更新的版本
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConflictInfo {
+    pub path: String,
+    /// 另存的冲突副本；服务端拒绝上传时为 None
+    #[serde(default)]
+    pub copy_path: Option<String>,
+    pub device_id: Uuid,
+    pub device_name: String,
+    /// 设备修改前持有的版本
+    #[serde(default)]
+    pub base_version: Option<i32>,
+    pub detected_at: DateTime<Utc>,
+}
+
+/// `GET /api/trash` 的条目：被删除的文件或目录，到期前可以恢复到原路径
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashItem {
+    pub id: Uuid,
+    pub path: String,
+    /// 其中被记录的文件数
+    pub files: usize,
+    pub size: u64,
+    pub deleted_at: DateTime<Utc>,
+    /// 到期后自动清除，无法再恢复
+    pub expires_at: DateTime<Utc>,
+}
+
+/// `GET /api/me/usage`：当前用户名下的文件占用的空间
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageUsage {
+    pub files: usize,
+    pub used_bytes: u64,
+    /// 未设置配额时为空
+    #[serde(default)]
+    pub quota_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileRecord {
+    pub id: Uuid,
+    pub path: String,
+    pub hash: Option<String>,
+    pub size: u64,
+    pub version: i32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// 图片上传时提取的尺寸与拍摄时间
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media: Option<MediaMetadata>,
+    /// 客户端自定义的键值对，内容更新时保留
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+    /// 最近一次读取内容的时间，批量延迟写入，可能落后最多一个刷新周期
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_accessed_at: Option<DateTime<Utc>>,
+    /// 落盘文件在文件系统中的标识，用于识别外部移动
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fs_id: Option<FsId>,
+    /// 创建该文件的用户；未启用认证时或早期版本的记录为空
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<Uuid>,
+}
+
+/// 文件某个版本的内容；每次创建或更新文件记录时追加一条。
+/// 删除文件后仍然保留，用于列出过去某一时刻的目录
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct FileVersionRecord {
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub file_id: Uuid,
+    pub version: i32,
+    pub hash: Option<String>,
+    pub size: u64,
+    /// 该版本写入的时间
+    #[cfg_attr(feature = "openapi", schema(value_type = String, format = DateTime))]
+    pub created_at: DateTime<Utc>,
+}
+
+impl FileVersionRecord {
+    /// 文件记录当前的版本
+    pub fn of(file: &FileRecord) -> Self {
+        FileVersionRecord {
+            file_id: file.id,
+            version: file.version,
+            hash: file.hash.clone(),
+            size: file.size,
+            created_at: file.updated_at,
+        }
+    }
+}
+
+/// `GET /api/hashes` 的一项：客户端比对两边状态只需要这些字段
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct HashEntry {
+    pub path: String,
+    pub hash: Option<String>,
+    pub version: i32,
+    pub size: u64,
+    /// 只有设置过自定义元数据的文件才带上
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl From<&FileRecord> for HashEntry {
+    fn from(record: &FileRecord) -> Self {
+        HashEntry {
+            path: record.path.clone(),
+            hash: record.hash.clone(),
+            version: record.version,
+            size: record.size,
+            metadata: record.metadata.clone(),
+        }
+    }
+}
+
+/// Unix 上是 (st_dev, st_ino)，Windows 上是 (卷序列号, 文件索引)；
+/// 同一卷内移动、重命名后不变，只有在文件仍然存在时才唯一
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FsId {
+    pub device: u64,
+    pub inode: u64,
+}
+
+/// EXIF 中的拍摄时间没有时区，按原样保存为本地时间
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MediaMetadata {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub taken_at: Option<NaiveDateTime>,
+}
+
+// [知识点 #024] 新建记录与完整记录分离
+// ----------------------------------------
+// 题目：为什么需要 NewFileRecord 和 FileRecord 两个结构体？
+//
+// 讲解：
+// 这是 Rust 中常见的 "输入类型" 与 "存储类型" 分离模式：
+// - NewFileRecord：创建时需要的字段（不含 id, created_at 等自动生成的）
+// - FileRecord：完整记录，包含所有字段
+//
+// 这样设计的好处：
+// 1. 类型系统强制调用者提供必需字段
+// 2. 自动生成的字段不会被误设置
+// 3. API 更清晰，不易出错
+//
+// 思考：有没有办法用一个结构体实现两种用途？
+// ----------------------------------------
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewFileRecord {
+    pub path: String,
+    pub hash: Option<String>,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceRecord {
+    pub id: Uuid,
+    pub name: String,
+    pub last_seen: DateTime<Utc>,
+    /// 注册该设备的用户；未启用认证时或早期版本的记录为空
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<Uuid>,
+}
+
+/// 心跳的响应：设备记录，以及上次拉取同步计划之后是否有其他客户端的变更
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceHeartbeat {
+    #[serde(flatten)]
+    pub device: DeviceRecord,
+    #[serde(default)]
+    pub pending_changes: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewDeviceRecord {
+    pub name: String,
+}
+
+impl FileRecord {
+    pub fn new(new_record: NewFileRecord, now: DateTime<Utc>) -> Self {
+        FileRecord {
+            id: Uuid::new_v4(),
+            path: new_record.path,
+            hash: new_record.hash,
+            size: new_record.size,
+            version: 1,
+            created_at: now,
+            updated_at: now,
+            media: None,
+            metadata: BTreeMap::new(),
+            last_accessed_at: None,
+            fs_id: None,
+            owner: None,
+        }
+    }
+
+    pub fn increment_version(&mut self, now: DateTime<Utc>) {
+        self.version += 1;
+        self.updated_at = now;
+    }
+
+    /// 最近一次被读取或修改的时间，用于判断冷数据
+    pub fn last_used_at(&self) -> DateTime<Utc> {
+        self.last_accessed_at
+            .map_or(self.updated_at, |at| at.max(self.updated_at))
+    }
+}
+
+impl DeviceRecord {
+    pub fn new(new_record: NewDeviceRecord, now: DateTime<Utc>) -> Self {
+        DeviceRecord {
+            id: Uuid::new_v4(),
+            name: new_record.name,
+            last_seen: now,
+            owner: None,
+        }
+    }
+
+    pub fn update_last_seen(&mut self, now: DateTime<Utc>) {
+        self.last_seen = now;
+    }
+
+    /// 在 timeout 内有过心跳即视为在线
+    pub fn is_online(&self, now: DateTime<Utc>, timeout: chrono::Duration) -> bool {
+        now - self.last_seen < timeout
+    }
+}
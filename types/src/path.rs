@@ -0,0 +1,162 @@
+// [知识点 #163] 跨平台路径
+// ----------------------------------------
+// 题目：Linux 上合法的文件名，同步到 Windows 为什么会出问题？
+//
+// 讲解：
+// 服务端统一用 "/" 分隔的相对路径（逻辑路径）标识文件，
+// 但 Windows 文件系统多了不少限制：
+// - 分隔符是 "\"，客户端可能原样发来 "docs\a.txt"
+// - CON、NUL、COM1 等是保留设备名，连 "con.txt" 也不能创建
+// - < > : " | ? * 和控制字符不能出现在文件名中，结尾也不能是点或空格
+// - 超过 260 字符的路径要加 "\\?\" 前缀才能访问
+// - 不区分大小写："Docs/A.txt" 和 "docs/a.txt" 是同一个文件
+//
+// 这里的函数在写入前把路径规范成所有平台都能落盘的形式；
+// 大小写冲突则沿用已有路径的写法，而不是再建一个只差大小写的文件
+//
+// 思考：为什么大小写比较用 to_lowercase 而不是 to_ascii_lowercase？
+// ----------------------------------------
+
+use std::fmt;
+
+/// Windows 保留的设备名，忽略大小写和扩展名
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+const INVALID_CHARS: [char; 7] = ['<', '>', ':', '"', '|', '?', '*'];
+
+/// 无法在所有平台上落盘的路径
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathError {
+    Empty,
+    ParentDir,
+    Reserved(String),
+    InvalidChar(String, char),
+    TrailingDotOrSpace(String),
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathError::Empty => write!(f, "path is empty"),
+            PathError::ParentDir => write!(f, "path must not contain '..'"),
+            PathError::Reserved(name) => write!(f, "'{}' is a reserved name on Windows", name),
+            PathError::InvalidChar(name, c) => {
+                write!(f, "'{}' contains invalid character {:?}", name, c)
+            }
+            PathError::TrailingDotOrSpace(name) => {
+                write!(f, "'{}' must not end with a dot or space", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PathError {}
+
+/// 规范成 "a/b/c" 形式的逻辑路径：统一分隔符，去掉空段和 "."，拒绝 ".." 与 Windows 不接受的名字
+pub fn normalize(raw: &str) -> Result<String, PathError> {
+    let raw = strip_long_path_prefix(raw).replace('\\', "/");
+    let mut segments = Vec::new();
+    for segment in raw.split('/') {
+        match segment {
+            "" | "." => continue,
+            ".." => return Err(PathError::ParentDir),
+            _ => {
+                validate_segment(segment)?;
+                segments.push(segment);
+            }
+        }
+    }
+    if segments.is_empty() {
+        return Err(PathError::Empty);
+    }
+    Ok(segments.join("/"))
+}
+
+/// 单个文件名或目录名能否在 Windows 上创建
+pub fn validate_segment(segment: &str) -> Result<(), PathError> {
+    if let Some(c) = segment
+        .chars()
+        .find(|c| c.is_control() || INVALID_CHARS.contains(c))
+    {
+        return Err(PathError::InvalidChar(segment.to_string(), c));
+    }
+    if segment.ends_with('.') || segment.ends_with(' ') {
+        return Err(PathError::TrailingDotOrSpace(segment.to_string()));
+    }
+    if is_reserved_name(segment) {
+        return Err(PathError::Reserved(segment.to_string()));
+    }
+    Ok(())
+}
+
+/// "nul"、"Con.txt"、"com1.tar.gz" 都算保留名
+pub fn is_reserved_name(segment: &str) -> bool {
+    let stem = segment.split('.').next().unwrap_or(segment).trim_end();
+    RESERVED_NAMES
+        .iter()
+        .any(|name| name.eq_ignore_ascii_case(stem))
+}
+
+/// 不区分大小写比较时使用的键
+pub fn case_key(path: &str) -> String {
+    path.to_lowercase()
+}
+
+/// 逐段比较，与已有路径只差大小写的段沿用已有写法
+pub fn adopt_existing_case<'a>(path: &str, existing: impl IntoIterator<Item = &'a str>) -> String {
+    let existing: Vec<Vec<&str>> = existing
+        .into_iter()
+        .map(|p| p.split('/').collect())
+        .collect();
+    let mut adopted: Vec<String> = Vec::new();
+    for (depth, segment) in path.split('/').enumerate() {
+        let key = case_key(segment);
+        let siblings = existing
+            .iter()
+            .filter(|p| p.len() > depth && p[..depth] == adopted[..])
+            .map(|p| p[depth]);
+        let mut matched = None;
+        for sibling in siblings {
+            if sibling == segment {
+                matched = Some(sibling);
+                break;
+            }
+            if matched.is_none() && case_key(sibling) == key {
+                matched = Some(sibling);
+            }
+        }
+        adopted.push(matched.unwrap_or(segment).to_string());
+    }
+    adopted.join("/")
+}
+
+/// 去掉 Windows 长路径前缀："\\?\C:\x" -> "C:\x"，"\\?\UNC\host\share" -> "\\host\share"
+pub fn strip_long_path_prefix(path: &str) -> std::borrow::Cow<'_, str> {
+    for prefix in [r"\\?\", "//?/"] {
+        if let Some(rest) = path.strip_prefix(prefix) {
+            return match rest
+                .strip_prefix(r"UNC\")
+                .or_else(|| rest.strip_prefix("UNC/"))
+            {
+                Some(unc) => format!(r"\\{}", unc).into(),
+                None => rest.into(),
+            };
+        }
+    }
+    path.into()
+}
+
+/// 为绝对路径加上长路径前缀，使其不受 260 字符限制
+pub fn with_long_path_prefix(path: &str) -> String {
+    if path.starts_with(r"\\?\") {
+        return path.to_string();
+    }
+    let path = path.replace('/', r"\");
+    match path.strip_prefix(r"\\") {
+        Some(unc) => format!(r"\\?\UNC\{}", unc),
+        None => format!(r"\\?\{}", path),
+    }
+}
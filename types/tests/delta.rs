@@ -0,0 +1,102 @@
+use rustcloud_types::delta::{diff, signature, Delta, DeltaError, DeltaOp, FileSignature};
+
+fn sign(content: &[u8], block_size: usize) -> FileSignature {
+    signature(content, block_size).unwrap()
+}
+
+// 按增量用旧内容拼出新内容，与服务端的做法相同
+fn apply(base: &[u8], delta: &Delta) -> Vec<u8> {
+    let mut out = Vec::new();
+    for op in &delta.ops {
+        match op {
+            DeltaOp::Data(data) => out.extend_from_slice(data),
+            DeltaOp::Copy { start, count } => {
+                let from = (*start as usize * delta.block_size).min(base.len());
+                let to = ((start + count) as usize * delta.block_size).min(base.len());
+                out.extend_from_slice(&base[from..to]);
+            }
+        }
+    }
+    out
+}
+
+fn sample(len: usize) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i * 31 % 251) as u8 ^ (i / 251) as u8)
+        .collect()
+}
+
+#[test]
+fn test_unchanged_content_is_all_copies() {
+    let content = sample(10_000);
+    let delta = diff(&sign(&content, 1024), &content);
+    assert_eq!(delta.literal_len(), 0);
+    assert_eq!(
+        delta.ops,
+        vec![DeltaOp::Copy {
+            start: 0,
+            count: 10
+        }]
+    );
+    assert_eq!(delta.output_len(content.len() as u64), Ok(10_000));
+    assert_eq!(apply(&content, &delta), content);
+}
+
+#[test]
+fn test_insertions_and_edits_only_send_changed_bytes() {
+    let base = sample(50_000);
+    let mut edited = base.clone();
+    edited.splice(20_000..20_000, b"inserted".iter().copied());
+    edited.drain(40_000..40_100);
+    let end = edited.len();
+    edited[end - 5..].copy_from_slice(b"tail!");
+
+    let delta = diff(&sign(&base, 1024), &edited);
+    assert!(delta.literal_len() < 4 * 1024, "{}", delta.literal_len());
+    assert_eq!(delta.output_len(base.len() as u64), Ok(edited.len() as u64));
+    assert_eq!(apply(&base, &delta), edited);
+}
+
+#[test]
+fn test_short_and_empty_files() {
+    let base = b"short".to_vec();
+    let delta = diff(&sign(&base, 1024), &base);
+    assert_eq!(delta.ops, vec![DeltaOp::Copy { start: 0, count: 1 }]);
+
+    let delta = diff(&sign(b"", 1024), b"new content");
+    assert_eq!(delta.ops, vec![DeltaOp::Data(b"new content".to_vec())]);
+    assert_eq!(apply(b"", &delta), b"new content");
+}
+
+#[test]
+fn test_encoding_round_trips_and_rejects_garbage() {
+    let base = sample(5_000);
+    let mut edited = base.clone();
+    edited.extend_from_slice(b"appended");
+    let delta = diff(&sign(&base, 1024), &edited);
+    assert_eq!(Delta::decode(&delta.encode()), Ok(delta.clone()));
+
+    assert_eq!(Delta::decode(b"nope"), Err(DeltaError::BadMagic));
+    let encoded = delta.encode();
+    assert_eq!(
+        Delta::decode(&encoded[..encoded.len() - 1]),
+        Err(DeltaError::Truncated)
+    );
+}
+
+#[test]
+fn test_output_len_rejects_blocks_past_the_end() {
+    let mut delta = Delta::new(1024);
+    delta.push_copy(4);
+    // 4 个完整块加 100 字节，第 5 块（下标 4）只有 100 字节
+    assert_eq!(delta.output_len(4 * 1024 + 100), Ok(100));
+    assert_eq!(
+        delta.output_len(4 * 1024),
+        Err(DeltaError::BlockOutOfRange(4))
+    );
+    delta.push_copy(5);
+    assert_eq!(
+        delta.output_len(4 * 1024 + 100),
+        Err(DeltaError::BlockOutOfRange(6))
+    );
+}
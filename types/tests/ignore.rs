@@ -0,0 +1,80 @@
+use rustcloud_types::ignore::IgnoreRules;
+
+#[test]
+fn test_unanchored_patterns_match_names_at_any_depth() {
+    let rules = IgnoreRules::parse("*.tmp\nThumbs.db\n");
+    assert!(rules.is_ignored("a.tmp", false));
+    assert!(rules.is_ignored("docs/deep/b.tmp", false));
+    assert!(rules.is_ignored("photos/Thumbs.db", false));
+    assert!(!rules.is_ignored("a.tmp.txt", false));
+    assert!(!rules.is_ignored("docs/thumbs.db", false));
+}
+
+#[test]
+fn test_directory_patterns_ignore_everything_inside() {
+    let rules = IgnoreRules::parse("node_modules/\n.git/\n");
+    assert!(rules.is_ignored("node_modules", true));
+    assert!(rules.is_ignored("web/node_modules/react/index.js", false));
+    assert!(rules.is_ignored(".git/HEAD", false));
+    // 以 "/" 结尾的模式不匹配同名文件
+    assert!(!rules.is_ignored("node_modules", false));
+}
+
+#[test]
+fn test_anchored_and_double_star_patterns() {
+    let rules = IgnoreRules::parse("/build\ndocs/*.pdf\n**/cache\nlogs/**\n");
+    assert!(rules.is_ignored("build/out.bin", false));
+    assert!(!rules.is_ignored("src/build/out.bin", false));
+    assert!(rules.is_ignored("docs/a.pdf", false));
+    assert!(!rules.is_ignored("docs/sub/a.pdf", false));
+    assert!(rules.is_ignored("cache", true));
+    assert!(rules.is_ignored("a/b/cache/x", false));
+    assert!(rules.is_ignored("logs/2024/app.log", false));
+    assert!(!rules.is_ignored("logs", true));
+}
+
+#[test]
+fn test_negation_and_rule_order() {
+    let rules = IgnoreRules::parse("*.log\n!keep.log\n");
+    assert!(rules.is_ignored("app.log", false));
+    assert!(!rules.is_ignored("keep.log", false));
+    assert!(!rules.is_ignored("sub/keep.log", false));
+
+    // 后面的规则优先
+    let rules = IgnoreRules::parse("!keep.log\n*.log\n");
+    assert!(rules.is_ignored("keep.log", false));
+
+    // 被忽略目录下的文件不能重新包含
+    let rules = IgnoreRules::parse("build/\n!build/keep.txt\n");
+    assert!(rules.is_ignored("build/keep.txt", false));
+}
+
+#[test]
+fn test_wildcards_classes_comments_and_escapes() {
+    let rules = IgnoreRules::parse(
+        "# comment\n\n  \nfile?.txt\nreport[0-9].csv\nnote[!a].md\n\\#literal\n\\!bang\n[unclosed\n",
+    );
+    assert!(rules.is_ignored("file1.txt", false));
+    assert!(!rules.is_ignored("file10.txt", false));
+    assert!(rules.is_ignored("report7.csv", false));
+    assert!(!rules.is_ignored("reportx.csv", false));
+    assert!(rules.is_ignored("noteb.md", false));
+    assert!(!rules.is_ignored("notea.md", false));
+    assert!(rules.is_ignored("#literal", false));
+    assert!(rules.is_ignored("!bang", false));
+    assert!(rules.is_ignored("[unclosed", false));
+    assert!(!rules.is_ignored("# comment", false));
+
+    assert!(IgnoreRules::parse("# only comments\n").is_empty());
+}
+
+#[test]
+fn test_configured_patterns_combine_with_file_rules() {
+    // 配置中的模式在前，.rcloudignore 中的规则可以覆盖它们
+    let mut rules = IgnoreRules::new();
+    rules.extend(["*.tmp", "target/"]);
+    rules.extend("!important.tmp\n".lines());
+    assert!(rules.is_ignored("scratch.tmp", false));
+    assert!(!rules.is_ignored("important.tmp", false));
+    assert!(rules.is_ignored("target/debug/app", false));
+}
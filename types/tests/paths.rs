@@ -0,0 +1,104 @@
+use rustcloud_types::path::{
+    adopt_existing_case, case_key, is_reserved_name, normalize, strip_long_path_prefix,
+    with_long_path_prefix, PathError,
+};
+
+#[test]
+fn test_normalize_separators_and_empty_segments() {
+    assert_eq!(
+        normalize(r"docs\reports\q1.pdf").unwrap(),
+        "docs/reports/q1.pdf"
+    );
+    assert_eq!(normalize("/docs//./q1.pdf/").unwrap(), "docs/q1.pdf");
+    assert_eq!(
+        normalize(r"docs/sub\mixed.txt").unwrap(),
+        "docs/sub/mixed.txt"
+    );
+    assert_eq!(normalize("notes (1).txt").unwrap(), "notes (1).txt");
+    assert_eq!(normalize("照片/猫.jpg").unwrap(), "照片/猫.jpg");
+}
+
+#[test]
+fn test_normalize_rejects_unportable_paths() {
+    assert_eq!(normalize(""), Err(PathError::Empty));
+    assert_eq!(normalize(r"\/."), Err(PathError::Empty));
+    assert_eq!(normalize("docs/../etc/passwd"), Err(PathError::ParentDir));
+    assert_eq!(normalize(r"docs\..\x"), Err(PathError::ParentDir));
+    assert_eq!(
+        normalize("a/CON"),
+        Err(PathError::Reserved("CON".to_string()))
+    );
+    assert_eq!(
+        normalize("what?.txt"),
+        Err(PathError::InvalidChar("what?.txt".to_string(), '?'))
+    );
+    assert_eq!(
+        normalize("C:/Windows"),
+        Err(PathError::InvalidChar("C:".to_string(), ':'))
+    );
+    assert!(matches!(
+        normalize("tab\there"),
+        Err(PathError::InvalidChar(_, '\t'))
+    ));
+    assert_eq!(
+        normalize("draft."),
+        Err(PathError::TrailingDotOrSpace("draft.".to_string()))
+    );
+    assert!(matches!(
+        normalize("dir /file"),
+        Err(PathError::TrailingDotOrSpace(_))
+    ));
+}
+
+#[test]
+fn test_reserved_names_ignore_case_and_extension() {
+    for name in ["nul", "Con.txt", "com1.tar.gz", "LPT9", "aux .log"] {
+        assert!(is_reserved_name(name), "{}", name);
+    }
+    for name in ["console", "nul1", "com10", "lpt", "my.con"] {
+        assert!(!is_reserved_name(name), "{}", name);
+    }
+}
+
+#[test]
+fn test_adopt_existing_case() {
+    let existing = ["Docs/Report.pdf", "Docs/img/a.png", "music/b.mp3"];
+    assert_eq!(
+        adopt_existing_case("docs/report.PDF", existing),
+        "Docs/Report.pdf"
+    );
+    assert_eq!(
+        adopt_existing_case("DOCS/IMG/new.png", existing),
+        "Docs/img/new.png"
+    );
+    assert_eq!(adopt_existing_case("Music/B.mp3", existing), "music/b.mp3");
+    assert_eq!(adopt_existing_case("other/x.txt", existing), "other/x.txt");
+
+    // 完全相同的写法优先于只差大小写的
+    let both = ["a/File.txt", "a/file.txt"];
+    assert_eq!(adopt_existing_case("a/file.txt", both), "a/file.txt");
+    assert_eq!(adopt_existing_case("a/FILE.txt", both), "a/File.txt");
+
+    assert_eq!(case_key("Ä/Straße.TXT"), case_key("ä/straße.txt"));
+}
+
+#[test]
+fn test_long_path_prefix() {
+    assert_eq!(
+        strip_long_path_prefix(r"\\?\C:\sync\a.txt"),
+        r"C:\sync\a.txt"
+    );
+    assert_eq!(
+        strip_long_path_prefix(r"\\?\UNC\nas\share\a"),
+        r"\\nas\share\a"
+    );
+    assert_eq!(strip_long_path_prefix("/home/me/sync"), "/home/me/sync");
+
+    assert_eq!(with_long_path_prefix("C:/sync/a.txt"), r"\\?\C:\sync\a.txt");
+    assert_eq!(
+        with_long_path_prefix(r"\\nas\share\a"),
+        r"\\?\UNC\nas\share\a"
+    );
+    assert_eq!(with_long_path_prefix(r"\\?\C:\x"), r"\\?\C:\x");
+    assert_eq!(normalize(r"\\?\sync\a.txt").unwrap(), "sync/a.txt");
+}
@@ -0,0 +1,100 @@
+use std::collections::BTreeMap;
+
+use chrono::Utc;
+
+use rustcloud_types::{
+    ApiResponse, DeviceRecord, FileInfo, FileRecord, MediaMetadata, NewFileRecord,
+};
+
+fn sample_record() -> FileRecord {
+    let mut record = FileRecord::new(
+        NewFileRecord {
+            path: "photos/cat.jpg".to_string(),
+            hash: Some("abc123".to_string()),
+            size: 42,
+        },
+        Utc::now(),
+    );
+    record.media = Some(MediaMetadata {
+        width: Some(640),
+        height: Some(480),
+        taken_at: None,
+    });
+    record.metadata = BTreeMap::from([("album".to_string(), "pets".to_string())]);
+    record.last_accessed_at = Some(record.updated_at);
+    record
+}
+
+#[test]
+fn test_file_record_roundtrip() {
+    let record = sample_record();
+    let json = serde_json::to_string(&record).unwrap();
+    let back: FileRecord = serde_json::from_str(&json).unwrap();
+
+    assert_eq!(back.id, record.id);
+    assert_eq!(back.path, record.path);
+    assert_eq!(back.hash, record.hash);
+    assert_eq!(back.version, record.version);
+    assert_eq!(back.updated_at, record.updated_at);
+    assert_eq!(back.media, record.media);
+    assert_eq!(back.metadata, record.metadata);
+    assert_eq!(back.last_accessed_at, record.last_accessed_at);
+}
+
+#[test]
+fn test_file_record_accepts_minimal_json() {
+    // 旧数据库与旧客户端没有可选字段
+    let json = serde_json::json!({
+        "id": "6c2b5d3e-8b6f-4f0e-9a41-7f1d2a0c9e11",
+        "path": "a.txt",
+        "hash": null,
+        "size": 0,
+        "version": 1,
+        "created_at": "2024-01-01T00:00:00Z",
+        "updated_at": "2024-01-01T00:00:00Z"
+    });
+    let record: FileRecord = serde_json::from_value(json).unwrap();
+    assert!(record.media.is_none());
+    assert!(record.metadata.is_empty());
+    assert!(record.last_accessed_at.is_none());
+}
+
+#[test]
+fn test_device_record_roundtrip() {
+    let device = DeviceRecord::new(
+        rustcloud_types::NewDeviceRecord {
+            name: "laptop".to_string(),
+        },
+        Utc::now(),
+    );
+    let json = serde_json::to_value(&device).unwrap();
+    assert_eq!(json["name"], "laptop");
+
+    let back: DeviceRecord = serde_json::from_value(json).unwrap();
+    assert_eq!(back.id, device.id);
+    assert_eq!(back.last_seen, device.last_seen);
+}
+
+#[test]
+fn test_api_response_decodes_as_typed_payload() {
+    let record = sample_record();
+    let sent = ApiResponse::success(FileInfo::from_record(&record));
+    let json = serde_json::to_string(&sent).unwrap();
+
+    let received: ApiResponse<FileInfo> = serde_json::from_str(&json).unwrap();
+    assert!(received.success);
+    let info = received.data.unwrap();
+    assert_eq!(info.name, "cat.jpg");
+    assert_eq!(info.hash.as_deref(), Some("abc123"));
+    assert_eq!(info.version, Some(1));
+    assert_eq!(info.metadata, record.metadata);
+}
+
+#[test]
+fn test_api_response_error_has_no_data() {
+    let json = serde_json::to_string(&ApiResponse::error("File not found")).unwrap();
+    let received: ApiResponse<FileInfo> = serde_json::from_str(&json).unwrap();
+    assert!(!received.success);
+    assert!(received.data.is_none());
+    assert_eq!(received.error.as_deref(), Some("File not found"));
+}
@@ -17,7 +17,7 @@
 // ----------------------------------------
 
 import axios from 'axios';
-import type { ApiResponse, FileInfo, Device, FileRecord, SyncRecord, SyncPlanItem } from '../types';
+import type { ApiResponse, DeleteSummary, FileInfo, FilePreviewData, Device, FileRecord, SyncRecord, SyncPlanItem, SearchResult, Share, ShareLimits, ShareStats, Comment, ActivityEntry } from '../types';
 
 // [知识点 #202] Axios 实例配置
 // ----------------------------------------
@@ -64,17 +64,37 @@ export const fileApi = {
   getFile: (path: string) =>
     api.get<ApiResponse<FileInfo>>(`/files/${path}`).then(r => r.data),
   
-  uploadFile: (path: string, content: string) =>
-    api.put<ApiResponse<FileInfo>>(`/files/${path}`, content).then(r => r.data),
+  // onConflict：目标已存在时覆盖（默认）、另存为 "name (1).ext" 或报错
+  uploadFile: (path: string, content: string, onConflict?: 'overwrite' | 'rename' | 'fail') =>
+    api.put<ApiResponse<FileInfo>>(`/files/${path}`, content, {
+      params: onConflict ? { on_conflict: onConflict } : undefined,
+    }).then(r => r.data),
   
-  deleteFile: (path: string) =>
-    api.delete<ApiResponse<boolean>>(`/files/${path}`).then(r => r.data),
+  // 删除非空目录必须带 recursive，否则服务端返回 409
+  deleteFile: (path: string, recursive = false) =>
+    api.delete<ApiResponse<DeleteSummary>>(`/files/${path}`, {
+      params: recursive ? { recursive: true } : undefined,
+    }).then(r => r.data),
+
+  // 只读取文件头部/尾部，大文件也不需要整体下载
+  previewFile: (path: string, params: { lines?: number; tail?: boolean } = {}) =>
+    api.get<ApiResponse<FilePreviewData>>(`/preview/${path}`, { params }).then(r => r.data),
+
+  // 原始字节流，支持 Range，可直接作为 <video>/<audio> 的 src
+  streamUrl: (path: string) => `/api/stream/${path}`,
 
   getContent: (path: string) =>
     api.get(`/files/${path}`, { responseType: 'text' }),
 
   createFolder: (path: string) =>
     api.post<ApiResponse<FileInfo>>('/files', { path }).then(r => r.data),
+
+  // 值为 null 表示删除该键
+  updateMetadata: (path: string, patch: Record<string, string | null>) =>
+    api.patch<ApiResponse<FileInfo>>(`/metadata/${path}`, patch).then(r => r.data),
+
+  search: (params: { q?: string; meta_key?: string; meta_value?: string; limit?: number }) =>
+    api.get<ApiResponse<SearchResult>>('/search', { params }).then(r => r.data),
 };
 
 // 设备 API
@@ -95,6 +115,41 @@ export const versionApi = {
     api.get<ApiResponse<FileRecord[]>>('/versions').then(r => r.data),
 };
 
+// 分享 API
+export const shareApi = {
+  listShares: () =>
+    api.get<ApiResponse<Share[]>>('/shares').then(r => r.data),
+
+  createShare: (path: string, expiresInHours?: number, limits: ShareLimits = {}) =>
+    api.post<ApiResponse<Share>>('/shares', { path, expires_in_hours: expiresInHours, ...limits }).then(r => r.data),
+
+  deleteShare: (id: string) =>
+    api.delete<ApiResponse<boolean>>(`/shares/${id}`).then(r => r.data),
+
+  getStats: (id: string) =>
+    api.get<ApiResponse<ShareStats>>(`/shares/${id}/stats`).then(r => r.data),
+
+  downloadUrl: (id: string) => `/api/shares/${id}/download`,
+};
+
+// 评论 API
+export const commentApi = {
+  listComments: (path: string) =>
+    api.get<ApiResponse<Comment[]>>('/comments', { params: { path } }).then(r => r.data),
+
+  addComment: (path: string, text: string, author?: string) =>
+    api.post<ApiResponse<Comment>>('/comments', { path, text, author }).then(r => r.data),
+
+  deleteComment: (id: string) =>
+    api.delete<ApiResponse<boolean>>(`/comments/${id}`).then(r => r.data),
+};
+
+// 动态 API
+export const activityApi = {
+  listActivity: (limit?: number) =>
+    api.get<ApiResponse<ActivityEntry[]>>('/activity', { params: { limit } }).then(r => r.data),
+};
+
 // 同步 API
 export const syncApi = {
   getSyncStatus: (fileId: string) =>
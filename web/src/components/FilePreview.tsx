@@ -4,6 +4,13 @@ import type { FileInfo } from '../types';
 import { fileApi } from '../api';
 import { formatFileSize } from '../utils';
 
+// 文本预览返回的最大行数
+const PREVIEW_LINES = 200;
+
+const TEXT_FILE_PATTERN = /\.(txt|md|json|js|ts|jsx|tsx|css|html|xml|yaml|yml|log|csv)$/i;
+const VIDEO_FILE_PATTERN = /\.(mp4|webm|ogv|mov|m4v)$/i;
+const AUDIO_FILE_PATTERN = /\.(mp3|wav|ogg|oga|flac|m4a|aac)$/i;
+
 interface FilePreviewProps {
   file: FileInfo | null;
   onClose: () => void;
@@ -13,12 +20,18 @@ export function FilePreview({ file, onClose }: FilePreviewProps) {
   const [content, setContent] = useState<string | null>(null);
   const [loading, setLoading] = useState(false);
   const [error, setError] = useState<string | null>(null);
+  const [truncated, setTruncated] = useState(false);
 
   useEffect(() => {
     if (!file) return;
-    
-    // 只尝试加载小文件的文本内容
-    if (file.size > 1024 * 1024) {
+
+    // 音视频由播放器通过 Range 请求直接读取流地址，无需预加载
+    if (VIDEO_FILE_PATTERN.test(file.name) || AUDIO_FILE_PATTERN.test(file.name)) return;
+
+    const isText = TEXT_FILE_PATTERN.test(file.name);
+
+    // 文本文件走预览接口，只取开头若干行，不受文件大小限制
+    if (!isText && file.size > 1024 * 1024) {
       setError('文件过大，无法预览');
       return;
     }
@@ -26,11 +39,20 @@ export function FilePreview({ file, onClose }: FilePreviewProps) {
     const loadContent = async () => {
       setLoading(true);
       setError(null);
+      setTruncated(false);
       try {
-        const response = await fileApi.getFile(file.path);
-        if (response.success && response.data) {
-          // 尝试解析为文本
-          setContent(response.data.content || '');
+        if (isText) {
+          const response = await fileApi.previewFile(file.path, { lines: PREVIEW_LINES });
+          if (response.success && response.data) {
+            setContent(response.data.content);
+            setTruncated(response.data.truncated);
+          }
+        } else {
+          const response = await fileApi.getFile(file.path);
+          if (response.success && response.data) {
+            // 尝试解析为文本
+            setContent(response.data.content || '');
+          }
         }
       } catch {
         setError('无法加载文件内容');
@@ -45,7 +67,9 @@ export function FilePreview({ file, onClose }: FilePreviewProps) {
   if (!file) return null;
 
   const isImage = /\.(jpg|jpeg|png|gif|webp|svg)$/i.test(file.name);
-  const isText = /\.(txt|md|json|js|ts|jsx|tsx|css|html|xml|yaml|yml)$/i.test(file.name);
+  const isText = TEXT_FILE_PATTERN.test(file.name);
+  const isVideo = VIDEO_FILE_PATTERN.test(file.name);
+  const isAudio = AUDIO_FILE_PATTERN.test(file.name);
 
   return (
     <div className="fixed inset-0 bg-black/50 flex items-center justify-center z-50 p-4">
@@ -104,11 +128,30 @@ export function FilePreview({ file, onClose }: FilePreviewProps) {
             </div>
           )}
 
+          {isVideo && (
+            <video
+              src={fileApi.streamUrl(file.path)}
+              controls
+              className="max-w-full max-h-96 mx-auto rounded"
+            />
+          )}
+
+          {isAudio && (
+            <audio src={fileApi.streamUrl(file.path)} controls className="w-full" />
+          )}
+
           {!loading && !error && content !== null && (
             isText ? (
-              <pre className="bg-gray-900 text-gray-100 p-4 rounded-lg overflow-x-auto text-sm font-mono whitespace-pre-wrap">
-                {content}
-              </pre>
+              <>
+                <pre className="bg-gray-900 text-gray-100 p-4 rounded-lg overflow-x-auto text-sm font-mono whitespace-pre-wrap">
+                  {content}
+                </pre>
+                {truncated && (
+                  <p className="mt-2 text-xs text-gray-500">
+                    仅显示前 {PREVIEW_LINES} 行
+                  </p>
+                )}
+              </>
             ) : isImage ? (
               <div className="text-center">
                 <img 
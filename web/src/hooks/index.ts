@@ -107,7 +107,8 @@ export const useDeleteFile = () => {
   const queryClient = useQueryClient();
   
   return useMutation({
-    mutationFn: (path: string) => fileApi.deleteFile(path),
+    mutationFn: ({ path, recursive }: { path: string; recursive?: boolean }) =>
+      fileApi.deleteFile(path, recursive),
     onSuccess: () => {
       queryClient.invalidateQueries({ queryKey: ['files'] });
       queryClient.invalidateQueries({ queryKey: ['versions'] });
@@ -38,8 +38,12 @@ export function FileManager() {
   };
 
   const handleDelete = (path: string) => {
-    if (confirm(`确定要删除 "${path}" 吗？`)) {
-      deleteFile.mutate(path, {
+    const isDir = files.some(f => f.path === path && f.is_dir);
+    const message = isDir
+      ? `确定要删除文件夹 "${path}" 及其中的所有内容吗？`
+      : `确定要删除 "${path}" 吗？`;
+    if (confirm(message)) {
+      deleteFile.mutate({ path, recursive: isDir }, {
         onSuccess: () => refetch(),
       });
     }
@@ -73,7 +77,7 @@ export function FileManager() {
 
   const handleDownload = (file: FileInfo) => {
     const fullPath = currentPath ? `${currentPath}/${file.name}` : file.name;
-    window.open(`/api/files/${encodeURIComponent(fullPath)}`, '_blank');
+    window.open(`/api/files/${encodeURIComponent(fullPath)}/raw`, '_blank');
   };
 
   return (
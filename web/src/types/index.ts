@@ -49,7 +49,23 @@ export interface FileInfo {
   modified?: string;
   hash?: string;
   version?: number;
+  deduplicated?: boolean;
   content?: string;
+  metadata?: Record<string, string>;
+}
+
+// 删除涉及的文件数和字节数；409 响应的 data 也是这个结构
+export interface DeleteSummary {
+  files: number;
+  bytes: number;
+}
+
+export interface FilePreviewData {
+  path: string;
+  size: number;
+  offset: number;
+  truncated: boolean;
+  content: string;
 }
 
 export interface Device {
@@ -66,8 +82,29 @@ export interface FileRecord {
   version: number;
   created_at: string;
   updated_at: string;
+  media?: MediaMetadata;
+  metadata?: Record<string, string>;
+  last_accessed_at?: string;
+}
+
+export interface MediaMetadata {
+  width?: number;
+  height?: number;
+  taken_at?: string;
+}
+
+export interface Comment {
+  id: string;
+  file_id: string;
+  author: string;
+  text: string;
+  created_at: string;
 }
 
+export type ActivityEntry =
+  | { kind: 'file_updated'; path: string; version: number; at: string }
+  | { kind: 'comment'; path: string; author: string; text: string; at: string };
+
 export interface SyncRecord {
   id: string;
   device_id: string;
@@ -101,6 +138,38 @@ export interface BreadcrumbItem {
   path: string;
 }
 
+// 分享链接的用量上限，均为可选
+export interface ShareLimits {
+  max_downloads?: number;
+  max_bytes?: number;
+  max_concurrent?: number;
+}
+
+export interface Share extends ShareLimits {
+  id: string;
+  path: string;
+  created_at: string;
+  expires_at?: string;
+}
+
+export interface ShareAccess {
+  share_id: string;
+  accessed_at: string;
+  ip?: string;
+  bytes: number;
+}
+
+export interface ShareStats {
+  share_id: string;
+  path: string;
+  access_count: number;
+  bytes_served: number;
+  unique_ips: number;
+  active_streams: number;
+  last_accessed_at?: string;
+  recent: ShareAccess[];
+}
+
 export interface SearchResult {
   files: FileInfo[];
   total: number;